@@ -0,0 +1,82 @@
+//! the index writes of a block under the durability levels, eg: `cargo bench --bench write_path`
+#![feature(test)]
+
+extern crate test;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use consensus::common::random_dir;
+use consensus::store::durability::{Durability, DurabilityConfig, DurabilityLevel, FsyncPolicy};
+use consensus::store::schema::Schema;
+use consensus::types::account::Account;
+use cryptocurrency_kit::ethkey::Address;
+use kvdb_rocksdb::Database;
+use test::Bencher;
+
+// about the account writes of a block with 100 transfers
+const WRITES_PER_BLOCK: u64 = 200;
+
+fn open() -> (Schema<Database>, String) {
+    let path = random_dir().to_string();
+    (Schema::new(Arc::new(Database::open_default(&path).unwrap())), path)
+}
+
+// one block of writes, every iteration changes the values
+fn write_block(schema: &Schema<Database>, round: u64) {
+    let mut accounts = schema.accounts();
+    for idx in 0..WRITES_PER_BLOCK {
+        let mut account = Account::default();
+        account.credit(round + idx);
+        accounts.put(&Address::from(idx), account);
+    }
+}
+
+fn bench_level(b: &mut Bencher, level: DurabilityLevel, fsync: FsyncPolicy) {
+    let (schema, path) = open();
+    let config = DurabilityConfig { level, flush_blocks: 16, flush_interval: Duration::from_secs(1), fsync };
+    let mut durability = Durability::new(config, Some(path));
+    let mut round = 0;
+    b.iter(|| {
+        round += 1;
+        write_block(&schema, round);
+        durability.commit(schema.database().as_ref());
+    });
+}
+
+// the former write path, every put is flushed
+#[bench]
+fn bench_flush_per_write(b: &mut Bencher) {
+    let (schema, _) = open();
+    let mut accounts = schema.accounts();
+    let mut round = 0;
+    b.iter(|| {
+        round += 1;
+        for idx in 0..WRITES_PER_BLOCK {
+            let mut account = Account::default();
+            account.credit(round + idx);
+            accounts.put(&Address::from(idx), account);
+            schema.database().flush().unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_flush_per_block(b: &mut Bencher) {
+    bench_level(b, DurabilityLevel::Block, FsyncPolicy::Never);
+}
+
+#[bench]
+fn bench_flush_per_batch(b: &mut Bencher) {
+    bench_level(b, DurabilityLevel::Batch, FsyncPolicy::Never);
+}
+
+#[bench]
+fn bench_fsync_per_block(b: &mut Bencher) {
+    bench_level(b, DurabilityLevel::Block, FsyncPolicy::Flush);
+}
+
+#[bench]
+fn bench_fsync_per_batch(b: &mut Bencher) {
+    bench_level(b, DurabilityLevel::Batch, FsyncPolicy::Flush);
+}
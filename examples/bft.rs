@@ -23,7 +23,283 @@ fn main() {
                     .long("config")
                     .default_value("config.toml")
                     .short("c")
-                    .value_name("CONFIG")))
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("verify-db")
+                    .long("verify-db")
+                    .help("verify the stored chain from the genesis, refuse to start on the first bad block"))
+            .arg(
+                Arg::with_name("repair")
+                    .long("repair")
+                    .requires("verify-db")
+                    .help("truncate the chain back to the last good block instead of refusing to start"))
+            .arg(
+                Arg::with_name("keystore")
+                    .long("keystore")
+                    .value_name("PATH")
+                    .help("unlock the validator key from the encrypted key file instead of the secret of the config"))
+            .arg(
+                Arg::with_name("password-file")
+                    .long("password-file")
+                    .requires("keystore")
+                    .value_name("PATH")
+                    .help("the password of the keystore, it's asked on the terminal without it")))
+        .subcommand(SubCommand::with_name("config")
+            .about("the config tools")
+            .subcommand(SubCommand::with_name("schema")
+                .about("print the annotated config schema with the defaults")))
+        .subcommand(SubCommand::with_name("identity")
+            .about("the network identity in the data directory")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .default_value("config.toml")
+                    .short("c")
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT"))
+            .subcommand(SubCommand::with_name("show")
+                .about("print the peer id and the node address"))
+            .subcommand(SubCommand::with_name("rotate")
+                .about("replace the node key and the peer id, the node must be stopped")))
+        .subcommand(SubCommand::with_name("keystore")
+            .about("the encrypted key files of the validator")
+            .arg(
+                Arg::with_name("path")
+                    .long("path")
+                    .required(true)
+                    .value_name("PATH"))
+            .arg(
+                Arg::with_name("password-file")
+                    .long("password-file")
+                    .value_name("PATH"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT"))
+            .subcommand(SubCommand::with_name("new")
+                .about("generate a new validator key into the key file"))
+            .subcommand(SubCommand::with_name("import")
+                .about("encrypt the secret of the config into the key file")
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .default_value("config.toml")
+                        .short("c")
+                        .value_name("CONFIG"))))
+        .subcommand(SubCommand::with_name("signer")
+            .about("run the remote signer of a validator, the nodes sign by the `[signer]` config")
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .default_value("127.0.0.1:7100")
+                    .value_name("ADDRESS"))
+            .arg(
+                Arg::with_name("keystore")
+                    .long("keystore")
+                    .required(true)
+                    .value_name("PATH"))
+            .arg(
+                Arg::with_name("password-file")
+                    .long("password-file")
+                    .value_name("PATH"))
+            .arg(
+                Arg::with_name("token-file")
+                    .long("token-file")
+                    .required(true)
+                    .value_name("PATH")
+                    .help("the token shared with the nodes"))
+            .arg(
+                Arg::with_name("state-dir")
+                    .long("state-dir")
+                    .required(true)
+                    .value_name("PATH")
+                    .help("the high-water mark of the signatures, it's kept across restarts")))
+        .subcommand(SubCommand::with_name("inspect")
+            .about("examine the store of a stopped node, it serves the read api without --height and --tx")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .default_value("config.toml")
+                    .short("c")
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("height")
+                    .long("height")
+                    .value_name("HEIGHT"))
+            .arg(
+                Arg::with_name("tx")
+                    .long("tx")
+                    .value_name("HASH")
+                    .conflicts_with("height"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("report")
+            .about("build the signed SLA report of a validator from the store of a stopped node")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .default_value("config.toml")
+                    .short("c")
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("from")
+                    .long("from")
+                    .required(true)
+                    .value_name("HEIGHT"))
+            .arg(
+                Arg::with_name("to")
+                    .long("to")
+                    .required(true)
+                    .value_name("HEIGHT"))
+            .arg(
+                Arg::with_name("validator")
+                    .long("validator")
+                    .required(true)
+                    .value_name("ADDRESS"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("reindex")
+            .about("rebuild the secondary indexes from the raw blocks in the store of a stopped node")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .default_value("config.toml")
+                    .short("c")
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("export")
+            .about("write the blocks of the store of a stopped node to a file")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .default_value("config.toml")
+                    .short("c")
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .required(true)
+                    .value_name("FILE"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("rlp")
+                    .possible_values(&["rlp", "json"])
+                    .value_name("FORMAT"))
+            .arg(
+                Arg::with_name("from")
+                    .long("from")
+                    .default_value("0")
+                    .value_name("HEIGHT"))
+            .arg(
+                Arg::with_name("to")
+                    .long("to")
+                    .value_name("HEIGHT"))
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("import")
+            .about("validate the blocks of an exported file and apply them to the store of a stopped node")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .default_value("config.toml")
+                    .short("c")
+                    .value_name("CONFIG"))
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .required(true)
+                    .value_name("FILE"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("rlp")
+                    .possible_values(&["rlp", "json"])
+                    .value_name("FORMAT"))
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("fuzz-store")
+            .about("check the ledger consistency with randomized operations")
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .default_value("0")
+                    .value_name("SEED"))
+            .arg(
+                Arg::with_name("steps")
+                    .long("steps")
+                    .default_value("10000")
+                    .value_name("STEPS"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("simulate")
+            .about("run the consensus of the validators on a virtual clock, the same seed replays the same run")
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .default_value("0")
+                    .value_name("SEED"))
+            .arg(
+                Arg::with_name("validators")
+                    .long("validators")
+                    .default_value("4")
+                    .value_name("VALIDATORS"))
+            .arg(
+                Arg::with_name("duration")
+                    .long("duration")
+                    .default_value("60")
+                    .value_name("SECONDS"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
+        .subcommand(SubCommand::with_name("dev-keys")
+            .about("print the derived keys of a local network, they are accepted only in dev mode")
+            .arg(
+                Arg::with_name("count")
+                    .long("count")
+                    .default_value("4")
+                    .value_name("COUNT"))
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .default_value("pretty")
+                    .possible_values(&["json", "pretty", "canonical"])
+                    .value_name("FORMAT")))
         .get_matches();
     let result = run(matches);
     if let Err(err) = result {
@@ -36,14 +312,181 @@ fn run(matches: ArgMatches) -> Result<(), String> {
         ("start", Some(m)) => {
             run_start(&m)
         }
+        ("config", Some(m)) => {
+            run_config(&m)
+        }
+        ("identity", Some(m)) => {
+            run_identity(&m)
+        }
+        ("keystore", Some(m)) => {
+            run_keystore(&m)
+        }
+        ("signer", Some(m)) => {
+            run_signer(&m)
+        }
+        ("inspect", Some(m)) => {
+            run_inspect(&m)
+        }
+        ("report", Some(m)) => {
+            run_report(&m)
+        }
+        ("reindex", Some(m)) => {
+            run_reindex(&m)
+        }
+        ("export", Some(m)) => {
+            run_export(&m)
+        }
+        ("import", Some(m)) => {
+            run_import(&m)
+        }
+        ("fuzz-store", Some(m)) => {
+            run_fuzz_store(&m)
+        }
+        ("simulate", Some(m)) => {
+            run_simulate(&m)
+        }
+        ("dev-keys", Some(m)) => {
+            run_dev_keys(&m)
+        }
         _ => Err("not matches any command".to_string())
     }
 }
 
+fn output_format(matches: &ArgMatches) -> Result<consensus::util::canonical::OutputFormat, String> {
+    matches.value_of("format").unwrap().parse()
+}
+
 fn run_start(matches: &ArgMatches) -> Result<(), String> {
+    use consensus::core::verify::VerifyMode;
     let config = matches.value_of("config").expect("config is None");
+    let verify_db = match (matches.is_present("verify-db"), matches.is_present("repair")) {
+        (true, true) => VerifyMode::Repair,
+        (true, false) => VerifyMode::Check,
+        _ => VerifyMode::Off,
+    };
+    let mut builder = consensus::cmd::NodeBuilder::new(config).verify_db(verify_db);
+    if let Some(keystore) = matches.value_of("keystore") {
+        builder = builder.keystore(keystore);
+    }
+    if let Some(password_file) = matches.value_of("password-file") {
+        builder = builder.password_file(password_file);
+    }
     let (tx, rx) = channel();
-    consensus::cmd::start_node(config, tx)?;
+    builder.start(tx).map_err(|err| err.diagnostic())?;
     rx.recv().unwrap();
     Ok(())
-}
\ No newline at end of file
+}
+fn run_config(matches: &ArgMatches) -> Result<(), String> {
+    match matches.subcommand() {
+        ("schema", Some(_)) => {
+            println!("{}", consensus::config::schema::schema());
+            Ok(())
+        }
+        _ => Err("not matches any config command".to_string())
+    }
+}
+
+fn run_identity(matches: &ArgMatches) -> Result<(), String> {
+    let config = matches.value_of("config").expect("config is None");
+    match matches.subcommand() {
+        ("show", Some(_)) => consensus::cmd::identity::show(config, output_format(matches)?),
+        ("rotate", Some(_)) => consensus::cmd::identity::rotate(config, output_format(matches)?),
+        _ => Err("not matches any identity command".to_string())
+    }
+}
+
+fn run_keystore(matches: &ArgMatches) -> Result<(), String> {
+    let path = matches.value_of("path").expect("path is None");
+    let password_file = matches.value_of("password-file");
+    match matches.subcommand() {
+        ("new", Some(_)) => consensus::cmd::keystore::new(path, password_file, output_format(matches)?),
+        ("import", Some(m)) => {
+            let config = m.value_of("config").expect("config is None");
+            consensus::cmd::keystore::import(config, path, password_file, output_format(matches)?)
+        }
+        _ => Err("not matches any keystore command".to_string())
+    }
+}
+
+fn run_signer(matches: &ArgMatches) -> Result<(), String> {
+    consensus::cmd::signer::serve(
+        matches.value_of("listen").expect("listen is None"),
+        matches.value_of("keystore").expect("keystore is None"),
+        matches.value_of("password-file"),
+        matches.value_of("token-file").expect("token-file is None"),
+        matches.value_of("state-dir").expect("state-dir is None"),
+    )
+}
+
+fn run_inspect(matches: &ArgMatches) -> Result<(), String> {
+    use consensus::cmd::inspect::{inspect, Inspect};
+    let config = matches.value_of("config").expect("config is None");
+    let action = if let Some(height) = matches.value_of("height") {
+        Inspect::Height(height.parse().map_err(|err| format!("{:?}", err))?)
+    } else if let Some(tx) = matches.value_of("tx") {
+        Inspect::Transaction(tx.to_owned())
+    } else {
+        Inspect::Serve
+    };
+    inspect(config, action, output_format(matches)?)
+}
+
+fn run_report(matches: &ArgMatches) -> Result<(), String> {
+    let config = matches.value_of("config").expect("config is None");
+    let from = matches.value_of("from").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let to = matches.value_of("to").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let validator = matches.value_of("validator").unwrap();
+    consensus::cmd::report::report(config, from, to, validator, output_format(matches)?)
+}
+
+fn run_reindex(matches: &ArgMatches) -> Result<(), String> {
+    let config = matches.value_of("config").expect("config is None");
+    consensus::cmd::reindex::reindex(config, output_format(matches)?)
+}
+
+// the blocks are encoded by `--format`, the report by `--output`
+fn run_export(matches: &ArgMatches) -> Result<(), String> {
+    let config = matches.value_of("config").expect("config is None");
+    let file = matches.value_of("file").expect("file is None");
+    let from = matches.value_of("from").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let to = match matches.value_of("to") {
+        Some(to) => Some(to.parse().map_err(|err| format!("{:?}", err))?),
+        None => None,
+    };
+    let output = matches.value_of("output").unwrap().parse()?;
+    consensus::cmd::export::export(config, file, matches.value_of("format").unwrap().parse()?, from, to, output)
+}
+
+fn run_import(matches: &ArgMatches) -> Result<(), String> {
+    let config = matches.value_of("config").expect("config is None");
+    let file = matches.value_of("file").expect("file is None");
+    let output = matches.value_of("output").unwrap().parse()?;
+    consensus::cmd::import::import(config, file, matches.value_of("format").unwrap().parse()?, output)
+}
+
+fn run_fuzz_store(matches: &ArgMatches) -> Result<(), String> {
+    let seed: u64 = matches.value_of("seed").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let steps: u64 = matches.value_of("steps").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let dir = consensus::common::random_dir();
+    let report = consensus::core::consistency::fuzz_ledger(&dir, seed, steps)?;
+    println!("{}", output_format(matches)?.render(&report));
+    std::fs::remove_dir_all(*dir).map_err(|err| err.to_string())
+}
+
+fn run_simulate(matches: &ArgMatches) -> Result<(), String> {
+    use consensus::consensus::simulation::{simulate, SimConfig};
+    let seed: u64 = matches.value_of("seed").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let validators: usize = matches.value_of("validators").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let duration: u64 = matches.value_of("duration").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let config = SimConfig { seed, validators, duration: std::time::Duration::from_secs(duration), ..SimConfig::default() };
+    let report = simulate(config)?;
+    println!("{}", output_format(matches)?.render(&report));
+    Ok(())
+}
+
+fn run_dev_keys(matches: &ArgMatches) -> Result<(), String> {
+    let count: u64 = matches.value_of("count").unwrap().parse().map_err(|err| format!("{:?}", err))?;
+    let keys = consensus::util::dev_keys::dev_keys(count);
+    println!("{}", output_format(matches)?.render(&keys));
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::core::chain::Chain;
+use crate::minner::MinnerStatus;
+
+// the ready node has at least so many connected peers
+pub const MIN_READY_PEERS: usize = 1;
+
+/// HealthChecks are what the readiness probe looks at, the node is ready when the store is
+/// readable, the stored genesis is the configured one, it has peers and a validator takes part
+/// in the consensus.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthChecks {
+    pub db_opened: bool,
+    pub genesis_verified: bool,
+    pub peers: usize,
+    pub validator: bool,
+    // always true for the full nodes
+    pub consensus_participating: bool,
+}
+
+impl HealthChecks {
+    /// `peers` and `minner` are none when the p2p server or the minner is absent or doesn't answer
+    pub fn new(chain: &Arc<Chain>, peers: Option<usize>, minner: Option<MinnerStatus>) -> Self {
+        let last_height = chain.get_last_height();
+        let db_opened = chain.get_block_hash_by_height(last_height).is_some();
+        let genesis_verified = chain.get_block_hash_by_height(0) == Some(chain.get_genesis().hash());
+        let validator = chain.config.validator_address()
+            .map(|address| chain.get_validator_addresses(last_height + 1).contains(&address))
+            .unwrap_or(false);
+        let consensus_participating = !validator || (
+            !chain.is_halted() && !chain.is_syncing() && minner.map_or(false, |status| !status.paused)
+        );
+        HealthChecks { db_opened, genesis_verified, peers: peers.unwrap_or(0), validator, consensus_participating }
+    }
+
+    /// the names of the failed checks
+    pub fn failures(&self) -> Vec<&'static str> {
+        let mut failures = vec![];
+        if !self.db_opened {
+            failures.push("db_opened");
+        }
+        if !self.genesis_verified {
+            failures.push("genesis_verified");
+        }
+        if self.peers < MIN_READY_PEERS {
+            failures.push("peers");
+        }
+        if !self.consensus_participating {
+            failures.push("consensus_participating");
+        }
+        failures
+    }
+
+    pub fn ready(&self) -> bool {
+        self.failures().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_health_checks() {
+        let mut checks = HealthChecks { db_opened: true, genesis_verified: true, peers: 1, validator: false, consensus_participating: true };
+        assert!(checks.ready());
+        checks.peers = 0;
+        checks.consensus_participating = false;
+        assert_eq!(checks.failures(), vec!["peers", "consensus_participating"]);
+        assert!(!checks.ready());
+    }
+}
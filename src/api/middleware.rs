@@ -0,0 +1,285 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::future::FutureObj;
+use http::header::{self, HeaderValue};
+use http::{Method, StatusCode};
+use parking_lot::RwLock;
+use tide::middleware::{Middleware, RequestContext};
+use tide::{Body, Response};
+
+use crate::metrics;
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str = "content-type, authorization";
+// the seconds which browsers cache the preflight reply
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+/// Cors answers the preflight requests and marks the replies to the allowed origins, so the
+/// browsers can query the node directly, eg: a block explorer
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    pub fn new(origins: Vec<String>) -> Self {
+        Cors { origins }
+    }
+
+    // the origin echoed in the reply, `*` allows every origin
+    fn allow(&self, origin: &str) -> Option<HeaderValue> {
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        if self.origins.iter().any(|allowed| allowed.trim_end_matches('/') == origin) {
+            return HeaderValue::from_str(origin).ok();
+        }
+        None
+    }
+}
+
+impl<Data: Clone + Send> Middleware<Data> for Cors {
+    fn handle<'a>(&'a self, ctx: RequestContext<'a, Data>) -> FutureObj<'a, Response> {
+        let allowed = ctx.req.headers().get(header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+            .and_then(|origin| self.allow(origin));
+        let preflight = ctx.req.method() == Method::OPTIONS
+            && ctx.req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        FutureObj::new(Box::new(async move {
+            if preflight {
+                let mut response = http::Response::builder();
+                match allowed {
+                    Some(allowed) => {
+                        response.status(StatusCode::NO_CONTENT)
+                            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed)
+                            .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+                            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS)
+                            .header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE)
+                            .header(header::VARY, "origin");
+                    }
+                    None => {
+                        metrics::inc_counter("api_cors_rejected_total");
+                        response.status(StatusCode::FORBIDDEN);
+                    }
+                }
+                return response.body(Body::empty()).unwrap();
+            }
+            let mut response = await!(ctx.next());
+            if let Some(allowed) = allowed {
+                response.headers_mut().insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+                response.headers_mut().append(header::VARY, HeaderValue::from_static("origin"));
+            }
+            response
+        }))
+    }
+}
+
+/// BodyLimit refuses the request bodies over the limit before they're read, a chunked body
+/// without a length is refused too
+pub struct BodyLimit {
+    max: usize,
+}
+
+impl BodyLimit {
+    pub fn new(max: usize) -> Self {
+        BodyLimit { max }
+    }
+
+    fn check(&self, content_length: Option<&HeaderValue>, chunked: bool) -> Result<(), StatusCode> {
+        match content_length {
+            Some(length) => {
+                let length: usize = length.to_str().ok().and_then(|length| length.parse().ok()).ok_or(StatusCode::BAD_REQUEST)?;
+                if length > self.max {
+                    return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                }
+                Ok(())
+            }
+            None if chunked => Err(StatusCode::LENGTH_REQUIRED),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<Data: Clone + Send> Middleware<Data> for BodyLimit {
+    fn handle<'a>(&'a self, ctx: RequestContext<'a, Data>) -> FutureObj<'a, Response> {
+        let headers = ctx.req.headers();
+        let result = self.check(headers.get(header::CONTENT_LENGTH), headers.contains_key(header::TRANSFER_ENCODING));
+        FutureObj::new(Box::new(async move {
+            match result {
+                Ok(_) => await!(ctx.next()),
+                Err(status) => {
+                    metrics::inc_counter("api_refused_bodies_total");
+                    http::Response::builder().status(status).body(Body::empty()).unwrap()
+                }
+            }
+        }))
+    }
+}
+
+// the endpoints which change the node, they're refused without the admin token
+const ADMIN_PATHS: &[&str] = &["/minner/", "/sync/policy"];
+// the endpoints which expose the process, eg: the profiles, even their reads need the admin token
+const ADMIN_READ_PATHS: &[&str] = &["/debug/"];
+
+/// AdminAuth refuses the admin requests without the bearer token of `api.admin_token`, eg:
+/// `authorization: Bearer <token>`, the admin endpoints are disabled when the token isn't set
+pub struct AdminAuth {
+    token: Option<String>,
+}
+
+impl AdminAuth {
+    pub fn new(token: Option<String>) -> Self {
+        AdminAuth { token: token.filter(|token| !token.is_empty()) }
+    }
+
+    fn check(&self, method: &Method, path: &str, authorization: Option<&HeaderValue>) -> Result<(), StatusCode> {
+        let admin = ADMIN_READ_PATHS.iter().any(|prefix| path.starts_with(prefix))
+            || (method != Method::GET && ADMIN_PATHS.iter().any(|prefix| path.starts_with(prefix)));
+        if !admin {
+            return Ok(());
+        }
+        let token = self.token.as_ref().ok_or(StatusCode::FORBIDDEN)?;
+        let given = authorization.and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().splitn(2, ' ').nth(1))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        // every byte is compared, the time doesn't tell the matched prefix
+        let matched = given.len() == token.len()
+            && given.bytes().zip(token.bytes()).fold(0_u8, |diff, (a, b)| diff | (a ^ b)) == 0;
+        if matched { Ok(()) } else { Err(StatusCode::UNAUTHORIZED) }
+    }
+}
+
+impl<Data: Clone + Send> Middleware<Data> for AdminAuth {
+    fn handle<'a>(&'a self, ctx: RequestContext<'a, Data>) -> FutureObj<'a, Response> {
+        let result = self.check(ctx.req.method(), ctx.req.uri().path(), ctx.req.headers().get(header::AUTHORIZATION));
+        FutureObj::new(Box::new(async move {
+            match result {
+                Ok(_) => await!(ctx.next()),
+                Err(status) => {
+                    metrics::inc_counter("api_unauthorized_total");
+                    http::Response::builder().status(status).body(Body::empty()).unwrap()
+                }
+            }
+        }))
+    }
+}
+
+// the endpoint label of the requests which match no route or use an unknown method
+const UNKNOWN_ENDPOINT: &str = "unknown";
+
+/// the templates of the routes, eg: `/block/{}/consensus`, they're added as the routes are
+/// registered, eg: `app.at(routes.add("/state/{}"))`
+#[derive(Clone, Default)]
+pub struct RouteTemplates(Arc<RwLock<Vec<&'static str>>>);
+
+impl RouteTemplates {
+    pub fn add(&self, template: &'static str) -> &'static str {
+        self.0.write().push(template);
+        template
+    }
+
+    // the template of the path, `{}` matches a segment
+    fn matched(&self, path: &str) -> Option<&'static str> {
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+        self.0.read().iter().cloned().find(|template| {
+            let parts: Vec<&str> = template.split('/').collect();
+            parts.len() == segments.len() && parts.iter().zip(segments.iter())
+                .all(|(part, segment)| *part == *segment || (*part == "{}" && !segment.is_empty()))
+        })
+    }
+}
+
+/// RequestMetrics counts the requests and the errors of every endpoint and records their latency,
+/// eg: `api_request_duration_ms{endpoint="GET /block/{}/consensus"}`
+pub struct RequestMetrics {
+    routes: RouteTemplates,
+}
+
+impl RequestMetrics {
+    pub fn new(routes: RouteTemplates) -> Self {
+        RequestMetrics { routes }
+    }
+}
+
+// the endpoint is labeled by the template of its route, so the label is bounded by the routes,
+// the other requests share `unknown`
+fn endpoint_label(routes: &RouteTemplates, method: &Method, path: &str) -> String {
+    match routes.matched(path) {
+        Some(template) if is_known(method) => format!("{} {}", method, template),
+        _ => UNKNOWN_ENDPOINT.to_string(),
+    }
+}
+
+fn is_known(method: &Method) -> bool {
+    [Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::DELETE, Method::PATCH, Method::OPTIONS].contains(method)
+}
+
+impl<Data: Clone + Send> Middleware<Data> for RequestMetrics {
+    fn handle<'a>(&'a self, ctx: RequestContext<'a, Data>) -> FutureObj<'a, Response> {
+        let endpoint = endpoint_label(&self.routes, ctx.req.method(), ctx.req.uri().path());
+        let start = Instant::now();
+        FutureObj::new(Box::new(async move {
+            let response = await!(ctx.next());
+            let elapsed = start.elapsed();
+            let millis = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_micros() as f64 / 1000.0;
+            metrics::inc_counter(&format!("api_requests_total{{endpoint=\"{}\"}}", endpoint));
+            metrics::observe(&format!("api_request_duration_ms{{endpoint=\"{}\"}}", endpoint), millis);
+            let status = response.status();
+            if status.is_client_error() || status.is_server_error() {
+                metrics::inc_counter(&format!("api_request_errors_total{{endpoint=\"{}\",status=\"{}\"}}", endpoint, status.as_u16()));
+            }
+            response
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_middleware() {
+        let cors = Cors::new(vec!["https://explorer.example.org/".to_owned()]);
+        assert_eq!(cors.allow("https://explorer.example.org"), Some(HeaderValue::from_static("https://explorer.example.org")));
+        assert_eq!(cors.allow("https://evil.example.org"), None);
+        assert_eq!(Cors::new(vec!["*".to_owned()]).allow("https://evil.example.org"), Some(HeaderValue::from_static("*")));
+
+        let limit = BodyLimit::new(1024);
+        assert_eq!(limit.check(Some(&HeaderValue::from_static("1024")), false), Ok(()));
+        assert_eq!(limit.check(Some(&HeaderValue::from_static("1025")), false), Err(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(limit.check(Some(&HeaderValue::from_static("abc")), false), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(limit.check(None, true), Err(StatusCode::LENGTH_REQUIRED));
+        assert_eq!(limit.check(None, false), Ok(()));
+
+        let auth = AdminAuth::new(Some("secret".to_owned()));
+        assert_eq!(auth.check(&Method::POST, "/minner/pause", Some(&HeaderValue::from_static("Bearer secret"))), Ok(()));
+        assert_eq!(auth.check(&Method::POST, "/minner/pause", Some(&HeaderValue::from_static("Bearer secreT"))), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(auth.check(&Method::POST, "/sync/policy", None), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(auth.check(&Method::PUT, "/sync/policy", Some(&HeaderValue::from_static("Bearer other"))), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(auth.check(&Method::POST, "/sync/policy", Some(&HeaderValue::from_static("Bearer secret"))), Ok(()));
+        assert_eq!(AdminAuth::new(None).check(&Method::POST, "/sync/policy", None), Err(StatusCode::FORBIDDEN));
+        assert_eq!(auth.check(&Method::GET, "/minner", None), Ok(()));
+        assert_eq!(auth.check(&Method::POST, "/rpc", None), Ok(()));
+        assert_eq!(AdminAuth::new(None).check(&Method::POST, "/minner/resume", Some(&HeaderValue::from_static("Bearer "))), Err(StatusCode::FORBIDDEN));
+        assert_eq!(AdminAuth::new(Some(String::new())).check(&Method::POST, "/minner/trigger", None), Err(StatusCode::FORBIDDEN));
+        assert_eq!(auth.check(&Method::GET, "/debug/pprof/heap", None), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(auth.check(&Method::POST, "/debug/pprof/start", Some(&HeaderValue::from_static("Bearer secret"))), Ok(()));
+        assert_eq!(AdminAuth::new(None).check(&Method::GET, "/debug/pprof/mailboxes", None), Err(StatusCode::FORBIDDEN));
+
+        let routes = RouteTemplates::default();
+        for template in &["/block/{}/consensus", "/block/at-time/{}", "/account/{}", "/minner/pause", "/status"] {
+            routes.add(template);
+        }
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/block/100/consensus"), "GET /block/{}/consensus");
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/block/at-time/2019-01-01"), "GET /block/at-time/{}");
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/account/0x5701ab"), "GET /account/{}");
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/account/not-an-address"), "GET /account/{}");
+        assert_eq!(endpoint_label(&routes, &Method::POST, "/minner/pause"), "POST /minner/pause");
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/status/"), "GET /status");
+        // the paths out of the routes and the made up methods don't grow the labels
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/"), "unknown");
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/wp-admin/setup.php"), "unknown");
+        assert_eq!(endpoint_label(&routes, &Method::GET, "/account//"), "unknown");
+        assert_eq!(endpoint_label(&routes, &Method::from_bytes(b"SCAN").unwrap(), "/status"), "unknown");
+    }
+}
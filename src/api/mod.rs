@@ -1,37 +1,935 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::{hash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use parking_lot::RwLock;
+
+use crate::common::is_http_url;
 use crate::core::chain::Chain;
-use crate::types::block::Blocks;
+use crate::core::maintenance::last_usage;
+use self::health::HealthChecks;
+use self::middleware::{AdminAuth, BodyLimit, Cors, RequestMetrics, RouteTemplates};
+use self::pagination::{Page, DEFAULT_MAX_PAGE_SIZE};
+use self::rpc::DEFAULT_MAX_BATCH_SIZE;
+use self::tls::{Shutdown, ShutdownSignal};
+use self::ws::{DEFAULT_MAX_WS_CONNECTIONS, DEFAULT_MAX_WS_SUBSCRIPTIONS};
+use crate::features::Feature;
+use crate::light::TransactionProof;
+use crate::p2p::protocol::PROTOCOL_VERSION;
+use crate::pprof::{self, HeapStats, MailboxStats};
+use crate::p2p::server::{GetPeers, GetSyncStatus, SetSyncPolicy, TcpServer};
+use crate::p2p::sync::SyncPolicy;
+use crate::core::tx_pool::{SafeTxPool, TxPoolContents};
+use crate::minner::{Minner, MineBlocks, MiningMode, MinnerStatus, Pause, Resume, SetMiningMode, TriggerMine, GetMinnerStatus};
+use crate::consensus::pbft::core::core::{CONSENSUS_PHASES, phase_metric_name};
+use crate::types::{Height, Timestamp};
+use crate::types::block::Block;
+use crate::types::account::AccountSummary;
+use crate::types::receipt::LogFilter;
+use crate::subscriber::cursor::{load_cursor, load_cursors, SubscriptionCursor, SubscriptionFilter, SubscriptionManager};
+use crate::error::{ApiError, RequestError};
+use crate::util::blocking::BlockingPool;
+use crate::util::canonical::OutputFormat;
+use crate::util::single_flight::SingleFlight;
+use crate::util::request::{send_retry, send_wait, RequestPolicy, DEFAULT_REQUEST_TIMEOUT};
 
+use futures::FutureExt;
 use http::StatusCode;
-use tide::{body, head, configuration::{Configuration, Environment}, App, AppData};
-
-async fn blocks(mut chain: AppData<Arc<Chain>>) -> String {
-    let state: &Arc<Chain> = &chain.0;
-    let last_height = state.get_last_height();
-    let mut blocks: Blocks = Blocks(vec![]);
-    (0..last_height + 1).for_each(|height| {
-        let block_hash = state.get_block_hash_by_height(height).unwrap();
-        let block = state.get_block_by_hash(&block_hash).unwrap();
-        blocks.0.push(block);
-    });
-    serde_json::to_string(&blocks).unwrap()
-}
-
-async fn transactions(mut chain: AppData<Arc<Chain>>) -> String {
-    let state: &Arc<Chain> = &chain.0;
-    let mut transactions = state.get_transactions();
-    serde_json::to_string(&transactions).unwrap()
-}
-
-pub fn start_api(chain: Arc<Chain>, ip: String, port: u16) {
-    let mut app = App::new(chain);
-    app.at("/blocks").get(blocks);
-    app.at("/transactions").get(transactions);
-    app.config(Configuration {
-        env: Environment::Production,
-        address: ip,
-        port: port,
-    });
-    app.serve();
-}
\ No newline at end of file
+use tide::{body, head, App, AppData};
+use tokio_rustls::rustls::ServerConfig;
+
+mod health;
+pub mod middleware;
+pub mod pagination;
+pub mod rpc;
+pub mod tls;
+pub mod ws;
+
+// the bytes of a request body, the transactions and the subscriptions are far smaller
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1 << 20;
+
+/// the browser access and the limits of the http api, eg: `[api]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+    // the origins which the browsers can query the api from, eg: `["https://explorer.example.org"]`,
+    // `*` allows every origin
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    // the pem certificate chain and private key, the api is served over https when both are set
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    #[serde(default)]
+    pub tls_key: Option<String>,
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+    // the max `limit` of the list apis
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: usize,
+    // the calls of a json-rpc batch request
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    // the websocket subscriptions are served on the port of the api ip when it's set, over tls
+    // with the certificate of the api
+    #[serde(default)]
+    pub ws_port: Option<u16>,
+    #[serde(default = "default_max_ws_connections")]
+    pub max_ws_connections: usize,
+    // the subscriptions of a websocket connection
+    #[serde(default = "default_max_ws_subscriptions")]
+    pub max_ws_subscriptions: usize,
+    // the bearer token of the admin endpoints, eg: `/minner/pause`, unset disables them
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            cors_origins: vec![],
+            tls_cert: None,
+            tls_key: None,
+            max_body_size: default_max_body_size(),
+            max_page_size: default_max_page_size(),
+            max_batch_size: default_max_batch_size(),
+            ws_port: None,
+            max_ws_connections: default_max_ws_connections(),
+            max_ws_subscriptions: default_max_ws_subscriptions(),
+            admin_token: None,
+        }
+    }
+}
+
+fn default_max_body_size() -> usize {
+    DEFAULT_MAX_BODY_SIZE
+}
+
+fn default_max_page_size() -> usize {
+    DEFAULT_MAX_PAGE_SIZE
+}
+
+fn default_max_batch_size() -> usize {
+    DEFAULT_MAX_BATCH_SIZE
+}
+
+fn default_max_ws_connections() -> usize {
+    DEFAULT_MAX_WS_CONNECTIONS
+}
+
+fn default_max_ws_subscriptions() -> usize {
+    DEFAULT_MAX_WS_SUBSCRIPTIONS
+}
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub chain: Arc<Chain>,
+    pub txpool: Arc<RwLock<SafeTxPool>>,
+    // the minner is absent in the read-only inspector, the control apis are not served then
+    pub minner: Option<Addr<Minner>>,
+    // the p2p server is absent in the read-only inspector
+    pub p2p: Option<Addr<TcpServer>>,
+    // the dev apis are only available in dev mode
+    pub dev: bool,
+    pub features: Vec<Feature>,
+    // the identical concurrent reads share one storage fetch
+    pub reads: Arc<SingleFlight<Result<String, StatusCode>>>,
+    // the long blocking reads, eg: the state replay, run on it
+    pub blocking: Arc<BlockingPool>,
+    // the durable webhook cursors, they're absent in the read-only inspector
+    pub subscriptions: Option<Arc<SubscriptionManager>>,
+    // the max `limit` of the list apis
+    pub max_page_size: usize,
+    // the max calls of a json-rpc batch
+    pub max_batch_size: usize,
+}
+
+// parse the query parameter from the request uri, eg: `?blocks=10&interval=0`
+fn query_param<T: FromStr>(head: &head::Head, key: &str) -> Result<Option<T>, StatusCode> {
+    let query = match head.uri().query() {
+        Some(query) => query,
+        None => return Ok(None),
+    };
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        if kv.next() == Some(key) {
+            let value = kv.next().unwrap_or("");
+            return value.parse::<T>().map(Some).map_err(|_| StatusCode::BAD_REQUEST);
+        }
+    }
+    Ok(None)
+}
+
+// the output format of the read apis, eg: `?format=canonical`
+fn output_format(head: &head::Head) -> Result<OutputFormat, StatusCode> {
+    Ok(query_param(head, "format")?.unwrap_or(OutputFormat::Json))
+}
+
+// the reads are keyed by the uri (the endpoint and the params) and the head hash, so a new block
+// never gets the result of the previous head. The first read fetches on the blocking pool, the
+// identical reads await its result, they fail with it if the fetch panics
+fn coalesce<F>(state: &ApiState, head: &head::Head, fetch: F) -> impl futures::Future<Output = Result<String, StatusCode>>
+    where F: FnOnce(&ApiState) -> Result<String, StatusCode> + Send + 'static {
+    let key = format!("{}#{:?}", head.uri(), state.chain.get_last_hash());
+    let result = match state.reads.join(key) {
+        Ok(flight) => {
+            let fetch_state = state.clone();
+            state.blocking.run(move || {
+                let value = fetch(&fetch_state);
+                flight.finish(value.clone());
+                value
+            })
+        }
+        Err(shared) => shared,
+    };
+    result.map(|result| result.unwrap_or(Err(StatusCode::INTERNAL_SERVER_ERROR)))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WebhookRequest {
+    url: String,
+    #[serde(default)]
+    start_height: Option<Height>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SubscriptionRequest {
+    // the webhook of the blocks, a websocket connection reads the cursor without it
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    filter: SubscriptionFilter,
+    // the existing cursor keeps its position if it's absent, a new one starts from the next block
+    #[serde(default)]
+    position: Option<Height>,
+}
+
+/// the node status with the enabled feature set
+async fn status(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let chain: &Arc<Chain> = &state.0.chain;
+    let last_block = chain.get_last_block();
+    Ok(format.render(&json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol_version": PROTOCOL_VERSION,
+        "chain_id": chain.config.chain_id,
+        "genesis": chain.get_genesis().hash(),
+        "height": last_block.height(),
+        "block_hash": last_block.hash(),
+        "halted": chain.is_halted(),
+        "finalized_height": chain.last_checkpoint().map(|checkpoint| checkpoint.height),
+        "features": state.0.features,
+    })))
+}
+
+/// the liveness probe, the node is alive while the api and the p2p actors answer
+async fn health_live(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    if let Some(ref p2p) = state.0.p2p {
+        send_retry(p2p, GetPeers, &RequestPolicy::default()).map_err(request_status)?;
+    }
+    Ok(json!({"status": "live"}).to_string())
+}
+
+/// the readiness probe, it's 503 until the node can serve the chain, eg: a validator is synced
+/// and not paused
+async fn health_ready(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let peers = state.0.p2p.as_ref()
+        .and_then(|p2p| send_retry(p2p, GetPeers, &RequestPolicy::default()).ok())
+        .map(|peers| peers.len());
+    let minner = state.0.minner.as_ref()
+        .and_then(|minner| send_retry(minner, GetMinnerStatus, &RequestPolicy::default()).ok());
+    let checks = HealthChecks::new(&state.0.chain, peers, minner);
+    if !checks.ready() {
+        debug!("The node is not ready, failed checks: {:?}", checks.failures());
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    Ok(format.render(&checks))
+}
+
+/// the connected peers with their traffic statistics
+async fn peers(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let p2p = state.0.p2p.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    let peers = send_retry(p2p, GetPeers, &RequestPolicy::default()).map_err(request_status)?;
+    Ok(format.render(&page.slice(peers)))
+}
+
+/// the sync policy and the strategy chosen for every connected peer
+async fn sync_status(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let p2p = state.0.p2p.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let status = send_retry(p2p, GetSyncStatus, &RequestPolicy::default()).map_err(request_status)?;
+    Ok(format.render(&status))
+}
+
+/// switch the sync policy at runtime, eg: `{"archival": "full_blocks", "light": "disabled"}`
+async fn set_sync_policy(mut state: AppData<ApiState>, policy: body::Json<SyncPolicy>) -> Result<String, StatusCode> {
+    let p2p = state.0.p2p.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let status = send_retry(p2p, SetSyncPolicy(policy.0.clone()), &RequestPolicy::default())
+        .and_then(|_| send_retry(p2p, GetSyncStatus, &RequestPolicy::default()))
+        .map_err(request_status)?;
+    Ok(json!(status).to_string())
+}
+
+/// the blocks from the height `offset`, eg: `/blocks?offset=100&limit=10`
+async fn blocks(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let state: &Arc<Chain> = &state.chain;
+        let total = state.get_last_height() as usize + 1;
+        let range = page.range(total);
+        let blocks: Vec<Block> = if range.start < range.end {
+            state.iter_blocks(range.start as Height, range.end as Height - 1).collect()
+        } else {
+            vec![]
+        };
+        Ok(format.render(&page.wrap(total, blocks)))
+    }))
+}
+
+/// the headers from the height `offset`, eg: `/headers?offset=100&limit=10`
+async fn headers(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let state: &Arc<Chain> = &state.chain;
+        let total = state.get_last_height() as usize + 1;
+        let range = page.range(total);
+        let headers = if range.start < range.end {
+            state.get_headers(range.start as Height, range.end as Height - 1)
+        } else {
+            vec![]
+        };
+        Ok(format.render(&page.wrap(total, headers)))
+    }))
+}
+
+async fn header(mut state: AppData<ApiState>, height: head::Path<Height>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let state: &Arc<Chain> = &state.chain;
+        let header = state.get_header_by_height(height.0).ok_or(StatusCode::NOT_FOUND)?;
+        Ok(format.render(&header))
+    }))
+}
+
+/// the transactions of the index from `offset`, the page is read from the store
+async fn transactions(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let transactions = state.chain.get_transactions_page(page.offset, page.fetch_count());
+        Ok(format.render(&page.window(transactions)))
+    }))
+}
+
+async fn txpool_status(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let txpool = state.0.txpool.read();
+    Ok(format.render(&json!({
+        "pending": txpool.len(),
+        "capacity": txpool.capacity(),
+        "min_gas_price": txpool.min_gas_price(),
+    })))
+}
+
+/// the pool contents by sender, the pending transactions can be packed now, the queued ones wait
+/// for a missing nonce
+async fn txpool(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let txpool = state.0.txpool.read();
+    let chain: &Arc<Chain> = &state.0.chain;
+    let contents = TxPoolContents::new(&**txpool, |sender| chain.get_account(sender).nonce);
+    Ok(format.render(&contents))
+}
+
+/// drop a stuck transaction from the local pool, the other nodes keep their copies
+async fn drop_txpool_tx(mut state: AppData<ApiState>, tx_hash: head::Path<String>) -> Result<String, StatusCode> {
+    let tx_hash = hex::decode(tx_hash.0.trim_start_matches("0x")).ok()
+        .and_then(|bytes| Hash::from_slice(&bytes))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let tx = state.0.txpool.write().drop_tx(&tx_hash, "removed by the operator").ok_or(StatusCode::NOT_FOUND)?;
+    info!("Drop the transaction from the pool, hash: {:?}", tx_hash);
+    Ok(json!(tx).to_string())
+}
+
+/// the confirmed balance and nonce with the pending transactions of the pool, the pool lock is
+/// held while reading the state, so the transactions which were just committed are filtered by nonce
+async fn address_summary(mut state: AppData<ApiState>, address: head::Path<String>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let address = Address::from_str(address.0.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let txpool = state.0.txpool.read();
+    let account = state.0.chain.get_account(&address);
+    let summary = AccountSummary::new(address, account, &txpool.get_txs_by_sender(&address));
+    Ok(format.render(&summary))
+}
+
+/// the transactions which send to or from the address, eg: `/address/0x.../txs?offset=0&limit=100`
+async fn address_txs(mut state: AppData<ApiState>, address: head::Path<String>, head: head::Head) -> Result<String, StatusCode> {
+    let address = Address::from_str(address.0.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let format = output_format(&head)?;
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    let chain: &Arc<Chain> = &state.0.chain;
+    let items: Vec<serde_json::Value> = chain.get_address_txs_page(&address, page.offset, page.fetch_count()).into_iter()
+        .map(|(height, tx_hash)| json!({"height": height, "hash": tx_hash}))
+        .collect();
+    if !page.versioned {
+        // the old shape of the route
+        let mut items = items;
+        items.truncate(page.limit);
+        let total = chain.count_address_txs(&address);
+        return Ok(format.render(&json!({"address": address, "total": total, "transactions": items})));
+    }
+    Ok(format.render(&page.window(items)))
+}
+
+/// the block rewards paid to the address, eg: `/address/0x.../rewards?offset=0&limit=100`
+async fn address_rewards(mut state: AppData<ApiState>, address: head::Path<String>, head: head::Head) -> Result<String, StatusCode> {
+    let address = Address::from_str(address.0.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let format = output_format(&head)?;
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    let items: Vec<serde_json::Value> = state.0.chain.get_rewards_page(&address, page.offset, page.fetch_count()).into_iter()
+        .map(|(height, amount)| json!({"height": height, "amount": amount}))
+        .collect();
+    Ok(format.render(&page.window(items)))
+}
+
+/// the confirmed balance, nonce and last active height of the account, eg: `/account/0x...?height=100`
+async fn account(mut state: AppData<ApiState>, address: head::Path<String>, head: head::Head) -> Result<String, StatusCode> {
+    let address = Address::from_str(address.0.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let format = output_format(&head)?;
+    let height: Option<Height> = query_param(&head, "height")?;
+    let account = state.0.chain.get_account_state(&address, height).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(format.render(&account))
+}
+
+/// the pool floor is the lowest gas price that the pool accepts now
+async fn estimate_fee(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let txpool = state.0.txpool.read();
+    Ok(format.render(&json!({
+        "min_gas_price": txpool.min_gas_price(),
+    })))
+}
+
+/// prometheus text format metrics
+async fn metrics(_state: AppData<ApiState>) -> String {
+    crate::metrics::gather()
+}
+
+/// start a profile, the spans are recorded until it's stopped
+async fn start_profile(_state: AppData<ApiState>) -> Result<String, StatusCode> {
+    let started = pprof::start_profile().map_err(|err| {
+        debug!("Failed to start the profile, err: {}", err);
+        StatusCode::CONFLICT
+    })?;
+    Ok(json!({"started": started, "dir": pprof::output_dir()}).to_string())
+}
+
+/// stop the profile and return its flame graph, the graph is kept in the output directory, it's
+/// written and read back on the blocking pool
+async fn stop_profile(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    let stop = state.0.blocking.run(pprof::stop_profile);
+    match await!(stop).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? {
+        Ok((_, html)) => Ok(html),
+        Err(err) => {
+            debug!("Failed to stop the profile, err: {}", err);
+            Err(StatusCode::CONFLICT)
+        }
+    }
+}
+
+async fn heap_stats(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let read = state.0.blocking.run(HeapStats::read);
+    let stats = await!(read).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(format.render(&stats))
+}
+
+/// the last disk usage accounting of the store, it's unavailable before the first one
+async fn store_usage(state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let usage = last_usage(&state.0.chain.config.store).ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(format.render(&usage))
+}
+
+async fn mailbox_stats(_state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    Ok(format.render(&MailboxStats::gather()))
+}
+
+/// the latency of pbft phases, it helps operators to tune round timeouts
+async fn consensus_timings(_state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let mut timings = serde_json::Map::new();
+    for phase in CONSENSUS_PHASES.iter() {
+        let summary = crate::metrics::REGISTRY.histogram_summary(&phase_metric_name(phase));
+        timings.insert(phase.to_string(), json!(summary));
+    }
+    Ok(format.render(&json!({
+        "timings": timings,
+        "round_timeouts": crate::metrics::REGISTRY.counter("consensus_round_timeout_total"),
+    })))
+}
+
+/// produce N blocks immediately, `interval` is the millis between two blocks
+async fn dev_mine(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    if !state.0.dev {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let minner = state.0.minner.clone().ok_or(StatusCode::NOT_FOUND)?;
+    let blocks: u64 = query_param(&head, "blocks")?.unwrap_or(1);
+    let interval: u64 = query_param(&head, "interval")?.unwrap_or(0);
+    // the blocks are produced before the reply
+    let timeout = DEFAULT_REQUEST_TIMEOUT + Duration::from_millis(interval.saturating_mul(blocks));
+    match send_wait(&minner, MineBlocks { blocks, interval: Duration::from_millis(interval) }, timeout) {
+        Ok(Ok(height)) => Ok(json!({"blocks": blocks, "height": height}).to_string()),
+        Ok(Err(err)) => {
+            warn!("Failed to mine blocks on demand, err: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(err) => Err(request_status(err)),
+    }
+}
+
+fn minner(state: &ApiState) -> Result<&Addr<Minner>, StatusCode> {
+    state.minner.as_ref().ok_or(StatusCode::NOT_FOUND)
+}
+
+// the stopped actor is unavailable, the busy actor is a gateway timeout
+fn request_status(err: RequestError) -> StatusCode {
+    warn!("Failed to request the actor, err: {}", err);
+    match err {
+        RequestError::Closed => StatusCode::SERVICE_UNAVAILABLE,
+        RequestError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+    }
+}
+
+fn minner_status(result: Result<MinnerStatus, RequestError>) -> Result<String, StatusCode> {
+    result
+        .map(|status| serde_json::to_string(&status).unwrap())
+        .map_err(request_status)
+}
+
+async fn get_minner(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    minner_status(send_retry(minner(&state.0)?, GetMinnerStatus, &RequestPolicy::default()))
+}
+
+/// halt the block production, eg: during maintenance
+async fn pause_minner(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    minner_status(send_wait(minner(&state.0)?, Pause, DEFAULT_REQUEST_TIMEOUT))
+}
+
+async fn resume_minner(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    minner_status(send_wait(minner(&state.0)?, Resume, DEFAULT_REQUEST_TIMEOUT))
+}
+
+/// switch the mining mode, eg: `?mode=on_demand`
+async fn set_mining_mode(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let mode: MiningMode = query_param(&head, "mode")?.ok_or(StatusCode::BAD_REQUEST)?;
+    minner_status(send_wait(minner(&state.0)?, SetMiningMode(mode), DEFAULT_REQUEST_TIMEOUT))
+}
+
+/// propose a block now, it only makes sense in the on-demand mode
+async fn trigger_minner(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    minner_status(send_wait(minner(&state.0)?, TriggerMine, DEFAULT_REQUEST_TIMEOUT))
+}
+
+/// the block which is active at the unix time, eg: `/block/at-time/1546300800`
+async fn block_at_time(mut state: AppData<ApiState>, time: head::Path<Timestamp>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let state: &Arc<Chain> = &state.chain;
+        let block = state.get_block_at_time(time.0).ok_or(StatusCode::NOT_FOUND)?;
+        Ok(format.render(&block))
+    }))
+}
+
+/// the prepare and commit messages which committed the block at the height
+async fn block_consensus(mut state: AppData<ApiState>, height: head::Path<Height>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let state: &Arc<Chain> = &state.chain;
+        let messages = state.get_consensus_messages(height.0).ok_or(StatusCode::NOT_FOUND)?;
+        let messages: Vec<serde_json::Value> = messages.0.into_iter().map(|mut message| {
+            let address = message.address().map(|address| format!("{:?}", address)).ok();
+            json!({
+                "code": message.code,
+                "create_time": message.create_time,
+                "address": address,
+                "signature": message.signature,
+                "commit_seal": message.commit_seal,
+            })
+        }).collect();
+        Ok(format.render(&json!({"height": height.0, "messages": messages})))
+    }))
+}
+
+/// the commit certificate of the block at the height, the signers are recovered from the seals
+async fn block_certificate(mut state: AppData<ApiState>, height: head::Path<Height>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let chain: &Arc<Chain> = &state.0.chain;
+    let certificate = chain.get_commit_certificate(height.0).ok_or(StatusCode::NOT_FOUND)?;
+    let signers = certificate.signers().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(format.render(&json!({"certificate": certificate, "signers": signers})))
+}
+
+// the max headers of a light client request
+const LIGHT_HEADERS_LIMIT: Height = 1000;
+
+/// the headers with their commit seals for `light::verify_header_chain`, eg: `?from=1&to=100`
+async fn light_headers(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let (from, to): (Option<Height>, Option<Height>) = (query_param(&head, "from")?, query_param(&head, "to")?);
+    await!(coalesce(&state.0, &head, move |state| {
+        let chain: &Arc<Chain> = &state.chain;
+        let last_height = chain.get_last_height();
+        let (from, to) = (from.unwrap_or(0), to.unwrap_or(last_height).min(last_height));
+        if from > to || to - from >= LIGHT_HEADERS_LIMIT {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(format.render(&chain.get_headers(from, to)))
+    }))
+}
+
+// the max blocks scanned by a log query
+const LOGS_RANGE_LIMIT: Height = 10_000;
+
+/// the logs of the blocks `from..=to` which are emitted by the address and carry the topic, the
+/// blocks whose bloom can't match are skipped, eg: `/logs?from=1&to=100&address=0x...&topic=0x...`
+async fn logs(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let (from, to): (Option<Height>, Option<Height>) = (query_param(&head, "from")?, query_param(&head, "to")?);
+    let address = match query_param::<String>(&head, "address")? {
+        Some(address) => Some(Address::from_str(address.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let topic = match query_param::<String>(&head, "topic")? {
+        Some(topic) => Some(hex::decode(topic.trim_start_matches("0x")).ok()
+            .and_then(|bytes| Hash::from_slice(&bytes))
+            .ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    await!(coalesce(&state.0, &head, move |state| {
+        let chain: &Arc<Chain> = &state.chain;
+        let last_height = chain.get_last_height();
+        let (from, to) = (from.unwrap_or(0), to.unwrap_or(last_height).min(last_height));
+        if from > to || to - from >= LOGS_RANGE_LIMIT {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let filter = LogFilter { from, to, address, topic };
+        Ok(format.render(&chain.get_logs(&filter, state.max_page_size)))
+    }))
+}
+
+/// the state root, the validator set and optionally an account at a historical height, eg:
+/// `/state/100?address=0x...`, the pruned heights are gone
+async fn state_at(mut state: AppData<ApiState>, height: head::Path<Height>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let chain: &Arc<Chain> = &state.0.chain;
+    if height.0 > chain.get_last_height() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let address = match query_param::<String>(&head, "address")? {
+        Some(address) => Some(Address::from_str(address.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    // the replay holds the ledger read lock for a while, it runs off the executor
+    let (replay_chain, replay_height) = (chain.clone(), height.0);
+    let replay = state.0.blocking.run(move || replay_chain.state_at(replay_height));
+    let view = await!(replay).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.map_err(|err| {
+        debug!("Failed to read the state, height: {}, err: {}", height.0, err);
+        StatusCode::GONE
+    })?;
+    let account = address.map(|address| json!({"address": address, "account": view.account(&address)}));
+    Ok(format.render(&json!({
+        "height": view.height,
+        "root": view.root(),
+        "accounts": view.accounts().len(),
+        "validators": view.validators(),
+        "account": account,
+    })))
+}
+
+/// the stake bonded to the validator with the locked unbonded stakes, eg: `/staking/0x...`
+async fn staking(mut state: AppData<ApiState>, validator: head::Path<String>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let validator = Address::from_str(validator.0.trim_start_matches("0x")).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let chain: &Arc<Chain> = &state.0.chain;
+    let last_height = chain.get_last_height();
+    let stake = chain.get_stake(&validator);
+    let unbonding: Vec<_> = stake.unbonding.iter().filter(|unbonding| unbonding.release > last_height).collect();
+    Ok(format.render(&json!({
+        "validator": validator,
+        "height": last_height,
+        "stake": stake.total(),
+        "bonds": stake.bonds,
+        "unbonding": unbonding,
+    })))
+}
+
+/// the json-rpc reads, a batch (an array of calls) fetches many items per round-trip, eg:
+/// `[{"jsonrpc": "2.0", "id": 1, "method": "get_block_by_height", "params": [100]}, ...]`
+async fn rpc(mut state: AppData<ApiState>, request: body::Json<serde_json::Value>) -> Result<String, StatusCode> {
+    let chain: &Arc<Chain> = &state.0.chain;
+    Ok(rpc::handle_request(&request.0, state.0.max_batch_size, |method, params| rpc::execute(chain, method, params)).to_string())
+}
+
+/// the validator addresses at the height
+async fn light_validators(mut state: AppData<ApiState>, height: head::Path<Height>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let chain: &Arc<Chain> = &state.0.chain;
+    if height.0 > chain.get_last_height() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let validators = chain.get_validator_addresses(height.0);
+    Ok(format.render(&json!({"height": height.0, "validators": validators})))
+}
+
+/// the inclusion proof of a transaction in the block at the height, eg: `/light/proof/10?tx=0x...`
+async fn light_transaction_proof(mut state: AppData<ApiState>, height: head::Path<Height>, head: head::Head) -> Result<String, StatusCode> {
+    let format = output_format(&head)?;
+    let tx_hash: String = query_param(&head, "tx")?.ok_or(StatusCode::BAD_REQUEST)?;
+    let tx_hash = hex::decode(tx_hash.trim_start_matches("0x")).ok()
+        .and_then(|bytes| Hash::from_slice(&bytes))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    await!(coalesce(&state.0, &head, move |state| {
+        let chain: &Arc<Chain> = &state.chain;
+        let block = chain.get_block_by_height(height.0).ok_or(StatusCode::NOT_FOUND)?;
+        let proof = TransactionProof::new(block.header().clone(), block.transactions().clone(), &tx_hash).ok_or(StatusCode::NOT_FOUND)?;
+        Ok(format.render(&proof))
+    }))
+}
+
+/// subscribe the committed blocks with a webhook, the historical blocks from `start_height` are
+/// posted before the live ones. It's the durable subscription named after the url, eg:
+/// `webhook-1f2e3d4c5b6a7988`, so it's resumed after a restart and can be deleted by its name
+async fn subscribe_webhook(mut state: AppData<ApiState>, request: body::Json<WebhookRequest>) -> Result<String, StatusCode> {
+    let manager = subscription_manager(&state.0)?;
+    let request: WebhookRequest = request.0;
+    if !is_http_url(&request.url) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let start_height = request.start_height.unwrap_or_else(|| state.0.chain.get_last_height() + 1);
+    let name = webhook_name(&request.url);
+    let cursor = SubscriptionCursor { name: name.clone(), url: Some(request.url.clone()), filter: SubscriptionFilter::default(), position: start_height, failures: 0 };
+    manager.put(cursor);
+    Ok(json!({"name": name, "url": request.url, "start_height": start_height}).to_string())
+}
+
+// the webhook of the url is put again instead of posting every block twice
+fn webhook_name(url: &str) -> String {
+    format!("webhook-{}", hex::encode(&hash(url.as_bytes()).as_ref()[..8]))
+}
+
+fn subscription_manager(state: &ApiState) -> Result<&Arc<SubscriptionManager>, StatusCode> {
+    state.subscriptions.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+/// the durable subscriptions with their cursors
+async fn subscriptions(mut state: AppData<ApiState>, head: head::Head) -> Result<String, StatusCode> {
+    let page = Page::parse(&head, state.0.max_page_size)?;
+    Ok(json!(page.slice(load_cursors(&state.0.chain))).to_string())
+}
+
+async fn get_subscription(mut state: AppData<ApiState>, name: head::Path<String>) -> Result<String, StatusCode> {
+    let cursor = load_cursor(&state.0.chain, &name.0).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(json!(cursor).to_string())
+}
+
+/// create or replace the named subscription, the delivery resumes from its position after
+/// the node or the receiver restarts, eg: `PUT /subscriptions/indexer {"url": "http://...", "position": 100}`,
+/// without the url the cursor is read by a websocket `cursor` subscription
+async fn put_subscription(mut state: AppData<ApiState>, name: head::Path<String>, request: body::Json<SubscriptionRequest>) -> Result<String, StatusCode> {
+    let manager = subscription_manager(&state.0)?;
+    let request: SubscriptionRequest = request.0;
+    if request.url.as_ref().map_or(false, |url| !is_http_url(url)) || request.filter.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let chain: &Arc<Chain> = &state.0.chain;
+    let position = request.position
+        .or_else(|| load_cursor(chain, &name.0).map(|cursor| cursor.position))
+        .unwrap_or_else(|| chain.get_last_height() + 1);
+    let cursor = SubscriptionCursor { name: name.0.clone(), url: request.url, filter: request.filter, position, failures: 0 };
+    manager.put(cursor.clone());
+    Ok(json!(cursor).to_string())
+}
+
+async fn delete_subscription(mut state: AppData<ApiState>, name: head::Path<String>) -> Result<String, StatusCode> {
+    let cursor = subscription_manager(&state.0)?.remove(&name.0).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(json!(cursor).to_string())
+}
+
+/// serve the api on `ip:port`, it blocks the thread, see `bind_api`
+pub fn start_api(state: ApiState, ip: String, port: u16, config: ApiConfig) -> Result<(), ApiError> {
+    bind_api(state, ip, port, config)?.serve();
+    Ok(())
+}
+
+/// ApiServer is the api whose address is bound, `serve` blocks the thread
+pub struct ApiServer {
+    app: App<ApiState>,
+    // the bound listener, the connections are served on it in the process
+    listener: ::std::net::TcpListener,
+    // the certificate, the connections are served over tls with it
+    tls: Option<Arc<ServerConfig>>,
+    // the server without it serves until the process exits
+    shutdown: Option<ShutdownSignal>,
+}
+
+impl ApiServer {
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|err| err.to_string())
+    }
+
+    /// stop serving when the shutdown is shut down, the listener and the state are dropped then
+    pub fn with_shutdown(mut self, shutdown: &Shutdown) -> Self {
+        self.shutdown = Some(shutdown.signal());
+        self
+    }
+
+    pub fn serve(self) {
+        let ApiServer { app, listener, tls, shutdown } = self;
+        let address = listener.local_addr().map(|address| address.to_string()).unwrap_or_default();
+        let result = match tls {
+            Some(config) => {
+                info!("Serve the api over tls, address: {}", address);
+                tls::serve_tls(config, listener, app.into_server(), shutdown)
+            }
+            None => tls::serve_plain(listener, app.into_server(), shutdown),
+        };
+        match result {
+            Ok(()) => info!("The api is shut down, address: {}", address),
+            Err(err) => error!("Failed to serve the api, address: {}, err: {}", address, err),
+        }
+    }
+}
+
+/// the tls config of the api and the websocket subscriptions, they're served over tls when both
+/// `tls_cert` and `tls_key` are set
+pub fn api_tls_config(config: &ApiConfig) -> Result<Option<Arc<ServerConfig>>, ApiError> {
+    match (config.tls_cert.as_ref(), config.tls_key.as_ref()) {
+        (Some(cert), Some(key)) => tls::load_tls_config(cert, key).map(Some).map_err(ApiError::Tls),
+        (None, None) => Ok(None),
+        _ => Err(ApiError::Tls("the api tls needs both `tls_cert` and `tls_key`".to_string())),
+    }
+}
+
+/// build the api of `ip:port` and bind its address, so the node fails to start when the address
+/// is taken instead of serving without the api. The bound listener serves the connections, the
+/// https ones with the tls of the config
+pub fn bind_api(state: ApiState, ip: String, port: u16, config: ApiConfig) -> Result<ApiServer, ApiError> {
+    let tls = api_tls_config(&config)?;
+    // the inspector runs without the minner
+    let read_only = state.minner.is_none();
+    let mut app = App::new(state);
+    // the refused requests are counted too, the routes below label them
+    let routes = RouteTemplates::default();
+    app.middleware(RequestMetrics::new(routes.clone()));
+    // the preflight requests are answered before the other checks
+    if !config.cors_origins.is_empty() {
+        app.middleware(Cors::new(config.cors_origins.clone()));
+    }
+    app.middleware(BodyLimit::new(config.max_body_size));
+    app.middleware(AdminAuth::new(config.admin_token.clone()));
+    app.at(routes.add("/status")).get(status);
+    app.at(routes.add("/health/live")).get(health_live);
+    app.at(routes.add("/health/ready")).get(health_ready);
+    app.at(routes.add("/peers")).get(peers);
+    app.at(routes.add("/v1/peers")).get(peers);
+    app.at(routes.add("/status/sync")).get(sync_status);
+    app.at(routes.add("/blocks")).get(blocks);
+    app.at(routes.add("/v1/blocks")).get(blocks);
+    app.at(routes.add("/headers")).get(headers);
+    app.at(routes.add("/v1/headers")).get(headers);
+    app.at(routes.add("/header/{}")).get(header);
+    app.at(routes.add("/transactions")).get(transactions);
+    app.at(routes.add("/v1/transactions")).get(transactions);
+    app.at(routes.add("/txpool")).get(txpool);
+    app.at(routes.add("/txpool/status")).get(txpool_status);
+    app.at(routes.add("/fee/estimate")).get(estimate_fee);
+    app.at(routes.add("/address/{}/summary")).get(address_summary);
+    app.at(routes.add("/address/{}/txs")).get(address_txs);
+    app.at(routes.add("/v1/address/{}/txs")).get(address_txs);
+    app.at(routes.add("/address/{}/rewards")).get(address_rewards);
+    app.at(routes.add("/v1/address/{}/rewards")).get(address_rewards);
+    app.at(routes.add("/account/{}")).get(account);
+    app.at(routes.add("/metrics")).get(metrics);
+    app.at(routes.add("/consensus/timings")).get(consensus_timings);
+    app.at(routes.add("/block/{}/consensus")).get(block_consensus);
+    app.at(routes.add("/block/{}/certificate")).get(block_certificate);
+    app.at(routes.add("/block/at-time/{}")).get(block_at_time);
+    app.at(routes.add("/logs")).get(logs);
+    app.at(routes.add("/state/{}")).get(state_at);
+    app.at(routes.add("/staking/{}")).get(staking);
+    app.at(routes.add("/light/headers")).get(light_headers);
+    app.at(routes.add("/light/validators/{}")).get(light_validators);
+    app.at(routes.add("/light/proof/{}")).get(light_transaction_proof);
+    app.at(routes.add("/store/usage")).get(store_usage);
+    // the read-only inspector serves the reads of the store only
+    if !read_only {
+        app.at(routes.add("/rpc")).post(rpc);
+        app.at(routes.add("/debug/pprof/start")).post(start_profile);
+        app.at(routes.add("/debug/pprof/stop")).post(stop_profile);
+        app.at(routes.add("/debug/pprof/heap")).get(heap_stats);
+        app.at(routes.add("/debug/pprof/mailboxes")).get(mailbox_stats);
+        app.at(routes.add("/subscriptions/webhook")).post(subscribe_webhook);
+        app.at(routes.add("/subscriptions")).get(subscriptions);
+        app.at(routes.add("/v1/subscriptions")).get(subscriptions);
+        let mut subscription = app.at(routes.add("/subscriptions/{}"));
+        subscription.get(get_subscription);
+        subscription.put(put_subscription);
+        subscription.delete(delete_subscription);
+        app.at(routes.add("/txpool/{}")).delete(drop_txpool_tx);
+        app.at(routes.add("/dev/mine")).post(dev_mine);
+        app.at(routes.add("/minner")).get(get_minner);
+        app.at(routes.add("/minner/pause")).post(pause_minner);
+        app.at(routes.add("/minner/resume")).post(resume_minner);
+        app.at(routes.add("/minner/mode")).post(set_mining_mode);
+        app.at(routes.add("/minner/trigger")).post(trigger_minner);
+        app.at(routes.add("/sync/policy")).post(set_sync_policy);
+    }
+    let listen = SocketAddr::from_str(&format!("{}:{}", ip, port)).map_err(|err| ApiError::InvalidAddress(format!("{}:{}, {}", ip, port, err)))?;
+    // the bound listener is served, no other process can take the address before the api
+    let listener = ::std::net::TcpListener::bind(listen).map_err(|err| ApiError::Bind(format!("{}, {}", listen, err)))?;
+    Ok(ApiServer { app, listener, tls, shutdown: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_bind_api() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::thread;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::core::test_util::memory_chain;
+        use crate::core::tx_pool::BaseTxPool;
+
+        let _system = System::new("t_bind_api");
+        let key_pair = Random.generate().unwrap();
+        let txpool: Arc<RwLock<SafeTxPool>> = Arc::new(RwLock::new(Box::new(BaseTxPool::new())));
+        let state = ApiState { chain: memory_chain(&key_pair), txpool, minner: None, p2p: None, dev: false, features: vec![], reads: Default::default(), blocking: Default::default(), subscriptions: None, max_page_size: DEFAULT_MAX_PAGE_SIZE, max_batch_size: DEFAULT_MAX_BATCH_SIZE };
+        let server = bind_api(state.clone(), "127.0.0.1".to_owned(), 0, ApiConfig::default()).unwrap();
+        let listen = server.local_addr().unwrap();
+        // the bound listener is served, the address is never released before it
+        assert!(bind_api(state, "127.0.0.1".to_owned(), listen.port(), ApiConfig::default()).is_err());
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(listen).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        write!(stream, "GET /health/live HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", listen).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("live"));
+    }
+}
@@ -0,0 +1,146 @@
+use std::ops::Range;
+
+use http::StatusCode;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use tide::head;
+
+use super::query_param;
+
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+pub const DEFAULT_MAX_PAGE_SIZE: usize = 1000;
+
+/// the prefix of the versioned list apis, eg: `/v1/blocks`, they reply the `Paged` envelope
+pub const PAGE_PREFIX: &str = "/v1";
+pub const PAGE_VERSION: u32 = 1;
+
+/// Page is the standard `?offset=0&limit=100` of the list apis, the limit is capped by the
+/// configured max page size. The unversioned routes keep their old shape, the bare items of the
+/// page, so the existing clients don't break
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: usize,
+    pub versioned: bool,
+}
+
+impl Page {
+    pub fn parse(head: &head::Head, max_page_size: usize) -> Result<Page, StatusCode> {
+        let offset: usize = query_param(head, "offset")?.unwrap_or(0);
+        let limit: usize = query_param(head, "limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if limit == 0 {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let versioned = head.uri().path().starts_with(&format!("{}/", PAGE_PREFIX));
+        Ok(Page { offset, limit: limit.min(max_page_size), versioned })
+    }
+
+    /// the range of the page in a list of `total` items
+    pub fn range(&self, total: usize) -> Range<usize> {
+        let start = self.offset.min(total);
+        start..total.min(start.saturating_add(self.limit))
+    }
+
+    /// the items to read from the store, one more than the limit tells if there are more pages
+    pub fn fetch_count(&self) -> usize {
+        self.limit.saturating_add(1)
+    }
+
+    /// the page of the items, `total` is the size of the whole list
+    pub fn wrap<T>(&self, total: usize, items: Vec<T>) -> Paged<T> {
+        let more = self.range(total).end < total;
+        Paged { versioned: self.versioned, total: Some(total), offset: self.offset, limit: self.limit, more, items }
+    }
+
+    /// the page of the items which were read from the store at the offset, at most `fetch_count`,
+    /// the total isn't counted, it needs a scan of the whole index
+    pub fn window<T>(&self, mut items: Vec<T>) -> Paged<T> {
+        let more = items.len() > self.limit;
+        items.truncate(self.limit);
+        Paged { versioned: self.versioned, total: None, offset: self.offset, limit: self.limit, more, items }
+    }
+
+    /// the page of a small list in memory, eg: the peers
+    pub fn slice<T>(&self, items: Vec<T>) -> Paged<T> {
+        let total = items.len();
+        let range = self.range(total);
+        self.wrap(total, items.into_iter().skip(range.start).take(range.end - range.start).collect())
+    }
+}
+
+/// Paged is the envelope of every versioned list reply, eg:
+/// `{"version": 1, "offset": 0, "limit": 100, "total": 10, "more": false, "items": [...]}`,
+/// the unversioned routes reply the items only
+#[derive(Debug, Clone)]
+pub struct Paged<T> {
+    pub versioned: bool,
+    pub total: Option<usize>,
+    pub offset: usize,
+    pub limit: usize,
+    pub more: bool,
+    pub items: Vec<T>,
+}
+
+impl<T: Serialize> Serialize for Paged<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.versioned {
+            return self.items.serialize(serializer);
+        }
+        let mut envelope = serializer.serialize_struct("Paged", 6)?;
+        envelope.serialize_field("version", &PAGE_VERSION)?;
+        envelope.serialize_field("offset", &self.offset)?;
+        envelope.serialize_field("limit", &self.limit)?;
+        if let Some(total) = self.total {
+            envelope.serialize_field("total", &total)?;
+        } else {
+            envelope.skip_field("total")?;
+        }
+        envelope.serialize_field("more", &self.more)?;
+        envelope.serialize_field("items", &self.items)?;
+        envelope.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_page() {
+        let page = Page { offset: 8, limit: 5, versioned: true };
+        assert_eq!(page.range(10), 8..10);
+        assert_eq!(page.range(4), 4..4);
+        assert_eq!(page.range(100), 8..13);
+
+        let paged = page.slice((0..10).collect::<Vec<u32>>());
+        assert_eq!((paged.total, paged.more, paged.items), (Some(10), false, vec![8, 9]));
+        let paged = Page { offset: usize::max_value(), limit: 5, versioned: true }.slice(vec![1, 2, 3]);
+        assert!(paged.items.is_empty());
+        assert!(page.wrap(100, vec![8, 9, 10, 11, 12]).more);
+    }
+
+    #[test]
+    fn t_page_window() {
+        let page = Page { offset: 10, limit: 2, versioned: true };
+        assert_eq!(page.fetch_count(), 3);
+        let paged = page.window(vec![10, 11, 12]);
+        assert_eq!((paged.total, paged.more, paged.items), (None, true, vec![10, 11]));
+        let paged = page.window(vec![10]);
+        assert_eq!((paged.more, paged.items), (false, vec![10]));
+    }
+
+    // the versioned routes reply the envelope, the old routes the bare items
+    #[test]
+    fn t_paged_shape() {
+        let page = Page { offset: 0, limit: 2, versioned: true };
+        assert_eq!(
+            serde_json::to_value(page.slice(vec![1, 2, 3])).unwrap(),
+            json!({"version": PAGE_VERSION, "offset": 0, "limit": 2, "total": 3, "more": true, "items": [1, 2]}),
+        );
+        assert_eq!(
+            serde_json::to_value(page.window(vec![1])).unwrap(),
+            json!({"version": PAGE_VERSION, "offset": 0, "limit": 2, "more": false, "items": [1]}),
+        );
+        let legacy = Page { versioned: false, ..page };
+        assert_eq!(serde_json::to_value(legacy.slice(vec![1, 2, 3])).unwrap(), json!([1, 2]));
+    }
+}
@@ -0,0 +1,160 @@
+use std::str::FromStr;
+use std::time::Instant;
+
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::ethkey::Address;
+use serde_json::Value;
+
+use crate::core::chain::Chain;
+use crate::metrics;
+use crate::types::Height;
+
+// the calls of a batch request, eg: an indexer fetches 100 blocks per round-trip
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// the error object of a json-rpc response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        RpcError { code, message: message.to_string() }
+    }
+}
+
+// the method, the params and the id of a call
+fn parse_call(call: &Value) -> Result<(String, Vec<Value>), RpcError> {
+    let method = call.get("method").and_then(|method| method.as_str())
+        .ok_or(RpcError::new(INVALID_REQUEST, "the method is missing"))?;
+    let params = match call.get("params") {
+        None | Some(Value::Null) => vec![],
+        Some(Value::Array(params)) => params.clone(),
+        Some(_) => return Err(RpcError::new(INVALID_PARAMS, "the params must be an array")),
+    };
+    Ok((method.to_string(), params))
+}
+
+fn respond<F>(call: &Value, execute: &F) -> Value
+    where F: Fn(&str, &[Value]) -> Result<Value, RpcError> {
+    let id = call.get("id").cloned().unwrap_or(Value::Null);
+    let result = parse_call(call).and_then(|(method, params)| {
+        let start = Instant::now();
+        let result = execute(&method, &params);
+        let elapsed = start.elapsed();
+        let millis = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_micros() as f64 / 1000.0;
+        // the unknown methods are not labeled, the label doesn't grow with the requests
+        let label = if result.as_ref().err().map(|err| err.code) == Some(METHOD_NOT_FOUND) { "unknown" } else { method.as_str() };
+        metrics::inc_counter(&format!("api_rpc_calls_total{{method=\"{}\"}}", label));
+        metrics::observe(&format!("api_rpc_duration_ms{{method=\"{}\"}}", label), millis);
+        if result.is_err() {
+            metrics::inc_counter(&format!("api_rpc_errors_total{{method=\"{}\"}}", label));
+        }
+        result
+    });
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(err) => json!({"jsonrpc": "2.0", "id": id, "error": err}),
+    }
+}
+
+/// answer a json-rpc request, a batch (an array of calls) is answered by an array in the same
+/// order, at most `max_batch_size` calls per batch
+pub fn handle_request<F>(request: &Value, max_batch_size: usize, execute: F) -> Value
+    where F: Fn(&str, &[Value]) -> Result<Value, RpcError> {
+    match request {
+        Value::Array(calls) if calls.is_empty() => {
+            json!({"jsonrpc": "2.0", "id": null, "error": RpcError::new(INVALID_REQUEST, "the batch is empty")})
+        }
+        Value::Array(calls) if calls.len() > max_batch_size => {
+            metrics::inc_counter("api_rpc_refused_batches_total");
+            json!({"jsonrpc": "2.0", "id": null, "error": RpcError::new(INVALID_REQUEST, &format!("the batch is over {} calls", max_batch_size))})
+        }
+        Value::Array(calls) => {
+            metrics::inc_counter("api_rpc_batches_total");
+            Value::Array(calls.iter().map(|call| respond(call, &execute)).collect())
+        }
+        Value::Object(_) => respond(request, &execute),
+        _ => json!({"jsonrpc": "2.0", "id": null, "error": RpcError::new(PARSE_ERROR, "the request must be an object or an array")}),
+    }
+}
+
+fn height_param(params: &[Value], idx: usize) -> Result<Height, RpcError> {
+    params.get(idx).and_then(|height| height.as_u64()).ok_or(RpcError::new(INVALID_PARAMS, "expect a height"))
+}
+
+fn hash_param(params: &[Value], idx: usize) -> Result<Hash, RpcError> {
+    params.get(idx).and_then(|hash| hash.as_str())
+        .and_then(|hash| hex::decode(hash.trim_start_matches("0x")).ok())
+        .and_then(|bytes| Hash::from_slice(&bytes))
+        .ok_or(RpcError::new(INVALID_PARAMS, "expect a hash"))
+}
+
+fn address_param(params: &[Value], idx: usize) -> Result<Address, RpcError> {
+    params.get(idx).and_then(|address| address.as_str())
+        .and_then(|address| Address::from_str(address.trim_start_matches("0x")).ok())
+        .ok_or(RpcError::new(INVALID_PARAMS, "expect an address"))
+}
+
+/// the read methods of the chain, the missing items are null, eg:
+/// `{"jsonrpc": "2.0", "id": 1, "method": "get_block_by_height", "params": [100]}`
+pub fn execute(chain: &Chain, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    let value = match method {
+        "get_last_height" => json!(chain.get_last_height()),
+        "get_block_by_height" => json!(chain.get_block_by_height(height_param(params, 0)?)),
+        "get_block_by_hash" => json!(chain.get_block_by_hash(&hash_param(params, 0)?)),
+        "get_header_by_height" => json!(chain.get_header_by_height(height_param(params, 0)?)),
+        "get_header_by_hash" => json!(chain.get_header_by_hash(&hash_param(params, 0)?)),
+        "get_receipts" => json!(chain.get_receipts(height_param(params, 0)?)),
+        "get_account" => {
+            let height = match params.get(1) {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(height_param(params, 1)?),
+            };
+            json!(chain.get_account_state(&address_param(params, 0)?, height))
+        }
+        _ => return Err(RpcError::new(METHOD_NOT_FOUND, &format!("unknown method {}", method))),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "echo" => Ok(Value::Array(params.to_vec())),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "unknown method")),
+        }
+    }
+
+    #[test]
+    fn t_handle_request() {
+        let response = handle_request(&json!({"jsonrpc": "2.0", "id": 7, "method": "echo", "params": [1]}), 2, echo);
+        assert_eq!(response, json!({"jsonrpc": "2.0", "id": 7, "result": [1]}));
+
+        let batch = json!([
+            {"id": 1, "method": "echo", "params": [1]},
+            {"id": 2, "method": "nope"},
+        ]);
+        let response = handle_request(&batch, 2, echo);
+        assert_eq!(response[0]["result"], json!([1]));
+        assert_eq!(response[1]["error"]["code"], json!(METHOD_NOT_FOUND));
+        assert_eq!(response[1]["id"], json!(2));
+
+        // over the batch size
+        let batch = json!([{"method": "echo"}, {"method": "echo"}, {"method": "echo"}]);
+        assert_eq!(handle_request(&batch, 2, echo)["error"]["code"], json!(INVALID_REQUEST));
+        assert_eq!(handle_request(&json!([]), 2, echo)["error"]["code"], json!(INVALID_REQUEST));
+        assert_eq!(handle_request(&json!({"id": 1, "method": "echo", "params": 1}), 2, echo)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(handle_request(&json!("echo"), 2, echo)["error"]["code"], json!(PARSE_ERROR));
+    }
+}
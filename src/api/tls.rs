@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tide::Server;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::prelude::{future, Future, Stream};
+use tokio::reactor::Handle;
+use tokio::sync::oneshot;
+use tokio::timer::Timeout;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::metrics;
+
+// the handshakes in progress, the next connections wait until one of them ends
+const MAX_PENDING_HANDSHAKES: usize = 64;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// the signal which stops a server, it fires when its `Shutdown` is shut down
+pub type ShutdownSignal = Box<Future<Item = (), Error = ()> + Send>;
+
+/// Shutdown stops the servers which it signals, the requests in flight are finished first, eg: the
+/// api and the websocket subscriptions of a stopped embedded node
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<Mutex<Vec<oneshot::Sender<()>>>>);
+
+impl Shutdown {
+    pub fn signal(&self) -> ShutdownSignal {
+        let (sender, receiver) = oneshot::channel();
+        self.0.lock().push(sender);
+        // the dropped shutdown never fires
+        Box::new(receiver.or_else(|_| future::empty()))
+    }
+
+    pub fn shutdown(&self) {
+        for sender in self.0.lock().drain(..) {
+            let _ = sender.send(());
+        }
+    }
+}
+
+// the server without a signal runs until the process exits
+fn signal_or_never(shutdown: Option<ShutdownSignal>) -> ShutdownSignal {
+    shutdown.unwrap_or_else(|| Box::new(future::empty()))
+}
+
+/// the tls config of the pem certificate chain and private key, the key is pkcs8 or rsa
+pub fn load_tls_config(cert: &str, key: &str) -> Result<Arc<ServerConfig>, String> {
+    let open = |path: &str| File::open(path).map(BufReader::new).map_err(|err| format!("failed to open {}, {}", path, err));
+    let chain = certs(&mut open(cert)?).map_err(|_| format!("invalid certificate {}", cert))?;
+    if chain.is_empty() {
+        return Err(format!("no certificate in {}", cert));
+    }
+    let mut keys = pkcs8_private_keys(&mut open(key)?).map_err(|_| format!("invalid private key {}", key))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(key)?).map_err(|_| format!("invalid private key {}", key))?;
+    }
+    let key = keys.into_iter().next().ok_or(format!("no private key in {}", key))?;
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(chain, key).map_err(|err| format!("the certificate doesn't match the key, {}", err))?;
+    Ok(Arc::new(config))
+}
+
+/// serve the tide server over tls on the bound listener in the process, the handshakes run
+/// concurrently and a slow or failed one only drops its connection, it blocks the thread until
+/// the shutdown
+pub fn serve_tls<Data>(config: Arc<ServerConfig>, listener: ::std::net::TcpListener, server: Server<Data>, shutdown: Option<ShutdownSignal>) -> Result<(), String>
+    where Data: Clone + Send + Sync + 'static {
+    let acceptor = TlsAcceptor::from(config);
+    let listener = TcpListener::from_std(listener, &Handle::default()).map_err(|err| format!("failed to listen the api, {}", err))?;
+    let incoming = listener.incoming()
+        .then(|stream| Ok::<_, io::Error>(stream.map_err(|err| warn!("Failed to accept the api connection, err: {}", err)).ok()))
+        .filter_map(|stream| stream)
+        .map(move |stream| accept_tls(&acceptor, stream).then(|tls| Ok::<_, io::Error>(tls.ok())))
+        .buffer_unordered(MAX_PENDING_HANDSHAKES)
+        .filter_map(|tls| tls);
+    let server = hyper::Server::builder(incoming)
+        .serve(move || Ok::<_, io::Error>(server.clone()))
+        .with_graceful_shutdown(signal_or_never(shutdown))
+        .map_err(|err| error!("Failed to serve the api over tls, err: {}", err));
+    tokio::run(server);
+    Ok(())
+}
+
+/// serve the tide server on the bound listener without tls, it blocks the thread like `serve_tls`
+pub fn serve_plain<Data>(listener: ::std::net::TcpListener, server: Server<Data>, shutdown: Option<ShutdownSignal>) -> Result<(), String>
+    where Data: Clone + Send + Sync + 'static {
+    let server = hyper::Server::from_tcp(listener)
+        .map_err(|err| format!("failed to listen the api, {}", err))?
+        .serve(move || Ok::<_, io::Error>(server.clone()))
+        .with_graceful_shutdown(signal_or_never(shutdown))
+        .map_err(|err| error!("Failed to serve the api, err: {}", err));
+    tokio::run(server);
+    Ok(())
+}
+
+/// the tls handshake of the accepted connection, the failed or slow one is counted and dropped
+pub fn accept_tls<S>(acceptor: &TlsAcceptor, stream: S) -> impl Future<Item = impl AsyncRead + AsyncWrite + Send + 'static, Error = ()> + Send
+    where S: AsyncRead + AsyncWrite + Send + 'static {
+    Timeout::new(acceptor.accept(stream), HANDSHAKE_TIMEOUT).map_err(|err| {
+        metrics::inc_counter("api_tls_handshake_failures_total");
+        debug!("The api tls handshake failed, err: {:?}", err);
+    })
+}
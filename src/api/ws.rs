@@ -0,0 +1,694 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::prelude::{Future, Sink, Stream};
+use tokio::reactor::Handle;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use super::rpc::{self, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND};
+use super::tls::{accept_tls, Shutdown, ShutdownSignal};
+use crate::core::chain::Chain;
+use crate::error::ApiError;
+use crate::metrics;
+use crate::subscriber::cursor::SubscriptionManager;
+use crate::subscriber::events::{ChainEvent, TxPoolEvent, TxPoolEventCT, COMMITTED_EVENT};
+use crate::types::receipt::{LogEntry, LogFilter};
+use crate::types::Height;
+
+pub const DEFAULT_MAX_WS_CONNECTIONS: usize = 64;
+pub const DEFAULT_MAX_WS_SUBSCRIPTIONS: usize = 16;
+// the notifications queued for a connection, a slow client misses the overflow ones
+const WS_QUEUE_SIZE: usize = 256;
+// the historical heads read from the store for a subscription in one tick
+pub const REPLAY_BATCH_SIZE: u64 = 50;
+pub const REPLAY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// the streams a websocket client can subscribe, eg:
+/// `{"id": 1, "method": "subscribe", "params": ["logs", {"address": "0x..."}]}`, the heads
+/// can start from a historical height, eg: `["newHeads", {"start_height": 100}]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subscription {
+    // the headers of the committed blocks
+    NewHeads,
+    // the hashes of the transactions added to the pool
+    PendingTransactions,
+    // the commits, the reverts, the syncs and the safety violations of the chain
+    ConsensusEvents,
+    // the logs of the committed blocks which match the filter
+    Logs(LogFilter),
+    // the blocks of the durable cursor from its position, they're acknowledged by `ack`
+    Cursor(String),
+}
+
+impl Subscription {
+    fn parse(params: &[Value]) -> Result<Subscription, RpcError> {
+        match params.get(0).and_then(|kind| kind.as_str()) {
+            Some("newHeads") => Ok(Subscription::NewHeads),
+            Some("pendingTransactions") => Ok(Subscription::PendingTransactions),
+            Some("consensusEvents") => Ok(Subscription::ConsensusEvents),
+            Some("logs") => {
+                let filter = params.get(1).cloned().unwrap_or(Value::Null);
+                let address = match filter.get("address").and_then(|address| address.as_str()) {
+                    Some(address) => Some(Address::from_str(address.trim_start_matches("0x"))
+                        .map_err(|_| RpcError::new(INVALID_PARAMS, "invalid address"))?),
+                    None => None,
+                };
+                let topic = match filter.get("topic").and_then(|topic| topic.as_str()) {
+                    Some(topic) => Some(hex::decode(topic.trim_start_matches("0x")).ok()
+                        .and_then(|bytes| Hash::from_slice(&bytes))
+                        .ok_or(RpcError::new(INVALID_PARAMS, "invalid topic"))?),
+                    None => None,
+                };
+                Ok(Subscription::Logs(LogFilter { from: 0, to: Height::max_value(), address, topic }))
+            }
+            Some("cursor") => {
+                let name = params.get(1).and_then(|cursor| cursor.get("name")).and_then(|name| name.as_str())
+                    .ok_or(RpcError::new(INVALID_PARAMS, "expect the name of the cursor"))?;
+                Ok(Subscription::Cursor(name.to_owned()))
+            }
+            _ => Err(RpcError::new(INVALID_PARAMS, "expect newHeads, pendingTransactions, consensusEvents, logs or cursor")),
+        }
+    }
+}
+
+/// an event of the node which is pushed to the matched subscriptions
+#[derive(Debug, Clone)]
+pub enum Notification {
+    NewHead(Value),
+    PendingTransaction(Hash),
+    Consensus(Value),
+    Logs(Vec<LogEntry>),
+}
+
+/// the subscriptions of a connection, at most `limit` of them
+#[derive(Debug)]
+pub struct Subscriptions {
+    next_id: u64,
+    limit: usize,
+    active: BTreeMap<u64, Subscription>,
+    // the next head of the subscriptions which start from a height, the heads below the live
+    // one are read from the store, so every head is sent once and in order
+    replays: BTreeMap<u64, Height>,
+}
+
+impl Subscriptions {
+    pub fn new(limit: usize) -> Self {
+        Subscriptions { next_id: 1, limit, active: BTreeMap::new(), replays: BTreeMap::new() }
+    }
+
+    pub fn wants_logs(&self) -> bool {
+        self.active.values().any(|subscription| match subscription {
+            Subscription::Logs(_) => true,
+            _ => false,
+        })
+    }
+
+    /// the name of the cursor of the subscription
+    pub fn cursor(&self, id: u64) -> Option<&str> {
+        match self.active.get(&id) {
+            Some(Subscription::Cursor(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn cursors(&self) -> Vec<String> {
+        self.active.keys().filter_map(|id| self.cursor(*id)).map(|name| name.to_owned()).collect()
+    }
+
+    /// answer a `subscribe` or an `unsubscribe` call
+    pub fn execute(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "subscribe" => {
+                let subscription = Subscription::parse(params)?;
+                let start_height = match params.get(1).and_then(|options| options.get("start_height")) {
+                    Some(_) if subscription != Subscription::NewHeads => {
+                        return Err(RpcError::new(INVALID_PARAMS, "only newHeads starts from a height"));
+                    }
+                    Some(height) => Some(height.as_u64().ok_or(RpcError::new(INVALID_PARAMS, "invalid start height"))?),
+                    None => None,
+                };
+                if self.active.len() >= self.limit {
+                    metrics::inc_counter("api_ws_refused_subscriptions_total");
+                    return Err(RpcError::new(INVALID_PARAMS, &format!("the connection has {} subscriptions", self.limit)));
+                }
+                let id = self.next_id;
+                self.next_id += 1;
+                self.active.insert(id, subscription);
+                if let Some(height) = start_height {
+                    self.replays.insert(id, height);
+                }
+                Ok(json!(id))
+            }
+            "unsubscribe" => {
+                let id = params.get(0).and_then(|id| id.as_u64()).ok_or(RpcError::new(INVALID_PARAMS, "expect a subscription id"))?;
+                self.replays.remove(&id);
+                Ok(json!(self.active.remove(&id).is_some()))
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, &format!("unknown method {}", method))),
+        }
+    }
+
+    /// the messages of the notification for the matched subscriptions
+    pub fn messages(&mut self, notification: &Notification) -> Vec<Value> {
+        let mut messages = vec![];
+        let replays = &mut self.replays;
+        for (id, subscription) in &self.active {
+            let result = match (subscription, notification) {
+                (Subscription::NewHeads, Notification::NewHead(header)) => {
+                    // the replay sends the heads until it reaches the live one
+                    if let Some(next) = replays.get_mut(id) {
+                        if header["height"].as_u64() != Some(*next) {
+                            continue;
+                        }
+                        *next += 1;
+                    }
+                    header.clone()
+                }
+                (Subscription::PendingTransactions, Notification::PendingTransaction(hash)) => json!(format!("{:?}", hash)),
+                (Subscription::ConsensusEvents, Notification::Consensus(event)) => event.clone(),
+                (Subscription::Logs(filter), Notification::Logs(entries)) => {
+                    let matched: Vec<&LogEntry> = entries.iter().filter(|entry| filter.matches(&entry.log)).collect();
+                    if matched.is_empty() {
+                        continue;
+                    }
+                    json!(matched)
+                }
+                _ => continue,
+            };
+            messages.push(json!({"jsonrpc": "2.0", "method": "subscription", "params": {"subscription": id, "result": result}}));
+        }
+        messages
+    }
+
+    /// send the stored heads of the replays up to `last_height`, at most `REPLAY_BATCH_SIZE` of
+    /// every subscription, a head which `send` refuses is sent again on the next call
+    pub fn replay<H, S>(&mut self, last_height: Height, header_at: H, mut send: S)
+        where H: Fn(Height) -> Option<Value>, S: FnMut(Value) -> bool {
+        for (id, next) in self.replays.iter_mut() {
+            let end_height = ::std::cmp::min(last_height, next.saturating_add(REPLAY_BATCH_SIZE - 1));
+            while *next <= end_height {
+                let header = match header_at(*next) {
+                    Some(header) => header,
+                    None => break,
+                };
+                if !send(json!({"jsonrpc": "2.0", "method": "subscription", "params": {"subscription": id, "result": header}})) {
+                    return;
+                }
+                *next += 1;
+            }
+        }
+    }
+}
+
+// the consensus event of the chain event, the sync progress of every block is skipped
+fn consensus_event(event: &ChainEvent) -> Option<Value> {
+    let value = match event {
+        ChainEvent::Committed { block, duration, round } => json!({
+            "event": COMMITTED_EVENT,
+            "height": block.height(),
+            "hash": format!("{:?}", block.hash()),
+            "duration_ms": duration.as_millis() as u64,
+            "round": round,
+        }),
+        ChainEvent::Reverted { blocks } => json!({
+            "event": "reverted",
+            "blocks": blocks.iter().map(|block| json!({"height": block.height(), "hash": format!("{:?}", block.hash())})).collect::<Vec<_>>(),
+        }),
+        ChainEvent::SyncStarted { from, to } => json!({"event": "sync_started", "from": from, "to": to}),
+        ChainEvent::SyncFinished { height } => json!({"event": "sync_finished", "height": height}),
+        ChainEvent::SafetyViolation(evidence) => json!({
+            "event": "safety_violation",
+            "height": evidence.height,
+            "equivocators": evidence.equivocators(),
+        }),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// a websocket connection is accepted
+#[derive(Message)]
+pub struct WsConnect {
+    pub id: usize,
+    pub sender: Sender<String>,
+}
+
+/// a websocket connection is closed
+#[derive(Message)]
+pub struct WsDisconnect {
+    pub id: usize,
+}
+
+/// a text message of a websocket client
+#[derive(Message)]
+pub struct WsRequest {
+    pub id: usize,
+    pub text: String,
+}
+
+struct Connection {
+    sender: Sender<String>,
+    subscriptions: Subscriptions,
+}
+
+// the cursors are read through the subscription manager, it sends their blocks and persists the
+// acks, eg: `{"method": "ack", "params": [1, 100]}` acknowledges the blocks up to 100
+fn execute(manager: Option<&Arc<SubscriptionManager>>, id: usize, connection: &mut Connection, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    let available = || manager.ok_or(RpcError::new(METHOD_NOT_FOUND, "the cursors are unavailable"));
+    match method {
+        "ack" => {
+            let subscription = params.get(0).and_then(|id| id.as_u64()).ok_or(RpcError::new(INVALID_PARAMS, "expect a subscription id"))?;
+            let height = params.get(1).and_then(|height| height.as_u64()).ok_or(RpcError::new(INVALID_PARAMS, "expect a height"))?;
+            let name = connection.subscriptions.cursor(subscription).ok_or(RpcError::new(INVALID_PARAMS, "not a cursor subscription"))?;
+            available()?.ack(name, id, height).map_err(|err| RpcError::new(INVALID_PARAMS, &err))?;
+            Ok(json!(true))
+        }
+        "unsubscribe" => {
+            let cursor = params.get(0).and_then(|id| id.as_u64())
+                .and_then(|subscription| connection.subscriptions.cursor(subscription))
+                .map(|name| name.to_owned());
+            let result = connection.subscriptions.execute(method, params)?;
+            if let (Some(name), Some(manager)) = (cursor, manager) {
+                manager.detach(&name, id);
+            }
+            Ok(result)
+        }
+        _ => {
+            let result = connection.subscriptions.execute(method, params)?;
+            let subscription = result.as_u64().unwrap_or_default();
+            if let Some(name) = connection.subscriptions.cursor(subscription).map(|name| name.to_owned()) {
+                let attached = available().and_then(|manager| {
+                    manager.attach(&name, id, subscription, connection.sender.clone()).map_err(|err| RpcError::new(INVALID_PARAMS, &err))
+                });
+                if let Err(err) = attached {
+                    connection.subscriptions.execute("unsubscribe", &[json!(subscription)])?;
+                    return Err(err);
+                }
+            }
+            Ok(result)
+        }
+    }
+}
+
+/// WsHub keeps the subscriptions of the websocket connections and pushes them the chain and the
+/// transaction pool events
+pub struct WsHub {
+    chain: Arc<Chain>,
+    txpool_events: Addr<TxPoolEventCT::ProcessSignals>,
+    // the durable cursors, they're absent without the store subscriptions
+    subscriptions: Option<Arc<SubscriptionManager>>,
+    connections: HashMap<usize, Connection>,
+    max_subscriptions: usize,
+    max_batch_size: usize,
+}
+
+impl Actor for WsHub {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.chain.subscriber_event(ctx.address().recipient());
+        self.txpool_events.do_send(TxPoolEventCT::SubscribeMessage::new_subscribe(ctx.address().recipient()));
+        ctx.run_interval(REPLAY_INTERVAL, |hub, _| hub.replay());
+        info!("Websocket hub has started");
+    }
+}
+
+impl Handler<WsConnect> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: WsConnect, _: &mut Self::Context) {
+        self.connections.insert(msg.id, Connection { sender: msg.sender, subscriptions: Subscriptions::new(self.max_subscriptions) });
+        metrics::set_gauge("api_ws_connections", self.connections.len() as i64);
+    }
+}
+
+impl Handler<WsDisconnect> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: WsDisconnect, _: &mut Self::Context) {
+        self.forget(msg.id);
+        metrics::set_gauge("api_ws_connections", self.connections.len() as i64);
+    }
+}
+
+impl Handler<WsRequest> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: WsRequest, _: &mut Self::Context) {
+        let (max_batch_size, manager) = (self.max_batch_size, self.subscriptions.clone());
+        let connection = match self.connections.get_mut(&msg.id) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let response = match serde_json::from_str::<Value>(&msg.text) {
+            Ok(request) => {
+                let connection = RefCell::new(&mut *connection);
+                rpc::handle_request(&request, max_batch_size, |method, params| {
+                    execute(manager.as_ref(), msg.id, &mut connection.borrow_mut(), method, params)
+                })
+            }
+            Err(_) => json!({"jsonrpc": "2.0", "id": null, "error": RpcError::new(rpc::PARSE_ERROR, "invalid json")}),
+        };
+        if connection.sender.try_send(response.to_string()).is_err() {
+            metrics::inc_counter("api_ws_dropped_messages_total");
+        }
+    }
+}
+
+impl Handler<ChainEvent> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChainEvent, _: &mut Self::Context) {
+        let mut notifications = vec![];
+        if let ChainEvent::Committed { ref block, .. } = msg {
+            notifications.push(Notification::NewHead(json!(block.header())));
+            // the receipts are only read for the log subscribers
+            if self.connections.values().any(|connection| connection.subscriptions.wants_logs()) {
+                let entries: Vec<LogEntry> = self.chain.get_receipts(block.height()).into_iter()
+                    .flat_map(|receipt| {
+                        let (height, tx_hash) = (receipt.height, receipt.tx_hash);
+                        receipt.logs.into_iter().enumerate()
+                            .map(move |(idx, log)| LogEntry { height, tx_hash, log_index: idx as u64, log })
+                    })
+                    .collect();
+                if !entries.is_empty() {
+                    notifications.push(Notification::Logs(entries));
+                }
+            }
+        }
+        if let Some(event) = consensus_event(&msg) {
+            notifications.push(Notification::Consensus(event));
+        }
+        self.push(&notifications);
+    }
+}
+
+impl Handler<TxPoolEvent> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: TxPoolEvent, _: &mut Self::Context) {
+        if let TxPoolEvent::Added(transaction) = msg {
+            self.push(&[Notification::PendingTransaction(transaction.hash())]);
+        }
+    }
+}
+
+impl WsHub {
+    pub fn new(chain: Arc<Chain>, txpool_events: Addr<TxPoolEventCT::ProcessSignals>, subscriptions: Option<Arc<SubscriptionManager>>, max_subscriptions: usize, max_batch_size: usize) -> Self {
+        WsHub { chain, txpool_events, subscriptions, connections: HashMap::new(), max_subscriptions, max_batch_size }
+    }
+
+    // the cursors of the closed connection can be read by another one
+    fn forget(&mut self, id: usize) {
+        if let Some(connection) = self.connections.remove(&id) {
+            if let Some(ref manager) = self.subscriptions {
+                connection.subscriptions.cursors().iter().for_each(|name| manager.detach(name, id));
+            }
+        }
+    }
+
+    // the historical heads are read from the store without blocking on a full connection, its
+    // replay waits for the next tick instead
+    fn replay(&mut self) {
+        let (chain, last_height) = (self.chain.clone(), self.chain.get_last_height());
+        for connection in self.connections.values_mut() {
+            let sender = &mut connection.sender;
+            connection.subscriptions.replay(last_height, |height| chain.get_header_by_height(height).map(|header| json!(header)), |message| {
+                match sender.try_send(message.to_string()) {
+                    Ok(()) => {
+                        metrics::inc_counter("api_ws_notifications_total");
+                        true
+                    }
+                    Err(_) => false,
+                }
+            });
+        }
+    }
+
+    // the closed connections are forgotten, the full ones miss the notification
+    fn push(&mut self, notifications: &[Notification]) {
+        let mut closed = vec![];
+        for (id, connection) in self.connections.iter_mut() {
+            let messages: Vec<Value> = notifications.iter().flat_map(|notification| connection.subscriptions.messages(notification)).collect();
+            for message in messages {
+                match connection.sender.try_send(message.to_string()) {
+                    Ok(()) => metrics::inc_counter("api_ws_notifications_total"),
+                    Err(ref err) if err.is_closed() => {
+                        closed.push(*id);
+                        break;
+                    }
+                    Err(_) => metrics::inc_counter("api_ws_dropped_messages_total"),
+                }
+            }
+        }
+        for id in closed {
+            self.forget(id);
+        }
+    }
+}
+
+/// WsServer is the bound listener of the websocket subscriptions, `serve` blocks the thread
+pub struct WsServer {
+    listener: ::std::net::TcpListener,
+    hub: Addr<WsHub>,
+    max_connections: usize,
+    // the connections are served over tls with the certificate of the api
+    tls: Option<Arc<ServerConfig>>,
+    // the server without it serves until the process exits
+    shutdown: Option<ShutdownSignal>,
+}
+
+/// bind the websocket subscriptions of `listen`, so the node fails to start when the address is
+/// taken instead of running without them
+pub fn bind_ws(listen: SocketAddr, hub: Addr<WsHub>, max_connections: usize, tls: Option<Arc<ServerConfig>>) -> Result<WsServer, ApiError> {
+    let listener = ::std::net::TcpListener::bind(listen).map_err(|err| ApiError::Bind(format!("{}, {}", listen, err)))?;
+    Ok(WsServer { listener, hub, max_connections, tls, shutdown: None })
+}
+
+impl WsServer {
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|err| err.to_string())
+    }
+
+    /// stop accepting the connections when the shutdown is shut down, the listener is dropped
+    /// then and the connections end with the hub
+    pub fn with_shutdown(mut self, shutdown: &Shutdown) -> Self {
+        self.shutdown = Some(shutdown.signal());
+        self
+    }
+
+    /// serve at most `max_connections` clients at once, a connection is counted from its tls
+    /// handshake to its close
+    pub fn serve(self) -> Result<(), String> {
+        let WsServer { listener, hub, max_connections, tls, shutdown } = self;
+        let listener = TcpListener::from_std(listener, &Handle::default()).map_err(|err| format!("failed to listen the websocket api, {}", err))?;
+        let acceptor = tls.map(TlsAcceptor::from);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let mut next_id = 0;
+        let server = listener.incoming()
+            .map_err(|err| warn!("Failed to accept the websocket connection, err: {}", err))
+            .for_each(move |stream| {
+                // the refused connection is closed by dropping it
+                if connections.load(Ordering::SeqCst) >= max_connections {
+                    metrics::inc_counter("api_ws_refused_connections_total");
+                    return Ok(());
+                }
+                connections.fetch_add(1, Ordering::SeqCst);
+                next_id += 1;
+                let (id, hub, connections) = (next_id, hub.clone(), connections.clone());
+                let session: Box<Future<Item = (), Error = ()> + Send> = match acceptor {
+                    Some(ref acceptor) => Box::new(accept_tls(acceptor, stream).and_then(move |stream| ws_session(stream, id, hub))),
+                    None => Box::new(ws_session(stream, id, hub)),
+                };
+                tokio::spawn(session.then(move |result| {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    result
+                }));
+                Ok(())
+            });
+        match shutdown {
+            Some(shutdown) => tokio::run(server.select(shutdown).then(|_| Ok::<(), ()>(()))),
+            None => tokio::run(server),
+        }
+        Ok(())
+    }
+}
+
+// the websocket handshake and the messages of the connection, it's closed as soon as either side
+// closes
+fn ws_session<S>(stream: S, id: usize, hub: Addr<WsHub>) -> impl Future<Item = (), Error = ()> + Send
+    where S: AsyncRead + AsyncWrite + Send + 'static {
+    accept_async(stream)
+        .map_err(|err| debug!("Failed to accept the websocket handshake, err: {}", err))
+        .and_then(move |ws| {
+            let (sink, stream) = ws.split();
+            let (sender, receiver) = channel(WS_QUEUE_SIZE);
+            hub.do_send(WsConnect { id, sender });
+            let reader = hub.clone();
+            let read = stream
+                .for_each(move |message| {
+                    if let WsMessage::Text(text) = message {
+                        reader.do_send(WsRequest { id, text });
+                    }
+                    Ok(())
+                })
+                .map_err(|err| debug!("The websocket connection is closed, err: {}", err));
+            let write = receiver
+                .map(WsMessage::Text)
+                .map_err(|_| ())
+                .forward(sink.sink_map_err(|err| debug!("Failed to write the websocket message, err: {}", err)))
+                .map(|_| ());
+            read.select(write).then(move |_| {
+                hub.do_send(WsDisconnect { id });
+                Ok(())
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::receipt::Log;
+
+    #[test]
+    fn t_subscriptions() {
+        let mut subscriptions = Subscriptions::new(2);
+        assert_eq!(subscriptions.execute("subscribe", &[json!("newHeads")]).unwrap(), json!(1));
+        let address = Address::from(7);
+        let logs = subscriptions.execute("subscribe", &[json!("logs"), json!({"address": format!("0x{}", hex::encode(address.to_vec()))})]).unwrap();
+        assert_eq!(logs, json!(2));
+        assert!(subscriptions.wants_logs());
+        // over the limit of the connection
+        assert_eq!(subscriptions.execute("subscribe", &[json!("pendingTransactions")]).unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(subscriptions.execute("subscribe", &[json!("everything")]).unwrap_err().code, INVALID_PARAMS);
+
+        // only the matched logs are pushed
+        let log = |address: Address| LogEntry { height: 1, tx_hash: Hash::zero(), log_index: 0, log: Log { address, topics: vec![], data: vec![] } };
+        let messages = subscriptions.messages(&Notification::Logs(vec![log(address), log(Address::from(8))]));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["params"]["subscription"], json!(2));
+        assert_eq!(messages[0]["params"]["result"].as_array().unwrap().len(), 1);
+        assert!(subscriptions.messages(&Notification::Logs(vec![log(Address::from(8))])).is_empty());
+        assert!(subscriptions.messages(&Notification::PendingTransaction(Hash::zero())).is_empty());
+
+        assert_eq!(subscriptions.execute("unsubscribe", &[json!(1)]).unwrap(), json!(true));
+        assert_eq!(subscriptions.execute("unsubscribe", &[json!(1)]).unwrap(), json!(false));
+        assert!(subscriptions.messages(&Notification::NewHead(json!({}))).is_empty());
+        assert_eq!(subscriptions.execute("subscribe", &[json!("consensusEvents")]).unwrap(), json!(3));
+
+        // the cursor subscriptions are named
+        assert_eq!(subscriptions.execute("unsubscribe", &[json!(2)]).unwrap(), json!(true));
+        assert_eq!(subscriptions.execute("subscribe", &[json!("cursor")]).unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(subscriptions.execute("subscribe", &[json!("cursor"), json!({"name": "indexer"})]).unwrap(), json!(4));
+        assert_eq!(subscriptions.cursor(4), Some("indexer"));
+        assert!(subscriptions.cursor(3).is_none());
+        assert_eq!(subscriptions.cursors(), vec!["indexer".to_owned()]);
+    }
+
+    #[test]
+    fn t_replay_heads() {
+        let mut subscriptions = Subscriptions::new(4);
+        assert_eq!(subscriptions.execute("subscribe", &[json!("newHeads"), json!({"start_height": 3})]).unwrap(), json!(1));
+        assert_eq!(subscriptions.execute("subscribe", &[json!("newHeads")]).unwrap(), json!(2));
+        assert_eq!(subscriptions.execute("subscribe", &[json!("newHeads"), json!({"start_height": "3"})]).unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(subscriptions.execute("subscribe", &[json!("consensusEvents"), json!({"start_height": 3})]).unwrap_err().code, INVALID_PARAMS);
+        let head = |height: Height| json!({"height": height});
+        let heights = |messages: &[Value]| -> Vec<(u64, u64)> {
+            messages.iter().map(|message| (message["params"]["subscription"].as_u64().unwrap(), message["params"]["result"]["height"].as_u64().unwrap())).collect()
+        };
+
+        // the live head above the replay only goes to the live subscription
+        assert_eq!(heights(&subscriptions.messages(&Notification::NewHead(head(6)))), vec![(2, 6)]);
+        // the second head is refused by the full connection, it's sent on the next call
+        let mut sent = vec![];
+        subscriptions.replay(6, |height| Some(head(height)), |message| {
+            if sent.len() == 2 {
+                return false;
+            }
+            sent.push(message);
+            true
+        });
+        assert_eq!(heights(&sent), vec![(1, 3), (1, 4)]);
+        let mut sent = vec![];
+        subscriptions.replay(5, |height| Some(head(height)), |message| {
+            sent.push(message);
+            true
+        });
+        assert_eq!(heights(&sent), vec![(1, 5)]);
+        // the replay reached the live head, it's sent once
+        assert_eq!(heights(&subscriptions.messages(&Notification::NewHead(head(6)))), vec![(1, 6), (2, 6)]);
+        subscriptions.replay(6, |height| Some(head(height)), |_| panic!("the head 6 is sent twice"));
+        assert_eq!(heights(&subscriptions.messages(&Notification::NewHead(head(7)))), vec![(1, 7), (2, 7)]);
+    }
+
+    // a raw client: the upgrade request and the masked text frames, the zero mask keeps the payload
+    fn ws_call(stream: &mut ::std::net::TcpStream, request: &[u8]) -> Value {
+        use std::io::{Read, Write};
+
+        let mut frame = vec![0x81, 0x80 | request.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(request);
+        stream.write_all(&frame).unwrap();
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x81);
+        let mut payload = vec![0u8; (header[1] & 0x7f) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[test]
+    fn t_serve_ws() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::thread;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::core::test_util::memory_chain;
+
+        let system = System::new("t_serve_ws");
+        let key_pair = Random.generate().unwrap();
+        let hub = WsHub::new(memory_chain(&key_pair), TxPoolEventCT::ProcessSignals::new().start(), None, 4, 4).start();
+        let server = bind_ws("127.0.0.1:0".parse().unwrap(), hub.clone(), 4, None).unwrap();
+        let listen = server.local_addr().unwrap();
+        // the taken address fails the bind instead of the serving thread
+        assert!(bind_ws(listen, hub, 4, None).is_err());
+        thread::spawn(move || server.serve());
+
+        let current = System::current();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(listen).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            write!(stream, "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n", listen).unwrap();
+            let (mut response, mut byte) = (vec![], [0u8; 1]);
+            while !response.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                response.push(byte[0]);
+            }
+            let response = String::from_utf8(response).unwrap();
+            let subscribed = ws_call(&mut stream, br#"{"jsonrpc":"2.0","id":1,"method":"subscribe","params":["newHeads"]}"#);
+            let invalid = ws_call(&mut stream, b"{");
+            current.stop();
+            (response, subscribed, invalid)
+        });
+        system.run();
+        let (response, subscribed, invalid) = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"));
+        assert_eq!(subscribed["id"], json!(1));
+        assert_eq!(subscribed["result"], json!(1));
+        assert_eq!(invalid["error"]["code"], json!(rpc::PARSE_ERROR));
+    }
+}
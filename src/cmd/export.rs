@@ -0,0 +1,30 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::{
+    common::hash_algo::set_hash_algo,
+    core::export::{export_blocks, ExportFormat},
+    error::StoreError,
+    logger::init_log,
+    types::Height,
+    util::canonical::OutputFormat,
+};
+use super::{init_config, init_store};
+
+/// Write the blocks in [from, to] of the store of a stopped node to a file, eg: to move a
+/// datadir to another machine or to seed the fixtures of the tests, see `cmd::import`.
+pub fn export(config: &str, file: &str, format: ExportFormat, from: Height, to: Option<Height>, output: OutputFormat) -> Result<(), String> {
+    init_log();
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    if !Path::new(&config.store).exists() {
+        return Err(StoreError::NotFound(config.store).to_string());
+    }
+    set_hash_algo(config.hash_algo())?;
+    let ledger = init_store(&config).map_err(|err| err.diagnostic())?;
+    let mut writer = BufWriter::new(File::create(file).map_err(|err| format!("failed to create {}, {}", file, err))?);
+    let report = export_blocks(&ledger, &mut writer, format, from, to)?;
+    info!("Export blocks, file: {}, format: {:?}, blocks: {}", file, format, report.blocks);
+    println!("{}", output.render(&report));
+    Ok(())
+}
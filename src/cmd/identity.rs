@@ -0,0 +1,36 @@
+use crate::{
+    p2p::identity::NodeIdentity,
+    util::canonical::OutputFormat,
+};
+use super::init_config;
+
+/// print the network identity of the data directory, the node key is never printed
+pub fn show(config: &str, format: OutputFormat) -> Result<(), String> {
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    let identity = NodeIdentity::load(&config.data_dir())?
+        .ok_or(format!("identity is not found, it's created on the first run: {:?}", NodeIdentity::path(&config.data_dir())))?;
+    println!("{}", format.render(&identity_view(&identity)?));
+    Ok(())
+}
+
+/// replace the identity with a new node key and its peer id, the node must be stopped. The peers
+/// learn the new identity by the next handshake.
+pub fn rotate(config: &str, format: OutputFormat) -> Result<(), String> {
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    let old = NodeIdentity::load(&config.data_dir())?;
+    let identity = NodeIdentity::generate()?;
+    identity.save(&config.data_dir())?;
+    if let Some(old) = old {
+        info!("Node identity is rotated, old peer id: {}, new peer id: {}", old.peer_id, identity.peer_id);
+    }
+    println!("{}", format.render(&identity_view(&identity)?));
+    Ok(())
+}
+
+fn identity_view(identity: &NodeIdentity) -> Result<serde_json::Value, String> {
+    Ok(json!({
+        "peer_id": identity.peer_id,
+        "node": format!("{:?}", identity.key_pair()?.address()),
+        "created": identity.created,
+    }))
+}
@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use ::actix::prelude::*;
+use parking_lot::RwLock;
+
+use crate::{
+    common::hash_algo::set_hash_algo,
+    config::Config,
+    core::chain::Chain,
+    core::checkpoint::TrustedCheckpoint,
+    core::export::{import_blocks, ExportFormat, ImportReport},
+    core::ledger::Ledger,
+    logger::init_log,
+    util::canonical::OutputFormat,
+};
+use super::{init_config, init_store};
+
+/// Validate the exported blocks of a file and apply them to the store of a stopped node, the
+/// store is created if it doesn't exist, eg: a fresh datadir on another machine. The genesis
+/// block is stored from the config first, the genesis block of the file must be the same block.
+pub fn import(config: &str, file: &str, format: ExportFormat, output: OutputFormat) -> Result<(), String> {
+    init_log();
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    if config.is_memory_store() {
+        return Err("the memory store is lost when the import ends, import into a store path".to_string());
+    }
+    if config.genesis.is_none() {
+        return Err("the genesis config is required to import the blocks".to_string());
+    }
+    set_hash_algo(config.hash_algo())?;
+    let ledger = init_store(&config).map_err(|err| err.diagnostic())?;
+    let reader = BufReader::new(File::open(file).map_err(|err| format!("failed to open {}, {}", file, err))?);
+
+    // the chain creates actors, the blocks are imported beside the system which drains their events
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    System::run(move || {
+        let system = System::current();
+        ::std::thread::spawn(move || {
+            tx.send(import_into_chain(config, ledger, reader, format)).unwrap();
+            system.stop();
+        });
+    });
+    let report = rx.recv().map_err(|err| err.to_string())??;
+    info!("Import blocks, file: {}, imported: {}, skipped: {}", file, report.imported, report.skipped);
+    println!("{}", output.render(&report));
+    Ok(())
+}
+
+fn import_into_chain(config: Config, ledger: Ledger, mut reader: BufReader<File>, format: ExportFormat) -> Result<ImportReport, String> {
+    let mut chain = Chain::new(config.clone(), Arc::new(RwLock::new(ledger)));
+    chain.store_genesis_block().map_err(|err| format!("{}", err))?;
+    for checkpoint in config.checkpoint.iter().chain(config.checkpoints.iter()) {
+        chain.add_checkpoint(TrustedCheckpoint::from_config(checkpoint)?);
+    }
+    let chain = Arc::new(chain);
+    let result = import_blocks(&chain, &mut reader, format, |height| {
+        info!("Import blocks, height: {}", height);
+    });
+    // the blocks before an invalid one are kept
+    chain.get_ledger().write().flush();
+    result
+}
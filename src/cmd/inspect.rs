@@ -0,0 +1,71 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use cryptocurrency_kit::crypto::Hash;
+use parking_lot::RwLock;
+
+use crate::{
+    api::{start_api, ApiState},
+    core::chain::Chain,
+    core::ledger::Ledger,
+    error::StoreError,
+    features::node_features,
+    logger::init_log,
+    types::Height,
+    util::canonical::OutputFormat,
+};
+use super::{init_config, init_read_only_store, init_transaction_pool};
+
+/// what the inspector does with the store
+pub enum Inspect {
+    // print the block at the height
+    Height(Height),
+    // print the transaction
+    Transaction(String),
+    // serve the read api
+    Serve,
+}
+
+/// Open an existing store of a stopped node and examine it, there is no p2p, no consensus
+/// and no minner, and no chain data is written to the store: it's opened read only and the genesis
+/// block is loaded, not stored. The api serves the reads only. Rocksdb may still replay its log
+/// and rewrite its manifest on open, see `Backend::open_read_only`, examine a copy to keep the
+/// files untouched.
+pub fn inspect(config: &str, action: Inspect, format: OutputFormat) -> Result<(), String> {
+    init_log();
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    if !Path::new(&config.store).exists() {
+        return Err(StoreError::NotFound(config.store).to_string());
+    }
+    let mut ledger = init_read_only_store(&config).map_err(|err| err.diagnostic())?;
+    if ledger.get_schema().block_hashes_by_height().len() == 0 {
+        return Err(format!("store is empty: {}", config.store));
+    }
+    ledger.reload_meta();
+    let ledger: Arc<RwLock<Ledger>> = Arc::new(RwLock::new(ledger));
+
+    match action {
+        Inspect::Height(height) => {
+            let block = ledger.read().get_block_by_height(height).ok_or(format!("block is not found, height: {}", height))?;
+            println!("{}", format.render(&block));
+        }
+        Inspect::Transaction(tx_hash) => {
+            let tx_hash = hex::decode(tx_hash.trim_start_matches("0x")).ok()
+                .and_then(|bytes| Hash::from_slice(&bytes))
+                .ok_or(format!("invalid tx hash: {}", tx_hash))?;
+            let transaction = ledger.read().get_transaction(&tx_hash).ok_or(format!("transaction is not found: {:?}", tx_hash))?;
+            println!("{}", format.render(&transaction));
+        }
+        Inspect::Serve => {
+            let mut chain = Chain::new(config.clone(), ledger);
+            chain.load_genesis_block().map_err(|err| format!("{}", err))?;
+            info!("Inspect store: {}, height: {}", config.store, chain.get_last_height());
+            let chain = Arc::new(chain);
+            let txpool = Arc::new(RwLock::new(init_transaction_pool(&config, chain.clone(), None)));
+            let features = node_features(&config);
+            let state = ApiState { chain, txpool, minner: None, p2p: None, dev: false, features, reads: Default::default(), blocking: Default::default(), subscriptions: None, max_page_size: config.api.max_page_size, max_batch_size: config.api.max_batch_size };
+            start_api(state, config.api_ip, config.api_port, config.api).map_err(|err| err.to_string())?;
+        }
+    }
+    Ok(())
+}
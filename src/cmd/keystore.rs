@@ -0,0 +1,68 @@
+use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+
+use crate::{
+    config::Config,
+    keystore::{read_password_file, KeyFile, UnlockedKey, STANDARD_SCRYPT_N, STANDARD_SCRYPT_P},
+    util::canonical::OutputFormat,
+};
+use super::init_config;
+
+/// decrypt the key file of `keystore` into the validator key, the password is read from the
+/// `password_file` or asked on the terminal. The key is kept in memory, the `secret` is left as is
+pub(crate) fn unlock(config: &mut Config) -> Result<(), String> {
+    let path = match config.keystore {
+        Some(ref path) => path.clone(),
+        None => return Ok(()),
+    };
+    if !config.secret.is_empty() {
+        warn!("The secret of the config is ignored, the validator key is unlocked from the keystore");
+    }
+    let key_file = KeyFile::load(&path)?;
+    let password = read_password(config.password_file.as_ref(), false)?;
+    let key_pair = key_file.decrypt(&password).map_err(|err| format!("failed to unlock the keystore {}, {}", path, err))?;
+    info!("Keystore is unlocked, validator: {:?}", key_pair.address());
+    config.unlocked_key = Some(UnlockedKey::new(key_pair));
+    Ok(())
+}
+
+/// generate a new validator key and write it into the key file `path`
+pub fn new(path: &str, password_file: Option<&str>, format: OutputFormat) -> Result<(), String> {
+    let key_pair = Random.generate().map_err(|err| format!("{:?}", err))?;
+    save(&key_pair, path, password_file, format)
+}
+
+/// encrypt the plain `secret` of the config into the key file `path`, the secret should be
+/// removed from the config afterwards
+pub fn import(config: &str, path: &str, password_file: Option<&str>, format: OutputFormat) -> Result<(), String> {
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    if config.secret.is_empty() {
+        return Err("the config has no secret to import".to_string());
+    }
+    save(&config.validator_key_pair()?, path, password_file, format)
+}
+
+fn save(key_pair: &KeyPair, path: &str, password_file: Option<&str>, format: OutputFormat) -> Result<(), String> {
+    let password = read_password(password_file, true)?;
+    let key_file = KeyFile::encrypt(key_pair, &password, STANDARD_SCRYPT_N, STANDARD_SCRYPT_P)?;
+    key_file.save(path)?;
+    println!("{}", format.render(&json!({
+        "address": format!("{:?}", key_pair.address()),
+        "path": path,
+    })));
+    Ok(())
+}
+
+// the new passwords are typed twice on the terminal
+pub(crate) fn read_password<P: AsRef<str>>(password_file: Option<P>, confirm: bool) -> Result<String, String> {
+    if let Some(path) = password_file {
+        return read_password_file(path.as_ref());
+    }
+    let password = rpassword::read_password_from_tty(Some("Keystore password: ")).map_err(|err| err.to_string())?;
+    if confirm {
+        let repeated = rpassword::read_password_from_tty(Some("Repeat password: ")).map_err(|err| err.to_string())?;
+        if password != repeated {
+            return Err("the passwords don't match".to_string());
+        }
+    }
+    Ok(password)
+}
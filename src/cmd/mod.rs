@@ -1,137 +1,408 @@
+pub mod export;
+pub mod identity;
+pub mod import;
+pub mod inspect;
+pub mod keystore;
+pub mod node;
+pub mod reindex;
+pub mod report;
+pub mod signer;
+
+pub use self::node::Node;
+
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, prelude::*};
 use std::str::FromStr;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{spawn, JoinHandle};
 use std::time::Duration;
 
 use ::actix::prelude::*;
 use cryptocurrency_kit::crypto::Hash;
-use cryptocurrency_kit::ethkey::{Generator, KeyPair, Secret, Random};
+use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
 use futures::Future;
-use kvdb_rocksdb::Database;
 use libp2p::{Multiaddr, PeerId};
 use lru_time_cache::LruCache;
 use parking_lot::RwLock;
 
 use crate::{
     common,
-    config::Config,
+    config::{Config, schema::parse_config},
     consensus::pbft::core::core::{Core, handle_msg_middle},
-    consensus::consensus::{create_bft_engine, SafeEngine},
+    consensus::consensus::{create_bft_engine, create_dpos_engine, EngineKind, SafeEngine},
+    consensus::events::OpCMD,
+    consensus::policy::{DefaultProposalPolicy, SafeProposalPolicy},
+    consensus::sign_guard::{open_sign_guard, SafeSignGuard},
     core::chain::Chain,
+    core::checkpoint::TrustedCheckpoint,
     core::ledger::{LastMeta, Ledger},
     core::tx_pool::{BaseTxPool, TxPool, SafeTxPool},
-    error::ChainResult,
+    core::scrub::Scrubber,
+    core::maintenance::{compact_if_due, Maintenance},
+    core::verify::{verify_store, VerifyMode},
+    core::warm_up::warm_up,
+    consensus::error::ConsensusError,
+    error::{ApiError, ChainResult, ConfigError, NodeError, P2PError, StoreError},
     logger::init_log,
+    metrics,
+    features::{node_features, report_features},
     minner::Minner,
     p2p::{
-        protocol::Payload,
+        protocol::{Payload, NodeType},
         discover_service::DiscoverService,
-        server::{author_handshake, TcpServer},
+        address_book::AddressBook,
+        dns_seed::DnsSeeder,
+        identity::{key_peer_id, BindingSigner, NodeIdentity},
+        discovery::{DiscoveryRecords, RECORDS_FILE},
+        server::{author_handshake, handshake_packet, TcpServer},
+        serve::BlockServer,
+        mux::PortMux,
         spawn_sync_subscriber,
     },
-    pprof::spawn_signal_handler,
+    pprof::{self, install_crash_dump, spawn_signal_watcher},
+    signer::{read_token_file, LocalSigner, RemoteSigner, SafeSigner, Signer},
+    store::backend::Backend,
     store::schema::Schema,
-    subscriber::events::{BroadcastEventSubscriber, ChainEventSubscriber, SubscriberType},
+    subscriber::events::{BroadcastEventSubscriber, ChainEvent, ChainEventSubscriber, SubscriberType, TxPoolEventCT},
     subscriber::*,
+    subscriber::cursor::SubscriptionManager,
     types::Validator,
-    api::start_api,
+    api::{api_tls_config, bind_api, ApiState},
+    api::tls::Shutdown,
+    api::ws::{bind_ws, WsHub},
+    util::clock::check_clock_drift,
+    util::request::{send_timeout, DEFAULT_REQUEST_TIMEOUT},
 };
 
-pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), String> {
-    print_art();
-    init_log();
-    let result = init_config(config);
-    if result.is_err() {
-        return Err(result.err().unwrap());
-    }
-    let config = result.unwrap();
-    let secret = Secret::from_str(&config.secret).expect("Secret is uncorrect");
-    let key_pair = KeyPair::from_secret(secret).unwrap();
-    let ledger = init_store(&config)?;
+pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), NodeError> {
+    NodeBuilder::new(config).start(sender)
+}
+
+/// NodeBuilder injects the application hooks into the node, eg:
+///
+/// ```ignore
+/// let node = NodeBuilder::new("config.toml").proposal_policy(Arc::new(MyPolicy)).build()?;
+/// ```
+#[derive(Clone)]
+pub struct NodeBuilder {
+    config: String,
+    proposal_policy: SafeProposalPolicy,
+    verify_db: VerifyMode,
+    // override the `keystore` and the `password_file` of the config
+    keystore: Option<String>,
+    password_file: Option<String>,
+}
+
+impl NodeBuilder {
+    pub fn new(config: &str) -> Self {
+        NodeBuilder {
+            config: config.to_owned(),
+            proposal_policy: Arc::new(DefaultProposalPolicy),
+            verify_db: VerifyMode::Off,
+            keystore: None,
+            password_file: None,
+        }
+    }
+
+    pub fn proposal_policy(mut self, proposal_policy: SafeProposalPolicy) -> Self {
+        self.proposal_policy = proposal_policy;
+        self
+    }
+
+    /// verify the stores from the genesis before the chains start, eg: `start --verify-db`
+    pub fn verify_db(mut self, mode: VerifyMode) -> Self {
+        self.verify_db = mode;
+        self
+    }
+
+    /// unlock the validator key from the key file, eg: `start --keystore key.json`
+    pub fn keystore(mut self, path: &str) -> Self {
+        self.keystore = Some(path.to_owned());
+        self
+    }
+
+    /// the password of the keystore, the password is asked on the terminal without it
+    pub fn password_file(mut self, path: &str) -> Self {
+        self.password_file = Some(path.to_owned());
+        self
+    }
+
+    /// start the node and return its handle, the embedding programs drive the node by it
+    pub fn build(self) -> Result<Node, NodeError> {
+        Node::with_builder(self)
+    }
+
+    /// the glue of the binary, it returns once the node runs, the node is stopped when the
+    /// process receives a signal and then the sender is notified
+    pub fn start(self, sender: Sender<()>) -> Result<(), NodeError> {
+        print_art();
+        init_log();
+        let node = self.build()?;
+        spawn_signal_watcher(pprof::output_dir(), move || {
+            node.stop();
+            let _ = sender.send(());
+        });
+        Ok(())
+    }
+}
+
+// every chain runs in its own system, so the event broker of a chain never sees the events of
+// the others
+fn spawn_chain(builder: &NodeBuilder, config: Config, mux: Option<PortMux>, stopping: Arc<AtomicBool>) -> Result<ChainHandle, NodeError> {
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    let builder = builder.clone();
+    ::std::thread::spawn(move || {
+        let chain_stopping = stopping.clone();
+        let code = System::run(move || {
+            let result = start_chain(&builder, &config, mux, chain_stopping.clone());
+            if result.is_err() {
+                // the node is torn down by the caller
+                chain_stopping.store(true, Ordering::SeqCst);
+                System::current().stop();
+            }
+            tx.send(result).unwrap();
+        });
+        exit_unless_stopping(&stopping, code);
+    });
+    rx.recv().map_err(|err| NodeError::Other(err.to_string()))?
+}
+
+// the system of the binary exits the process when it ends, the stopped node ends quietly
+fn exit_unless_stopping(stopping: &AtomicBool, code: i32) {
+    if !stopping.load(Ordering::SeqCst) {
+        ::std::process::exit(code);
+    }
+}
+
+/// the actors and the shared state of a running chain
+#[derive(Clone)]
+pub struct ChainHandle {
+    pub chain: Arc<Chain>,
+    pub tx_pool: Arc<RwLock<SafeTxPool>>,
+    // the pbft core, the dpos engine has no vote round
+    pub core: Option<Addr<Core>>,
+    pub p2p: Addr<TcpServer>,
+    pub minner: Addr<Minner>,
+    // the systems of the chain actors, the minner and the pbft core
+    systems: Vec<System>,
+    // the temporary data directory of a memory store, it's removed when the chain stops
+    temp_dir: Option<String>,
+    // the api and the websocket servers of the chain
+    api_shutdown: Shutdown,
+}
+
+impl ChainHandle {
+    fn stop(&self) {
+        if let Some(ref core) = self.core {
+            core.do_send(OpCMD::stop);
+        }
+        self.systems.iter().for_each(|system| system.stop());
+        self.api_shutdown.shutdown();
+        // the blocks of an unfinished batch
+        self.chain.get_ledger().write().flush();
+        if let Some(ref dir) = self.temp_dir {
+            if let Err(err) = ::std::fs::remove_dir_all(dir) {
+                warn!("Failed to remove the temporary data directory {}, err: {}", dir, err);
+            }
+        }
+    }
+}
+
+// the chains which share a p2p port are multiplexed by the chain id of the handshake
+fn port_muxes(configs: &[(String, Config)]) -> HashMap<u16, PortMux> {
+    let mut muxes = HashMap::new();
+    for (_, config) in configs.iter() {
+        if configs.iter().filter(|(_, other)| other.port == config.port).count() > 1 {
+            muxes.entry(config.port).or_insert_with(PortMux::default);
+        }
+    }
+    muxes
+}
+
+fn start_chain(builder: &NodeBuilder, config: &Config, mux: Option<PortMux>, stopping: Arc<AtomicBool>) -> Result<ChainHandle, NodeError> {
+    let mut config = config.clone();
+    // the proposals of a skewed clock are rejected by the other validators
+    check_clock_drift(&config.clock.ntp_servers, config.clock.max_drift, config.clock.strict).map_err(NodeError::Clock)?;
+    init_identity(&mut config).map_err(P2PError::Identity)?;
+    let signer = init_signer(&config).map_err(NodeError::Signer)?;
+    let mut ledger = init_store(&config)?;
+    // the validator never signs below the high-water mark, a broken mark must be examined first
+    let sign_guard = open_sign_guard(&config).map_err(StoreError::SignState)?;
+    if let Some(last) = sign_guard.lock().last() {
+        info!("Sign state, height: {}, round: {}, step: {:?}, dir: {}", last.height, last.round, last.step, config.sign_state_dir());
+    }
+    let mut reverted = vec![];
+    if builder.verify_db != VerifyMode::Off && ledger.get_schema().block_hashes_by_height().len() > 0 {
+        reverted = verify_store(&mut ledger, config.engine.seal_verifier().as_ref(), builder.verify_db).map_err(StoreError::Corrupted)?.reverted;
+    }
     let ledger: Arc<RwLock<Ledger>> = Arc::new(RwLock::new(ledger));
 
     let mut chain = Chain::new(config.clone(), ledger);
+    chain.set_proposal_policy(builder.proposal_policy.clone());
 
     // init genesis
-    init_genesis(&mut chain).map_err(|err| format!("{}", err))?;
+    init_genesis(&mut chain)?;
+    // the node never continues on a broken fork, the operator must examine the evidence first
+    if let Some(evidence) = chain.safety_evidence() {
+        return Err(StoreError::SafetyEvidence(serde_json::to_string(&evidence).unwrap_or_default()).into());
+    }
+    for checkpoint in config.checkpoint.iter().chain(config.checkpoints.iter()) {
+        chain.add_checkpoint(TrustedCheckpoint::from_config(checkpoint).map_err(ConfigError::Invalid)?);
+        info!("Trusted checkpoint, height: {}", checkpoint.height);
+    }
+    chain.verify_checkpoints()?;
+    if config.warm_up.enabled {
+        let report = warm_up(&chain.get_ledger().read(), &config.warm_up, &config.cache);
+        info!("Warm up the store, {:?}", report);
+    }
     let genesis = chain.get_genesis().clone();
     info!("Genesis hash: {:?}", chain.get_genesis().hash());
 
-    // init transaction pool
-    let _tx_pool = Arc::new(RwLock::new(init_transaction_pool(&config)));
-
     let chain = Arc::new(chain);
 
-    init_api(&config, chain.clone());
+    // init transaction pool, the transactions are admitted against the confirmed balances
+    let txpool_events = spawn_txpool_event_notify(&config);
+    let _tx_pool = Arc::new(RwLock::new(init_transaction_pool(&config, chain.clone(), Some(txpool_events.clone()))));
 
     let broadcast_subscriber = BroadcastEventSubscriber::new(SubscriberType::Async).start();
 
-    let (core_pid, engine) = start_consensus_engine(
+    let (core, engine) = start_consensus_engine(
         &config,
-        key_pair.clone(),
+        signer.clone(),
+        sign_guard,
         chain.clone(),
         broadcast_subscriber.clone(),
-    );
+    )?;
+    let core_pid = core.as_ref().map(|(core_pid, _)| core_pid.clone());
 
-    let config_clone = config.clone();
-    {
-        let p2p_event_notify = init_p2p_event_notify();
-        let _discover_pid = init_p2p_service(p2p_event_notify.clone(), &config_clone);
-        init_tcp_server(chain.clone(), p2p_event_notify.clone(), genesis.hash(), core_pid.clone(), &config_clone);
+    if config.scrub.enabled {
+        Scrubber::new(chain.clone(), config.scrub.clone()).start();
     }
+    if config.maintenance.usage_interval > 0 || config.maintenance.compact_after_pruned > 0 {
+        Maintenance::new(chain.clone(), config.maintenance.clone(), config.store.clone()).start();
+    }
+
+    let config_clone = config.clone();
+    let tcp_server = {
+        let p2p_event_notify = init_p2p_event_notify(&config_clone);
+        let _discover_pid = init_p2p_service(p2p_event_notify.clone(), &config_clone)?;
+        // the node validates the next block when it's in the set of the next height
+        let node_type = if chain.get_validator_addresses(chain.get_last_height() + 1).contains(&signer.address()) {
+            NodeType::Validator
+        } else {
+            NodeType::Full
+        };
+        let tcp_server = init_tcp_server(chain.clone(), p2p_event_notify.clone(), genesis.hash(), node_type, core_pid.clone(), &config_clone, &signer, mux)?;
+        // the tcp server must subscribe the p2p events before the seeds are dialed
+        init_dns_seeder(p2p_event_notify.clone(), &config_clone)?;
+        tcp_server
+    };
 
     // spawn new thread to handle mine
-    ::std::thread::spawn(move || {
-        let code = System::run(move || {
-            start_mint(&config, key_pair.clone(), chain.clone(), _tx_pool.clone(), engine);
+    let ws_txpool_events = txpool_events.clone();
+    let (minner_tx, minner_rx) = ::std::sync::mpsc::channel();
+    {
+        let config = config.clone();
+        let chain = chain.clone();
+        let tx_pool = _tx_pool.clone();
+        ::std::thread::spawn(move || {
+            let code = System::run(move || {
+                let minner = start_mint(&config, signer.clone(), chain.clone(), tx_pool.clone(), engine, txpool_events);
+                minner_tx.send((minner, System::current())).unwrap();
+            });
+            exit_unless_stopping(&stopping, code);
         });
-        ::std::process::exit(code);
-    });
+    }
+    let (minner, minner_system) = minner_rx.recv().map_err(|err| NodeError::Other(err.to_string()))?;
 
-    init_signal_handle();
-    Ok(())
+    let api_shutdown = Shutdown::default();
+    init_api(&config, chain.clone(), _tx_pool.clone(), minner.clone(), tcp_server.clone(), ws_txpool_events, &api_shutdown)?;
+    // the subscribers learn the blocks dropped by the repair
+    if !reverted.is_empty() {
+        chain.post_event(ChainEvent::Reverted { blocks: reverted });
+    }
+
+    let mut systems = vec![System::current(), minner_system];
+    systems.extend(core.map(|(_, core_system)| core_system));
+    Ok(ChainHandle {
+        chain,
+        tx_pool: _tx_pool,
+        core: core_pid,
+        p2p: tcp_server,
+        minner,
+        systems,
+        temp_dir: if config.has_temp_data_dir() { Some(config.data_dir()) } else { None },
+        api_shutdown,
+    })
 }
 
-fn init_p2p_event_notify() -> Addr<ProcessSignals> {
+fn init_p2p_event_notify(config: &Config) -> Addr<ProcessSignals> {
     info!("Init p2p event nofity");
-    spawn_sync_subscriber()
+    spawn_sync_subscriber(config.event_queue_capacity, config.p2p_event_policy)
 }
 
 fn init_p2p_service(
     p2p_subscriber: Addr<ProcessSignals>,
     config: &Config,
-) -> Addr<DiscoverService> {
-    let peer_id = PeerId::from_str(&config.peer_id).unwrap();
-    let mul_addr = Multiaddr::from_str(&format!("/ip4/{}/tcp/{}", config.ip, config.port)).unwrap();
+) -> Result<Addr<DiscoverService>, P2PError> {
+    let peer_id = config.local_peer_id().map_err(P2PError::Identity)?;
+    // announce the advertised address, the bind address may be unreachable behind NAT
+    let mul_addr = parse_multiaddr(&config.advertised_address())?;
     let discover_service =
         DiscoverService::spawn_discover_service(p2p_subscriber, peer_id, mul_addr, config.ttl);
     info!("Init p2p service successfully");
-    discover_service
+    Ok(discover_service)
+}
+
+fn init_dns_seeder(p2p_subscriber: Addr<ProcessSignals>, config: &Config) -> Result<Addr<DnsSeeder>, P2PError> {
+    let address_book = AddressBook::load(format!("{}/peers.json", config.data_dir()));
+    let seeds = config.p2p.dns_seeds.clone();
+    let port = config.port;
+    let local_address = parse_multiaddr(&config.advertised_address())?;
+    Ok(DnsSeeder::create(move |_| DnsSeeder::new(seeds, port, local_address, address_book, p2p_subscriber)))
 }
 
-fn init_tcp_server(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, genesis: Hash, core_pid: Addr<Core>, config: &Config) {
-    let peer_id = PeerId::from_str(&config.peer_id).unwrap();
-    let mul_addr = Multiaddr::from_str(&format!("/ip4/{}/tcp/{}", config.ip, config.port)).unwrap();
-    let author = author_handshake(genesis.clone());
+fn parse_multiaddr(address: &str) -> Result<Multiaddr, P2PError> {
+    Multiaddr::from_str(address).map_err(|err| P2PError::InvalidAddress(format!("{}, {}", address, err)))
+}
+
+fn init_tcp_server(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, genesis: Hash, node_type: NodeType, core_pid: Option<Addr<Core>>, config: &Config, signer: &SafeSigner, mux: Option<PortMux>) -> Result<Addr<TcpServer>, P2PError> {
+    let peer_id = config.local_peer_id().map_err(P2PError::Identity)?;
+    let mul_addr = parse_multiaddr(&format!("/ip4/{}/tcp/{}", config.ip, config.port))?;
+    let advertised = parse_multiaddr(&config.advertised_address())?;
+    let author = author_handshake(genesis.clone(), config.chain_id);
+    let binding = match validator_binding(&peer_id, config, signer) {
+        Ok(binding) => binding,
+        Err(err) => {
+            error!("Failed to load the key of the validator binding, err: {}", err);
+            None
+        }
+    };
+    // the peers of protocol 4 reject the handshake without the signed addresses
+    let node_key = discovery_key(&peer_id, config).map_err(P2PError::Identity)?;
+    let handshake_fn = Arc::new(handshake_packet(peer_id.clone(), chain.clone(), node_type, binding, Some((node_key, advertised))));
     let h1 = Box::new(handle_msg_middle(core_pid, chain.clone()));
-    let server = TcpServer::new(peer_id, mul_addr, None, genesis.clone(), Box::new(author), h1);
+    let block_server = BlockServer::new(chain.get_ledger().clone(), config.p2p.serve_rate);
+    let server = TcpServer::new(peer_id, mul_addr, None, handshake_fn, Box::new(author), h1, config.session_mailbox_capacity, config.p2p.outbound, &config.p2p_outbound_quotas, config.p2p.session_grace, config.p2p.sync.clone(), mux, Some(block_server), config.size_limits().max_frame_size(), DiscoveryRecords::load(format!("{}/{}", config.data_dir(), RECORDS_FILE)));
 
     // subscriber p2p event, sync operation
     {
         let recipient = server.clone().recipient();
         // register
         let message = SubscribeMessage::SubScribe(recipient);
-        let request_fut = p2p_subscriber.send(message);
+        let request_fut = send_timeout(&p2p_subscriber, message, DEFAULT_REQUEST_TIMEOUT);
         Arbiter::spawn(
             request_fut
-                .and_then(|_result| {
-                    info!("Subsribe p2p discover event successfully");
+                .then(|result| {
+                    match result {
+                        Ok(_) => info!("Subsribe p2p discover event successfully"),
+                        Err(err) => error!("Failed to subscribe p2p discover event, err: {}", err),
+                    }
                     futures::future::ok(())
-                })
-                .map_err(|err| unimplemented!("{}", err)),
+                }),
         );
     }
 
@@ -140,40 +411,157 @@ fn init_tcp_server(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, gene
         chain.subscriber_event(server.clone().recipient());
     }
     info!("Init tcp server successfully");
+    Ok(server)
+}
+
+/// bind the p2p identity to the validator key, the full node without a validator key publishes nothing
+fn validator_binding(peer_id: &PeerId, config: &Config, signer: &SafeSigner) -> Result<Option<BindingSigner>, String> {
+    if !config.has_local_key() && config.signer.is_none() {
+        return Ok(None);
+    }
+    // the identity file always sets the node key
+    let node_key = config.node_key_pair()?.ok_or("the node key is not initialized".to_string())?;
+    if key_peer_id(&node_key)? != *peer_id {
+        return Err("the peer id isn't derived from the node key".to_string());
+    }
+    Ok(Some(BindingSigner::new(peer_id.clone(), node_key, signer.clone())))
+}
+
+/// the remote signer of the config, or the validator key of the `secret`
+fn init_signer(config: &Config) -> Result<SafeSigner, String> {
+    let signer_config = match config.signer {
+        Some(ref signer_config) => signer_config,
+        None => return Ok(Arc::new(LocalSigner::new(config.validator_key_pair()?))),
+    };
+    let token = read_token_file(&signer_config.token_file)?;
+    let signer = RemoteSigner::new(&signer_config.endpoint, config.validator_address()?, token, signer_config.timeout);
+    signer.probe().map_err(|err| format!("the remote signer {} is unavailable, {}", signer_config.endpoint, err))?;
+    info!("Remote signer is ready, endpoint: {}, validator: {:?}", signer_config.endpoint, signer.address());
+    Ok(Arc::new(signer))
+}
+
+// the discovery records are signed by the node key of the identity file, the peer id is derived
+// from it, or the other peers reject the records
+fn discovery_key(peer_id: &PeerId, config: &Config) -> Result<KeyPair, String> {
+    let identity = NodeIdentity::load(&config.data_dir())?
+        .ok_or(format!("the identity file is absent, path: {:?}", NodeIdentity::path(&config.data_dir())))?;
+    let node_key = identity.key_pair()?;
+    if key_peer_id(&node_key)? != *peer_id {
+        return Err("the peer id isn't derived from the node key of the identity file".to_string());
+    }
+    Ok(node_key)
 }
 
-fn init_config(config: &str) -> Result<Config, String> {
+fn init_config(config: &str) -> Result<Config, ConfigError> {
     info!("Init config: {}", config);
     let mut input = String::new();
     File::open(config)
         .and_then(|mut f| f.read_to_string(&mut input))
-        .map(|_| toml::from_str::<Config>(&input).unwrap())
-        .map_err(|err| err.to_string())
+        .map_err(|err| ConfigError::Read(config.to_owned(), err.to_string()))?;
+    parse_config(&input).map_err(ConfigError::Invalid)
+}
+
+fn init_transaction_pool(config: &Config, chain: Arc<Chain>, notifier: Option<Addr<TxPoolEventCT::ProcessSignals>>) -> SafeTxPool {
+    info!("Init transaction pool successfully, capacity: {}, min gas price: {}", config.txpool_capacity, config.min_gas_price);
+    Box::new(BaseTxPool::with_capacity(config.txpool_capacity, config.min_gas_price)
+        .with_size_limits(config.size_limits())
+        .with_accounts(chain)
+        .with_chain_id(config.chain_id)
+        .with_notifier(notifier)) as SafeTxPool
+}
+
+fn spawn_txpool_event_notify(config: &Config) -> Addr<TxPoolEventCT::ProcessSignals> {
+    let (capacity, policy) = (config.event_queue_capacity, config.chain_event_policy);
+    Actor::create(move |_| TxPoolEventCT::ProcessSignals::with_policy(capacity, policy))
 }
 
-fn init_transaction_pool(_: &Config) -> SafeTxPool {
-    info!("Init transaction pool successfully");
-    Box::new(BaseTxPool::new()) as SafeTxPool
+/// the identity file of the store overrides the `peer_id` and the `node_key` of the config, the
+/// first run seeds it with the node key of the config, or the local validator key, or a fresh key.
+/// The peer id is always derived from the node key
+fn init_identity(config: &mut Config) -> Result<(), String> {
+    let data_dir = config.data_dir();
+    let identity = match NodeIdentity::load(&data_dir)? {
+        Some(mut identity) => {
+            let derived = key_peer_id(&identity.key_pair()?)?.to_base58();
+            if derived != identity.peer_id {
+                warn!("The peer id of the identity file isn't derived from its node key, {} => {}, path: {:?}", identity.peer_id, derived, NodeIdentity::path(&data_dir));
+                identity.peer_id = derived;
+                identity.save(&data_dir)?;
+            }
+            let peer_id = config.local_peer_id().map(|peer_id| peer_id.to_base58()).ok();
+            if peer_id.as_ref() != Some(&identity.peer_id) {
+                warn!("The peer id of the config is ignored, the identity file keeps {}, path: {:?}", identity.peer_id, NodeIdentity::path(&data_dir));
+            }
+            let node = config.node_key_pair().ok().and_then(|node_key| node_key).map(|node_key| node_key.address());
+            if node.is_some() && node != identity.key_pair().ok().map(|node_key| node_key.address()) {
+                warn!("The node key of the config is ignored, the identity file keeps its own, path: {:?}", NodeIdentity::path(&data_dir));
+            }
+            identity
+        }
+        None => {
+            let node_key = match config.node_key_pair()? {
+                Some(node_key) => node_key,
+                None if config.has_local_key() && config.signer.is_none() => {
+                    warn!("The node key is not configured, the validator key backs the p2p identity too");
+                    config.validator_key_pair()?
+                }
+                None => Random.generate().map_err(|err| format!("{:?}", err))?,
+            };
+            let identity = NodeIdentity::new(&node_key, &key_peer_id(&node_key)?);
+            identity.save(&data_dir)?;
+            info!("Node identity is created, peer id: {}, path: {:?}", identity.peer_id, NodeIdentity::path(&data_dir));
+            identity
+        }
+    };
+    config.peer_id = identity.peer_id;
+    config.node_key = Some(identity.node_key);
+    Ok(())
 }
 
-fn init_store(config: &Config) -> Result<Ledger, String> {
+fn init_store(config: &Config) -> Result<Ledger, NodeError> {
     info!("Init store: {}", config.store);
-    let genesis_config = config.genesis.as_ref().unwrap();
+    let backend = Backend::open(&config.store).map_err(|err| StoreError::Open(config.store.clone(), err))?;
+    if backend.is_memory() {
+        warn!("The store is kept in memory, the chain is lost when the node stops");
+    }
+    let ledger = init_ledger(config, backend)?;
+    // the memory store has nothing to flush
+    if config.is_memory_store() {
+        return Ok(ledger);
+    }
+    let mut ledger = ledger.with_durability(config.durability.clone(), &config.store);
+    // nothing writes the store yet
+    compact_if_due(&mut ledger, &config.maintenance, &config.store).map_err(|err| StoreError::Open(config.store.clone(), err))?;
+    Ok(ledger)
+}
+
+/// the ledger over the existing store which refuses the writes, eg: the inspector, the store is
+/// neither compacted nor flushed. Rocksdb may still rewrite its own files on open, see
+/// `Backend::open_read_only`
+fn init_read_only_store(config: &Config) -> Result<Ledger, NodeError> {
+    info!("Init read only store: {}, rocksdb may still replay its log on open", config.store);
+    let backend = Backend::open_read_only(&config.store).map_err(|err| StoreError::Open(config.store.clone(), err))?;
+    init_ledger(config, backend)
+}
+
+fn init_ledger(config: &Config, backend: Backend) -> Result<Ledger, NodeError> {
+    let genesis_config = config.genesis.as_ref().ok_or(ConfigError::MissingGenesis)?;
 
     let mut validators: Vec<Validator> = vec![];
     for validator in &genesis_config.validator {
-        validators.push(Validator::new(common::string_to_address(validator)?));
+        validators.push(Validator::new(common::string_to_address(validator).map_err(ConfigError::Invalid)?));
     }
 
-    let database = Database::open_default(&config.store).map_err(|err| err.to_string())?;
-    let schema = Schema::new(Arc::new(database));
-    Ok(Ledger::new(
+    let schema = Schema::new(Arc::new(backend));
+    let ledger = Ledger::new(
         LastMeta::new_zero(),
-        LruCache::with_capacity(1 << 10),
-        LruCache::with_capacity(1 << 10),
+        LruCache::with_capacity(config.cache.headers),
+        LruCache::with_capacity(config.cache.blocks),
         validators,
         schema,
-    ))
+    ).with_validator_cache(config.cache.validators)
+        .with_state_config(config.state);
+    Ok(ledger)
 }
 
 fn init_genesis(chain: &mut Chain) -> ChainResult {
@@ -182,44 +570,90 @@ fn init_genesis(chain: &mut Chain) -> ChainResult {
 }
 
 fn start_consensus_engine(
-    _config: &Config,
-    key_pair: KeyPair,
+    config: &Config,
+    signer: SafeSigner,
+    sign_guard: SafeSignGuard,
     chain: Arc<Chain>,
     subscriber: Addr<BroadcastEventSubscriber>,
-) -> (Addr<Core>, SafeEngine) {
-    info!("Init consensus engine");
-    let mut result = create_bft_engine(key_pair, chain, subscriber);
-    result.1.start().unwrap();
-    result
+) -> Result<(Option<(Addr<Core>, System)>, SafeEngine), ConsensusError> {
+    info!("Init consensus engine, engine: {:?}", config.engine);
+    let (core, mut engine) = match config.engine {
+        EngineKind::Pbft => {
+            let (core_pid, core_system, engine) = create_bft_engine(signer, sign_guard, chain, subscriber);
+            (Some((core_pid, core_system)), engine)
+        }
+        EngineKind::Dpos => (None, create_dpos_engine(signer, sign_guard, chain)),
+    };
+    engine.start().map_err(|err| ConsensusError::Unknown(format!("failed to start the engine, {}", err)))?;
+    Ok((core, engine))
 }
 
 fn start_mint(
     config: &Config,
-    key_pair: KeyPair,
+    signer: SafeSigner,
     chain: Arc<Chain>,
     txpool: Arc<RwLock<SafeTxPool>>,
     engine: SafeEngine,
+    txpool_events: Addr<TxPoolEventCT::ProcessSignals>,
 ) -> Addr<Minner> {
-    let minter = key_pair.address();
+    let minter = signer.address();
+    let mode = config.mining_mode;
     Minner::create(move |ctx| {
         let recipient = ctx.address().recipient();
         chain.subscriber_event(recipient);
+        txpool_events.do_send(TxPoolEventCT::SubscribeMessage::new_subscribe(ctx.address().recipient()));
         let (tx, rx) = crossbeam::channel::bounded(1);
-        Minner::new(minter, key_pair, chain, txpool, engine, tx, rx)
+        Minner::new(minter, signer, chain, txpool, engine, tx, rx, mode)
     })
 }
 
-fn init_api(config: &Config, chain: Arc<Chain>) {
+// the api and the websocket servers stop when the shutdown is shut down, eg: the chain stops
+fn init_api(config: &Config, chain: Arc<Chain>, txpool: Arc<RwLock<SafeTxPool>>, minner: Addr<Minner>, p2p: Addr<TcpServer>, txpool_events: Addr<TxPoolEventCT::ProcessSignals>, shutdown: &Shutdown) -> Result<(), ApiError> {
     let config = config.clone();
-    let chain = chain.clone();
+    // the subscriptions resume from their persisted cursors
+    let subscriptions = Arc::new(SubscriptionManager::new(chain.clone()));
+    subscriptions.resume();
+    if let Some(ws_port) = config.api.ws_port {
+        init_ws(&config, ws_port, chain.clone(), txpool_events, subscriptions.clone(), shutdown)?;
+    }
+    let features = node_features(&config);
+    let state = ApiState { chain: chain.clone(), txpool, minner: Some(minner), p2p: Some(p2p), dev: config.dev, features, reads: Default::default(), blocking: Default::default(), subscriptions: Some(subscriptions), max_page_size: config.api.max_page_size, max_batch_size: config.api.max_batch_size };
+    // the address is bound before the node starts, the node doesn't run without its api
+    let server = bind_api(state, config.api_ip.clone(), config.api_port, config.api.clone())?.with_shutdown(shutdown);
+    spawn(move || {
+        info!("Start service api, address: {}:{}", config.api_ip, config.api_port);
+        server.serve();
+    });
+    Ok(())
+}
+
+// the websocket address is bound before the node starts like the api's
+fn init_ws(config: &Config, port: u16, chain: Arc<Chain>, txpool_events: Addr<TxPoolEventCT::ProcessSignals>, subscriptions: Arc<SubscriptionManager>, shutdown: &Shutdown) -> Result<(), ApiError> {
+    let listen: ::std::net::SocketAddr = format!("{}:{}", config.api_ip, port).parse()
+        .map_err(|err| ApiError::InvalidAddress(format!("{}:{}, {}", config.api_ip, port, err)))?;
+    let tls = api_tls_config(&config.api)?;
+    let (max_subscriptions, max_batch_size, max_connections) = (config.api.max_ws_subscriptions, config.api.max_batch_size, config.api.max_ws_connections);
+    let hub = WsHub::new(chain, txpool_events, Some(subscriptions), max_subscriptions, max_batch_size).start();
+    let server = bind_ws(listen, hub, max_connections, tls)?.with_shutdown(shutdown);
     spawn(move || {
-        info!("Start service api");
-        start_api(chain, config.api_ip, config.api_port);
+        info!("Start websocket api, address: {}", listen);
+        if let Err(err) = server.serve() {
+            error!("Failed to serve the websocket api, err: {}", err);
+        }
     });
+    Ok(())
 }
 
-fn init_signal_handle() {
-    spawn_signal_handler(*common::random_dir());
+fn init_features(config: &Config) {
+    let features = node_features(config);
+    report_features(&features);
+    install_crash_dump(format!("{}/crash", config.data_dir()), features);
+}
+
+fn init_metrics(config: &Config) {
+    if let Some(ref url) = config.metrics_push_url {
+        metrics::spawn_push_gateway(url.clone(), config.metrics_push_interval);
+    }
 }
 
 fn print_art() {
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::common::hash_algo::set_hash_algo;
+use crate::core::chain::Chain;
+use crate::core::tx_pool::SafeTxPool;
+use crate::error::{ConfigError, NodeError};
+use crate::pprof;
+use super::keystore::unlock;
+use super::{init_config, init_features, init_metrics, port_muxes, spawn_chain, ChainHandle, NodeBuilder};
+
+/// Node is a running node embedded in the process, the other programs drive it by the handle, eg:
+///
+/// ```ignore
+/// let node = Node::start("config.toml")?;
+/// let height = node.chain().get_last_height();
+/// node.stop();
+/// ```
+///
+/// The hooks are injected by `NodeBuilder::new("config.toml").proposal_policy(..).build()`.
+pub struct Node {
+    // the chain of the root config keys
+    main_chain_id: u64,
+    chains: HashMap<u64, ChainHandle>,
+    // the threads of a stopped node end quietly instead of exiting the process
+    stopping: Arc<AtomicBool>,
+}
+
+impl Node {
+    pub fn start(config: &str) -> Result<Node, NodeError> {
+        NodeBuilder::new(config).build()
+    }
+
+    pub(crate) fn with_builder(builder: NodeBuilder) -> Result<Node, NodeError> {
+        let mut config = init_config(&builder.config)?;
+        if builder.keystore.is_some() {
+            config.keystore = builder.keystore.clone();
+        }
+        if builder.password_file.is_some() {
+            config.password_file = builder.password_file.clone();
+        }
+        // the chains inherit the unlocked key of the root config
+        unlock(&mut config).map_err(NodeError::Signer)?;
+        // the hashes of the genesis block depend on it, so it's selected before the store is opened
+        set_hash_algo(config.hash_algo()).map_err(ConfigError::Invalid)?;
+        info!("Hash algo: {:?}", config.hash_algo());
+        let configs = config.chain_configs().map_err(ConfigError::Invalid)?;
+
+        init_metrics(&config);
+        init_features(&config);
+        pprof::set_output_dir(config.pprof_dir());
+
+        let muxes = port_muxes(&configs);
+        let mut node = Node { main_chain_id: config.chain_id, chains: HashMap::new(), stopping: Arc::new(AtomicBool::new(false)) };
+        for (name, config) in configs {
+            let chain_id = config.chain_id;
+            let mux = muxes.get(&config.port).cloned();
+            match spawn_chain(&builder, config, mux, node.stopping.clone()) {
+                Ok(handle) => {
+                    info!("Chain `{}` started, chain id: {}", name, chain_id);
+                    node.chains.insert(chain_id, handle);
+                }
+                Err(err) => {
+                    node.stop();
+                    return Err(NodeError::ChainStart(name, Box::new(err)));
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    /// the chain of the root config keys
+    pub fn chain(&self) -> &Arc<Chain> {
+        &self.chains[&self.main_chain_id].chain
+    }
+
+    pub fn tx_pool(&self) -> &Arc<RwLock<SafeTxPool>> {
+        &self.chains[&self.main_chain_id].tx_pool
+    }
+
+    /// the chain of a `[chains.*]` section, eg: a test shard
+    pub fn chain_handle(&self, chain_id: u64) -> Option<&ChainHandle> {
+        self.chains.get(&chain_id)
+    }
+
+    pub fn chain_ids(&self) -> Vec<u64> {
+        let mut chain_ids: Vec<u64> = self.chains.keys().cloned().collect();
+        chain_ids.sort();
+        chain_ids
+    }
+
+    /// stop the consensus, the minner and the p2p actors and the apis of all the chains, the stores
+    /// are closed when the last handle is dropped. The apis finish the requests in flight and
+    /// release their ports.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        for (chain_id, handle) in self.chains.iter() {
+            info!("Stop the chain {}", chain_id);
+            handle.stop();
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use rand::random;
+
+    use super::*;
+    use crate::common::random_dir;
+    use crate::util::dev_keys::dev_validator_address;
+
+    #[test]
+    fn t_node_start_stop() {
+        let port = 20000 + random::<u16>() % 20000;
+        let validator = format!("\"0x{}\"", hex::encode(dev_validator_address(0).as_ref()));
+        let config = format!(r#"chain_id = 98
+ip = "127.0.0.1"
+port = {port}
+api_ip = "127.0.0.1"
+api_port = {api_port}
+block_period = 3000
+request_time = 3000
+peer_id = "dev:0"
+ttl = 3000
+store = "{store}"
+secret = "dev:0"
+dev = true
+
+[genesis]
+validator = [{validator}]
+epoch_time = 2019-01-01T00:00:00Z
+proposer = {validator}
+gas_used = 0
+extra = "test"
+[genesis.accounts]
+"#, port = port, api_port = port + 1, store = random_dir(), validator = validator);
+        let path = format!("{}.toml", random_dir());
+        fs::write(&path, config).unwrap();
+
+        let node = Node::start(&path).unwrap();
+        assert_eq!(node.chain_ids(), vec![98]);
+        assert!(node.chain().get_block_by_height(0).is_some());
+        let handle = node.chain_handle(98).unwrap().clone();
+        assert!(handle.core.is_some());
+
+        node.stop();
+        assert!(node.is_stopped());
+        // the systems of the chain end, so their actors are dropped
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.minner.connected() || handle.p2p.connected() || handle.core.as_ref().map_or(false, |core| core.connected()) {
+            assert!(Instant::now() < deadline, "the actors of the stopped chain still run");
+            thread::sleep(Duration::from_millis(50));
+        }
+        // the api is shut down, its port is released
+        while ::std::net::TcpListener::bind(("127.0.0.1", port + 1)).is_err() {
+            assert!(Instant::now() < deadline, "the api of the stopped node still listens");
+            thread::sleep(Duration::from_millis(50));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use crate::{
+    common::hash_algo::set_hash_algo,
+    core::maintenance::compact,
+    core::reindex::reindex as reindex_store,
+    error::StoreError,
+    logger::init_log,
+    util::canonical::OutputFormat,
+};
+use super::{init_config, init_store};
+
+/// Rebuild the secondary indexes of the store of a stopped node from its raw blocks, eg: after
+/// a new index is added to an existing datadir or after a partial corruption.
+pub fn reindex(config: &str, format: OutputFormat) -> Result<(), String> {
+    init_log();
+    let config = init_config(config).map_err(|err| err.to_string())?;
+    if !Path::new(&config.store).exists() {
+        return Err(StoreError::NotFound(config.store).to_string());
+    }
+    // the keys of the indexes are the hashes of the chain's algo
+    set_hash_algo(config.hash_algo())?;
+    let ledger = init_store(&config).map_err(|err| err.diagnostic())?;
+    let report = reindex_store(ledger.get_schema(), |height, last_height| {
+        info!("Reindex blocks, height: {}/{}", height, last_height);
+    })?;
+    for skipped in report.skipped.iter() {
+        warn!("Skip blocks, {}", skipped);
+    }
+    // the cleared indexes leave their tombstones behind
+    if let (true, Some(db)) = (config.maintenance.compact_after_reindex, ledger.get_schema().database().rocksdb()) {
+        let compaction = compact(db, &config.store)?;
+        info!("Compact the store after the reindex, {:?}", compaction);
+    }
+    println!("{}", format.render(&report));
+    Ok(())
+}
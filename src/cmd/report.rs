@@ -0,0 +1,78 @@
+use std::path::Path;
+
+use crate::{
+    common,
+    common::hash_algo::set_hash_algo,
+    config::Config,
+    core::ledger::Ledger,
+    core::report::{validator_report, SignedReport},
+    error::StoreError,
+    types::Height,
+    util::canonical::OutputFormat,
+};
+use super::keystore::unlock;
+use super::{init_config, init_read_only_store, init_signer};
+
+/// Build the SLA report of a validator from the store of a stopped node, it's signed by the
+/// validator key of the node, the store is opened read only, so no chain data is written to it,
+/// see `Backend::open_read_only`.
+pub fn report(config: &str, from: Height, to: Height, validator: &str, format: OutputFormat) -> Result<(), String> {
+    let mut config = init_config(config).map_err(|err| err.to_string())?;
+    unlock(&mut config)?;
+    set_hash_algo(config.hash_algo())?;
+    let validator = common::string_to_address(&validator.to_owned())?;
+    let signer = init_signer(&config)?;
+    let ledger = open_report_store(&config)?;
+    let report = validator_report(&ledger, validator, from, to)?;
+    println!("{}", format.render(&SignedReport::sign(report, signer.as_ref())?));
+    Ok(())
+}
+
+// the ledger over the existing store, it's neither created nor compacted
+fn open_report_store(config: &Config) -> Result<Ledger, String> {
+    if !Path::new(&config.store).exists() {
+        return Err(StoreError::NotFound(config.store.clone()).to_string());
+    }
+    let mut ledger = init_read_only_store(config).map_err(|err| err.diagnostic())?;
+    if ledger.get_schema().block_hashes_by_height().len() == 0 {
+        return Err(format!("store is empty: {}", config.store));
+    }
+    ledger.reload_meta();
+    Ok(ledger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::Hash;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::core::test_util::{genesis_config, sealed_block};
+    use crate::store::backend::Backend;
+    use super::super::init_ledger;
+
+    #[test]
+    fn t_report_read_only() {
+        let key_pair = Random.generate().unwrap();
+        let mut config = Config::default();
+        config.store = *common::random_dir();
+        config.genesis = Some(genesis_config(&key_pair));
+        {
+            let mut ledger = init_ledger(&config, Backend::open(&config.store).unwrap()).unwrap();
+            let mut parent = Hash::zero();
+            for height in 0..3 {
+                let block = sealed_block(parent, height, &key_pair);
+                parent = block.hash();
+                ledger.add_block(&block);
+            }
+        }
+        let ledger = open_report_store(&config).unwrap();
+        assert!(ledger.get_schema().database().is_read_only());
+        let report = validator_report(&ledger, key_pair.address(), 1, 2).unwrap();
+        assert_eq!((report.blocks, report.proposed, report.commits), (2, 2, 2));
+
+        // a missing store isn't created
+        config.store = *common::random_dir();
+        assert!(open_report_store(&config).is_err());
+        assert!(!Path::new(&config.store).exists());
+    }
+}
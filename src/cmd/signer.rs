@@ -0,0 +1,25 @@
+use std::fs;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{
+    consensus::sign_guard::SignGuard,
+    keystore::KeyFile,
+    signer::{read_token_file, serve_signer, LocalSigner},
+};
+use super::keystore::read_password;
+
+/// run the signing box of a validator, the key is unlocked from the keystore and never leaves
+/// the process, the nodes sign by `[signer]` with the same token. The high-water mark of the
+/// votes is kept in the state dir, so a restarted signer still refuses the old votes
+pub fn serve(listen: &str, keystore: &str, password_file: Option<&str>, token_file: &str, state_dir: &str) -> Result<(), String> {
+    let key_pair = KeyFile::load(keystore)?.decrypt(&read_password(password_file, false)?)
+        .map_err(|err| format!("failed to unlock the keystore {}, {}", keystore, err))?;
+    let token = read_token_file(token_file)?;
+    fs::create_dir_all(state_dir).map_err(|err| format!("failed to create the state dir {}, {}", state_dir, err))?;
+    let guard = Arc::new(Mutex::new(SignGuard::load(state_dir)?));
+    let listener = TcpListener::bind(listen).map_err(|err| format!("failed to listen on {}, {}", listen, err))?;
+    serve_signer(listener, Arc::new(LocalSigner::new(key_pair)), token, guard)
+}
@@ -0,0 +1,188 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use blake2::VarBlake2b;
+use blake2::digest::{Input as BlakeInput, VariableOutput};
+use cryptocurrency_kit::crypto::{hash, Hash};
+use sha3::{Digest, Keccak256, Sha3_256};
+
+/// the hash function of the chain data (block, transaction, seal digests ...), it's selected
+/// by the genesis config and all nodes of a chain must agree on it, eg:
+///
+/// ```toml
+/// [genesis]
+/// hash_algo = "keccak256"
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgo {
+    // the kit's default, the chains created before the option keep it
+    Default,
+    Keccak256,
+    #[serde(rename = "sha3_256")]
+    Sha3_256,
+    // blake2b with a 32 bytes output
+    Blake2b,
+}
+
+impl Default for HashAlgo {
+    fn default() -> Self {
+        HashAlgo::Default
+    }
+}
+
+impl HashAlgo {
+    fn from_usize(v: usize) -> Self {
+        match v {
+            1 => HashAlgo::Keccak256,
+            2 => HashAlgo::Sha3_256,
+            3 => HashAlgo::Blake2b,
+            _ => HashAlgo::Default,
+        }
+    }
+
+    pub fn digest<T: AsRef<[u8]>>(self, data: T) -> Hash {
+        let data = data.as_ref();
+        match self {
+            HashAlgo::Default => hash(data),
+            HashAlgo::Keccak256 => {
+                let mut hasher = Keccak256::default();
+                hasher.input(data);
+                Hash::from_slice(hasher.result().as_slice()).unwrap()
+            }
+            HashAlgo::Sha3_256 => {
+                let mut hasher = Sha3_256::default();
+                hasher.input(data);
+                Hash::from_slice(hasher.result().as_slice()).unwrap()
+            }
+            HashAlgo::Blake2b => {
+                let mut hasher = VarBlake2b::new(32).unwrap();
+                hasher.input(data);
+                let mut out = None;
+                hasher.variable_result(|bytes| out = Hash::from_slice(bytes));
+                out.unwrap()
+            }
+        }
+    }
+}
+
+lazy_static! {
+    // the selected algo plus one, zero until it's selected
+    static ref HASH_ALGO: AtomicUsize = AtomicUsize::new(0);
+}
+
+thread_local! {
+    // overrides the process's algo on the thread, see `with_hash_algo`
+    static SCOPED_HASH_ALGO: Cell<Option<HashAlgo>> = Cell::new(None);
+}
+
+/// select the hash function of the process, it should be called once before the genesis block is
+/// loaded. The hashes can't be told apart by the chain, so all chains of the process share it and
+/// it can't be switched once it's selected.
+pub fn set_hash_algo(algo: HashAlgo) -> Result<(), String> {
+    let selected = HASH_ALGO.compare_and_swap(0, algo as usize + 1, Ordering::SeqCst);
+    if selected != 0 && selected != algo as usize + 1 {
+        return Err(format!("the hash algo {:?} is selected, it can't be switched to {:?}", HashAlgo::from_usize(selected - 1), algo));
+    }
+    Ok(())
+}
+
+pub fn hash_algo() -> HashAlgo {
+    if let Some(algo) = SCOPED_HASH_ALGO.with(|scoped| scoped.get()) {
+        return algo;
+    }
+    match HASH_ALGO.load(Ordering::SeqCst) {
+        0 => HashAlgo::Default,
+        selected => HashAlgo::from_usize(selected - 1),
+    }
+}
+
+/// run `f` with the algo on the current thread only, eg: the tools which read a chain of
+/// another algo, or the tests which run in parallel
+pub fn with_hash_algo<F, R>(algo: HashAlgo, f: F) -> R
+    where F: FnOnce() -> R {
+    let last = SCOPED_HASH_ALGO.with(|scoped| scoped.replace(Some(algo)));
+    let result = f();
+    SCOPED_HASH_ALGO.with(|scoped| scoped.set(last));
+    result
+}
+
+/// hash the data with the selected hash function
+pub fn digest<T: AsRef<[u8]>>(data: T) -> Hash {
+    hash_algo().digest(data)
+}
+
+/// the merkle root of the leaves by the algo, a leaf without a sibling is paired with itself
+pub fn merkle_root(algo: HashAlgo, leaves: Vec<Vec<u8>>) -> Hash {
+    let mut level: Vec<Hash> = leaves.iter().map(|leaf| algo.digest(leaf)).collect();
+    if level.is_empty() {
+        return algo.digest(b"");
+    }
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| {
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            let mut buffer = pair[0].as_ref().to_vec();
+            buffer.extend_from_slice(right.as_ref());
+            algo.digest(buffer)
+        }).collect();
+    }
+    level[0]
+}
+
+/// like `implement_cryptohash_traits!`, but the storage bytes are hashed by the selected hash function
+#[macro_export]
+macro_rules! implement_hash_algo_traits {
+    ($key: ident) => {
+        impl cryptocurrency_kit::crypto::CryptoHash for $key {
+            fn hash(&self) -> cryptocurrency_kit::crypto::Hash {
+                use cryptocurrency_kit::storage::values::StorageValue;
+                $crate::common::hash_algo::digest(self.clone().into_bytes())
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_hash_algo() {
+        assert_eq!(HashAlgo::Default.digest(&b""[..]), hash(&b""[..]));
+        assert_eq!(hex::encode(HashAlgo::Keccak256.digest(b"").as_ref()), "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+        assert_eq!(hex::encode(HashAlgo::Sha3_256.digest(b"").as_ref()), "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a");
+        assert_eq!(hex::encode(HashAlgo::Blake2b.digest(b"").as_ref()), "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8");
+        for algo in &[HashAlgo::Default, HashAlgo::Keccak256, HashAlgo::Sha3_256, HashAlgo::Blake2b] {
+            assert_eq!(HashAlgo::from_usize(*algo as usize), *algo);
+        }
+        let algo: HashAlgo = serde_json::from_str("\"sha3_256\"").unwrap();
+        assert_eq!(algo, HashAlgo::Sha3_256);
+    }
+
+    #[test]
+    fn t_with_hash_algo() {
+        let outer = hash_algo();
+        let inner = with_hash_algo(HashAlgo::Blake2b, || {
+            assert_eq!(with_hash_algo(HashAlgo::Keccak256, || digest(b"")), HashAlgo::Keccak256.digest(b""));
+            digest(b"")
+        });
+        assert_eq!(inner, HashAlgo::Blake2b.digest(b""));
+        assert_eq!(hash_algo(), outer);
+        // the other threads keep the process's algo
+        let other = ::std::thread::spawn(hash_algo);
+        with_hash_algo(HashAlgo::Sha3_256, || assert_eq!(other.join().unwrap(), outer));
+    }
+
+    #[test]
+    fn t_merkle_root() {
+        let algo = HashAlgo::Keccak256;
+        let (a, b, c) = (vec![1_u8], vec![2_u8], vec![3_u8]);
+        let pair = |left: Hash, right: Hash| algo.digest([left.as_ref(), right.as_ref()].concat());
+        assert_eq!(merkle_root(algo, vec![a.clone()]), algo.digest(&a));
+        let ab = pair(algo.digest(&a), algo.digest(&b));
+        assert_eq!(merkle_root(algo, vec![a.clone(), b.clone()]), ab);
+        let cc = pair(algo.digest(&c), algo.digest(&c));
+        assert_eq!(merkle_root(algo, vec![a.clone(), b.clone(), c.clone()]), pair(ab, cc));
+        assert_ne!(merkle_root(HashAlgo::Blake2b, vec![a, b, c]), pair(ab, cc));
+    }
+}
@@ -6,6 +6,7 @@ use core::str::FromStr;
 use std::env;
 use std::fmt::{self, Display};
 use std::net::{SocketAddr, AddrParseError};
+use std::time::Duration;
 
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
 use cryptocurrency_kit::merkle_tree::MerkleTree;
@@ -15,7 +16,15 @@ use libp2p::{
     Multiaddr,
 };
 
+#[macro_use]
+pub mod hash_algo;
+
 pub fn merkle_tree_root<T: StorageValue>(input: Vec<T>) -> Hash {
+    // the chains of the kit's default keep the kit's tree
+    let algo = hash_algo::hash_algo();
+    if algo != hash_algo::HashAlgo::Default {
+        return hash_algo::merkle_root(algo, input.into_iter().map(|item| item.into_bytes()).collect());
+    }
     let mut v: Vec<Vec<_>> = vec![];
     for item in input {
         let bytes = item.into_bytes();
@@ -85,6 +94,50 @@ pub fn multiaddr_to_ipv4(mul_addr: &Multiaddr) -> Result<SocketAddr, AddrParseEr
     ipv4.parse()
 }
 
+/// Post a json body to a `http://` or `https://` url, returns the response status code
+pub fn http_post_json(url: &str, body: &str, timeout: Duration) -> Result<u16, String> {
+    http_post(url, "application/json", body, timeout)
+}
+
+/// the urls which `http_post` can post to, `http://` or `https://` with a host
+pub fn is_http_url(url: &str) -> bool {
+    match url.parse::<hyper::Uri>() {
+        Ok(uri) => (uri.scheme_str() == Some("http") || uri.scheme_str() == Some("https")) && uri.host().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// post the body by hyper on a runtime of the calling thread, the https receivers are verified
+/// against the webpki roots. A status above 2xx is an error.
+pub fn http_post(url: &str, content_type: &str, body: &str, timeout: Duration) -> Result<u16, String> {
+    use hyper::{header::CONTENT_TYPE, Body, Client, Request};
+    use hyper_rustls::HttpsConnector;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Timeout;
+
+    if !is_http_url(url) {
+        return Err(format!("invalid url: {}", url));
+    }
+    let request = Request::post(url)
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body.to_owned()))
+        .map_err(|err| err.to_string())?;
+    // the connection isn't pooled, the runtime is dropped with the post
+    let client = Client::builder().keep_alive(false).build::<_, Body>(HttpsConnector::new(1));
+    let mut runtime = Runtime::new().map_err(|err| err.to_string())?;
+    let response = runtime.block_on(Timeout::new(client.request(request), timeout))
+        .map_err(|err| if err.is_elapsed() {
+            format!("timeout after {:?}", timeout)
+        } else {
+            err.into_inner().map_or("timer failure".to_string(), |err| err.to_string())
+        })?;
+    let status = response.status().as_u16();
+    if status >= 300 {
+        return Err(format!("unexpected status code: {}", status));
+    }
+    Ok(status)
+}
+
 pub fn random_uuid() -> uuid::Uuid {
     use uuid::Uuid;
     Uuid::new_v5(&Uuid::NAMESPACE_DNS, chrono::Local::now().to_string().as_bytes())
@@ -126,4 +179,14 @@ mod test {
         assert_eq!("0x93908f59c6eff007d228398349214acb6b4ac9a4", format!("{:?}", address));
         println!("address: {:?}", address);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn t_is_http_url() {
+        assert!(is_http_url("http://127.0.0.1:9000/hook"));
+        assert!(is_http_url("https://indexer.example.org/hook"));
+        assert!(!is_http_url("ftp://indexer.example.org/hook"));
+        assert!(!is_http_url("/hook"));
+        assert!(!is_http_url("indexer"));
+        assert!(http_post_json("ws://127.0.0.1:9000", "{}", Duration::from_secs(1)).is_err());
+    }
+}
@@ -1,19 +1,66 @@
 use std::time::Duration;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::path::Path;
+use std::{env, process};
 
 use toml::Value as Toml;
 use toml::value::Table;
 use toml::value::Datetime;
+use cryptocurrency_kit::crypto::{hash, Hash};
+use cryptocurrency_kit::ethkey::{Address, KeyPair, Secret};
+use libp2p::PeerId;
 
-use crate::common::random_dir;
+use crate::api::ApiConfig;
+use crate::common::hash_algo::HashAlgo;
+use crate::common::{random_dir, string_to_address};
+use crate::consensus::consensus::EngineKind;
+use crate::consensus::dpos::DposConfig;
+use crate::consensus::epoch::DEFAULT_EPOCH_LENGTH;
+use crate::core::checkpoint::{CheckpointConfig, DEFAULT_CHECKPOINT_INTERVAL};
+use crate::core::gas::GasConfig;
+use crate::core::limits::{SizeLimits, DEFAULT_MAX_BLOCK_SIZE, DEFAULT_MAX_TX_SIZE};
+use crate::core::ledger::DEFAULT_CACHE_CAPACITY;
+use crate::core::tx_pool::MAX_TXPOOL_SIZE;
+use crate::core::scrub::ScrubConfig;
+use crate::core::maintenance::MaintenanceConfig;
+use crate::store::backend::is_memory;
+use crate::store::durability::DurabilityConfig;
+use crate::core::state::StateConfig;
+use crate::core::staking::StakingConfig;
+use crate::core::reward::RewardConfig;
+use crate::core::fee::FeeConfig;
+use crate::core::warm_up::WarmUpConfig;
+use crate::keystore::UnlockedKey;
+use crate::minner::MiningMode;
+use crate::p2p::bandwidth::TrafficCategory;
+use crate::p2p::serve::DEFAULT_SERVE_RATE;
+use crate::p2p::outbound::OutboundConfig;
+use crate::p2p::sync::SyncPolicy;
+use crate::signer::DEFAULT_SIGNER_TIMEOUT;
+use crate::subscriber::events::MAX_MAILBOX_CAPACITY;
+use crate::subscriber::queue::{OverflowPolicy, DEFAULT_QUEUE_CAPACITY};
+use crate::types::Height;
+use crate::util::dev_keys::{dev_key_pair, dev_name, dev_node_key, dev_peer_id, parse_dev_index};
 
-#[derive(Debug, Clone, Deserialize)]
+pub mod schema;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub chain_id: u64,
     pub ip: String,
     pub port: u16,
+    // the address announced to the other peers when it differs from the bind address, eg: behind NAT
+    #[serde(default)]
+    pub advertised_ip: Option<String>,
+    #[serde(default)]
+    pub advertised_port: Option<u16>,
     pub api_ip: String,
     pub api_port: u16,
+    // the browser access and the limits of the api
+    #[serde(default)]
+    pub api: ApiConfig,
     #[serde(with = "serde_millis")]
     pub block_period: Duration,
     #[serde(with = "serde_millis")]
@@ -21,19 +68,647 @@ pub struct Config {
     pub peer_id: String,
     #[serde(with = "serde_millis")]
     pub ttl: Duration,
+    // the directory of the store, `:memory:` keeps the store in memory, it's lost when the node stops
     pub store: String,
+    // the directory of the node files: the identity, the address book and the
+    // crash dumps, the store directory if it's absent
+    #[serde(default)]
+    pub data_dir: Option<String>,
+    // the directory of the sign state, it's never inside the store, a restored store backup
+    // must not roll the high-water mark back
+    #[serde(default)]
+    pub sign_state_dir: Option<String>,
+    // the validator key, it signs the consensus messages, it's empty if the key is kept by
+    // the keystore
+    #[serde(default)]
     pub secret: String,
+    // the encrypted key file of the validator key, it replaces the `secret`
+    #[serde(default)]
+    pub keystore: Option<String>,
+    // the password of the keystore, it's asked on the terminal if it's absent
+    #[serde(default)]
+    pub password_file: Option<String>,
+    // the validator key unlocked from the keystore, it's kept in memory only, so the chain
+    // sections and the dumps of the config never carry it
+    #[serde(skip)]
+    pub unlocked_key: Option<UnlockedKey>,
+    // the remote signer which keeps the validator key, it replaces the `secret` and the keystore
+    #[serde(default)]
+    pub signer: Option<SignerConfig>,
+    // the key of the p2p identity, the validator key is used if it's absent
+    #[serde(default)]
+    pub node_key: Option<String>,
     pub genesis: Option<GenesisConfig>,
+    // the fresh node refuses the chains which don't include the checkpoint
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+    // more trusted checkpoints, eg: `[[checkpoints]]`
+    #[serde(default)]
+    pub checkpoints: Vec<CheckpointConfig>,
+    // a checkpoint record is persisted every N blocks, 0 disables it
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: Height,
+    #[serde(default)]
+    pub gas: GasConfig,
+    // the headers below it are not checked by the bft header rules, so the stores which were
+    // written before the rules can be synced
+    #[serde(default)]
+    pub bft_fields_height: Height,
+    // dev mode enables the `/dev/*` apis
+    #[serde(default)]
+    pub dev: bool,
+    #[serde(default)]
+    pub mining_mode: MiningMode,
+    #[serde(default = "default_txpool_capacity")]
+    pub txpool_capacity: u64,
+    #[serde(default)]
+    pub min_gas_price: u64,
+    #[serde(default = "default_event_queue_capacity")]
+    pub event_queue_capacity: usize,
+    #[serde(default = "default_chain_event_policy")]
+    pub chain_event_policy: OverflowPolicy,
+    #[serde(default)]
+    pub p2p_event_policy: OverflowPolicy,
+    #[serde(default = "default_session_mailbox_capacity")]
+    pub session_mailbox_capacity: usize,
+    // the outbound bytes per second of every traffic category, eg: `tx_gossip = 65536`
+    #[serde(default)]
+    pub p2p_outbound_quotas: HashMap<TrafficCategory, u64>,
+    #[serde(default)]
+    pub p2p: P2PConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    // pre-load the recent store regions before the node starts
+    #[serde(default)]
+    pub warm_up: WarmUpConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub consensus: ConsensusTimeouts,
+    #[serde(default)]
+    pub engine: EngineKind,
+    // the blocks of an epoch, the validator set only changes at the epoch boundaries
+    #[serde(default = "default_epoch_length")]
+    pub epoch_length: Height,
+    // the delegate elections and the slots, only read by the dpos engine
+    #[serde(default)]
+    pub dpos: DposConfig,
+    #[serde(default)]
+    pub scrub: ScrubConfig,
+    // the disk usage accounting and the compaction of the store
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    // when the committed blocks are flushed and the write ahead log is synced
+    #[serde(default)]
+    pub durability: DurabilityConfig,
+    #[serde(default)]
+    pub state: StateConfig,
+    // the directory of the flame graphs, `<data_dir>/pprof` if it's absent
+    #[serde(default)]
+    pub pprof_dir: Option<String>,
+    #[serde(default)]
+    pub metrics_push_url: Option<String>,
+    #[serde(default = "default_metrics_push_interval", with = "serde_millis")]
+    pub metrics_push_interval: Duration,
+    // the other chains run by the same process, eg: `[chains.shard]`, the keys of a section
+    // override the root keys of the config
+    #[serde(default)]
+    pub chains: BTreeMap<String, Toml>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct P2PConfig {
+    // the dns names which resolve to the bootstrap peers, eg: `seed.example.org` or `seed.example.org:7960`
+    #[serde(default)]
+    pub dns_seeds: Vec<String>,
+    // the peer which reconnects within it resumes its session state, in millis
+    #[serde(default = "default_session_grace", with = "serde_millis")]
+    pub session_grace: Duration,
+    // the sync strategy of every peer class
+    #[serde(default)]
+    pub sync: SyncPolicy,
+    // the bytes per second of the historical blocks served to every peer
+    #[serde(default = "default_serve_rate")]
+    pub serve_rate: u64,
+    // the queues of the messages to the slow peers
+    #[serde(default)]
+    pub outbound: OutboundConfig,
+}
+
+impl Default for P2PConfig {
+    fn default() -> Self {
+        P2PConfig { dns_seeds: vec![], session_grace: default_session_grace(), sync: SyncPolicy::default(), serve_rate: default_serve_rate(), outbound: OutboundConfig::default() }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockConfig {
+    // the ntp servers which the local clock is compared with at startup, eg: `pool.ntp.org:123`
+    #[serde(default)]
+    pub ntp_servers: Vec<String>,
+    // the block timestamps can be ahead of the local clock by it, and the local clock can drift
+    // from the ntp servers by it, in millis
+    #[serde(default = "default_max_clock_drift", with = "serde_millis")]
+    pub max_drift: Duration,
+    // refuse to start instead of warning when the local clock drifts too much
+    #[serde(default)]
+    pub strict: bool,
+    // the gossiped blocks ahead of the local clock within it are held until their timestamps are
+    // valid, the later ones are dropped, in millis
+    #[serde(default = "default_future_block_tolerance", with = "serde_millis")]
+    pub future_block_tolerance: Duration,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            ntp_servers: vec![],
+            max_drift: default_max_clock_drift(),
+            strict: false,
+            future_block_tolerance: default_future_block_tolerance(),
+        }
+    }
+}
+
+/// the signing box which keeps the validator key, eg: `[signer]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignerConfig {
+    // the tcp address of the signer, eg: `10.0.0.5:7100`
+    pub endpoint: String,
+    // the validator address of the key, every signature is checked against it
+    pub validator: String,
+    // the file of the token shared with the signer, it authenticates the requests
+    pub token_file: String,
+    // the timeout of a sign request, in millis
+    #[serde(default = "default_signer_timeout", with = "serde_millis")]
+    pub timeout: Duration,
+}
+
+fn default_signer_timeout() -> Duration {
+    DEFAULT_SIGNER_TIMEOUT
+}
+
+/// the round change timeouts of the consensus phases, the timeout of the round `r` is the phase
+/// timeout multiplied by `timeout_backoff ^ r`, eg: `[consensus]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsensusTimeouts {
+    // wait for the preprepare of the proposer, in millis
+    #[serde(default = "default_phase_timeout", with = "serde_millis")]
+    pub timeout_propose: Duration,
+    // wait for the prepare quorum after the preprepare, in millis
+    #[serde(default = "default_phase_timeout", with = "serde_millis")]
+    pub timeout_prepare: Duration,
+    // wait for the commit quorum after the prepare quorum, in millis
+    #[serde(default = "default_phase_timeout", with = "serde_millis")]
+    pub timeout_commit: Duration,
+    #[serde(default = "default_timeout_backoff")]
+    pub timeout_backoff: f64,
+}
+
+impl Default for ConsensusTimeouts {
+    fn default() -> Self {
+        ConsensusTimeouts {
+            timeout_propose: default_phase_timeout(),
+            timeout_prepare: default_phase_timeout(),
+            timeout_commit: default_phase_timeout(),
+            timeout_backoff: default_timeout_backoff(),
+        }
+    }
+}
+
+fn default_phase_timeout() -> Duration {
+    Duration::from_millis(3 * 1000)
+}
+
+fn default_timeout_backoff() -> f64 {
+    1.0
+}
+
+/// the entries of the ledger caches, eg: `[cache]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_capacity")]
+    pub headers: usize,
+    #[serde(default = "default_cache_capacity")]
+    pub blocks: usize,
+    // the validator addresses by height, the seal verification reads them for every block
+    #[serde(default = "default_cache_capacity")]
+    pub validators: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { headers: default_cache_capacity(), blocks: default_cache_capacity(), validators: default_cache_capacity() }
+    }
+}
+
+fn default_cache_capacity() -> usize {
+    DEFAULT_CACHE_CAPACITY
+}
+
+fn default_future_block_tolerance() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_clock_drift() -> Duration {
+    Duration::from_secs(15)
+}
+
+fn default_session_grace() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_serve_rate() -> u64 {
+    DEFAULT_SERVE_RATE
+}
+
+fn default_txpool_capacity() -> u64 {
+    MAX_TXPOOL_SIZE
+}
+
+fn default_checkpoint_interval() -> Height {
+    DEFAULT_CHECKPOINT_INTERVAL
+}
+
+fn default_event_queue_capacity() -> usize {
+    DEFAULT_QUEUE_CAPACITY
+}
+
+// the chain events should not be lost if possible
+fn default_chain_event_policy() -> OverflowPolicy {
+    OverflowPolicy::Block
+}
+
+fn default_session_mailbox_capacity() -> usize {
+    MAX_MAILBOX_CAPACITY
+}
+
+fn default_metrics_push_interval() -> Duration {
+    Duration::from_millis(15 * 1000)
+}
+
+fn default_epoch_length() -> Height {
+    DEFAULT_EPOCH_LENGTH
+}
+
+fn default_max_block_size_bytes() -> u64 {
+    DEFAULT_MAX_BLOCK_SIZE
+}
+
+fn default_max_tx_size_bytes() -> u64 {
+    DEFAULT_MAX_TX_SIZE
+}
+
+fn parse_key_pair(secret: &str) -> Result<KeyPair, String> {
+    Secret::from_str(secret).map_err(|err| format!("{:?}", err))
+        .and_then(|secret| KeyPair::from_secret(secret).map_err(|err| format!("{:?}", err)))
+}
+
+/// ConsensusParams are the consensus-critical parameters, all nodes of a chain must agree on them
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConsensusParams {
+    pub chain_id: u64,
+    // millis
+    pub block_period: u64,
+    // millis
+    pub request_time: u64,
+    // the genesis validators, they define the quorum
+    pub validators: Vec<String>,
+    #[serde(default)]
+    pub hash_algo: HashAlgo,
+    #[serde(default)]
+    pub engine: EngineKind,
+    #[serde(default = "default_epoch_length")]
+    pub epoch_length: Height,
+    #[serde(default = "default_max_block_size_bytes")]
+    pub max_block_size_bytes: u64,
+    #[serde(default = "default_max_tx_size_bytes")]
+    pub max_tx_size_bytes: u64,
+    #[serde(default)]
+    pub staking: StakingConfig,
+    #[serde(default)]
+    pub reward: RewardConfig,
+    #[serde(default)]
+    pub fee: FeeConfig,
+    // the gas schedule and the fork height of the bft header rules
+    #[serde(default)]
+    pub gas: GasConfig,
+    #[serde(default)]
+    pub bft_fields_height: Height,
+    // only the dpos chains carry it, so the fingerprints of the pbft chains are unchanged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpos: Option<DposConfig>,
+}
+
+impl ConsensusParams {
+    pub fn fingerprint(&self) -> Hash {
+        hash(serde_json::to_vec(self).unwrap())
+    }
+
+    /// the readable differences between self(local) and other(remote)
+    pub fn diff(&self, other: &ConsensusParams) -> Vec<String> {
+        let mut diffs = vec![];
+        if self.chain_id != other.chain_id {
+            diffs.push(format!("chain_id: {} != {}", self.chain_id, other.chain_id));
+        }
+        if self.block_period != other.block_period {
+            diffs.push(format!("block_period: {}ms != {}ms", self.block_period, other.block_period));
+        }
+        if self.request_time != other.request_time {
+            diffs.push(format!("request_time: {}ms != {}ms", self.request_time, other.request_time));
+        }
+        if self.validators != other.validators {
+            diffs.push(format!("validators: {:?} != {:?}", self.validators, other.validators));
+        }
+        if self.hash_algo != other.hash_algo {
+            diffs.push(format!("hash_algo: {:?} != {:?}", self.hash_algo, other.hash_algo));
+        }
+        if self.engine != other.engine {
+            diffs.push(format!("engine: {:?} != {:?}", self.engine, other.engine));
+        }
+        if self.epoch_length != other.epoch_length {
+            diffs.push(format!("epoch_length: {} != {}", self.epoch_length, other.epoch_length));
+        }
+        if self.max_block_size_bytes != other.max_block_size_bytes {
+            diffs.push(format!("max_block_size_bytes: {} != {}", self.max_block_size_bytes, other.max_block_size_bytes));
+        }
+        if self.max_tx_size_bytes != other.max_tx_size_bytes {
+            diffs.push(format!("max_tx_size_bytes: {} != {}", self.max_tx_size_bytes, other.max_tx_size_bytes));
+        }
+        if self.staking != other.staking {
+            diffs.push(format!("staking: {:?} != {:?}", self.staking, other.staking));
+        }
+        if self.reward != other.reward {
+            diffs.push(format!("reward: {:?} != {:?}", self.reward, other.reward));
+        }
+        if self.fee != other.fee {
+            diffs.push(format!("fee: {:?} != {:?}", self.fee, other.fee));
+        }
+        if self.gas != other.gas {
+            diffs.push(format!("gas: {:?} != {:?}", self.gas, other.gas));
+        }
+        if self.bft_fields_height != other.bft_fields_height {
+            diffs.push(format!("bft_fields_height: {} != {}", self.bft_fields_height, other.bft_fields_height));
+        }
+        if self.dpos != other.dpos {
+            diffs.push(format!("dpos: {:?} != {:?}", self.dpos, other.dpos));
+        }
+        diffs
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Config {
+    pub fn consensus_params(&self) -> ConsensusParams {
+        let mut validators = self.genesis.as_ref().map(|genesis| genesis.validator.clone()).unwrap_or_default();
+        validators.iter_mut().for_each(|validator| *validator = validator.to_lowercase());
+        validators.sort();
+        let size_limits = self.size_limits();
+        ConsensusParams {
+            chain_id: self.chain_id,
+            block_period: self.block_period.as_millis() as u64,
+            request_time: self.request_time.as_millis() as u64,
+            validators,
+            hash_algo: self.hash_algo(),
+            engine: self.engine,
+            epoch_length: self.epoch_length,
+            max_block_size_bytes: size_limits.max_block_size,
+            max_tx_size_bytes: size_limits.max_tx_size,
+            staking: self.staking(),
+            reward: self.reward(),
+            fee: self.fee(),
+            gas: self.gas.clone(),
+            bft_fields_height: self.bft_fields_height,
+            dpos: match self.engine {
+                EngineKind::Dpos => Some(self.dpos.clone()),
+                EngineKind::Pbft => None,
+            },
+        }
+    }
+
+    /// the hash function of the chain, it's selected by the genesis config
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.genesis.as_ref().map(|genesis| genesis.hash_algo).unwrap_or_default()
+    }
+
+    /// the staking rules of the chain, they're set by the genesis config
+    pub fn staking(&self) -> StakingConfig {
+        self.genesis.as_ref().map(|genesis| genesis.staking).unwrap_or_default()
+    }
+
+    /// the block reward schedule of the chain, it's set by the genesis config
+    pub fn reward(&self) -> RewardConfig {
+        self.genesis.as_ref().map(|genesis| genesis.reward).unwrap_or_default()
+    }
+
+    /// the transaction fee rules of the chain, they're set by the genesis config
+    pub fn fee(&self) -> FeeConfig {
+        self.genesis.as_ref().map(|genesis| genesis.fee).unwrap_or_default()
+    }
+
+    /// the block and transaction size limits of the chain, they're set by the genesis config
+    pub fn size_limits(&self) -> SizeLimits {
+        self.genesis.as_ref()
+            .map(|genesis| SizeLimits::new(genesis.max_block_size_bytes, genesis.max_tx_size_bytes))
+            .unwrap_or_default()
+    }
+
+    pub fn pprof_dir(&self) -> String {
+        self.pprof_dir.clone().unwrap_or_else(|| format!("{}/pprof", self.data_dir()))
+    }
+
+    pub fn is_memory_store(&self) -> bool {
+        is_memory(&self.store)
+    }
+
+    /// the directory of the node files, a memory store without `data_dir` keeps them in a
+    /// temporary directory of the process, the chains of the process differ by the api port, it's
+    /// removed when the chain stops
+    pub fn data_dir(&self) -> String {
+        match self.data_dir {
+            Some(ref data_dir) => data_dir.clone(),
+            None if self.is_memory_store() => format!("{}/consensus-{}-{}", env::temp_dir().display(), process::id(), self.api_port),
+            None => self.store.clone(),
+        }
+    }
+
+    /// the data directory is the temporary one of a memory store, it's removed when the chain stops
+    pub fn has_temp_data_dir(&self) -> bool {
+        self.data_dir.is_none() && self.is_memory_store()
+    }
+
+    /// the directory of the sign state, the data directory if it's out of the store, or else
+    /// the sibling `<store>.sign` of the store directory
+    pub fn sign_state_dir(&self) -> String {
+        if let Some(ref dir) = self.sign_state_dir {
+            return dir.clone();
+        }
+        let data_dir = self.data_dir();
+        if self.is_memory_store() || !Path::new(&data_dir).starts_with(&self.store) {
+            return data_dir;
+        }
+        format!("{}.sign", self.store.trim_end_matches('/'))
+    }
+
+    /// the multiaddr announced by discovery, the advertised ip and port override the bind address
+    pub fn advertised_address(&self) -> String {
+        let ip = self.advertised_ip.as_ref().unwrap_or(&self.ip);
+        let port = self.advertised_port.unwrap_or(self.port);
+        format!("/ip4/{}/tcp/{}", ip, port)
+    }
+
+    /// the validator key unlocked from the keystore or of the `secret`, `dev:2` selects the derived
+    /// key of `validator-2` in dev mode
+    pub fn validator_key_pair(&self) -> Result<KeyPair, String> {
+        if let Some(ref unlocked_key) = self.unlocked_key {
+            return Ok(unlocked_key.key_pair().clone());
+        }
+        if let Some(index) = self.dev_index(&self.secret)? {
+            return Ok(dev_key_pair(index));
+        }
+        parse_key_pair(&self.secret).map_err(|err| format!("invalid secret, {}", err))
+    }
+
+    /// the validator key is kept by the node, the unlocked keystore or the `secret`
+    pub fn has_local_key(&self) -> bool {
+        self.unlocked_key.is_some() || !self.secret.is_empty()
+    }
+
+    /// the validator address, it's configured with the remote signer, or derived from the secret
+    pub fn validator_address(&self) -> Result<Address, String> {
+        match self.signer {
+            Some(ref signer) => string_to_address(&signer.validator).map_err(|err| format!("invalid signer validator, {}", err)),
+            None => self.validator_key_pair().map(|key_pair| key_pair.address()),
+        }
+    }
+
+    /// the key of the p2p identity, `dev:2` selects the derived node key of `validator-2` in dev mode
+    pub fn node_key_pair(&self) -> Result<Option<KeyPair>, String> {
+        let node_key = match self.node_key {
+            Some(ref node_key) => node_key,
+            None => return Ok(None),
+        };
+        if let Some(index) = self.dev_index(node_key)? {
+            return Ok(Some(dev_node_key(index)));
+        }
+        parse_key_pair(node_key).map(Some).map_err(|err| format!("invalid node key, {}", err))
+    }
+
+    /// the `peer_id`, `dev:2` selects the peer id of the derived node key of `validator-2` in dev mode
+    pub fn local_peer_id(&self) -> Result<PeerId, String> {
+        if let Some(index) = self.dev_index(&self.peer_id)? {
+            return Ok(dev_peer_id(index));
+        }
+        PeerId::from_str(&self.peer_id).map_err(|err| format!("invalid peer id, {:?}", err))
+    }
+
+    // the derived keys are public, a node out of dev mode refuses them
+    fn dev_index(&self, value: &str) -> Result<Option<u64>, String> {
+        let index = match parse_dev_index(value) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        if !self.dev {
+            return Err(format!("the dev key `{}` is accepted only in dev mode", value));
+        }
+        warn!("The dev key of {} is used, it's derived from a public index, never use it out of a test network", dev_name(index));
+        Ok(Some(index))
+    }
+
+    /// `dpos.epoch_length` is the old key of `epoch_length`, it's moved to the root key, the
+    /// config can't set both, `root_set` tells the root key is set
+    pub(crate) fn move_old_epoch_length(&mut self, root_set: bool) -> Result<(), String> {
+        if let Some(length) = self.dpos.epoch_length.take() {
+            if root_set {
+                return Err("both `epoch_length` and its old key `dpos.epoch_length` are set, keep `epoch_length`".to_string());
+            }
+            warn!("The key `dpos.epoch_length` is renamed to `epoch_length`");
+            self.epoch_length = length;
+        }
+        Ok(())
+    }
+
+    /// the root chain named `main` and the chains of the `[chains.*]` sections. A section replaces
+    /// the root keys it sets, so a nested table like `[chains.shard.p2p]` replaces the whole `[p2p]`.
+    /// The chains may share the p2p port, they must not share the chain id, the store or the api port.
+    pub fn chain_configs(&self) -> Result<Vec<(String, Config)>, String> {
+        let mut root = self.clone();
+        root.chains.clear();
+        let base = Toml::try_from(&root).map_err(|err| err.to_string())?;
+        let mut configs = vec![(MAIN_CHAIN.to_owned(), root)];
+        for (name, section) in self.chains.iter() {
+            let section = section.as_table().ok_or(format!("the chain section `{}` is not a table", name))?;
+            if section.contains_key("chains") {
+                return Err(format!("the chain section `{}` can't define chains", name));
+            }
+            let mut merged = base.clone();
+            merged.as_table_mut().unwrap().extend(section.clone());
+            let mut config: Config = merged.try_into().map_err(|err| format!("chain `{}`, {}", name, err))?;
+            config.unlocked_key = self.unlocked_key.clone();
+            config.move_old_epoch_length(section.contains_key("epoch_length")).map_err(|err| format!("chain `{}`, {}", name, err))?;
+            configs.push((name.clone(), config));
+        }
+
+        for (idx, (name, config)) in configs.iter().enumerate() {
+            for (other_name, other) in configs[..idx].iter() {
+                if config.chain_id == other.chain_id {
+                    return Err(format!("the chains `{}` and `{}` have the same chain id {}", other_name, name, config.chain_id));
+                }
+                if config.store == other.store && !config.is_memory_store() {
+                    return Err(format!("the chains `{}` and `{}` share the store {}", other_name, name, config.store));
+                }
+                if config.api_port == other.api_port {
+                    return Err(format!("the chains `{}` and `{}` share the api port {}", other_name, name, config.api_port));
+                }
+                // the hash function is selected once for the process
+                if config.hash_algo() != other.hash_algo() {
+                    return Err(format!("the chains `{}` and `{}` use different hash algorithms", other_name, name));
+                }
+            }
+        }
+        Ok(configs)
+    }
+}
+
+/// the name of the chain configured by the root keys
+pub const MAIN_CHAIN: &str = "main";
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct GenesisConfig {
+    // it must equal to the `chain_id` of config if it's set
+    #[serde(default)]
+    pub chain_id: Option<u64>,
     pub validator: Vec<String>,
     pub accounts: Table,
+    // the pre-funded accounts, address => initial balance
+    #[serde(default)]
+    pub alloc: BTreeMap<String, u64>,
     pub epoch_time: Datetime,
     pub proposer: String,
     pub gas_used: u64,
     pub extra: String,
+    // the hash function of the chain data, `default`, `keccak256`, `sha3_256` or `blake2b`
+    #[serde(default)]
+    pub hash_algo: HashAlgo,
+    // the rlp size limits of a block and of a transaction
+    #[serde(default = "default_max_block_size_bytes")]
+    pub max_block_size_bytes: u64,
+    #[serde(default = "default_max_tx_size_bytes")]
+    pub max_tx_size_bytes: u64,
+    // the minimum stake of the validators and the unbonding period
+    #[serde(default)]
+    pub staking: StakingConfig,
+    // the block reward schedule, no reward by default
+    #[serde(default)]
+    pub reward: RewardConfig,
+    // the senders pay the gas fees when they are enabled, the `burn_percent` of them is burned
+    #[serde(default)]
+    pub fee: FeeConfig,
 }
 
 impl Default for Config {
@@ -42,15 +717,55 @@ impl Default for Config {
             chain_id: 98,
             ip: "127.0.0.1".to_string(),
             port: 7960,
+            advertised_ip: None,
+            advertised_port: None,
             api_ip: "0.0.0.0".to_owned(),
             api_port: 8960,
+            api: ApiConfig::default(),
             block_period: Duration::from_millis(3 * 1000),
             request_time: Duration::from_millis(3 * 1000),
             peer_id: "QmbBr2fHwLFKvHkAq1BpbEr4dvR8P6orQxHkVaxeJsJiW8".to_string(),
             ttl: Duration::from_millis(5 * 1000),
             store: *random_dir(),
+            data_dir: None,
+            sign_state_dir: None,
             secret: "".into(),
+            keystore: None,
+            password_file: None,
+            unlocked_key: None,
+            signer: None,
+            node_key: None,
             genesis: None,
+            checkpoint: None,
+            checkpoints: vec![],
+            checkpoint_interval: default_checkpoint_interval(),
+            gas: GasConfig::default(),
+            bft_fields_height: 0,
+            dev: false,
+            mining_mode: MiningMode::default(),
+            txpool_capacity: default_txpool_capacity(),
+            min_gas_price: 0,
+            event_queue_capacity: default_event_queue_capacity(),
+            chain_event_policy: default_chain_event_policy(),
+            p2p_event_policy: OverflowPolicy::default(),
+            session_mailbox_capacity: default_session_mailbox_capacity(),
+            p2p_outbound_quotas: HashMap::new(),
+            p2p: P2PConfig::default(),
+            clock: ClockConfig::default(),
+            warm_up: WarmUpConfig::default(),
+            cache: CacheConfig::default(),
+            consensus: ConsensusTimeouts::default(),
+            engine: EngineKind::default(),
+            epoch_length: default_epoch_length(),
+            dpos: DposConfig::default(),
+            scrub: ScrubConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            durability: DurabilityConfig::default(),
+            state: StateConfig::default(),
+            pprof_dir: None,
+            metrics_push_url: None,
+            metrics_push_interval: default_metrics_push_interval(),
+            chains: BTreeMap::new(),
         }
     }
 }
@@ -67,6 +782,144 @@ mod tests {
         println!("{:?}", PeerId::from_str("QmbBr2fHwLFKvHkAq1BpbEr4dvR8P6orQxHkVaxeJsJiW8").unwrap());
     }
 
+    #[test]
+    fn t_consensus_params() {
+        let local = Config::default().consensus_params();
+        let mut remote = local.clone();
+        assert_eq!(local.fingerprint(), remote.fingerprint());
+        assert!(local.diff(&remote).is_empty());
+
+        remote.block_period += 1000;
+        remote.validators.push("0x72d5c75fd6703414aa87f79b3e4797dd09cd9251".to_owned());
+        assert_ne!(local.fingerprint(), remote.fingerprint());
+        let diffs = local.diff(&remote);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0], "block_period: 3000ms != 4000ms");
+
+        // the dpos rules are a part of the params of a dpos chain only
+        assert!(local.dpos.is_none());
+        assert!(!serde_json::to_string(&local).unwrap().contains("dpos"));
+        let mut config = Config::default();
+        config.engine = EngineKind::Dpos;
+        let local = config.consensus_params();
+        config.dpos.delegates += 1;
+        let remote = config.consensus_params();
+        assert_ne!(local.fingerprint(), remote.fingerprint());
+        assert_eq!(local.diff(&remote).len(), 1);
+        assert!(local.diff(&remote)[0].starts_with("dpos: "));
+
+        // the gas schedule and the fork heights split the chain
+        let local = Config::default().consensus_params();
+        let mut config = Config::default();
+        config.gas.min_gas_limit += 1;
+        config.bft_fields_height = 100;
+        let remote = config.consensus_params();
+        assert_ne!(local.fingerprint(), remote.fingerprint());
+        let diffs = local.diff(&remote);
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].starts_with("gas: "));
+        assert_eq!(diffs[1], "bft_fields_height: 0 != 100");
+    }
+
+    #[test]
+    fn t_chain_configs() {
+        let input = r#"
+            chain_id = 98
+            ip = "127.0.0.1"
+            port = 7960
+            api_ip = "0.0.0.0"
+            api_port = 8960
+            block_period = 3000
+            request_time = 3000
+            peer_id = "QmbBr2fHwLFKvHkAq1BpbEr4dvR8P6orQxHkVaxeJsJiW8"
+            ttl = 5000
+            store = "/tmp/main"
+            secret = ""
+
+            [chains.shard]
+            chain_id = 99
+            store = "/tmp/shard"
+            api_port = 8961
+        "#;
+        let config: Config = toml::from_str(input).unwrap();
+        let configs = config.chain_configs().unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].0, MAIN_CHAIN);
+        assert_eq!(configs[1].0, "shard");
+        assert_eq!((configs[1].1.chain_id, configs[1].1.port, configs[1].1.api_port), (99, 7960, 8961));
+        assert!(configs[1].1.chains.is_empty());
+
+        // the unlocked key is inherited without being serialized
+        let mut config = config;
+        let key_pair = dev_key_pair(1);
+        config.unlocked_key = Some(UnlockedKey::new(key_pair.clone()));
+        assert!(!Toml::try_from(&config).unwrap().to_string().contains(&hex::encode(key_pair.secret().to_vec())));
+        let configs = config.chain_configs().unwrap();
+        assert_eq!(configs[1].1.validator_key_pair().unwrap().address(), key_pair.address());
+        assert!(configs[1].1.has_local_key() && configs[1].1.secret.is_empty());
+
+        config.chains.get_mut("shard").unwrap().as_table_mut().unwrap().remove("api_port");
+        assert!(config.chain_configs().is_err());
+    }
+
+    #[test]
+    fn t_advertised_address() {
+        let mut config = Config::default();
+        assert_eq!(config.advertised_address(), format!("/ip4/{}/tcp/7960", config.ip));
+        config.advertised_ip = Some("203.0.113.7".to_owned());
+        config.advertised_port = Some(30303);
+        assert_eq!(config.advertised_address(), "/ip4/203.0.113.7/tcp/30303");
+    }
+
+    #[test]
+    fn t_dev_keys() {
+        let mut config = Config::default();
+        config.secret = "dev:2".to_owned();
+        config.peer_id = "dev:2".to_owned();
+        assert!(config.validator_key_pair().is_err());
+        assert!(config.local_peer_id().is_err());
+
+        config.dev = true;
+        assert_eq!(config.validator_key_pair().unwrap().address(), dev_key_pair(2).address());
+        assert_eq!(config.local_peer_id().unwrap(), dev_peer_id(2));
+        assert!(config.node_key_pair().unwrap().is_none());
+    }
+
+    #[test]
+    fn t_memory_store() {
+        let mut config = Config::default();
+        assert!(!config.is_memory_store());
+        assert_eq!(config.data_dir(), config.store);
+        assert!(!config.has_temp_data_dir());
+
+        // the node files of a memory store differ by the api port
+        config.store = crate::store::backend::MEMORY_STORE.to_string();
+        let mut other = config.clone();
+        other.api_port += 1;
+        assert!(config.is_memory_store());
+        assert_ne!(config.data_dir(), other.data_dir());
+        assert_eq!(config.pprof_dir(), format!("{}/pprof", config.data_dir()));
+        assert!(config.has_temp_data_dir());
+        // the configured directory is kept
+        config.data_dir = Some("/tmp/node".to_string());
+        assert_eq!(config.data_dir(), "/tmp/node");
+        assert!(!config.has_temp_data_dir());
+    }
+
+    #[test]
+    fn t_sign_state_dir() {
+        let mut config = Config::default();
+        config.store = "/data/chain/".to_string();
+        // the mark is never restored with the store
+        assert_eq!(config.sign_state_dir(), "/data/chain.sign");
+        config.data_dir = Some("/data/chain/node".to_string());
+        assert_eq!(config.sign_state_dir(), "/data/chain.sign");
+        config.data_dir = Some("/data/node".to_string());
+        assert_eq!(config.sign_state_dir(), "/data/node");
+        config.sign_state_dir = Some("/secure/sign".to_string());
+        assert_eq!(config.sign_state_dir(), "/secure/sign");
+    }
+
     #[test]
     fn t_load_secret(){
         use cryptocurrency_kit::ethkey::{Secret, KeyPair};
@@ -0,0 +1,239 @@
+use toml::Value as Toml;
+
+use super::Config;
+
+/// the documented config keys in the schema order, the nested keys are prefixed by their section
+const FIELD_DOCS: &[(&str, &str)] = &[
+    ("chain_id", "the chain id, the devnets with the same genesis are told apart by it"),
+    ("ip", "the p2p bind ip"),
+    ("port", "the p2p bind port"),
+    ("advertised_ip", "the ip announced to the other peers when it differs from the bind ip, eg: behind NAT"),
+    ("advertised_port", "the port announced to the other peers"),
+    ("api_ip", "the http api bind ip"),
+    ("api_port", "the http api bind port"),
+    ("api.cors_origins", "the origins which the browsers can query the api from, eg: `[\"https://explorer.example.org\"]`, `*` allows every origin"),
+    ("api.tls_cert", "the pem certificate chain of the api, it and the websocket subscriptions are served over tls when `tls_cert` and `tls_key` are set"),
+    ("api.tls_key", "the pem private key (pkcs8 or rsa) of the api certificate"),
+    ("api.max_body_size", "the bytes of a request body, the bigger requests are refused with 413"),
+    ("api.max_page_size", "the max `limit` of the list apis, eg: `/v1/blocks?offset=100&limit=10`"),
+    ("api.max_batch_size", "the calls of a json-rpc batch request to `/rpc`, the bigger batches are refused"),
+    ("api.ws_port", "the websocket port of the subscriptions, eg: `{\"method\": \"subscribe\", \"params\": [\"newHeads\"]}`, unset disables it"),
+    ("api.max_ws_connections", "the websocket clients served at once, the others are refused"),
+    ("api.max_ws_subscriptions", "the subscriptions of a websocket connection, the others are refused"),
+    ("api.admin_token", "the bearer token of the admin endpoints, eg: `/minner/pause`, `/sync/policy`, `/debug/pprof/heap`, unset disables them"),
+    ("block_period", "the millis between two blocks"),
+    ("request_time", "the round change timeout in millis"),
+    ("peer_id", "the libp2p peer id of the node, it seeds the identity file of the store on the first run, then the file wins, `dev:N` selects the derived peer id of `validator-N` in dev mode"),
+    ("ttl", "the ttl of the mdns discovery in millis"),
+    ("store", "the directory of the store, `:memory:` keeps the store in memory, it's lost when the node stops"),
+    ("data_dir", "the directory of the identity, the address book and the crash dumps, the store directory if it's absent, a temporary directory for a memory store which is removed when the chain stops"),
+    ("sign_state_dir", "the directory of the sign state (the high-water mark of the signatures), `data_dir` if it's out of the store, or else `<store>.sign`, it must not be restored with a store backup"),
+    ("keystore", "the encrypted key file (geth's keystore format) of the validator key, it replaces `secret`"),
+    ("password_file", "the file which keeps the password of the keystore, the password is asked on the terminal if it's absent"),
+    ("signer", "the remote signer which keeps the validator key, it replaces `secret` and `keystore`"),
+    ("signer.endpoint", "the tcp address of the signer, eg: `10.0.0.5:7100`"),
+    ("signer.validator", "the validator address of the key, every signature of the signer is checked against it"),
+    ("signer.token_file", "the file of the token shared with the signer, it authenticates the sign requests"),
+    ("signer.timeout", "the millis which a sign request waits for the signer"),
+    ("secret", "the hex secret key of the validator, it signs the consensus messages, `dev:N` selects the derived key of `validator-N` in dev mode"),
+    ("node_key", "the hex secret key of the p2p identity, it's bound to the validator by a signed record, it seeds the identity file of the store on the first run, the local validator key seeds it if it's absent, a new key on a full node or with the remote signer, the peer id is derived from it, `dev:N` selects the derived node key of `validator-N` in dev mode"),
+    ("dev", "enable the `/dev/*` apis and the derived `dev:N` keys"),
+    ("mining_mode", "`continuous` or `on_demand`"),
+    ("txpool_capacity", "the max transactions of the pool"),
+    ("min_gas_price", "the lowest gas price that the pool accepts"),
+    ("event_queue_capacity", "the queue capacity of every event subscriber"),
+    ("chain_event_policy", "what to do when a chain event queue is full, `block`, `drop_newest` or `drop_oldest`"),
+    ("p2p_event_policy", "what to do when a p2p event queue is full"),
+    ("session_mailbox_capacity", "the mailbox capacity of every p2p session, the messages to a slow peer are dropped when it's full"),
+    ("p2p_outbound_quotas", "the outbound bytes per second of every traffic category, eg: `tx_gossip = 65536`"),
+    ("pprof_dir", "the directory of the flame graphs written by the profiling apis and the signal handler, `<data_dir>/pprof` if it's absent"),
+    ("metrics_push_url", "the url which the metrics are pushed to"),
+    ("metrics_push_interval", "the millis between two metrics pushes"),
+    ("chains", "the other chains run by the same process, eg: `[chains.shard]`, a section overrides the root keys it sets"),
+    ("p2p.dns_seeds", "the dns names which resolve to the bootstrap peers, eg: `seed.example.org:7960`"),
+    ("p2p.session_grace", "the peer which reconnects within it resumes its session state, in millis"),
+    ("p2p.serve_rate", "the bytes per second of the historical blocks served to every syncing peer"),
+    ("p2p.outbound", "the queue of every session: the tx gossip is dropped while it holds more than `high_watermark` messages until it drains to `low_watermark`, the announcements queued longer than `stale_after` millis are dropped"),
+    ("p2p.sync", "the sync strategy of every peer class, `full_blocks`, `headers_and_certificates` or `disabled`, the snapshot peers are disabled by default"),
+    ("clock.ntp_servers", "the ntp servers which the local clock is compared with at startup, eg: `pool.ntp.org:123`"),
+    ("clock.max_drift", "the millis which the block timestamps can be ahead of the local clock, and the local clock can drift from ntp"),
+    ("clock.strict", "refuse to start instead of warning when the local clock drifts too much"),
+    ("clock.future_block_tolerance", "the millis which a gossiped block can be ahead of the local clock, it's held until its timestamp is valid"),
+    ("warm_up.enabled", "pre-load the recent headers, blocks and the hot indexes before the node starts"),
+    ("warm_up.headers", "the recent headers loaded by the warm-up, at most `cache.headers`"),
+    ("warm_up.blocks", "the recent blocks with their bodies and consensus messages loaded by the warm-up, at most `cache.blocks`"),
+    ("consensus.timeout_propose", "the millis which a round waits for the preprepare of the proposer"),
+    ("consensus.timeout_prepare", "the millis which a round waits for the prepare quorum after the preprepare"),
+    ("consensus.timeout_commit", "the millis which a round waits for the commit quorum after the prepare quorum"),
+    ("consensus.timeout_backoff", "the phase timeouts are multiplied by it for every failed round of a height, 1 keeps them fixed"),
+    ("engine", "the consensus engine, `pbft` or `dpos`, all nodes of a chain must run the same one"),
+    ("epoch_length", "the blocks of an epoch, the validator set only changes at the epoch boundaries, the election of a dpos epoch counts the votes before its first block"),
+    ("dpos.delegates", "the delegates elected every epoch, the genesis validators are the delegates of the first epoch"),
+    ("dpos.max_votes", "the delegates that one voting transaction can vote for"),
+    ("cache.headers", "the headers kept by the ledger cache"),
+    ("cache.blocks", "the blocks kept by the ledger cache"),
+    ("cache.validators", "the validator sets by height kept by the ledger cache, the seal verification reads them for every block"),
+    ("scrub.enabled", "re-read and re-hash the stored blocks in the background, the mismatches are alerted"),
+    ("scrub.fraction", "the fraction of the stored blocks scrubbed per hour"),
+    ("maintenance.usage_interval", "the seconds between two disk usage accountings of the store, every accounting scans all keys, 0 disables it"),
+    ("maintenance.usage_keys_per_tick", "the keys scanned by the accounting every minute, the pass over a bigger store spans several minutes"),
+    ("maintenance.compact_after_pruned", "the store is compacted at the next start after the prunes and the truncates drop so many bytes, 0 never compacts it"),
+    ("maintenance.compact_after_reindex", "the `reindex` command compacts the store after it rebuilds the indexes"),
+    ("durability.level", "when the committed blocks are synced to the disk, `block`, `batch` (every `flush_blocks` blocks or `flush_interval`) or `os`, the indexes never sync per write"),
+    ("durability.flush_blocks", "the blocks of a `batch` flush"),
+    ("durability.flush_interval", "the millis after which a `batch` flush is due, it's checked when a block is committed"),
+    ("durability.fsync", "`flush` (the default) syncs the write ahead log and the store directory at every flush, `never` lets the os write them back"),
+    ("state.snapshot_interval", "the account states are snapshotted every N blocks, the historical states are replayed from the nearest snapshot, 0 disables them"),
+    ("state.history", "the snapshots retained behind the last one, the states below the oldest one are pruned, 0 keeps all"),
+    ("gas.activation_height", "the blocks below it are not checked by the gas rules"),
+    ("gas.min_gas_limit", "the lowest block gas limit"),
+    ("gas.target_gas_limit", "the block gas limit that the minner moves to"),
+    ("gas.gas_limit_bound_divisor", "the gas limit changes at most `parent_gas_limit / divisor` between two blocks"),
+    ("bft_fields_height", "the headers below it are not checked by the bft header rules (the difficulty is 1 and the extra is a short vanity)"),
+    ("checkpoint_interval", "a checkpoint record is persisted every N blocks, the blocks below the last one can't be reorganized, 0 disables it"),
+    ("checkpoints", "more trusted checkpoints with the same keys as `[checkpoint]`, eg: `[[checkpoints]]`"),
+    ("checkpoint.height", "the height of the trusted checkpoint"),
+    ("checkpoint.block_hash", "the block hash at the checkpoint height"),
+    ("checkpoint.validators_hash", "the hash of the sorted validator addresses at the checkpoint height"),
+    ("genesis.chain_id", "it must equal to the `chain_id` of config if it's set"),
+    ("genesis.validator", "the genesis validator addresses"),
+    ("genesis.epoch_time", "the time of the genesis block, eg: 2019-01-01T00:00:00Z"),
+    ("genesis.proposer", "the proposer address of the genesis block"),
+    ("genesis.gas_used", "the gas used of the genesis block"),
+    ("genesis.extra", "the extra data of the genesis block"),
+    ("genesis.accounts", "the genesis accounts table"),
+    ("genesis.alloc", "the pre-funded accounts, address => initial balance, eg: `\"0x5701...d313\" = 500000`"),
+    ("genesis.hash_algo", "the hash function of the chain data, `default`, `keccak256`, `sha3_256` or `blake2b`"),
+    ("genesis.max_block_size_bytes", "the rlp size limit of a block, the oversized proposals are refused, 4MB by default"),
+    ("genesis.max_tx_size_bytes", "the rlp size limit of a transaction, the oversized transactions never enter the pool, 128KB by default"),
+    ("genesis.staking", "the staking rules, `min_stake` of a validator before an epoch (0 disables it) and the `unbonding_period` in blocks, eg: `{ min_stake = 1000, unbonding_period = 1000 }`"),
+    ("genesis.reward", "the block reward schedule, the `block_reward` (0 disables it) is paid to the proposer and halved every `halving_interval` blocks, eg: `{ block_reward = 50, halving_interval = 100000 }`"),
+    ("genesis.fee", "the transaction fees, off by default, the senders pay `gas used * gas price` when `enabled = true` and the `burn_percent` of every fee is burned, the rest is paid to the proposer, eg: `{ enabled = true, burn_percent = 50 }`"),
+];
+
+/// parse the config strictly, an unknown key is an error which names the nearest valid key
+pub fn parse_config(input: &str) -> Result<Config, String> {
+    let mut config = toml::from_str::<Config>(input).map_err(|err| explain(&err.to_string()))?;
+    if config.dpos.epoch_length.is_some() {
+        let root: Toml = toml::from_str(input).map_err(|err| err.to_string())?;
+        config.move_old_epoch_length(root.get("epoch_length").is_some())?;
+    }
+    Ok(config)
+}
+
+// the serde error looks like: "unknown field `blok_period`, expected one of `chain_id`, `ip` for key `p2p` at line 3"
+fn explain(err: &str) -> String {
+    let unknown = match err.find("unknown field `") {
+        Some(idx) => &err[idx + "unknown field `".len()..],
+        None => return err.to_owned(),
+    };
+    let field = match unknown.find('`') {
+        Some(end) => &unknown[..end],
+        None => return err.to_owned(),
+    };
+    let expected = unknown.find("expected").map(|idx| &unknown[idx..]).unwrap_or("");
+    let expected = expected.split(" for key").next().unwrap_or("");
+    let candidates: Vec<&str> = expected.split('`').skip(1).step_by(2).collect();
+    match nearest(field, &candidates) {
+        Some(candidate) => format!("{}, did you mean `{}`?", err, candidate),
+        None => err.to_owned(),
+    }
+}
+
+fn nearest<'a>(field: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates.iter()
+        .map(|candidate| (edit_distance(field, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= ::std::cmp::max(2, field.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb { prev } else { 1 + ::std::cmp::min(prev, ::std::cmp::min(row[j], row[j + 1])) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// the annotated config with the defaults, the keys without a default are commented out
+pub fn schema() -> String {
+    let defaults = Toml::try_from(Config::default()).unwrap();
+    let defaults = defaults.as_table().unwrap();
+    let mut sections: Vec<&str> = vec![];
+    for (key, _) in FIELD_DOCS {
+        if let Some(idx) = key.find('.') {
+            if !sections.contains(&&key[..idx]) {
+                sections.push(&key[..idx]);
+            }
+        } else if let Some(value) = defaults.get(*key) {
+            if value.is_table() {
+                sections.push(*key);
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    for (key, doc) in FIELD_DOCS.iter().filter(|(key, _)| !key.contains('.') && !sections.contains(key)) {
+        lines.push(format!("# {}", doc));
+        lines.push(render(key, defaults.get(*key)));
+    }
+    for section in sections {
+        lines.push(String::new());
+        if let Some((_, doc)) = FIELD_DOCS.iter().find(|(key, _)| *key == section) {
+            lines.push(format!("# {}", doc));
+        }
+        lines.push(format!("[{}]", section));
+        let table = defaults.get(section).and_then(|value| value.as_table());
+        let prefix = format!("{}.", section);
+        for (key, doc) in FIELD_DOCS.iter().filter(|(key, _)| key.starts_with(&prefix)) {
+            let key = &key[prefix.len()..];
+            lines.push(format!("# {}", doc));
+            lines.push(render(key, table.and_then(|table| table.get(key))));
+        }
+    }
+    lines.join("\n")
+}
+
+fn render(key: &str, value: Option<&Toml>) -> String {
+    match value {
+        Some(value) => format!("{} = {}", key, value),
+        None => format!("# {} =", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_parse_config() {
+        let err = parse_config("chain_id = 1\nblok_period = 3000\n").err().unwrap();
+        assert!(err.contains("did you mean `block_period`?"), err);
+
+        let err = parse_config("[p2p]\ndns_seed = []\n").err().unwrap();
+        assert!(err.contains("did you mean `dns_seeds`?"), err);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        // the old key of the epoch length
+        assert_eq!(parse_config("[dpos]\nepoch_length = 50\n").unwrap().epoch_length, 50);
+        assert_eq!(parse_config("epoch_length = 30\n").unwrap().epoch_length, 30);
+        assert!(parse_config("epoch_length = 30\n[dpos]\nepoch_length = 50\n").is_err());
+    }
+
+    #[test]
+    fn t_schema() {
+        let schema = schema();
+        assert!(schema.contains("block_period = 3000"));
+        assert!(schema.contains("[gas]\n# the blocks below it are not checked by the gas rules\nactivation_height = 0"));
+        assert!(schema.contains("# advertised_ip ="));
+        assert!(schema.contains("bft_fields_height = 0"));
+    }
+}
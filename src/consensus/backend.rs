@@ -13,13 +13,12 @@ use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::crypto::{CryptoHash, Hash, hash, EMPTY_HASH};
 use cryptocurrency_kit::ethkey::{
     keccak::Keccak256,
-    sign, verify_address, Address, KeyPair, Message, Public, Secret, Signature,
+    verify_address, Address, Message, Public, Secret, Signature,
 };
 use futures::future::Err;
 use futures::sync::oneshot;
 use futures::Future;
 use futures::*;
-use lru_time_cache::LruCache;
 use parking_lot::RwLock;
 use tokio_threadpool::ThreadPool;
 
@@ -29,17 +28,24 @@ use super::{
     pbft::core::core::Core,
     error::{EngineError, EngineResult},
     events::{MessageEvent, FinalCommittedEvent, NewHeaderEvent, OpCMD},
-    types::Proposal,
+    sign_guard::SafeSignGuard,
+    types::{CommitCertificate, Proposal},
     validator::{fn_selector, ImplValidatorSet, ValidatorSet},
 };
 use crate::{
     common::merkle_tree_root,
     core::chain::Chain,
+    core::executor::verify_block_bloom,
     error::{ChainError, ChainResult},
-    protocol::GossipMessage,
+    metrics::cache::MeteredCache,
+    protocol::{GossipMessage, GossipMessages, MessageType},
+    signer::SafeSigner,
     subscriber::events::{BroadcastEvent, BroadcastEventSubscriber},
-    types::block::{Block, Header},
+    types::block::{Block, Header, IstanbulExtra},
+    types::votes::Votes,
     types::{Height, Validator, EMPTY_ADDRESS},
+    util::cancel::Canceller,
+    util::request::{send_timeout, DEFAULT_REQUEST_TIMEOUT},
 };
 use ethereum_types::H256;
 
@@ -47,29 +53,59 @@ pub trait Backend {
     type ValidatorsType;
     /// address is the current validator's address
     fn address(&self) -> Address;
-    /// validators returns a set of current validator
-    fn validators(&self, height: Height) -> &Self::ValidatorsType;
+    /// validators returns the validator set of the epoch of the height
+    fn validators(&self, height: Height) -> Self::ValidatorsType;
     /// gossip sends a message to all validators (exclude self)
     fn gossip(&mut self, vals: &ValidatorSet, msg: GossipMessage) -> EngineResult;
-    /// commit a proposal with seals
-    fn commit(&mut self, proposal: &mut Proposal, seals: Vec<Signature>) -> Result<(), String>;
+    /// commit a proposal with the certificate of its seals, the certificate and the prepare and
+    /// commit messages are persisted as audit trail
+    fn commit(&mut self, proposal: &mut Proposal, certificate: CommitCertificate, messages: GossipMessages) -> Result<(), String>;
     /// verifies the proposal. If a err_future_block error is returned,
     /// the time difference of the proposal and current time is also returned.
     fn verify(&self, proposal: &Proposal) -> (Duration, Result<(), EngineError>);
-    fn sign(&self, digest: &[u8; 32]) -> Result<Vec<u8>, String>;
     fn check_signature(&self, data: &[u8; 32], address: Address, sig: &[u8]) -> Result<bool, ()>;
 
     fn last_proposal(&self) -> Result<Proposal, ()>;
     fn has_proposal(&self, hash: &Hash, height: Height) -> bool;
     fn get_proposer(&self, height: Height) -> Address;
-    fn parent_validators(&self, proposal: &Proposal) -> &Self::ValidatorsType;
+    fn parent_validators(&self, proposal: &Proposal) -> Self::ValidatorsType;
     fn has_bad_proposal(&self, hash: Hash) -> bool;
 
     fn get_header_by_height(&self, height: Height) -> Option<Header>;
+    /// cancel_inflight drops the proposal and the queued messages of the superseded round
+    fn cancel_inflight(&mut self);
+}
+
+/// the timestamp must be at least a block period after the parent, the block which is ahead of
+/// the local clock within the drift waits, beyond the drift it's rejected
+pub fn verify_timestamp(time: u64, parent_time: u64, block_period: u64, now: u64, max_drift: u64) -> Result<(), EngineError> {
+    if time < parent_time + block_period {
+        return Err(EngineError::InvalidTimestamp);
+    }
+    if time > now + max_drift {
+        crate::metrics::inc_counter("consensus_drifted_block_total");
+        return Err(EngineError::InvalidTimestamp);
+    }
+    if time > now {
+        return Err(EngineError::FutureBlock);
+    }
+    Ok(())
+}
+
+/// the validators of the istanbul extra are the set of the epoch of the header, in any order
+pub fn verify_extra_validators(extra: &[Address], validators: &[Address]) -> Result<(), EngineError> {
+    let (mut extra, mut validators) = (extra.to_vec(), validators.to_vec());
+    extra.sort();
+    validators.sort();
+    if extra != validators {
+        return Err(EngineError::InvalidHeaderField("the validators of the istanbul extra mismatch".to_string()));
+    }
+    Ok(())
 }
 
 pub fn new_impl_backend(
-    keypair: KeyPair,
+    signer: SafeSigner,
+    sign_guard: SafeSignGuard,
     chain: Arc<Chain>,
     subscriber: Addr<BroadcastEventSubscriber>,
 ) -> ImplBackend {
@@ -78,17 +114,13 @@ pub fn new_impl_backend(
     let config = Config {
         request_time: request_time as u64,
         block_period: block_period as u64,
-        chain_id: 0,
+        chain_id: chain.config.chain_id,
+        max_clock_drift: chain.config.clock.max_drift.as_secs(),
+        size_limits: chain.config.size_limits(),
     };
 
-    let addresses: Vec<Address> = chain
-        .get_validators(chain.get_last_height())
-        .iter()
-        .map(|validator| *validator.address())
-        .collect();
-    let validator_set = ImplValidatorSet::new(&addresses, Box::new(fn_selector));
-    let inbound_cache = LruCache::with_capacity(1 << 10);
-    let outbound_cache = LruCache::with_capacity(1 << 10);
+    let inbound_cache = MeteredCache::with_capacity("consensus_inbound", 1 << 10);
+    let outbound_cache = MeteredCache::with_capacity("consensus_outbound", 1 << 10);
     let proposed_block_hash = EMPTY_HASH;
     let (tx, rx) = crossbeam_channel::bounded(1);
 
@@ -96,9 +128,9 @@ pub fn new_impl_backend(
         core_pid: None,
         broadcast_subscriber: subscriber,
         started: false,
-        validaor: Validator::new(keypair.address()),
-        validator_set: validator_set,
-        key_pair: keypair,
+        validaor: Validator::new(signer.address()),
+        signer: signer,
+        sign_guard: sign_guard,
         inbound_cache: inbound_cache,
         outbound_cache: outbound_cache,
         proposed_block_hash: proposed_block_hash,
@@ -106,6 +138,7 @@ pub fn new_impl_backend(
         commit_rx: rx,
         chain: chain,
         config: config,
+        canceller: Canceller::default(),
     }
 }
 
@@ -114,10 +147,10 @@ pub struct ImplBackend {
     core_pid: Option<Addr<Core>>,
     broadcast_subscriber: Addr<BroadcastEventSubscriber>,
     validaor: Validator,
-    validator_set: ImplValidatorSet,
-    key_pair: KeyPair,
-    inbound_cache: LruCache<Hash, ()>,
-    outbound_cache: LruCache<Hash, ()>,
+    signer: SafeSigner,
+    sign_guard: SafeSignGuard,
+    inbound_cache: MeteredCache<Hash, ()>,
+    outbound_cache: MeteredCache<Hash, ()>,
     proposed_block_hash: Hash,
     // proposal hash it from local node
     commit_tx: Sender<Block>,
@@ -125,6 +158,8 @@ pub struct ImplBackend {
     chain: Arc<Chain>,
     started: bool,
     config: Config,
+    // shared by the clones, so the core cancels the work of the engine and the gossip
+    canceller: Canceller,
 }
 
 impl Backend for ImplBackend {
@@ -133,8 +168,8 @@ impl Backend for ImplBackend {
         *self.validaor.address()
     }
 
-    fn validators(&self, _: Height) -> &ImplValidatorSet {
-        &self.validator_set
+    fn validators(&self, height: Height) -> ImplValidatorSet {
+        ImplValidatorSet::new(&self.chain.get_validator_addresses(height), Box::new(fn_selector))
     }
 
     /// TODO
@@ -148,20 +183,26 @@ impl Backend for ImplBackend {
 
         self.outbound_cache.insert(msg_hash, ());
         let core_pid = self.core_pid.as_ref().ok_or(EngineError::EngineNotStarted)?;
-        Arbiter::spawn(core_pid.send(MessageEvent { payload: msg.clone().into_bytes() }).then(|result| {
+        Arbiter::spawn(send_timeout(core_pid, MessageEvent { payload: msg.clone().into_bytes() }, DEFAULT_REQUEST_TIMEOUT).then(|result| {
             if let Err(ref err) = result {
-                error!("Failed to send message");
+                error!("Failed to send message, err: {}", err);
             }
             trace!("Success to send message");
             future::ok::<(), ()>(())
-        }).map_err(|err| panic!(err)));
+        }));
+        // the round change messages lead to the next round, they are never superseded
+        let token = match msg.code {
+            MessageType::RoundChange => None,
+            _ => Some(self.canceller.token()),
+        };
         self.broadcast_subscriber
-            .do_send(BroadcastEvent::Consensus(msg));
+            .do_send(BroadcastEvent::Consensus(msg, token));
         Ok(())
     }
 
     /// TODO
-    fn commit(&mut self, proposal: &mut Proposal, seals: Vec<Signature>) -> Result<(), String> {
+    fn commit(&mut self, proposal: &mut Proposal, certificate: CommitCertificate, messages: GossipMessages) -> Result<(), String> {
+        let seals = certificate.seals.votes().clone();
         // write seal into block
         proposal.set_seal(seals.clone());
         let block = proposal.block();
@@ -172,7 +213,11 @@ impl Backend for ImplBackend {
         let mut block = proposal.block().clone();
         let votes = block.mut_votes();
         votes.unwrap().add_votes(&seals);
-        let result = self.chain.insert_block(&block);
+        if let Some(mut istanbul) = block.header().istanbul_extra() {
+            istanbul.committed_seal = seals.clone();
+            block.mut_header().set_istanbul_extra(&istanbul);
+        }
+        let result = self.chain.insert_committed_block(&block, Some((certificate, messages)));
         if let Err(err) = result {
             match err {
                 ChainError::Exists(block_hash) => {
@@ -202,6 +247,7 @@ impl Backend for ImplBackend {
 
     /// TODO
     fn verify(&self, proposal: &Proposal) -> (Duration, Result<(), EngineError>) {
+        let _span = crate::pprof::span("consensus_verify_proposal");
         let block = &proposal.0;
         let header = block.header();
         let blh = header.block_hash();
@@ -224,6 +270,16 @@ impl Backend for ImplBackend {
                     Err(EngineError::InvalidTransactionHash(header.tx_hash.clone(), transaction_hash)),
                 );
             }
+            if let Err(err) = verify_block_bloom(&header, &transactions) {
+                return (Duration::from_nanos(0), Err(EngineError::InvalidHeaderField(err)));
+            }
+        }
+        if let Err(err) = self.chain.verify_gas(block) {
+            return (Duration::from_nanos(0), Err(EngineError::InvalidGas(err.to_string())));
+        }
+        // application-specific content rules
+        if let Err(err) = self.chain.proposal_policy().verify(block) {
+            return (Duration::from_nanos(0), Err(EngineError::PolicyViolation(err)));
         }
         let result = self.verify_header(&header, false);
         if let Err(ref err) = result {
@@ -231,7 +287,7 @@ impl Backend for ImplBackend {
                 EngineError::FutureBlock => {
                     let now = Local::now().timestamp() as u64;
                     if now <= block.header().time {
-                        return (Duration::from_nanos(now - block.header().time), result);
+                        return (Duration::from_secs(block.header().time - now), result);
                     } else {
                         return (Duration::from_nanos(0), result);
                     }
@@ -239,16 +295,13 @@ impl Backend for ImplBackend {
                 _ => return (Duration::from_nanos(0), result),
             }
         }
-        (Duration::from_nanos(0), Ok(()))
-    }
-
-    /// TODO
-    fn sign(&self, digest: &[u8; 32]) -> Result<Vec<u8>, String> {
-        let message = Message::from(digest);
-        match sign(&self.key_pair.secret(), &message) {
-            Ok(signature) => Ok(signature.to_vec()),
-            Err(_) => Err("invalid sign".to_string()),
+        // the transactions must be valid on top of the last committed state
+        if let Err(err) = self.chain.proposal_validator().validate(block) {
+            crate::metrics::inc_counter("consensus_invalid_proposal_total");
+            warn!("Reject the invalid proposal, hash: {:?}, height: {}, err: {}", block.hash().short(), block.height(), err);
+            return (Duration::from_nanos(0), Err(EngineError::InvalidTransactions(err)));
         }
+        (Duration::from_nanos(0), Ok(()))
     }
 
     /// TODO
@@ -275,9 +328,8 @@ impl Backend for ImplBackend {
         header.map_or(*EMPTY_ADDRESS, |header| header.proposer)
     }
 
-    // TODO
-    fn parent_validators(&self, _proposal: &Proposal) -> &Self::ValidatorsType {
-        &self.validator_set
+    fn parent_validators(&self, proposal: &Proposal) -> Self::ValidatorsType {
+        self.validators(proposal.block().height().saturating_sub(1))
     }
 
     /// TODO
@@ -288,6 +340,11 @@ impl Backend for ImplBackend {
     fn get_header_by_height(&self, height: Height) -> Option<Header> {
         self.chain.get_header_by_height(height)
     }
+
+    fn cancel_inflight(&mut self) {
+        crate::metrics::inc_counter("consensus_inflight_cancel_total");
+        self.canceller.cancel();
+    }
 }
 
 impl Engine for ImplBackend {
@@ -301,11 +358,11 @@ impl Engine for ImplBackend {
     }
 
     fn stop(&mut self) -> Result<(), String> {
-        let request = self.core_pid.as_ref().unwrap().send(OpCMD::stop);
+        let request = send_timeout(self.core_pid.as_ref().unwrap(), OpCMD::stop, DEFAULT_REQUEST_TIMEOUT);
         Arbiter::spawn(
             request
                 .and_then(|_| futures::future::ok(()))
-                .map_err(|err| panic!(err)),
+                .map_err(|err| warn!("Failed to stop core, err: {}", err)),
         );
         self.core_pid = None;
         self.started = false;
@@ -331,9 +388,18 @@ impl Engine for ImplBackend {
                 format!("parent hash({:?}) != heaer.prev hash({:?})", parent_header.block_hash(), header.prev_hash),
             ));
         }
-        if header.time < parent_header.time + self.config.block_period {
-            return Err(EngineError::InvalidTimestamp);
+        header.verify_bft_fields(self.config.bft_fields_height).map_err(EngineError::InvalidHeaderField)?;
+        if let Some(istanbul) = header.istanbul_extra() {
+            if istanbul.recover_proposer(&header.block_hash()).map_err(EngineError::InvalidHeaderField)? != header.proposer {
+                return Err(EngineError::InvalidSignature);
+            }
+            // the parent is committed, so the epoch of the header is decided
+            let validators = self.chain.get_decided_validators(header.height)
+                .ok_or(EngineError::UnknownAncestor(header.height, header.height - 1))?;
+            verify_extra_validators(&istanbul.validators, &validators)?;
         }
+        let now = Local::now().timestamp() as u64;
+        verify_timestamp(header.time, parent_header.time, self.config.block_period, now, self.config.max_clock_drift)?;
         if seal {
             self.verify_seal(header)?;
         }
@@ -342,46 +408,39 @@ impl Engine for ImplBackend {
     }
 
     fn verify_seal(&self, header: &Header) -> EngineResult {
-        // check votes
+        // the seals are checked against the validators of the epoch of the header
+        let validator_set = self.validators(header.height);
         {
-            let votes = header.votes.as_ref().ok_or(EngineError::LackVotes(
-                self.validator_set.two_thirds_majority() + 1,
-                header.votes.as_ref().unwrap().len(),
-            ))?;
-            if votes.verify_signs(CryptoHash::hash(header), |validator| {
-                self.validator_set.get_by_address(validator).is_some()
+            // the commit seals of the istanbul extra stand in for the absent votes
+            let votes = header.votes.clone()
+                .or_else(|| header.istanbul_extra().map(|istanbul| Votes::new(istanbul.committed_seal)))
+                .ok_or(EngineError::LackVotes(validator_set.two_thirds_majority() + 1, 0))?;
+            if votes.verify_commit_seals(&header.block_hash(), |validator| {
+                validator_set.get_by_address(validator).is_some()
             }) == false
             {
                 return Err(EngineError::InvalidSignature);
             }
-            let maj32 = self.validator_set.two_thirds_majority();
+            let maj32 = validator_set.two_thirds_majority();
             if maj32 + 1 > votes.len() {
                 return Err(EngineError::LackVotes(maj32 + 1, votes.len()));
             }
         }
 
         let proposer = header.proposer;
-        self.validator_set
+        validator_set
             .get_by_address(proposer)
             .ok_or(EngineError::Unknown("proposer is not validators".to_string()))
             .map(|_| ())
     }
 
-    fn new_chain_header(&mut self, proposal: &Proposal) -> EngineResult {
-        debug!(
-            "Backend handle new chain header, hash: {:?}, height: {:?}",
-            proposal.block().hash(),
-            proposal.block().height()
-        );
-        if !self.started {
-            return Err(EngineError::EngineNotStarted);
+    fn on_new_chain_head(&mut self, header: &Header) -> EngineResult {
+        // the local proposal is superseded by the block of another proposer
+        if self.proposed_block_hash != EMPTY_HASH && self.proposed_block_hash != header.block_hash() {
+            debug!("The proposal is superseded by the new head, hash: {:?}, height: {}", header.block_hash().short(), header.height);
+            self.proposed_block_hash = EMPTY_HASH;
+            self.cancel_inflight();
         }
-        // send a new round event
-        let core = self.core_pid.as_ref().unwrap().clone();
-        let proposal = proposal.clone();
-        core.do_send(NewHeaderEvent {
-            proposal: proposal.clone(),
-        });
         Ok(())
     }
 
@@ -391,38 +450,22 @@ impl Engine for ImplBackend {
             header.block_hash(),
             header.height
         );
-        let parent_header = {
-            self.chain
-                .get_header_by_height(header.height - 1)
-                .ok_or("not found parent block for the header".to_string())?
-        };
-        // TODO maybe reset validator
-
+        self.chain
+            .get_header_by_height(header.height - 1)
+            .ok_or("not found parent block for the header".to_string())?;
+        // the istanbul extra carries the validators, the seal of the proposer is added by `seal`
+        let validators = self.chain.get_validator_addresses(header.height);
+        header.set_istanbul_extra(&IstanbulExtra::new(validators));
         header.votes = None;
-        self.proposed_block_hash = header.block_hash();
         Ok(())
     }
 
-    // Finalize runs any post-transaction state modifications (e.g. block rewards)
-    // and assembles the final block.
-    //
-    // Note, the block header and state database might be updated to reflect any
-    // consensus rules that happen at finalization (e.g. block rewards).
-    fn finalize(&mut self, _header: &Header) -> Result<(), String> {
+    // the local proposal is committed, the core stops the timer of the height
+    fn finalize(&mut self, header: &Header) -> Result<(), String> {
         self.proposed_block_hash = EMPTY_HASH;
-        // send a new round event
-        let core = self.core_pid.as_ref().unwrap();
-        let (tx, rx) = crossbeam_channel::bounded(1);
-        let request = core.send(FinalCommittedEvent {});
-        Arbiter::spawn(
-            request
-                .and_then(move |result| {
-                    tx.send(result);
-                    futures::future::ok(())
-                })
-                .map_err(|err| panic!(err)),
-        );
-        rx.recv().unwrap();
+        // the minner calls it from its actor, so the core isn't waited for
+        let core = self.core_pid.as_ref().ok_or("the core isn't started".to_string())?;
+        core.do_send(FinalCommittedEvent { height: header.height });
         Ok(())
     }
 
@@ -431,6 +474,8 @@ impl Engine for ImplBackend {
             return Err(EngineError::EngineNotStarted);
         }
 
+        // the round which the block is assembled in, it's superseded by a round change
+        let token = self.canceller.token();
         let header = new_block.mut_header();
 
         // TODO update new validator
@@ -448,17 +493,28 @@ impl Engine for ImplBackend {
             "⛏️⛏️⛏👷️ Minnig next block, hash:{:?}, height:{:?}, delay: {}s",
             header.block_hash().short(), header.height, delay);
         ::std::thread::sleep(Duration::from_secs(delay));
+        if token.is_cancelled() {
+            crate::metrics::inc_counter("consensus_cancelled_proposal_total");
+            debug!("The round has changed while assembling the block, drop it, height: {}", header.height);
+            return Err(EngineError::Interrupt);
+        }
 
-        // add clear function
-        self.prepare(header).unwrap();
+        // the minner prepares the header, the istanbul extra carries the validators
+        let mut istanbul = header.istanbul_extra().ok_or(EngineError::InvalidHeaderField("the header isn't prepared".to_string()))?;
+        self.proposed_block_hash = header.block_hash();
+        let vote = self.sign_guard.lock().check_seal(header.height, header.block_hash()).map_err(|err| {
+            error!("Refuse to seal the block, height: {}, err: {}", header.height, err);
+            EngineError::InvalidSignature
+        })?;
+        istanbul.seal = Some(self.signer.sign_vote(&header.block_hash(), &vote).map_err(|err| {
+            error!("Failed to sign the proposer seal, height: {}, err: {}", header.height, err);
+            EngineError::InvalidSignature
+        })?);
+        header.set_istanbul_extra(&istanbul);
         // ready to new consensus
-        self.new_chain_header(&Proposal(new_block.clone())).unwrap();
+        self.new_proposal(&Proposal(new_block.clone())).unwrap();
         let commit_tx = self.commit_rx.clone();
 
-        let mut receover = || {
-            self.finalize(new_block.header()).unwrap();
-        };
-
         let tx = worker.sender().clone();
         let new_hash = new_block.hash().clone();
         let new_height = new_block.height();
@@ -475,6 +531,10 @@ impl Engine for ImplBackend {
                     } else {
                         return futures::future::err(EngineError::Interrupt);
                     }
+                    if token.is_cancelled() {
+                        crate::metrics::inc_counter("consensus_cancelled_proposal_total");
+                        return futures::future::err(EngineError::Interrupt);
+                    }
 
                     match commit_tx.recv_timeout(Duration::from_secs(1)) {
                         Ok(block) => {
@@ -530,8 +590,55 @@ impl ImplBackend {
         self.core_pid = Some(core_pid);
         trace!("Set core pid for backend");
     }
+
+    /// the guard of the validator, the core checks its votes against the seals of the backend
+    pub fn sign_guard(&self) -> SafeSignGuard {
+        self.sign_guard.clone()
+    }
+
+    /// hand the proposal to the core, it starts a new round for it
+    fn new_proposal(&mut self, proposal: &Proposal) -> EngineResult {
+        debug!(
+            "Backend handle new proposal, hash: {:?}, height: {:?}",
+            proposal.block().hash(),
+            proposal.block().height()
+        );
+        if !self.started {
+            return Err(EngineError::EngineNotStarted);
+        }
+        // send a new round event
+        let core = self.core_pid.as_ref().unwrap().clone();
+        let proposal = proposal.clone();
+        core.do_send(NewHeaderEvent {
+            proposal: proposal.clone(),
+        });
+        Ok(())
+    }
 }
 
 lazy_static! {
 pub static ref worker: ThreadPool = ThreadPool::new();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_verify_timestamp() {
+        assert!(verify_timestamp(103, 100, 3, 110, 15).is_ok());
+        assert!(match verify_timestamp(102, 100, 3, 110, 15) { Err(EngineError::InvalidTimestamp) => true, _ => false });
+        assert!(match verify_timestamp(120, 100, 3, 110, 15) { Err(EngineError::FutureBlock) => true, _ => false });
+        assert!(match verify_timestamp(126, 100, 3, 110, 15) { Err(EngineError::InvalidTimestamp) => true, _ => false });
+    }
+
+    #[test]
+    fn t_verify_extra_validators() {
+        let (a, b, c) = (Address::from(1), Address::from(2), Address::from(3));
+        assert!(verify_extra_validators(&[a, b, c], &[a, b, c]).is_ok());
+        assert!(verify_extra_validators(&[c, a, b], &[a, b, c]).is_ok());
+        assert!(verify_extra_validators(&[a, b], &[a, b, c]).is_err());
+        assert!(verify_extra_validators(&[a, b, b], &[a, b, c]).is_err());
+        assert!(verify_extra_validators(&[], &[]).is_ok());
+    }
+}
@@ -1,16 +1,24 @@
+use crate::core::limits::SizeLimits;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub request_time: u64,
     pub block_period: u64,
     pub chain_id: u64,
+    // the seconds which a block timestamp can be ahead of the local clock
+    pub max_clock_drift: u64,
+    // the oversized proposals are refused before they're verified
+    pub size_limits: SizeLimits,
 }
 
 impl Config {
-    pub fn new(request_time:u64, block_period: u64, chain_id: u64) -> Self {
+    pub fn new(request_time:u64, block_period: u64, chain_id: u64, max_clock_drift: u64, size_limits: SizeLimits) -> Self {
         Config{
             request_time,
             block_period,
             chain_id,
+            max_clock_drift,
+            size_limits,
         }
     }
 }
\ No newline at end of file
@@ -2,20 +2,22 @@ use std::sync::Arc;
 
 use actix::Addr;
 use cryptocurrency_kit::ethkey::Address;
-use cryptocurrency_kit::ethkey::KeyPair;
 use crossbeam::Receiver;
 
 use super::{
     error::{EngineError, EngineResult},
-    types::Proposal,
     pbft::core::core::Core,
+    sign_guard::SafeSignGuard,
+    dpos::engine::{verify_proposer_seal, DposEngine},
     backend::{Backend, ImplBackend, new_impl_backend},
     validator::ImplValidatorSet,
 };
 
 use crate::{
+    signer::SafeSigner,
     subscriber::events::{BroadcastEvent, BroadcastEventSubscriber},
     types::block::{Block, Header},
+    types::votes::verify_block_seals,
     core::chain::Chain,
     consensus::events::OpCMD,
 };
@@ -25,36 +27,145 @@ struct BftConfig {
     block_period: u64,
 }
 
+/// Engine is the consensus engine driven by the minner, the minner packs a block and hands it
+/// to `seal`, the engine decides when and how it's committed. The lifecycle of a block is
+/// `prepare` -> `seal` -> `finalize`, and `on_new_chain_head` is called whenever the chain head
+/// moves, by the local seal or by the synced blocks.
 pub trait Engine {
     fn start(&mut self) -> Result<(), String>;
     fn stop(&mut self) -> Result<(), String>;
+
+    /// the address which proposed the block
     fn author(&self, header: &Header) -> Result<Address, String>;
+
+    /// check the header against its parent and the consensus rules, the seals are checked too
+    /// if `seal` is set
     fn verify_header(&self, header: &Header, seal: bool) -> EngineResult;
+
     fn verify_seal(&self, header: &Header) -> EngineResult;
-    fn new_chain_header(&mut self, proposal: &Proposal) -> EngineResult;
+
+    /// initialize the consensus fields of the header, the changes are made inline
     fn prepare(&mut self, header: &mut Header) -> Result<(), String>;
-    fn finalize(&mut self, header: &Header) -> Result<(), String>;
+
+    /// seal the block and insert it into the chain, it blocks until the block is committed or
+    /// `abort` fires, the superseded work returns `EngineError::Interrupt`
     fn seal(&mut self, new_block: &mut Block, abort: Receiver<()>) -> EngineResult;
+
+    /// the post-commit work of the sealed block
+    fn finalize(&mut self, header: &Header) -> Result<(), String>;
+
+    /// the chain head moves to the header, the engine drops the work on the older heads
+    fn on_new_chain_head(&mut self, header: &Header) -> EngineResult;
 }
 
 pub type SafeEngine = Box<Engine + Send + Sync>;
 
-pub fn create_bft_engine(key_pair: KeyPair, chain: Arc<Chain>, subscriber: Addr<BroadcastEventSubscriber>) -> (Addr<Core>, SafeEngine) {
+/// the consensus engine of the chain, all nodes of a chain must run the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineKind {
+    // the validators commit every block by the pbft votes
+    Pbft,
+    // the elected delegates produce the blocks in turn, see `consensus::dpos`
+    Dpos,
+}
+
+impl Default for EngineKind {
+    fn default() -> Self {
+        EngineKind::Pbft
+    }
+}
+
+impl EngineKind {
+    /// the seal verifier of the engine, the chain starts with it, see `Chain::set_seal_verifier`
+    pub fn seal_verifier(&self) -> SafeSealVerifier {
+        match self {
+            EngineKind::Pbft => Arc::new(CommitSeals),
+            EngineKind::Dpos => Arc::new(ProposerSeal),
+        }
+    }
+}
+
+/// SealVerifier is the seal check which doesn't need the chain state, `validators` is the
+/// validator set of the header height, eg: the block importer verifies the synced blocks in
+/// parallel by it. An engine outside the tree plugs its own one into the chain.
+pub trait SealVerifier {
+    fn verify_seals(&self, header: &Header, validators: &[Address]) -> Result<(), String>;
+}
+
+pub type SafeSealVerifier = Arc<SealVerifier + Send + Sync>;
+
+/// the commit seals of the pbft validators
+pub struct CommitSeals;
+
+impl SealVerifier for CommitSeals {
+    fn verify_seals(&self, header: &Header, validators: &[Address]) -> Result<(), String> {
+        verify_block_seals(header, validators)
+    }
+}
+
+/// the seal of the dpos delegate, the delegates are carried by the header
+pub struct ProposerSeal;
+
+impl SealVerifier for ProposerSeal {
+    fn verify_seals(&self, header: &Header, _validators: &[Address]) -> Result<(), String> {
+        verify_proposer_seal(header).map(|_| ())
+    }
+}
+
+/// the core runs in its own system, the thread ends when the returned system is stopped
+pub fn create_bft_engine(signer: SafeSigner, sign_guard: SafeSignGuard, chain: Arc<Chain>, subscriber: Addr<BroadcastEventSubscriber>) -> (Addr<Core>, actix::System, SafeEngine) {
     info!("Create bft consensus engine");
-    let mut backend = new_impl_backend(key_pair.clone(), chain.clone(), subscriber);
+    let mut backend = new_impl_backend(signer.clone(), sign_guard, chain.clone(), subscriber);
 
     // use new thread to handle core
     let (tx, rx) = ::std::sync::mpsc::channel();
     let core_backend = backend.clone();
     ::std::thread::spawn(move || {
-        let core = actix::System::run(move || {
-            let core_pid = Core::new(chain, core_backend, key_pair);
-            tx.send(core_pid).unwrap();
+        let code = actix::System::run(move || {
+            let core_pid = Core::new(chain, core_backend, signer);
+            tx.send((core_pid, actix::System::current())).unwrap();
         });
-        ::std::process::exit(core);
+        info!("The consensus core stopped, code: {}", code);
     });
-    let core_pid = rx.recv().unwrap();
+    let (core_pid, core_system) = rx.recv().unwrap();
     backend.set_core_pid(core_pid.clone());
     let engine_backend: SafeEngine = Box::new(backend.clone()) as SafeEngine;
-    (core_pid, engine_backend)
+    (core_pid, core_system, engine_backend)
+}
+
+pub fn create_dpos_engine(signer: SafeSigner, sign_guard: SafeSignGuard, chain: Arc<Chain>) -> SafeEngine {
+    info!("Create dpos consensus engine, delegates: {}, epoch length: {}", chain.config.dpos.delegates, chain.config.epoch_length);
+    Box::new(DposEngine::new(signer, sign_guard, chain)) as SafeEngine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::types::block::IstanbulExtra;
+    use crate::types::votes::{encrypt_commit_bytes, Votes};
+
+    #[test]
+    fn t_seal_verifier() {
+        let key_pair = Random.generate().unwrap();
+        let validators = vec![key_pair.address()];
+        let (pbft, dpos) = (EngineKind::Pbft.seal_verifier(), EngineKind::Dpos.seal_verifier());
+
+        // the commit seals of the validators
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, None);
+        header.votes = Some(Votes::new(vec![encrypt_commit_bytes(&header.block_hash(), key_pair.secret())]));
+        assert!(pbft.verify_seals(&header, &validators).is_ok());
+        assert!(dpos.verify_seals(&header, &validators).is_err());
+
+        // the seal of the delegate
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, None);
+        header.set_istanbul_extra(&IstanbulExtra::new(validators.clone()));
+        let mut istanbul = header.istanbul_extra().unwrap();
+        istanbul.seal = Some(header.block_hash().sign(key_pair.secret()).unwrap());
+        header.set_istanbul_extra(&istanbul);
+        assert!(dpos.verify_seals(&header, &validators).is_ok());
+        assert!(pbft.verify_seals(&header, &validators).is_err());
+    }
 }
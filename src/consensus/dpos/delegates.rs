@@ -1,80 +1,196 @@
-use crate::types::{Height, transaction::Transaction, block::Block};
+use std::collections::HashMap;
 
-use super::{util, slot};
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use rlp::{RlpStream, UntrustedRlp};
 
-// TODO: add_delegate
-fn add_delegate() {}
+use super::DposConfig;
+use crate::consensus::epoch::EpochHook;
+use crate::core::ledger::Ledger;
+use crate::store::keys::AddressHeightKey;
+use crate::store::schema::Schema;
+use crate::store::types::Fork;
+use crate::types::Height;
+use crate::types::transaction::Transaction;
 
-/// Generates delegates list and checks if block generator publicKey maches delegate id.
-pub fn validate_block_slot(block: Block) -> bool {
-    // get the height delegates list
-    let block_height = block.height();
-    let delegates = slot::get_active_delegates(block_height);
-    let slot_time = block.header().time as i64;
+lazy_static! {
+    /// the voting transactions are sent to it, the payload is the rlp list of the delegates
+    pub static ref DPOS_VOTE_ADDRESS: Address = Address::from(0x64706f73_u64);
+}
+
+/// the delegates voted by a voting transaction, a new vote replaces the previous one of the voter,
+/// the election reads the last one before the epoch
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Vote {
+    pub height: Height,
+    pub delegates: Vec<Address>,
+}
 
-    let current_slot = slot::get_slot_number(slot_time);
-    let idx = current_slot as usize % delegates.len();
-    let delegate_id = delegates[idx];
+implement_storagevalue_traits! {Vote}
+implement_cryptohash_traits! {Vote}
 
-    util::equal_delegate(delegate_id, &block.header().proposer.as_bytes())
+pub fn encode_vote(delegates: &[Address]) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(delegates.len());
+    delegates.iter().for_each(|delegate| {
+        stream.append(&delegate.to_vec());
+    });
+    stream.out()
 }
 
+pub fn decode_vote(payload: &[u8]) -> Result<Vec<Address>, String> {
+    let rlp = UntrustedRlp::new(payload);
+    let mut delegates = vec![];
+    for item in rlp.iter() {
+        let bytes: Vec<u8> = item.as_val().map_err(|err| format!("{:?}", err))?;
+        if bytes.len() != 20 {
+            return Err(format!("invalid delegate address length: {}", bytes.len()));
+        }
+        delegates.push(Address::from_slice(&bytes));
+    }
+    Ok(delegates)
+}
 
-//// TODO: Opz
-//pub fn generate_delegate_list<'a>(height: Height) -> (Timespec, Vec<&'a str>){
-//   let generator_id = slot::get_active_delegates(height);
-//}
+/// the voting transaction votes for the delegates, the duplicates and the delegates beyond
+/// `max_votes` are ignored
+pub fn voted_delegates(transaction: &Transaction, max_votes: usize) -> Option<Vec<Address>> {
+    if transaction.to() != Some(&*DPOS_VOTE_ADDRESS) {
+        return None;
+    }
+    let mut delegates = decode_vote(transaction.payload()).ok()?;
+    let mut seen = vec![];
+    delegates.retain(|delegate| {
+        let fresh = !seen.contains(delegate);
+        seen.push(*delegate);
+        fresh
+    });
+    delegates.truncate(max_votes);
+    Some(delegates)
+}
 
-pub fn get_block_slot_data<'a>(slot: i64, height: Height) -> Option<(&'a str, i64)> {
-    let current_slot = slot;
-    let delegates = slot::get_active_delegates(height);
-    let delegate_pos = current_slot % slot::DELEGATES;
-    let delegate_id = delegates.get(delegate_pos as usize);
-    Some((delegate_id.unwrap(), slot::get_slot_time(slot)))
+/// record the votes of the block in the vote index, the coinbase transaction never votes
+pub fn write_votes<T: Fork + ?Sized>(schema: &Schema<T>, height: Height, transactions: &[Transaction]) {
+    let mut votes_db = schema.dpos_votes();
+    for transaction in transactions.iter().skip(1) {
+        let (voter, delegates) = match (transaction.sender(), voted_delegates(transaction, usize::max_value())) {
+            (Some(voter), Some(delegates)) => (voter, delegates),
+            _ => continue,
+        };
+        // a later vote of the voter in the same block replaces the earlier one
+        votes_db.put(&AddressHeightKey::new(voter, height), Vote { height, delegates });
+    }
 }
 
-// TODO:
-// height --> slot_list
-// 根据当前的slot，找到高度为height的相应见证人id以及相对应的slot
-//
-//fn get_block_slot_data<'a>(slot: i64, height: Height) -> Option<(&'a str, i64)> {
-//    let current_slot = slot;
-//    let last_slot = slot::get_last_slot(current_slot);
-//    let delegates = slot::get_active_delegates(height);
-//
-//    for _slot in current_slot..last_slot {
-//        let delegate_pos = _slot % slot::DELEGATES;
-//        let deletegate_id = delegates.get(delegate_pos as usize);
-//        if deletegate_id.is_none() {
-//            continue;
-//        }
-//        return Some((deletegate_id.unwrap(), slot::get_slot_time(_slot)));
-//    }
-//    None
-//}
-
-//
-//fn get_keys_sort_by_vote() {
-//
-//}
-//
-//// TODO
-//fn get_accounts(_: String) {
-//
-//}
+/// the last vote of every voter before the boundary, the votes are in the (voter, height) order of
+/// the vote index, so it's one pass over them
+pub fn last_votes<I>(votes: I, boundary: Height) -> Vec<(Address, Vec<Address>)>
+    where I: Iterator<Item=(AddressHeightKey, Vote)> {
+    let mut last: Vec<(Address, Vec<Address>)> = vec![];
+    for (key, vote) in votes.filter(|(key, _)| key.height < boundary) {
+        match last.last_mut() {
+            Some((voter, delegates)) if *voter == key.address => *delegates = vote.delegates,
+            _ => last.push((key.address, vote.delegates)),
+        }
+    }
+    last
+}
+
+/// Elect the delegates by the approval voting: a delegate gets the whole stake of every voter who
+/// votes for it, the delegates with the most stake win and the ties are broken by the address. The
+/// seats which nobody is voted for are kept by the previous delegates.
+pub fn elect<I, F>(votes: I, stake_of: F, seats: usize, max_votes: usize, previous: &[Address]) -> Vec<Address>
+    where I: Iterator<Item=(Address, Vec<Address>)>,
+          F: Fn(&Address) -> u64 {
+    let mut weights: HashMap<Address, u64> = HashMap::new();
+    for (voter, delegates) in votes {
+        let stake = stake_of(&voter);
+        if stake == 0 {
+            continue;
+        }
+        for delegate in delegates.into_iter().take(max_votes) {
+            let weight = weights.entry(delegate).or_insert(0);
+            *weight = weight.saturating_add(stake);
+        }
+    }
+    let mut ranked: Vec<(Address, u64)> = weights.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let mut elected: Vec<Address> = ranked.into_iter().take(seats).map(|(delegate, _)| delegate).collect();
+    for delegate in previous {
+        if elected.len() >= seats {
+            break;
+        }
+        if !elected.contains(delegate) {
+            elected.push(*delegate);
+        }
+    }
+    elected
+}
+
+/// DposElection elects the delegates of every epoch by the votes before its first block, the
+/// stake of a voter is its balance at the last block before the epoch
+pub struct DposElection {
+    config: DposConfig,
+}
+
+impl DposElection {
+    pub fn new(config: DposConfig) -> Self {
+        DposElection { config }
+    }
+}
+
+impl EpochHook for DposElection {
+    fn elect(&self, ledger: &Ledger, _number: u64, boundary: Height, previous: &[Address]) -> Vec<Address> {
+        let votes = last_votes(ledger.get_schema().dpos_votes().iter(), boundary);
+        // the stakes are read from one state, a later epoch isn't final and it's elected from the
+        // last state, the delegates are kept if the state is pruned
+        let height = ::std::cmp::min(boundary - 1, *ledger.get_last_block_height());
+        let state = match ledger.state_at(height) {
+            Ok(state) => state,
+            Err(err) => {
+                warn!("Keep the delegates, the state before the epoch is missing, boundary: {}, err: {}", boundary, err);
+                return previous.to_vec();
+            }
+        };
+        let stake_of = |voter: &Address| state.account(voter).balance;
+        elect(votes.into_iter(), stake_of, self.config.delegates, self.config.max_votes, previous)
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use std::io::{self, Write};
     use super::*;
 
     #[test]
-    fn test_get_block_slot_data() {
-        for height in 1..12 {
-            let slot = height - 1;
-            let (delegate_id, slot_time) = get_block_slot_data(slot, Height(height as u64)).unwrap();
-            let slot_number = super::slot::get_slot_number(slot_time);
-            writeln!(io::stdout(), "deletegate_id: {}, slot_number: {}, slot_time: {}", delegate_id, slot_number, slot_time);
-        }
+    fn t_elect() {
+        let (a, b, c, d) = (Address::from(1), Address::from(2), Address::from(3), Address::from(4));
+        assert_eq!(decode_vote(&encode_vote(&[a, b])).unwrap(), vec![a, b]);
+        assert!(decode_vote(&[0xc1, 0x01]).is_err());
+
+        let stakes: HashMap<Address, u64> = vec![(Address::from(10), 100), (Address::from(11), 30), (Address::from(12), 0)].into_iter().collect();
+        let votes = vec![
+            (Address::from(10), vec![c, b]),
+            (Address::from(11), vec![a, b]),
+            // no stake, no weight
+            (Address::from(12), vec![d]),
+        ];
+        let stake_of = |voter: &Address| stakes.get(voter).cloned().unwrap_or(0);
+        // b: 130, c: 100, a: 30
+        assert_eq!(elect(votes.clone().into_iter(), stake_of, 2, 2, &[]), vec![b, c]);
+        // the empty seat is kept by the previous delegate
+        assert_eq!(elect(votes.clone().into_iter(), stake_of, 4, 2, &[d, b]), vec![b, c, a, d]);
+        // only the first vote of every voter counts
+        assert_eq!(elect(votes.into_iter(), stake_of, 3, 1, &[]), vec![c, a]);
+    }
+
+    #[test]
+    fn t_last_votes() {
+        let (a, b, c) = (Address::from(1), Address::from(2), Address::from(3));
+        let (voter, other) = (Address::from(10), Address::from(11));
+        let vote = |address: Address, height: Height, delegates: Vec<Address>| (AddressHeightKey::new(address, height), Vote { height, delegates });
+        let votes = vec![vote(voter, 1, vec![a]), vote(voter, 5, vec![b]), vote(voter, 9, vec![c]), vote(other, 12, vec![a])];
+        // the votes at or after the boundary don't count
+        assert_eq!(last_votes(votes.clone().into_iter(), 9), vec![(voter, vec![b])]);
+        assert_eq!(last_votes(votes.clone().into_iter(), 13), vec![(voter, vec![c]), (other, vec![a])]);
+        assert!(last_votes(votes.into_iter(), 1).is_empty());
     }
 }
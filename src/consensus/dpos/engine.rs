@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam::crossbeam_channel::Receiver;
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use super::slot::{missed_by_delegates, missed_slots, slot_delegate, slot_number, slot_time};
+use crate::consensus::consensus::Engine;
+use crate::consensus::error::{EngineError, EngineResult};
+use crate::consensus::sign_guard::SafeSignGuard;
+use crate::core::chain::Chain;
+use crate::signer::SafeSigner;
+use crate::types::block::{Block, Header, IstanbulExtra};
+use crate::types::Timestamp;
+
+/// the proposer seal must be signed by the proposer, and the proposer must be one of the
+/// delegates carried by the istanbul extra
+pub fn verify_proposer_seal(header: &Header) -> Result<Address, String> {
+    let istanbul = header.istanbul_extra().ok_or("the istanbul extra is absent".to_string())?;
+    let proposer = istanbul.recover_proposer(&header.block_hash())?;
+    if proposer != header.proposer {
+        return Err(format!("the seal is signed by {:?}, not the proposer {:?}", proposer, header.proposer));
+    }
+    if !istanbul.validators.contains(&proposer) {
+        return Err(format!("the proposer {:?} is not a delegate", proposer));
+    }
+    Ok(proposer)
+}
+
+/// the slots missed by every delegate before a block, see `DposSchedule::missed`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct MissedSlots(pub Vec<(Address, u64)>);
+
+implement_storagevalue_traits! {MissedSlots}
+implement_cryptohash_traits! {MissedSlots}
+
+/// DposSchedule maps the time to the slots from the genesis time, every block must be produced by
+/// the delegate of its slot. The chain builds it once with its genesis block and shares it with
+/// the engine and the block importers, see `Chain::dpos_schedule`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DposSchedule {
+    genesis_time: Timestamp,
+    // secs, the length of a slot
+    interval: u64,
+}
+
+impl DposSchedule {
+    pub fn new(genesis_time: Timestamp, block_period: Duration) -> Self {
+        DposSchedule { genesis_time, interval: ::std::cmp::max(block_period.as_secs(), 1) }
+    }
+
+    pub fn slot_number(&self, time: Timestamp) -> u64 {
+        slot_number(self.genesis_time, time, self.interval)
+    }
+
+    pub fn slot_time(&self, slot: u64) -> Timestamp {
+        slot_time(self.genesis_time, slot, self.interval)
+    }
+
+    /// the slot of the timestamp, it must be the start of the slot
+    pub fn slot_of(&self, time: Timestamp) -> Option<u64> {
+        let slot = self.slot_number(time);
+        if self.slot_time(slot) == time {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+
+    /// the block must follow its parent in a later slot, be proposed by the delegate of the slot
+    /// and carry the delegates of its epoch
+    pub fn verify_header(&self, header: &Header, parent: &Header, delegates: &[Address]) -> EngineResult {
+        if header.height == 0 {
+            return Err(EngineError::InvalidHeight);
+        }
+        if parent.height + 1 != header.height || parent.block_hash() != header.prev_hash {
+            return Err(EngineError::Unknown(format!("parent hash({:?}) != header.prev hash({:?})", parent.block_hash(), header.prev_hash)));
+        }
+        let slot = self.slot_of(header.time).ok_or(EngineError::InvalidTimestamp)?;
+        if slot <= self.slot_number(parent.time) {
+            return Err(EngineError::InvalidTimestamp);
+        }
+        if slot_delegate(delegates, slot) != Some(header.proposer) {
+            return Err(EngineError::Unauthorized);
+        }
+        match header.istanbul_extra() {
+            Some(ref istanbul) if istanbul.validators == delegates => Ok(()),
+            _ => Err(EngineError::InvalidHeaderField("the delegates of the istanbul extra mismatch".to_string())),
+        }
+    }
+
+    /// the delegates which missed their slots between the parent and the block, the genesis block
+    /// has no slot, so the first block misses nothing
+    pub fn missed(&self, parent: &Header, header: &Header, delegates: &[Address]) -> MissedSlots {
+        if parent.height == 0 {
+            return MissedSlots::default();
+        }
+        let slots = missed_slots(self.slot_number(parent.time), self.slot_number(header.time));
+        MissedSlots(missed_by_delegates(delegates, slots))
+    }
+
+    /// the fork choice of two blocks of one parent: the block of the earlier slot wins, and the
+    /// blocks of one slot (its delegate produced twice) are ordered by the hash, so all nodes keep
+    /// the same block
+    pub fn prefer(&self, current: &Header, candidate: &Header) -> bool {
+        let (current_slot, candidate_slot) = (self.slot_number(current.time), self.slot_number(candidate.time));
+        candidate_slot < current_slot || (candidate_slot == current_slot && candidate.block_hash() < current.block_hash())
+    }
+}
+
+/// DposEngine produces a block in every slot of its own, there is no vote round, a block is
+/// final once it's sealed by the delegate of its slot.
+pub struct DposEngine {
+    chain: Arc<Chain>,
+    signer: SafeSigner,
+    // a delegate seals one block of a height, the other forks of its slot are refused
+    sign_guard: SafeSignGuard,
+    started: bool,
+}
+
+impl DposEngine {
+    pub fn new(signer: SafeSigner, sign_guard: SafeSignGuard, chain: Arc<Chain>) -> Self {
+        DposEngine { chain, signer, sign_guard, started: false }
+    }
+
+    /// the missed slots of every delegate, they're recorded by the chain with the blocks
+    pub fn missed_slots(&self) -> HashMap<Address, u64> {
+        self.chain.get_missed_slots()
+    }
+
+    fn schedule(&self) -> Result<DposSchedule, EngineError> {
+        self.chain.dpos_schedule().cloned().ok_or(EngineError::Unknown("the chain has no slot schedule".to_string()))
+    }
+}
+
+impl Engine for DposEngine {
+    fn start(&mut self) -> Result<(), String> {
+        self.started = true;
+        info!("Dpos engine start successfully");
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        self.started = false;
+        Ok(())
+    }
+
+    fn author(&self, header: &Header) -> Result<Address, String> {
+        verify_proposer_seal(header)
+    }
+
+    fn verify_header(&self, header: &Header, seal: bool) -> EngineResult {
+        self.chain.verify_slot(header)?;
+        if seal {
+            self.verify_seal(header)?;
+        }
+        Ok(())
+    }
+
+    fn verify_seal(&self, header: &Header) -> EngineResult {
+        verify_proposer_seal(header).map(|_| ()).map_err(|err| {
+            debug!("Invalid proposer seal, height: {}, err: {}", header.height, err);
+            EngineError::InvalidSignature
+        })
+    }
+
+    fn prepare(&mut self, header: &mut Header) -> Result<(), String> {
+        header.votes = None;
+        header.set_istanbul_extra(&IstanbulExtra::new(self.chain.get_validator_addresses(header.height)));
+        Ok(())
+    }
+
+    fn finalize(&mut self, _header: &Header) -> Result<(), String> {
+        Ok(())
+    }
+
+    // the slot is computed from the parent on every seal, nothing is kept across the heads
+    fn on_new_chain_head(&mut self, _header: &Header) -> EngineResult {
+        Ok(())
+    }
+
+    // the seal never waits for the slot, the minner packs the block again on its next idle check
+    fn seal(&mut self, new_block: &mut Block, _abort: Receiver<()>) -> EngineResult {
+        if !self.started {
+            return Err(EngineError::EngineNotStarted);
+        }
+        let schedule = self.schedule()?;
+        let height = new_block.height();
+        let chain = self.chain.clone();
+        let parent = chain.get_header_by_height(height - 1)
+            .ok_or(EngineError::UnknownAncestor(height, height - 1))?;
+        let now = chrono::Local::now().timestamp() as u64;
+        let slot = ::std::cmp::max(schedule.slot_number(parent.time) + 1, schedule.slot_number(now));
+        let delegates = chain.get_validator_addresses(height);
+        if slot_delegate(&delegates, slot) != Some(self.signer.address()) {
+            return Err(EngineError::Interrupt);
+        }
+        let time = schedule.slot_time(slot);
+        if time > now {
+            return Err(EngineError::Interrupt);
+        }
+        // a block of the other delegates is imported since the block was packed
+        if chain.get_last_height() != height - 1 {
+            return Err(EngineError::Interrupt);
+        }
+
+        {
+            let header = new_block.mut_header();
+            header.time = time;
+            // the minner prepares the header, the istanbul extra carries the delegates
+            let mut istanbul = header.istanbul_extra().ok_or(EngineError::InvalidHeaderField("the header isn't prepared".to_string()))?;
+            let vote = self.sign_guard.lock().check_seal(height, header.block_hash()).map_err(|err| {
+                error!("Refuse to seal the slot block, height: {}, err: {}", height, err);
+                EngineError::InvalidSignature
+            })?;
+            istanbul.seal = Some(self.signer.sign_vote(&header.block_hash(), &vote).map_err(|err| {
+                error!("Failed to sign the slot block, height: {}, err: {}", height, err);
+                EngineError::InvalidSignature
+            })?);
+            header.set_istanbul_extra(&istanbul);
+            header.cache_hash(None);
+        }
+        info!("⛏️ Produce the block of the slot, hash: {:?}, height: {}, slot: {}", new_block.hash().short(), height, slot);
+        chain.insert_block(new_block).map_err(|err| {
+            error!("Failed to insert the sealed block, height: {}, err: {:?}", height, err);
+            EngineError::InvalidBlock
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::actix::prelude::System;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+    use parking_lot::Mutex;
+    use crate::common::random_dir;
+    use crate::consensus::consensus::EngineKind;
+    use crate::consensus::sign_guard::SignGuard;
+    use crate::core::test_util::memory_chain_of;
+    use crate::signer::LocalSigner;
+
+    // the block after the parent in the slot, proposed and sealed by the key pair
+    fn slot_header(schedule: &DposSchedule, parent: &Header, slot: u64, key_pair: &KeyPair, delegates: Vec<Address>) -> Header {
+        let mut header = Header::new_mock(parent.block_hash(), key_pair.address(), EMPTY_HASH, parent.height + 1, schedule.slot_time(slot), None);
+        header.set_istanbul_extra(&IstanbulExtra::new(delegates));
+        let mut istanbul = header.istanbul_extra().unwrap();
+        istanbul.seal = Some(header.block_hash().sign(key_pair.secret()).unwrap());
+        header.set_istanbul_extra(&istanbul);
+        header
+    }
+
+    #[test]
+    fn t_dpos_schedule() {
+        let (a, b) = (Random.generate().unwrap(), Random.generate().unwrap());
+        let delegates = vec![a.address(), b.address()];
+        let schedule = DposSchedule::new(100, Duration::from_secs(3));
+        assert_eq!(schedule.slot_of(106), Some(2));
+        assert_eq!(schedule.slot_of(107), None);
+
+        // slot 1 is b's, slot 2 is a's
+        let genesis = Header::new_mock(EMPTY_HASH, a.address(), EMPTY_HASH, 0, 100, None);
+        let parent = slot_header(&schedule, &genesis, 1, &b, delegates.clone());
+        assert!(schedule.verify_header(&parent, &genesis, &delegates).is_ok());
+        let header = slot_header(&schedule, &parent, 2, &a, delegates.clone());
+        assert!(schedule.verify_header(&header, &parent, &delegates).is_ok());
+        // the wrong delegate, a slot before the parent, a time inside the slot, other delegates
+        assert!(match schedule.verify_header(&slot_header(&schedule, &parent, 2, &b, delegates.clone()), &parent, &delegates) { Err(EngineError::Unauthorized) => true, _ => false });
+        assert!(match schedule.verify_header(&slot_header(&schedule, &parent, 0, &a, delegates.clone()), &parent, &delegates) { Err(EngineError::InvalidTimestamp) => true, _ => false });
+        let mut misaligned = header.clone();
+        misaligned.time += 1;
+        assert!(match schedule.verify_header(&misaligned, &parent, &delegates) { Err(EngineError::InvalidTimestamp) => true, _ => false });
+        assert!(schedule.verify_header(&slot_header(&schedule, &parent, 2, &a, vec![a.address()]), &parent, &delegates).is_err());
+        assert!(schedule.verify_header(&header, &genesis, &delegates).is_err());
+
+        // b missed slot 3, a missed slot 4 before the block of slot 5, nothing before the first block
+        let later = slot_header(&schedule, &header, 5, &b, delegates.clone());
+        assert_eq!(schedule.missed(&header, &later, &delegates), MissedSlots(vec![(b.address(), 1), (a.address(), 1)]));
+        assert_eq!(schedule.missed(&genesis, &slot_header(&schedule, &genesis, 3, &b, delegates.clone()), &delegates), MissedSlots::default());
+
+        // the earlier slot wins, one slot is ordered by the hash
+        let (early, late) = (slot_header(&schedule, &parent, 2, &a, delegates.clone()), slot_header(&schedule, &parent, 4, &a, delegates.clone()));
+        assert!(schedule.prefer(&late, &early) && !schedule.prefer(&early, &late));
+        let mut twin = early.clone();
+        twin.extra = Some(IstanbulExtra::new(delegates.clone()).to_extra(b"twin"));
+        assert_ne!(schedule.prefer(&early, &twin), schedule.prefer(&twin, &early));
+        assert!(!schedule.prefer(&early, &early));
+    }
+
+    #[test]
+    fn t_dpos_chain() {
+        let _system = System::new("t_dpos_chain");
+        let (key_pair, other) = (Random.generate().unwrap(), Random.generate().unwrap());
+        let chain = memory_chain_of(&key_pair, EngineKind::Dpos);
+        let schedule = *chain.dpos_schedule().unwrap();
+        let dir = *random_dir();
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let signer: SafeSigner = Arc::new(LocalSigner::new(key_pair.clone()));
+        let engine = DposEngine::new(signer, Arc::new(Mutex::new(SignGuard::load(&dir).unwrap())), chain.clone());
+        let delegates = vec![key_pair.address()];
+
+        // the block of the slot, the forged ones fail
+        let genesis = chain.get_genesis().header().clone();
+        let late = slot_header(&schedule, &genesis, 3, &key_pair, delegates.clone());
+        assert!(engine.verify_header(&late, true).is_ok());
+        let mut misaligned = late.clone();
+        misaligned.time += 1;
+        assert!(engine.verify_header(&misaligned, false).is_err());
+        assert!(match engine.verify_header(&slot_header(&schedule, &genesis, 3, &other, delegates.clone()), false) { Err(EngineError::Unauthorized) => true, _ => false });
+        let mut forged = late.clone();
+        let mut istanbul = forged.istanbul_extra().unwrap();
+        istanbul.seal = Some(forged.block_hash().sign(other.secret()).unwrap());
+        forged.set_istanbul_extra(&istanbul);
+        assert!(engine.verify_header(&forged, false).is_ok());
+        assert!(match engine.verify_header(&forged, true) { Err(EngineError::InvalidSignature) => true, _ => false });
+
+        // the block of the earlier slot replaces the head, the later one is refused
+        chain.insert_block(&Block::new(late.clone(), vec![])).unwrap();
+        let early = slot_header(&schedule, &genesis, 1, &key_pair, delegates.clone());
+        chain.insert_block(&Block::new(early.clone(), vec![])).unwrap();
+        assert_eq!(chain.get_last_hash(), early.block_hash());
+        assert!(chain.insert_block(&Block::new(late.clone(), vec![])).is_err());
+        assert_eq!(chain.get_last_hash(), early.block_hash());
+
+        // the missed slots are kept with the blocks and dropped with them
+        let next = slot_header(&schedule, &early, 4, &key_pair, delegates.clone());
+        chain.insert_block(&Block::new(next, vec![])).unwrap();
+        assert_eq!(engine.missed_slots().get(&key_pair.address()), Some(&2));
+        chain.truncate(1).unwrap();
+        assert!(engine.missed_slots().is_empty());
+    }
+
+    #[test]
+    fn t_verify_proposer_seal() {
+        let key_pair = Random.generate().unwrap();
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, None);
+        assert!(verify_proposer_seal(&header).is_err());
+
+        header.set_istanbul_extra(&IstanbulExtra::new(vec![key_pair.address()]));
+        let mut istanbul = header.istanbul_extra().unwrap();
+        istanbul.seal = Some(header.block_hash().sign(key_pair.secret()).unwrap());
+        header.set_istanbul_extra(&istanbul);
+        assert_eq!(verify_proposer_seal(&header), Ok(key_pair.address()));
+
+        // the seal of a key which is not a delegate
+        let other = Random.generate().unwrap();
+        istanbul.validators = vec![other.address()];
+        header.set_istanbul_extra(&istanbul);
+        assert!(verify_proposer_seal(&header).is_err());
+    }
+}
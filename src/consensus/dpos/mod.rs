@@ -1,3 +1,49 @@
+//! The delegated proof of stake engine: the stake holders vote for the delegates with the voting
+//! transactions, the delegates with the most stake are elected every epoch and produce the blocks
+//! in turn, one slot of the block period each.
+
 pub mod delegates;
 pub mod slot;
-pub mod util;
\ No newline at end of file
+pub mod engine;
+
+use crate::types::Height;
+
+/// the dpos rules, all nodes of a chain must agree on them, the delegates are elected every
+/// `epoch_length` blocks, eg:
+/// ```toml
+/// engine = "dpos"
+/// epoch_length = 100
+/// [dpos]
+/// delegates = 11
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DposConfig {
+    // the delegates elected every epoch
+    #[serde(default = "default_delegates")]
+    pub delegates: usize,
+    // the delegates that one voting transaction can vote for
+    #[serde(default = "default_max_votes")]
+    pub max_votes: usize,
+    // the old key of the root `epoch_length`, `parse_config` moves it there
+    #[serde(default, skip_serializing)]
+    pub epoch_length: Option<Height>,
+}
+
+impl Default for DposConfig {
+    fn default() -> Self {
+        DposConfig {
+            delegates: default_delegates(),
+            max_votes: default_max_votes(),
+            epoch_length: None,
+        }
+    }
+}
+
+fn default_delegates() -> usize {
+    11
+}
+
+fn default_max_votes() -> usize {
+    default_delegates()
+}
@@ -1,153 +1,77 @@
-use time::{self, Timespec, Duration};
-use chrono::*;
+use std::ops::Range;
+
 use cryptocurrency_kit::ethkey::Address;
 
-use crate::types::Height;
+use crate::types::Timestamp;
 
+/// The slots are counted from the genesis time by the block period, every slot has one delegate
+/// and at most one block:
 ///
-///     [1, 2, 3, 4], [5, 6, 7, 8], [9, 10]
-///     slot0           slot1      slot2(current slot)
-///     next_slot = [13, 14, 15, 16]
-pub const INTERVAL: i64 = 3;
-pub const DELEGATES: i64 = 11;
-pub const ACTIVE_DELEGATES:[&str; DELEGATES as usize] = [
-    "a",
-    "b",
-    "c",
-    "d",
-    "e",
-    "f",
-    "g",
-    "h",
-    "i",
-    "j",
-    "k"];
-
-lazy_static! {
-    static ref ACTIVE_DELEGATES_LIST: Vec<&'static str> = {
-        let mut active_delegates = vec![];
-        {
-            active_delegates.push("a");
-            active_delegates.push("b");
-            active_delegates.push("c");
-            active_delegates.push("d");
-            active_delegates.push("e");
-            active_delegates.push("f");
-            active_delegates.push("g");
-            active_delegates.push("h");
-            active_delegates.push("i");
-            active_delegates.push("j");
-            active_delegates.push("k");
-        }
-        active_delegates
-    };
-}
-
-pub fn get_active_delegates<'a>(height: Height) -> Vec<&'a str> {
-//    ACTIVE_DELEGATES.to_vec()
-    ACTIVE_DELEGATES_LIST.clone()
+///     genesis  slot1  slot2  slot3  slot4
+///     |------|------|------|------|
+///              a      b      c      a
+pub fn slot_number(genesis_time: Timestamp, time: Timestamp, interval: u64) -> u64 {
+    time.saturating_sub(genesis_time) / ::std::cmp::max(interval, 1)
 }
 
-/// this is a epoch time
-pub fn get_time(time_spec: Timespec) -> i64{
-     return epoch_time(time_spec)
+/// the start time of the slot, the block of the slot is stamped with it
+pub fn slot_time(genesis_time: Timestamp, slot: u64, interval: u64) -> Timestamp {
+    genesis_time + slot * interval
 }
 
-/// real time, accurate to milliseconds
-pub fn get_real_time(epoch_spec: i64) -> i64 {
-    (epoch_spec + begin_epoch_time()) * 1000
-}
-
-/// epoch_time time's slot
-pub fn get_slot_number(mut epoch_time: i64) -> i64 {
-    if epoch_time == 0 {
-        epoch_time = get_time(time::get_time());
+/// the delegates produce the blocks of the slots in turn
+pub fn slot_delegate(delegates: &[Address], slot: u64) -> Option<Address> {
+    if delegates.is_empty() {
+        return None;
     }
-    return epoch_time / INTERVAL
-}
-
-/// this is epoch time
-pub fn get_slot_time(slot: i64) -> i64{
-    return slot * INTERVAL
-}
-
-// current slot + 1
-pub fn get_next_slot() -> i64 {
-    let time_now = time::get_time();
-    let epoch_time = get_time(time_now);
-    let slot = get_slot_number(epoch_time);
-    slot + 1
-}
-
-pub fn get_last_slot(next_slot: i64) -> i64 {
-    next_slot + DELEGATES
-}
-
-// [time_spec - begin_time]
-fn epoch_time(time_spec: Timespec) -> i64 {
-    time_spec.sec - begin_epoch_time()
-}
-
-// return begin epoch time
-fn begin_epoch_time() -> i64 {
-    let epoch_time = DateTime::parse_from_rfc2822("Fri, 14 Jul 2017 02:40:00 +0000").unwrap();
-    epoch_time.timestamp()
+    delegates.get((slot % delegates.len() as u64) as usize).cloned()
 }
 
-fn round_time(data: Timespec) -> i64 {
-    data.sec
+/// the slots between the parent and the block, their delegates didn't produce a block
+pub fn missed_slots(parent_slot: u64, slot: u64) -> Range<u64> {
+    parent_slot + 1..::std::cmp::max(slot, parent_slot + 1)
 }
 
-// calc height round
-fn calc_round(height: i64) -> i64{
-    let round = (height as f64) / (DELEGATES as f64);
-    round.ceil() as i64
+/// the slots of the range missed by every delegate, it's counted by the turns instead of the
+/// slots, so a long outage costs no more than a short one
+pub fn missed_by_delegates(delegates: &[Address], slots: Range<u64>) -> Vec<(Address, u64)> {
+    if delegates.is_empty() || slots.start >= slots.end {
+        return vec![];
+    }
+    let count = delegates.len() as u64;
+    let (turns, rest) = ((slots.end - slots.start) / count, (slots.end - slots.start) % count);
+    (0..count).filter_map(|offset| {
+        let missed = turns + if offset < rest { 1 } else { 0 };
+        if missed == 0 {
+            return None;
+        }
+        slot_delegate(delegates, slots.start + offset).map(|delegate| (delegate, missed))
+    }).collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::{self, Write};
-
-    #[test]
-    fn test_epoch_time(){
-        println!("Hello Word ....");
-        let epoch = super::DateTime::parse_from_rfc2822("Fri, 14 Jul 2017 02:40:00 +0000").unwrap();
-        writeln!(io::stdout(), "{}", epoch.timestamp()).unwrap();
-
-        let time_now = super::time::get_time();
-        let epoch_time = super::epoch_time(time_now);
-        writeln!(io::stdout(), "epoch time {}", epoch_time).unwrap();
-    }
-
-    #[test]
-    fn test_get_real_time(){
-        let time_now = super::time::get_time();
-        let epoch_time = super::get_time(time_now);
-        assert_eq!(super::get_real_time(epoch_time), time_now.sec *1000);
-        writeln!(io::stdout(), "real time {}", super::get_real_time(epoch_time)).unwrap();
-    }
+    use super::*;
 
     #[test]
-    fn test_get_slot_number(){
-        let time_now = super::time::get_time();
-        let epoch_time = super::get_time(time_now);
-        writeln!(io::stdout(), "epoch time {}, slot number {}",epoch_time, super::get_slot_number(epoch_time)).unwrap();
+    fn t_slot() {
+        let delegates = vec![Address::from(1), Address::from(2), Address::from(3)];
+        assert_eq!(slot_number(100, 100, 3), 0);
+        assert_eq!(slot_number(100, 105, 3), 1);
+        assert_eq!(slot_number(100, 99, 3), 0);
+        assert_eq!(slot_time(100, 2, 3), 106);
+        assert_eq!(slot_delegate(&delegates, 4), Some(Address::from(2)));
+        assert_eq!(slot_delegate(&[], 4), None);
+        assert_eq!(missed_slots(3, 6).collect::<Vec<u64>>(), vec![4, 5]);
+        assert_eq!(missed_slots(3, 4).count(), 0);
+        assert_eq!(missed_slots(3, 2).count(), 0);
+
+        // slot 4 is b's, 5 is c's, 6 is a's
+        assert_eq!(missed_by_delegates(&delegates, 4..6), vec![(Address::from(2), 1), (Address::from(3), 1)]);
+        assert_eq!(missed_by_delegates(&delegates, 4..12), vec![(Address::from(2), 3), (Address::from(3), 3), (Address::from(1), 2)]);
+        assert_eq!(missed_by_delegates(&delegates, 4..4), vec![]);
+        assert_eq!(missed_by_delegates(&[], 4..12), vec![]);
+        let long: u64 = missed_by_delegates(&delegates, 1..1 << 40).iter().map(|(_, missed)| missed).sum();
+        assert_eq!(long, (1 << 40) - 1);
     }
-
-    #[test]
-    fn test_get_next_slot_number(){
-        let time_now = super::time::get_time();
-        let epoch_time = super::get_time(time_now);
-        let slot_number = super::get_slot_number(epoch_time);
-
-        writeln!(io::stdout(), "prev slot number {}, next slot number {}", slot_number, super::get_next_slot()).unwrap();
-    }
-
-    #[test]
-    fn test_round_time(){
-        assert_eq!(super::calc_round(1), 1);
-        assert_eq!(super::calc_round(10), 1);
-        assert_eq!(super::calc_round(11), 1);
-        assert_eq!(super::calc_round(12), 2);
-    }
-}
\ No newline at end of file
+}
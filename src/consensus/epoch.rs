@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use parking_lot::RwLock;
+
+use crate::core::ledger::Ledger;
+use crate::metrics;
+use crate::types::Height;
+
+pub const DEFAULT_EPOCH_LENGTH: Height = 100;
+
+/// Epoch is a span of blocks which share one validator set, the set of an epoch is decided by the
+/// blocks before its first one, so it's known before the epoch starts.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Epoch {
+    pub number: u64,
+    pub start: Height,
+    pub length: Height,
+    pub validators: Vec<Address>,
+}
+
+implement_storagevalue_traits! {Epoch}
+implement_hash_algo_traits! {Epoch}
+
+impl Epoch {
+    pub fn end(&self) -> Height {
+        self.start + self.length - 1
+    }
+
+    pub fn contains(&self, height: Height) -> bool {
+        height >= self.start && height <= self.end()
+    }
+}
+
+pub fn epoch_number(height: Height, length: Height) -> u64 {
+    height / ::std::cmp::max(length, 1)
+}
+
+pub fn epoch_start(number: u64, length: Height) -> Height {
+    number * ::std::cmp::max(length, 1)
+}
+
+/// EpochHook decides the validators of an epoch at its boundary, every engine has its own
+pub trait EpochHook {
+    /// the validators of the epoch `number`, `boundary` is its first height and `previous` is the
+    /// validator set of the epoch before
+    fn elect(&self, ledger: &Ledger, number: u64, boundary: Height, previous: &[Address]) -> Vec<Address>;
+}
+
+pub type SafeEpochHook = Box<EpochHook + Send + Sync>;
+
+/// the pbft validators are the set scheduled at or before the boundary, a set scheduled in the
+/// middle of an epoch takes effect from the next one
+pub struct StaticValidators;
+
+impl EpochHook for StaticValidators {
+    fn elect(&self, ledger: &Ledger, _number: u64, boundary: Height, _previous: &[Address]) -> Vec<Address> {
+        ledger.get_validators_at(boundary)
+    }
+}
+
+/// EpochManager tracks the epochs of the chain, the decided epochs are persisted so the elections
+/// never run twice, the first epoch is kept by the genesis validators.
+pub struct EpochManager {
+    ledger: Arc<RwLock<Ledger>>,
+    length: Height,
+    hook: SafeEpochHook,
+    cache: RwLock<BTreeMap<u64, Epoch>>,
+}
+
+impl EpochManager {
+    pub fn new(ledger: Arc<RwLock<Ledger>>, length: Height, hook: SafeEpochHook) -> Self {
+        EpochManager { ledger, length: ::std::cmp::max(length, 1), hook, cache: RwLock::new(BTreeMap::new()) }
+    }
+
+    pub fn length(&self) -> Height {
+        self.length
+    }
+
+    /// the epoch of the height, the missing epochs are decided one by one from the last known one
+    pub fn epoch_at(&self, height: Height) -> Epoch {
+        let target = epoch_number(height, self.length);
+        if let Some(epoch) = self.cache.read().get(&target) {
+            return epoch.clone();
+        }
+        let ledger = self.ledger.read();
+        let mut epoch = self.last_known(&ledger, target);
+        while epoch.number < target {
+            let number = epoch.number + 1;
+            let boundary = epoch_start(number, self.length);
+            let validators = self.hook.elect(&ledger, number, boundary, &epoch.validators);
+            info!("New epoch, number: {}, start: {}, validators: {:?}", number, boundary, validators);
+            epoch = Epoch { number, start: boundary, length: self.length, validators };
+            // the epoch is final only when all the blocks before it are committed
+            if boundary <= ledger.get_last_block_height() + 1 {
+                self.persist(&ledger, &epoch);
+            }
+        }
+        epoch
+    }
+
+    /// the epoch of the height if it's final, the validators of a later epoch are elected from
+    /// the blocks which aren't committed yet, so they can't verify a block
+    pub fn decided_epoch_at(&self, height: Height) -> Option<Epoch> {
+        let boundary = epoch_start(epoch_number(height, self.length), self.length);
+        if boundary > self.ledger.read().get_last_block_height() + 1 {
+            return None;
+        }
+        Some(self.epoch_at(height))
+    }
+
+    // the store keeps the decided epochs, the cache only answers the exact hits of `epoch_at`,
+    // the first epoch is kept by the genesis validators
+    fn last_known(&self, ledger: &Ledger, target: u64) -> Epoch {
+        if let Some((_, epoch)) = ledger.get_schema().epochs().iter_rev_from(&target).next() {
+            self.cache.write().insert(epoch.number, epoch.clone());
+            return epoch;
+        }
+        let epoch = Epoch { number: 0, start: 0, length: self.length, validators: ledger.get_validators_at(0) };
+        self.persist(ledger, &epoch);
+        epoch
+    }
+
+    fn persist(&self, ledger: &Ledger, epoch: &Epoch) {
+        let mut epochs = ledger.get_schema().epochs();
+        epochs.put(&epoch.number, epoch.clone());
+        metrics::set_gauge("consensus_epoch", epoch.number as i64);
+        self.cache.write().insert(epoch.number, epoch.clone());
+    }
+
+    /// forget the cached epochs, eg: the chain is truncated and the epochs are decided again
+    pub fn clear(&self) {
+        self.cache.write().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::core::test_util::{memory_ledger, sealed_block};
+    use crate::core::verify::truncate_chain;
+    use crate::types::Validator;
+
+    // the hook of the tests elects the addresses from the epoch number
+    struct Numbered(u64);
+
+    impl EpochHook for Numbered {
+        fn elect(&self, _ledger: &Ledger, number: u64, _boundary: Height, _previous: &[Address]) -> Vec<Address> {
+            vec![Address::from(self.0 + number)]
+        }
+    }
+
+    fn epoch_ledger(blocks: Height) -> Arc<RwLock<Ledger>> {
+        let key_pair = Random.generate().unwrap();
+        let mut ledger = memory_ledger(&key_pair);
+        ledger.add_validators(vec![Validator::new(key_pair.address())]);
+        let mut parent = Hash::zero();
+        for height in 0..=blocks {
+            let block = sealed_block(parent, height, &key_pair);
+            parent = block.hash();
+            ledger.add_block(&block);
+        }
+        Arc::new(RwLock::new(ledger))
+    }
+
+    // the decided epochs are read from the store after a restart, the elections never run again
+    #[test]
+    fn t_epoch_restart() {
+        let ledger = epoch_ledger(25);
+        let genesis = ledger.read().get_validators_at(0);
+        let manager = EpochManager::new(ledger.clone(), 10, Box::new(Numbered(100)));
+        assert_eq!(manager.epoch_at(5).validators, genesis);
+        assert_eq!(manager.epoch_at(25).validators, vec![Address::from(102)]);
+        // the epoch beyond the next block isn't final
+        assert_eq!(manager.epoch_at(45).validators, vec![Address::from(104)]);
+        assert_eq!(ledger.read().get_schema().epochs().keys().collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        let restarted = EpochManager::new(ledger.clone(), 10, Box::new(Numbered(200)));
+        assert_eq!(restarted.epoch_at(15).validators, vec![Address::from(101)]);
+        assert_eq!(restarted.epoch_at(25).validators, vec![Address::from(102)]);
+        assert_eq!(restarted.epoch_at(35).validators, vec![Address::from(203)]);
+    }
+
+    #[test]
+    fn t_decided_epoch() {
+        let ledger = epoch_ledger(25);
+        let manager = EpochManager::new(ledger.clone(), 10, Box::new(Numbered(100)));
+        assert_eq!(manager.decided_epoch_at(25).unwrap().validators, vec![Address::from(102)]);
+        // the epoch 3 starts at the height 30, the blocks before it aren't committed
+        assert!(manager.decided_epoch_at(35).is_none());
+        let ledger = epoch_ledger(29);
+        let manager = EpochManager::new(ledger.clone(), 10, Box::new(Numbered(100)));
+        assert_eq!(manager.decided_epoch_at(35).unwrap().validators, vec![Address::from(103)]);
+    }
+
+    // the epochs above the truncated chain are decided again
+    #[test]
+    fn t_epoch_truncate() {
+        let ledger = epoch_ledger(25);
+        let manager = EpochManager::new(ledger.clone(), 10, Box::new(Numbered(100)));
+        assert_eq!(manager.epoch_at(25).validators, vec![Address::from(102)]);
+
+        truncate_chain(&mut ledger.write(), 8).unwrap();
+        manager.clear();
+        let manager = EpochManager { hook: Box::new(Numbered(300)), ..manager };
+        assert_eq!(manager.epoch_at(5).validators, ledger.read().get_validators_at(0));
+        assert_eq!(manager.epoch_at(15).validators, vec![Address::from(301)]);
+        // the epoch 1 starts beyond the next block
+        assert_eq!(ledger.read().get_schema().epochs().keys().collect::<Vec<_>>(), vec![0]);
+    }
+
+    // the pbft sets take effect from the first epoch at or after their height
+    #[test]
+    fn t_static_validators() {
+        let ledger = epoch_ledger(25);
+        let genesis = ledger.read().get_validators_at(0);
+        ledger.write().schedule_validators(12, vec![Validator::new(Address::from(7))]);
+        let manager = EpochManager::new(ledger.clone(), 10, Box::new(StaticValidators));
+        assert_eq!(manager.epoch_at(15).validators, genesis);
+        assert_eq!(manager.epoch_at(25).validators, vec![Address::from(7)]);
+        assert_eq!(manager.epoch_at(0).validators, genesis);
+    }
+
+    #[test]
+    fn t_epoch() {
+        let epoch = Epoch { number: 2, start: epoch_start(2, 100), length: 100, validators: vec![] };
+        assert_eq!((epoch.start, epoch.end()), (200, 299));
+        assert!(epoch.contains(200) && epoch.contains(299) && !epoch.contains(300));
+        assert_eq!(epoch_number(299, 100), 2);
+        assert_eq!(epoch_number(300, 100), 3);
+        assert_eq!(epoch_number(7, 0), 7);
+    }
+}
@@ -64,6 +64,16 @@ pub enum EngineError {
     UnknownAncestor(Height, Height),
     #[fail(display = "Consensus interrupt")]
     Interrupt,
+    #[fail(display = "Proposal policy violation, ({})", _0)]
+    PolicyViolation(String),
+    #[fail(display = "Invalid proposal transactions, ({})", _0)]
+    InvalidTransactions(String),
+    #[fail(display = "Invalid gas, ({})", _0)]
+    InvalidGas(String),
+    #[fail(display = "Invalid header field, ({})", _0)]
+    InvalidHeaderField(String),
+    #[fail(display = "Oversized proposal, ({})", _0)]
+    OversizedBlock(String),
     #[fail(display = "An unknown error has occurred, ({})", _0)]
     Unknown(String),
 }
@@ -49,8 +49,11 @@ impl Message for MessageEvent {
     type Result = ConsensusResult;
 }
 
+/// the local proposal of the height is committed
 #[derive(Debug, Message)]
-pub struct FinalCommittedEvent {}
+pub struct FinalCommittedEvent {
+    pub height: Height,
+}
 
 #[derive(Debug, Message)]
 pub struct TimerEvent {}
@@ -7,3 +7,9 @@ pub mod backend;
 pub mod engine;
 pub mod error;
 pub mod pbft;
+pub mod dpos;
+pub mod epoch;
+pub mod policy;
+pub mod sign_guard;
+pub mod validate;
+pub mod simulation;
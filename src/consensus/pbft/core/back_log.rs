@@ -8,14 +8,98 @@ use serde::{Deserialize, Serialize};
 
 use std::borrow::Cow;
 use std::io::Cursor;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::time::Duration;
 
 use crate::protocol::{GossipMessage, MessageType, to_priority};
-use crate::consensus::types::{View, Subject, PrePrepare};
+use crate::consensus::types::{View, Subject, PrePrepare, Round};
 use crate::consensus::validator::ImplValidatorSet;
+use crate::types::Height;
+use crate::metrics;
 use super::core::Core;
 
+// the max messages buffered for the future heights
+pub const MAX_FUTURE_BACKLOG_SIZE: usize = 1 << 10;
+// the messages higher than `current height + MAX_FUTURE_HEIGHT_DISTANCE` are discarded
+pub const MAX_FUTURE_HEIGHT_DISTANCE: Height = 16;
+
+/// the view carried by the message payload
+pub fn message_view(msg: &GossipMessage) -> View {
+    match &msg.code {
+        MessageType::Preprepare => {
+            let preprepare: PrePrepare = PrePrepare::from_bytes(Cow::from(msg.msg()));
+            preprepare.view
+        }
+        _other_type => {
+            let subject: Subject = Subject::from_bytes(Cow::from(msg.msg()));
+            subject.view
+        }
+    }
+}
+
+/// FutureBacklog buffers the messages of future heights, keyed by (height, round). They are
+/// replayed when the node advances to the height. If it is full, the farthest messages are
+/// discarded first.
+pub struct FutureBacklog {
+    messages: BTreeMap<(Height, Round), Vec<GossipMessage>>,
+    len: usize,
+    capacity: usize,
+}
+
+impl FutureBacklog {
+    pub fn new(capacity: usize) -> Self {
+        FutureBacklog {
+            messages: BTreeMap::new(),
+            len: 0,
+            capacity: ::std::cmp::max(capacity, 1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// returns false if the message is discarded
+    pub fn push(&mut self, view: View, msg: GossipMessage) -> bool {
+        let key = (view.height, view.round);
+        if self.len >= self.capacity {
+            let farthest = *self.messages.keys().next_back().unwrap();
+            if farthest <= key {
+                metrics::inc_counter("consensus_backlog_discarded_total");
+                return false;
+            }
+            self.discard(farthest);
+        }
+        self.messages.entry(key).or_insert_with(Vec::new).push(msg);
+        self.len += 1;
+        metrics::inc_counter("consensus_backlog_buffered_total");
+        metrics::set_gauge("consensus_backlog_size", self.len as i64);
+        true
+    }
+
+    /// take the messages of the height order by round, the lower heights are discarded
+    pub fn pop_height(&mut self, height: Height) -> Vec<GossipMessage> {
+        let stale: Vec<(Height, Round)> = self.messages.range(..(height, 0)).map(|(key, _)| *key).collect();
+        stale.into_iter().for_each(|key| self.discard(key));
+        let ready: Vec<(Height, Round)> = self.messages.range((height, 0)..(height + 1, 0)).map(|(key, _)| *key).collect();
+        let mut messages = vec![];
+        for key in ready {
+            messages.extend(self.messages.remove(&key).unwrap());
+        }
+        self.len -= messages.len();
+        metrics::REGISTRY.inc_counter("consensus_backlog_replayed_total", messages.len() as u64);
+        metrics::set_gauge("consensus_backlog_size", self.len as i64);
+        messages
+    }
+
+    fn discard(&mut self, key: (Height, Round)) {
+        if let Some(messages) = self.messages.remove(&key) {
+            self.len -= messages.len();
+            metrics::REGISTRY.inc_counter("consensus_backlog_discarded_total", messages.len() as u64);
+        }
+    }
+}
+
 pub struct BackLogActor {
     qp: HashMap<Address, PriorityQueue<GossipMessage, i64>>,
     core: Addr<Core>,
@@ -74,19 +158,39 @@ impl BackLogActor {
         ctx.run_interval(Duration::from_millis(100), |act, _ctx| {
             for (_key, value) in act.qp.iter_mut() {
                 for (message, _) in value.iter_mut() {
-                    let view;
-                    match &message.code {
-                        MessageType::Preprepare => {
-                            let preprepare: PrePrepare = PrePrepare::from_bytes(Cow::from(message.msg()));
-                            view = preprepare.view;
-                        }
-                        _other_type => {
-                            let subject: Subject = Subject::from_bytes(Cow::from(message.msg()));
-                            view = subject.view;
-                        }
-                    }
+                    let _view = message_view(message);
                 }
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(round: Round) -> GossipMessage {
+        GossipMessage::new(MessageType::Commit, vec![round as u8], None)
+    }
+
+    #[test]
+    fn t_future_backlog() {
+        let mut backlog = FutureBacklog::new(3);
+        assert!(backlog.push(View::new(3, 1), message(1)));
+        assert!(backlog.push(View::new(2, 0), message(0)));
+        assert!(backlog.push(View::new(3, 0), message(0)));
+        // full, the farther message is discarded
+        assert!(!backlog.push(View::new(4, 0), message(0)));
+        // full, the nearer message evicts the farthest
+        assert!(backlog.push(View::new(1, 0), message(0)));
+        assert_eq!(backlog.len(), 3);
+
+        // height 1 is stale
+        let messages = backlog.pop_height(2);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(backlog.len(), 1);
+        let messages = backlog.pop_height(3);
+        assert_eq!(messages.iter().map(|msg| msg.msg().to_vec()).collect::<Vec<_>>(), vec![vec![0]]);
+        assert_eq!(backlog.len(), 0);
+    }
+}
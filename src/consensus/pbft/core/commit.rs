@@ -1,24 +1,21 @@
 use cryptocurrency_kit::crypto::Hash;
-use cryptocurrency_kit::ethkey::{
-    public_to_address, recover, verify_address, Address, Message as SignMessage, Signature,
-};
+use cryptocurrency_kit::ethkey::{public_to_address, recover, Address, Signature};
 use cryptocurrency_kit::storage::values::StorageValue;
 
 use super::core::Core;
 use crate::{
     consensus::error::{ConsensusError, ConsensusResult},
+    consensus::sign_guard::SignStep,
     consensus::types::{Subject, View},
     consensus::validator::ValidatorSet,
     protocol::{GossipMessage, MessageType, State},
     types::{
-        votes::{decrypt_commit_bytes, encrypt_commit_bytes, Votes},
+        votes::{commit_seal_digest, decrypt_commit_bytes, recover_commit_seal, Votes},
         Validator,
     },
 };
 
 use std::borrow::Cow;
-use ethereum_types::H256;
-use cryptocurrency_kit::common::to_fixed_array_32;
 
 pub trait HandleCommit {
     fn send_commit(&mut self);
@@ -55,7 +52,20 @@ impl HandleCommit for Core {
     // TOOD
     fn broadcast_commit(&mut self, subject: &Subject, _digest: Hash) {
         trace!("broadcast commit");
-        let commit_seal = encrypt_commit_bytes(&subject.digest, self.keypair.secret());
+        let vote = match self.sign_guard.lock().check(&subject.view, SignStep::Commit, subject.digest) {
+            Ok(vote) => vote,
+            Err(err) => {
+                warn!("Refuse to sign the commit seal, err: {}", err);
+                return;
+            }
+        };
+        let commit_seal = match self.signer.sign_vote(&commit_seal_digest(&subject.digest), &vote) {
+            Ok(commit_seal) => commit_seal,
+            Err(err) => {
+                error!("Failed to sign the commit seal, height: {}, err: {}", subject.view.height, err);
+                return;
+            }
+        };
         let encoded_subject = subject.clone().into_bytes();
         let msg = GossipMessage::new(MessageType::Commit, encoded_subject, Some(commit_seal));
         self.broadcast(&msg);
@@ -99,13 +109,12 @@ impl HandleCommit for Core {
             return Err(ConsensusError::Unknown("commit seal is nil".to_string()));
         }
         let commit_seal = commit_seal.unwrap();
-        let digest = H256::from(to_fixed_array_32(commit_subject.digest.as_ref()));
-        let sign_message = SignMessage::from(digest);
-        verify_address(&sender, commit_seal, &sign_message)
-            .map(|_| ())
-            .map_err(|_| {
-                ConsensusError::Unknown("message's sender should be commit seal".to_string())
-            })?;
+        match recover_commit_seal(&commit_subject.digest, commit_seal) {
+            Ok(ref signer) if *signer == sender => {}
+            _ => {
+                return Err(ConsensusError::Unknown("message's sender should be commit seal".to_string()));
+            }
+        }
         let current_state = &self.current_state;
         let current_subject = current_state.subject().unwrap();
         if current_subject.digest != commit_subject.digest
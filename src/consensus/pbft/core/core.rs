@@ -2,7 +2,7 @@ use ::actix::prelude::*;
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
 use cryptocurrency_kit::ethkey::Address;
-use cryptocurrency_kit::ethkey::{KeyPair, Signature};
+use cryptocurrency_kit::ethkey::Signature;
 use serde::{Deserialize, Serialize};
 use futures::Future;
 use tokio::timer::Delay;
@@ -26,34 +26,69 @@ use super::{
     round_change::HandleRoundChange,
     round_change_set::RoundChangeSet,
     round_state::RoundState,
-    timer::{Op, Timer},
-    back_log::BackLogActor,
+    timer::{ActorTimer, CoreTimer},
+    back_log::{message_view, BackLogActor, FutureBacklog, MAX_FUTURE_BACKLOG_SIZE, MAX_FUTURE_HEIGHT_DISTANCE},
 };
 use crate::{
+    config::ConsensusTimeouts,
     core::chain::Chain,
     consensus::validator::fn_selector,
     consensus::backend::{Backend, ImplBackend},
     consensus::config::Config,
     consensus::error::{ConsensusError, ConsensusResult},
+    consensus::sign_guard::{message_vote, SafeSignGuard, SignStep},
     consensus::events::{OpCMD, MessageEvent, NewHeaderEvent, FinalCommittedEvent, BackLogEvent, TimerEvent},
-    consensus::types::{Proposal, Request as CSRequest, Round, Subject, View},
-    consensus::validator::{ImplValidatorSet, ValidatorSet, Validators},
+    consensus::types::{CommitCertificate, Proposal, Request as CSRequest, Round, Subject, View},
+    consensus::validator::{ImplValidatorSet, ValidatorSet},
+    core::import::{ImportPipeline, IMPORT_QUEUE_SIZE},
     p2p::server::HandleMsgFn,
     p2p::protocol::{RawMessage, P2PMsgCode, Payload},
-    protocol::{GossipMessage, MessageType, State},
+    p2p::serve::SyncReply,
+    signer::SafeSigner,
+    protocol::{GossipMessage, GossipMessages, MessageType, State},
     types::Validator,
     types::block::{Block, Blocks},
+    types::votes::Votes,
     types::Height,
     subscriber::events::ChainEvent,
+    util::request::{send_timeout, DEFAULT_REQUEST_TIMEOUT},
+    util::supervise::{guard, record_restart},
+    metrics,
 };
 
-pub fn handle_msg_middle(core_pid: Addr<Core>, chain: Arc<Chain>) -> impl Fn(PeerId, RawMessage) -> Result<(), String> {
+/// recover the sender of the consensus payload, the message which isn't signed by a validator at
+/// its height is rejected before it reaches the core
+pub fn authorize_consensus_message<F>(payload: &[u8], validators_at: F) -> Result<Address, String>
+    where F: Fn(Height) -> Vec<Address> {
+    let mut msg: GossipMessage = GossipMessage::from_bytes(Cow::from(payload));
+    let address = msg.address()?;
+    let view = message_view(&msg);
+    if !validators_at(view.height).contains(&address) {
+        return Err(format!("the sender is not a validator, address: {:?}, height: {}", address, view.height));
+    }
+    Ok(address)
+}
+
+pub fn handle_msg_middle(core_pid: Option<Addr<Core>>, chain: Arc<Chain>) -> impl Fn(PeerId, RawMessage) -> Result<(), String> {
+    let importer = ImportPipeline::new(chain.clone(), IMPORT_QUEUE_SIZE);
     move |peer_id: PeerId, msg: RawMessage| {
         let header = msg.header();
         let payload = msg.payload().to_vec();
         match header.code {
             P2PMsgCode::Consensus => {
-                let request = core_pid.send(MessageEvent { payload: payload });
+                let core_pid = match core_pid {
+                    Some(ref core_pid) => core_pid,
+                    None => {
+                        debug!("Drop consensus message, the engine has no vote round, peer: {}", peer_id.to_base58());
+                        return Ok(());
+                    }
+                };
+                if let Err(err) = authorize_consensus_message(&payload, |height| chain.get_validator_addresses(height)) {
+                    metrics::inc_counter("consensus_unauthorized_message_total");
+                    warn!("Drop consensus message, peer: {}, err: {}", peer_id.to_base58(), err);
+                    return Err(err);
+                }
+                let request = send_timeout(core_pid, MessageEvent { payload: payload }, DEFAULT_REQUEST_TIMEOUT);
                 let chain = chain.clone();
                 Arbiter::spawn(request.and_then(move |result| {
                     if let Err(err) = result {
@@ -70,42 +105,27 @@ pub fn handle_msg_middle(core_pid: Addr<Core>, chain: Arc<Chain>) -> impl Fn(Pee
                         debug!("Failed to handle message, err:{:?}", err);
                     }
                     futures::future::ok(())
-                }).map_err(|err| panic!(err)));
+                }).map_err(|err| warn!("Failed to send message to core, err: {}", err)));
             }
             P2PMsgCode::Block => {
-                let blocks: Blocks = Blocks::from_bytes(Cow::from(&payload));
+                let blocks: Blocks = Blocks::from_rlp(&payload).map_err(|err| format!("{:?}", err))?;
                 debug!("Receive a batch block from network, size:{:?}", blocks.0.len());
-                // TODO FIXME
-                blocks.0.iter().for_each(|block| {
-                    chain.insert_block(&block);
-                });
+                if let Err(err) = importer.submit(&peer_id.to_base58(), blocks) {
+                    warn!("Drop the synced blocks, peer: {}, err: {}", peer_id.to_base58(), err);
+                }
             }
-            P2PMsgCode::Sync => {
-                let height = Height::from_bytes(Cow::from(&payload));
-                debug!("Receive a new sync event from network, height: {:?}", height);
-
-                let last_height = chain.get_last_height();
-                let mut total = 0;
-                let mut batch = 0;
-                let mut blocks = Blocks(vec![]);
-                for height in (height..last_height + 1) {
-                    if let Some(block) = chain.get_block_by_height(height) {
-                        blocks.0.push(block);
-                    }
-                    if batch > 20 {
-                        chain.post_event(ChainEvent::PostBlock(Some(peer_id.clone()), blocks.clone()));
-                        batch = 0;
-                        // FIXME
-                        blocks.0.clear();
-                    }
-                    if total > 100 {
-                        break;
-                    }
-                    batch += 1;
-                    total += 1;
+            // the sync requests are served by the p2p server from the store, the records are kept by it
+            P2PMsgCode::Sync | P2PMsgCode::Records => {}
+            P2PMsgCode::SyncReply => {
+                let reply: SyncReply = SyncReply::from_bytes(Cow::from(&payload));
+                debug!("Receive the synced blocks from network, size: {}, certificates: {}", reply.blocks.len(), reply.certificates.len());
+                if let Err(err) = reply.verify_certificates(|height| chain.get_validator_addresses(height)) {
+                    metrics::inc_counter("chain_import_invalid_block_total");
+                    warn!("Drop the synced blocks with the invalid certificates, peer: {}, err: {}", peer_id.to_base58(), err);
+                    return Err(err);
                 }
-                if blocks.0.len() > 0 {
-                    chain.post_event(ChainEvent::PostBlock(Some(peer_id.clone()), blocks));
+                if let Err(err) = importer.submit(&peer_id.to_base58(), Blocks(reply.blocks)) {
+                    warn!("Drop the synced blocks, peer: {}, err: {}", peer_id.to_base58(), err);
                 }
             }
             _ => unimplemented!()
@@ -116,12 +136,48 @@ pub fn handle_msg_middle(core_pid: Addr<Core>, chain: Arc<Chain>) -> impl Fn(Pee
 }
 
 
+pub const CONSENSUS_PHASES: [&str; 3] = ["preprepare", "prepare", "commit"];
+// the backoff never grows a round timeout beyond it
+pub const MAX_ROUND_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// the timeout of the phase which waits to leave `state`, it's multiplied by the backoff for every
+/// failed round of the height
+pub fn round_timeout(timeouts: &ConsensusTimeouts, state: &State, round: Round) -> Duration {
+    let timeout = match state {
+        State::AcceptRequest => timeouts.timeout_propose,
+        State::PrePrepared => timeouts.timeout_prepare,
+        State::Prepared | State::Committed => timeouts.timeout_commit,
+    };
+    let backoff = timeouts.timeout_backoff.max(1.0).powf(round as f64);
+    let millis = timeout.as_millis() as f64 * backoff;
+    if millis >= MAX_ROUND_TIMEOUT.as_millis() as f64 {
+        return MAX_ROUND_TIMEOUT;
+    }
+    Duration::from_millis(millis as u64)
+}
+
+/// the metric name of the phase which finishes by leaving `state`
+pub fn phase_metric(state: &State) -> String {
+    let phase = match state {
+        State::AcceptRequest => CONSENSUS_PHASES[0],
+        State::PrePrepared => CONSENSUS_PHASES[1],
+        State::Prepared | State::Committed => CONSENSUS_PHASES[2],
+    };
+    phase_metric_name(phase)
+}
+
+pub fn phase_metric_name(phase: &str) -> String {
+    format!("consensus_phase_duration_ms{{phase=\"{}\"}}", phase)
+}
+
 pub struct Core {
     pid: Addr<Core>,
     pub config: Config,
 
     address: Address,
-    pub keypair: KeyPair,
+    pub signer: SafeSigner,
+    // the high-water mark of the signed steps, it survives the restarts
+    pub sign_guard: SafeSignGuard,
     pub state: State,
 
     validators: ImplValidatorSet,
@@ -130,18 +186,28 @@ pub struct Core {
     pub round_change_set: RoundChangeSet<ImplValidatorSet>, // store round change messages
 
     pub wait_round_change: bool,
-    future_prepprepare_timer: Addr<Timer>,
-    round_change_timer: Addr<Timer>,
+    timer: Box<CoreTimer>,
+    timeouts: ConsensusTimeouts,
     pub consensus_timestamp: Duration,
 
     backlog_store: Addr<BackLogActor>,
+    // the messages of future heights
+    future_backlog: FutureBacklog,
     pub backend: Box<Backend<ValidatorsType=ImplValidatorSet>>,
-    pub round_change_limiter: Instant,
+    // the time of the last round change message
+    pub round_change_limiter: Duration,
+    // the time when entering the current state
+    pub state_timestamp: Instant,
+    // it's stopped by the engine, the supervisor keeps it idle
+    shutdown: bool,
 }
 
 impl Actor for Core {
     type Context = Context<Self>;
     fn started(&mut self, _ctx: &mut Self::Context) {
+        if self.shutdown {
+            return;
+        }
         info!("core actor has started");
         self.start_new_zero_round();
     }
@@ -151,32 +217,58 @@ impl Actor for Core {
     }
 }
 
+// the state of the failed round is dropped, `started` recovers the round from the ledger
+impl Supervised for Core {
+    fn restarting(&mut self, _ctx: &mut Self::Context) {
+        if self.shutdown {
+            return;
+        }
+        record_restart("core");
+        self.stop_timer();
+        self.wait_round_change = false;
+        self.future_backlog = FutureBacklog::new(MAX_FUTURE_BACKLOG_SIZE);
+        self.backend.cancel_inflight();
+    }
+}
+
 impl Handler<NewHeaderEvent> for Core {
     type Result = ConsensusResult;
 
-    fn handle(&mut self, msg: NewHeaderEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: NewHeaderEvent, ctx: &mut Self::Context) -> Self::Result {
         debug!("Receive a new header event");
+        if self.shutdown {
+            return Err(ConsensusError::Ignored);
+        }
         let proposal = msg.proposal.clone();
-        self.start_new_zero_round();
-        <Core as HandlerRequest>::handle(self, &CSRequest::new(proposal))
+        guard("core", ctx, || {
+            self.start_new_zero_round();
+            <Core as HandlerRequest>::handle(self, &CSRequest::new(proposal))
+        }).unwrap_or(Err(ConsensusError::Unknown("core panicked".to_string())))
     }
 }
 
 impl Handler<FinalCommittedEvent> for Core {
     type Result = ();
 
-    fn handle(&mut self, _msg: FinalCommittedEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: FinalCommittedEvent, _ctx: &mut Self::Context) -> Self::Result {
+        // the core has started the next height already, its timer is kept
+        if msg.height < self.current_state.height() {
+            return;
+        }
         self.stop_timer();
         self.wait_round_change = false;
-        ()
     }
 }
 
 impl Handler<MessageEvent> for Core {
     type Result = ConsensusResult;
 
-    fn handle(&mut self, msg: MessageEvent, _ctx: &mut Self::Context) -> Self::Result {
-        let result = self.handle_message(&msg.payload);
+    fn handle(&mut self, msg: MessageEvent, ctx: &mut Self::Context) -> Self::Result {
+        if self.shutdown {
+            return Err(ConsensusError::Ignored);
+        }
+        let result = guard("core", ctx, || self.handle_message(&msg.payload))
+            .unwrap_or(Err(ConsensusError::Unknown("core panicked".to_string())));
         if let Err(ref err) = result {
             match err {
                 e @ ConsensusError::FutureBlockMessage(_) => {
@@ -197,30 +289,33 @@ impl Handler<MessageEvent> for Core {
 impl Handler<BackLogEvent> for Core {
     type Result = ConsensusResult;
 
-    fn handle(&mut self, msg: BackLogEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: BackLogEvent, ctx: &mut Self::Context) -> Self::Result {
         let msg = msg.msg;
         let src = Validator::new(msg.address);
-        self.handle_check_message(&msg, &src)
+        guard("core", ctx, || self.handle_check_message(&msg, &src))
+            .unwrap_or(Err(ConsensusError::Unknown("core panicked".to_string())))
     }
 }
 
 impl Handler<TimerEvent> for Core {
     type Result = ();
 
-    fn handle(&mut self, _msg: TimerEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, _msg: TimerEvent, ctx: &mut Self::Context) -> Self::Result {
         debug!("Receive timer event");
-        let last_proposal = self.backend.last_proposal().unwrap();
-        let last_block = last_proposal.block();
-        let cur_view = self.current_view();
-        if last_block.height() >= cur_view.height {
-            debug!("Round change timeout, catch up latest height");
-            self.stop_timer();
-            self.wait_round_change = false;
-        } else {
-            // send new round message
-            self.send_next_round_change();
-        }
-        ()
+        guard("core", ctx, || {
+            let last_proposal = self.backend.last_proposal().unwrap();
+            let last_block = last_proposal.block();
+            let cur_view = self.current_view();
+            metrics::inc_counter("consensus_round_timeout_total");
+            if last_block.height() >= cur_view.height {
+                debug!("Round change timeout, catch up latest height");
+                self.stop_timer();
+                self.wait_round_change = false;
+            } else {
+                // send new round message
+                self.send_next_round_change();
+            }
+        });
     }
 }
 
@@ -231,6 +326,7 @@ impl Handler<OpCMD> for Core {
         match msg {
             OpCMD::stop => {
                 self.stop_timer();
+                self.shutdown = true;
                 ctx.stop();
             }
             OpCMD::Ping => {
@@ -243,12 +339,25 @@ impl Handler<OpCMD> for Core {
 }
 
 impl Core {
-    pub fn new(chain: Arc<Chain>, backend: ImplBackend, key_pair: KeyPair) -> Addr<Core> {
-        //    let core_backend: Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync> = Box::new(backend.clone()) as Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync>;
-        let address = key_pair.address();
+    pub fn new(chain: Arc<Chain>, backend: ImplBackend, signer: SafeSigner) -> Addr<Core> {
+        Core::with_backend(chain, backend, signer, |backend| Box::new(backend))
+    }
+
+    /// create a core whose backend is wrapped by `wrap`, eg: the byzantine backend of the mocks
+    pub fn with_backend<F>(chain: Arc<Chain>, backend: ImplBackend, signer: SafeSigner, wrap: F) -> Addr<Core>
+        where F: FnOnce(ImplBackend) -> Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync> + 'static {
+        let request_time = Duration::from_millis(chain.config.request_time.as_millis() as u64);
+        Core::with_parts(chain, backend, signer, wrap, move |pid| Box::new(ActorTimer::new(pid, request_time)))
+    }
+
+    /// create a core with the backend wrapper and the timer, eg: the virtual time timer of the simulation
+    pub fn with_parts<F, T>(chain: Arc<Chain>, backend: ImplBackend, signer: SafeSigner, wrap: F, timer: T) -> Addr<Core>
+        where F: FnOnce(ImplBackend) -> Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync> + 'static,
+              T: FnOnce(Addr<Core>) -> Box<CoreTimer> + 'static {
+        let address = signer.address();
         let last_block = chain.get_last_block();
-        let validators = chain.get_validators(last_block.height());
-        let addresses: Vec<Address> = validators.iter().map(|validator| *validator.address()).collect();
+        // the validators of the epoch of the next height
+        let addresses = chain.get_validator_addresses(last_block.height() + 1);
         let validators = ImplValidatorSet::new(&addresses, Box::new(fn_selector));
 
         let last_view = View::new(last_block.height(), 0);
@@ -260,29 +369,30 @@ impl Core {
                                                         None);
         let round_change_set = RoundChangeSet::new(validators.clone(), None);
 
-        let request_time = Duration::from_millis(chain.config.request_time.as_millis() as u64);
-        let f_request_time = request_time.clone();
-        let r_request_time = request_time.clone();
         let config = Config {
             request_time: chain.config.request_time.as_millis() as u64,
             block_period: chain.config.block_period.as_secs(),
-            chain_id: 0,
+            chain_id: chain.config.chain_id,
+            max_clock_drift: chain.config.clock.max_drift.as_secs(),
+            size_limits: chain.config.size_limits(),
         };
+        let timeouts = chain.config.consensus.clone();
+        let sign_guard = backend.sign_guard();
 
-        Core::create(move |ctx| {
+        Supervisor::start(move |ctx| {
             let core_pid = ctx.address().clone();
             let address = address.clone();
-            let (f_core_pid, r_core_pid) = (core_pid.clone(), core_pid.clone());
             let b_core_pid = core_pid.clone();
             let mut backend = backend.clone();
             backend.set_core_pid(ctx.address());
-            let core_backend: Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync> = Box::new(backend.clone()) as Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync>;
+            let core_backend = wrap(backend.clone());
 
             Core {
                 pid: ctx.address(),
                 config: config,
                 address: address,
-                keypair: key_pair,
+                signer: signer,
+                sign_guard: sign_guard,
                 state: State::AcceptRequest,
                 validators: validators,
 
@@ -290,12 +400,8 @@ impl Core {
                 round_change_set: round_change_set,
                 wait_round_change: false,
 
-                future_prepprepare_timer: Timer::create(move |_| {
-                    Timer::new("future".to_owned(), f_request_time, f_core_pid, None)
-                }),
-                round_change_timer: Timer::create(move |_| {
-                    Timer::new("round change".to_owned(), r_request_time, r_core_pid, None)
-                }),
+                timer: timer(core_pid),
+                timeouts: timeouts,
 
                 consensus_timestamp: Duration::from_secs(0),
 
@@ -304,8 +410,11 @@ impl Core {
                 backlog_store: BackLogActor::create(move |_| {
                     BackLogActor::new(b_core_pid)
                 }),
+                future_backlog: FutureBacklog::new(MAX_FUTURE_BACKLOG_SIZE),
 
-                round_change_limiter: Instant::now(),
+                round_change_limiter: Duration::from_secs(0),
+                state_timestamp: Instant::now(),
+                shutdown: false,
             }
         })
     }
@@ -335,6 +444,11 @@ impl Core {
 
     pub fn handle_check_message(&mut self, msg: &GossipMessage, src: &Validator) -> ConsensusResult {
         debug!("Handle check message, {}", msg.trace());
+        let view = message_view(msg);
+        if view.height > self.current_state.height() {
+            self.buffer_future_message(view, msg);
+            return Err(ConsensusError::FutureBlockMessage(view.height));
+        }
         let result = match msg.code {
             MessageType::Preprepare => {
                 <Core as HandlePreprepare>::handle(self, msg, src)
@@ -361,6 +475,28 @@ impl Core {
         result
     }
 
+    // keep the message until the node advances to its height
+    fn buffer_future_message(&mut self, view: View, msg: &GossipMessage) {
+        if view.height > self.current_state.height() + MAX_FUTURE_HEIGHT_DISTANCE {
+            metrics::inc_counter("consensus_backlog_discarded_total");
+            return;
+        }
+        if !self.future_backlog.push(view, msg.clone()) {
+            debug!("Future backlog is full, discard message, {}", msg.trace());
+        }
+    }
+
+    // replay the buffered messages of the current height
+    fn replay_future_messages(&mut self) {
+        let messages = self.future_backlog.pop_height(self.current_state.height());
+        if !messages.is_empty() {
+            debug!("Replay {} future messages, height: {}", messages.len(), self.current_state.height());
+        }
+        for msg in messages {
+            self.pid.do_send(BackLogEvent { msg });
+        }
+    }
+
     /// need to check：height，round，State
     /// if at waitting for change，should handle receive to fast consensus
     pub fn check_message(&self, code: MessageType, view: &View) -> Result<(), ConsensusError> {
@@ -402,14 +538,23 @@ impl Core {
         self.set_state(State::Committed);
         let mut committed_seals = Vec::with_capacity(self.current_state.commits.len());
         self.current_state.commits.values().iter().for_each(|v| {
-            committed_seals.push(v.signature.as_ref().unwrap().clone());
+            committed_seals.push(v.commit_seal.as_ref().unwrap().clone());
         });
         let has_more_than_maj23 =
             self.validators.two_thirds_majority() + 1 <= committed_seals.len();
         assert!(has_more_than_maj23);
+        let digest = self.current_state.proposal().unwrap().block().hash();
+        let validators = &self.validators;
+        if !Votes::new(committed_seals.clone()).verify_commit_seals(&digest, |address| validators.get_by_address(address).is_some()) {
+            error!("Failed to verify commit seals, hash: {}", digest.short());
+            return;
+        }
+        let mut messages = self.current_state.prepares.values();
+        messages.extend(self.current_state.commits.values());
         // TODO commit
         let mut proposal = self.current_state.proposal().unwrap().clone();
-        if let Err(err) = self.backend.commit(&mut proposal, committed_seals) {
+        let certificate = CommitCertificate::new(self.current_view(), digest, committed_seals);
+        if let Err(err) = self.backend.commit(&mut proposal, certificate, GossipMessages(messages)) {
             error!("Failed to commit block");
         }
 
@@ -421,15 +566,19 @@ impl Core {
     }
 
     // TODO do more things
-    pub fn finalize_message(&self, msg: &mut GossipMessage) -> Result<(), String> {
+    pub fn finalize_message(&mut self, msg: &mut GossipMessage) -> Result<(), String> {
         msg.address = self.address.clone();
-        msg.set_sign(&self.keypair.secret());
-        Ok(())
+        let (view, digest) = message_vote(msg);
+        let vote = self.sign_guard.lock().check(&view, SignStep::from(&msg.code), digest)?;
+        msg.sign_with(self.signer.as_ref(), &vote)
     }
 
     pub fn broadcast(&mut self, msg: &GossipMessage) {
         let mut copy_msg = msg.clone();
-        self.finalize_message(&mut copy_msg).unwrap();
+        if let Err(err) = self.finalize_message(&mut copy_msg) {
+            error!("Failed to sign the message, type: {:?}, err: {}", msg.code, err);
+            return;
+        }
         if let Err(err) = self.backend.gossip(&self.validators, copy_msg) {
             error!("Failed to gossip message, err: {:?}", err);
         }
@@ -443,8 +592,8 @@ impl Core {
         let last_height = last_proposal.block().height();
         // TODO 增加判断，last_proposal == blockend.proposal_hash
         let new_view: View = View::new(last_height + 1, 0);
-        // TODO 从backend 获取 backend.validator_set
-        self.validators = self.backend.validators(last_height + 1).clone();
+        // the validator set changes at the epoch boundaries
+        self.validators = self.backend.validators(last_height + 1);
         self.round_change_set = RoundChangeSet::new(self.validators.clone(), None);
         assert_ne!(
             self.validators.size(),
@@ -465,6 +614,7 @@ impl Core {
         self.set_state(State::AcceptRequest);
         // reset new round change timer
         self.new_round_change_timer();
+        self.replay_future_messages();
         debug!("after start zero round");
     }
 
@@ -514,6 +664,8 @@ impl Core {
         // TODO prove tree
         self.round_change_set = RoundChangeSet::new(self.validators.clone(), None);
 
+        // the proposal and the queued messages of the old round are superseded
+        self.backend.cancel_inflight();
         // New snapshot for new round
         self.update_round_state(new_view, self.validators.clone(), true);
         // calc new proposer
@@ -601,13 +753,32 @@ impl Core {
 
     pub fn set_state(&mut self, new_state: State) {
         trace!("state change, from {:?} to {:?}", self.state, new_state);
+        // only the forward transition finishes a phase
+        if new_state > self.state {
+            let elapsed = self.state_timestamp.elapsed();
+            let millis = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_micros() as f64 / 1000.0;
+            metrics::observe(&phase_metric(&self.state), millis);
+        }
+        if new_state != self.state {
+            self.state_timestamp = Instant::now();
+        }
+        let forward = new_state > self.state;
         self.state = new_state;
+        // every phase has its own timeout, the commit waits for the next round
+        if forward && (self.state == State::PrePrepared || self.state == State::Prepared) && !self.wait_round_change {
+            self.new_round_change_timer();
+        }
     }
 
     pub fn address(&self) -> Address {
         self.address
     }
 
+    /// the time of the core timer, it's virtual in the simulation
+    pub fn now(&self) -> Duration {
+        self.timer.now()
+    }
+
     pub fn is_proposer(&self) -> bool {
         self.validators.is_proposer(self.backend.address())
     }
@@ -626,11 +797,11 @@ impl Core {
 
     pub fn stop_future_preprepare_timer(&mut self) {
         // stop old timer
-        self.future_prepprepare_timer.try_send(Op::Stop);
+        self.timer.stop_future_preprepare();
     }
 
     pub fn stop_round_change_timer(&mut self) {
-        self.round_change_timer.try_send(Op::Stop);
+        self.timer.stop_round_change();
         trace!("stop round change timer");
     }
 
@@ -640,26 +811,50 @@ impl Core {
     }
 
     pub fn new_round_change_timer(&mut self) {
-        trace!("start new round timer");
-        // stop old timer
-        self.round_change_timer.try_send(Op::Stop);
-        // start new timer
-        let pid = self.pid.clone();
-        self.round_change_timer = Timer::create(move |_| {
-            Timer::new(
-                "round change".to_string(),
-                Duration::from_millis(3 * 1000),
-                pid,
-                None,
-            )
-        })
+        let timeout = round_timeout(&self.timeouts, &self.state, self.current_state.round());
+        trace!("start new round timer, timeout: {:?}", timeout);
+        self.timer.start_round_change(timeout);
     }
 
     pub fn new_round_future_preprepare_timer(&mut self, duraton: Duration, msg: GossipMessage) {
         trace!("stop future preprepare timer");
-        self.stop_future_preprepare_timer();
-        let pid = self.pid.clone();
-        self.future_prepprepare_timer =
-            Timer::create(move |_| Timer::new("future preprepare".to_string(), duraton, pid, Some(msg)));
+        self.timer.start_future_preprepare(duraton, msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+
+    #[test]
+    fn t_round_timeout() {
+        let mut timeouts = ConsensusTimeouts::default();
+        timeouts.timeout_propose = Duration::from_millis(1000);
+        timeouts.timeout_commit = Duration::from_millis(500);
+        assert_eq!(round_timeout(&timeouts, &State::AcceptRequest, 0), Duration::from_millis(1000));
+        assert_eq!(round_timeout(&timeouts, &State::Prepared, 0), Duration::from_millis(500));
+        // the fixed timeouts by default
+        assert_eq!(round_timeout(&timeouts, &State::AcceptRequest, 3), Duration::from_millis(1000));
+        timeouts.timeout_backoff = 2.0;
+        assert_eq!(round_timeout(&timeouts, &State::AcceptRequest, 3), Duration::from_millis(8000));
+        assert_eq!(round_timeout(&timeouts, &State::AcceptRequest, 64), MAX_ROUND_TIMEOUT);
+    }
+
+    #[test]
+    fn t_authorize_consensus_message() {
+        let key_pair = Random.generate().unwrap();
+        let subject = Subject { view: View { round: 0, height: 3 }, digest: Hash::zero() };
+        let mut msg = GossipMessage::new(MessageType::Prepare, subject.into_bytes(), None);
+        msg.set_sign(key_pair.secret());
+        let payload = msg.into_payload();
+
+        let address = authorize_consensus_message(&payload, |height| {
+            assert_eq!(height, 3);
+            vec![key_pair.address()]
+        });
+        assert_eq!(address, Ok(key_pair.address()));
+        let stranger = Random.generate().unwrap();
+        assert!(authorize_consensus_message(&payload, |_| vec![stranger.address()]).is_err());
     }
 }
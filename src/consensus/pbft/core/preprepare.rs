@@ -9,6 +9,7 @@ use crate::{
     consensus::types::{PrePrepare, Proposal, Request, Subject},
     consensus::validator::Validators,
     consensus::validator::ValidatorSet,
+    metrics,
     protocol::{GossipMessage, MessageType, State},
     types::Validator,
 };
@@ -63,7 +64,7 @@ impl HandlePreprepare for Core {
                         return Err(ConsensusError::Engine(EngineError::InvalidProposal));
                     }
                     let pre_height = block.height() - 1;
-                    let mut val_set = self.backend.validators(pre_height).clone();
+                    let mut val_set = self.backend.validators(pre_height);
                     let _previous_proposer = self.backend.get_proposer(pre_height);
                     val_set.calc_proposer(&block.header().prev_hash, pre_height, preprepare.view.round);
                     if val_set.is_proposer(src.address().clone())
@@ -85,6 +86,14 @@ impl HandlePreprepare for Core {
             return Err(ConsensusError::NotFromProposer);
         }
 
+        // the oversized proposal is refused before the expensive verification
+        if let Err(err) = self.config.size_limits.check_block(preprepare.proposal.block()) {
+            metrics::inc_counter("consensus_oversized_proposal_total");
+            warn!("Refuse the oversized proposal, proposer: {:?}, err: {}", src.address(), err);
+            self.send_next_round_change();
+            return Err(ConsensusError::Engine(EngineError::OversizedBlock(err)));
+        }
+
         // TODO
         let (d, result) = self
             .backend
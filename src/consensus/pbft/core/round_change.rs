@@ -1,5 +1,4 @@
 use std::borrow::Cow;
-use std::time::Instant;
 use std::time::Duration;
 
 use cryptocurrency_kit::crypto::EMPTY_HASH;
@@ -36,12 +35,13 @@ impl HandleRoundChange for Core {
     }
 
     fn send_round_change(&mut self, round: Round) {
-        if Instant::now().duration_since(self.round_change_limiter) <= Duration::from_millis(50) {
+        let now = self.now();
+        if now.checked_sub(self.round_change_limiter).map_or(true, |elapsed| elapsed <= Duration::from_millis(50)) {
             debug!("Skip round change sent");
             self.new_round_change_timer();
             return;
         }
-        self.round_change_limiter = Instant::now();
+        self.round_change_limiter = now;
 
         if self.current_view().round < round {
             self.catchup_round(round);
@@ -58,7 +58,7 @@ impl HandleRoundChange for Core {
         };
         debug!("Vote for round change, current:{}, vote: {}", current_view.round, round);
         let mut msg = GossipMessage::new(MessageType::RoundChange, subject.into_bytes(), None);
-        msg.create_time = chrono::Local::now().timestamp_millis() as u64;
+        msg.create_time = now.as_millis() as u64;
         self.broadcast(&msg);
     }
 
@@ -1,7 +1,7 @@
 use ::actix::prelude::*;
 use uuid::Uuid;
 
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::{
     consensus::validator::{ValidatorSet, ImplValidatorSet},
@@ -71,4 +71,60 @@ impl Timer {
     pub fn new_tmp(name: String, interval: Duration) -> Self {
         Timer { uuid: random_uuid(), name, interval, pid: None, msg: None }
     }
-}
\ No newline at end of file
+}
+
+/// CoreTimer is the timers of the core, the round change timer fires a `TimerEvent` and the
+/// future preprepare timer replays its message as a `BackLogEvent`. Starting a timer replaces
+/// the running one.
+pub trait CoreTimer {
+    /// the time since the unix epoch, the simulation returns the virtual time
+    fn now(&self) -> Duration;
+    fn start_round_change(&mut self, timeout: Duration);
+    fn stop_round_change(&mut self);
+    fn start_future_preprepare(&mut self, timeout: Duration, msg: GossipMessage);
+    fn stop_future_preprepare(&mut self);
+}
+
+/// ActorTimer runs the timers on the wall clock with the timer actors
+pub struct ActorTimer {
+    pid: Addr<Core>,
+    round_change: Addr<Timer>,
+    future_preprepare: Addr<Timer>,
+}
+
+impl ActorTimer {
+    pub fn new(pid: Addr<Core>, request_time: Duration) -> Self {
+        let (f_pid, r_pid) = (pid.clone(), pid.clone());
+        ActorTimer {
+            pid,
+            round_change: Timer::create(move |_| Timer::new("round change".to_owned(), request_time, r_pid, None)),
+            future_preprepare: Timer::create(move |_| Timer::new("future".to_owned(), request_time, f_pid, None)),
+        }
+    }
+}
+
+impl CoreTimer for ActorTimer {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+
+    fn start_round_change(&mut self, timeout: Duration) {
+        self.stop_round_change();
+        let pid = self.pid.clone();
+        self.round_change = Timer::create(move |_| Timer::new("round change".to_string(), timeout, pid, None));
+    }
+
+    fn stop_round_change(&mut self) {
+        self.round_change.try_send(Op::Stop);
+    }
+
+    fn start_future_preprepare(&mut self, timeout: Duration, msg: GossipMessage) {
+        self.stop_future_preprepare();
+        let pid = self.pid.clone();
+        self.future_preprepare = Timer::create(move |_| Timer::new("future preprepare".to_string(), timeout, pid, Some(msg)));
+    }
+
+    fn stop_future_preprepare(&mut self) {
+        self.future_preprepare.try_send(Op::Stop);
+    }
+}
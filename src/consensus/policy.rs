@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::types::Height;
+use crate::types::block::Block;
+use crate::types::transaction::Transaction;
+
+/// ProposalPolicy lets the application chains enforce their own invariants on the block
+/// content, eg: at most one governance transaction per block. `assemble` is invoked when the
+/// minner packs a new block and `verify` is invoked before the node votes for a proposal.
+pub trait ProposalPolicy {
+    /// select the transactions of the next block from the pool transactions,
+    /// the coinbase transaction is excluded
+    fn assemble(&self, _height: Height, transactions: Vec<Transaction>) -> Vec<Transaction> {
+        transactions
+    }
+
+    /// verify the content of a proposal, the error rejects the proposal
+    fn verify(&self, _block: &Block) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub type SafeProposalPolicy = Arc<ProposalPolicy + Send + Sync>;
+
+/// DefaultProposalPolicy accepts any content
+pub struct DefaultProposalPolicy;
+
+impl ProposalPolicy for DefaultProposalPolicy {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::Address;
+    use crate::types::block::Header;
+
+    // only one transaction with payload per block
+    struct OnePayloadPolicy;
+
+    impl ProposalPolicy for OnePayloadPolicy {
+        fn assemble(&self, _height: Height, transactions: Vec<Transaction>) -> Vec<Transaction> {
+            let mut has_payload = false;
+            transactions.into_iter().filter(|tx| {
+                if tx.payload().is_empty() {
+                    return true;
+                }
+                !::std::mem::replace(&mut has_payload, true)
+            }).collect()
+        }
+
+        fn verify(&self, block: &Block) -> Result<(), String> {
+            let count = block.transactions().iter().filter(|tx| !tx.payload().is_empty()).count();
+            if count > 1 {
+                return Err(format!("too many payload transactions: {}", count));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn t_proposal_policy() {
+        let transactions: Vec<Transaction> = (0..4).map(|idx| {
+            Transaction::new(idx, Address::from(1), 1, 21000, 1, if idx % 2 == 0 { vec![1] } else { vec![] })
+        }).collect();
+        let policy: SafeProposalPolicy = Arc::new(OnePayloadPolicy);
+        let selected = policy.assemble(1, transactions.clone());
+        assert_eq!(selected.len(), 3);
+        assert!(policy.verify(&Block::new(Header::zero_header(), selected)).is_ok());
+        assert!(policy.verify(&Block::new(Header::zero_header(), transactions.clone())).is_err());
+
+        let policy: SafeProposalPolicy = Arc::new(DefaultProposalPolicy);
+        assert_eq!(policy.assemble(1, transactions).len(), 4);
+    }
+}
@@ -0,0 +1,248 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::storage::values::StorageValue;
+use parking_lot::Mutex;
+
+use super::types::{PrePrepare, Round, Subject, View};
+use crate::config::Config;
+use crate::metrics;
+use crate::protocol::{GossipMessage, MessageType};
+use crate::types::Height;
+
+// the high-water mark in the sign state directory
+pub const SIGN_STATE_FILE: &str = "sign_state.json";
+
+/// the guard shared by every signing path of a validator: the votes of the core, the seal of
+/// the proposer and the slot blocks of dpos
+pub type SafeSignGuard = Arc<Mutex<SignGuard>>;
+
+/// SignStep is the step of a round in the signing order, a round change opens the round, the
+/// proposer seals its block before the preprepare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SignStep {
+    RoundChange,
+    Seal,
+    Preprepare,
+    Prepare,
+    Commit,
+}
+
+impl From<&MessageType> for SignStep {
+    fn from(code: &MessageType) -> Self {
+        match code {
+            MessageType::RoundChange => SignStep::RoundChange,
+            MessageType::Preprepare => SignStep::Preprepare,
+            MessageType::Prepare => SignStep::Prepare,
+            MessageType::Commit => SignStep::Commit,
+        }
+    }
+}
+
+/// SignState is the last (height, round, step) signed by the validator and the digest it voted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignState {
+    pub height: Height,
+    pub round: Round,
+    pub step: SignStep,
+    pub digest: Hash,
+}
+
+impl SignState {
+    fn key(&self) -> (Height, Round, SignStep) {
+        (self.height, self.round, self.step)
+    }
+}
+
+/// SignGuard keeps the high-water mark of the signatures out of the store, it's persisted
+/// before every signature, so a validator restored from an old backup can't sign a second vote
+/// for a step which it already voted.
+pub struct SignGuard {
+    path: PathBuf,
+    last: Option<SignState>,
+}
+
+impl SignGuard {
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let path = dir.as_ref().join(SIGN_STATE_FILE);
+        let last = match fs::read(&path) {
+            Ok(buf) => Some(serde_json::from_slice(&buf).map_err(|err| format!("broken sign state {:?}, {}", path, err))?),
+            Err(ref err) if err.kind() == ::std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(format!("failed to read the sign state {:?}, {}", path, err)),
+        };
+        Ok(SignGuard { path, last })
+    }
+
+    pub fn last(&self) -> Option<&SignState> {
+        self.last.as_ref()
+    }
+
+    /// allow the signature of the step, a lower step is refused, the same step is only signed
+    /// again for the same digest, eg: the resent round change. It returns the allowed vote, the
+    /// remote signer checks it against its own mark
+    pub fn check(&mut self, view: &View, step: SignStep, digest: Hash) -> Result<SignState, String> {
+        let state = SignState { height: view.height, round: view.round, step, digest };
+        self.check_state(state.clone())?;
+        Ok(state)
+    }
+
+    /// allow the seal of a block at the height, it's sealed in the last signed round of the
+    /// height, so a second block of the height is refused until the round changes
+    pub fn check_seal(&mut self, height: Height, digest: Hash) -> Result<SignState, String> {
+        let round = match self.last {
+            Some(ref last) if last.height == height => last.round,
+            _ => 0,
+        };
+        self.check(&View::new(height, round), SignStep::Seal, digest)
+    }
+
+    /// raise the mark to a higher one, eg: the mark of the old location
+    pub fn raise(&mut self, state: &SignState) -> Result<(), String> {
+        if self.last.as_ref().map_or(false, |last| last.key() >= state.key()) {
+            return Ok(());
+        }
+        self.check_state(state.clone())
+    }
+
+    /// allow the vote, eg: the vote of a sign request on the remote signer
+    pub fn check_state(&mut self, state: SignState) -> Result<(), String> {
+        if let Some(ref last) = self.last {
+            if state.key() < last.key() {
+                metrics::inc_counter("consensus_sign_refused_total");
+                return Err(format!("{:?} is below the signed {:?}", state.key(), last.key()));
+            }
+            if state.key() == last.key() {
+                if state.digest != last.digest {
+                    metrics::inc_counter("consensus_sign_refused_total");
+                    return Err(format!("{:?} is signed for another digest {:?}", state.key(), last.digest));
+                }
+                return Ok(());
+            }
+        }
+        self.persist(&state)?;
+        self.last = Some(state);
+        Ok(())
+    }
+
+    // the rename is atomic, a crash leaves the old or the new mark, the directory is synced,
+    // or else the rename may be lost on a power failure
+    fn persist(&self, state: &SignState) -> Result<(), String> {
+        let buf = serde_json::to_vec(state).map_err(|err| err.to_string())?;
+        let tmp = self.path.with_extension("json.tmp");
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        fs::write(&tmp, buf)
+            .and_then(|_| fs::File::open(&tmp).and_then(|file| file.sync_all()))
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .and_then(|_| fs::File::open(dir).and_then(|dir| dir.sync_all()))
+            .map_err(|err| format!("failed to persist the sign state {:?}, {}", self.path, err))
+    }
+}
+
+/// open the guard of the chain in its sign state directory, the mark of the old location (the
+/// data directory, it was the store by default) is carried over
+pub fn open_sign_guard(config: &Config) -> Result<SafeSignGuard, String> {
+    let dir = config.sign_state_dir();
+    if !config.is_memory_store() && Path::new(&dir).starts_with(&config.store) {
+        return Err(format!("the sign state directory {:?} is inside the store {:?}", dir, config.store));
+    }
+    fs::create_dir_all(&dir).map_err(|err| format!("failed to create the sign state directory {:?}, {}", dir, err))?;
+    let mut guard = SignGuard::load(&dir)?;
+    let old_dir = config.data_dir();
+    if old_dir != dir {
+        if let Some(old) = SignGuard::load(&old_dir)?.last() {
+            guard.raise(old)?;
+        }
+    }
+    Ok(Arc::new(Mutex::new(guard)))
+}
+
+/// the view and the digest voted by the consensus message
+pub fn message_vote(msg: &GossipMessage) -> (View, Hash) {
+    match msg.code {
+        MessageType::Preprepare => {
+            let preprepare: PrePrepare = PrePrepare::from_bytes(Cow::from(msg.msg()));
+            let digest = preprepare.proposal.block().hash();
+            (preprepare.view, digest)
+        }
+        _ => {
+            let subject: Subject = Subject::from_bytes(Cow::from(msg.msg()));
+            (subject.view, subject.digest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_sign_guard() {
+        let dir = *random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let digest = Hash::from_slice(&[1; 32]).unwrap();
+        let mut guard = SignGuard::load(&dir).unwrap();
+        assert!(guard.check(&View::new(2, 0), SignStep::Prepare, digest).is_ok());
+        // the same vote again and the later steps
+        assert!(guard.check(&View::new(2, 0), SignStep::Prepare, digest).is_ok());
+        assert!(guard.check(&View::new(2, 0), SignStep::Commit, digest).is_ok());
+        assert!(guard.check(&View::new(2, 1), SignStep::RoundChange, EMPTY_HASH).is_ok());
+
+        // the restored validator keeps the mark
+        let mut guard = SignGuard::load(&dir).unwrap();
+        assert_eq!(guard.last().map(|last| last.key()), Some((2, 1, SignStep::RoundChange)));
+        assert!(guard.check(&View::new(2, 0), SignStep::Commit, digest).is_err());
+        assert!(guard.check(&View::new(1, 5), SignStep::Prepare, digest).is_err());
+        assert!(guard.check(&View::new(2, 1), SignStep::RoundChange, digest).is_err());
+        assert!(guard.check(&View::new(2, 1), SignStep::Preprepare, digest).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn t_check_seal() {
+        let dir = *random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let (block, other) = (Hash::from_slice(&[1; 32]).unwrap(), Hash::from_slice(&[2; 32]).unwrap());
+        let mut guard = SignGuard::load(&dir).unwrap();
+        assert!(guard.check_seal(3, block).is_ok());
+        assert!(guard.check_seal(3, block).is_ok());
+        // a second block of the height is refused
+        assert!(guard.check_seal(3, other).is_err());
+        assert!(guard.check(&View::new(3, 0), SignStep::Preprepare, block).is_ok());
+        assert!(guard.check_seal(3, block).is_err());
+        // the next round seals again
+        assert!(guard.check(&View::new(3, 1), SignStep::RoundChange, EMPTY_HASH).is_ok());
+        assert!(guard.check_seal(3, other).is_ok());
+        assert_eq!(SignGuard::load(&dir).unwrap().last().map(|last| last.key()), Some((3, 1, SignStep::Seal)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn t_open_sign_guard() {
+        let root = *random_dir();
+        let mut config = Config::default();
+        config.store = format!("{}/db", root);
+        fs::create_dir_all(&config.store).unwrap();
+        let digest = Hash::from_slice(&[1; 32]).unwrap();
+        // the mark of the old location in the store
+        SignGuard::load(&config.store).unwrap().check(&View::new(5, 0), SignStep::Commit, digest).unwrap();
+
+        let guard = open_sign_guard(&config).unwrap();
+        assert_eq!(guard.lock().last().map(|last| last.key()), Some((5, 0, SignStep::Commit)));
+        assert!(guard.lock().check(&View::new(5, 0), SignStep::Prepare, digest).is_err());
+        assert!(Path::new(&format!("{}/db.sign/{}", root, SIGN_STATE_FILE)).exists());
+
+        // a restored store keeps the newer mark
+        guard.lock().check(&View::new(6, 0), SignStep::Commit, digest).unwrap();
+        let guard = open_sign_guard(&config).unwrap();
+        assert_eq!(guard.lock().last().map(|last| last.key()), Some((6, 0, SignStep::Commit)));
+
+        config.sign_state_dir = Some(format!("{}/db/sign", root));
+        assert!(open_sign_guard(&config).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,522 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::ethkey::{Address, KeyPair};
+use cryptocurrency_kit::storage::values::StorageValue;
+use futures::Future;
+use lru_time_cache::LruCache;
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use rand::prng::XorShiftRng;
+
+use crate::{
+    common::{merkle_tree_root, random_dir},
+    config::{Config, GenesisConfig},
+    core::chain::Chain,
+    core::gas::next_gas_limit,
+    core::ledger::{LastMeta, Ledger},
+    mocks::byzantine::{ByzantineBehavior, ByzantineCore},
+    protocol::{GossipMessage, GossipMessages},
+    signer::{LocalSigner, SafeSigner},
+    store::backend::{Backend as StoreBackend, MEMORY_STORE},
+    store::memory::MemoryDB,
+    store::schema::Schema,
+    subscriber::events::{BroadcastEventSubscriber, SubscriberType},
+    types::block::{Block, Header},
+    types::transaction::Transaction,
+    types::{Height, Validator},
+    util::dev_keys::dev_key_pair,
+    util::rng::seeded_rng,
+};
+use super::{
+    backend::{new_impl_backend, Backend, ImplBackend},
+    error::{EngineError, EngineResult},
+    events::{BackLogEvent, MessageEvent, NewHeaderEvent, OpCMD, TimerEvent},
+    pbft::core::core::Core,
+    pbft::core::timer::CoreTimer,
+    sign_guard::open_sign_guard,
+    types::{CommitCertificate, Proposal},
+    validator::{ImplValidatorSet, ValidatorSet},
+};
+
+/// the parameters of a simulation, the same parameters replay the same run
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub validators: usize,
+    // the latency of every message is picked from [min_latency, max_latency]
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    // the virtual time to run
+    pub duration: Duration,
+    pub block_period: Duration,
+    // the faulty nodes by their index, the rest are honest
+    pub faults: Vec<(usize, ByzantineBehavior)>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 0,
+            validators: 4,
+            min_latency: Duration::from_millis(10),
+            max_latency: Duration::from_millis(500),
+            duration: Duration::from_secs(60),
+            block_period: Duration::from_secs(1),
+            faults: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SimReport {
+    pub seed: u64,
+    // the virtual millis when the simulation stopped
+    pub time: u64,
+    pub delivered: u64,
+    pub timeouts: u64,
+    // the last height and block hash of every node
+    pub heads: Vec<(Height, Hash)>,
+    // the committed block hashes of every node, from the genesis
+    pub chains: Vec<Vec<Hash>>,
+}
+
+impl SimReport {
+    /// the heights where the nodes committed different blocks, a safety violation
+    pub fn forks(&self) -> Vec<Height> {
+        let highest = self.chains.iter().map(|chain| chain.len()).max().unwrap_or(0);
+        (0..highest).filter(|height| {
+            let mut hashes = self.chains.iter().filter_map(|chain| chain.get(*height));
+            match hashes.next() {
+                Some(first) => hashes.any(|hash| hash != first),
+                None => false,
+            }
+        }).map(|height| height as Height).collect()
+    }
+}
+
+#[derive(Debug)]
+enum SimEvent {
+    Message { to: usize, msg: GossipMessage },
+    RoundChangeTimer { to: usize, generation: u64 },
+    FuturePreprepareTimer { to: usize, generation: u64, msg: GossipMessage },
+    Propose { to: usize, height: Height },
+}
+
+struct Scheduled {
+    at: u64,
+    seq: u64,
+    event: SimEvent,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// the earliest event is the greatest, so the max heap pops it first
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// SimNetwork is the virtual clock and the in-memory network of the simulation, every message
+/// and timer is an event ordered by (virtual time, sequence), the latencies come from the seeded rng
+pub struct SimNetwork {
+    now: u64,
+    seq: u64,
+    rng: XorShiftRng,
+    nodes: usize,
+    min_latency: u64,
+    max_latency: u64,
+    queue: BinaryHeap<Scheduled>,
+    // the generations of the running timers of every node, the stale timer events are ignored
+    round_change: Vec<u64>,
+    future_preprepare: Vec<u64>,
+}
+
+pub type SafeSimNetwork = Arc<Mutex<SimNetwork>>;
+
+impl SimNetwork {
+    pub fn new(config: &SimConfig) -> Self {
+        SimNetwork {
+            now: 0,
+            seq: 0,
+            rng: seeded_rng(config.seed),
+            nodes: config.validators,
+            min_latency: config.min_latency.as_millis() as u64,
+            max_latency: ::std::cmp::max(config.min_latency, config.max_latency).as_millis() as u64,
+            queue: BinaryHeap::new(),
+            round_change: vec![0; config.validators],
+            future_preprepare: vec![0; config.validators],
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    fn schedule(&mut self, after: u64, event: SimEvent) {
+        self.seq += 1;
+        self.queue.push(Scheduled { at: self.now + after, seq: self.seq, event });
+    }
+
+    // the message to self is delivered without latency
+    fn broadcast(&mut self, from: usize, msg: GossipMessage) {
+        for to in 0..self.nodes {
+            let latency = if to == from { 0 } else { self.rng.gen_range(self.min_latency, self.max_latency + 1) };
+            self.schedule(latency, SimEvent::Message { to, msg: msg.clone() });
+        }
+    }
+
+    fn pop(&mut self) -> Option<SimEvent> {
+        let scheduled = self.queue.pop()?;
+        self.now = scheduled.at;
+        Some(scheduled.event)
+    }
+}
+
+/// SimTimer schedules the timers of a core on the virtual clock
+pub struct SimTimer {
+    node: usize,
+    network: SafeSimNetwork,
+}
+
+impl CoreTimer for SimTimer {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.network.lock().now())
+    }
+
+    fn start_round_change(&mut self, timeout: Duration) {
+        let mut network = self.network.lock();
+        network.round_change[self.node] += 1;
+        let generation = network.round_change[self.node];
+        network.schedule(timeout.as_millis() as u64, SimEvent::RoundChangeTimer { to: self.node, generation });
+    }
+
+    fn stop_round_change(&mut self) {
+        self.network.lock().round_change[self.node] += 1;
+    }
+
+    fn start_future_preprepare(&mut self, timeout: Duration, msg: GossipMessage) {
+        let mut network = self.network.lock();
+        network.future_preprepare[self.node] += 1;
+        let generation = network.future_preprepare[self.node];
+        network.schedule(timeout.as_millis() as u64, SimEvent::FuturePreprepareTimer { to: self.node, generation, msg });
+    }
+
+    fn stop_future_preprepare(&mut self) {
+        self.network.lock().future_preprepare[self.node] += 1;
+    }
+}
+
+/// SimBackend sends the messages of a core to the simulated network instead of the p2p server
+#[derive(Clone)]
+pub struct SimBackend {
+    node: usize,
+    inner: ImplBackend,
+    network: SafeSimNetwork,
+}
+
+impl Backend for SimBackend {
+    type ValidatorsType = ImplValidatorSet;
+
+    fn address(&self) -> Address {
+        self.inner.address()
+    }
+
+    fn validators(&self, height: Height) -> ImplValidatorSet {
+        self.inner.validators(height)
+    }
+
+    fn gossip(&mut self, _vals: &ValidatorSet, msg: GossipMessage) -> EngineResult {
+        self.network.lock().broadcast(self.node, msg);
+        Ok(())
+    }
+
+    fn commit(&mut self, proposal: &mut Proposal, certificate: CommitCertificate, messages: GossipMessages) -> Result<(), String> {
+        self.inner.commit(proposal, certificate, messages)
+    }
+
+    fn verify(&self, proposal: &Proposal) -> (Duration, Result<(), EngineError>) {
+        self.inner.verify(proposal)
+    }
+
+    fn check_signature(&self, data: &[u8; 32], address: Address, sig: &[u8]) -> Result<bool, ()> {
+        self.inner.check_signature(data, address, sig)
+    }
+
+    fn last_proposal(&self) -> Result<Proposal, ()> {
+        self.inner.last_proposal()
+    }
+
+    fn has_proposal(&self, hash: &Hash, height: Height) -> bool {
+        self.inner.has_proposal(hash, height)
+    }
+
+    fn get_proposer(&self, height: Height) -> Address {
+        self.inner.get_proposer(height)
+    }
+
+    fn parent_validators(&self, proposal: &Proposal) -> ImplValidatorSet {
+        self.inner.parent_validators(proposal)
+    }
+
+    fn has_bad_proposal(&self, hash: Hash) -> bool {
+        self.inner.has_bad_proposal(hash)
+    }
+
+    fn get_header_by_height(&self, height: Height) -> Option<Header> {
+        self.inner.get_header_by_height(height)
+    }
+
+    fn cancel_inflight(&mut self) {
+        self.inner.cancel_inflight()
+    }
+}
+
+struct SimNode {
+    key_pair: KeyPair,
+    core: Addr<Core>,
+    chain: Arc<Chain>,
+    // the height which was proposed last
+    proposed: Height,
+}
+
+/// Run the consensus engine of `config.validators` nodes on the simulated network and the
+/// virtual clock. The events are delivered one by one, so a run is reproducible from the seed.
+pub fn simulate(config: SimConfig) -> Result<SimReport, String> {
+    // the delay fault waits on the real clock, the latency delays the messages of the simulation
+    if config.faults.iter().any(|(_, behavior)| behavior.delay.is_some()) {
+        return Err("the delay fault isn't simulated, raise the latency instead".to_string());
+    }
+    let network: SafeSimNetwork = Arc::new(Mutex::new(SimNetwork::new(&config)));
+    let key_pairs = sim_key_pairs(config.validators);
+    let dirs: Vec<String> = (0..config.validators).map(|_| *random_dir()).collect();
+
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    {
+        let network = network.clone();
+        let (config, key_pairs, dirs) = (config.clone(), key_pairs.clone(), dirs.clone());
+        ::std::thread::spawn(move || {
+            System::run(move || {
+                let nodes = spawn_nodes(&config, &key_pairs, &dirs, &network);
+                tx.send((System::current(), nodes)).unwrap();
+            });
+        });
+    }
+    let (system, mut nodes) = rx.recv().map_err(|err| err.to_string())?;
+    let result = run_events(&config, &network, &mut nodes);
+    system.stop();
+    for dir in dirs {
+        let _ = ::std::fs::remove_dir_all(dir);
+    }
+    result
+}
+
+fn spawn_nodes(config: &SimConfig, key_pairs: &[KeyPair], dirs: &[String], network: &SafeSimNetwork) -> Result<Vec<SimNode>, String> {
+    let genesis = sim_genesis(key_pairs)?;
+    let mut nodes = Vec::with_capacity(key_pairs.len());
+    for (node, key_pair) in key_pairs.iter().enumerate() {
+        let mut node_config = Config::default();
+        // the chains are kept in memory, the directories only keep the sign states
+        node_config.store = MEMORY_STORE.to_string();
+        node_config.data_dir = Some(dirs[node].clone());
+        node_config.block_period = config.block_period;
+        node_config.genesis = Some(genesis.clone());
+        let ledger = Ledger::new(
+            LastMeta::new_zero(),
+            LruCache::with_capacity(1 << 10),
+            LruCache::with_capacity(1 << 10),
+            key_pairs.iter().map(|key_pair| Validator::new(key_pair.address())).collect(),
+            Schema::new(Arc::new(StoreBackend::Memory(MemoryDB::new()))),
+        );
+        let mut chain = Chain::new(node_config, Arc::new(RwLock::new(ledger)));
+        chain.store_genesis_block().map_err(|err| format!("{}", err))?;
+        let chain = Arc::new(chain);
+
+        let subscriber = BroadcastEventSubscriber::new(SubscriberType::Async).start();
+        let signer: SafeSigner = Arc::new(LocalSigner::new(key_pair.clone()));
+        let sign_guard = open_sign_guard(&chain.config)?;
+        let backend = new_impl_backend(signer.clone(), sign_guard, chain.clone(), subscriber);
+        let (backend_network, timer_network) = (network.clone(), network.clone());
+        let fault = config.faults.iter().find(|(idx, _)| *idx == node).map(|(_, behavior)| (key_pair.clone(), behavior.clone()));
+        let core = Core::with_parts(
+            chain.clone(),
+            backend,
+            signer,
+            move |inner| {
+                let backend = SimBackend { node, inner, network: backend_network };
+                match fault {
+                    Some((key_pair, behavior)) => Box::new(ByzantineCore::new(backend, key_pair, behavior)) as Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync>,
+                    None => Box::new(backend),
+                }
+            },
+            move |_| Box::new(SimTimer { node, network: timer_network }),
+        );
+        nodes.push(SimNode { key_pair: key_pair.clone(), core, chain, proposed: 0 });
+    }
+    Ok(nodes)
+}
+
+fn run_events(config: &SimConfig, network: &SafeSimNetwork, nodes: &mut Result<Vec<SimNode>, String>) -> Result<SimReport, String> {
+    let nodes = nodes.as_mut().map_err(|err| err.clone())?;
+    // the cores have started once they reply, the first timers are scheduled then
+    for node in nodes.iter() {
+        barrier(node)?;
+    }
+    let block_period = config.block_period.as_millis() as u64;
+    let duration = config.duration.as_millis() as u64;
+    let mut report = SimReport { seed: config.seed, ..SimReport::default() };
+    loop {
+        // every node proposes the next height a block period after it commits the last one
+        {
+            let mut network = network.lock();
+            for (idx, node) in nodes.iter_mut().enumerate() {
+                let height = node.chain.get_last_height() + 1;
+                if node.proposed < height {
+                    node.proposed = height;
+                    network.schedule(block_period, SimEvent::Propose { to: idx, height });
+                }
+            }
+        }
+        let event = {
+            let mut network = network.lock();
+            match network.pop() {
+                Some(event) => event,
+                None => break,
+            }
+        };
+        report.time = network.lock().now();
+        if report.time > duration {
+            break;
+        }
+        match event {
+            SimEvent::Message { to, msg } => {
+                report.delivered += 1;
+                let _ = nodes[to].core.send(MessageEvent { payload: msg.into_bytes() }).wait();
+                barrier(&nodes[to])?;
+            }
+            SimEvent::RoundChangeTimer { to, generation } => {
+                if network.lock().round_change[to] == generation {
+                    report.timeouts += 1;
+                    let _ = nodes[to].core.send(TimerEvent {}).wait();
+                    barrier(&nodes[to])?;
+                }
+            }
+            SimEvent::FuturePreprepareTimer { to, generation, msg } => {
+                if network.lock().future_preprepare[to] == generation {
+                    let _ = nodes[to].core.send(BackLogEvent { msg }).wait();
+                    barrier(&nodes[to])?;
+                }
+            }
+            SimEvent::Propose { to, height } => {
+                let proposal = next_proposal(&nodes[to], height, config.block_period.as_secs());
+                if let Some(proposal) = proposal {
+                    let _ = nodes[to].core.send(NewHeaderEvent { proposal }).wait();
+                    barrier(&nodes[to])?;
+                }
+            }
+        }
+    }
+    report.heads = nodes.iter().map(|node| (node.chain.get_last_height(), node.chain.get_last_hash())).collect();
+    report.chains = nodes.iter().map(|node| {
+        (0..=node.chain.get_last_height()).filter_map(|height| node.chain.get_block_hash_by_height(height)).collect()
+    }).collect();
+    Ok(report)
+}
+
+// the messages that the core posted to itself are handled before the reply of the ping
+fn barrier(node: &SimNode) -> Result<(), String> {
+    node.core.send(OpCMD::Ping).wait().map_err(|err| format!("core is stopped, err: {:?}", err))
+}
+
+fn next_proposal(node: &SimNode, height: Height, block_period: u64) -> Option<Proposal> {
+    let parent = node.chain.get_last_block();
+    if parent.height() + 1 != height {
+        return None;
+    }
+    let transactions: Vec<Transaction> = vec![];
+    let tx_hash = merkle_tree_root(transactions.clone());
+    let mut header = Header::new_mock(parent.hash(), node.key_pair.address(), tx_hash, height, parent.header().time + block_period, None);
+    header.gas_limit = next_gas_limit(parent.header().gas_limit, &node.chain.config.gas);
+    Some(Proposal::new(Block::new(header, transactions)))
+}
+
+// the validator keys don't depend on the seed, the node N is always `validator-N` in the logs
+fn sim_key_pairs(validators: usize) -> Vec<KeyPair> {
+    (0..validators as u64).map(dev_key_pair).collect()
+}
+
+fn sim_genesis(key_pairs: &[KeyPair]) -> Result<GenesisConfig, String> {
+    let validators: Vec<String> = key_pairs.iter().map(|key_pair| format!("\"0x{}\"", hex::encode(key_pair.address().as_ref()))).collect();
+    let genesis = format!(
+        "validator = [{}]\nepoch_time = 2019-01-01T00:00:00Z\nproposer = {}\ngas_used = 0\nextra = \"simulation\"\n[accounts]\n",
+        validators.join(", "),
+        validators[0],
+    );
+    toml::from_str(&genesis).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use cryptocurrency_kit::crypto::hash;
+
+    #[test]
+    fn t_simulate() {
+        let config = SimConfig { seed: 42, duration: Duration::from_secs(20), ..SimConfig::default() };
+        let first = simulate(config.clone()).unwrap();
+        let second = simulate(config).unwrap();
+        assert_eq!(first, second);
+        assert!(first.delivered > 0);
+        // every node commits, and all of them commit the same block at every height
+        assert!(first.heads.iter().all(|(height, _)| *height > 0));
+        assert!(first.forks().is_empty());
+        for (chain, (height, hash)) in first.chains.iter().zip(first.heads.iter()) {
+            assert_eq!(chain.len() as Height, height + 1);
+            assert_eq!(chain.last(), Some(hash));
+        }
+    }
+
+    #[test]
+    fn t_sim_report_forks() {
+        let (a, b, c) = (Hash::zero(), hash(&[1]), hash(&[2]));
+        let mut report = SimReport { chains: vec![vec![a, b], vec![a, b, c], vec![a]], ..SimReport::default() };
+        assert!(report.forks().is_empty());
+        report.chains[2].push(c);
+        assert_eq!(report.forks(), vec![1]);
+    }
+
+    #[test]
+    fn t_simulate_byzantine() {
+        // one of four validators is tolerated, the honest nodes keep committing
+        let behavior = ByzantineBehavior::from_str("equivocate,withhold_commits,invalid_proposal").unwrap();
+        let config = SimConfig { seed: 7, duration: Duration::from_secs(20), faults: vec![(3, behavior)], ..SimConfig::default() };
+        let report = simulate(config.clone()).unwrap();
+        assert_eq!(report, simulate(config).unwrap());
+        let honest = &report.heads[..3];
+        assert!(honest.iter().all(|(height, _)| *height > 0));
+        // the faulty node can't fork the honest ones
+        let honest = SimReport { chains: report.chains[..3].to_vec(), ..report.clone() };
+        assert!(honest.forks().is_empty());
+
+        let delayed = SimConfig { faults: vec![(0, ByzantineBehavior::from_str("delay=100").unwrap())], ..SimConfig::default() };
+        assert!(simulate(delayed).is_err());
+    }
+}
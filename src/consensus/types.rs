@@ -1,5 +1,5 @@
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
-use cryptocurrency_kit::ethkey::Signature;
+use cryptocurrency_kit::ethkey::{Address, Signature};
 use cryptocurrency_kit::storage::values::StorageValue;
 use serde::{Deserialize, Serialize};
 
@@ -9,7 +9,7 @@ use std::cmp::Ordering;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::io::Cursor;
 
-use crate::types::{Height, block::Block, votes::Votes};
+use crate::types::{Height, block::Block, votes::{batch_recover_commit_seals, commit_quorum, Votes}};
 
 pub type Round = u64;
 
@@ -35,7 +35,7 @@ impl Proposal {
     }
 }
 
-implement_cryptohash_traits! {Proposal}
+implement_hash_algo_traits! {Proposal}
 implement_storagevalue_traits! {Proposal}
 
 #[derive(Debug)]
@@ -62,7 +62,7 @@ pub struct BlockPart {
     pub round: Round,
 }
 
-implement_cryptohash_traits! {BlockPart}
+implement_hash_algo_traits! {BlockPart}
 implement_storagevalue_traits! {BlockPart}
 
 #[derive(Debug)]
@@ -78,7 +78,7 @@ pub struct View {
     pub height: Height,
 }
 
-implement_cryptohash_traits! {View}
+implement_hash_algo_traits! {View}
 implement_storagevalue_traits! {View}
 
 impl View {
@@ -149,7 +149,7 @@ pub struct PrePrepare {
     pub proposal: Proposal,
 }
 
-implement_cryptohash_traits! {PrePrepare}
+implement_hash_algo_traits! {PrePrepare}
 implement_storagevalue_traits! {PrePrepare}
 
 impl PrePrepare {
@@ -158,6 +158,46 @@ impl PrePrepare {
     }
 }
 
+/// CommitCertificate is the compact proof that a block is finalized, the commit seals of more
+/// than 2/3 of the validators on the block hash at the view. It's stored per height, so the light
+/// clients, the sync and the api proofs consume one object instead of the loose votes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CommitCertificate {
+    pub view: View,
+    pub block_hash: Hash,
+    pub seals: Votes,
+}
+
+implement_storagevalue_traits! {CommitCertificate}
+implement_hash_algo_traits! {CommitCertificate}
+
+impl CommitCertificate {
+    pub fn new(view: View, block_hash: Hash, seals: Vec<Signature>) -> Self {
+        CommitCertificate { view, block_hash, seals: Votes::new(seals) }
+    }
+
+    pub fn height(&self) -> Height {
+        self.view.height
+    }
+
+    /// the validators which signed the commit seals, in the order of the seals
+    pub fn signers(&self) -> Result<Vec<Address>, String> {
+        batch_recover_commit_seals(&self.block_hash, self.seals.votes()).into_iter().collect()
+    }
+
+    /// the seals must come from the quorum of the different validators of the set
+    pub fn verify(&self, validators: &[Address]) -> Result<(), String> {
+        let quorum = commit_quorum(validators.len());
+        if self.seals.len() < quorum {
+            return Err(format!("lack of commit seals, expect: {}, got: {}", quorum, self.seals.len()));
+        }
+        if !self.seals.verify_commit_seals(&self.block_hash, |address| validators.contains(&address)) {
+            return Err("invalid commit seals".to_string());
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -367,4 +407,26 @@ mod test {
             assert!(a <= b);
         }
     }
+
+    #[test]
+    fn test_commit_certificate() {
+        use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+        use crate::types::votes::encrypt_commit_bytes;
+
+        let block_hash = hash(vec![1, 2, 3]);
+        let key_pairs: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let validators: Vec<Address> = key_pairs.iter().map(|key_pair| key_pair.address()).collect();
+        let seals: Vec<Signature> = key_pairs.iter().map(|key_pair| encrypt_commit_bytes(&block_hash, key_pair.secret())).collect();
+
+        let certificate = CommitCertificate::new(View::new(10, 1), block_hash, seals[..3].to_vec());
+        let decoded = CommitCertificate::from_bytes(Cow::from(certificate.clone().into_bytes()));
+        assert_eq!(decoded.height(), 10);
+        assert!(decoded.verify(&validators).is_ok());
+        assert_eq!(decoded.signers().unwrap(), validators[..3].to_vec());
+
+        // 2 of 4 is below the quorum
+        assert!(CommitCertificate::new(View::new(10, 1), block_hash, seals[..2].to_vec()).verify(&validators).is_err());
+        assert!(CommitCertificate::new(View::new(10, 1), hash(vec![4]), seals[..3].to_vec()).verify(&validators).is_err());
+        assert!(certificate.verify(&validators[1..]).is_err());
+    }
 }
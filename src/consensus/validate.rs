@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cryptocurrency_kit::ethkey::Address;
+use parking_lot::RwLock;
+
+use crate::core::fee::FeeConfig;
+use crate::core::gas::{block_gas, intrinsic_gas};
+use crate::core::ledger::Ledger;
+use crate::types::account::Account;
+use crate::types::block::{Block, Header};
+use crate::types::transaction::Transaction;
+
+/// ProposalValidator checks the transactions of a proposal against the local state before the
+/// node accepts the PrePrepare, the quorum never executes the block, so a garbage proposal would
+/// be committed once 2f+1 nodes vote for it.
+pub trait ProposalValidator {
+    /// the error rejects the proposal
+    fn validate(&self, block: &Block) -> Result<(), String>;
+}
+
+pub type SafeProposalValidator = Arc<ProposalValidator + Send + Sync>;
+
+/// the coinbase transaction leads a non-empty block, it pays the proposer, uses no gas and mints
+/// nothing. The executor mints the amount of the first transaction, so a transfer or an inflated
+/// amount in its place is rejected, the block reward is written with the block indexes.
+pub fn is_coinbase(header: &Header, transaction: &Transaction) -> bool {
+    transaction.gas() == 0 && transaction.amount() == 0 && transaction.to() == Some(&header.proposer)
+}
+
+/// check the transactions of the block: the first one must be the coinbase transaction, the others
+/// need a valid signature, a nonce which follows the confirmed nonce of the sender (`account_of`)
+/// without a gap, the gas which covers the execution, and a balance which covers the amount and
+/// the fee after the earlier transactions of the block. The declared gas of the block must fit
+/// the block gas limit.
+pub fn validate_transactions<F>(header: &Header, transactions: &[Transaction], chain_id: u64, fees: &FeeConfig, account_of: F) -> Result<(), String>
+    where F: Fn(&Address) -> Account {
+    let (coinbase, transactions) = match transactions.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    if !is_coinbase(header, coinbase) {
+        return Err(format!("the first transaction isn't the coinbase one, tx: {:?}", coinbase.get_hash()));
+    }
+    // the accounts touched by the block
+    let mut accounts: HashMap<Address, Account> = HashMap::new();
+    for transaction in transactions {
+        if !transaction.verify_sign(chain_id) {
+            return Err(format!("invalid signature, tx: {:?}", transaction.get_hash()));
+        }
+        let sender = transaction.sender().ok_or(format!("unknown sender, tx: {:?}", transaction.get_hash()))?;
+        if transaction.gas() < intrinsic_gas(transaction) {
+            return Err(format!("intrinsic gas too low, tx: {:?}, gas: {}", transaction.get_hash(), transaction.gas()));
+        }
+        let account = accounts.entry(sender).or_insert_with(|| account_of(&sender));
+        if transaction.nonce() != account.nonce {
+            return Err(format!("invalid nonce, tx: {:?}, sender: {:?}, expect: {}, got: {}", transaction.get_hash(), sender, account.nonce, transaction.nonce()));
+        }
+        let cost = transaction.amount().saturating_add(fees.fee(transaction));
+        if account.balance < cost {
+            return Err(format!("insufficient balance, tx: {:?}, sender: {:?}, balance: {}, cost: {}", transaction.get_hash(), sender, account.balance, cost));
+        }
+        account.balance -= cost;
+        account.nonce += 1;
+        if let Some(recipient) = transaction.to() {
+            accounts.entry(*recipient).or_insert_with(|| account_of(recipient)).credit(transaction.amount());
+        }
+    }
+    let (declared, _) = block_gas(transactions);
+    if declared > header.gas_limit {
+        return Err(format!("transactions gas exceeds the block gas limit, limit: {}, got: {}", header.gas_limit, declared));
+    }
+    Ok(())
+}
+
+/// StateValidator validates the proposal on top of the last committed state, a proposal which
+/// doesn't extend the last block is left to the header verification
+pub struct StateValidator {
+    ledger: Arc<RwLock<Ledger>>,
+    chain_id: u64,
+}
+
+impl StateValidator {
+    pub fn new(ledger: Arc<RwLock<Ledger>>, chain_id: u64) -> Self {
+        StateValidator { ledger, chain_id }
+    }
+}
+
+impl ProposalValidator for StateValidator {
+    fn validate(&self, block: &Block) -> Result<(), String> {
+        let ledger = self.ledger.read();
+        if block.header().prev_hash != *ledger.get_last_block_hash() {
+            return Err(format!("the proposal doesn't extend the last block, height: {}", block.height()));
+        }
+        let fees = ledger.get_fee_config();
+        validate_transactions(block.header(), block.transactions(), self.chain_id, &fees, |address| ledger.get_account(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+
+    #[test]
+    fn t_validate_transactions() {
+        use cryptocurrency_kit::crypto::EMPTY_HASH;
+        use crate::types::Gas;
+
+        let (chain_id, key_pair) = (1, Random.generate().unwrap());
+        let new_tx = |nonce: u64, amount: u64, gas: Gas| {
+            let mut transaction = Transaction::new(nonce, Address::from(1), amount, gas, 1, vec![]);
+            transaction.sign(chain_id, key_pair.secret());
+            transaction
+        };
+        let mut header = Header::new_mock(EMPTY_HASH, Address::from(2), EMPTY_HASH, 1, 0, None);
+        header.gas_limit = 100_000;
+        let coinbase = Transaction::new(0, Address::from(2), 0, 0, 1, vec![]);
+        let (sender, fees) = (key_pair.address(), FeeConfig::disabled());
+        let account_of = |address: &Address| if *address == sender { Account { balance: 10, nonce: 3 } } else { Account::default() };
+        let validate = |transactions: &[Transaction]| validate_transactions(&header, transactions, chain_id, &fees, account_of);
+
+        let transactions = vec![coinbase.clone(), new_tx(3, 1, 21000), new_tx(4, 1, 21000)];
+        assert!(validate(&transactions).is_ok());
+        assert!(validate(&[]).is_ok());
+        // the other chain
+        assert!(validate_transactions(&header, &transactions, chain_id + 1, &fees, account_of).is_err());
+        // the missing coinbase, the transfer in its place would be minted
+        assert!(validate(&[new_tx(3, 1, 21000)]).is_err());
+        assert!(validate(&[Transaction::new(0, Address::from(3), 0, 0, 1, vec![]), new_tx(3, 1, 21000)]).is_err());
+        // the inflated coinbase would mint the amount to the proposer
+        assert!(validate(&[Transaction::new(0, Address::from(2), 1, 0, 1, vec![])]).is_err());
+        assert!(validate(&[Transaction::new(0, Address::from(2), u64::max_value(), 0, 1, vec![]), new_tx(3, 1, 21000)]).is_err());
+        // the gap, the replay and the duplicate nonces
+        assert!(validate(&[coinbase.clone(), new_tx(4, 1, 21000)]).is_err());
+        assert!(validate(&[coinbase.clone(), new_tx(2, 1, 21000)]).is_err());
+        assert!(validate(&[coinbase.clone(), new_tx(3, 1, 21000), new_tx(3, 1, 21000)]).is_err());
+        // the gas of a transaction and of the block
+        assert!(validate(&[coinbase.clone(), new_tx(3, 1, 1)]).is_err());
+        assert!(validate(&[coinbase.clone(), new_tx(3, 1, 200_000)]).is_err());
+        assert!(validate(&[coinbase.clone(), new_tx(3, 1, 50_000), new_tx(4, 1, 50_001)]).is_err());
+        // the balance is spent by the earlier transactions of the block
+        assert!(validate(&[coinbase.clone(), new_tx(3, 10, 21000)]).is_ok());
+        assert!(validate(&[coinbase.clone(), new_tx(3, 11, 21000)]).is_err());
+        assert!(validate(&[coinbase.clone(), new_tx(3, 6, 21000), new_tx(4, 5, 21000)]).is_err());
+        // the fee is a part of the cost
+        let fees = FeeConfig { enabled: true, burn_percent: 0 };
+        assert!(validate_transactions(&header, &[coinbase, new_tx(3, 1, 21000)], chain_id, &fees, account_of).is_err());
+    }
+}
@@ -1,21 +1,45 @@
 use std::sync::Arc;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 use ::actix::prelude::*;
 use parking_lot::RwLock;
 use cryptocurrency_kit::ethkey::Address;
 use cryptocurrency_kit::crypto::Hash;
 use futures::Future;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::{
     config::Config,
+    consensus::consensus::{EngineKind, SafeSealVerifier},
+    consensus::dpos::delegates::DposElection,
+    consensus::dpos::engine::DposSchedule,
+    consensus::error::EngineError,
+    consensus::epoch::{Epoch, EpochManager, SafeEpochHook, StaticValidators},
+    consensus::policy::{DefaultProposalPolicy, SafeProposalPolicy},
+    consensus::types::CommitCertificate,
+    consensus::validate::{SafeProposalValidator, StateValidator},
     error::{ChainError, ChainResult},
-    types::{Height, Validators, ValidatorArray, Validator, transaction::Transaction, block::Block, block::Header},
+    types::{Height, Timestamp, ValidatorArray, Validator, transaction::Transaction, block::Block, block::Header, account::{Account, AccountState}},
+    types::receipt::{LogEntry, LogFilter, Receipt},
     subscriber::events::{ChainEvent, ChainEventCT::ProcessSignals, ChainEventCT::SubscribeMessage},
+    protocol::GossipMessages,
+    metrics,
+    util::clock::{SafeClock, SystemClock},
 };
+use super::checkpoint::TrustedCheckpoint;
+use super::gas::verify_block_gas;
 use super::genesis::store_genesis_block;
 use super::ledger::Ledger;
+use super::future_block::{Admission, FutureBlockQueue};
+use super::orphan::{OrphanPool, MAX_ORPHAN_BLOCKS, ORPHAN_TTL};
+use super::safety::{detect_conflict, SafetyEvidence, SAFETY_EXIT_CODE, SAFETY_EXIT_DELAY};
+use super::state::StateView;
+use super::tx_pool::AccountReader;
+use super::verify::truncate_chain;
+use super::staking::{StakeFilter, ValidatorStake};
+use super::reward::RewardHistory;
+use super::read_ahead::{BlockReadAhead, DEFAULT_READ_AHEAD};
 
 pub struct Chain {
     ledger: Arc<RwLock<Ledger>>,
@@ -23,53 +47,385 @@ pub struct Chain {
     genesis: Option<Block>,
     lock: RwLock<()>,
     sync_limiter: RwLock<Instant>,
+    // the blocks whose parent is missing, they are imported when the parent arrives
+    orphans: RwLock<OrphanPool>,
+    // the gossiped blocks whose timestamps are slightly ahead of the local clock
+    future_blocks: RwLock<FutureBlockQueue>,
+    // the clock the future blocks are held against
+    clock: SafeClock,
+    // the height the chain syncs up to, it's none if the chain follows the network
+    sync_target: RwLock<Option<Height>>,
+    // set when two finalized blocks at the same height are observed, nothing is imported then
+    halted: AtomicBool,
+    proposal_policy: SafeProposalPolicy,
+    // checks the proposal transactions against the state before the node votes for it
+    proposal_validator: SafeProposalValidator,
+    // checks the seals of the synced blocks, it's the one of the engine
+    seal_verifier: SafeSealVerifier,
+    // the trusted checkpoints pinned by the config
+    checkpoints: Vec<TrustedCheckpoint>,
+    // the validator set of every epoch, decided by the hook of the engine
+    epochs: EpochManager,
+    // the slots of the dpos chains, it's set with the genesis block
+    schedule: Option<DposSchedule>,
     pub config: Config,
 }
 
 impl Chain {
     pub fn new(config: Config, ledger: Arc<RwLock<Ledger>>) -> Self {
-        let subscriber = Actor::create(|ctx| {
+        let (capacity, policy) = (config.event_queue_capacity, config.chain_event_policy);
+        let future_block_tolerance = config.clock.future_block_tolerance;
+        let subscriber = Actor::create(move |ctx| {
             ctx.set_mailbox_capacity(1024);
-            ProcessSignals::new()
+            ProcessSignals::with_policy(capacity, policy)
         });
+        let hook: SafeEpochHook = match config.engine {
+            EngineKind::Pbft => Box::new(StaticValidators),
+            EngineKind::Dpos => Box::new(DposElection::new(config.dpos.clone())),
+        };
+        // the validators without the minimum stake are dropped from the elected set
+        let hook: SafeEpochHook = Box::new(StakeFilter::new(hook));
+        let epochs = EpochManager::new(ledger.clone(), config.epoch_length, hook);
+        let proposal_validator: SafeProposalValidator = Arc::new(StateValidator::new(ledger.clone(), config.chain_id));
+        let seal_verifier = config.engine.seal_verifier();
         Chain {
             ledger,
+            epochs,
             subscriber: subscriber,
             lock: RwLock::new(()),
             config,
             sync_limiter: RwLock::new(Instant::now()),
+            orphans: RwLock::new(OrphanPool::new(ORPHAN_TTL, MAX_ORPHAN_BLOCKS)),
+            future_blocks: RwLock::new(FutureBlockQueue::new(future_block_tolerance)),
+            clock: Arc::new(SystemClock),
+            sync_target: RwLock::new(None),
+            halted: AtomicBool::new(false),
+            proposal_policy: Arc::new(DefaultProposalPolicy),
+            proposal_validator,
+            seal_verifier,
+            checkpoints: vec![],
             genesis: None,
+            schedule: None,
         }
     }
 
+    pub fn set_proposal_policy(&mut self, proposal_policy: SafeProposalPolicy) {
+        self.proposal_policy = proposal_policy;
+    }
+
+    pub fn proposal_policy(&self) -> &SafeProposalPolicy {
+        &self.proposal_policy
+    }
+
+    pub fn set_proposal_validator(&mut self, proposal_validator: SafeProposalValidator) {
+        self.proposal_validator = proposal_validator;
+    }
+
+    pub fn proposal_validator(&self) -> &SafeProposalValidator {
+        &self.proposal_validator
+    }
+
+    pub fn set_clock(&mut self, clock: SafeClock) {
+        self.clock = clock;
+    }
+
+    pub fn set_seal_verifier(&mut self, seal_verifier: SafeSealVerifier) {
+        self.seal_verifier = seal_verifier;
+    }
+
+    pub fn seal_verifier(&self) -> &SafeSealVerifier {
+        &self.seal_verifier
+    }
+
+    pub fn add_checkpoint(&mut self, checkpoint: TrustedCheckpoint) {
+        self.checkpoints.push(checkpoint);
+        self.checkpoints.sort_by_key(|checkpoint| checkpoint.height);
+    }
+
+    /// verify the stored blocks at the checkpoint heights, the store which was synced from
+    /// another fork can't be used
+    pub fn verify_checkpoints(&self) -> ChainResult {
+        for checkpoint in &self.checkpoints {
+            if let Some(block) = self.get_block_by_height(checkpoint.height) {
+                self.check_checkpoint(&block, &self.ledger.read())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_checkpoint(&self, block: &Block, ledger: &Ledger) -> ChainResult {
+        let validators = ledger.get_validator_addresses(block.height());
+        for checkpoint in self.checkpoints.iter().filter(|checkpoint| checkpoint.height == block.height()) {
+            if let Err(err) = checkpoint.verify(block, &validators) {
+                metrics::inc_counter("chain_checkpoint_rejected_total");
+                return Err(ChainError::Checkpoint(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// the highest checkpoint record, the blocks at or below its height are finalized
+    pub fn last_checkpoint(&self) -> Option<TrustedCheckpoint> {
+        self.ledger.read().get_schema().last_checkpoint().get()
+    }
+
+    pub fn get_checkpoint(&self, height: Height) -> Option<TrustedCheckpoint> {
+        self.ledger.read().get_schema().checkpoints().get(&height)
+    }
+
+    // persist a checkpoint record every `checkpoint_interval` blocks
+    fn record_checkpoint(&self, block: &Block, ledger: &mut Ledger) {
+        if !TrustedCheckpoint::is_due(block.height(), self.config.checkpoint_interval) {
+            return;
+        }
+        let validators = ledger.get_validator_addresses(block.height());
+        let checkpoint = TrustedCheckpoint::new(block, &validators);
+        ledger.get_schema().checkpoints().put(&checkpoint.height, checkpoint.clone());
+        ledger.get_schema().last_checkpoint().set(checkpoint);
+        metrics::set_gauge("chain_checkpoint_height", block.height() as i64);
+    }
+
+    /// verify the gas of the block which follows the last block
+    pub fn verify_gas(&self, block: &Block) -> ChainResult {
+        let parent = self.get_header_by_hash(&block.header().prev_hash)
+            .ok_or(ChainError::Unknown("Not found ancestor".to_owned()))?;
+        verify_block_gas(block.header(), block.transactions(), &parent, &self.config.gas).map_err(|err| {
+            metrics::inc_counter("chain_gas_rejected_total");
+            ChainError::Gas(err)
+        })
+    }
+
     pub fn insert_block(&self, block: &Block) -> ChainResult {
-        self.lock.write();
+        self.insert_committed_block(block, None)
+    }
+
+    /// insert the block which the local consensus committed, the commit certificate and the
+    /// consensus messages are written with the block
+    pub fn insert_committed_block(&self, block: &Block, commit: Option<(CommitCertificate, GossipMessages)>) -> ChainResult {
+        let _span = crate::pprof::span("chain_insert_block");
+        let round = commit.as_ref().map(|(certificate, _)| certificate.view.round);
+        // the fork choice, the halt check and the insert are serialized against the other importers
+        let guard = self.lock.write();
+        self.choose_fork(block)?;
+        if self.is_halted() {
+            return Err(ChainError::Halted);
+        }
 //        info!("Ready insert a new block, hash: {}, height: {}", block.hash().short(), block.height());
+        let mut duration = Duration::from_secs(0);
         {
             let mut ledger = self.ledger.write();
             if let Some(old_block) = ledger.get_block_by_height(block.height()) {
+                if old_block.hash() != block.hash() {
+                    let validators = ledger.get_validator_addresses(block.height());
+                    if let Some(evidence) = detect_conflict(old_block.header(), block.header(), &validators) {
+                        self.halt(&mut ledger, evidence);
+                        return Err(ChainError::SafetyViolation(block.height()));
+                    }
+                    // the finalized blocks are never replaced
+                    let finalized = ledger.get_schema().last_checkpoint().get().map_or(0, |checkpoint| checkpoint.height);
+                    if block.height() <= finalized {
+                        metrics::inc_counter("chain_checkpoint_rejected_total");
+                        return Err(ChainError::Checkpoint(format!("the fork is below the finalized height {}, height: {}", finalized, block.height())));
+                    }
+                }
                 return Err(ChainError::Exists(block.hash()));
             }
             let last_height = ledger.get_last_block_height();
             if last_height + 1 < block.height() {
-                self.post_event(ChainEvent::SyncBlock(last_height + 1));
+                self.start_sync(last_height + 1, block.height());
+                let mut orphans = self.orphans.write();
+                orphans.insert(block.clone());
+                if orphans.should_request(last_height + 1) {
+                    self.post_event(ChainEvent::SyncBlock(last_height + 1));
+                }
                 return Err(ChainError::Unknown("Not found ancestor".to_owned()));
             }
+            self.check_checkpoint(block, &ledger)?;
+            if let Some(parent) = ledger.get_block_header(&block.header().prev_hash) {
+                verify_block_gas(block.header(), block.transactions(), &parent, &self.config.gas).map_err(|err| {
+                    metrics::inc_counter("chain_gas_rejected_total");
+                    ChainError::Gas(err)
+                })?;
+                duration = Duration::from_secs(block.header().time.saturating_sub(parent.time));
+            }
 
-            ledger.add_block(block);
+            ledger.add_committed_block(block, commit);
+            self.record_checkpoint(block, &mut ledger);
+            self.record_missed_slots(block, &mut ledger);
         }
-        self.subscriber.do_send(ChainEvent::NewBlock(block.clone()));
-        self.subscriber.do_send(ChainEvent::NewHeader(block.header().clone()));
-//        Arbiter::spawn(self.subscriber.send(ChainEvent::NewBlock(block.clone())).then(|result| {
-//            futures::future::ok::<(), ()>(())
-//        }).map_err(|err| panic!(err)));
-//
-//        Arbiter::spawn(self.subscriber.send(ChainEvent::NewHeader(block.header().clone())).then(|result| {
-//            futures::future::ok::<(), ()>(())
-//        }).map_err(|err| panic!(err)));
+        // the orphans take the lock when they are inserted
+        drop(guard);
+        self.subscriber.do_send(ChainEvent::Committed { block: block.clone(), duration, round });
+        self.finish_sync(block.height());
+        self.import_orphans(block);
         Ok(())
     }
 
+    // the dpos fork choice: the block which is preferred to the head of the same parent replaces
+    // it, see `DposSchedule::prefer`. The finalized blocks are never replaced. The caller holds
+    // the lock.
+    fn choose_fork(&self, block: &Block) -> ChainResult {
+        let schedule = match self.schedule {
+            Some(ref schedule) => schedule,
+            None => return Ok(()),
+        };
+        let height = block.height();
+        let head = self.get_last_block();
+        if height == 0 || head.height() != height || head.hash() == block.hash() || head.header().prev_hash != block.header().prev_hash {
+            return Ok(());
+        }
+        if !schedule.prefer(head.header(), block.header()) {
+            return Ok(());
+        }
+        let finalized = self.last_checkpoint().map_or(0, |checkpoint| checkpoint.height);
+        if height <= finalized {
+            return Ok(());
+        }
+        self.verify_slot(block.header()).map_err(|err| ChainError::Unknown(format!("{}", err)))?;
+        self.seal_verifier.verify_seals(block.header(), &self.get_validator_addresses(height)).map_err(ChainError::Unknown)?;
+        self.verify_gas(block)?;
+        let blocks = self.truncate_locked(height - 1).map_err(ChainError::Unknown)?;
+        metrics::inc_counter("chain_dpos_fork_switch_total");
+        warn!("Switch to the block of the earlier slot, height: {}, old: {:?}, new: {:?}", height, head.hash().short(), block.hash().short());
+        self.post_event(ChainEvent::Reverted { blocks });
+        Ok(())
+    }
+
+    // persist the slots missed before the dpos block, the header carries the delegates
+    fn record_missed_slots(&self, block: &Block, ledger: &mut Ledger) {
+        let (schedule, istanbul) = match (self.schedule, block.header().istanbul_extra()) {
+            (Some(schedule), Some(istanbul)) => (schedule, istanbul),
+            _ => return,
+        };
+        let parent = match ledger.get_block_header(&block.header().prev_hash) {
+            Some(parent) => parent,
+            None => return,
+        };
+        let missed = schedule.missed(&parent, block.header(), &istanbul.validators);
+        if missed.0.is_empty() {
+            return;
+        }
+        let total: u64 = missed.0.iter().map(|(_, slots)| slots).sum();
+        warn!("The delegates missed {} slots before the block, height: {}", total, block.height());
+        metrics::inc_counter_by("dpos_missed_slots_total", total);
+        ledger.get_schema().dpos_missed_slots().put(&block.height(), missed);
+    }
+
+    /// the slot schedule of a dpos chain, none on pbft
+    pub fn dpos_schedule(&self) -> Option<&DposSchedule> {
+        self.schedule.as_ref()
+    }
+
+    /// the dpos block must be produced by the delegate of its slot after its parent, the parent is
+    /// looked up by the hash, so a fork of the head is verified too. The pbft blocks have no slot.
+    pub fn verify_slot(&self, header: &Header) -> Result<(), EngineError> {
+        let schedule = match self.schedule {
+            Some(ref schedule) => schedule,
+            None => return Ok(()),
+        };
+        let parent = self.get_header_by_hash(&header.prev_hash)
+            .ok_or(EngineError::UnknownAncestor(header.height, header.height.saturating_sub(1)))?;
+        schedule.verify_header(header, &parent, &self.get_validator_addresses(header.height))
+    }
+
+    /// the slots missed by every delegate, they're recorded with the blocks
+    pub fn get_missed_slots(&self) -> HashMap<Address, u64> {
+        let mut missed = HashMap::new();
+        for (_, slots) in self.ledger.read().get_schema().dpos_missed_slots().iter() {
+            for (delegate, count) in slots.0 {
+                *missed.entry(delegate).or_insert(0) += count;
+            }
+        }
+        missed
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.sync_target.read().is_some()
+    }
+
+    // the missing blocks are requested, the sync is reported once until the target is reached
+    fn start_sync(&self, from: Height, to: Height) {
+        let mut target = self.sync_target.write();
+        match *target {
+            Some(ref mut height) => *height = ::std::cmp::max(*height, to),
+            None => {
+                info!("Start to sync blocks, from: {}, to: {}", from, to);
+                *target = Some(to);
+                self.subscriber.do_send(ChainEvent::SyncStarted { from, to });
+            }
+        }
+    }
+
+    fn finish_sync(&self, height: Height) {
+        let mut target = self.sync_target.write();
+        if target.map_or(false, |target| height >= target) {
+            info!("Sync blocks finished, height: {}", height);
+            *target = None;
+            self.subscriber.do_send(ChainEvent::SyncFinished { height });
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+    }
+
+    /// the evidence of the safety violation which halted the node
+    pub fn safety_evidence(&self) -> Option<SafetyEvidence> {
+        self.ledger.read().get_schema().safety_evidence().get()
+    }
+
+    // persist the evidence, stop the imports and the minner, raise the alarms and exit the process
+    fn halt(&self, ledger: &mut Ledger, evidence: SafetyEvidence) {
+        if self.halted.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        error!("Safety violation, conflicting finalized blocks at height {}, first: {:?}, second: {:?}, equivocators: {:?}",
+               evidence.height, evidence.first.block_hash(), evidence.second.block_hash(), evidence.equivocators());
+        ledger.get_schema().safety_evidence().set(evidence.clone());
+        metrics::inc_counter("chain_safety_violation_total");
+        metrics::set_gauge("chain_halted", 1);
+        self.subscriber.do_send(ChainEvent::SafetyViolation(evidence));
+        ::std::thread::spawn(|| {
+            ::std::thread::sleep(SAFETY_EXIT_DELAY);
+            ::std::process::exit(SAFETY_EXIT_CODE);
+        });
+    }
+
+    // import the orphans which wait for the block, in height order
+    fn import_orphans(&self, parent: &Block) {
+        let children = self.orphans.write().take_children(&parent.hash());
+        for child in children {
+            match self.insert_block(&child) {
+                Ok(()) => metrics::inc_counter("chain_orphan_imported_total"),
+                Err(err) => debug!("Failed to import orphan block, hash: {}, err: {:?}", child.hash().short(), err),
+            }
+        }
+    }
+
+    /// admit the block gossiped by the peer, the held block is imported by `import_future_blocks`
+    /// once its timestamp is valid
+    pub fn admit_future_block(&self, peer: &str, block: &Block) -> Admission {
+        let now = self.clock.now();
+        self.future_blocks.write().admit(peer, block, now)
+    }
+
+    /// the wait until the first held future block is valid, none if no block is held
+    pub fn next_future_block(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.future_blocks.read().next_ready().map(|time| Duration::from_secs(time.saturating_sub(now)))
+    }
+
+    pub fn import_future_blocks(&self) {
+        let now = self.clock.now();
+        let blocks = self.future_blocks.write().pop_ready(now);
+        for block in blocks {
+            match self.insert_block(&block) {
+                Ok(()) => metrics::inc_counter("chain_future_block_imported_total"),
+                Err(err) => debug!("Failed to import future block, hash: {}, err: {:?}", block.hash().short(), err),
+            }
+        }
+    }
+
     pub fn insert_block_mock(block: &Block, ledger: Arc<RwLock<Ledger>>) -> ChainResult {
         info!("Ready insert a new block, hash: {}, height: {}", block.hash().short(), block.height());
         {
@@ -107,8 +463,8 @@ impl Chain {
         }
     }
 
-    pub fn get_transactions(&self) -> Vec<Transaction> {
-        self.ledger.read().get_transactions()
+    pub fn get_transactions_page(&self, offset: usize, count: usize) -> Vec<Transaction> {
+        self.ledger.read().get_transactions_page(offset, count)
     }
 
     pub fn get_block_hash_by_height(&self, height: Height) -> Option<Hash> {
@@ -119,20 +475,143 @@ impl Chain {
         self.ledger.read().get_header_by_height(height)
     }
 
+    /// the block which is active at the unix time, it's the last block whose timestamp is not
+    /// after it, None if the time is before the genesis
+    pub fn get_block_at_time(&self, time: Timestamp) -> Option<Block> {
+        let height = {
+            let ledger = self.ledger.read();
+            let last_height = *ledger.get_last_block_height();
+            search_height_by_time(last_height, time, |height| ledger.get_header_by_height(height).map(|header| header.time))?
+        };
+        self.get_block_by_height(height)
+    }
+
+    /// the confirmed state of the account, it's the default account if it never appears
+    pub fn get_account(&self, address: &Address) -> Account {
+        self.ledger.read().get_account(address)
+    }
+
+    /// the account at the height, the latest committed state by default, it's none if the height is
+    /// not committed yet or its state is pruned
+    pub fn get_account_state(&self, address: &Address, height: Option<Height>) -> Option<AccountState> {
+        let ledger = self.ledger.read();
+        let last_height = *ledger.get_last_block_height();
+        let height = height.unwrap_or(last_height);
+        if height > last_height {
+            return None;
+        }
+        let account = if height == last_height {
+            ledger.get_account(address)
+        } else {
+            ledger.get_account_at(address, height).map_err(|err| debug!("Read the account state, height: {}, err: {}", height, err)).ok()?
+        };
+        let last_active_height = ledger.last_address_tx_height(address, height);
+        Some(AccountState { address: *address, height, balance: account.balance, nonce: account.nonce, last_active_height })
+    }
+
+    /// the (height, tx hash) of the transactions which send to or from the address
+    pub fn get_address_txs(&self, address: &Address) -> Vec<(Height, Hash)> {
+        self.ledger.read().get_address_txs(address)
+    }
+
+    pub fn get_address_txs_page(&self, address: &Address, offset: usize, count: usize) -> Vec<(Height, Hash)> {
+        self.ledger.read().get_address_txs_page(address, offset, count)
+    }
+
+    pub fn count_address_txs(&self, address: &Address) -> usize {
+        self.ledger.read().count_address_txs(address)
+    }
+
+    pub fn get_header_by_hash(&self, block_hash: &Hash) -> Option<Header> {
+        self.ledger.read().get_block_header(block_hash)
+    }
+
+    pub fn get_headers(&self, from: Height, to: Height) -> Vec<Header> {
+        self.ledger.read().get_headers(from, to)
+    }
+
+    /// iterate the blocks in [from, to] with read-ahead, it's for the sequential scans
+    pub fn iter_blocks(&self, from: Height, to: Height) -> BlockReadAhead {
+        BlockReadAhead::new(self.ledger.clone(), from, to, DEFAULT_READ_AHEAD)
+    }
+
     pub fn get_last_hash(&self) -> Hash {
         self.ledger.read().get_last_block_hash().clone()
     }
 
-    pub fn add_validators(&self, _height: Height, validators: Vec<Address>) -> ChainResult {
+    pub fn get_consensus_messages(&self, height: Height) -> Option<GossipMessages> {
+        self.ledger.read().get_consensus_messages(height)
+    }
+
+    /// the account states after the block at the height with the validator set of its epoch,
+    /// see `Ledger::state_at`, the replay blocks, so the async callers run it on a blocking pool
+    pub fn state_at(&self, height: Height) -> Result<StateView, String> {
+        let view = self.ledger.read().state_at(height)?;
+        Ok(view.with_validators(self.get_validator_addresses(height)))
+    }
+
+    pub fn get_state_root(&self, height: Height) -> Option<Hash> {
+        self.ledger.read().get_state_root(height)
+    }
+
+    pub fn get_stake(&self, validator: &Address) -> ValidatorStake {
+        self.ledger.read().get_stake(validator)
+    }
+
+    pub fn get_rewards(&self, address: &Address) -> RewardHistory {
+        self.ledger.read().get_rewards(address)
+    }
+
+    pub fn get_rewards_page(&self, address: &Address, offset: usize, count: usize) -> Vec<(Height, u64)> {
+        self.ledger.read().get_rewards_page(address, offset, count)
+    }
+
+    pub fn get_receipts(&self, height: Height) -> Vec<Receipt> {
+        self.ledger.read().get_receipts(height)
+    }
+
+    pub fn get_logs(&self, filter: &LogFilter, limit: usize) -> Vec<LogEntry> {
+        self.ledger.read().get_logs(filter, limit)
+    }
+
+    /// the proof that the block at height is finalized, the blocks imported by the sync have none
+    pub fn get_commit_certificate(&self, height: Height) -> Option<CommitCertificate> {
+        self.ledger.read().get_commit_certificate(height)
+    }
+
+    /// the new validators take effect from the first epoch which starts at or after the height
+    pub fn add_validators(&self, height: Height, validators: Vec<Address>) -> ChainResult {
         let validators = validators.iter().map(|address| Validator::new(*address)).collect();
-        self.ledger.write().add_validators(validators);
+        self.ledger.write().schedule_validators(height, validators);
         Ok(())
     }
 
-    // FIXME: Opz avoid to copy validator memory
-    pub fn get_validators(&self, height: Height) -> Validators {
-        let ledger = self.ledger.write();
-        ledger.get_validators(height).clone()
+    /// the validators of the epoch of the height
+    pub fn get_validator_addresses(&self, height: Height) -> Vec<Address> {
+        self.epochs.epoch_at(height).validators
+    }
+
+    /// the validators of the height if its epoch is decided, see `EpochManager::decided_epoch_at`
+    pub fn get_decided_validators(&self, height: Height) -> Option<Vec<Address>> {
+        self.epochs.decided_epoch_at(height).map(|epoch| epoch.validators)
+    }
+
+    pub fn get_epoch(&self, height: Height) -> Epoch {
+        self.epochs.epoch_at(height)
+    }
+
+    /// drop the blocks above the last good height, the epochs are decided again from the shorter
+    /// chain. It returns the dropped blocks, the highest first
+    pub fn truncate(&self, last_good: Height) -> Result<Vec<Block>, String> {
+        let _guard = self.lock.write();
+        self.truncate_locked(last_good)
+    }
+
+    // `truncate` by the caller which holds the lock
+    fn truncate_locked(&self, last_good: Height) -> Result<Vec<Block>, String> {
+        let (_, reverted) = truncate_chain(&mut self.ledger.write(), last_good)?;
+        self.epochs.clear();
+        Ok(reverted)
     }
 
     pub fn get_genesis(&self) -> &Block {
@@ -140,6 +619,12 @@ impl Chain {
     }
 
     pub fn store_genesis_block(&mut self) -> ChainResult {
+        let genesis_config = self.config.genesis.as_ref().unwrap();
+        if let Some(chain_id) = genesis_config.chain_id {
+            if chain_id != self.config.chain_id {
+                return Err(ChainError::Unknown(format!("genesis chain id mismatch, config: {}, genesis: {}", self.config.chain_id, chain_id)));
+            }
+        }
         let result = store_genesis_block(self.config.genesis.as_ref().unwrap(), self.ledger.clone())
             .map_err(ChainError::Unknown);
         if result.is_ok() {
@@ -147,12 +632,29 @@ impl Chain {
                 let mut ledger = self.ledger.write();
                 ledger.get_genesis_block().unwrap().clone()
             };
-            self.genesis = Some(genesis);
+            self.set_genesis(genesis);
         }
 
         result
     }
 
+    /// load the stored genesis block without writing, it's used by the read-only inspector
+    pub fn load_genesis_block(&mut self) -> ChainResult {
+        let genesis = self.ledger.write().get_genesis_block().cloned();
+        let genesis = genesis.ok_or(ChainError::Unknown("genesis block is missing".to_owned()))?;
+        self.set_genesis(genesis);
+        Ok(())
+    }
+
+    // the dpos slots start from the genesis time
+    fn set_genesis(&mut self, genesis: Block) {
+        self.schedule = match self.config.engine {
+            EngineKind::Dpos => Some(DposSchedule::new(genesis.header().time, self.config.block_period)),
+            EngineKind::Pbft => None,
+        };
+        self.genesis = Some(genesis);
+    }
+
     pub fn get_subscriber(&self) -> Addr<ProcessSignals> {
         self.subscriber.clone()
     }
@@ -175,26 +677,53 @@ impl Chain {
     }
 }
 
+impl AccountReader for Chain {
+    fn get_account(&self, address: &Address) -> Account {
+        Chain::get_account(self, address)
+    }
+}
+
+// binary search the last height whose timestamp is not after the time, the timestamps increase
+// with the heights
+fn search_height_by_time<F>(last_height: Height, time: Timestamp, time_at: F) -> Option<Height>
+    where F: Fn(Height) -> Option<Timestamp> {
+    if time_at(0)? > time {
+        return None;
+    }
+    let (mut low, mut high) = (0, last_height);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if time_at(mid)? <= time {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Some(low)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::common::random_dir;
     use cryptocurrency_kit::ethkey::{Generator, Random};
-    use kvdb_rocksdb::Database;
+    use crate::store::backend::Backend;
+    use crate::store::memory::MemoryDB;
     use crate::store::schema::Schema;
     use crate::core::ledger::{Ledger, LastMeta};
     use lru_time_cache::LruCache;
     use std::sync::Arc;
     use parking_lot::RwLock;
     use cryptocurrency_kit::crypto::EMPTY_HASH;
-
+    use crate::types::Bloom;
+    use crate::common::hash_algo::{with_hash_algo, HashAlgo};
+    use crate::core::test_util::{memory_chain, sealed_block};
+    use crate::types::votes::verify_block_seals;
 
     #[test]
     fn t_batch() {
         let secret = Random.generate().unwrap();
 
-        let database = Database::open_default(&random_dir()).map_err(|err| err.to_string()).unwrap();
-        let schema = Schema::new(Arc::new(database));
+        let schema = Schema::new(Arc::new(Backend::Memory(MemoryDB::new())));
         let mut ledger = Ledger::new(
             LastMeta::new_zero(),
             LruCache::with_capacity(1 << 10),
@@ -204,7 +733,7 @@ mod test {
         );
 
         let mut header = Header::new(EMPTY_HASH, Address::from(10), EMPTY_HASH, EMPTY_HASH, EMPTY_HASH,
-                                     0, 0, 0, 10, 10,
+                                     Bloom::zero(), 0, 0, 10, 10,
                                      chrono::Local::now().timestamp() as u64, None, Some(vec![12, 1]));
         let block = Block::new(header, vec![]);
 
@@ -215,7 +744,7 @@ mod test {
 
         (1_u64..10).for_each(|height| {
             let mut header = Header::new(EMPTY_HASH, Address::from(10), EMPTY_HASH, EMPTY_HASH, EMPTY_HASH,
-                                         0, 0, height, 10, 10,
+                                         Bloom::zero(), 0, height, 10, 10,
                                          chrono::Local::now().timestamp() as u64, None, Some(vec![12, 1]));
             let block = Block::new(header, vec![]);
 
@@ -237,4 +766,46 @@ mod test {
 
         println!("last_block {:?}", ledger.get_last_block());
     }
+
+    #[test]
+    fn t_search_height_by_time() {
+        let times: Vec<Timestamp> = vec![100, 103, 106, 109, 115];
+        let time_at = |height: Height| times.get(height as usize).cloned();
+        assert_eq!(search_height_by_time(4, 99, time_at), None);
+        assert_eq!(search_height_by_time(4, 100, time_at), Some(0));
+        assert_eq!(search_height_by_time(4, 108, time_at), Some(2));
+        assert_eq!(search_height_by_time(4, 109, time_at), Some(3));
+        assert_eq!(search_height_by_time(4, 1000, time_at), Some(4));
+    }
+
+    #[test]
+    fn t_hash_algo_chain() {
+        let _system = System::new("t_hash_algo_chain");
+        let key_pair = Random.generate().unwrap();
+        let default_genesis = memory_chain(&key_pair).get_last_hash();
+        let (chain, blocks) = with_hash_algo(HashAlgo::Keccak256, || {
+            let chain = memory_chain(&key_pair);
+            let mut parent = chain.get_last_hash();
+            let mut blocks = vec![];
+            for height in 1..5 {
+                let block = sealed_block(parent, height, &key_pair);
+                parent = block.hash();
+                chain.insert_block(&block).unwrap();
+                blocks.push(block);
+            }
+            for block in &blocks {
+                assert_eq!(block.hash(), HashAlgo::Keccak256.digest(block.header().rlp_bytes_without_votes()));
+                assert_eq!(chain.get_block_by_hash(&block.hash()).unwrap().height(), block.height());
+            }
+            (chain, blocks)
+        });
+        // the genesis block and the seals are hashed by the chain's algo
+        assert_ne!(with_hash_algo(HashAlgo::Keccak256, || chain.get_block_by_height(0).unwrap().hash()), default_genesis);
+        let header = blocks[3].header().clone();
+        assert!(with_hash_algo(HashAlgo::Keccak256, || verify_block_seals(&header, &[key_pair.address()])).is_ok());
+        assert!(with_hash_algo(HashAlgo::Blake2b, || verify_block_seals(&header, &[key_pair.address()])).is_err());
+        // the blocks of another algo don't link
+        let other = sealed_block(blocks[3].hash(), 5, &key_pair);
+        assert!(with_hash_algo(HashAlgo::Blake2b, || chain.insert_block(&other)).is_err());
+    }
 }
@@ -0,0 +1,122 @@
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use crate::common::hash_algo::digest;
+use crate::types::block::Block;
+use crate::types::Height;
+
+// a checkpoint record is persisted every N blocks by default
+pub const DEFAULT_CHECKPOINT_INTERVAL: Height = 1000;
+
+/// the trusted checkpoint in the config, more checkpoints can be pinned with `[[checkpoints]]`, eg:
+///
+/// ```toml
+/// [checkpoint]
+/// height = 100000
+/// block_hash = "0x..."
+/// validators_hash = "0x..."
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointConfig {
+    pub height: Height,
+    pub block_hash: String,
+    pub validators_hash: String,
+}
+
+/// TrustedCheckpoint pins the block and the validator set at a height, the chains which
+/// don't include it are refused, so a fresh node can't be fed a long-range fork. The node
+/// persists one every `checkpoint_interval` blocks too, the blocks below it are finalized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrustedCheckpoint {
+    pub height: Height,
+    pub block_hash: Hash,
+    pub validators_hash: Hash,
+}
+
+implement_storagevalue_traits! {TrustedCheckpoint}
+implement_hash_algo_traits! {TrustedCheckpoint}
+
+impl TrustedCheckpoint {
+    pub fn new(block: &Block, validators: &[Address]) -> Self {
+        TrustedCheckpoint {
+            height: block.height(),
+            block_hash: block.hash(),
+            validators_hash: validators_hash(validators),
+        }
+    }
+
+    /// whether the block at the height should be recorded as a checkpoint
+    pub fn is_due(height: Height, interval: Height) -> bool {
+        interval > 0 && height > 0 && height % interval == 0
+    }
+
+    pub fn from_config(config: &CheckpointConfig) -> Result<Self, String> {
+        Ok(TrustedCheckpoint {
+            height: config.height,
+            block_hash: parse_hash(&config.block_hash)?,
+            validators_hash: parse_hash(&config.validators_hash)?,
+        })
+    }
+
+    /// verify the block and its validator set, the blocks at the other heights always pass
+    pub fn verify(&self, block: &Block, validators: &[Address]) -> Result<(), String> {
+        if block.height() != self.height {
+            return Ok(());
+        }
+        if block.hash() != self.block_hash {
+            return Err(format!("block hash mismatch, height: {}, expect: {:?}, got: {:?}", self.height, self.block_hash, block.hash()));
+        }
+        let validators_hash = validators_hash(validators);
+        if validators_hash != self.validators_hash {
+            return Err(format!("validators hash mismatch, height: {}, expect: {:?}, got: {:?}", self.height, self.validators_hash, validators_hash));
+        }
+        Ok(())
+    }
+}
+
+/// the hash of the sorted validator addresses
+pub fn validators_hash(validators: &[Address]) -> Hash {
+    let mut validators = validators.to_vec();
+    validators.sort();
+    let bytes: Vec<u8> = validators.iter().flat_map(|address| address.to_vec()).collect();
+    digest(bytes)
+}
+
+fn parse_hash(s: &str) -> Result<Hash, String> {
+    hex::decode(s.trim_start_matches("0x")).ok()
+        .and_then(|bytes| Hash::from_slice(&bytes))
+        .ok_or(format!("invalid hash: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::types::block::Header;
+
+    #[test]
+    fn t_trusted_checkpoint() {
+        let block = Block::new(Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, 10, 0, None), vec![]);
+        let validators = vec![Address::from(2), Address::from(1)];
+        let config = CheckpointConfig {
+            height: 10,
+            block_hash: format!("0x{}", hex::encode(block.hash().as_ref())),
+            validators_hash: hex::encode(validators_hash(&[Address::from(1), Address::from(2)]).as_ref()),
+        };
+        let checkpoint = TrustedCheckpoint::from_config(&config).unwrap();
+        assert!(checkpoint.verify(&block, &validators).is_ok());
+        assert!(checkpoint.verify(&block, &validators[..1]).is_err());
+
+        let fork = Block::new(Header::new_mock(EMPTY_HASH, Address::from(3), EMPTY_HASH, 10, 0, None), vec![]);
+        assert!(checkpoint.verify(&fork, &validators).is_err());
+        let other = Block::new(Header::new_mock(EMPTY_HASH, Address::from(3), EMPTY_HASH, 11, 0, None), vec![]);
+        assert!(checkpoint.verify(&other, &validators).is_ok());
+
+        assert_eq!(TrustedCheckpoint::new(&block, &validators), checkpoint);
+        assert!(TrustedCheckpoint::is_due(2000, DEFAULT_CHECKPOINT_INTERVAL));
+        assert!(!TrustedCheckpoint::is_due(0, DEFAULT_CHECKPOINT_INTERVAL));
+        assert!(!TrustedCheckpoint::is_due(2000, 0));
+    }
+}
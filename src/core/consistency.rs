@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use cryptocurrency_kit::crypto::{Hash, EMPTY_HASH};
+use cryptocurrency_kit::ethkey::{Address, Generator, Random, Secret};
+use lru_time_cache::LruCache;
+use parking_lot::RwLock;
+use rand::Rng;
+
+use crate::{
+    store::backend::Backend,
+    store::schema::Schema,
+    types::block::{Block, Header},
+    types::transaction::{Transaction, merkle_root_transactions},
+    types::Height,
+    util::rng::seeded_rng,
+};
+use super::ledger::{LastMeta, Ledger};
+use super::read_ahead::BlockReadAhead;
+
+// small caches, so the evictions happen frequently
+const FUZZ_CACHE_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FuzzReport {
+    pub seed: u64,
+    pub steps: u64,
+    pub commits: u64,
+    pub duplicate_commits: u64,
+    pub evictions: u64,
+    pub restarts: u64,
+    pub reads: u64,
+    pub scans: u64,
+    pub height: Height,
+}
+
+/// Run randomized interleavings of block commits, cache evictions, simulated restarts and
+/// reads against a scratch store in `dir`, and check the ledger invariants after every step:
+///
+/// - the height never goes backwards
+/// - the height index and the header index agree
+/// - the last meta matches the last indexed block
+///
+/// The same seed replays the same operations, the error carries the seed and the step.
+pub fn fuzz_ledger(dir: &str, seed: u64, steps: u64) -> Result<FuzzReport, String> {
+    let mut rng = seeded_rng(seed);
+    let secret = Random.generate().unwrap().secret().clone();
+    let mut ledger = open_ledger(dir)?;
+    let genesis = Block::new(Header::new_mock(EMPTY_HASH, Address::from(0), EMPTY_HASH, 0, 0, None), vec![]);
+    ledger.write().add_genesis_block(&genesis);
+
+    // the committed hashes by height, it's the model of the ledger
+    let mut model: Vec<Hash> = vec![genesis.hash()];
+    let mut report = FuzzReport { seed, steps, ..FuzzReport::default() };
+    for step in 0..steps {
+        match rng.gen_range(0, 100) {
+            0..=39 => {
+                let block = next_block(&mut rng, &secret, &ledger.read());
+                ledger.write().add_block(&block);
+                model.push(block.hash());
+                report.commits += 1;
+            }
+            40..=44 => {
+                // commit an old block again, it must be ignored
+                let height = rng.gen_range(1, model.len() as u64 + 1);
+                let block = ledger.read().get_block_by_height(height);
+                if let Some(block) = block {
+                    ledger.write().add_block(&block);
+                    report.duplicate_commits += 1;
+                }
+            }
+            45..=54 => {
+                ledger.write().clear_caches();
+                report.evictions += 1;
+            }
+            55..=59 => {
+                // drop the database and reopen it
+                drop(ledger);
+                ledger = open_ledger(dir)?;
+                ledger.write().reload_meta();
+                report.restarts += 1;
+            }
+            60..=89 => {
+                let height = rng.gen_range(0, model.len() as u64 + 2);
+                check_read(&ledger.read(), &model, height).map_err(|err| fuzz_error(seed, step, err))?;
+                report.reads += 1;
+            }
+            _ => {
+                let from = rng.gen_range(0, model.len() as u64);
+                let to = from + rng.gen_range(0, 16);
+                check_scan(&ledger, &model, from, to).map_err(|err| fuzz_error(seed, step, err))?;
+                report.scans += 1;
+            }
+        }
+        check_invariants(&ledger.read(), &model).map_err(|err| fuzz_error(seed, step, err))?;
+    }
+    report.height = *ledger.read().get_last_block_height();
+    Ok(report)
+}
+
+fn fuzz_error(seed: u64, step: u64, err: String) -> String {
+    format!("ledger consistency is broken, seed: {}, step: {}, err: {}", seed, step, err)
+}
+
+fn open_ledger(dir: &str) -> Result<Arc<RwLock<Ledger>>, String> {
+    let backend = Backend::open(dir)?;
+    let ledger = Ledger::new(
+        LastMeta::new_zero(),
+        LruCache::with_capacity(FUZZ_CACHE_SIZE),
+        LruCache::with_capacity(FUZZ_CACHE_SIZE),
+        vec![],
+        Schema::new(Arc::new(backend)),
+    );
+    Ok(Arc::new(RwLock::new(ledger)))
+}
+
+fn next_block<R: Rng>(rng: &mut R, secret: &Secret, ledger: &Ledger) -> Block {
+    let pre_header = ledger.get_last_block_header();
+    let transactions: Vec<Transaction> = (0..rng.gen_range(0, 4)).map(|_| {
+        let mut transaction = Transaction::new(rng.gen(), Address::from(rng.gen::<u64>()), rng.gen_range(0, 1000), 21000, 1, vec![]);
+        transaction.sign(0, secret);
+        transaction
+    }).collect();
+    let tx_hash = merkle_root_transactions(transactions.clone());
+    let header = Header::new_mock(pre_header.block_hash(), Address::from(1), tx_hash, pre_header.height + 1, pre_header.time + 1, None);
+    Block::new(header, transactions)
+}
+
+fn check_invariants(ledger: &Ledger, model: &[Hash]) -> Result<(), String> {
+    let schema = ledger.get_schema();
+    let height = schema.height();
+    if height + 1 != model.len() as u64 {
+        return Err(format!("height index mismatch, expect: {}, got: {}", model.len() - 1, height));
+    }
+    if *ledger.get_last_block_height() != height {
+        return Err(format!("meta height mismatch, expect: {}, got: {}", height, ledger.get_last_block_height()));
+    }
+    let last_hash = schema.block_hash_by_height(height).ok_or("last hash is missing")?;
+    if *ledger.get_last_block_hash() != last_hash || last_hash != model[height as usize] {
+        return Err(format!("meta hash mismatch, height: {}", height));
+    }
+    if ledger.get_last_block_header().block_hash() != last_hash {
+        return Err(format!("meta header mismatch, height: {}", height));
+    }
+    Ok(())
+}
+
+fn check_read(ledger: &Ledger, model: &[Hash], height: Height) -> Result<(), String> {
+    let block = ledger.get_block_by_height(height);
+    let header = ledger.get_header_by_height(height);
+    match model.get(height as usize) {
+        Some(hash) => {
+            let block = block.ok_or(format!("block is missing, height: {}", height))?;
+            let header = header.ok_or(format!("header is missing, height: {}", height))?;
+            if block.hash() != *hash || header.block_hash() != *hash || header.height != height {
+                return Err(format!("hash/height index disagree, height: {}", height));
+            }
+            let by_hash = ledger.get_block(hash).ok_or(format!("block is missing, hash: {:?}", hash))?;
+            if by_hash.height() != height || by_hash.transactions().len() != block.transactions().len() {
+                return Err(format!("block by hash disagrees with block by height, height: {}", height));
+            }
+        }
+        None => {
+            if block.is_some() || header.is_some() {
+                return Err(format!("read an uncommitted height: {}", height));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_scan(ledger: &Arc<RwLock<Ledger>>, model: &[Hash], from: Height, to: Height) -> Result<(), String> {
+    let window = (to - from) / 2 + 1;
+    let hashes: Vec<Hash> = BlockReadAhead::new(ledger.clone(), from, to, window).map(|block| block.hash()).collect();
+    let last = ::std::cmp::min(to as usize + 1, model.len());
+    if hashes[..] != model[from as usize..last] {
+        return Err(format!("read ahead scan mismatch, from: {}, to: {}", from, to));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_fuzz_ledger() {
+        let report = fuzz_ledger(&random_dir(), 7, 300).unwrap();
+        assert_eq!(report.height, report.commits);
+        assert_eq!(report.steps, 300);
+    }
+}
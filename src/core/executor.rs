@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+
+use crate::consensus::dpos::delegates::{voted_delegates, DPOS_VOTE_ADDRESS};
+use crate::core::fee::FeeConfig;
+use crate::core::gas::intrinsic_gas;
+use crate::core::staking::{staking_op, StakingOp, STAKING_ADDRESS};
+use crate::store::map_index::MapIndex;
+use crate::store::types::Fork;
+use crate::types::account::Account;
+use crate::types::block::Header;
+use crate::types::receipt::{address_topic, event_topic, logs_bloom, Log, Receipt, MINT_TOPIC, TRANSFER_TOPIC};
+use crate::types::transaction::Transaction;
+use crate::types::{Bloom, Height};
+
+lazy_static! {
+    pub static ref VOTE_TOPIC: Hash = event_topic("Vote(address,address[])");
+    pub static ref BOND_TOPIC: Hash = event_topic("Bond(address,address,uint64)");
+    pub static ref UNBOND_TOPIC: Hash = event_topic("Unbond(address,address,uint64)");
+}
+
+/// the account states which the transactions are applied to, the store or a replayed state
+pub trait Accounts {
+    fn account(&self, address: &Address) -> Account;
+    fn set_account(&mut self, address: &Address, account: Account);
+}
+
+impl<T: Fork + ?Sized> Accounts for MapIndex<Address, Account, T> {
+    fn account(&self, address: &Address) -> Account {
+        self.get(address).unwrap_or_default()
+    }
+
+    fn set_account(&mut self, address: &Address, account: Account) {
+        self.put(address, account);
+    }
+}
+
+impl Accounts for BTreeMap<Address, Account> {
+    fn account(&self, address: &Address) -> Account {
+        self.get(address).cloned().unwrap_or_default()
+    }
+
+    fn set_account(&mut self, address: &Address, account: Account) {
+        self.insert(*address, account);
+    }
+}
+
+/// the outcome of a transaction applied to the accounts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TxOutcome {
+    // the amount is moved to the recipient, eg: a bond which isn't moved stakes nothing
+    pub moved: bool,
+    // the charged fee, at most the balance left after the amount
+    pub fee: u64,
+}
+
+/// apply the transactions of a block to the accounts, the coinbase transaction (position 0) only
+/// mints the amount to the proposer, the senders of the others pay the fees. The amount of a
+/// transaction which the balance of its sender doesn't cover isn't moved, only its nonce is used,
+/// and the fee is charged up to the balance left, so the proposer is never paid more than the
+/// senders were charged.
+pub fn apply_transactions<A: Accounts>(accounts: &mut A, transactions: &[Transaction], fees: &FeeConfig) -> Vec<TxOutcome> {
+    transactions.iter().enumerate().map(|(position, transaction)| {
+        let outcome = if position == 0 {
+            TxOutcome { moved: true, fee: 0 }
+        } else {
+            match transaction.sender() {
+                Some(sender) => {
+                    let mut account = accounts.account(&sender);
+                    let moved = account.try_debit(transaction);
+                    let fee = account.charge(fees.fee(transaction));
+                    accounts.set_account(&sender, account);
+                    TxOutcome { moved, fee }
+                }
+                None => TxOutcome::default(),
+            }
+        };
+        if let (true, Some(recipient)) = (outcome.moved, transaction.to()) {
+            let mut account = accounts.account(recipient);
+            account.credit(transaction.amount());
+            accounts.set_account(recipient, account);
+        }
+        outcome
+    }).collect()
+}
+
+/// the logs emitted by the transaction at the position of the block, the coinbase transaction
+/// (position 0) mints its amount, the others transfer their amounts, the dpos votes and the
+/// staking transactions emit a vote or a bond/unbond log too
+pub fn transaction_logs(position: usize, transaction: &Transaction) -> Vec<Log> {
+    let to = match transaction.to() {
+        Some(to) => *to,
+        None => return vec![],
+    };
+    let amount = transaction.amount().to_be_bytes().to_vec();
+    if position == 0 {
+        return vec![Log { address: to, topics: vec![*MINT_TOPIC, address_topic(&to)], data: amount }];
+    }
+    let from = match transaction.sender() {
+        Some(from) => from,
+        None => return vec![],
+    };
+    let mut logs = vec![Log { address: from, topics: vec![*TRANSFER_TOPIC, address_topic(&from), address_topic(&to)], data: amount }];
+    if let Some(delegates) = voted_delegates(transaction, usize::max_value()) {
+        let data = delegates.iter().flat_map(|delegate| delegate.to_vec()).collect();
+        logs.push(Log { address: *DPOS_VOTE_ADDRESS, topics: vec![*VOTE_TOPIC, address_topic(&from)], data });
+    }
+    // the unbonded amount is requested, the bonded amount may be less
+    let (topic, validator, staked) = match staking_op(transaction) {
+        Some(StakingOp::Bond(validator)) => (*BOND_TOPIC, validator, transaction.amount()),
+        Some(StakingOp::Unbond(validator, amount)) => (*UNBOND_TOPIC, validator, amount),
+        None => return logs,
+    };
+    logs.push(Log { address: *STAKING_ADDRESS, topics: vec![topic, address_topic(&from), address_topic(&validator)], data: staked.to_be_bytes().to_vec() });
+    logs
+}
+
+/// execute the transactions of the block at the height into their receipts with the outcomes of
+/// `apply_transactions`, the coinbase transaction uses no gas and pays no fee
+pub fn execute_block(height: Height, transactions: &[Transaction], outcomes: &[TxOutcome], fees: &FeeConfig) -> Vec<Receipt> {
+    transactions.iter().zip(outcomes).enumerate().map(|(position, (transaction, outcome))| {
+        let (gas_used, fee) = if position == 0 { (0, 0) } else { (intrinsic_gas(transaction), outcome.fee) };
+        Receipt {
+            tx_hash: transaction.hash(),
+            height,
+            position: position as u64,
+            logs: transaction_logs(position, transaction),
+            gas_used,
+            fee,
+            burned: fees.split(fee).1,
+        }
+    }).collect()
+}
+
+/// the bloom of the header, it indexes the logs of all transactions, they're the logs of the
+/// receipts of `execute_block`, which don't depend on the account states
+pub fn block_bloom(transactions: &[Transaction]) -> Bloom {
+    let logs: Vec<Log> = transactions.iter().enumerate().flat_map(|(position, transaction)| transaction_logs(position, transaction)).collect();
+    logs_bloom(&logs)
+}
+
+/// the bloom of the header must be the bloom of the receipts of its transactions, the zero bloom
+/// is only a block without logs
+pub fn verify_block_bloom(header: &Header, transactions: &[Transaction]) -> Result<(), String> {
+    let bloom = block_bloom(transactions);
+    if header.bloom != bloom {
+        return Err(format!("the bloom {:?} mismatches the logs {:?}", header.bloom, bloom));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::types::receipt::receipts_bloom;
+
+    #[test]
+    fn t_apply_transactions() {
+        let key_pair = Random.generate().unwrap();
+        let coinbase = Transaction::new(0, key_pair.address(), 10, 0, 1, vec![]);
+        let mut overdrawn = Transaction::new(0, Address::from(2), 11, 0, 1, vec![]);
+        overdrawn.sign(1, key_pair.secret());
+        let mut transfer = Transaction::new(1, Address::from(2), 4, 0, 1, vec![]);
+        transfer.sign(1, key_pair.secret());
+        let unsigned = Transaction::new(0, Address::from(3), 1, 0, 1, vec![]);
+
+        let transactions = vec![coinbase, overdrawn, transfer, unsigned];
+
+        let mut accounts = BTreeMap::new();
+        let outcomes = apply_transactions(&mut accounts, &transactions, &FeeConfig::disabled());
+        assert_eq!(outcomes.iter().map(|outcome| outcome.moved).collect::<Vec<_>>(), vec![true, false, true, false]);
+        assert_eq!(accounts.account(&key_pair.address()), Account { balance: 6, nonce: 2 });
+        assert_eq!(accounts.account(&Address::from(2)).balance, 4);
+        assert_eq!(accounts.account(&Address::from(3)).balance, 0);
+
+        // the fees are charged up to the balance, 21000 gas at the price 1
+        let mut accounts = BTreeMap::new();
+        accounts.set_account(&key_pair.address(), Account { balance: 21_010, nonce: 0 });
+        let outcomes = apply_transactions(&mut accounts, &transactions, &FeeConfig { enabled: true, burn_percent: 0 });
+        assert_eq!(outcomes[1..3], [TxOutcome { moved: true, fee: 21_000 }, TxOutcome { moved: true, fee: 5 }]);
+        assert_eq!(accounts.account(&key_pair.address()).balance, 0);
+    }
+
+    #[test]
+    fn t_execute_block() {
+        let key_pair = Random.generate().unwrap();
+        let coinbase = Transaction::new(0, Address::from(1), 10, 0, 1, vec![]);
+        let mut transfer = Transaction::new(0, Address::from(2), 5, 0, 1, vec![]);
+        transfer.sign(1, key_pair.secret());
+        let transactions = vec![coinbase, transfer];
+
+        let outcomes = vec![TxOutcome { moved: true, fee: 0 }, TxOutcome { moved: true, fee: 21_000 }];
+        let receipts = execute_block(3, &transactions, &outcomes, &FeeConfig { enabled: true, burn_percent: 50 });
+        assert_eq!(receipts.len(), 2);
+        assert_eq!((receipts[0].gas_used, receipts[0].fee), (0, 0));
+        assert_eq!((receipts[1].gas_used, receipts[1].fee, receipts[1].burned), (21_000, 21_000, 10_500));
+        assert_eq!(receipts[0].logs[0].topics[0], *MINT_TOPIC);
+        assert_eq!(receipts[1].logs[0].topics, vec![*TRANSFER_TOPIC, address_topic(&key_pair.address()), address_topic(&Address::from(2))]);
+        assert_eq!(receipts[1].logs[0].data, 5u64.to_be_bytes().to_vec());
+
+        let bloom = block_bloom(&transactions);
+        assert!(bloom.contains(key_pair.address().as_ref()));
+        assert!(bloom.contains(address_topic(&Address::from(2)).as_ref()));
+        assert_eq!(bloom, receipts_bloom(&receipts));
+
+        let mut header = Header::zero_header();
+        assert!(verify_block_bloom(&header, &transactions).is_err());
+        header.bloom = bloom;
+        assert!(verify_block_bloom(&header, &transactions).is_ok());
+        // the zero bloom doesn't skip the check
+        assert!(verify_block_bloom(&Header::zero_header(), &transactions[..1]).is_err());
+        assert!(verify_block_bloom(&Header::zero_header(), &[]).is_ok());
+    }
+}
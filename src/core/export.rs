@@ -0,0 +1,266 @@
+use std::io::{BufRead, Read, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rlp::UntrustedRlp;
+
+use crate::core::chain::Chain;
+use crate::core::import::verify_block;
+use crate::core::ledger::Ledger;
+use crate::types::Height;
+use crate::types::block::Block;
+
+// the progress of an import is reported every N blocks
+pub const IMPORT_PROGRESS_INTERVAL: u64 = 1000;
+
+/// the encoding of the exported blocks, eg: `export --format rlp`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    // the wire encoding of the blocks, one item after another
+    Rlp,
+    // one block per line
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rlp" => Ok(ExportFormat::Rlp),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("unknown block format: {}, expect `rlp` or `json`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExportReport {
+    pub blocks: u64,
+    pub from: Height,
+    pub to: Height,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: u64,
+    // the blocks which are already in the store
+    pub skipped: u64,
+    pub last_height: Option<Height>,
+}
+
+/// Write the blocks in [from, to] one by one, `to` is the last block by default. The blocks
+/// are read from the store as they are written, so the whole range is never held in memory.
+pub fn export_blocks<W: Write>(ledger: &Ledger, writer: &mut W, format: ExportFormat, from: Height, to: Option<Height>) -> Result<ExportReport, String> {
+    let last_height = match ledger.get_schema().block_hashes_by_height().len() {
+        0 => return Err("there is no stored block".to_string()),
+        len => len - 1,
+    };
+    let to = to.map_or(last_height, |to| ::std::cmp::min(to, last_height));
+    if from > to {
+        return Err(format!("the range is empty, from: {}, to: {}, last height: {}", from, to, last_height));
+    }
+    let mut report = ExportReport { from, to, ..ExportReport::default() };
+    for height in from..=to {
+        let block = ledger.get_block_by_height(height).ok_or(format!("the block of the height {} is missing", height))?;
+        let bytes = match format {
+            ExportFormat::Rlp => rlp::encode(&block).to_vec(),
+            ExportFormat::Json => {
+                let mut line = serde_json::to_vec(&block).map_err(|err| err.to_string())?;
+                line.push(b'\n');
+                line
+            }
+        };
+        writer.write_all(&bytes).map_err(|err| err.to_string())?;
+        report.blocks += 1;
+        report.bytes += bytes.len() as u64;
+    }
+    writer.flush().map_err(|err| err.to_string())?;
+    Ok(report)
+}
+
+/// Read the blocks one by one and insert them into the chain. The genesis block of the file must
+/// be the genesis block of the chain, it's stored from the genesis config before the import. The
+/// other blocks must continue the chain: the block links to the last block, carries the valid
+/// seals of the epoch, the transactions of its transaction root with valid signatures, the slot of
+/// its proposer on dpos, and passes the checks of the chain, eg: the gas and the checkpoints. The
+/// blocks which are already stored are skipped, a different block at a stored height is an error.
+pub fn import_blocks<R: BufRead, F>(chain: &Arc<Chain>, reader: &mut R, format: ExportFormat, mut progress: F) -> Result<ImportReport, String>
+    where F: FnMut(Height) {
+    let chain_id = chain.config.chain_id;
+    let max_block_size = chain.config.size_limits().max_block_size;
+    let mut report = ImportReport::default();
+    while let Some(block) = read_block(reader, format, max_block_size)? {
+        let height = block.height();
+        let stored = chain.get_last_height() + 1;
+        if height < stored {
+            let hash = chain.get_block_hash_by_height(height).unwrap();
+            if height == 0 && hash != block.hash() {
+                return Err(format!("the genesis block {:?} isn't the genesis block {:?} of the config", block.hash(), hash));
+            }
+            if hash != block.hash() {
+                return Err(format!("the block {:?} conflicts with the stored block {:?} at height {}", block.hash(), hash, height));
+            }
+            report.skipped += 1;
+            continue;
+        }
+        if height > stored {
+            return Err(format!("the blocks from height {} to {} are missing", stored, height - 1));
+        }
+        if block.header().prev_hash != chain.get_last_hash() {
+            return Err(format!("the parent hash {:?} isn't the block at height {}", block.header().prev_hash, height - 1));
+        }
+        let validators = chain.get_validator_addresses(height);
+        verify_block(&block, chain.seal_verifier().as_ref(), &validators, chain_id).map_err(|err| format!("invalid block at height {}, {}", height, err))?;
+        chain.verify_slot(block.header()).map_err(|err| format!("invalid block at height {}, {}", height, err))?;
+        chain.insert_block(&block).map_err(|err| format!("invalid block at height {}, {}", height, err))?;
+        report.imported += 1;
+        report.last_height = Some(height);
+        if report.imported % IMPORT_PROGRESS_INTERVAL == 0 {
+            progress(height);
+        }
+    }
+    Ok(report)
+}
+
+// the next block of the file, none at the end of the file. A json block carries every byte as
+// up to 4 chars, eg: `255,`, so its line is bounded by 4 times the rlp limit
+fn read_block<R: BufRead>(reader: &mut R, format: ExportFormat, max_block_size: u64) -> Result<Option<Block>, String> {
+    match format {
+        ExportFormat::Rlp => {
+            let item = match read_rlp_item(reader, max_block_size)? {
+                Some(item) => item,
+                None => return Ok(None),
+            };
+            UntrustedRlp::new(&item).as_val().map(Some).map_err(|err| format!("invalid rlp block, {:?}", err))
+        }
+        ExportFormat::Json => {
+            let max_line = max_block_size.saturating_mul(4);
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.by_ref().take(max_line + 1).read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+                    return Ok(None);
+                }
+                if line.len() as u64 > max_line {
+                    return Err(format!("the json block is larger than {} bytes", max_line));
+                }
+                // the blank lines are skipped, eg: a trailing newline
+                if !line.trim().is_empty() {
+                    break;
+                }
+            }
+            serde_json::from_str(&line).map(Some).map_err(|err| format!("invalid json block, {}", err))
+        }
+    }
+}
+
+// a block is a rlp list, the prefix tells the length of its payload, the blocks above the size
+// limit of the chain are refused before their payload is read
+fn read_rlp_item<R: Read>(reader: &mut R, max_block_size: u64) -> Result<Option<Vec<u8>>, String> {
+    let mut prefix = [0_u8; 1];
+    if reader.read(&mut prefix).map_err(|err| err.to_string())? == 0 {
+        return Ok(None);
+    }
+    let mut item = vec![prefix[0]];
+    let len = match prefix[0] {
+        0xc0..=0xf7 => (prefix[0] - 0xc0) as u64,
+        0xf8..=0xff => {
+            let mut len_bytes = vec![0_u8; (prefix[0] - 0xf7) as usize];
+            reader.read_exact(&mut len_bytes).map_err(|err| format!("truncated rlp block, {}", err))?;
+            item.extend_from_slice(&len_bytes);
+            len_bytes.iter().fold(0_u64, |len, byte| len.saturating_mul(256).saturating_add(*byte as u64))
+        }
+        _ => return Err(format!("invalid rlp block, the prefix {:#x} isn't a list", prefix[0])),
+    };
+    let size = len.saturating_add(item.len() as u64);
+    if size > max_block_size {
+        return Err(format!("the rlp block of {} bytes is larger than {} bytes", size, max_block_size));
+    }
+    let offset = item.len();
+    item.resize(offset + len as usize, 0);
+    reader.read_exact(&mut item[offset..]).map_err(|err| format!("truncated rlp block, {}", err))?;
+    Ok(Some(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use ::actix::prelude::*;
+    use cryptocurrency_kit::crypto::Hash;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::core::test_util::{memory_chain, sealed_block};
+
+    #[test]
+    fn t_export_import() {
+        let _system = System::new("t_export_import");
+        let key_pair = Random.generate().unwrap();
+        let chain = memory_chain(&key_pair);
+        let mut parent = chain.get_last_hash();
+        for height in 1..10 {
+            let block = sealed_block(parent, height, &key_pair);
+            parent = block.hash();
+            chain.insert_block(&block).unwrap();
+        }
+
+        for format in vec![ExportFormat::Rlp, ExportFormat::Json] {
+            let mut file = vec![];
+            let report = export_blocks(&chain.get_ledger().read(), &mut file, format, 0, None).unwrap();
+            assert_eq!((report.blocks, report.to, report.bytes), (10, 9, file.len() as u64));
+
+            // the genesis block is stored from the config, it's skipped
+            let imported = memory_chain(&key_pair);
+            let report = import_blocks(&imported, &mut Cursor::new(&file), format, |_| {}).unwrap();
+            assert_eq!((report.imported, report.skipped, report.last_height), (9, 1, Some(9)));
+            assert_eq!(imported.get_last_hash(), parent);
+            // the stored blocks are skipped
+            let report = import_blocks(&imported, &mut Cursor::new(&file), format, |_| {}).unwrap();
+            assert_eq!((report.imported, report.skipped), (0, 10));
+
+            // a part of the chain
+            let mut part = vec![];
+            assert_eq!(export_blocks(&chain.get_ledger().read(), &mut part, format, 3, Some(5)).unwrap().blocks, 3);
+            assert!(import_blocks(&memory_chain(&key_pair), &mut Cursor::new(&part), format, |_| {}).is_err());
+            let mut truncated = file.clone();
+            truncated.truncate(file.len() - 3);
+            assert!(import_blocks(&memory_chain(&key_pair), &mut Cursor::new(&truncated), format, |_| {}).is_err());
+        }
+        assert!(export_blocks(&chain.get_ledger().read(), &mut vec![], ExportFormat::Rlp, 10, None).is_err());
+
+        // the genesis block of another config
+        let mut file = vec![];
+        export_blocks(&chain.get_ledger().read(), &mut file, ExportFormat::Rlp, 0, None).unwrap();
+        let other = memory_chain(&Random.generate().unwrap());
+        let err = import_blocks(&other, &mut Cursor::new(&file), ExportFormat::Rlp, |_| {}).unwrap_err();
+        assert!(err.contains("genesis"));
+        assert_eq!(other.get_last_height(), 0);
+
+        // the seals of another validator
+        let mut file = vec![];
+        export_blocks(&chain.get_ledger().read(), &mut file, ExportFormat::Rlp, 0, Some(0)).unwrap();
+        let forged = sealed_block(chain.get_genesis().hash(), 1, &Random.generate().unwrap());
+        file.extend_from_slice(&rlp::encode(&forged));
+        let imported = memory_chain(&key_pair);
+        let err = import_blocks(&imported, &mut Cursor::new(&file), ExportFormat::Rlp, |_| {}).unwrap_err();
+        assert!(err.contains("height 1"));
+        assert_eq!(imported.get_last_height(), 0);
+    }
+
+    #[test]
+    fn t_read_size_limit() {
+        let block = sealed_block(Hash::zero(), 1, &Random.generate().unwrap());
+        let bytes = rlp::encode(&block).to_vec();
+        let size = bytes.len() as u64;
+        assert_eq!(read_rlp_item(&mut Cursor::new(&bytes), size).unwrap(), Some(bytes.clone()));
+        assert!(read_rlp_item(&mut Cursor::new(&bytes), size - 1).unwrap_err().contains("larger"));
+        // a forged length is refused before the payload is allocated
+        assert!(read_rlp_item(&mut Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), size).is_err());
+
+        let mut line = serde_json::to_vec(&block).unwrap();
+        line.push(b'\n');
+        assert!(read_block(&mut Cursor::new(&line), ExportFormat::Json, size * 4).unwrap().is_some());
+        assert!(read_block(&mut Cursor::new(&line), ExportFormat::Json, 8).is_err());
+    }
+}
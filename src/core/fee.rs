@@ -0,0 +1,160 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use serde::{Deserialize, Serialize};
+
+use super::executor::TxOutcome;
+use super::gas::intrinsic_gas;
+use crate::metrics;
+use crate::store::schema::Schema;
+use crate::store::types::Fork;
+use crate::types::transaction::Transaction;
+use crate::types::Height;
+
+/// the transaction fees of the chain, eg: `[genesis.fee]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeConfig {
+    // the senders pay `gas used * gas price` for every transaction, it's off by default like the
+    // stores which were created before the fees
+    #[serde(default)]
+    pub enabled: bool,
+    // the percent of every fee which is burned, the rest goes to the proposer
+    #[serde(default)]
+    pub burn_percent: u8,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        FeeConfig::disabled()
+    }
+}
+
+implement_hash_algo_traits! {FeeConfig}
+implement_storagevalue_traits! {FeeConfig}
+
+impl FeeConfig {
+    /// the stores which were created before the fees charge nothing
+    pub fn disabled() -> Self {
+        FeeConfig { enabled: false, burn_percent: 0 }
+    }
+
+    /// the fee which the sender of the transaction pays
+    pub fn fee(&self, transaction: &Transaction) -> u64 {
+        if !self.enabled {
+            return 0;
+        }
+        intrinsic_gas(transaction).saturating_mul(transaction.gas_price())
+    }
+
+    /// split the fee into (paid to the proposer, burned)
+    pub fn split(&self, fee: u64) -> (u64, u64) {
+        let burned = (fee as u128 * ::std::cmp::min(self.burn_percent, 100) as u128 / 100) as u64;
+        (fee - burned, burned)
+    }
+
+    /// the charged fees of the block transactions, (paid to the proposer, burned)
+    pub fn block_fees(&self, outcomes: &[TxOutcome]) -> (u64, u64) {
+        outcomes.iter().fold((0, 0), |(paid, burned), outcome| {
+            let (tx_paid, tx_burned) = self.split(outcome.fee);
+            (paid.saturating_add(tx_paid), burned.saturating_add(tx_burned))
+        })
+    }
+}
+
+/// the fees earned by a proposer in height order, (height, amount)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct FeeIncome(pub Vec<(Height, u64)>);
+
+implement_storagevalue_traits! {FeeIncome}
+implement_cryptohash_traits! {FeeIncome}
+
+impl FeeIncome {
+    /// the fees earned at or below the height
+    pub fn total_at(&self, height: Height) -> u64 {
+        self.0.iter().take_while(|(earned, _)| *earned <= height).fold(0_u64, |acc, (_, amount)| acc.saturating_add(*amount))
+    }
+}
+
+/// pay the unburned fees of the block to its proposer, only the fees which were charged from the
+/// senders by `apply_transactions` are paid
+pub fn write_fees<T: Fork + ?Sized>(schema: &Schema<T>, height: Height, hash: &Hash, outcomes: &[TxOutcome]) {
+    let config = schema.fee_config().get().unwrap_or_else(FeeConfig::disabled);
+    let (paid, burned) = config.block_fees(outcomes);
+    metrics::inc_counter_by("ledger_fees_burned_total", burned);
+    if paid == 0 {
+        return;
+    }
+    let proposer: Address = match schema.headers().get(hash) {
+        Some(header) => header.proposer,
+        None => return,
+    };
+    let mut accounts_db = schema.accounts();
+    let mut account = accounts_db.get(&proposer).unwrap_or_default();
+    account.credit(paid);
+    accounts_db.put(&proposer, account);
+    let mut income_db = schema.fee_income();
+    let mut income = income_db.get(&proposer).unwrap_or_default();
+    income.0.push((height, paid));
+    income_db.put(&proposer, income);
+    metrics::inc_counter_by("ledger_fees_paid_total", paid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_block_fees() {
+        let config = FeeConfig { enabled: true, burn_percent: 25 };
+        let transfer = Transaction::new(0, Address::from(2), 10, 30_000, 2, vec![0; 10]);
+        // 21680 gas used
+        assert_eq!(config.fee(&transfer), 43_360);
+        assert_eq!(config.split(43_360), (32_520, 10_840));
+        // the coinbase transaction pays nothing, the charged fees are split
+        let outcomes = [TxOutcome { moved: true, fee: 0 }, TxOutcome { moved: true, fee: config.fee(&transfer) }];
+        assert_eq!(config.block_fees(&outcomes), (32_520, 10_840));
+        assert_eq!(config.block_fees(&[TxOutcome { moved: false, fee: 100 }]), (75, 25));
+        assert_eq!(FeeConfig::disabled().fee(&transfer), 0);
+        assert_eq!(FeeConfig::default(), FeeConfig::disabled());
+        assert_eq!(FeeConfig { enabled: true, burn_percent: 200 }.split(10), (0, 10));
+
+        let income = FeeIncome(vec![(1, 10), (3, 20)]);
+        assert_eq!(income.total_at(2), 10);
+    }
+
+    // the proposer is paid the charged fees, not the fees the senders couldn't cover
+    #[test]
+    fn t_write_fees() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::core::test_util::memory_ledger_of;
+        use crate::types::block::{Block, Header};
+
+        let sender = Random.generate().unwrap();
+        let mut ledger = memory_ledger_of(vec![]);
+        ledger.set_fee_config(FeeConfig { enabled: true, burn_percent: 0 });
+        ledger.add_genesis_alloc(&[(sender.address(), 30_000)]);
+        for height in 0..2 {
+            let mut header = Header::zero_header();
+            header.height = height;
+            header.proposer = Address::from(7);
+            let mut transactions = vec![Transaction::new(height, Address::from(8), 0, 0, 1, vec![])];
+            // 21000 gas at the price 1 for every transfer
+            for nonce in 0..2 {
+                let mut transfer = Transaction::new(height * 2 + nonce, Address::from(9), 1, 21_000, 1, vec![]);
+                transfer.sign(1, sender.secret());
+                transactions.push(transfer);
+            }
+            ledger.add_block(&Block::new(header, transactions));
+        }
+        // 30000 - 1 - 21000 - 1 leaves 8998 for the second fee of the genesis block
+        assert_eq!(ledger.get_account(&sender.address()).balance, 0);
+        assert_eq!(ledger.get_account(&Address::from(9)).balance, 2);
+        assert_eq!(ledger.get_fee_income(&Address::from(7)).0, vec![(0, 29_998)]);
+        assert_eq!(ledger.get_account(&Address::from(7)).balance, 29_998);
+        assert_eq!(ledger.get_receipts(0)[2].fee, 8_998);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use cryptocurrency_kit::crypto::Hash;
+
+use crate::metrics;
+use crate::types::Timestamp;
+use crate::types::block::Block;
+
+// the max blocks waiting for their timestamps
+pub const MAX_FUTURE_BLOCKS: usize = 64;
+// a peer can't fill the queue alone
+pub const MAX_FUTURE_BLOCKS_PER_PEER: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum Admission {
+    // the timestamp is valid, import it now
+    Ready,
+    // the block is held, it's valid after the duration
+    Held(Duration),
+    // too far in the future, or the queue is full
+    Rejected,
+}
+
+/// FutureBlockQueue holds the gossiped blocks whose timestamps are slightly ahead of the local
+/// clock, they are imported when their timestamps become valid instead of being dropped
+pub struct FutureBlockQueue {
+    // seconds
+    tolerance: u64,
+    blocks: BTreeMap<(Timestamp, Hash), (String, Block)>,
+    held: HashMap<String, usize>,
+}
+
+impl FutureBlockQueue {
+    pub fn new(tolerance: Duration) -> Self {
+        FutureBlockQueue { tolerance: tolerance.as_secs(), blocks: BTreeMap::new(), held: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// the timestamp of the first held block, the queue is polled then
+    pub fn next_ready(&self) -> Option<Timestamp> {
+        self.blocks.keys().next().map(|(time, _)| *time)
+    }
+
+    /// decide what to do with the block which is received from the peer at `now` (seconds)
+    pub fn admit(&mut self, peer: &str, block: &Block, now: Timestamp) -> Admission {
+        let time = block.header().time;
+        if time <= now {
+            return Admission::Ready;
+        }
+        // the metrics aren't labeled by the peers, the peer ids are unbounded
+        if time > now + self.tolerance {
+            metrics::inc_counter("chain_future_block_rejected_total{reason=\"too_far\"}");
+            return Admission::Rejected;
+        }
+        let key = (time, block.hash());
+        if self.blocks.contains_key(&key) {
+            return Admission::Held(Duration::from_secs(time - now));
+        }
+        let held = self.held.get(peer).cloned().unwrap_or(0);
+        if self.blocks.len() >= MAX_FUTURE_BLOCKS || held >= MAX_FUTURE_BLOCKS_PER_PEER {
+            metrics::inc_counter("chain_future_block_rejected_total{reason=\"full\"}");
+            return Admission::Rejected;
+        }
+        self.blocks.insert(key, (peer.to_owned(), block.clone()));
+        self.held.insert(peer.to_owned(), held + 1);
+        metrics::inc_counter("chain_future_block_held_total");
+        metrics::set_gauge("chain_future_blocks", self.blocks.len() as i64);
+        Admission::Held(Duration::from_secs(time - now))
+    }
+
+    /// take the blocks whose timestamps are valid at `now`, in timestamp order
+    pub fn pop_ready(&mut self, now: Timestamp) -> Vec<Block> {
+        let pending = self.blocks.split_off(&(now + 1, Hash::zero()));
+        let ready = ::std::mem::replace(&mut self.blocks, pending);
+        metrics::set_gauge("chain_future_blocks", self.blocks.len() as i64);
+        ready.into_iter().map(|(_, (peer, block))| {
+            let held = self.held.get(&peer).cloned().unwrap_or(1) - 1;
+            if held == 0 {
+                self.held.remove(&peer);
+            } else {
+                self.held.insert(peer, held);
+            }
+            block
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::types::{Height, EMPTY_ADDRESS};
+    use crate::types::block::Header;
+
+    #[test]
+    fn t_future_block_queue() {
+        let block_at = |height: Height, time: Timestamp| Block::new(Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, height, time, None), vec![]);
+        let block = |time: Timestamp| block_at(1, time);
+        let mut queue = FutureBlockQueue::new(Duration::from_secs(5));
+        assert_eq!(queue.admit("a", &block(100), 100), Admission::Ready);
+        assert_eq!(queue.admit("a", &block(103), 100), Admission::Held(Duration::from_secs(3)));
+        assert_eq!(queue.admit("b", &block(102), 100), Admission::Held(Duration::from_secs(2)));
+        assert_eq!(queue.admit("a", &block(106), 100), Admission::Rejected);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.next_ready(), Some(102));
+
+        assert!(queue.pop_ready(101).is_empty());
+        let ready = queue.pop_ready(102);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].header().time, 102);
+        assert_eq!(queue.pop_ready(110).len(), 1);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.next_ready(), None);
+
+        for height in 0..MAX_FUTURE_BLOCKS_PER_PEER as Height {
+            assert_eq!(queue.admit("c", &block_at(height, 101), 100), Admission::Held(Duration::from_secs(1)));
+        }
+        assert_eq!(queue.admit("c", &block_at(100, 101), 100), Admission::Rejected);
+        assert_eq!(queue.admit("d", &block_at(100, 101), 100), Admission::Held(Duration::from_secs(1)));
+        // the peers add no metric series
+        assert!(metrics::gather().lines().all(|line| !line.starts_with("chain_future_block") || !line.contains("peer=")));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::types::block::Header;
+use crate::types::transaction::Transaction;
+use crate::types::{Gas, Height};
+
+// the gas of a transaction without payload
+pub const TX_BASE_GAS: Gas = 21_000;
+// the gas of every payload byte
+pub const TX_DATA_GAS: Gas = 68;
+
+/// the gas rules of the blocks, eg:
+///
+/// ```toml
+/// [gas]
+/// min_gas_limit = 5000000
+/// target_gas_limit = 8000000
+/// gas_limit_bound_divisor = 1024
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GasConfig {
+    // the blocks below it are not checked, so the stores which were written before the rules can be synced
+    #[serde(default)]
+    pub activation_height: Height,
+    #[serde(default = "default_min_gas_limit")]
+    pub min_gas_limit: Gas,
+    // the gas limit that the minner moves to
+    #[serde(default = "default_target_gas_limit")]
+    pub target_gas_limit: Gas,
+    // the gas limit changes at most `parent_gas_limit / gas_limit_bound_divisor` between two blocks
+    #[serde(default = "default_gas_limit_bound_divisor")]
+    pub gas_limit_bound_divisor: u64,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        GasConfig {
+            activation_height: 0,
+            min_gas_limit: default_min_gas_limit(),
+            target_gas_limit: default_target_gas_limit(),
+            gas_limit_bound_divisor: default_gas_limit_bound_divisor(),
+        }
+    }
+}
+
+fn default_min_gas_limit() -> Gas {
+    5_000_000
+}
+
+fn default_target_gas_limit() -> Gas {
+    8_000_000
+}
+
+fn default_gas_limit_bound_divisor() -> u64 {
+    1024
+}
+
+/// the gas that executing the transaction costs
+pub fn intrinsic_gas(transaction: &Transaction) -> Gas {
+    TX_BASE_GAS.saturating_add(TX_DATA_GAS.saturating_mul(transaction.payload().len() as Gas))
+}
+
+/// the declared gas limit and the used gas of the block transactions, the coinbase
+/// transaction (the first one) costs nothing
+pub fn block_gas(transactions: &[Transaction]) -> (Gas, Gas) {
+    transactions.iter().skip(1).fold((0, 0), |(declared, used), transaction| {
+        (declared.saturating_add(transaction.gas()), used.saturating_add(intrinsic_gas(transaction)))
+    })
+}
+
+/// the gas limit of the next block, it moves from the parent's to the target within the bound
+pub fn next_gas_limit(parent_gas_limit: Gas, config: &GasConfig) -> Gas {
+    if parent_gas_limit < config.min_gas_limit {
+        return ::std::cmp::max(config.min_gas_limit, config.target_gas_limit);
+    }
+    let bound = parent_gas_limit / config.gas_limit_bound_divisor.max(1);
+    let gas_limit = if config.target_gas_limit > parent_gas_limit {
+        ::std::cmp::min(parent_gas_limit.saturating_add(bound), config.target_gas_limit)
+    } else {
+        ::std::cmp::max(parent_gas_limit - bound, config.target_gas_limit)
+    };
+    ::std::cmp::max(gas_limit, config.min_gas_limit)
+}
+
+/// verify the gas of the block against its transactions and its parent
+pub fn verify_block_gas(header: &Header, transactions: &[Transaction], parent: &Header, config: &GasConfig) -> Result<(), String> {
+    if header.height < config.activation_height {
+        return Ok(());
+    }
+    for transaction in transactions.iter().skip(1) {
+        if transaction.gas() < intrinsic_gas(transaction) {
+            return Err(format!("intrinsic gas too low, tx: {:?}, gas: {}, intrinsic: {}", transaction.get_hash(), transaction.gas(), intrinsic_gas(transaction)));
+        }
+    }
+    let (declared, used) = block_gas(transactions);
+    if declared > header.gas_limit {
+        return Err(format!("transactions gas exceeds the block gas limit, limit: {}, got: {}", header.gas_limit, declared));
+    }
+    if used != header.gas_used {
+        return Err(format!("gas used mismatch, expect: {}, got: {}", used, header.gas_used));
+    }
+    if header.gas_limit < config.min_gas_limit {
+        return Err(format!("gas limit is below the minimum, min: {}, got: {}", config.min_gas_limit, header.gas_limit));
+    }
+    // the parent which is below the minimum (eg: the genesis block) can jump to it
+    if parent.gas_limit >= config.min_gas_limit {
+        let bound = parent.gas_limit / config.gas_limit_bound_divisor.max(1);
+        let diff = if header.gas_limit > parent.gas_limit { header.gas_limit - parent.gas_limit } else { parent.gas_limit - header.gas_limit };
+        if diff > bound {
+            return Err(format!("gas limit changes too much, parent: {}, got: {}, bound: {}", parent.gas_limit, header.gas_limit, bound));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::Address;
+
+    fn header(height: Height, gas_limit: Gas, gas_used: Gas) -> Header {
+        let mut header = Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, height, 0, None);
+        header.gas_limit = gas_limit;
+        header.gas_used = gas_used;
+        header
+    }
+
+    #[test]
+    fn t_verify_block_gas() {
+        let config = GasConfig::default();
+        let coinbase = Transaction::new(0, Address::from(1), 10, 0, 1, vec![]);
+        let transfer = Transaction::new(0, Address::from(2), 10, 30_000, 1, vec![0; 10]);
+        let transactions = vec![coinbase, transfer];
+        assert_eq!(block_gas(&transactions), (30_000, 21_680));
+
+        let parent = header(0, 10, 0);
+        let gas_limit = next_gas_limit(parent.gas_limit, &config);
+        assert_eq!(gas_limit, config.target_gas_limit);
+        assert!(verify_block_gas(&header(1, gas_limit, 21_680), &transactions, &parent, &config).is_ok());
+        assert!(verify_block_gas(&header(1, gas_limit, 21_000), &transactions, &parent, &config).is_err());
+        assert!(verify_block_gas(&header(1, 20_000, 21_680), &transactions, &parent, &config).is_err());
+
+        let parent = header(1, config.min_gas_limit, 0);
+        let gas_limit = next_gas_limit(parent.gas_limit, &config);
+        assert_eq!(gas_limit, config.min_gas_limit + config.min_gas_limit / 1024);
+        assert!(verify_block_gas(&header(2, gas_limit, 21_680), &transactions, &parent, &config).is_ok());
+        assert!(verify_block_gas(&header(2, gas_limit + 1, 21_680), &transactions, &parent, &config).is_err());
+    }
+}
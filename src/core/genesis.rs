@@ -7,15 +7,16 @@ use ethereum_types::H160;
 use parking_lot::RwLock;
 
 use cryptocurrency_kit::ethkey::Address;
-use cryptocurrency_kit::crypto::EMPTY_HASH;
+use cryptocurrency_kit::crypto::{Hash, EMPTY_HASH};
 
 use crate::{
-    types::{Timestamp, Gas, Difficulty, Height, EMPTY_ADDRESS},
+    types::{Bloom, Timestamp, Gas, Difficulty, Height, EMPTY_ADDRESS},
     types::block::{Block, Header},
     types::votes::{decrypt_commit_bytes, encrypt_commit_bytes, Votes},
     types::{Validator, Validators},
     config::GenesisConfig,
     common,
+    common::hash_algo::digest,
 };
 use super::{
     ledger::Ledger,
@@ -39,6 +40,13 @@ pub(crate) fn store_genesis_block(genesis_config: &GenesisConfig, ledger: Arc<Rw
         ledger.add_validators(validators);
     }
 
+    // pre-fund the accounts
+    let alloc = parse_alloc(genesis_config)?;
+    ledger.add_genesis_alloc(&alloc);
+    ledger.set_staking_config(genesis_config.staking);
+    ledger.set_reward_config(genesis_config.reward);
+    ledger.set_fee_config(genesis_config.fee);
+
     // TODO Add more xin
     {
         let proposer = common::string_to_address(&genesis_config.proposer)?;
@@ -48,8 +56,8 @@ pub(crate) fn store_genesis_block(genesis_config: &GenesisConfig, ledger: Arc<Rw
         }.map_err(|err: ParseError| err.to_string())?;
 
         let extra = genesis_config.extra.as_bytes().to_vec();
-        let mut header = Header::new(EMPTY_HASH, proposer, EMPTY_HASH, EMPTY_HASH, EMPTY_HASH,
-                                     0, 0, 0, genesis_config.gas_used + 10, genesis_config.gas_used,
+        let mut header = Header::new(EMPTY_HASH, proposer, alloc_root(&alloc), EMPTY_HASH, EMPTY_HASH,
+                                     Bloom::zero(), 0, 0, genesis_config.gas_used + 10, genesis_config.gas_used,
                                      epoch_time.timestamp() as Timestamp, None, Some(extra));
         let block = Block::new(header, vec![]);
         ledger.add_genesis_block(&block);
@@ -58,6 +66,28 @@ pub(crate) fn store_genesis_block(genesis_config: &GenesisConfig, ledger: Arc<Rw
     Ok(())
 }
 
+fn parse_alloc(genesis_config: &GenesisConfig) -> Result<Vec<(Address, u64)>, String> {
+    genesis_config.alloc.iter().map(|(address, balance)| {
+        common::string_to_address(address)
+            .map(|address| (address, *balance))
+            .map_err(|err| format!("invalid genesis alloc address {}, err: {}", address, err))
+    }).collect()
+}
+
+/// the root commits the allocation into the genesis hash, so the nodes with different allocations
+/// can't peer, it's empty without an allocation and the existing genesis hashes are unchanged
+pub(crate) fn alloc_root(alloc: &[(Address, u64)]) -> Hash {
+    if alloc.is_empty() {
+        return EMPTY_HASH;
+    }
+    let mut bytes = vec![];
+    for (address, balance) in alloc {
+        bytes.extend_from_slice(address.as_ref());
+        bytes.extend_from_slice(&balance.to_be_bytes());
+    }
+    digest(bytes)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -83,7 +113,7 @@ mod test {
         );
 
         let mut header = Header::new(EMPTY_HASH, Address::from(10), EMPTY_HASH, EMPTY_HASH, EMPTY_HASH,
-                                     0, 0, 0, 10, 10,
+                                     Bloom::zero(), 0, 0, 10, 10,
                                      192, None, Some(vec![12, 1]));
         let block = Block::new(header, vec![]);
 
@@ -108,7 +138,7 @@ mod test {
         );
 
         let mut header = Header::new(EMPTY_HASH, Address::from(10), EMPTY_HASH, EMPTY_HASH, EMPTY_HASH,
-                                     0, 0, 0, 10, 10,
+                                     Bloom::zero(), 0, 0, 10, 10,
                                      192, None, Some(vec![12, 1]));
         let block = Block::new(header, vec![]);
 
@@ -117,7 +147,7 @@ mod test {
 
         (1_u64..10).for_each(|height|{
             let mut header = Header::new(EMPTY_HASH, Address::from(10), EMPTY_HASH, EMPTY_HASH, EMPTY_HASH,
-                                         0, 0, height, 10, 10,
+                                         Bloom::zero(), 0, height, 10, 10,
                                          192, None, Some(vec![12, 1]));
             let block = Block::new(header, vec![]);
 
@@ -140,6 +170,27 @@ mod test {
 //        println!("last_block {:?}", ledger.get_last_block());
     }
 
+    #[test]
+    fn t_genesis_alloc() {
+        let database = Database::open_default(&random_dir()).map_err(|err| err.to_string()).unwrap();
+        let schema = Schema::new(Arc::new(database));
+        let mut ledger = Ledger::new(
+            LastMeta::new_zero(),
+            LruCache::with_capacity(1 << 10),
+            LruCache::with_capacity(1 << 10),
+            vec![],
+            schema,
+        );
+
+        let alloc = vec![(Address::from(1), 500), (Address::from(2), 1000)];
+        ledger.add_genesis_alloc(&alloc);
+        assert_eq!(ledger.get_account(&Address::from(1)).balance, 500);
+        assert_eq!(ledger.get_account(&Address::from(2)).balance, 1000);
+        assert_eq!(ledger.get_account(&Address::from(3)).balance, 0);
+        assert_eq!(alloc_root(&[]), EMPTY_HASH);
+        assert_ne!(alloc_root(&alloc), alloc_root(&alloc[..1]));
+    }
+
     #[test]
     fn t_exists_db() {
 //        let database = Database::open_default("/tmp/block/c1").map_err(|err| err.to_string()).unwrap();
@@ -0,0 +1,303 @@
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crossbeam::crossbeam_channel::{self, Receiver, RecvTimeoutError, Sender, TrySendError};
+use cryptocurrency_kit::ethkey::Address;
+use futures::future;
+use futures::sync::oneshot;
+use futures::Future;
+use tokio_threadpool::ThreadPool;
+
+use crate::consensus::consensus::{EngineKind, SealVerifier};
+use crate::core::chain::Chain;
+use crate::core::executor::verify_block_bloom;
+use crate::core::future_block::Admission;
+use crate::metrics;
+use crate::subscriber::events::ChainEvent;
+use crate::types::block::{Block, Blocks};
+use crate::types::transaction::merkle_root_transactions;
+
+// the blocks which are verified or in verification, the network drops the batches beyond it
+pub const IMPORT_QUEUE_SIZE: usize = 1 << 10;
+
+// the verified block, and whether its seals are verified too
+type Verified = oneshot::SpawnHandle<(Block, bool), String>;
+
+/// ImportPipeline imports the synced blocks in two stages: the seals and the transaction
+/// signatures are verified by a worker pool, the verified blocks are applied to the chain one by
+/// one by a single importer thread, in the order they were received. The seals of a block whose
+/// epoch isn't decided yet are verified by the importer, after the blocks before it. The importer
+/// imports the held future blocks too, and stops with the pipeline.
+pub struct ImportPipeline {
+    chain: Arc<Chain>,
+    pool: ThreadPool,
+    queue: Option<Sender<(String, Verified)>>,
+    importer: Option<JoinHandle<()>>,
+}
+
+impl ImportPipeline {
+    pub fn new(chain: Arc<Chain>, queue_size: usize) -> Self {
+        let (tx, rx) = crossbeam_channel::bounded(queue_size);
+        let importer = chain.clone();
+        let importer = thread::Builder::new().name("block-importer".to_string()).spawn(move || {
+            import_loop(importer, rx);
+        }).expect("Failed to spawn the block importer");
+        ImportPipeline { chain, pool: ThreadPool::new(), queue: Some(tx), importer: Some(importer) }
+    }
+
+    /// verify the batch in the worker pool and queue it for the import, the batch is dropped if
+    /// the queue is full, the missing heights are synced again
+    pub fn submit(&self, peer: &str, blocks: Blocks) -> Result<(), String> {
+        let queue = self.queue.as_ref().ok_or("the block importer is stopped".to_string())?;
+        for block in blocks.0 {
+            let (height, chain) = (block.height(), self.chain.clone());
+            let verified = oneshot::spawn(future::lazy(move || {
+                let chain_id = chain.config.chain_id;
+                match chain.get_decided_validators(block.height()) {
+                    Some(validators) => verify_block(&block, chain.seal_verifier().as_ref(), &validators, chain_id).map(|_| (block, true)),
+                    None => verify_body(&block, chain_id).map(|_| (block, false)),
+                }
+            }), self.pool.sender());
+            match queue.try_send((peer.to_string(), verified)) {
+                Ok(()) => metrics::set_gauge("chain_import_queue_size", queue.len() as i64),
+                Err(TrySendError::Full(_)) => {
+                    metrics::inc_counter("chain_import_queue_full_total");
+                    return Err(format!("the import queue is full, drop the blocks from height {}", height));
+                }
+                Err(TrySendError::Disconnected(_)) => return Err("the block importer is stopped".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// stop the importer after the queued blocks and the due future blocks are imported, the
+    /// others stay held by the chain
+    pub fn stop(&mut self) {
+        self.queue.take();
+        if let Some(importer) = self.importer.take() {
+            if importer.join().is_err() {
+                error!("The block importer panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ImportPipeline {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// the checks which don't need the chain state, they run in parallel. The transactions must be
+/// the ones of the transaction root, the sealed header doesn't cover the body otherwise, the
+/// blocks without transactions keep the empty root of the genesis block. The header bloom must
+/// index the logs of the transactions.
+pub fn verify_block(block: &Block, seal_verifier: &SealVerifier, validators: &[Address], chain_id: u64) -> Result<(), String> {
+    seal_verifier.verify_seals(block.header(), validators)?;
+    verify_body(block, chain_id)
+}
+
+/// the checks of `verify_block` without the seals
+pub fn verify_body(block: &Block, chain_id: u64) -> Result<(), String> {
+    if !block.transactions().is_empty() {
+        let tx_hash = merkle_root_transactions(block.transactions().to_vec());
+        if tx_hash != block.header().tx_hash {
+            return Err(format!("the transaction root {:?} isn't the root of the transactions {:?}", block.header().tx_hash, tx_hash));
+        }
+    }
+    if let Some(tx) = block.transactions().iter().find(|tx| !tx.verify_sign(chain_id)) {
+        return Err(format!("invalid transaction signature, tx: {:?}", tx.get_hash()));
+    }
+    verify_block_bloom(block.header(), block.transactions())
+}
+
+// the slot schedule depends on the parent, so it's checked by the importer. The held future blocks
+// are imported when the first of them is due, the loop ends when the pipeline stops
+fn import_loop(chain: Arc<Chain>, rx: Receiver<(String, Verified)>) {
+    loop {
+        let received = match chain.next_future_block() {
+            Some(wait) => match rx.recv_timeout(wait) {
+                Ok(job) => Some(job),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(job) => Some(job),
+                Err(_) => break,
+            },
+        };
+        chain.import_future_blocks();
+        let (peer, verified) = match received {
+            Some(job) => job,
+            None => continue,
+        };
+        metrics::set_gauge("chain_import_queue_size", rx.len() as i64);
+        let verified = verified.wait()
+            .and_then(|(block, sealed)| verify_seals(&chain, block, sealed))
+            .and_then(|block| match chain.config.engine {
+                EngineKind::Dpos => verify_schedule(&chain, block),
+                EngineKind::Pbft => Ok(block),
+            });
+        match verified {
+            Ok(block) => import_block(&chain, &peer, block),
+            Err(err) => {
+                metrics::inc_counter("chain_import_invalid_block_total");
+                warn!("Drop the invalid block, peer: {}, err: {}", peer, err);
+            }
+        }
+    }
+    chain.import_future_blocks();
+    info!("Block importer is stopped");
+}
+
+// the seals of the block whose epoch wasn't decided in the worker, the blocks before it are
+// imported now. The epoch of a block beyond a gap is still unknown, the gap is synced instead
+fn verify_seals(chain: &Arc<Chain>, block: Block, sealed: bool) -> Result<Block, String> {
+    if sealed {
+        return Ok(block);
+    }
+    match chain.get_decided_validators(block.height()) {
+        Some(validators) => chain.seal_verifier().verify_seals(block.header(), &validators).map(|_| block),
+        None => {
+            chain.post_event(ChainEvent::SyncBlock(chain.get_last_height() + 1));
+            Err(format!("the epoch of the block isn't decided, height: {}", block.height()))
+        }
+    }
+}
+
+// the dpos blocks can't be held as orphans, their slots are only known after the parent, so the
+// gap is synced instead. A block at a stored height is checked too, it may replace the stored
+// block by the fork choice of the chain
+fn verify_schedule(chain: &Arc<Chain>, block: Block) -> Result<Block, String> {
+    let last_height = chain.get_last_height();
+    if block.height() > last_height + 1 {
+        chain.post_event(ChainEvent::SyncBlock(last_height + 1));
+        return Err(format!("the parent of the block is unknown, height: {}", block.height()));
+    }
+    chain.verify_slot(block.header()).map(|_| block).map_err(|err| format!("{}", err))
+}
+
+// the held block is imported by the loop when it's due
+fn import_block(chain: &Arc<Chain>, peer: &str, block: Block) {
+    match chain.admit_future_block(peer, &block) {
+        Admission::Ready => {
+            if let Err(err) = chain.insert_block(&block) {
+                debug!("Failed to import block, hash: {:?}, height: {}, err: {:?}", block.hash().short(), block.height(), err);
+            }
+        }
+        Admission::Held(wait) => {
+            debug!("Hold the future block, hash: {:?}, height: {}, wait: {:?}", block.hash().short(), block.height(), wait);
+        }
+        Admission::Rejected => {
+            warn!("Drop the block too far in the future, hash: {:?}, height: {}, time: {}", block.hash().short(), block.height(), block.header().time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use ::actix::prelude::System;
+    use crate::consensus::consensus::CommitSeals;
+    use crate::core::executor::block_bloom;
+    use crate::core::test_util::{memory_chain, memory_chain_at, sealed_block};
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::types::block::Header;
+    use crate::types::transaction::Transaction;
+    use crate::types::votes::{encrypt_commit_bytes, Votes};
+    use crate::util::clock::ManualClock;
+
+    #[test]
+    fn t_verify_block() {
+        let key_pair = Random.generate().unwrap();
+        let validators = vec![key_pair.address()];
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, None);
+        assert!(verify_block(&Block::new(header.clone(), vec![]), &CommitSeals, &validators, 1).is_err());
+        header.votes = Some(Votes::new(vec![encrypt_commit_bytes(&header.block_hash(), key_pair.secret())]));
+        assert!(verify_block(&Block::new(header.clone(), vec![]), &CommitSeals, &validators, 1).is_ok());
+        // the seals of an unknown validator
+        assert!(verify_block(&Block::new(header.clone(), vec![]), &CommitSeals, &[Random.generate().unwrap().address()], 1).is_err());
+
+        // the transactions which aren't sealed by the transaction root
+        let mut transaction = Transaction::new(0, Address::from(1), 1, 21_000, 1, vec![]);
+        transaction.sign(1, key_pair.secret());
+        assert!(verify_block(&Block::new(header, vec![transaction.clone()]), &CommitSeals, &validators, 1).is_err());
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), merkle_root_transactions(vec![transaction.clone()]), 1, 1, None);
+        header.votes = Some(Votes::new(vec![encrypt_commit_bytes(&header.block_hash(), key_pair.secret())]));
+        // the zero bloom hides the logs of the transactions
+        assert!(verify_block(&Block::new(header.clone(), vec![transaction.clone()]), &CommitSeals, &validators, 1).is_err());
+        header.bloom = block_bloom(&[transaction.clone()]);
+        header.votes = Some(Votes::new(vec![encrypt_commit_bytes(&header.block_hash(), key_pair.secret())]));
+        assert!(verify_block(&Block::new(header, vec![transaction]), &CommitSeals, &validators, 1).is_ok());
+    }
+
+    // the seals of an engine outside the tree, the blocks are checked by the verifier of the chain
+    struct ProposerOnly;
+
+    impl SealVerifier for ProposerOnly {
+        fn verify_seals(&self, header: &Header, validators: &[Address]) -> Result<(), String> {
+            if validators.contains(&header.proposer) {
+                Ok(())
+            } else {
+                Err("unknown proposer".to_string())
+            }
+        }
+    }
+
+    #[test]
+    fn t_verify_block_seal_verifier() {
+        let key_pair = Random.generate().unwrap();
+        let validators = vec![key_pair.address()];
+        let header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, None);
+        assert!(verify_block(&Block::new(header.clone(), vec![]), &CommitSeals, &validators, 1).is_err());
+        assert!(verify_block(&Block::new(header.clone(), vec![]), &ProposerOnly, &validators, 1).is_ok());
+        assert!(verify_block(&Block::new(header, vec![]), &ProposerOnly, &[Address::from(1)], 1).is_err());
+    }
+
+    #[test]
+    fn t_import_pipeline() {
+        let _system = System::new("t_import_pipeline");
+        let key_pair = Random.generate().unwrap();
+        let chain = memory_chain(&key_pair);
+        let mut pipeline = ImportPipeline::new(chain.clone(), 8);
+        let mut blocks = vec![];
+        let mut parent = chain.get_last_hash();
+        for height in 1..=3 {
+            let block = sealed_block(parent, height, &key_pair);
+            parent = block.hash();
+            blocks.push(block);
+        }
+        // the block sealed by a key which isn't a validator is dropped
+        blocks.push(sealed_block(parent, 4, &Random.generate().unwrap()));
+        pipeline.submit("peer", Blocks(blocks)).unwrap();
+
+        // the queued blocks are imported before the importer stops
+        pipeline.stop();
+        assert_eq!(chain.get_last_height(), 3);
+        assert!(pipeline.submit("peer", Blocks(vec![sealed_block(parent, 4, &key_pair)])).is_err());
+    }
+
+    #[test]
+    fn t_import_future_block() {
+        let _system = System::new("t_import_future_block");
+        let key_pair = Random.generate().unwrap();
+        let now = chrono::Local::now().timestamp() as u64;
+        let clock = Arc::new(ManualClock::new(now));
+        let chain = memory_chain_at(&key_pair, clock.clone());
+        let mut header = Header::new_mock(chain.get_last_hash(), key_pair.address(), EMPTY_HASH, 1, now + 2, None);
+        header.votes = Some(Votes::new(vec![encrypt_commit_bytes(&header.block_hash(), key_pair.secret())]));
+        let mut pipeline = ImportPipeline::new(chain.clone(), 8);
+        pipeline.submit("peer", Blocks(vec![Block::new(header, vec![])])).unwrap();
+        pipeline.stop();
+        assert_eq!(chain.get_last_height(), 0);
+        assert_eq!(chain.next_future_block(), Some(Duration::from_secs(2)));
+
+        // the held block is imported by the importer when it's due, without a new batch
+        clock.advance(Duration::from_secs(2));
+        ImportPipeline::new(chain.clone(), 8).stop();
+        assert_eq!(chain.get_last_height(), 1);
+        assert_eq!(chain.next_future_block(), None);
+    }
+}
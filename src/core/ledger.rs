@@ -1,14 +1,36 @@
+use std::sync::Arc;
+
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
 use kvdb_rocksdb::{Database, DatabaseConfig, DatabaseIterator};
 use lru_time_cache::LruCache;
 use parking_lot::RwLock;
 use chrono::{DateTime, TimeZone, NaiveDateTime, Utc};
 
 use crate::{
-    store::schema::Schema,
+    consensus::types::CommitCertificate,
+    consensus::dpos::delegates::write_votes,
+    consensus::epoch::epoch_number,
+    core::executor::{apply_transactions, execute_block},
+    core::fee::{write_fees, FeeConfig, FeeIncome},
+    core::state::{StateConfig, StateSnapshot, StateView},
+    core::staking::{write_stakes, StakingConfig, ValidatorStake},
+    core::reward::{block_rewards, write_rewards, RewardConfig, RewardHistory},
+    store::durability::{Durability, DurabilityConfig},
+    store::keys::AddressTxKey,
+    store::schema::{Schema, TxLocation},
+    store::backend::Backend,
+    store::staged::StagedFork,
+    store::types::Fork,
     types::block::{Block, Header},
     types::transaction::Transaction,
+    types::account::Account,
+    types::receipt::{LogEntry, LogFilter, Receipt, Receipts},
     types::{Height, Validator, ValidatorArray, HashesEntry},
+    protocol::GossipMessages,
+    metrics,
+    metrics::cache::MeteredCache,
 };
 
 pub struct LastMeta {
@@ -38,38 +60,75 @@ impl LastMeta {
     }
 }
 
-/// it is not thread safe
-pub struct Ledger {
+// the entries of a ledger cache
+pub const DEFAULT_CACHE_CAPACITY: usize = 1 << 10;
+
+/// it is not thread safe, the chain data is kept in the store `T`, the store of the config by default
+pub struct Ledger<T: ?Sized = Backend> {
     meta: LastMeta,
-    header_cache: RwLock<LruCache<Hash, Header>>,
-    block_cache: RwLock<LruCache<Hash, Block>>,
+    header_cache: RwLock<MeteredCache<Hash, Header>>,
+    block_cache: RwLock<MeteredCache<Hash, Block>>,
+    validator_cache: RwLock<MeteredCache<Height, Vec<Address>>>,
     genesis: Option<Block>,
     validators: Vec<Validator>,
-    schema: Schema,
+    state_config: StateConfig,
+    durability: Durability,
+    schema: Schema<T>,
 }
 
-impl Ledger {
+impl<T: Fork + ?Sized> Ledger<T> {
     pub fn new(
         meta: LastMeta,
         header_cache: LruCache<Hash, Header>,
         block_cache: LruCache<Hash, Block>,
         validators: Vec<Validator>,
-        schema: Schema,
+        schema: Schema<T>,
     ) -> Self {
         Ledger {
             meta,
-            header_cache: RwLock::new(header_cache),
-            block_cache: RwLock::new(block_cache),
+            header_cache: RwLock::new(MeteredCache::new("ledger_header", header_cache)),
+            block_cache: RwLock::new(MeteredCache::new("ledger_block", block_cache)),
+            validator_cache: RwLock::new(MeteredCache::with_capacity("ledger_validators", DEFAULT_CACHE_CAPACITY)),
             genesis: None,
             validators,
+            state_config: StateConfig::default(),
+            durability: Durability::new(DurabilityConfig::default(), None),
             schema,
         }
     }
 
+    pub fn with_validator_cache(mut self, capacity: usize) -> Self {
+        self.validator_cache = RwLock::new(MeteredCache::with_capacity("ledger_validators", capacity));
+        self
+    }
+
+    pub fn with_state_config(mut self, state_config: StateConfig) -> Self {
+        self.state_config = state_config;
+        self
+    }
+
+    /// the store at `path` is flushed by the durability level, the log is synced by its policy
+    pub fn with_durability(mut self, config: DurabilityConfig, path: &str) -> Self {
+        self.durability = Durability::new(config, Some(path.to_string()));
+        self
+    }
+
+    /// flush the blocks committed since the last flush, eg: before the node stops
+    pub fn flush(&mut self) {
+        if self.durability.pending() > 0 {
+            self.durability.flush(self.schema.database().as_ref());
+        }
+    }
+
     pub fn get_transaction(&self, tx_hash: &Hash) -> Option<Transaction> {
         self.schema.transaction().get(tx_hash)
     }
 
+    /// the block of a committed transaction, the stores before the index need `reindex`
+    pub fn get_transaction_location(&self, tx_hash: &Hash) -> Option<TxLocation> {
+        self.schema.tx_locations().get(tx_hash)
+    }
+
     pub fn get_genesis_block(&mut self) -> Option<&Block> {
         if self.genesis.is_some() {
             return self.genesis.as_ref();
@@ -109,11 +168,21 @@ impl Ledger {
         }
 
         if let Some(header) = self.schema.headers().get(block_hash) {
+            cache.insert(block_hash.clone(), header.clone());
             return Some(header);
         }
         None
     }
 
+    /// the transactions of the block, it's loaded from the body index
+    pub fn get_block_body(&self, block_hash: &Hash) -> Option<Vec<Transaction>> {
+        self.schema.block_bodies().get(block_hash).map(|body| {
+            body.0.iter().map(|tx_hash| {
+                self.schema.transaction().get(tx_hash).unwrap()
+            }).collect()
+        })
+    }
+
     pub fn get_block(&self, block_hash: &Hash) -> Option<Block> {
         let mut cache = self.block_cache.write();
         let mut header_cache = self.header_cache.write();
@@ -122,10 +191,7 @@ impl Ledger {
             Some(block) => Some(block.clone()),
             None => {
                 let result = self.schema.headers().get(block_hash).map(|header| {
-                    let transaction_entry = self.schema.transaction_hashes().get(block_hash).unwrap();
-                    let transactions: Vec<Transaction> = transaction_entry.0.iter().map(|hash| {
-                        self.schema.transaction().get(hash).unwrap()
-                    }).collect();
+                    let transactions = self.get_block_body(block_hash).unwrap();
                     Block::new(header, transactions)
                 });
 
@@ -140,17 +206,32 @@ impl Ledger {
         }
     }
 
-    pub fn get_transactions(&self) -> Vec<Transaction> {
-        let tx = self.schema.transaction();
-        let mut transactions = vec![];
-        for tx in tx.iter() {
-            transactions.push(tx.1.clone());
-        }
-        transactions
+    /// the transactions of the index from the offset, the skipped ones are not decoded
+    pub fn get_transactions_page(&self, offset: usize, count: usize) -> Vec<Transaction> {
+        let transactions = self.schema.transaction();
+        let hashes: Vec<Hash> = transactions.keys().skip(offset).take(count).collect();
+        hashes.iter().filter_map(|tx_hash| transactions.get(tx_hash)).collect()
     }
 
-    //  FIXME store it into schema
-    pub fn get_validators(&self, _height: Height) -> &Vec<Validator> { &self.validators }
+    /// the validator addresses at the height, the set of the decided epoch of the height or the
+    /// set scheduled at the height before the epoch is decided, the seal verification of every
+    /// block reads them
+    pub fn get_validator_addresses(&self, height: Height) -> Vec<Address> {
+        if let Some(addresses) = self.validator_cache.write().get(&height) {
+            return addresses.clone();
+        }
+        // the epochs share the length of the first one
+        let epochs = self.schema.epochs();
+        let epoch = epochs.get(&0).and_then(|first| epochs.get(&epoch_number(height, first.length)));
+        match epoch {
+            // a decided epoch never changes, its sets are cached until the chain is truncated
+            Some(epoch) => {
+                self.validator_cache.write().insert(height, epoch.validators.clone());
+                epoch.validators
+            }
+            None => self.get_validators_at(height),
+        }
+    }
 
     pub fn get_block_by_height(&self, height: Height) -> Option<Block> {
         if let Some(block_hash) = self.schema.block_hash_by_height(height) {
@@ -159,10 +240,7 @@ impl Ledger {
             }
 
             return self.schema.headers().get(&block_hash).map(|header| {
-                let transaction_entry = self.schema.transaction_hashes().get(&block_hash).unwrap();
-                let transactions: Vec<Transaction> = transaction_entry.0.iter().map(|block_hash| {
-                    self.schema.transaction().get(&block_hash).unwrap()
-                }).collect();
+                let transactions = self.get_block_body(&block_hash).unwrap();
                 Block::new(header, transactions)
             });
         }
@@ -185,29 +263,47 @@ impl Ledger {
         None
     }
 
+    /// headers in [from, to], it stops at the first missing height
+    pub fn get_headers(&self, from: Height, to: Height) -> Vec<Header> {
+        let mut headers = vec![];
+        for height in from..to + 1 {
+            match self.get_header_by_height(height) {
+                Some(header) => headers.push(header),
+                None => break,
+            }
+        }
+        headers
+    }
+
     pub fn add_genesis_block(&mut self, block: &Block) {
         self.add_block(block);
         self.genesis = Some(block.clone());
     }
 
     pub fn add_block(&mut self, block: &Block) {
+        self.add_committed_block(block, None);
+    }
+
+    /// add the block with the commit certificate and the consensus messages which committed it,
+    /// they're merged in the same write as the block, a committed block always has its certificate
+    pub fn add_committed_block(&mut self, block: &Block, commit: Option<(CommitCertificate, GossipMessages)>) {
         let header = block.header();
         let hash = header.block_hash();
         if self.meta.header.height >= header.height && block.height() != 0 {
             return;
         }
 
-        // persists
+        // the header, the body, the indexes and the commit are staged and merged in one write, a crash never
+        // leaves a part of them
+        let staged = Arc::new(StagedFork::new(self.schema.database().clone()));
         {
+            let schema = Schema::new(staged.clone());
 //            debug!("Write header");
-            let mut header_db = self.schema.headers();
+            let mut header_db = schema.headers();
             header_db.put(&hash, header.clone());
-        }
 
-        // transactions
-        {
             let mut tx_hashes = HashesEntry(vec![]);
-            let mut tx_db = self.schema.transaction();
+            let mut tx_db = schema.transaction();
 //            debug!("Write transaction");
             for transaction in block.transactions() {
                 let tx_hash = transaction.hash();
@@ -215,9 +311,20 @@ impl Ledger {
                 tx_hashes.0.push(tx_hash);
             }
 
-            let mut tx_hashes_db = self.schema.transaction_hashes();
-            tx_hashes_db.put(&hash, tx_hashes);
+            let mut body_db = schema.block_bodies();
+            body_db.put(&hash, tx_hashes);
+
+            write_block_indexes(&schema, header.height, &hash, block.transactions());
+
+            if let Some((certificate, messages)) = commit {
+                let mut messages_db = schema.consensus_messages();
+                messages_db.put(&header.height, messages);
+                let mut certificates_db = schema.commit_certificates();
+                certificates_db.put(&certificate.height(), certificate);
+            }
         }
+        staged.merge().expect("Failed to write the block");
+        self.snapshot_state(header.height);
 
         // height
         {
@@ -240,16 +347,219 @@ impl Ledger {
 
         // update last meta
         self.update_meta(block);
+        self.durability.commit(self.schema.database().as_ref());
+        metrics::set_gauge("chain_height", header.height as i64);
         let dt = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(header.time as i64, 0), Utc);
         info!("📝 Insert new block, hash:{:?}, height:{}, utime:{}, proposer:{:?}", hash.short(), header.height, dt.to_rfc3339(), header.proposer);
     }
 
+    pub fn get_account(&self, address: &Address) -> Account {
+        self.schema.accounts().get(address).unwrap_or_default()
+    }
+
+    pub fn get_address_txs(&self, address: &Address) -> Vec<(Height, Hash)> {
+        self.schema.address_txs().iter_prefix(address).map(|(key, tx_hash)| (key.height, tx_hash)).collect()
+    }
+
+    /// the transactions of the address from the offset in the height order, only the page is read
+    pub fn get_address_txs_page(&self, address: &Address, offset: usize, count: usize) -> Vec<(Height, Hash)> {
+        self.schema.address_txs().iter_prefix(address).skip(offset).take(count).map(|(key, tx_hash)| (key.height, tx_hash)).collect()
+    }
+
+    pub fn count_address_txs(&self, address: &Address) -> usize {
+        self.schema.address_tx_counts().get(address).unwrap_or(0) as usize
+    }
+
+    /// the height of the last transaction of the address at or below the height
+    pub fn last_address_tx_height(&self, address: &Address, height: Height) -> Option<Height> {
+        self.schema.address_txs().iter_prefix(address)
+            .map(|(key, _)| key.height)
+            .take_while(|tx_height| *tx_height <= height)
+            .last()
+    }
+
+    /// the account at a historical height, it's read from the state view of the height, so it
+    /// costs the replay from the nearest snapshot, not the history of the address
+    pub fn get_account_at(&self, address: &Address, height: Height) -> Result<Account, String> {
+        self.state_at(height).map(|view| view.account(address))
+    }
+
+    /// the account states after the block at the height, they're replayed from the nearest
+    /// snapshot below it, the heights below the retained snapshots are pruned
+    pub fn state_at(&self, height: Height) -> Result<StateView, String> {
+        let last_height = self.meta.height;
+        if height > last_height {
+            return Err(format!("the height {} is above the last block {}", height, last_height));
+        }
+        if height == last_height {
+            return Ok(StateView::new(height, self.schema.accounts().iter().collect()));
+        }
+        let oldest = self.state_config.oldest_retained(last_height);
+        if height < oldest {
+            return Err(format!("the state at height {} is pruned, the oldest retained is {}", height, oldest));
+        }
+        let (reward_config, fee_config) = (self.get_reward_config(), self.get_fee_config());
+        let mut view = match self.nearest_snapshot(height, oldest) {
+            Some(snapshot) => StateView::from_snapshot(snapshot),
+            None => {
+                let genesis = self.get_block_by_height(0).ok_or("the genesis block is missing".to_string())?;
+                let mut view = StateView::new(0, self.schema.genesis_alloc().iter().collect());
+                view.apply_block(0, genesis.transactions(), &fee_config);
+                view
+            }
+        };
+        for replay in view.height + 1..=height {
+            let block = self.get_block_by_height(replay).ok_or(format!("the block at height {} is missing", replay))?;
+            let outcomes = view.apply_block(replay, block.transactions(), &fee_config);
+            view.apply_rewards(&block_rewards(&reward_config, block.header()));
+            let (paid, _) = fee_config.block_fees(&outcomes);
+            if paid > 0 {
+                view.apply_rewards(&[(block.header().proposer, paid)]);
+            }
+            if let Some(releases) = self.schema.stake_releases().get(&replay) {
+                view.apply_releases(&releases.0);
+            }
+            metrics::inc_counter("ledger_state_replayed_blocks_total");
+        }
+        Ok(view)
+    }
+
+    /// the state root of the snapshot at the height
+    pub fn get_state_root(&self, height: Height) -> Option<Hash> {
+        self.schema.state_roots().get(&height)
+    }
+
+    /// the stake bonded to the validator
+    pub fn get_stake(&self, validator: &Address) -> ValidatorStake {
+        self.schema.stakes().get(validator).unwrap_or_default()
+    }
+
+    pub fn get_staking_config(&self) -> StakingConfig {
+        self.schema.staking_config().get().unwrap_or_default()
+    }
+
+    /// set the staking rules, it runs once before the genesis block is stored
+    pub fn set_staking_config(&mut self, config: StakingConfig) {
+        self.schema.staking_config().set(config);
+    }
+
+    /// the block rewards paid to the address in height order
+    pub fn get_rewards(&self, address: &Address) -> RewardHistory {
+        RewardHistory(self.schema.rewards().iter_prefix(address).map(|(key, amount)| (key.height, amount)).collect())
+    }
+
+    /// the rewards of the address from the offset, the scan stops at the page
+    pub fn get_rewards_page(&self, address: &Address, offset: usize, count: usize) -> Vec<(Height, u64)> {
+        self.schema.rewards().iter_prefix(address).skip(offset).take(count).map(|(key, amount)| (key.height, amount)).collect()
+    }
+
+    pub fn get_reward_config(&self) -> RewardConfig {
+        self.schema.reward_config().get().unwrap_or_default()
+    }
+
+    /// set the reward schedule, it runs once before the genesis block is stored
+    pub fn set_reward_config(&mut self, config: RewardConfig) {
+        self.schema.reward_config().set(config);
+    }
+
+    /// the unburned fees paid to the address as a proposer
+    pub fn get_fee_income(&self, address: &Address) -> FeeIncome {
+        self.schema.fee_income().get(address).unwrap_or_default()
+    }
+
+    /// the stores which were created before the fees charge nothing
+    pub fn get_fee_config(&self) -> FeeConfig {
+        self.schema.fee_config().get().unwrap_or_else(FeeConfig::disabled)
+    }
+
+    /// set the fee rules, it runs once before the genesis block is stored
+    pub fn set_fee_config(&mut self, config: FeeConfig) {
+        self.schema.fee_config().set(config);
+    }
+
+    pub fn get_receipts(&self, height: Height) -> Vec<Receipt> {
+        self.schema.receipts().get(&height).map(|receipts| receipts.0).unwrap_or_default()
+    }
+
+    /// the matched logs of the filter, the receipts of a block are only read when its header
+    /// bloom may match, at most `limit` logs are returned
+    pub fn get_logs(&self, filter: &LogFilter, limit: usize) -> Vec<LogEntry> {
+        let mut entries = vec![];
+        let to = ::std::cmp::min(filter.to, self.meta.height);
+        for height in filter.from..=to {
+            match self.get_header_by_height(height) {
+                Some(ref header) if filter.may_match(&header.bloom) => {}
+                _ => continue,
+            }
+            metrics::inc_counter("ledger_log_scanned_blocks_total");
+            for receipt in self.get_receipts(height) {
+                for (log_index, log) in receipt.logs.into_iter().enumerate() {
+                    if !filter.matches(&log) {
+                        continue;
+                    }
+                    entries.push(LogEntry { height, tx_hash: receipt.tx_hash, log_index: log_index as u64, log });
+                    if entries.len() >= limit {
+                        return entries;
+                    }
+                }
+            }
+        }
+        entries
+    }
+
+    pub fn add_consensus_messages(&mut self, height: Height, messages: GossipMessages) {
+        let mut messages_db = self.schema.consensus_messages();
+        messages_db.put(&height, messages);
+    }
+
+    pub fn get_consensus_messages(&self, height: Height) -> Option<GossipMessages> {
+        self.schema.consensus_messages().get(&height)
+    }
+
+    pub fn add_commit_certificate(&mut self, certificate: CommitCertificate) {
+        let mut certificates_db = self.schema.commit_certificates();
+        certificates_db.put(&certificate.height(), certificate);
+    }
+
+    pub fn get_commit_certificate(&self, height: Height) -> Option<CommitCertificate> {
+        self.schema.commit_certificates().get(&height)
+    }
+
+    /// credit the genesis allocation, it runs once before the genesis block is stored
+    pub fn add_genesis_alloc(&mut self, alloc: &[(Address, u64)]) {
+        let mut accounts_db = self.schema.accounts();
+        let mut alloc_db = self.schema.genesis_alloc();
+        for (address, balance) in alloc {
+            let mut account = accounts_db.get(address).unwrap_or_default();
+            account.credit(*balance);
+            accounts_db.put(address, account.clone());
+            alloc_db.put(address, account);
+        }
+    }
+
+    /// the genesis validators, they're kept until a set is scheduled by `schedule_validators`
     pub fn add_validators(&mut self, validators: Vec<Validator>) {
+        self.schedule_validators(0, validators);
+    }
+
+    /// the validators from the height on, the sets of the lower heights are kept
+    pub fn schedule_validators(&mut self, height: Height, validators: Vec<Validator>) {
         let val_array = ValidatorArray::from(validators.clone());
+        self.schema.validator_changes().put(&height, val_array.clone());
         let mut validators_entry = self.schema.validators();
         validators_entry.set(val_array);
         // cache it
         self.validators = validators;
+        self.validator_cache.get_mut().clear();
+    }
+
+    /// the validators set at the height, the stores without the scheduled sets fall back to the
+    /// current set
+    pub fn get_validators_at(&self, height: Height) -> Vec<Address> {
+        match self.schema.validator_changes().iter_rev_from(&height).next() {
+            Some((_, validators)) => validators.addresses().to_vec(),
+            None => self.validators.iter().map(|validator| *validator.address()).collect(),
+        }
     }
 
     pub fn reload_meta(&mut self) {
@@ -259,10 +569,73 @@ impl Ledger {
         self.update_meta(&last_block);
     }
 
-    pub fn get_schema(&self) -> &Schema {
+    /// evict all cached headers and blocks
+    pub fn clear_caches(&mut self) {
+        self.header_cache.get_mut().clear();
+        self.block_cache.get_mut().clear();
+        self.validator_cache.get_mut().clear();
+    }
+
+    pub fn get_schema(&self) -> &Schema<T> {
         &self.schema
     }
 
+    /// the bytes dropped by the prunes and the truncates since the last compaction, they're
+    /// persisted, so the compaction at the next start knows them
+    pub fn pruned_bytes(&self) -> u64 {
+        self.schema.pruned_bytes().get().unwrap_or(0)
+    }
+
+    pub fn add_pruned_bytes(&mut self, bytes: u64) {
+        let pruned = self.pruned_bytes();
+        self.schema.pruned_bytes().set(pruned.saturating_add(bytes));
+    }
+
+    pub fn reset_pruned_bytes(&mut self) {
+        self.schema.pruned_bytes().remove();
+    }
+
+    // the nearest retained snapshot at or below the height
+    fn nearest_snapshot(&self, height: Height, oldest: Height) -> Option<StateSnapshot> {
+        let interval = self.state_config.snapshot_interval;
+        let snapshots = self.schema.state_snapshots();
+        let mut candidate = self.state_config.snapshot_below(height);
+        while interval > 0 && candidate > 0 && candidate >= oldest {
+            if let Some(snapshot) = snapshots.get(&candidate) {
+                return Some(snapshot);
+            }
+            candidate -= interval;
+        }
+        None
+    }
+
+    // persist the account states every `snapshot_interval` blocks and prune the snapshots
+    // beyond the history
+    fn snapshot_state(&mut self, height: Height) {
+        if !self.state_config.is_due(height) {
+            return;
+        }
+        let snapshot = StateView::new(height, self.schema.accounts().iter().collect()).snapshot();
+        self.schema.state_roots().put(&height, snapshot.root);
+        self.schema.state_snapshots().put(&height, snapshot);
+        metrics::set_gauge("ledger_state_snapshot_height", height as i64);
+
+        let oldest = self.state_config.oldest_retained(height);
+        let pruned: Vec<Height> = self.schema.state_roots().keys().take_while(|snapshot_height| *snapshot_height < oldest).collect();
+        let (mut roots_db, mut snapshots_db) = (self.schema.state_roots(), self.schema.state_snapshots());
+        let mut pruned_bytes = 0;
+        for snapshot_height in pruned {
+            if let Some(snapshot) = snapshots_db.get(&snapshot_height) {
+                pruned_bytes += snapshot.into_bytes().len() as u64;
+            }
+            roots_db.remove(&snapshot_height);
+            snapshots_db.remove(&snapshot_height);
+        }
+        if pruned_bytes > 0 {
+            self.add_pruned_bytes(pruned_bytes);
+        }
+    }
+
     fn update_meta(&mut self, block: &Block) {
         let header = block.header();
         self.meta.header = header.clone();
@@ -272,6 +645,70 @@ impl Ledger {
     }
 }
 
+/// the indexes derived from the transactions of a block: the account states, the address
+/// transaction index, the transaction locations, the votes, the stakes, the rewards, the fees and
+/// the receipts, `reindex` rebuilds them with it, the header of the block must be stored before
+pub fn write_block_indexes<T: Fork + ?Sized>(schema: &Schema<T>, height: Height, hash: &Hash, transactions: &[Transaction]) {
+    let fee_config = schema.fee_config().get().unwrap_or_else(FeeConfig::disabled);
+    // account state, the amounts which the senders can't cover aren't moved
+    let outcomes = apply_transactions(&mut schema.accounts(), transactions, &fee_config);
+
+    // address transaction index
+    {
+        let mut address_txs_db = schema.address_txs();
+        let mut counts_db = schema.address_tx_counts();
+        for (position, transaction) in transactions.iter().enumerate() {
+            let tx_hash = transaction.hash();
+            let mut addresses: Vec<Address> = transaction.sender().into_iter().chain(transaction.to().cloned()).collect();
+            addresses.dedup();
+            for address in addresses {
+                address_txs_db.put(&AddressTxKey::new(address, height, position as u32), tx_hash);
+                let count = counts_db.get(&address).unwrap_or(0);
+                counts_db.put(&address, count + 1);
+            }
+        }
+    }
+
+    // transaction location index
+    {
+        let mut locations_db = schema.tx_locations();
+        for (position, transaction) in transactions.iter().enumerate() {
+            locations_db.put(&transaction.hash(), TxLocation { block_height: height, block_hash: *hash, position: position as u64 });
+        }
+    }
+
+    // dpos vote index
+    write_votes(schema, height, transactions);
+
+    // staking index and the released stakes, the bonds which weren't moved stake nothing
+    write_stakes(schema, height, transactions, &outcomes);
+
+    // block rewards of the proposer and the signers
+    write_rewards(schema, height, hash);
+
+    // the unburned fees of the proposer
+    write_fees(schema, height, hash, &outcomes);
+
+    // receipts with the logs
+    schema.receipts().put(&height, Receipts(execute_block(height, transactions, &outcomes, &fee_config)));
+}
+
+/// drop the state snapshots above the height, they were taken on the blocks which are gone, eg:
+/// after a truncate. It returns the dropped bytes
+pub fn drop_states_above<T: Fork + ?Sized>(schema: &Schema<T>, height: Height) -> u64 {
+    let (mut roots_db, mut snapshots_db) = (schema.state_roots(), schema.state_snapshots());
+    let stale: Vec<Height> = roots_db.keys().filter(|snapshot_height| *snapshot_height > height).collect();
+    let mut dropped_bytes = 0;
+    for snapshot_height in stale {
+        if let Some(snapshot) = snapshots_db.get(&snapshot_height) {
+            dropped_bytes += snapshot.into_bytes().len() as u64;
+        }
+        roots_db.remove(&snapshot_height);
+        snapshots_db.remove(&snapshot_height);
+    }
+    dropped_bytes
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -325,4 +762,269 @@ mod tests {
         println!("---> {:?}", header);
 //        let mut ledger = Ledger::new();
     }
+
+    #[test]
+    fn t_validator_cache() {
+        use crate::core::test_util::ledger_of;
+        use crate::consensus::epoch::Epoch;
+        let validators = vec![Validator::new(Address::from(1))];
+        let mut ledger = ledger_of(Backend::open(&crate::common::random_dir()).unwrap(), validators)
+            .with_validator_cache(4);
+        ledger.add_validators(vec![Validator::new(Address::from(1))]);
+        // the heights of the decided epochs read their sets
+        let mut epochs = ledger.get_schema().epochs();
+        epochs.put(&0, Epoch { number: 0, start: 0, length: 10, validators: vec![Address::from(1)] });
+        epochs.put(&1, Epoch { number: 1, start: 10, length: 10, validators: vec![Address::from(3)] });
+        assert_eq!(ledger.get_validator_addresses(1), vec![Address::from(1)]);
+        assert_eq!(ledger.get_validator_addresses(1), vec![Address::from(1)]);
+        assert_eq!(ledger.get_validator_addresses(15), vec![Address::from(3)]);
+        assert_eq!(ledger.validator_cache.read().len(), 2);
+        // the undecided epochs read the scheduled sets, they aren't cached
+        ledger.schedule_validators(20, vec![Validator::new(Address::from(2))]);
+        assert!(ledger.validator_cache.read().is_empty());
+        assert_eq!(ledger.get_validator_addresses(25), vec![Address::from(2)]);
+        assert_eq!(ledger.get_validator_addresses(15), vec![Address::from(3)]);
+        assert_eq!(ledger.validator_cache.read().len(), 1);
+        ledger.clear_caches();
+        assert!(ledger.validator_cache.read().is_empty());
+    }
+
+    #[test]
+    fn t_state_at() {
+        use crate::core::test_util::ledger_of;
+        let mut ledger = ledger_of(Backend::open(&crate::common::random_dir()).unwrap(), vec![])
+            .with_state_config(StateConfig { snapshot_interval: 2, history: 1 });
+        ledger.add_genesis_alloc(&[(Address::from(1), 100)]);
+        for height in 0..7 {
+            let mut header = Header::zero_header();
+            header.height = height;
+            // every block mints 10 to the address 1
+            ledger.add_block(&Block::new(header, vec![Transaction::new(height, Address::from(1), 10, 0, 1, vec![])]));
+        }
+        // the snapshot of 2 is pruned by the history
+        assert_eq!(ledger.get_schema().state_snapshots().keys().collect::<Vec<_>>(), vec![4, 6]);
+        assert!(ledger.get_state_root(6).is_some() && ledger.get_state_root(2).is_none());
+        assert!(ledger.state_at(3).is_err());
+        assert!(ledger.state_at(7).is_err());
+        assert_eq!(ledger.state_at(5).unwrap().account(&Address::from(1)).balance, 160);
+        assert_eq!(ledger.state_at(6).unwrap().root(), ledger.get_state_root(6).unwrap());
+    }
+
+    // the ledger over the memory store needs no directory
+    #[test]
+    fn t_memory_ledger() {
+        use crate::core::test_util::memory_ledger_of;
+        let mut ledger = memory_ledger_of(vec![]);
+        for height in 0..3 {
+            let mut header = Header::zero_header();
+            header.height = height;
+            ledger.add_block(&Block::new(header, vec![Transaction::new(height, Address::from(1), 10, 0, 1, vec![])]));
+        }
+        assert_eq!(*ledger.get_last_block_height(), 2);
+        let hash = ledger.get_block_hash_by_height(1).unwrap();
+        ledger.clear_caches();
+        assert_eq!(ledger.get_block(&hash).unwrap().height(), 1);
+        assert_eq!(ledger.get_account(&Address::from(1)).balance, 30);
+        assert!(ledger.get_schema().database().len() > 0);
+    }
+
+    #[test]
+    fn t_get_logs() {
+        use crate::core::test_util::memory_ledger_of;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::core::executor::block_bloom;
+        use crate::types::receipt::{MINT_TOPIC, TRANSFER_TOPIC};
+        let key_pair = Random.generate().unwrap();
+        let (miner, recipient) = (Address::from(1), Address::from(2));
+        let mut ledger = memory_ledger_of(vec![]);
+        let mut transfer = Transaction::new(0, recipient, 5, 21_000, 1, vec![]);
+        transfer.sign(1, key_pair.secret());
+        let blocks = vec![
+            vec![Transaction::new(0, key_pair.address(), 10, 0, 1, vec![])],
+            vec![Transaction::new(1, miner, 10, 0, 1, vec![]), transfer],
+            vec![],
+            vec![Transaction::new(3, miner, 10, 0, 1, vec![])],
+        ];
+        for (height, transactions) in blocks.into_iter().enumerate() {
+            let mut header = Header::zero_header();
+            header.height = height as Height;
+            header.bloom = block_bloom(&transactions);
+            ledger.add_block(&Block::new(header, transactions));
+        }
+        fn logs(ledger: &Ledger, address: Option<Address>, topic: Option<Hash>) -> Vec<(Height, u64)> {
+            let filter = LogFilter { from: 0, to: 10, address, topic };
+            ledger.get_logs(&filter, 10).iter().map(|entry| (entry.height, entry.log_index)).collect()
+        }
+        assert_eq!(logs(&ledger, Some(miner), Some(*MINT_TOPIC)), vec![(1, 0), (3, 0)]);
+        // the recipient matches the transfer to it, the sender emits it
+        assert_eq!(logs(&ledger, Some(recipient), None), vec![(1, 0)]);
+        assert_eq!(logs(&ledger, Some(key_pair.address()), Some(*TRANSFER_TOPIC)), vec![(1, 0)]);
+        assert_eq!(logs(&ledger, None, Some(*TRANSFER_TOPIC)), vec![(1, 0)]);
+        assert!(logs(&ledger, Some(Address::from(3)), None).is_empty());
+        assert_eq!(ledger.get_logs(&LogFilter { from: 0, to: 10, address: None, topic: Some(*MINT_TOPIC) }, 2).len(), 2);
+
+        // the receipts of a block are only read when its bloom may match
+        let mut header = Header::zero_header();
+        header.height = 4;
+        ledger.add_block(&Block::new(header, vec![Transaction::new(4, miner, 10, 0, 1, vec![])]));
+        assert_eq!(logs(&ledger, Some(miner), Some(*MINT_TOPIC)), vec![(1, 0), (3, 0)]);
+    }
+
+    // the historical accounts are read from the state views, the transfers which the sender can't
+    // cover move nothing like they do in `add_block`
+    #[test]
+    fn t_account_at() {
+        use crate::core::test_util::memory_ledger_of;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let recipient = Address::from(2);
+        let mut ledger = memory_ledger_of(vec![])
+            .with_state_config(StateConfig { snapshot_interval: 2, history: 1 });
+        ledger.add_genesis_alloc(&[(key_pair.address(), 100)]);
+        for height in 0..7 {
+            let mut transfer = Transaction::new(height, recipient, if height == 3 { 1000 } else { 10 }, 0, 1, vec![]);
+            transfer.sign(1, key_pair.secret());
+            let mut header = Header::zero_header();
+            header.height = height;
+            ledger.add_block(&Block::new(header, vec![Transaction::new(height, Address::from(1), 10, 0, 1, vec![]), transfer]));
+        }
+        for height in 4..7 {
+            let view = ledger.state_at(height).unwrap();
+            assert_eq!(ledger.get_account_at(&recipient, height).unwrap(), view.account(&recipient));
+            assert_eq!(ledger.get_account_at(&key_pair.address(), height).unwrap(), view.account(&key_pair.address()));
+        }
+        // the transfer of 1000 at the height 3 moves nothing
+        assert_eq!(ledger.get_account_at(&recipient, 4).unwrap().balance, 40);
+        assert_eq!(ledger.get_account_at(&recipient, 6).unwrap().balance, ledger.get_account(&recipient).balance);
+        // the heights below the retained snapshots are pruned
+        assert!(ledger.get_account_at(&recipient, 2).is_err());
+        assert!(ledger.get_account_at(&recipient, 7).is_err());
+    }
+
+    // the nodes collect different commit seals of the same blocks, their states stay the same
+    #[test]
+    fn t_rewards_seal_subsets() {
+        use crate::core::test_util::memory_ledger_of;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::types::votes::{encrypt_commit_bytes, Votes};
+        let key_pairs: Vec<_> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let validators: Vec<Validator> = key_pairs.iter().map(|key_pair| Validator::new(key_pair.address())).collect();
+        let mut nodes: Vec<Ledger> = (0..2).map(|_| {
+            let mut ledger = memory_ledger_of(validators.clone());
+            ledger.set_reward_config(RewardConfig { block_reward: 100, halving_interval: 0 });
+            ledger
+        }).collect();
+        let mut prev_hash = Hash::zero();
+        for height in 0..5 {
+            let mut header = Header::zero_header();
+            header.height = height;
+            header.prev_hash = prev_hash;
+            header.proposer = key_pairs[height as usize % 4].address();
+            prev_hash = header.block_hash();
+            // the 2f+1 seals of the node `idx` leave out the validator `idx`
+            for (idx, ledger) in nodes.iter_mut().enumerate() {
+                let seals = key_pairs.iter().enumerate().filter(|(signer, _)| *signer != idx)
+                    .map(|(_, key_pair)| encrypt_commit_bytes(&prev_hash, key_pair.secret())).collect();
+                let mut header = header.clone();
+                header.votes = Some(Votes::new(seals));
+                ledger.add_block(&Block::new(header, vec![]));
+            }
+        }
+        let roots: Vec<Hash> = nodes.iter().map(|ledger| ledger.state_at(4).unwrap().root()).collect();
+        assert_eq!(roots[0], roots[1]);
+        // the genesis block pays nothing, only the proposers are paid
+        assert_eq!(nodes[1].get_rewards(&key_pairs[0].address()).0, vec![(4, 100)]);
+        assert!(key_pairs.iter().all(|key_pair| nodes[0].get_account(&key_pair.address()).balance == 100));
+    }
+
+    // the list apis read their pages from the index
+    #[test]
+    fn t_rewards_page() {
+        use crate::core::test_util::memory_ledger_of;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::types::votes::{encrypt_commit_bytes, Votes};
+        let key_pairs: Vec<_> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let validators: Vec<Validator> = key_pairs.iter().map(|key_pair| Validator::new(key_pair.address())).collect();
+        let mut ledger = memory_ledger_of(validators);
+        ledger.set_reward_config(RewardConfig { block_reward: 100, halving_interval: 0 });
+        let proposer = key_pairs[0].address();
+        let mut prev_hash = Hash::zero();
+        for height in 0..6 {
+            let mut header = Header::zero_header();
+            header.height = height;
+            header.prev_hash = prev_hash;
+            header.proposer = proposer;
+            prev_hash = header.block_hash();
+            header.votes = Some(Votes::new(key_pairs.iter().map(|key_pair| encrypt_commit_bytes(&prev_hash, key_pair.secret())).collect()));
+            ledger.add_block(&Block::new(header, vec![]));
+        }
+        assert_eq!(ledger.get_rewards_page(&proposer, 1, 2), vec![(2, 100), (3, 100)]);
+        assert_eq!(ledger.get_rewards_page(&proposer, 4, 10), vec![(5, 100)]);
+        assert!(ledger.get_rewards_page(&proposer, 10, 10).is_empty());
+        assert!(ledger.get_transactions_page(0, 10).is_empty());
+    }
+
+    // the address transaction index keeps a key per transaction, the pages only read their keys
+    #[test]
+    fn t_address_txs_page() {
+        use crate::core::test_util::memory_ledger_of;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let (miner, recipient) = (Address::from(1), Address::from(2));
+        let mut ledger = memory_ledger_of(vec![]);
+        let mut nonce = 0;
+        for height in 0..4 {
+            let mut transactions = vec![Transaction::new(height, miner, 10, 0, 1, vec![])];
+            // the block 2 carries no transfer, the block 3 carries two
+            let transfers = match height { 0 => 0, 2 => 0, 3 => 2, _ => 1 };
+            for _ in 0..transfers {
+                let mut transfer = Transaction::new(nonce, recipient, 1, 21_000, 1, vec![]);
+                transfer.sign(1, key_pair.secret());
+                transactions.push(transfer);
+                nonce += 1;
+            }
+            if height == 0 {
+                transactions[0] = Transaction::new(0, key_pair.address(), 100_000, 0, 1, vec![]);
+            }
+            let mut header = Header::zero_header();
+            header.height = height;
+            ledger.add_block(&Block::new(header, transactions));
+        }
+        let heights = |txs: Vec<(Height, Hash)>| txs.into_iter().map(|(height, _)| height).collect::<Vec<_>>();
+        assert_eq!(ledger.count_address_txs(&recipient), 3);
+        assert_eq!(ledger.count_address_txs(&key_pair.address()), 4);
+        assert_eq!(ledger.count_address_txs(&Address::from(3)), 0);
+        assert_eq!(heights(ledger.get_address_txs(&recipient)), vec![1, 3, 3]);
+        assert_eq!(heights(ledger.get_address_txs_page(&key_pair.address(), 1, 2)), vec![1, 3]);
+        assert_eq!(heights(ledger.get_address_txs_page(&recipient, 2, 10)), vec![3]);
+        assert!(ledger.get_address_txs_page(&recipient, 3, 10).is_empty());
+        // the transfers of a block are in their order in it
+        let block = ledger.get_block(&ledger.get_block_hash_by_height(3).unwrap()).unwrap();
+        let hashes: Vec<Hash> = ledger.get_address_txs_page(&recipient, 1, 2).into_iter().map(|(_, tx_hash)| tx_hash).collect();
+        assert_eq!(hashes, block.transactions()[1..].iter().map(|tx| tx.hash()).collect::<Vec<_>>());
+        assert_eq!(ledger.last_address_tx_height(&recipient, 2), Some(1));
+        assert_eq!(ledger.last_address_tx_height(&recipient, 0), None);
+        assert_eq!(ledger.last_address_tx_height(&miner, 10), Some(3));
+    }
+
+    // the commit certificate and the consensus messages are written with the block
+    #[test]
+    fn t_add_committed_block() {
+        use crate::consensus::types::View;
+        use crate::core::test_util::{memory_ledger, sealed_block};
+        use cryptocurrency_kit::crypto::EMPTY_HASH;
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let mut ledger = memory_ledger(&key_pair);
+        ledger.add_block(&sealed_block(EMPTY_HASH, 0, &key_pair));
+        let block = sealed_block(*ledger.get_last_block_hash(), 1, &key_pair);
+        let certificate = CommitCertificate::new(View::new(1, 0), block.hash(), vec![]);
+        ledger.add_committed_block(&block, Some((certificate, GossipMessages(vec![]))));
+        assert_eq!(*ledger.get_last_block_height(), 1);
+        assert_eq!(ledger.get_commit_certificate(1).unwrap().block_hash, block.hash());
+        assert!(ledger.get_consensus_messages(1).unwrap().0.is_empty());
+        // the block which isn't committed by the local consensus has no certificate
+        ledger.add_block(&sealed_block(block.hash(), 2, &key_pair));
+        assert!(ledger.get_commit_certificate(2).is_none() && ledger.get_consensus_messages(2).is_none());
+    }
 }
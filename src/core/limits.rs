@@ -0,0 +1,78 @@
+use crate::types::block::Block;
+use crate::types::transaction::Transaction;
+
+pub const DEFAULT_MAX_BLOCK_SIZE: u64 = 4 << 20;
+pub const DEFAULT_MAX_TX_SIZE: u64 = 128 << 10;
+// the block is nested in the json byte arrays of the consensus messages, every level
+// expands a byte into at most 4 bytes
+pub const FRAME_EXPANSION: u64 = 16;
+// the headers, the votes and the certificates around the block
+pub const FRAME_OVERHEAD: u64 = 64 << 10;
+
+/// SizeLimits are the rlp sizes which a block and its transactions can't exceed, they're
+/// consensus-critical and set by the genesis config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_block_size: u64,
+    pub max_tx_size: u64,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        SizeLimits::new(DEFAULT_MAX_BLOCK_SIZE, DEFAULT_MAX_TX_SIZE)
+    }
+}
+
+impl SizeLimits {
+    pub fn new(max_block_size: u64, max_tx_size: u64) -> Self {
+        SizeLimits { max_block_size, max_tx_size }
+    }
+
+    /// the size of the transaction, or the error if it's oversized
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<u64, String> {
+        let size = rlp::encode(transaction).len() as u64;
+        if size > self.max_tx_size {
+            return Err(format!("the transaction is {} bytes, more than the limit {}", size, self.max_tx_size));
+        }
+        Ok(size)
+    }
+
+    pub fn check_block(&self, block: &Block) -> Result<(), String> {
+        let size = rlp::encode(block).len() as u64;
+        if size > self.max_block_size {
+            return Err(format!("the block is {} bytes, more than the limit {}", size, self.max_block_size));
+        }
+        for transaction in block.transactions() {
+            self.check_transaction(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// the largest p2p frame which is decoded, the bigger frames drop the session before they're
+    /// buffered
+    pub fn max_frame_size(&self) -> usize {
+        self.max_block_size.saturating_mul(FRAME_EXPANSION).saturating_add(FRAME_OVERHEAD) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::Address;
+    use crate::types::block::Header;
+
+    #[test]
+    fn t_size_limits() {
+        let transaction = |payload: usize| Transaction::new(1, Address::from(1), 1, 0, 1, vec![7; payload]);
+        let block = |transactions: Vec<Transaction>| Block::new(Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, 1, 1, None), transactions);
+        let limits = SizeLimits::new(4096, 1024);
+
+        assert!(limits.check_transaction(&transaction(512)).is_ok());
+        assert!(limits.check_transaction(&transaction(2048)).unwrap_err().contains("transaction"));
+        assert!(limits.check_block(&block(vec![transaction(512)])).is_ok());
+        assert!(limits.check_block(&block(vec![transaction(2048)])).is_err());
+        assert!(limits.check_block(&block(vec![transaction(900); 8])).unwrap_err().contains("block"));
+        assert!(SizeLimits::default().max_frame_size() > DEFAULT_MAX_BLOCK_SIZE as usize);
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ::actix::prelude::*;
+use kvdb_rocksdb::Database;
+use parking_lot::RwLock;
+
+use crate::core::chain::Chain;
+use crate::core::ledger::Ledger;
+use crate::metrics;
+use crate::store::schema::INDEX_NAMES;
+use crate::store::types::Snapshot;
+
+// the maintenance wakes up every minute, the accounting and the compaction run on their own terms
+pub const MAINTENANCE_TICK: Duration = Duration::from_secs(60);
+// the keys out of the known indexes, eg: the keys of a newer version
+pub const OTHER_KEYS: &str = "other";
+// the keys copied per write batch by a compaction
+const COMPACT_BATCH: u64 = 10_000;
+
+lazy_static! {
+    // the chains of the process have their own stores, store directory => usage
+    static ref LAST_USAGE: RwLock<HashMap<String, StoreUsage>> = RwLock::new(HashMap::new());
+}
+
+/// the disk usage accounting and the compaction of the store, eg: `[maintenance]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    // the seconds between two accountings, every accounting scans the whole store, 0 disables it
+    #[serde(default = "default_usage_interval")]
+    pub usage_interval: u64,
+    // the keys scanned by the accounting per tick, a pass over a big store spans several ticks
+    #[serde(default = "default_usage_keys_per_tick")]
+    pub usage_keys_per_tick: u64,
+    // the store is compacted at the next start after the prunes and the truncates drop so many
+    // bytes, 0 never compacts it
+    #[serde(default = "default_compact_after_pruned")]
+    pub compact_after_pruned: u64,
+    // the `reindex` command compacts the store after it rebuilds the indexes
+    #[serde(default = "default_compact_after_reindex")]
+    pub compact_after_reindex: bool,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            usage_interval: default_usage_interval(),
+            usage_keys_per_tick: default_usage_keys_per_tick(),
+            compact_after_pruned: default_compact_after_pruned(),
+            compact_after_reindex: default_compact_after_reindex(),
+        }
+    }
+}
+
+fn default_usage_interval() -> u64 {
+    600
+}
+
+fn default_usage_keys_per_tick() -> u64 {
+    100_000
+}
+
+fn default_compact_after_pruned() -> u64 {
+    64 << 20
+}
+
+fn default_compact_after_reindex() -> bool {
+    true
+}
+
+/// the keys and the key plus value bytes under the prefix of an index
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IndexUsage {
+    pub name: String,
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StoreUsage {
+    // the bytes of the files in the store directory, the deleted keys take room until a compaction
+    pub disk_bytes: u64,
+    pub indexes: Vec<IndexUsage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompactionReport {
+    pub keys: u64,
+    pub disk_bytes_before: u64,
+    pub disk_bytes_after: u64,
+}
+
+/// the last accounting of the store by the maintenance task, it's absent before the first one
+pub fn last_usage(path: &str) -> Option<StoreUsage> {
+    LAST_USAGE.read().get(path).cloned()
+}
+
+/// UsageScan accounts the indexes in steps of a bounded number of keys, the store is written
+/// between the steps, so a pass is an estimate of the usage
+pub struct UsageScan {
+    usage: Vec<IndexUsage>,
+    // the first key of the next step, none once the pass is done
+    next: Option<Vec<u8>>,
+}
+
+impl Default for UsageScan {
+    fn default() -> Self {
+        let usage = INDEX_NAMES.iter().chain(Some(&OTHER_KEYS))
+            .map(|name| IndexUsage { name: name.to_string(), keys: 0, bytes: 0 })
+            .collect();
+        UsageScan { usage, next: Some(vec![]) }
+    }
+}
+
+impl UsageScan {
+    /// account at most `max_keys` keys, true if the pass is done
+    pub fn step<T: Snapshot + ?Sized>(&mut self, db: &T, max_keys: u64) -> bool {
+        let from = match self.next.take() {
+            Some(from) => from,
+            None => return true,
+        };
+        let mut scanned = 0;
+        for (key, value) in db.iter(&from) {
+            if scanned >= max_keys {
+                self.next = Some(key.to_vec());
+                return false;
+            }
+            // the longest prefix wins, eg: `core.consensus_message_cache` is no consensus message
+            let idx = INDEX_NAMES.iter().enumerate()
+                .filter(|(_, name)| key.starts_with(name.as_bytes()))
+                .max_by_key(|(_, name)| name.len())
+                .map_or(INDEX_NAMES.len(), |(idx, _)| idx);
+            self.usage[idx].keys += 1;
+            self.usage[idx].bytes += (key.len() + value.len()) as u64;
+            scanned += 1;
+        }
+        true
+    }
+
+    /// the usage of every non-empty index
+    pub fn finish(mut self) -> Vec<IndexUsage> {
+        self.usage.retain(|index| index.keys > 0);
+        self.usage
+    }
+}
+
+/// the usage of every non-empty index, it scans every key of the store
+pub fn index_usage<T: Snapshot + ?Sized>(db: &T) -> Vec<IndexUsage> {
+    let mut scan = UsageScan::default();
+    scan.step(db, u64::max_value());
+    scan.finish()
+}
+
+/// the bytes of the files under the directory
+pub fn disk_usage(path: &Path) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries.filter_map(Result::ok).fold(0, |acc, entry| {
+        match entry.metadata() {
+            Ok(ref metadata) if metadata.is_dir() => acc + disk_usage(&entry.path()),
+            Ok(metadata) => acc + metadata.len(),
+            Err(_) => acc,
+        }
+    })
+}
+
+/// account the store and publish the usage as the metrics, eg: `store_index_bytes{index="core.receipts"}`,
+/// the memory store takes no disk
+pub fn account_usage<T: Snapshot + ?Sized>(db: &T, path: &str) -> StoreUsage {
+    publish_usage(index_usage(db), path)
+}
+
+fn publish_usage(indexes: Vec<IndexUsage>, path: &str) -> StoreUsage {
+    let usage = StoreUsage { disk_bytes: disk_usage(Path::new(path)), indexes };
+    metrics::set_gauge("store_disk_bytes", usage.disk_bytes as i64);
+    for index in usage.indexes.iter() {
+        metrics::set_gauge(&format!("store_index_keys{{index=\"{}\"}}", index.name), index.keys as i64);
+        metrics::set_gauge(&format!("store_index_bytes{{index=\"{}\"}}", index.name), index.bytes as i64);
+    }
+    LAST_USAGE.write().insert(path.to_owned(), usage.clone());
+    usage
+}
+
+/// Rewrite the live keys into a fresh database and swap it with the store, the deleted keys and
+/// their tombstones are left behind. The store must not be in use, eg: the node compacts it at
+/// the start, before the chain writes, see `compact_if_due`.
+pub fn compact(db: &Database, path: &str) -> Result<CompactionReport, String> {
+    let start = Instant::now();
+    let disk_bytes_before = disk_usage(Path::new(path));
+    db.flush().map_err(|err| format!("failed to flush the store, {}", err))?;
+    let target = format!("{}.compact", path);
+    // the leftover of an interrupted compaction
+    let _ = fs::remove_dir_all(&target);
+    let mut keys = 0;
+    {
+        let fresh = Database::open_default(&target).map_err(|err| format!("failed to open {}, {}", target, err))?;
+        let mut tx = fresh.transaction();
+        if let Some(iter) = db.iter(None) {
+            for (key, value) in iter {
+                tx.put(None, &key, &value);
+                keys += 1;
+                if keys % COMPACT_BATCH == 0 {
+                    fresh.write(mem::replace(&mut tx, fresh.transaction())).map_err(|err| format!("failed to write {}, {}", target, err))?;
+                }
+            }
+        }
+        fresh.write(tx).map_err(|err| format!("failed to write {}, {}", target, err))?;
+        fresh.flush().map_err(|err| format!("failed to flush {}, {}", target, err))?;
+    }
+    db.restore(&target).map_err(|err| format!("failed to swap the compacted store, {}", err))?;
+    let report = CompactionReport { keys, disk_bytes_before, disk_bytes_after: disk_usage(Path::new(path)) };
+    let elapsed = start.elapsed();
+    metrics::inc_counter("store_compactions_total");
+    metrics::observe("store_compaction_duration_ms", elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_micros() as f64 / 1000.0);
+    metrics::set_gauge("store_disk_bytes", report.disk_bytes_after as i64);
+    Ok(report)
+}
+
+/// compact the store before the chain starts if the prunes and the truncates dropped enough
+/// bytes since the last compaction, nothing writes the store meanwhile
+pub fn compact_if_due(ledger: &mut Ledger, config: &MaintenanceConfig, path: &str) -> Result<Option<CompactionReport>, String> {
+    let pruned = ledger.pruned_bytes();
+    if config.compact_after_pruned == 0 || pruned < config.compact_after_pruned {
+        return Ok(None);
+    }
+    let report = match ledger.get_schema().database().rocksdb() {
+        Some(db) => compact(db, path)?,
+        // the memory store frees the pruned keys at once
+        None => CompactionReport::default(),
+    };
+    ledger.reset_pruned_bytes();
+    info!("Compact the store, pruned: {} bytes, {:?}", pruned, report);
+    Ok(Some(report))
+}
+
+/// Maintenance accounts the disk usage of the store periodically, a pass is spread over the ticks
+/// by `usage_keys_per_tick`. The compaction is left to the next start, the writes never wait for it.
+pub struct Maintenance {
+    chain: Arc<Chain>,
+    config: MaintenanceConfig,
+    path: String,
+    accounted: Option<Instant>,
+    scan: Option<UsageScan>,
+    // the due compaction is announced once
+    announced: bool,
+}
+
+impl Actor for Maintenance {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("Storage maintenance started, usage interval: {}s, compact after pruned: {} bytes", self.config.usage_interval, self.config.compact_after_pruned);
+        ctx.run_interval(MAINTENANCE_TICK, |act, _| act.maintain());
+    }
+}
+
+impl Maintenance {
+    pub fn new(chain: Arc<Chain>, config: MaintenanceConfig, path: String) -> Self {
+        Maintenance { chain, config, path, accounted: None, scan: None, announced: false }
+    }
+
+    fn maintain(&mut self) {
+        let pruned = self.chain.get_ledger().read().pruned_bytes();
+        metrics::set_gauge("store_pruned_bytes", pruned as i64);
+        if self.config.compact_after_pruned > 0 && pruned >= self.config.compact_after_pruned && !self.announced {
+            warn!("The prunes dropped {} bytes, the store is compacted at the next start", pruned);
+            self.announced = true;
+        }
+        if self.config.usage_interval == 0 {
+            return;
+        }
+        let due = self.accounted.map_or(true, |accounted| accounted.elapsed() >= Duration::from_secs(self.config.usage_interval));
+        if self.scan.is_none() && due {
+            self.scan = Some(UsageScan::default());
+        }
+        if let Some(mut scan) = self.scan.take() {
+            // the scan reads a consistent iterator, it holds no ledger lock
+            let db = self.chain.get_ledger().read().get_schema().database().clone();
+            if scan.step(db.as_ref(), self.config.usage_keys_per_tick) {
+                publish_usage(scan.finish(), &self.path);
+                self.accounted = Some(Instant::now());
+            } else {
+                self.scan = Some(scan);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::Hash;
+    use cryptocurrency_kit::ethkey::{Address, Generator, Random};
+    use crate::common::random_dir;
+    use crate::core::test_util::ledger_of;
+    use crate::store::backend::Backend;
+    use crate::store::memory::MemoryDB;
+    use crate::store::schema::Schema;
+    use crate::types::account::Account;
+    use crate::types::Validator;
+
+    #[test]
+    fn t_usage_and_compact() {
+        let path = random_dir();
+        let schema = Schema::new(Arc::new(Database::open_default(&path).unwrap()));
+        let mut accounts = schema.accounts();
+        for idx in 0..100_u64 {
+            accounts.put(&Address::from(idx), Account::default());
+        }
+        let usage = account_usage(schema.database().as_ref(), &path);
+        assert_eq!(usage.indexes.len(), 1);
+        assert_eq!(usage.indexes[0].name, "core.accounts");
+        assert_eq!(usage.indexes[0].keys, 100);
+        assert!(usage.disk_bytes > 0);
+        assert_eq!(last_usage(&path).map(|usage| usage.indexes), Some(usage.indexes.clone()));
+        // the usage of another store is kept apart
+        assert!(last_usage(&random_dir()).is_none());
+
+        for idx in 0..50_u64 {
+            accounts.remove(&Address::from(idx));
+        }
+        let report = compact(schema.database(), &path).unwrap();
+        assert_eq!(report.keys, 50);
+        // the store is still served after the swap
+        assert!(accounts.get(&Address::from(60)).is_some());
+        assert!(accounts.get(&Address::from(10)).is_none());
+        assert_eq!(index_usage(schema.database().as_ref())[0].keys, 50);
+    }
+
+    #[test]
+    fn t_usage_scan() {
+        let schema = Schema::new(Arc::new(MemoryDB::new()));
+        let mut accounts = schema.accounts();
+        for idx in 0..100_u64 {
+            accounts.put(&Address::from(idx), Account::default());
+        }
+        schema.state_roots().put(&1, Hash::zero());
+        // a pass in the steps of 30 keys
+        let mut scan = UsageScan::default();
+        let steps = (1..10).take_while(|_| !scan.step(schema.database().as_ref(), 30)).count() + 1;
+        assert_eq!(steps, 4);
+        assert_eq!(scan.finish(), index_usage(schema.database().as_ref()));
+    }
+
+    #[test]
+    fn t_compact_if_due() {
+        let path = random_dir();
+        let key_pair = Random.generate().unwrap();
+        let mut ledger = ledger_of(Backend::open(&path).unwrap(), vec![Validator::new(key_pair.address())]);
+        let config = MaintenanceConfig { compact_after_pruned: 100, ..MaintenanceConfig::default() };
+        ledger.add_pruned_bytes(60);
+        assert!(compact_if_due(&mut ledger, &config, &path).unwrap().is_none());
+        // the pruned bytes survive a restart
+        ledger.add_pruned_bytes(60);
+        assert_eq!(ledger.get_schema().pruned_bytes().get(), Some(120));
+        assert!(compact_if_due(&mut ledger, &config, &path).unwrap().is_some());
+        assert_eq!(ledger.pruned_bytes(), 0);
+        assert!(compact_if_due(&mut ledger, &MaintenanceConfig { compact_after_pruned: 0, ..config }, &path).unwrap().is_none());
+    }
+}
@@ -3,4 +3,27 @@ pub mod genesis;
 pub mod transaction_pool;
 pub mod tx_pool;
 pub mod chain;
+pub mod checkpoint;
+pub mod gas;
+pub mod executor;
+pub mod limits;
+pub mod state;
+pub mod staking;
+pub mod reward;
+pub mod fee;
+pub mod orphan;
+pub mod future_block;
+pub mod safety;
+pub mod read_ahead;
+pub mod warm_up;
+pub mod scrub;
+pub mod maintenance;
+pub mod report;
+pub mod consistency;
+pub mod reindex;
+pub mod verify;
+pub mod export;
+pub mod import;
 pub mod actor;
+#[cfg(test)]
+pub mod test_util;
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use cryptocurrency_kit::crypto::Hash;
+
+use crate::metrics;
+use crate::types::Height;
+use crate::types::block::Block;
+
+// the max blocks waiting for their parents
+pub const MAX_ORPHAN_BLOCKS: usize = 256;
+// the orphan is dropped if its parent doesn't arrive in time
+pub const ORPHAN_TTL: Duration = Duration::from_secs(60);
+// the same missing height is requested at most once in it
+const SYNC_REQUEST_INTERVAL: Duration = Duration::from_secs(3);
+
+/// OrphanPool keeps the blocks which arrived before their parents, keyed by the parent hash
+pub struct OrphanPool {
+    ttl: Duration,
+    capacity: usize,
+    orphans: BTreeMap<Hash, Vec<(Instant, Block)>>,
+    len: usize,
+    // the last missing height which was requested from the peers
+    requested: Option<(Height, Instant)>,
+}
+
+impl OrphanPool {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        OrphanPool { ttl, capacity, orphans: BTreeMap::new(), len: 0, requested: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// keep the block until its parent arrives, it returns false if the pool is full or the block is known
+    pub fn insert(&mut self, block: Block) -> bool {
+        self.expire();
+        if self.len >= self.capacity {
+            metrics::inc_counter("chain_orphan_dropped_total");
+            return false;
+        }
+        let hash = block.hash();
+        let siblings = self.orphans.entry(block.header().prev_hash).or_insert_with(Vec::new);
+        if siblings.iter().any(|(_, orphan)| orphan.hash() == hash) {
+            return false;
+        }
+        siblings.push((Instant::now(), block));
+        self.len += 1;
+        metrics::set_gauge("chain_orphan_blocks", self.len as i64);
+        true
+    }
+
+    /// whether the missing height should be requested, the bursts of orphans share one request
+    pub fn should_request(&mut self, height: Height) -> bool {
+        if let Some((requested, at)) = self.requested {
+            if requested == height && at.elapsed() < SYNC_REQUEST_INTERVAL {
+                metrics::inc_counter("chain_orphan_request_skipped_total");
+                return false;
+            }
+        }
+        self.requested = Some((height, Instant::now()));
+        true
+    }
+
+    /// take the orphans whose parent is the block
+    pub fn take_children(&mut self, parent: &Hash) -> Vec<Block> {
+        self.expire();
+        let mut children: Vec<Block> = self.orphans.remove(parent).unwrap_or_default()
+            .into_iter()
+            .map(|(_, block)| block)
+            .collect();
+        children.sort_by_key(|block| block.height());
+        self.len -= children.len();
+        metrics::set_gauge("chain_orphan_blocks", self.len as i64);
+        children
+    }
+
+    fn expire(&mut self) {
+        let ttl = self.ttl;
+        let mut expired = 0;
+        for siblings in self.orphans.values_mut() {
+            let before = siblings.len();
+            siblings.retain(|(arrived, _)| arrived.elapsed() < ttl);
+            expired += before - siblings.len();
+        }
+        self.orphans.retain(|_, siblings| !siblings.is_empty());
+        self.len -= expired;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::Address;
+    use crate::types::block::Header;
+
+    #[test]
+    fn t_orphan_pool() {
+        let parent = Block::new(Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, 1, 0, None), vec![]);
+        let child = Block::new(Header::new_mock(parent.hash(), Address::from(1), EMPTY_HASH, 2, 0, None), vec![]);
+        let other = Block::new(Header::new_mock(EMPTY_HASH, Address::from(2), EMPTY_HASH, 5, 0, None), vec![]);
+
+        let mut pool = OrphanPool::new(Duration::from_secs(10), 2);
+        assert!(pool.insert(child.clone()));
+        assert!(!pool.insert(child.clone()));
+        assert!(pool.insert(other.clone()));
+        assert!(!pool.insert(parent.clone()));
+        let children = pool.take_children(&parent.hash());
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].hash(), child.hash());
+        assert_eq!(pool.len(), 1);
+        assert!(pool.should_request(3));
+        assert!(!pool.should_request(3));
+        assert!(pool.should_request(4));
+
+        let mut pool = OrphanPool::new(Duration::from_millis(1), 2);
+        pool.insert(child);
+        ::std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(pool.len(), 1);
+        assert!(pool.take_children(&parent.hash()).is_empty());
+        assert_eq!(pool.len(), 0);
+    }
+}
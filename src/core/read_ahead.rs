@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::{
+    core::ledger::Ledger,
+    metrics,
+    types::block::Block,
+    types::Height,
+};
+
+pub const DEFAULT_READ_AHEAD: u64 = 64;
+
+/// BlockReadAhead iterates the blocks in [from, to] by height order. Instead of
+/// `hash-by-height` then `block-by-hash` for every block, it scans the ordered height
+/// index once for the next `window` hashes and prefetches their headers and bodies.
+/// The ledger lock is only held while a window is loading.
+pub struct BlockReadAhead {
+    ledger: Arc<RwLock<Ledger>>,
+    next: Height,
+    to: Height,
+    window: u64,
+    buffer: VecDeque<Block>,
+    exhausted: bool,
+}
+
+impl BlockReadAhead {
+    pub fn new(ledger: Arc<RwLock<Ledger>>, from: Height, to: Height, window: u64) -> Self {
+        BlockReadAhead {
+            ledger,
+            next: from,
+            to,
+            window: ::std::cmp::max(window, 1),
+            buffer: VecDeque::new(),
+            exhausted: from > to,
+        }
+    }
+
+    fn fill(&mut self) {
+        let count = ::std::cmp::min(self.window, self.to - self.next + 1);
+        let ledger = self.ledger.read();
+        let schema = ledger.get_schema();
+        let hashes: Vec<_> = schema.block_hashes_by_height().iter_from(self.next).take(count as usize).collect();
+        for block_hash in hashes {
+            let header = match schema.headers().get(&block_hash) {
+                Some(header) => header,
+                None => break,
+            };
+            let transactions = ledger.get_block_body(&block_hash).unwrap_or_default();
+            self.buffer.push_back(Block::new(header, transactions));
+        }
+        metrics::REGISTRY.inc_counter("store_read_ahead_blocks_total", self.buffer.len() as u64);
+        if (self.buffer.len() as u64) < count {
+            // reach the end of chain
+            self.exhausted = true;
+        }
+        self.next += self.buffer.len() as u64;
+        if self.next > self.to {
+            self.exhausted = true;
+        }
+    }
+}
+
+impl Iterator for BlockReadAhead {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Block> {
+        if self.buffer.is_empty() && !self.exhausted {
+            self.fill();
+        }
+        self.buffer.pop_front()
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use cryptocurrency_kit::crypto::Hash;
+
+use crate::core::ledger::{drop_states_above, write_block_indexes};
+use crate::store::schema::Schema;
+use crate::types::Height;
+
+// the progress is reported every N blocks
+pub const REINDEX_PROGRESS_INTERVAL: u64 = 1000;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReindexReport {
+    pub blocks: u64,
+    pub transactions: u64,
+    pub last_height: Option<Height>,
+    // the stored blocks which are not indexed, they are above a missing or broken block
+    pub skipped: Vec<String>,
+}
+
+/// Rebuild the secondary indexes from the raw blocks: the headers, the bodies and the transactions.
+/// The height index, the account states, the address transaction index, the transaction
+/// locations, the dpos votes, the epochs, the receipts, the stakes, the rewards and the fee income
+/// are cleared and rebuilt from the genesis.
+/// The state snapshots at or below the last indexed block are kept, the ones above it are dropped,
+/// see `drop_states_above`. The blocks are chained by the parent hash from the genesis, the indexing stops
+/// at the first missing or broken block.
+pub fn reindex<F>(schema: &Schema, mut progress: F) -> Result<ReindexReport, String>
+    where F: FnMut(Height, Height) {
+    // height => (hash, parent hash)
+    let mut heights: BTreeMap<Height, (Hash, Hash)> = BTreeMap::new();
+    for (hash, header) in schema.headers().iter() {
+        if let Some((other, _)) = heights.insert(header.height, (hash, header.prev_hash)) {
+            return Err(format!("conflicting headers at height {}, {:?} and {:?}", header.height, other, hash));
+        }
+    }
+    let last_height = match heights.keys().next_back() {
+        Some(height) => *height,
+        None => return Err("there is no stored block".to_string()),
+    };
+    if !heights.contains_key(&0) {
+        return Err("the genesis block is missing".to_string());
+    }
+
+    schema.block_hashes_by_height().clear();
+    schema.address_txs().clear();
+    schema.address_tx_counts().clear();
+    schema.tx_locations().clear();
+    schema.dpos_votes().clear();
+    schema.epochs().clear();
+    schema.receipts().clear();
+    schema.stakes().clear();
+    schema.stake_releases().clear();
+    schema.rewards().clear();
+    schema.fee_income().clear();
+    let mut accounts_db = schema.accounts();
+    accounts_db.clear();
+    for (address, account) in schema.genesis_alloc().iter() {
+        accounts_db.put(&address, account);
+    }
+
+    let mut report = ReindexReport::default();
+    let mut height_db = schema.block_hashes_by_height();
+    let mut parent: Option<Hash> = None;
+    for (height, (hash, prev_hash)) in heights.iter() {
+        if let Err(reason) = check_link(*height, prev_hash, parent, report.last_height) {
+            report.skipped.push(reason);
+            break;
+        }
+        let body = match schema.block_bodies().get(hash) {
+            Some(body) => body,
+            None => {
+                report.skipped.push(format!("the body of the height {} is missing", height));
+                break;
+            }
+        };
+        let transactions: Option<Vec<_>> = body.0.iter().map(|tx_hash| schema.transaction().get(tx_hash)).collect();
+        let transactions = match transactions {
+            Some(transactions) => transactions,
+            None => {
+                report.skipped.push(format!("a transaction of the height {} is missing", height));
+                break;
+            }
+        };
+        height_db.push(*hash);
+        write_block_indexes(schema, *height, hash, &transactions);
+        report.blocks += 1;
+        report.transactions += transactions.len() as u64;
+        report.last_height = Some(*height);
+        parent = Some(*hash);
+        if *height % REINDEX_PROGRESS_INTERVAL == 0 || *height == last_height {
+            progress(*height, last_height);
+        }
+    }
+    // the snapshots above the indexed blocks were taken on the blocks which aren't indexed
+    drop_states_above(schema, report.last_height.unwrap_or(0));
+    if let Some(indexed) = report.last_height {
+        let rest = heights.range(indexed + 1..).count();
+        if rest > 0 {
+            report.skipped.push(format!("{} blocks above the height {} are not indexed", rest, indexed));
+        }
+    }
+    Ok(report)
+}
+
+// the block must follow the last indexed block
+fn check_link(height: Height, prev_hash: &Hash, parent: Option<Hash>, last: Option<Height>) -> Result<(), String> {
+    let expect = last.map_or(0, |last| last + 1);
+    if height != expect {
+        return Err(format!("the height {} is missing", expect));
+    }
+    match parent {
+        Some(parent) if *prev_hash != parent => Err(format!("the block at the height {} doesn't link to its parent", height)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::core::test_util::ledger_of;
+    use crate::common::random_dir;
+    use crate::store::backend::Backend;
+    use crate::types::EMPTY_ADDRESS;
+    use crate::types::block::{Block, Header};
+
+    // the reindex scans the store, it runs on rocksdb whose iterator differs from the memory one
+    #[test]
+    fn t_reindex() {
+        let dir = *random_dir();
+        let backend = Backend::open(&dir).unwrap();
+        assert!(!backend.is_memory());
+        let mut ledger = ledger_of(backend, vec![]);
+        let genesis = Block::new(Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, 0, 0, None), vec![]);
+        ledger.add_genesis_block(&genesis);
+        ledger.reload_meta();
+        let mut parent = genesis.hash();
+        for height in 1..10 {
+            let block = Block::new(Header::new_mock(parent, *EMPTY_ADDRESS, EMPTY_HASH, height, height, None), vec![]);
+            parent = block.hash();
+            ledger.add_block(&block);
+        }
+        let hashes: Vec<Hash> = ledger.get_schema().block_hashes_by_height().iter().collect();
+
+        // the lost height index is rebuilt
+        ledger.get_schema().block_hashes_by_height().clear();
+        let mut reported = vec![];
+        let report = reindex(ledger.get_schema(), |height, last| reported.push((height, last))).unwrap();
+        assert_eq!(report.blocks, 10);
+        assert_eq!(report.last_height, Some(9));
+        assert!(report.skipped.is_empty());
+        assert_eq!(reported, vec![(0, 9), (9, 9)]);
+        assert_eq!(ledger.get_schema().block_hashes_by_height().iter().collect::<Vec<Hash>>(), hashes);
+
+        // the blocks above a missing body are skipped
+        ledger.get_schema().block_bodies().remove(&hashes[5]);
+        let report = reindex(ledger.get_schema(), |_, _| {}).unwrap();
+        assert_eq!(report.last_height, Some(4));
+        assert_eq!(report.skipped.len(), 2);
+        assert_eq!(ledger.get_schema().block_hashes_by_height().len(), 5);
+        drop(ledger);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::ethkey::{public_to_address, recover_bytes, Address, Signature};
+
+use crate::common::hash_algo::digest;
+use crate::consensus::types::{Round, Subject};
+use crate::consensus::validator::fn_selector;
+use crate::protocol::GossipMessages;
+use crate::signer::Signer;
+use crate::types::votes::recover_commit_seal;
+use crate::types::{Height, Validator, Validators};
+use crate::util::canonical::to_canonical_json;
+use super::ledger::Ledger;
+
+/// the performance of a validator over a range of blocks, for the SLA verification between the
+/// member organizations of a consortium
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidatorReport {
+    pub validator: Address,
+    pub from: Height,
+    pub to: Height,
+    pub blocks: u64,
+    pub proposed: u64,
+    // the blocks which carry the commit seal of the validator
+    pub commits: u64,
+    // the rounds which timed out while the validator was the proposer
+    pub rounds_caused: u64,
+    // the percentage of the blocks which the validator committed
+    pub uptime: f64,
+}
+
+/// the report signed by the node key, the other members recover the signer from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedReport {
+    pub report: ValidatorReport,
+    pub signer: Address,
+    pub signature: Signature,
+}
+
+impl SignedReport {
+    pub fn sign(report: ValidatorReport, signer: &Signer) -> Result<Self, String> {
+        let signature = signer.sign(&report_digest(&report))?;
+        Ok(SignedReport { report, signer: signer.address(), signature })
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        let signer = recover_bytes(&self.signature, report_digest(&self.report).as_ref())
+            .map(|public| public_to_address(&public))
+            .map_err(|_| "recover report signature failed".to_string())?;
+        if signer != self.signer {
+            return Err(format!("invalid report signature, expect: {:?}, got: {:?}", self.signer, signer));
+        }
+        Ok(())
+    }
+}
+
+// the canonical json is signed, so the report can be checked without this crate
+fn report_digest(report: &ValidatorReport) -> Hash {
+    digest(to_canonical_json(report).into_bytes())
+}
+
+/// the round which the block is committed at, the persisted prepares and commits are all of it
+pub fn commit_round(messages: &GossipMessages) -> Option<Round> {
+    messages.0.first().map(|message| Subject::from(message.msg()).view.round)
+}
+
+/// build the report from the stored headers and the persisted consensus messages
+pub fn validator_report(ledger: &Ledger, validator: Address, from: Height, to: Height) -> Result<ValidatorReport, String> {
+    if from == 0 || from > to {
+        return Err(format!("invalid range, from: {}, to: {}", from, to));
+    }
+    let mut report = ValidatorReport { validator, from, to, blocks: 0, proposed: 0, commits: 0, rounds_caused: 0, uptime: 0.0 };
+    for height in from..=to {
+        let header = ledger.get_header_by_height(height).ok_or(format!("block is not found, height: {}", height))?;
+        report.blocks += 1;
+        if header.proposer == validator {
+            report.proposed += 1;
+        }
+        let hash = header.block_hash();
+        let committed = header.votes.as_ref().map_or(false, |votes| {
+            votes.votes().iter().any(|seal| recover_commit_seal(&hash, seal) == Ok(validator))
+        });
+        if committed {
+            report.commits += 1;
+        }
+        // the proposers of the rounds before the commit round failed to get their proposals committed
+        let round = ledger.get_consensus_messages(height).as_ref().and_then(commit_round).unwrap_or(0);
+        let validators: Validators = ledger.get_validator_addresses(height).into_iter().map(Validator::new).collect();
+        report.rounds_caused += (0..round)
+            .filter(|round| *fn_selector(&header.prev_hash, height - 1, *round, &validators).address() == validator)
+            .count() as u64;
+    }
+    report.uptime = report.commits as f64 * 100.0 / report.blocks as f64;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::signer::LocalSigner;
+
+    #[test]
+    fn t_signed_report() {
+        let key_pair = Random.generate().unwrap();
+        let report = ValidatorReport {
+            validator: key_pair.address(), from: 1, to: 10, blocks: 10, proposed: 3, commits: 9, rounds_caused: 1, uptime: 90.0,
+        };
+        let signed = SignedReport::sign(report, &LocalSigner::new(key_pair)).unwrap();
+        assert!(signed.verify().is_ok());
+
+        let mut forged = signed.clone();
+        forged.report.uptime = 100.0;
+        assert!(forged.verify().is_err());
+        assert!(commit_round(&GossipMessages::default()).is_none());
+    }
+}
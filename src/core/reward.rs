@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics;
+use crate::store::schema::Schema;
+use crate::store::types::Fork;
+use crate::store::keys::AddressHeightKey;
+use crate::types::block::Header;
+use crate::types::Height;
+
+/// the reward schedule of the chain, eg: `[genesis.reward]`, the whole reward goes to the
+/// proposer: the commit seals are left out of the block hash and every node keeps the seals it
+/// collected, so the signers of a block differ between the nodes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardConfig {
+    // the reward of every block, 0 disables the rewards
+    #[serde(default)]
+    pub block_reward: u64,
+    // the reward is halved every N blocks, 0 never halves it
+    #[serde(default)]
+    pub halving_interval: Height,
+}
+
+implement_hash_algo_traits! {RewardConfig}
+implement_storagevalue_traits! {RewardConfig}
+
+impl RewardConfig {
+    /// the reward of the block at the height, the genesis block has none
+    pub fn reward_at(&self, height: Height) -> u64 {
+        if height == 0 {
+            return 0;
+        }
+        let halvings = if self.halving_interval == 0 { 0 } else { (height - 1) / self.halving_interval };
+        if halvings >= 64 {
+            return 0;
+        }
+        self.block_reward >> halvings
+    }
+}
+
+/// the rewards paid to an account in height order, (height, amount)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RewardHistory(pub Vec<(Height, u64)>);
+
+impl RewardHistory {
+    /// the rewards paid at or below the height
+    pub fn total_at(&self, height: Height) -> u64 {
+        self.0.iter().take_while(|(paid, _)| *paid <= height).fold(0_u64, |acc, (_, amount)| acc.saturating_add(*amount))
+    }
+}
+
+/// the rewards of the committed block, they only depend on the hashed fields of the header
+pub fn block_rewards(config: &RewardConfig, header: &Header) -> Vec<(Address, u64)> {
+    match config.reward_at(header.height) {
+        0 => vec![],
+        reward => vec![(header.proposer, reward)],
+    }
+}
+
+/// credit the reward of the block at the height to the proposer
+pub fn write_rewards<T: Fork + ?Sized>(schema: &Schema<T>, height: Height, hash: &Hash) {
+    let config = schema.reward_config().get().unwrap_or_default();
+    let header = match schema.headers().get(hash) {
+        Some(header) => header,
+        None => return,
+    };
+    let (mut accounts_db, mut rewards_db) = (schema.accounts(), schema.rewards());
+    for (address, amount) in block_rewards(&config, &header) {
+        let mut account = accounts_db.get(&address).unwrap_or_default();
+        account.credit(amount);
+        accounts_db.put(&address, account);
+        rewards_db.put(&AddressHeightKey::new(address, height), amount);
+        metrics::inc_counter_by("ledger_rewards_paid_total", amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_block_rewards() {
+        let config = RewardConfig { block_reward: 100, halving_interval: 10 };
+        assert_eq!(config.reward_at(0), 0);
+        assert_eq!(config.reward_at(10), 100);
+        assert_eq!(config.reward_at(11), 50);
+        assert_eq!(RewardConfig { block_reward: 100, halving_interval: 1 }.reward_at(100), 0);
+
+        let proposer = Address::from(1);
+        let header = Header::new_mock(Hash::zero(), proposer, Hash::zero(), 1, 1, None);
+        assert_eq!(block_rewards(&config, &header), vec![(proposer, 100)]);
+        assert!(block_rewards(&RewardConfig::default(), &header).is_empty());
+
+        let history = RewardHistory(vec![(1, 10), (3, 20)]);
+        assert_eq!(history.total_at(2), 10);
+        assert_eq!(history.total_at(3), 30);
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use crate::types::block::Header;
+use crate::types::votes::{recover_commit_seal, verify_block_seals};
+use crate::types::Height;
+
+// the exit code of the process which has halted on a safety violation
+pub const SAFETY_EXIT_CODE: i32 = 3;
+// the alarms (webhook, metrics push) have the time to go out before the process exits
+pub const SAFETY_EXIT_DELAY: Duration = Duration::from_secs(5);
+
+/// the evidence of two finalized blocks at the same height, both carry more than 2/3 commit seals,
+/// it means the fault assumption of the bft is broken, the node must not continue on either fork
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SafetyEvidence {
+    pub height: Height,
+    pub first: Header,
+    pub second: Header,
+    // millis
+    pub detected_at: u64,
+}
+
+implement_storagevalue_traits! {SafetyEvidence}
+implement_cryptohash_traits! {SafetyEvidence}
+
+impl SafetyEvidence {
+    /// the signers which sealed both blocks, they are the faulty validators
+    pub fn equivocators(&self) -> Vec<Address> {
+        let signers = |header: &Header| -> Vec<Address> {
+            header.votes.as_ref()
+                .map(|votes| votes.votes().iter().filter_map(|seal| recover_commit_seal(&header.block_hash(), seal).ok()).collect())
+                .unwrap_or_default()
+        };
+        let first = signers(&self.first);
+        signers(&self.second).into_iter().filter(|address| first.contains(address)).collect()
+    }
+}
+
+/// detect the conflict between the stored header and the incoming one at the same height, it's a
+/// violation only if both carry valid commit certificates
+pub fn detect_conflict(stored: &Header, incoming: &Header, validators: &[Address]) -> Option<SafetyEvidence> {
+    if stored.height != incoming.height || stored.block_hash() == incoming.block_hash() {
+        return None;
+    }
+    verify_block_seals(stored, validators).ok()?;
+    verify_block_seals(incoming, validators).ok()?;
+    Some(SafetyEvidence {
+        height: stored.height,
+        first: stored.clone(),
+        second: incoming.clone(),
+        detected_at: chrono::Utc::now().timestamp_millis() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::types::votes::{encrypt_commit_bytes, Votes};
+
+    #[test]
+    fn t_detect_conflict() {
+        let key_pairs: Vec<_> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let validators: Vec<Address> = key_pairs.iter().map(|key_pair| key_pair.address()).collect();
+        let seal = |mut header: Header, signers: usize| {
+            let seals = key_pairs[..signers].iter().map(|key_pair| encrypt_commit_bytes(&header.block_hash(), key_pair.secret())).collect();
+            header.votes = Some(Votes::new(seals));
+            header
+        };
+        let first = seal(Header::new_mock(EMPTY_HASH, validators[0], EMPTY_HASH, 1, 1, None), 3);
+        let second = seal(Header::new_mock(EMPTY_HASH, validators[1], EMPTY_HASH, 1, 2, None), 3);
+        let weak = seal(Header::new_mock(EMPTY_HASH, validators[2], EMPTY_HASH, 1, 3, None), 2);
+
+        assert!(detect_conflict(&first, &first, &validators).is_none());
+        assert!(detect_conflict(&first, &weak, &validators).is_none());
+        let evidence = detect_conflict(&first, &second, &validators).unwrap();
+        assert_eq!(evidence.height, 1);
+        assert_eq!(evidence.equivocators().len(), 3);
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+
+use crate::common::hash_algo::digest;
+use crate::core::chain::Chain;
+use crate::core::ledger::Ledger;
+use crate::metrics;
+use crate::store::schema::Schema;
+use crate::types::Height;
+
+// the scrubber wakes up every minute, so the hourly budget is spread into small batches
+pub const SCRUB_TICK: Duration = Duration::from_secs(60);
+const TICKS_PER_HOUR: f64 = 60.0;
+
+/// the background re-hashing of the stored blocks, eg: `[scrub]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrubConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // the fraction of the stored blocks scrubbed per hour, eg: 0.01 scrubs the whole store in 100 hours
+    #[serde(default = "default_scrub_fraction")]
+    pub fraction: f64,
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        ScrubConfig { enabled: false, fraction: default_scrub_fraction() }
+    }
+}
+
+fn default_scrub_fraction() -> f64 {
+    0.01
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScrubMismatch {
+    pub height: Height,
+    pub hash: Option<Hash>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScrubReport {
+    pub checked: u64,
+    pub mismatches: Vec<ScrubMismatch>,
+}
+
+/// re-read the blocks of `[from, from + count)` from the store, bypass the caches, and re-hash the
+/// headers and the transactions against the keys of the hash indexes
+pub fn scrub_blocks(ledger: &Ledger, from: Height, count: u64) -> ScrubReport {
+    let schema = ledger.get_schema();
+    let last_height = *ledger.get_last_block_height();
+    let mut report = ScrubReport::default();
+    for height in from..from.saturating_add(count) {
+        if height > last_height {
+            break;
+        }
+        report.checked += 1;
+        report.mismatches.extend(scrub_block(schema, height));
+    }
+    report
+}
+
+/// the mismatches of the stored block at the height, it's empty for a sound block
+pub fn scrub_block(schema: &Schema, height: Height) -> Vec<ScrubMismatch> {
+    let mut mismatches = vec![];
+    let mut mismatch = |hash: Option<Hash>, reason: String| {
+        mismatches.push(ScrubMismatch { height, hash, reason });
+    };
+    let hash = match schema.block_hash_by_height(height) {
+        Some(hash) => hash,
+        None => {
+            mismatch(None, "the height index is missing".to_string());
+            return mismatches;
+        }
+    };
+    let header = match schema.headers().get(&hash) {
+        Some(header) => header,
+        None => {
+            mismatch(Some(hash), "the header is missing".to_string());
+            return mismatches;
+        }
+    };
+    let rehash = digest(header.rlp_bytes_without_votes());
+    if rehash != hash {
+        mismatch(Some(hash), format!("the header hashes to {:?}", rehash));
+    }
+    if header.height != height {
+        mismatch(Some(hash), format!("the header is at height {}", header.height));
+    }
+    let body = match schema.block_bodies().get(&hash) {
+        Some(body) => body,
+        None => {
+            mismatch(Some(hash), "the body is missing".to_string());
+            return mismatches;
+        }
+    };
+    for tx_hash in body.0.iter() {
+        match schema.transaction().get(tx_hash) {
+            Some(transaction) => {
+                if transaction.hash() != *tx_hash {
+                    mismatch(Some(hash), format!("the transaction {:?} hashes to {:?}", tx_hash, transaction.hash()));
+                }
+            }
+            None => mismatch(Some(hash), format!("the transaction {:?} is missing", tx_hash)),
+        }
+    }
+    mismatches
+}
+
+/// Scrubber walks the stored blocks round and round at the configured rate, so the silent disk
+/// corruption is alerted before a peer or the consensus trips over it
+pub struct Scrubber {
+    chain: Arc<Chain>,
+    config: ScrubConfig,
+    // the next height to scrub, it wraps to the genesis after the tip
+    cursor: Height,
+}
+
+impl Actor for Scrubber {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("Storage scrubber started, fraction per hour: {}", self.config.fraction);
+        ctx.run_interval(SCRUB_TICK, |act, _| act.scrub());
+    }
+}
+
+impl Scrubber {
+    pub fn new(chain: Arc<Chain>, config: ScrubConfig) -> Self {
+        Scrubber { chain, config, cursor: 0 }
+    }
+
+    // the batch is small, the ledger read lock is held shortly and the writers barely notice it
+    fn scrub(&mut self) {
+        let last_height = self.chain.get_last_height();
+        let count = batch_size(last_height + 1, self.config.fraction);
+        if self.cursor > last_height {
+            self.cursor = 0;
+        }
+        let report = scrub_blocks(&self.chain.get_ledger().read(), self.cursor, count);
+        self.cursor += report.checked;
+        metrics::inc_counter_by("store_scrub_blocks_total", report.checked);
+        metrics::set_gauge("store_scrub_cursor", self.cursor as i64);
+        for mismatch in report.mismatches.iter() {
+            metrics::inc_counter("store_scrub_mismatch_total");
+            error!("Storage corruption is detected, height: {}, hash: {:?}, {}", mismatch.height, mismatch.hash, mismatch.reason);
+        }
+    }
+}
+
+// the blocks of one tick, at least one block so a tiny fraction still makes progress
+fn batch_size(blocks: u64, fraction: f64) -> u64 {
+    let per_tick = blocks as f64 * fraction.max(0.0) / TICKS_PER_HOUR;
+    ::std::cmp::max(1, per_tick.ceil() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::core::test_util::memory_ledger_of;
+    use crate::types::EMPTY_ADDRESS;
+    use crate::types::block::{Block, Header};
+
+    #[test]
+    fn t_scrub_blocks() {
+        let mut ledger = memory_ledger_of(vec![]);
+        ledger.add_genesis_block(&Block::new(Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, 0, 0, None), vec![]));
+        ledger.reload_meta();
+        for height in 1..10 {
+            ledger.add_block(&Block::new(Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, height, height, None), vec![]));
+        }
+        let report = scrub_blocks(&ledger, 0, 20);
+        assert_eq!(report.checked, 10);
+        assert!(report.mismatches.is_empty());
+
+        // a flipped header under the hash of the height 5
+        let hash = ledger.get_schema().block_hash_by_height(5).unwrap();
+        let corrupted = Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, 5, 500, None);
+        ledger.get_schema().headers().put(&hash, corrupted);
+        let report = scrub_blocks(&ledger, 4, 3);
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].height, 5);
+
+        assert_eq!(batch_size(100, 0.01), 1);
+        assert_eq!(batch_size(600_000, 0.01), 100);
+    }
+}
@@ -0,0 +1,400 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use rlp::{RlpStream, UntrustedRlp};
+use serde::{Deserialize, Serialize};
+
+use crate::consensus::epoch::{EpochHook, SafeEpochHook};
+use crate::core::executor::TxOutcome;
+use crate::core::ledger::Ledger;
+use crate::metrics;
+use crate::store::schema::Schema;
+use crate::store::types::Fork;
+use crate::types::transaction::Transaction;
+use crate::types::Height;
+
+pub const DEFAULT_UNBONDING_PERIOD: Height = 1000;
+
+lazy_static! {
+    /// the staking transactions are sent to it, the bonded stake is locked in its account until
+    /// it's released to the delegator
+    pub static ref STAKING_ADDRESS: Address = Address::from(0x7374616b_u64);
+}
+
+const BOND_OP: u8 = 0;
+const UNBOND_OP: u8 = 1;
+
+/// the staking rules of the chain, eg: `[genesis.staking]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StakingConfig {
+    // the stake a validator needs before an epoch to be in its validator set, 0 disables it
+    #[serde(default)]
+    pub min_stake: u64,
+    // the blocks an unbonded stake stays locked before it's released to the delegator
+    #[serde(default = "default_unbonding_period")]
+    pub unbonding_period: Height,
+}
+
+impl Default for StakingConfig {
+    fn default() -> Self {
+        StakingConfig { min_stake: 0, unbonding_period: default_unbonding_period() }
+    }
+}
+
+fn default_unbonding_period() -> Height {
+    DEFAULT_UNBONDING_PERIOD
+}
+
+implement_hash_algo_traits! {StakingConfig}
+implement_storagevalue_traits! {StakingConfig}
+
+/// the operation of a staking transaction, the bonded amount is the amount of the transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum StakingOp {
+    Bond(Address),
+    // validator, amount
+    Unbond(Address, u64),
+}
+
+pub fn encode_bond(validator: &Address) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&BOND_OP).append(&validator.to_vec());
+    stream.out()
+}
+
+pub fn encode_unbond(validator: &Address, amount: u64) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(3);
+    stream.append(&UNBOND_OP).append(&validator.to_vec()).append(&amount);
+    stream.out()
+}
+
+pub fn decode_staking(payload: &[u8]) -> Result<StakingOp, String> {
+    let rlp = UntrustedRlp::new(payload);
+    let op: u8 = rlp.val_at(0).map_err(|err| format!("{:?}", err))?;
+    let bytes: Vec<u8> = rlp.val_at(1).map_err(|err| format!("{:?}", err))?;
+    if bytes.len() != 20 {
+        return Err(format!("invalid validator address length: {}", bytes.len()));
+    }
+    let validator = Address::from_slice(&bytes);
+    match op {
+        BOND_OP => Ok(StakingOp::Bond(validator)),
+        UNBOND_OP => Ok(StakingOp::Unbond(validator, rlp.val_at(2).map_err(|err| format!("{:?}", err))?)),
+        op => Err(format!("unknown staking operation: {}", op)),
+    }
+}
+
+/// the staking operation of the transaction, the invalid payloads are plain transfers
+pub fn staking_op(transaction: &Transaction) -> Option<StakingOp> {
+    if transaction.to() != Some(&*STAKING_ADDRESS) {
+        return None;
+    }
+    decode_staking(transaction.payload()).ok()
+}
+
+/// a stake which is unbonded but still locked
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Unbonding {
+    pub delegator: Address,
+    pub amount: u64,
+    // the height which releases it
+    pub release: Height,
+}
+
+/// the stake bonded to a validator, `history` is the total bonded stake after the blocks which
+/// changed it, the epoch hook reads the stake before the boundary from it
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ValidatorStake {
+    pub bonds: Vec<(Address, u64)>,
+    pub unbonding: Vec<Unbonding>,
+    pub history: Vec<(Height, u64)>,
+}
+
+implement_storagevalue_traits! {ValidatorStake}
+implement_cryptohash_traits! {ValidatorStake}
+
+impl ValidatorStake {
+    pub fn total(&self) -> u64 {
+        self.bonds.iter().fold(0_u64, |acc, (_, amount)| acc.saturating_add(*amount))
+    }
+
+    pub fn bonded(&self, delegator: &Address) -> u64 {
+        self.bonds.iter().find(|(address, _)| address == delegator).map_or(0, |(_, amount)| *amount)
+    }
+
+    pub fn stake_before(&self, height: Height) -> u64 {
+        self.history.iter().rev().find(|(changed, _)| *changed < height).map_or(0, |(_, total)| *total)
+    }
+
+    pub fn bond(&mut self, delegator: Address, amount: u64, height: Height) {
+        match self.bonds.iter_mut().find(|(address, _)| *address == delegator) {
+            Some((_, bonded)) => *bonded = bonded.saturating_add(amount),
+            None => self.bonds.push((delegator, amount)),
+        }
+        self.record(height);
+    }
+
+    /// unbond at most the bonded amount of the delegator, returns the unbonded amount
+    pub fn unbond(&mut self, delegator: Address, amount: u64, height: Height, release: Height) -> u64 {
+        let amount = ::std::cmp::min(amount, self.bonded(&delegator));
+        if amount == 0 {
+            return 0;
+        }
+        self.bonds.iter_mut().filter(|(address, _)| *address == delegator).for_each(|(_, bonded)| *bonded -= amount);
+        self.bonds.retain(|(_, bonded)| *bonded > 0);
+        self.unbonding.push(Unbonding { delegator, amount, release });
+        self.record(height);
+        amount
+    }
+
+    // forget the released stakes
+    fn prune(&mut self, height: Height) {
+        self.unbonding.retain(|unbonding| unbonding.release > height);
+    }
+
+    fn record(&mut self, height: Height) {
+        let total = self.total();
+        match self.history.last_mut() {
+            Some((changed, last)) if *changed == height => *last = total,
+            _ => self.history.push((height, total)),
+        }
+    }
+}
+
+/// the stakes released to the delegators at a height, (delegator, amount)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Releases(pub Vec<(Address, u64)>);
+
+implement_storagevalue_traits! {Releases}
+implement_cryptohash_traits! {Releases}
+
+/// release the matured unbonded stakes and apply the staking transactions of the block, the
+/// coinbase transaction never stakes. A bond whose amount isn't moved by `apply_transactions`,
+/// it's above the balance of its delegator, stakes nothing.
+pub fn write_stakes<T: Fork + ?Sized>(schema: &Schema<T>, height: Height, transactions: &[Transaction], outcomes: &[TxOutcome]) {
+    let config = schema.staking_config().get().unwrap_or_default();
+    let (mut stakes_db, mut releases_db, mut accounts_db) = (schema.stakes(), schema.stake_releases(), schema.accounts());
+
+    if let Some(releases) = releases_db.get(&height) {
+        let mut locked = accounts_db.get(&STAKING_ADDRESS).unwrap_or_default();
+        for (delegator, amount) in releases.0 {
+            let mut account = accounts_db.get(&delegator).unwrap_or_default();
+            account.credit(amount);
+            accounts_db.put(&delegator, account);
+            locked.withdraw(amount);
+            metrics::inc_counter("staking_released_total");
+        }
+        accounts_db.put(&STAKING_ADDRESS, locked);
+    }
+
+    for (transaction, outcome) in transactions.iter().zip(outcomes).skip(1) {
+        let (delegator, op) = match (transaction.sender(), staking_op(transaction)) {
+            (Some(delegator), Some(op)) => (delegator, op),
+            _ => continue,
+        };
+        match op {
+            StakingOp::Bond(_) if !outcome.moved => {
+                metrics::inc_counter("staking_rejected_bond_total");
+                warn!("Skip the bond above the balance, delegator: {:?}, amount: {}, height: {}", delegator, transaction.amount(), height);
+            }
+            StakingOp::Bond(validator) => {
+                let mut stake = stakes_db.get(&validator).unwrap_or_default();
+                stake.prune(height);
+                stake.bond(delegator, transaction.amount(), height);
+                stakes_db.put(&validator, stake);
+            }
+            StakingOp::Unbond(validator, amount) => {
+                let mut stake = stakes_db.get(&validator).unwrap_or_default();
+                stake.prune(height);
+                let release = height + ::std::cmp::max(config.unbonding_period, 1);
+                let unbonded = stake.unbond(delegator, amount, height, release);
+                stakes_db.put(&validator, stake);
+                if unbonded > 0 {
+                    let mut releases = releases_db.get(&release).unwrap_or_default();
+                    releases.0.push((delegator, unbonded));
+                    releases_db.put(&release, releases);
+                }
+            }
+        }
+    }
+}
+
+/// StakeFilter drops the elected validators whose stake before the boundary is below the minimum
+/// stake, the previous set is kept if nobody has enough stake
+pub struct StakeFilter {
+    inner: SafeEpochHook,
+}
+
+impl StakeFilter {
+    pub fn new(inner: SafeEpochHook) -> Self {
+        StakeFilter { inner }
+    }
+}
+
+impl EpochHook for StakeFilter {
+    fn elect(&self, ledger: &Ledger, number: u64, boundary: Height, previous: &[Address]) -> Vec<Address> {
+        let elected = self.inner.elect(ledger, number, boundary, previous);
+        let min_stake = ledger.get_staking_config().min_stake;
+        if min_stake == 0 {
+            return elected;
+        }
+        let staked: Vec<Address> = elected.into_iter()
+            .filter(|validator| ledger.get_stake(validator).stake_before(boundary) >= min_stake)
+            .collect();
+        if staked.is_empty() {
+            warn!("No validator has the minimum stake {} at epoch {}, keep the previous validators", min_stake, number);
+            return previous.to_vec();
+        }
+        staked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+    use crate::core::test_util::memory_ledger_of;
+    use crate::core::verify::truncate_chain;
+    use crate::types::account::Account;
+    use crate::types::block::{Block, Header};
+
+    fn staking_ledger(delegator: &KeyPair) -> Ledger {
+        let mut ledger = memory_ledger_of(vec![]);
+        ledger.set_staking_config(StakingConfig { min_stake: 0, unbonding_period: 2 });
+        ledger.add_genesis_alloc(&[(delegator.address(), 100)]);
+        ledger
+    }
+
+    // the blocks of the staking tests, a coinbase transaction leads every block
+    fn staking_blocks(delegator: &KeyPair, validator: &Address) -> Vec<Block> {
+        let payloads: Vec<Vec<(u64, Vec<u8>)>> = vec![
+            vec![],
+            // the second bond is above the balance left
+            vec![(60, encode_bond(validator)), (60, encode_bond(validator))],
+            vec![(0, encode_unbond(validator, 50))],
+            vec![],
+            vec![],
+        ];
+        let (mut prev_hash, mut nonce, mut blocks) = (Hash::zero(), 0, vec![]);
+        for (height, payloads) in payloads.into_iter().enumerate() {
+            let mut transactions = vec![Transaction::new(height as u64, Address::from(99), 1, 0, 1, vec![])];
+            for (amount, payload) in payloads {
+                let mut transaction = Transaction::new(nonce, *STAKING_ADDRESS, amount, 0, 1, payload);
+                transaction.sign(1, delegator.secret());
+                transactions.push(transaction);
+                nonce += 1;
+            }
+            let mut header = Header::zero_header();
+            header.height = height as Height;
+            header.prev_hash = prev_hash;
+            let block = Block::new(header, transactions);
+            prev_hash = block.hash();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn t_write_stakes() {
+        let (delegator, validator) = (Random.generate().unwrap(), Address::from(1));
+        let mut ledger = staking_ledger(&delegator);
+        let blocks = staking_blocks(&delegator, &validator);
+        for block in blocks[..2].iter() {
+            ledger.add_block(block);
+        }
+        // the bond above the balance stakes nothing and moves nothing
+        assert_eq!(ledger.get_stake(&validator).bonds, vec![(delegator.address(), 60)]);
+        assert_eq!(ledger.get_account(&delegator.address()), Account { balance: 40, nonce: 2 });
+        assert_eq!(ledger.get_account(&STAKING_ADDRESS).balance, 60);
+
+        ledger.add_block(&blocks[2]);
+        let stake = ledger.get_stake(&validator);
+        assert_eq!(stake.history, vec![(1, 60), (2, 10)]);
+        assert_eq!(stake.unbonding, vec![Unbonding { delegator: delegator.address(), amount: 50, release: 4 }]);
+        assert_eq!(ledger.get_schema().stake_releases().get(&4), Some(Releases(vec![(delegator.address(), 50)])));
+
+        // the unbonded stake is locked until the release height
+        ledger.add_block(&blocks[3]);
+        assert_eq!(ledger.get_account(&delegator.address()).balance, 40);
+        ledger.add_block(&blocks[4]);
+        assert_eq!(ledger.get_account(&delegator.address()).balance, 90);
+        assert_eq!(ledger.get_account(&STAKING_ADDRESS).balance, 10);
+        assert_eq!(ledger.state_at(4).unwrap().account(&delegator.address()).balance, 90);
+        assert_eq!(ledger.state_at(3).unwrap().account(&STAKING_ADDRESS).balance, 60);
+    }
+
+    #[test]
+    fn t_stake_filter() {
+        struct Fixed(Vec<Address>);
+
+        impl EpochHook for Fixed {
+            fn elect(&self, _ledger: &Ledger, _number: u64, _boundary: Height, _previous: &[Address]) -> Vec<Address> {
+                self.0.clone()
+            }
+        }
+
+        let (delegator, validator, other) = (Random.generate().unwrap(), Address::from(1), Address::from(2));
+        let mut ledger = staking_ledger(&delegator);
+        for block in staking_blocks(&delegator, &validator)[..3].iter() {
+            ledger.add_block(block);
+        }
+        let filter = StakeFilter::new(Box::new(Fixed(vec![validator, other])));
+        let previous = vec![Address::from(3)];
+        // no minimum stake
+        assert_eq!(filter.elect(&ledger, 1, 3, &previous), vec![validator, other]);
+        // the stake before the boundary counts, 60 before the unbond at height 2 and 10 after it
+        ledger.set_staking_config(StakingConfig { min_stake: 20, unbonding_period: 2 });
+        assert_eq!(filter.elect(&ledger, 1, 2, &previous), vec![validator]);
+        assert_eq!(filter.elect(&ledger, 1, 3, &previous), previous);
+        ledger.set_staking_config(StakingConfig { min_stake: 10, unbonding_period: 2 });
+        assert_eq!(filter.elect(&ledger, 1, 3, &previous), vec![validator]);
+    }
+
+    // the truncated chain keeps the stakes, the releases and the history of the lower height
+    #[test]
+    fn t_truncate_stakes() {
+        let (delegator, validator) = (Random.generate().unwrap(), Address::from(1));
+        let blocks = staking_blocks(&delegator, &validator);
+        let mut ledger = staking_ledger(&delegator);
+        blocks.iter().for_each(|block| ledger.add_block(block));
+        let mut expect = staking_ledger(&delegator);
+        blocks[..2].iter().for_each(|block| expect.add_block(block));
+
+        let (_, reverted) = truncate_chain(&mut ledger, 1).unwrap();
+        assert_eq!(reverted.len(), 3);
+        assert_eq!(ledger.get_stake(&validator), expect.get_stake(&validator));
+        assert_eq!(ledger.get_stake(&validator).history, vec![(1, 60)]);
+        assert!(ledger.get_schema().stake_releases().iter().next().is_none());
+        for address in vec![delegator.address(), *STAKING_ADDRESS] {
+            assert_eq!(ledger.get_account(&address), expect.get_account(&address));
+        }
+    }
+
+    #[test]
+    fn t_staking() {
+        let validator = Address::from(1);
+        assert_eq!(decode_staking(&encode_bond(&validator)).unwrap(), StakingOp::Bond(validator));
+        assert_eq!(decode_staking(&encode_unbond(&validator, 5)).unwrap(), StakingOp::Unbond(validator, 5));
+        assert!(decode_staking(&[0xc1, 0x01]).is_err());
+
+        let (a, b) = (Address::from(10), Address::from(11));
+        let mut stake = ValidatorStake::default();
+        stake.bond(a, 100, 3);
+        stake.bond(b, 50, 3);
+        assert_eq!(stake.history, vec![(3, 150)]);
+        // at most the bonded amount is unbonded
+        assert_eq!(stake.unbond(b, 80, 5, 15), 50);
+        assert_eq!(stake.unbond(b, 1, 6, 16), 0);
+        assert_eq!(stake.bonds, vec![(a, 100)]);
+        assert_eq!(stake.unbonding, vec![Unbonding { delegator: b, amount: 50, release: 15 }]);
+
+        assert_eq!(stake.stake_before(3), 0);
+        assert_eq!(stake.stake_before(4), 150);
+        assert_eq!(stake.stake_before(6), 100);
+        stake.prune(15);
+        assert!(stake.unbonding.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use serde::{Deserialize, Serialize};
+
+use crate::common::merkle_tree_root;
+use crate::core::executor::{apply_transactions, TxOutcome};
+use crate::core::fee::FeeConfig;
+use crate::core::staking::STAKING_ADDRESS;
+use crate::types::account::Account;
+use crate::types::transaction::Transaction;
+use crate::types::Height;
+
+pub const DEFAULT_STATE_SNAPSHOT_INTERVAL: u64 = 1000;
+pub const DEFAULT_STATE_HISTORY: u64 = 16;
+
+/// the historical states kept by the ledger, eg: `[state]`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateConfig {
+    // a state snapshot is persisted every N blocks, 0 disables them
+    #[serde(default = "default_snapshot_interval")]
+    pub snapshot_interval: u64,
+    // the snapshots retained behind the last one, the older ones are pruned, 0 keeps all
+    #[serde(default = "default_state_history")]
+    pub history: u64,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig { snapshot_interval: default_snapshot_interval(), history: default_state_history() }
+    }
+}
+
+fn default_snapshot_interval() -> u64 {
+    DEFAULT_STATE_SNAPSHOT_INTERVAL
+}
+
+fn default_state_history() -> u64 {
+    DEFAULT_STATE_HISTORY
+}
+
+impl StateConfig {
+    pub fn is_due(&self, height: Height) -> bool {
+        self.snapshot_interval > 0 && height > 0 && height % self.snapshot_interval == 0
+    }
+
+    /// the snapshot height at or below the height
+    pub fn snapshot_below(&self, height: Height) -> Height {
+        if self.snapshot_interval == 0 {
+            return 0;
+        }
+        height - height % self.snapshot_interval
+    }
+
+    /// the lowest snapshot height retained when the last block is at `last_height`, the states
+    /// below it are pruned
+    pub fn oldest_retained(&self, last_height: Height) -> Height {
+        if self.snapshot_interval == 0 || self.history == 0 {
+            return 0;
+        }
+        self.snapshot_below(last_height).saturating_sub(self.history.saturating_mul(self.snapshot_interval))
+    }
+}
+
+/// all account states after the block at the height
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub height: Height,
+    pub root: Hash,
+    pub accounts: Vec<(Address, Account)>,
+}
+
+implement_hash_algo_traits! {StateSnapshot}
+implement_storagevalue_traits! {StateSnapshot}
+
+/// the root of the account states, the leaves are the sorted (address, account) pairs
+pub fn state_root<'a, I: IntoIterator<Item=(&'a Address, &'a Account)>>(accounts: I) -> Hash {
+    let leaves: Vec<Vec<u8>> = accounts.into_iter().map(|(address, account)| {
+        let mut leaf = address.to_vec();
+        leaf.extend(account.clone().into_bytes());
+        leaf
+    }).collect();
+    if leaves.is_empty() {
+        return Hash::zero();
+    }
+    merkle_tree_root(leaves)
+}
+
+/// StateView is the account states at a historical height
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateView {
+    pub height: Height,
+    accounts: BTreeMap<Address, Account>,
+    // the validator set of the epoch at the height, the ledger leaves it empty
+    validators: Vec<Address>,
+}
+
+impl StateView {
+    pub fn new(height: Height, accounts: BTreeMap<Address, Account>) -> Self {
+        StateView { height, accounts, validators: vec![] }
+    }
+
+    pub fn from_snapshot(snapshot: StateSnapshot) -> Self {
+        StateView { height: snapshot.height, accounts: snapshot.accounts.into_iter().collect(), validators: vec![] }
+    }
+
+    pub fn with_validators(mut self, validators: Vec<Address>) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    pub fn validators(&self) -> &[Address] {
+        &self.validators
+    }
+
+    pub fn account(&self, address: &Address) -> Account {
+        self.accounts.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> &BTreeMap<Address, Account> {
+        &self.accounts
+    }
+
+    pub fn root(&self) -> Hash {
+        state_root(self.accounts.iter())
+    }
+
+    /// apply the block at the height like `write_block_indexes`, see `apply_transactions`
+    pub fn apply_block(&mut self, height: Height, transactions: &[Transaction], fees: &FeeConfig) -> Vec<TxOutcome> {
+        let outcomes = apply_transactions(&mut self.accounts, transactions, fees);
+        self.height = height;
+        outcomes
+    }
+
+    /// credit the block rewards like `write_rewards` and the fees like `write_fees`
+    pub fn apply_rewards(&mut self, rewards: &[(Address, u64)]) {
+        for (address, amount) in rewards {
+            self.accounts.entry(*address).or_insert_with(Account::default).credit(*amount);
+        }
+    }
+
+    /// release the unbonded stakes from the staking account to the delegators like `write_stakes`
+    pub fn apply_releases(&mut self, releases: &[(Address, u64)]) {
+        for (delegator, amount) in releases {
+            self.accounts.entry(*delegator).or_insert_with(Account::default).credit(*amount);
+            self.accounts.entry(*STAKING_ADDRESS).or_insert_with(Account::default).withdraw(*amount);
+        }
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            height: self.height,
+            root: self.root(),
+            accounts: self.accounts.iter().map(|(address, account)| (*address, account.clone())).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+
+    #[test]
+    fn t_state_view() {
+        let config = StateConfig { snapshot_interval: 10, history: 2 };
+        assert!(config.is_due(20) && !config.is_due(0) && !config.is_due(21));
+        assert_eq!(config.snapshot_below(29), 20);
+        assert_eq!(config.oldest_retained(45), 20);
+        assert_eq!(config.oldest_retained(15), 0);
+        assert_eq!(StateConfig { snapshot_interval: 10, history: 0 }.oldest_retained(45), 0);
+
+        let key_pair = Random.generate().unwrap();
+        let mut view = StateView::new(0, BTreeMap::new());
+        assert_eq!(view.root(), Hash::zero());
+        let coinbase = Transaction::new(0, key_pair.address(), 10, 0, 1, vec![]);
+        let mut transfer = Transaction::new(0, Address::from(2), 4, 0, 1, vec![]);
+        transfer.sign(1, key_pair.secret());
+        view.apply_block(1, &[coinbase, transfer], &FeeConfig::disabled());
+        assert_eq!(view.account(&key_pair.address()), Account { balance: 6, nonce: 1 });
+        assert_eq!(view.account(&Address::from(2)).balance, 4);
+
+        let snapshot = view.snapshot();
+        assert_eq!(snapshot.height, 1);
+        assert_ne!(snapshot.root, Hash::zero());
+        assert_eq!(StateView::from_snapshot(snapshot.clone()).root(), snapshot.root);
+    }
+}
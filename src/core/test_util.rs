@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use cryptocurrency_kit::crypto::{Hash, EMPTY_HASH};
+use cryptocurrency_kit::ethkey::KeyPair;
+use lru_time_cache::LruCache;
+use parking_lot::RwLock;
+
+use crate::config::{Config, GenesisConfig};
+use crate::consensus::consensus::EngineKind;
+use crate::core::chain::Chain;
+use crate::core::gas::GasConfig;
+use crate::core::ledger::{LastMeta, Ledger};
+use crate::store::backend::{Backend, MEMORY_STORE};
+use crate::store::memory::MemoryDB;
+use crate::store::schema::Schema;
+use crate::types::block::{Block, Header};
+use crate::types::votes::{encrypt_commit_bytes, Votes};
+use crate::types::{Height, Validator};
+use crate::util::clock::SafeClock;
+
+/// the ledger over the store with the genesis validators, the tests build their ledgers by it
+pub fn ledger_of(backend: Backend, validators: Vec<Validator>) -> Ledger {
+    Ledger::new(LastMeta::new_zero(), LruCache::with_capacity(16), LruCache::with_capacity(16), validators, Schema::new(Arc::new(backend)))
+}
+
+/// the ledger over the memory store with the genesis validators
+pub fn memory_ledger_of(validators: Vec<Validator>) -> Ledger {
+    ledger_of(Backend::Memory(MemoryDB::new()), validators)
+}
+
+/// the ledger over the memory store whose only validator is the key pair
+pub fn memory_ledger(key_pair: &KeyPair) -> Ledger {
+    memory_ledger_of(vec![Validator::new(key_pair.address())])
+}
+
+/// the empty block at the height sealed by the key pair
+pub fn sealed_block(parent: Hash, height: Height, key_pair: &KeyPair) -> Block {
+    let mut header = Header::new_mock(parent, key_pair.address(), EMPTY_HASH, height, height, None);
+    header.votes = Some(Votes::new(vec![encrypt_commit_bytes(&header.block_hash(), key_pair.secret())]));
+    Block::new(header, vec![])
+}
+
+/// the genesis config whose only validator and proposer is the key pair
+pub fn genesis_config(key_pair: &KeyPair) -> GenesisConfig {
+    let validator = format!("\"0x{}\"", hex::encode(key_pair.address().as_ref()));
+    let genesis = format!("validator = [{0}]\nepoch_time = 2019-01-01T00:00:00Z\nproposer = {0}\ngas_used = 0\nextra = \"test\"\n[accounts]\n", validator);
+    toml::from_str(&genesis).unwrap()
+}
+
+/// the chain over the memory store with the genesis block of `genesis_config`, the mock headers
+/// carry no gas, so the gas rules never activate. It creates actors, the caller runs a system.
+pub fn memory_chain(key_pair: &KeyPair) -> Arc<Chain> {
+    memory_chain_of(key_pair, EngineKind::Pbft)
+}
+
+/// `memory_chain` of the engine, the key pair is the only delegate of a dpos chain
+pub fn memory_chain_of(key_pair: &KeyPair, engine: EngineKind) -> Arc<Chain> {
+    Arc::new(new_memory_chain(key_pair, engine))
+}
+
+/// `memory_chain` whose future blocks are held against the clock
+pub fn memory_chain_at(key_pair: &KeyPair, clock: SafeClock) -> Arc<Chain> {
+    let mut chain = new_memory_chain(key_pair, EngineKind::Pbft);
+    chain.set_clock(clock);
+    Arc::new(chain)
+}
+
+fn new_memory_chain(key_pair: &KeyPair, engine: EngineKind) -> Chain {
+    let mut config = Config::default();
+    config.engine = engine;
+    config.store = MEMORY_STORE.to_string();
+    config.genesis = Some(genesis_config(key_pair));
+    config.gas = GasConfig { activation_height: Height::max_value(), ..GasConfig::default() };
+    let mut chain = Chain::new(config, Arc::new(RwLock::new(memory_ledger(key_pair))));
+    chain.store_genesis_block().unwrap();
+    chain
+}
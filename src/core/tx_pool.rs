@@ -1,33 +1,76 @@
 use std::sync::Arc;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 use ::actix::prelude::*;
 use priority_queue::PriorityQueue;
 use cryptocurrency_kit::crypto::{Hash, hash, EMPTY_HASH};
+use cryptocurrency_kit::ethkey::Address;
 use evmap::{self, WriteHandle, ReadHandle};
 
 use crate::{
+    types::Height,
     types::transaction::Transaction,
+    types::account::Account,
+    core::limits::SizeLimits,
     error::TxPoolError,
+    subscriber::events::{TxPoolEvent, TxPoolEventCT::ProcessSignals},
+    metrics,
 };
 
 pub const MAX_TXPOOL_SIZE: u64 = 10_000_000;
 pub const MAX_SLOT_SIZE: u32 = 1_000;
+// the pool floor starts rising when the occupancy is more than the threshold
+pub const MIN_GAS_PRICE_THRESHOLD: f64 = 0.5;
+// the pool floor doubles every time the occupancy rises by the step
+pub const MIN_GAS_PRICE_DOUBLING_STEP: f64 = 0.05;
 
 pub trait TxPool {
     fn len(&self) -> usize;
+    fn capacity(&self) -> u64;
+    /// the effective minimum gas price, it rises exponentially with the pool occupancy
+    fn min_gas_price(&self) -> u64;
     fn get_tx(&self, tx_hash: &Hash) -> Option<&Transaction>;
+    /// at most n transactions by the fee priority, see `by_fee_priority`
     fn get_n_tx(&self, n: u64) -> Vec<&Transaction>;
+    /// the pending transactions of the sender, order by nonce
+    fn get_txs_by_sender(&self, sender: &Address) -> Vec<&Transaction>;
     fn add_tx(&mut self, transaction: Transaction) -> Result<u64, TxPoolError>;
     fn add_txs(&mut self, transactions: &Vec<Transaction>) -> Result<u64, TxPoolError>;
     fn remove_txs(&mut self, tx_hashes: Vec<&Hash>);
+    /// remove the transactions which are included by the block at the height
+    fn include_txs(&mut self, height: Height, tx_hashes: Vec<&Hash>);
+    /// the senders which have transactions in the pool
+    fn senders(&self) -> Vec<Address>;
+    /// drop the transaction from the local pool, eg: a stuck transaction removed by the operator
+    fn drop_tx(&mut self, tx_hash: &Hash, reason: &str) -> Option<Transaction>;
 }
 
 pub type SafeTxPool = Box<TxPool + Send + Sync>;
 
+/// the confirmed accounts which the pool admits the transactions against, eg: the chain
+pub trait AccountReader {
+    fn get_account(&self, address: &Address) -> Account;
+}
+
+pub type SafeAccountReader = Arc<AccountReader + Send + Sync>;
+
 pub struct BaseTxPool {
     pq: PriorityQueue<Hash, u64>,
     txs: Vec<BTreeMap<Hash, Transaction>>,
+    // sender => (nonce => tx hash)
+    senders: HashMap<Address, BTreeMap<u64, Hash>>,
+    count: u64,
+    capacity: u64,
+    base_min_gas_price: u64,
+    // the oversized transactions are refused, they can't be packed into a block
+    size_limits: SizeLimits,
+    // the pool events are published to it
+    notifier: Option<Addr<ProcessSignals>>,
+    // the amounts above the confirmed balance of the sender are refused, eg: a bond which can't
+    // be staked
+    accounts: Option<SafeAccountReader>,
+    // the transactions signed for another chain are refused, they can't be packed
+    chain_id: Option<u64>,
 }
 
 impl Actor for BaseTxPool {
@@ -36,7 +79,15 @@ impl Actor for BaseTxPool {
 
 impl TxPool for BaseTxPool {
     fn len(&self) -> usize {
-        self.txs.len()
+        self.count as usize
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn min_gas_price(&self) -> u64 {
+        adaptive_min_gas_price(self.base_min_gas_price, self.count, self.capacity)
     }
 
     fn get_tx(&self, tx_hash: &Hash) -> Option<&Transaction> {
@@ -44,18 +95,15 @@ impl TxPool for BaseTxPool {
     }
 
     fn get_n_tx(&self, n: u64) -> Vec<&Transaction> {
-        let mut txs = vec![];
-        let i: u64 = 0;
-        for (tx_hash, _) in self.pq.iter() {
-            if i >= n {
-                break;
-            }
-            let idx = self.get_idx(tx_hash);
+        let queues = self.senders.keys().map(|sender| self.get_txs_by_sender(sender)).collect();
+        by_fee_priority(queues, n as usize)
+    }
 
-            let m = self.txs.get(idx).unwrap();
-            txs.push(m.get(&tx_hash).unwrap());
+    fn get_txs_by_sender(&self, sender: &Address) -> Vec<&Transaction> {
+        match self.senders.get(sender) {
+            Some(nonces) => nonces.values().filter_map(|tx_hash| self.get_tx(tx_hash)).collect(),
+            None => vec![],
         }
-        txs
     }
 
     fn add_tx(&mut self, tx: Transaction) -> Result<u64, TxPoolError> {
@@ -64,8 +112,55 @@ impl TxPool for BaseTxPool {
         if v.get(&tx.get_hash().unwrap()).is_some() {
             return Ok(self.pq.len() as u64);
         }
+        if let Some(chain_id) = self.chain_id {
+            if tx.chain_id() != chain_id {
+                let err = TxPoolError::WrongChainId(chain_id, tx.chain_id());
+                self.notify(TxPoolEvent::Dropped(tx.get_hash().unwrap().clone(), err.to_string()));
+                return Err(err);
+            }
+        }
+        // the pooled transaction of the same sender and nonce is kept, the slot of a nonce holds
+        // one transaction
+        let sender = tx.sender();
+        if sender.map_or(false, |sender| self.senders.get(&sender).map_or(false, |nonces| nonces.contains_key(&tx.nonce()))) {
+            let err = TxPoolError::NonceTaken(tx.nonce());
+            self.notify(TxPoolEvent::Dropped(tx.get_hash().unwrap().clone(), err.to_string()));
+            return Err(err);
+        }
+        if self.count >= self.capacity {
+            let err = TxPoolError::MoreThanMaxSIZE(self.capacity);
+            self.notify(TxPoolEvent::Dropped(tx.get_hash().unwrap().clone(), err.to_string()));
+            return Err(err);
+        }
+        let size = rlp::encode(&tx).len() as u64;
+        if size > self.size_limits.max_tx_size {
+            let err = TxPoolError::Oversized(size, self.size_limits.max_tx_size);
+            metrics::inc_counter("txpool_oversized_total");
+            self.notify(TxPoolEvent::Dropped(tx.get_hash().unwrap().clone(), err.to_string()));
+            return Err(err);
+        }
+        let min_gas_price = adaptive_min_gas_price(self.base_min_gas_price, self.count, self.capacity);
+        if tx.gas_price() < min_gas_price {
+            let err = TxPoolError::Underpriced(tx.gas_price(), min_gas_price);
+            self.notify(TxPoolEvent::Dropped(tx.get_hash().unwrap().clone(), err.to_string()));
+            return Err(err);
+        }
+        if let (Some(accounts), Some(sender)) = (self.accounts.as_ref(), sender) {
+            let balance = accounts.get_account(&sender).balance;
+            if tx.amount() > balance {
+                let err = TxPoolError::InsufficientBalance(tx.amount(), balance);
+                self.notify(TxPoolEvent::Dropped(tx.get_hash().unwrap().clone(), err.to_string()));
+                return Err(err);
+            }
+        }
+        let v: &mut BTreeMap<_, _> = self.txs.get_mut(idx).unwrap();
         v.insert(tx.get_hash().unwrap().clone(), tx.clone());
-        self.pq.push(tx.get_hash().unwrap().clone(), tx.amount());
+        if let Some(sender) = sender {
+            self.senders.entry(sender).or_insert_with(BTreeMap::new).insert(tx.nonce(), tx.get_hash().unwrap().clone());
+        }
+        self.count += 1;
+        self.pq.push(tx.get_hash().unwrap().clone(), tx.gas_price());
+        self.notify(TxPoolEvent::Added(tx));
         Ok(self.pq.len() as u64)
     }
 
@@ -82,23 +177,150 @@ impl TxPool for BaseTxPool {
         tx_hashes.iter().for_each(|tx_hash| {
             let idx = self.get_idx(tx_hash);
             let m: &mut BTreeMap<_, _> = self.txs.get_mut(idx).unwrap();
-            m.remove(tx_hash);
+            if let Some(tx) = m.remove(tx_hash) {
+                self.count -= 1;
+                if let Some(sender) = tx.sender() {
+                    let empty = self.senders.get_mut(&sender).map_or(false, |nonces| {
+                        // the slot of the nonce is only unlinked when it's still the transaction
+                        if nonces.get(&tx.nonce()) == Some(*tx_hash) {
+                            nonces.remove(&tx.nonce());
+                        }
+                        nonces.is_empty()
+                    });
+                    if empty {
+                        self.senders.remove(&sender);
+                    }
+                }
+            }
         });
+        metrics::set_gauge("txpool_pending", self.count as i64);
+    }
+
+    fn include_txs(&mut self, height: Height, tx_hashes: Vec<&Hash>) {
+        // only the pooled transactions are published, a block may be included twice, eg: the
+        // dev blocks are included when they're mined and when they're committed
+        let tx_hashes: Vec<&Hash> = tx_hashes.into_iter().filter(|tx_hash| self.get_tx(tx_hash).is_some()).collect();
+        if tx_hashes.is_empty() {
+            return;
+        }
+        let included = tx_hashes.iter().map(|tx_hash| (*tx_hash).clone()).collect();
+        self.remove_txs(tx_hashes);
+        self.notify(TxPoolEvent::Included(height, included));
+    }
+
+    fn senders(&self) -> Vec<Address> {
+        self.senders.keys().cloned().collect()
+    }
+
+    fn drop_tx(&mut self, tx_hash: &Hash, reason: &str) -> Option<Transaction> {
+        let tx = self.get_tx(tx_hash).cloned()?;
+        self.remove_txs(vec![tx_hash]);
+        self.notify(TxPoolEvent::Dropped(*tx_hash, reason.to_string()));
+        Some(tx)
+    }
+}
+
+/// the pool contents by sender, the pending transactions follow the confirmed nonce without a gap
+/// and can be packed now, the queued ones wait for the missing nonces
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TxPoolContents {
+    pub pending: BTreeMap<String, Vec<Transaction>>,
+    pub queued: BTreeMap<String, Vec<Transaction>>,
+    pub pending_count: usize,
+    pub queued_count: usize,
+    // the rlp encoded size of the pending and queued transactions
+    pub bytes: u64,
+}
+
+impl TxPoolContents {
+    /// `nonce_of` is the confirmed nonce of the sender, the transactions below it are committed
+    /// and not counted
+    pub fn new<F>(pool: &TxPool, nonce_of: F) -> Self
+        where F: Fn(&Address) -> u64 {
+        let mut contents = TxPoolContents::default();
+        for sender in pool.senders() {
+            let mut next_nonce = nonce_of(&sender);
+            let (mut pending, mut queued) = (vec![], vec![]);
+            for tx in pool.get_txs_by_sender(&sender).into_iter().filter(|tx| tx.nonce() >= next_nonce) {
+                contents.bytes += ::rlp::encode(tx).len() as u64;
+                if queued.is_empty() && tx.nonce() == next_nonce {
+                    next_nonce += 1;
+                    pending.push(tx.clone());
+                } else {
+                    queued.push(tx.clone());
+                }
+            }
+            contents.pending_count += pending.len();
+            contents.queued_count += queued.len();
+            let sender = format!("{:?}", sender);
+            if !pending.is_empty() {
+                contents.pending.insert(sender.clone(), pending);
+            }
+            if !queued.is_empty() {
+                contents.queued.insert(sender, queued);
+            }
+        }
+        contents
     }
 }
 
 impl BaseTxPool {
     pub fn new() -> Self {
+        BaseTxPool::with_capacity(MAX_TXPOOL_SIZE, 0)
+    }
+
+    pub fn with_capacity(capacity: u64, base_min_gas_price: u64) -> Self {
         let n = (MAX_TXPOOL_SIZE / u64::from(MAX_SLOT_SIZE)) as usize;
         let mut tx_pool = BaseTxPool {
             pq: PriorityQueue::new(),
             txs: Vec::with_capacity(n),
+            senders: HashMap::new(),
+            count: 0,
+            capacity,
+            base_min_gas_price,
+            size_limits: SizeLimits::default(),
+            notifier: None,
+            accounts: None,
+            chain_id: None,
         };
         (0..n).for_each(|_| {
             tx_pool.txs.push(BTreeMap::new());
         });
         tx_pool
     }
+
+    pub fn with_size_limits(mut self, size_limits: SizeLimits) -> Self {
+        self.size_limits = size_limits;
+        self
+    }
+
+    pub fn with_notifier(mut self, notifier: Option<Addr<ProcessSignals>>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    pub fn with_accounts(mut self, accounts: SafeAccountReader) -> Self {
+        self.accounts = Some(accounts);
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    fn notify(&self, event: TxPoolEvent) {
+        match event {
+            TxPoolEvent::Added(_) => metrics::inc_counter("txpool_added_total"),
+            TxPoolEvent::Dropped(..) => metrics::inc_counter("txpool_dropped_total"),
+            TxPoolEvent::Included(_, ref tx_hashes) => metrics::inc_counter_by("txpool_included_total", tx_hashes.len() as u64),
+        }
+        metrics::set_gauge("txpool_pending", self.count as i64);
+        if let Some(ref notifier) = self.notifier {
+            notifier.do_send(event);
+        }
+    }
+
     fn get_idx(&self, tx_hash: &Hash) -> usize {
         use ethereum_types::U256;
         let u = U256::from(tx_hash.as_ref());
@@ -106,6 +328,41 @@ impl BaseTxPool {
     }
 }
 
+/// merge the transactions of the senders by the gas price, the higher price pays the proposer more
+/// fee for the same gas. Every queue is in the nonce order of a sender, so only its head competes
+/// and a sender's transactions never overtake each other, the ties keep the queue order.
+pub fn by_fee_priority(mut queues: Vec<Vec<&Transaction>>, n: usize) -> Vec<&Transaction> {
+    queues.iter_mut().for_each(|queue| queue.reverse());
+    let mut txs = Vec::with_capacity(n);
+    while txs.len() < n {
+        let best = queues.iter().enumerate()
+            .filter_map(|(idx, queue)| queue.last().map(|tx| (idx, tx.gas_price())))
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+        match best {
+            Some((idx, _)) => txs.push(queues[idx].pop().unwrap()),
+            None => break,
+        }
+    }
+    txs
+}
+
+/// the pool floor equals `base` until the occupancy reaches `MIN_GAS_PRICE_THRESHOLD`, then
+/// it doubles for every `MIN_GAS_PRICE_DOUBLING_STEP` of occupancy
+pub fn adaptive_min_gas_price(base: u64, len: u64, capacity: u64) -> u64 {
+    if capacity == 0 {
+        return u64::max_value();
+    }
+    let occupancy = len as f64 / capacity as f64;
+    if occupancy < MIN_GAS_PRICE_THRESHOLD {
+        return base;
+    }
+    let exp = (occupancy - MIN_GAS_PRICE_THRESHOLD) / MIN_GAS_PRICE_DOUBLING_STEP;
+    let floor = ::std::cmp::max(base, 1) as f64 * 2f64.powf(exp);
+    if floor >= u64::max_value() as f64 {
+        return u64::max_value();
+    }
+    floor.ceil() as u64
+}
 
 #[cfg(test)]
 mod tests {
@@ -120,6 +377,142 @@ mod tests {
         type Context = Context<Self>;
     }
 
+    #[test]
+    fn t_adaptive_min_gas_price() {
+        assert_eq!(adaptive_min_gas_price(0, 0, 100), 0);
+        assert_eq!(adaptive_min_gas_price(10, 49, 100), 10);
+        assert_eq!(adaptive_min_gas_price(10, 50, 100), 10);
+        assert_eq!(adaptive_min_gas_price(10, 55, 100), 20);
+        assert_eq!(adaptive_min_gas_price(10, 100, 100), 10 * 1024);
+        assert_eq!(adaptive_min_gas_price(0, 60, 100), 4);
+        assert_eq!(adaptive_min_gas_price(10, 0, 0), u64::max_value());
+    }
+
+    #[test]
+    fn t_txpool_contents() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let mut pool = BaseTxPool::with_capacity(100, 0);
+        let mut hashes = vec![];
+        // the nonce 3 waits for the nonce 2
+        for nonce in vec![0_u64, 1, 3] {
+            let mut tx = Transaction::new(nonce, Address::from(100), 1, 10, 1, vec![]);
+            tx.sign(1, key_pair.secret());
+            hashes.push(*tx.get_hash().unwrap());
+            pool.add_tx(tx).unwrap();
+        }
+        let contents = TxPoolContents::new(&pool, |_| 0);
+        assert_eq!((contents.pending_count, contents.queued_count), (2, 1));
+        assert!(contents.bytes > 0);
+        // the nonce 0 is committed
+        let contents = TxPoolContents::new(&pool, |_| 1);
+        assert_eq!((contents.pending_count, contents.queued_count), (1, 1));
+
+        assert!(pool.drop_tx(&hashes[2], "stuck").is_some());
+        assert!(pool.drop_tx(&hashes[2], "stuck").is_none());
+        assert_eq!(pool.len(), 2);
+        assert_eq!(TxPoolContents::new(&pool, |_| 0).queued_count, 0);
+
+        // the committed block is included once, the second time finds nothing pooled
+        pool.include_txs(1, vec![&hashes[0]]);
+        assert_eq!(pool.len(), 1);
+        pool.include_txs(1, vec![&hashes[0]]);
+        assert_eq!((pool.len(), pool.get_tx(&hashes[1]).is_some()), (1, true));
+
+        let mut pool = pool.with_size_limits(SizeLimits::new(4096, 256));
+        let mut tx = Transaction::new(4, Address::from(100), 1, 10, 1, vec![7; 512]);
+        tx.sign(1, key_pair.secret());
+        match pool.add_tx(tx) {
+            Err(TxPoolError::Oversized(_, max)) => assert_eq!(max, 256),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // the slot of a nonce holds one transaction, the pooled one is kept
+    #[test]
+    fn t_txpool_same_nonce() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let mut pool = BaseTxPool::with_capacity(100, 0);
+        let new_tx = |gas_price: u64| {
+            let mut tx = Transaction::new(0, Address::from(100), 1, 10, gas_price, vec![]);
+            tx.sign(1, key_pair.secret());
+            tx
+        };
+        let (first, second) = (new_tx(1), new_tx(2));
+        pool.add_tx(first.clone()).unwrap();
+        match pool.add_tx(second.clone()) {
+            Err(TxPoolError::NonceTaken(nonce)) => assert_eq!(nonce, 0),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(pool.len(), 1);
+        assert!(pool.get_tx(second.get_hash().unwrap()).is_none());
+        assert_eq!(pool.get_txs_by_sender(&key_pair.address()), vec![&first]);
+        assert_eq!(pool.get_n_tx(10).len(), 1);
+        // the unknown transaction of the nonce doesn't unlink the pooled one
+        pool.remove_txs(vec![second.get_hash().unwrap()]);
+        assert_eq!(pool.get_txs_by_sender(&key_pair.address()).len(), 1);
+        // the nonce is free after the pooled transaction is dropped
+        assert!(pool.drop_tx(first.get_hash().unwrap(), "stuck").is_some());
+        assert_eq!((pool.len(), pool.senders().is_empty()), (0, true));
+        pool.add_tx(second.clone()).unwrap();
+        assert_eq!(pool.get_txs_by_sender(&key_pair.address()), vec![&second]);
+    }
+
+    #[test]
+    fn t_txpool_balance() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        use crate::core::staking::{encode_bond, STAKING_ADDRESS};
+
+        struct Balances(u64);
+
+        impl AccountReader for Balances {
+            fn get_account(&self, _address: &Address) -> Account {
+                Account { balance: self.0, nonce: 0 }
+            }
+        }
+
+        let key_pair = Random.generate().unwrap();
+        let mut pool = BaseTxPool::with_capacity(100, 0).with_accounts(Arc::new(Balances(100)));
+        let mut bond = Transaction::new(0, *STAKING_ADDRESS, 101, 10, 1, encode_bond(&Address::from(1)));
+        bond.sign(1, key_pair.secret());
+        match pool.add_tx(bond) {
+            Err(TxPoolError::InsufficientBalance(amount, balance)) => assert_eq!((amount, balance), (101, 100)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut bond = Transaction::new(0, *STAKING_ADDRESS, 100, 10, 1, encode_bond(&Address::from(1)));
+        bond.sign(1, key_pair.secret());
+        assert_eq!(pool.add_tx(bond).unwrap(), 1);
+    }
+
+    #[test]
+    fn t_txpool_chain_id() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let mut pool = BaseTxPool::with_capacity(100, 0).with_chain_id(1);
+        let mut tx = Transaction::new(0, Address::from(100), 1, 10, 1, vec![]);
+        tx.sign(2, key_pair.secret());
+        match pool.add_tx(tx.clone()) {
+            Err(TxPoolError::WrongChainId(expect, got)) => assert_eq!((expect, got), (1, 2)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(pool.len(), 0);
+        tx.sign(1, key_pair.secret());
+        assert_eq!(pool.add_tx(tx).unwrap(), 1);
+    }
+
+    #[test]
+    fn t_fee_priority() {
+        let tx = |nonce: u64, gas_price: u64| Transaction::new(nonce, Address::from(100), 1, 21000, gas_price, vec![]);
+        let (a0, a1, b0, b1) = (tx(0, 1), tx(1, 9), tx(0, 5), tx(1, 2));
+        let order: Vec<(u64, u64)> = by_fee_priority(vec![vec![&a0, &a1], vec![&b0, &b1]], 10).into_iter()
+            .map(|tx| (tx.nonce(), tx.gas_price()))
+            .collect();
+        // the nonce 1 of a pays more but waits for the nonce 0
+        assert_eq!(order, vec![(0, 5), (1, 2), (0, 1), (1, 9)]);
+        assert_eq!(by_fee_priority(vec![vec![&a0, &a1], vec![&b0, &b1]], 1).len(), 1);
+    }
+
     #[test]
     fn t_txpool() {
 //        let mut v = vec![];
@@ -0,0 +1,236 @@
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use crate::consensus::consensus::SealVerifier;
+use crate::core::ledger::{drop_states_above, Ledger};
+use crate::core::reindex::{reindex, ReindexReport};
+use crate::core::scrub::{scrub_block, ScrubMismatch};
+use crate::store::schema::Schema;
+use crate::types::Height;
+use crate::types::block::Block;
+
+/// what the node does with the store before it starts, eg: `start --verify-db --repair`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyMode {
+    Off,
+    // refuse to start on the first bad block
+    Check,
+    // truncate the chain back to the last good block, the node resyncs the rest
+    Repair,
+}
+
+impl Default for VerifyMode {
+    fn default() -> Self {
+        VerifyMode::Off
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    pub checked: u64,
+    pub first_bad: Option<ScrubMismatch>,
+    // the blocks dropped by the repair, the highest first
+    #[serde(skip)]
+    pub reverted: Vec<Block>,
+}
+
+/// Walk the chain from the genesis, the stored block must hash to its key, link to its parent,
+/// carry the valid seals of the engine and be consistent with the indexes. It stops at the
+/// first bad block.
+pub fn verify_chain(ledger: &Ledger, seal_verifier: &SealVerifier) -> VerifyReport {
+    let schema = ledger.get_schema();
+    let mut report = VerifyReport::default();
+    let mut parent: Option<Hash> = None;
+    for height in 0..schema.block_hashes_by_height().len() {
+        report.checked += 1;
+        if let Err(mismatch) = verify_block(ledger, schema, seal_verifier, height, parent) {
+            report.first_bad = Some(mismatch);
+            break;
+        }
+        parent = schema.block_hash_by_height(height);
+    }
+    report
+}
+
+fn verify_block(ledger: &Ledger, schema: &Schema, seal_verifier: &SealVerifier, height: Height, parent: Option<Hash>) -> Result<(), ScrubMismatch> {
+    if let Some(mismatch) = scrub_block(schema, height).into_iter().next() {
+        return Err(mismatch);
+    }
+    // the scrub has checked the height index and the header
+    let hash = schema.block_hash_by_height(height).unwrap();
+    let header = schema.headers().get(&hash).unwrap();
+    let mismatch = |reason: String| ScrubMismatch { height, hash: Some(hash), reason };
+    if let Some(parent) = parent {
+        if header.prev_hash != parent {
+            return Err(mismatch(format!("the parent hash {:?} isn't the block at height {}", header.prev_hash, height - 1)));
+        }
+        seal_verifier.verify_seals(&header, &ledger.get_validator_addresses(height)).map_err(|err| mismatch(format!("invalid seals, {}", err)))?;
+    }
+    let body = schema.block_bodies().get(&hash).unwrap();
+    for (position, tx_hash) in body.0.iter().enumerate() {
+        // the stores before the location index have none
+        if let Some(location) = schema.tx_locations().get(tx_hash) {
+            if location.block_hash != hash || location.position != position as u64 {
+                return Err(mismatch(format!("the location index of the transaction {:?} points to the height {}", tx_hash, location.block_height)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drop the blocks above the last good height from the store and rebuild the indexes, the
+/// account states are replayed from the genesis, so they match the shorter chain. It returns the
+/// dropped blocks which are readable, the highest first.
+pub fn truncate_chain(ledger: &mut Ledger, last_good: Height) -> Result<(ReindexReport, Vec<Block>), String> {
+    let last_height = ledger.get_schema().block_hashes_by_height().len().saturating_sub(1);
+    let reverted: Vec<Block> = (last_good + 1..=last_height).rev().filter_map(|height| ledger.get_block_by_height(height)).collect();
+    let mut dropped_bytes = 0;
+    {
+        let schema = ledger.get_schema();
+        let mut hashes: Vec<Hash> = schema.block_hashes_by_height().iter().skip(last_good as usize + 1).collect();
+        // the headers beyond the height index, eg: a block which was half written
+        hashes.extend(schema.headers().iter().filter(|(_, header)| header.height > last_good).map(|(hash, _)| hash));
+        hashes.sort();
+        hashes.dedup();
+        let (mut headers, mut bodies, mut transactions) = (schema.headers(), schema.block_bodies(), schema.transaction());
+        for hash in hashes.iter() {
+            if let Some(body) = bodies.get(hash) {
+                for tx_hash in body.0.iter() {
+                    dropped_bytes += transactions.get(tx_hash).map_or(0, |transaction| transaction.into_bytes().len());
+                    transactions.remove(tx_hash);
+                }
+                dropped_bytes += body.into_bytes().len();
+            }
+            dropped_bytes += headers.get(hash).map_or(0, |header| header.into_bytes().len());
+            bodies.remove(hash);
+            headers.remove(hash);
+        }
+        let (mut certificates, mut messages) = (schema.commit_certificates(), schema.consensus_messages());
+        let heights: Vec<Height> = certificates.keys().filter(|height| *height > last_good).collect();
+        for height in heights.iter() {
+            dropped_bytes += certificates.get(height).map_or(0, |certificate| certificate.into_bytes().len());
+            certificates.remove(height);
+        }
+        let heights: Vec<Height> = messages.keys().filter(|height| *height > last_good).collect();
+        for height in heights.iter() {
+            dropped_bytes += messages.get(height).map_or(0, |messages| messages.into_bytes().len());
+            messages.remove(height);
+        }
+        let mut missed = schema.dpos_missed_slots();
+        let heights: Vec<Height> = missed.keys().filter(|height| *height > last_good).collect();
+        for height in heights.iter() {
+            missed.remove(height);
+        }
+        // the states of the dropped blocks, a replay must not start from them
+        dropped_bytes += drop_states_above(schema, last_good) as usize;
+        warn!("Truncate the chain to height {}, dropped blocks: {}", last_good, hashes.len());
+    }
+    let report = reindex(ledger.get_schema(), |height, last_height| {
+        info!("Reindex blocks, height: {}/{}", height, last_height);
+    })?;
+    ledger.add_pruned_bytes(dropped_bytes as u64);
+    ledger.clear_caches();
+    ledger.reload_meta();
+    Ok((report, reverted))
+}
+
+/// verify the store before the node starts, the repair truncates the chain to the last good block
+pub fn verify_store(ledger: &mut Ledger, seal_verifier: &SealVerifier, mode: VerifyMode) -> Result<VerifyReport, String> {
+    let report = verify_chain(ledger, seal_verifier);
+    let bad = match report.first_bad {
+        Some(ref bad) => bad.clone(),
+        None => {
+            info!("The store is verified, blocks: {}", report.checked);
+            return Ok(report);
+        }
+    };
+    error!("The store is broken, height: {}, hash: {:?}, {}", bad.height, bad.hash, bad.reason);
+    if mode != VerifyMode::Repair {
+        return Err(format!("the store is broken at height {}, {}", bad.height, bad.reason));
+    }
+    if bad.height == 0 {
+        return Err("the genesis block is broken, the store can't be repaired".to_string());
+    }
+    let (_, reverted) = truncate_chain(ledger, bad.height - 1)?;
+    Ok(VerifyReport { reverted, ..report })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::consensus::CommitSeals;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::core::test_util::{memory_ledger, sealed_block};
+    use crate::types::block::Header;
+
+    #[test]
+    fn t_verify_chain() {
+        let key_pair = Random.generate().unwrap();
+        let mut ledger = memory_ledger(&key_pair);
+        let genesis = Block::new(Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 0, 0, None), vec![]);
+        ledger.add_genesis_block(&genesis);
+        ledger.reload_meta();
+        let mut parent = genesis.hash();
+        for height in 1..10 {
+            let block = sealed_block(parent, height, &key_pair);
+            parent = block.hash();
+            ledger.add_block(&block);
+        }
+        let report = verify_chain(&ledger, &CommitSeals);
+        assert_eq!(report.checked, 10);
+        assert!(report.first_bad.is_none());
+
+        // an unsealed header under the hash of the height 6
+        let hash = ledger.get_schema().block_hash_by_height(6).unwrap();
+        let mut header = ledger.get_schema().headers().get(&hash).unwrap();
+        header.votes = None;
+        ledger.get_schema().headers().put(&hash, header);
+        assert!(verify_store(&mut ledger, &CommitSeals, VerifyMode::Check).is_err());
+        let report = verify_store(&mut ledger, &CommitSeals, VerifyMode::Repair).unwrap();
+        assert_eq!(report.first_bad.unwrap().height, 6);
+        assert_eq!(report.reverted.iter().map(|block| block.height()).collect::<Vec<Height>>(), vec![9, 8, 7, 6]);
+        assert_eq!(*ledger.get_last_block_height(), 5);
+        assert!(verify_chain(&ledger, &CommitSeals).first_bad.is_none());
+        // the dropped blocks are left for the compaction
+        assert!(ledger.pruned_bytes() > 0);
+    }
+
+    #[test]
+    fn t_truncate_states() {
+        use cryptocurrency_kit::ethkey::Address;
+        use crate::core::state::StateConfig;
+        use crate::types::transaction::Transaction;
+
+        let key_pair = Random.generate().unwrap();
+        let mut ledger = memory_ledger(&key_pair).with_state_config(StateConfig { snapshot_interval: 2, history: 0 });
+        let genesis = Block::new(Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 0, 0, None), vec![]);
+        ledger.add_genesis_block(&genesis);
+        ledger.reload_meta();
+        // every block mints the amount to the address 1
+        let add_blocks = |ledger: &mut Ledger, mut parent: Hash, heights: ::std::ops::RangeInclusive<Height>, amount: u64| {
+            for height in heights {
+                let header = Header::new_mock(parent, key_pair.address(), EMPTY_HASH, height, height, None);
+                let block = Block::new(header, vec![Transaction::new(height, Address::from(1), amount, 0, 1, vec![])]);
+                parent = block.hash();
+                ledger.add_block(&block);
+            }
+        };
+        add_blocks(&mut ledger, genesis.hash(), 1..=6, 10);
+        assert_eq!(ledger.get_schema().state_snapshots().keys().collect::<Vec<_>>(), vec![2, 4, 6]);
+
+        // the snapshots of the dropped blocks go with them
+        truncate_chain(&mut ledger, 3).unwrap();
+        assert_eq!(ledger.get_schema().state_snapshots().keys().collect::<Vec<_>>(), vec![2]);
+        assert!(ledger.get_state_root(4).is_none());
+        assert!(ledger.pruned_bytes() > 0);
+        assert_eq!(ledger.state_at(3).unwrap().account(&Address::from(1)).balance, 30);
+
+        // the other blocks on top of the height 3 are replayed, not the stale states
+        let parent = ledger.get_schema().block_hash_by_height(3).unwrap();
+        add_blocks(&mut ledger, parent, 4..=6, 20);
+        assert_eq!(ledger.state_at(5).unwrap().account(&Address::from(1)).balance, 70);
+        assert_eq!(ledger.state_at(4).unwrap().root(), ledger.get_state_root(4).unwrap());
+        assert_eq!(ledger.state_at(6).unwrap().account(&Address::from(1)).balance, 90);
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::Instant;
+
+use crate::config::CacheConfig;
+use crate::core::ledger::Ledger;
+use crate::metrics;
+
+/// which regions of the store are loaded by the warm-up, eg: `[warm_up]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WarmUpConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // the recent headers loaded into the header cache
+    #[serde(default = "default_warm_up_headers")]
+    pub headers: u64,
+    // the recent blocks (with bodies) loaded into the block cache
+    #[serde(default = "default_warm_up_blocks")]
+    pub blocks: u64,
+}
+
+impl Default for WarmUpConfig {
+    fn default() -> Self {
+        WarmUpConfig { enabled: false, headers: default_warm_up_headers(), blocks: default_warm_up_blocks() }
+    }
+}
+
+fn default_warm_up_headers() -> u64 {
+    1 << 10
+}
+
+fn default_warm_up_blocks() -> u64 {
+    64
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WarmUpReport {
+    pub headers: u64,
+    pub blocks: u64,
+    pub consensus_messages: u64,
+    pub checkpoints: u64,
+    pub elapsed_ms: u64,
+}
+
+/// pre-load the tip region of the hot indexes, so the first api and consensus reads after a
+/// restart don't hit the cold store: the recent headers, blocks and consensus messages, the
+/// validator set and the checkpoint records. The loaded regions are bounded by the capacities of
+/// the caches, the entries beyond them would only evict each other
+pub fn warm_up(ledger: &Ledger, config: &WarmUpConfig, cache: &CacheConfig) -> WarmUpReport {
+    let start = Instant::now();
+    let mut report = WarmUpReport::default();
+    let schema = ledger.get_schema();
+    let last_height = *ledger.get_last_block_height();
+
+    let headers = ::std::cmp::min(config.headers, cache.headers as u64);
+    let from = last_height.saturating_sub(headers.saturating_sub(1));
+    for height in from..=last_height {
+        if ledger.get_header_by_height(height).is_some() {
+            report.headers += 1;
+        }
+    }
+    let blocks = ::std::cmp::min(config.blocks, cache.blocks as u64);
+    let from = last_height.saturating_sub(blocks.saturating_sub(1));
+    for height in from..=last_height {
+        let block = schema.block_hash_by_height(height).and_then(|hash| ledger.get_block(&hash));
+        if block.is_some() {
+            report.blocks += 1;
+        }
+        if ledger.get_consensus_messages(height).is_some() {
+            report.consensus_messages += 1;
+        }
+    }
+
+    let _ = schema.validators().get();
+    if let Some(checkpoint) = schema.last_checkpoint().get() {
+        report.checkpoints += 1;
+        if schema.checkpoints().get(&checkpoint.height).is_some() {
+            report.checkpoints += 1;
+        }
+    }
+
+    report.elapsed_ms = start.elapsed().as_millis() as u64;
+    metrics::set_gauge("store_warm_up_ms", report.elapsed_ms as i64);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::core::test_util::memory_ledger_of;
+    use crate::types::EMPTY_ADDRESS;
+    use crate::types::block::{Block, Header};
+
+    #[test]
+    fn t_warm_up() {
+        let mut ledger = memory_ledger_of(vec![]);
+        ledger.add_genesis_block(&Block::new(Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, 0, 0, None), vec![]));
+        ledger.reload_meta();
+        for height in 1..10 {
+            ledger.add_block(&Block::new(Header::new_mock(EMPTY_HASH, *EMPTY_ADDRESS, EMPTY_HASH, height, height, None), vec![]));
+        }
+        ledger.clear_caches();
+
+        let report = warm_up(&ledger, &WarmUpConfig { enabled: true, headers: 5, blocks: 20 }, &CacheConfig::default());
+        assert_eq!(report.headers, 5);
+        assert_eq!(report.blocks, 10);
+        assert_eq!(report.checkpoints, 0);
+
+        // the caches hold no more
+        let cache = CacheConfig { headers: 3, blocks: 2, ..CacheConfig::default() };
+        let report = warm_up(&ledger, &WarmUpConfig::default(), &cache);
+        assert_eq!((report.headers, report.blocks), (3, 2));
+    }
+}
@@ -2,10 +2,22 @@ use failure::Error;
 
 use cryptocurrency_kit::crypto::Hash;
 
+use crate::consensus::error::ConsensusError;
+
 #[derive(Debug, Fail)]
 pub enum TxPoolError {
     #[fail(display = "More than max txpool limit, max:{}", _0)]
     MoreThanMaxSIZE(u64),
+    #[fail(display = "Transaction underpriced, price:{}, min:{}", _0, _1)]
+    Underpriced(u64, u64),
+    #[fail(display = "Oversized transaction, size:{}, max:{}", _0, _1)]
+    Oversized(u64, u64),
+    #[fail(display = "Insufficient balance, amount:{}, balance:{}", _0, _1)]
+    InsufficientBalance(u64, u64),
+    #[fail(display = "Wrong chain id, expect:{}, got:{}", _0, _1)]
+    WrongChainId(u64, u64),
+    #[fail(display = "Nonce taken by a pooled transaction, nonce:{}", _0)]
+    NonceTaken(u64),
 }
 
 #[derive(Debug, Fail)]
@@ -14,12 +26,22 @@ pub enum P2PError {
     HandShakeFailed,
     #[fail(display = "different genesis")]
     DifferentGenesis,
+    #[fail(display = "incompatible protocol version, local:{}, remote:{}", _0, _1)]
+    IncompatibleProtocol(u32, u32),
+    #[fail(display = "different chain id, local:{}, remote:{}", _0, _1)]
+    DifferentChainId(u64, u64),
+    #[fail(display = "invalid validator binding, {}", _0)]
+    InvalidBinding(String),
     #[fail(display = "Dump connected")]
     DumpConnected,
     #[fail(display = "Invalid Message type")]
     InvalidMessage,
     #[fail(display = "Timeout")]
     Timeout,
+    #[fail(display = "invalid p2p address, {}", _0)]
+    InvalidAddress(String),
+    #[fail(display = "invalid p2p identity, {}", _0)]
+    Identity(String),
 }
 
 pub type ChainResult = Result<(), ChainError>;
@@ -28,6 +50,164 @@ pub type ChainResult = Result<(), ChainError>;
 pub enum ChainError {
     #[fail(display = "the block has exist, ({:?})", _0)]
     Exists(Hash),
+    #[fail(display = "the block conflicts with the trusted checkpoint, ({})", _0)]
+    Checkpoint(String),
+    #[fail(display = "the block breaks the gas rules, ({})", _0)]
+    Gas(String),
+    #[fail(display = "conflicting finalized blocks at height {}, the chain has halted", _0)]
+    SafetyViolation(u64),
+    #[fail(display = "the chain has halted on a safety violation")]
+    Halted,
     #[fail(display = "An unknown error has occurred, ({})", _0)]
     Unknown(String),
-}
\ No newline at end of file
+}
+#[derive(Debug, Fail, PartialEq)]
+pub enum RequestError {
+    #[fail(display = "the actor has stopped")]
+    Closed,
+    #[fail(display = "the actor didn't reply in {:?}", _0)]
+    Timeout(::std::time::Duration),
+}
+
+#[derive(Debug, Fail)]
+pub enum ConfigError {
+    #[fail(display = "failed to read the config {}, {}", _0, _1)]
+    Read(String, String),
+    #[fail(display = "invalid config, {}", _0)]
+    Invalid(String),
+    #[fail(display = "the genesis section is missing")]
+    MissingGenesis,
+}
+
+#[derive(Debug, Fail)]
+pub enum StoreError {
+    #[fail(display = "failed to open the store {}, {}", _0, _1)]
+    Open(String, String),
+    #[fail(display = "the store is not found: {}", _0)]
+    NotFound(String),
+    #[fail(display = "the store failed the verification, {}", _0)]
+    Corrupted(String),
+    #[fail(display = "the store holds a safety violation evidence, refuse to start, evidence: {}", _0)]
+    SafetyEvidence(String),
+    #[fail(display = "the sign state of the store is broken, {}", _0)]
+    SignState(String),
+}
+
+#[derive(Debug, Fail)]
+pub enum ApiError {
+    #[fail(display = "invalid api address, {}", _0)]
+    InvalidAddress(String),
+    #[fail(display = "invalid api tls config, {}", _0)]
+    Tls(String),
+    #[fail(display = "failed to bind the api, {}", _0)]
+    Bind(String),
+}
+
+/// NodeError is what stops a node from starting, every subsystem converts into it
+#[derive(Debug, Fail)]
+pub enum NodeError {
+    #[fail(display = "{}", _0)]
+    Config(ConfigError),
+    #[fail(display = "{}", _0)]
+    Store(StoreError),
+    #[fail(display = "{}", _0)]
+    P2P(P2PError),
+    #[fail(display = "consensus error, {}", _0)]
+    Consensus(ConsensusError),
+    #[fail(display = "chain error, {}", _0)]
+    Chain(ChainError),
+    #[fail(display = "{}", _0)]
+    Api(ApiError),
+    #[fail(display = "the clock is skewed, {}", _0)]
+    Clock(String),
+    #[fail(display = "the validator key is unavailable, {}", _0)]
+    Signer(String),
+    #[fail(display = "failed to start the chain `{}`, {}", _0, _1)]
+    ChainStart(String, Box<NodeError>),
+    #[fail(display = "{}", _0)]
+    Other(String),
+}
+
+impl NodeError {
+    /// what the operator can do about the error
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            NodeError::Config(ConfigError::Read(..)) => Some("check the path of `--config`"),
+            NodeError::Config(ConfigError::Invalid(_)) => Some("`config schema` prints the keys of the config"),
+            NodeError::Config(ConfigError::MissingGenesis) => Some("add the `[genesis]` section to the config"),
+            NodeError::Store(StoreError::Open(..)) => Some("another node may hold the lock of the store, or the directory isn't writable"),
+            NodeError::Store(StoreError::NotFound(_)) => Some("check the `store` of the config"),
+            NodeError::Store(StoreError::Corrupted(_)) => Some("repair it by `start --verify-db --repair` or rebuild the indexes by `reindex`"),
+            NodeError::Store(StoreError::SafetyEvidence(_)) => Some("examine the evidence by `report`, the store must be resynced on the correct fork"),
+            NodeError::Store(StoreError::SignState(_)) => Some("examine the sign state before the validator signs again, a reset may double sign"),
+            NodeError::Api(ApiError::Tls(_)) => Some("check `api.tls_cert` and `api.tls_key`, both are pem files"),
+            NodeError::Api(_) => Some("check `api_ip` and `api_port`"),
+            NodeError::P2P(_) => Some("check `ip`, `port`, `peer_id` and `node_key`"),
+            NodeError::Clock(_) => Some("sync the system clock, or set `clock.strict = false` to only warn"),
+            NodeError::Signer(_) => Some("check the `[signer]` endpoint and token, or the keystore and its password"),
+            NodeError::ChainStart(_, err) => err.hint(),
+            _ => None,
+        }
+    }
+
+    /// the error with its hint, it's printed when the node fails to start
+    pub fn diagnostic(&self) -> String {
+        match self.hint() {
+            Some(hint) => format!("{}\n  hint: {}", self, hint),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl From<ConfigError> for NodeError {
+    fn from(err: ConfigError) -> Self {
+        NodeError::Config(err)
+    }
+}
+
+impl From<StoreError> for NodeError {
+    fn from(err: StoreError) -> Self {
+        NodeError::Store(err)
+    }
+}
+
+impl From<P2PError> for NodeError {
+    fn from(err: P2PError) -> Self {
+        NodeError::P2P(err)
+    }
+}
+
+impl From<ConsensusError> for NodeError {
+    fn from(err: ConsensusError) -> Self {
+        NodeError::Consensus(err)
+    }
+}
+
+impl From<ChainError> for NodeError {
+    fn from(err: ChainError) -> Self {
+        NodeError::Chain(err)
+    }
+}
+
+impl From<ApiError> for NodeError {
+    fn from(err: ApiError) -> Self {
+        NodeError::Api(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_node_error() {
+        let err: NodeError = ConfigError::MissingGenesis.into();
+        assert_eq!(err.diagnostic(), "the genesis section is missing\n  hint: add the `[genesis]` section to the config");
+        let err = NodeError::ChainStart("shard".to_owned(), Box::new(StoreError::Open("/tmp/store".to_owned(), "locked".to_owned()).into()));
+        assert!(err.to_string().starts_with("failed to start the chain `shard`, failed to open the store /tmp/store"));
+        assert!(err.hint().is_some());
+        assert_eq!(NodeError::Other("unknown".to_owned()).diagnostic(), "unknown");
+        let err: NodeError = ApiError::Bind("127.0.0.1:8080, address in use".to_owned()).into();
+        assert_eq!(err.diagnostic(), "failed to bind the api, 127.0.0.1:8080, address in use\n  hint: check `api_ip` and `api_port`");
+    }
+}
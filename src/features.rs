@@ -0,0 +1,71 @@
+use crate::config::Config;
+use crate::metrics;
+use crate::p2p::protocol::PROTOCOL_VERSION;
+
+/// Feature is a node-level capability, it's advertised in `/status`, the telemetry and
+/// the crash dumps, so the operators can tell which nodes can serve what
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Feature {
+    pub name: String,
+    pub version: u32,
+    pub enabled: bool,
+}
+
+impl Feature {
+    fn new(name: &str, version: u32, enabled: bool) -> Self {
+        Feature { name: name.to_owned(), version, enabled }
+    }
+}
+
+/// the feature set of the node, the unsupported features are listed with version 0, the others
+/// are enabled by the config or the build
+pub fn node_features(config: &Config) -> Vec<Feature> {
+    vec![
+        Feature::new("p2p_protocol", PROTOCOL_VERSION, true),
+        Feature::new("rlp_codec", 1, true),
+        Feature::new("batch_seal_verification", 1, true),
+        Feature::new("address_summary", 1, true),
+        Feature::new("address_tx_index", 1, true),
+        Feature::new("on_demand_mining", 1, true),
+        Feature::new("webhook_subscriptions", 1, true),
+        Feature::new("dev_api", 1, config.dev),
+        Feature::new("metrics_push", 1, config.metrics_push_url.is_some()),
+        Feature::new("dns_seeds", 1, !config.p2p.dns_seeds.is_empty()),
+        Feature::new("light_client", 1, true),
+        Feature::new("snapshots", 1, config.state.snapshot_interval > 0),
+        // the light client bindings are built with the `wasm` feature
+        Feature::new("wasm", 1, cfg!(feature = "wasm")),
+        Feature::new("evm", 0, false),
+    ]
+}
+
+/// export the feature set as gauges, eg: `node_feature_enabled{feature="snapshots",version="0"} 0`
+pub fn report_features(features: &[Feature]) {
+    for feature in features {
+        let name = format!("node_feature_enabled{{feature=\"{}\",version=\"{}\"}}", feature.name, feature.version);
+        metrics::set_gauge(&name, feature.enabled as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_node_features() {
+        let mut config = Config::default();
+        config.dev = true;
+        let features = node_features(&config);
+        let dev_api = features.iter().find(|feature| feature.name == "dev_api").unwrap();
+        assert!(dev_api.enabled);
+        let enabled = |features: &[Feature], name: &str| features.iter().any(|feature| feature.name == name && feature.enabled);
+        assert_eq!(enabled(&features, "wasm"), cfg!(feature = "wasm"));
+        assert!(enabled(&features, "snapshots"));
+        config.state.snapshot_interval = 0;
+        assert!(!enabled(&node_features(&config), "snapshots"));
+
+        let registry_name = "node_feature_enabled{feature=\"dev_api\",version=\"1\"}";
+        report_features(&features);
+        assert_eq!(metrics::REGISTRY.gauge(registry_name), 1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use aes_ctr::Aes128Ctr;
+use aes_ctr::stream_cipher::generic_array::GenericArray;
+use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use cryptocurrency_kit::ethkey::{KeyPair, Secret};
+use scrypt::{scrypt, ScryptParams};
+use sha3::{Digest, Keccak256};
+
+use crate::common::random_uuid;
+
+pub const KEY_FILE_VERSION: u32 = 3;
+pub const CIPHER: &str = "aes-128-ctr";
+pub const KDF: &str = "scrypt";
+// the scrypt cost of geth's standard key files
+pub const STANDARD_SCRYPT_N: u32 = 1 << 18;
+pub const STANDARD_SCRYPT_P: u32 = 1;
+// the cost of geth's `--lightkdf`
+pub const LIGHT_SCRYPT_N: u32 = 1 << 12;
+pub const LIGHT_SCRYPT_P: u32 = 6;
+const SCRYPT_R: u32 = 8;
+const DKLEN: usize = 32;
+
+/// KeyFile is an encrypted key in the web3 secret storage format (version 3), the key files
+/// of geth can be used as they are, eg: `UTC--2019-01-01T00-00-00.000000000Z--<address>`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KeyFile {
+    // the hex address without the `0x` prefix
+    pub address: String,
+    pub crypto: KeyCrypto,
+    pub id: String,
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KeyCrypto {
+    pub cipher: String,
+    pub ciphertext: String,
+    pub cipherparams: CipherParams,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KdfParams {
+    pub dklen: usize,
+    pub n: u32,
+    pub p: u32,
+    pub r: u32,
+    pub salt: String,
+}
+
+impl KeyFile {
+    /// encrypt the key with the password, `n` and `p` are the scrypt cost
+    pub fn encrypt(key_pair: &KeyPair, password: &str, n: u32, p: u32) -> Result<KeyFile, String> {
+        let salt: [u8; 32] = rand::random();
+        let iv: [u8; 16] = rand::random();
+        let kdfparams = KdfParams { dklen: DKLEN, n, p, r: SCRYPT_R, salt: hex::encode(&salt[..]) };
+        let derived = derive_key(password, &kdfparams)?;
+        let mut ciphertext = key_pair.secret().to_vec();
+        apply_cipher(&derived[..16], &iv, &mut ciphertext);
+        Ok(KeyFile {
+            address: hex::encode(key_pair.address().to_vec()),
+            crypto: KeyCrypto {
+                cipher: CIPHER.to_string(),
+                ciphertext: hex::encode(&ciphertext),
+                cipherparams: CipherParams { iv: hex::encode(&iv[..]) },
+                kdf: KDF.to_string(),
+                kdfparams,
+                mac: hex::encode(mac(&derived, &ciphertext)),
+            },
+            id: random_uuid().to_string(),
+            version: KEY_FILE_VERSION,
+        })
+    }
+
+    /// decrypt the key, the wrong password fails on the mac
+    pub fn decrypt(&self, password: &str) -> Result<KeyPair, String> {
+        if self.version != KEY_FILE_VERSION {
+            return Err(format!("unsupported key file version: {}", self.version));
+        }
+        if self.crypto.cipher != CIPHER || self.crypto.kdf != KDF {
+            return Err(format!("unsupported cipher or kdf: {}, {}", self.crypto.cipher, self.crypto.kdf));
+        }
+        let ciphertext = decode_hex(&self.crypto.ciphertext, "ciphertext")?;
+        let iv = decode_hex(&self.crypto.cipherparams.iv, "iv")?;
+        if iv.len() != 16 {
+            return Err(format!("invalid iv length: {}", iv.len()));
+        }
+        let derived = derive_key(password, &self.crypto.kdfparams)?;
+        if hex::encode(mac(&derived, &ciphertext)) != self.crypto.mac.to_lowercase() {
+            return Err("invalid password or the key file is broken".to_string());
+        }
+        let mut secret = ciphertext;
+        apply_cipher(&derived[..16], &iv, &mut secret);
+        let key_pair = Secret::from_str(&hex::encode(&secret)).map_err(|err| format!("{:?}", err))
+            .and_then(|secret| KeyPair::from_secret(secret).map_err(|err| format!("{:?}", err)))?;
+        if hex::encode(key_pair.address().to_vec()) != self.address.trim_start_matches("0x").to_lowercase() {
+            return Err("the key doesn't match the address of the key file".to_string());
+        }
+        Ok(key_pair)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<KeyFile, String> {
+        let buf = fs::read(path.as_ref()).map_err(|err| format!("failed to read the key file {:?}, {}", path.as_ref(), err))?;
+        serde_json::from_slice(&buf).map_err(|err| format!("broken key file {:?}, {}", path.as_ref(), err))
+    }
+
+    /// write the key file, it's readable by the owner only like geth's, an existing file is kept
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let buf = serde_json::to_vec_pretty(self).map_err(|err| err.to_string())?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path.as_ref()).map_err(|err| format!("failed to create the key file {:?}, {}", path.as_ref(), err))?;
+        file.write_all(&buf).and_then(|_| file.sync_all()).map_err(|err| format!("failed to write the key file {:?}, {}", path.as_ref(), err))
+    }
+}
+
+/// UnlockedKey is the validator key decrypted from the key file, it's kept in memory only, the
+/// debug output shows the address, not the secret
+#[derive(Clone)]
+pub struct UnlockedKey(KeyPair);
+
+impl UnlockedKey {
+    pub fn new(key_pair: KeyPair) -> Self {
+        UnlockedKey(key_pair)
+    }
+
+    pub fn key_pair(&self) -> &KeyPair {
+        &self.0
+    }
+}
+
+impl fmt::Debug for UnlockedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UnlockedKey({:?})", self.0.address())
+    }
+}
+
+/// the password of the key file, the trailing newline of the file is ignored
+pub fn read_password_file<P: AsRef<Path>>(path: P) -> Result<String, String> {
+    let password = fs::read_to_string(path.as_ref()).map_err(|err| format!("failed to read the password file {:?}, {}", path.as_ref(), err))?;
+    Ok(password.trim_end_matches(|c| c == '\n' || c == '\r').to_string())
+}
+
+fn derive_key(password: &str, params: &KdfParams) -> Result<Vec<u8>, String> {
+    if params.dklen != DKLEN || !params.n.is_power_of_two() || params.n < 2 {
+        return Err(format!("invalid scrypt params, dklen: {}, n: {}", params.dklen, params.n));
+    }
+    let salt = decode_hex(&params.salt, "salt")?;
+    let scrypt_params = ScryptParams::new(params.n.trailing_zeros() as u8, params.r, params.p)
+        .map_err(|err| format!("invalid scrypt params, {:?}", err))?;
+    let mut derived = vec![0u8; DKLEN];
+    scrypt(password.as_bytes(), &salt, &scrypt_params, &mut derived).map_err(|err| format!("{:?}", err))?;
+    Ok(derived)
+}
+
+fn apply_cipher(key: &[u8], iv: &[u8], data: &mut [u8]) {
+    let mut cipher = Aes128Ctr::new(GenericArray::from_slice(key), GenericArray::from_slice(iv));
+    cipher.apply_keystream(data);
+}
+
+// keccak256 of the second half of the derived key and the ciphertext
+fn mac(derived: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let mut hasher = Keccak256::default();
+    hasher.input(&derived[16..32]);
+    hasher.input(ciphertext);
+    hasher.result().to_vec()
+}
+
+fn decode_hex(value: &str, name: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value.trim_start_matches("0x")).map_err(|err| format!("invalid {}, {:?}", name, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+
+    #[test]
+    fn t_key_file() {
+        let key_pair = Random.generate().unwrap();
+        // the cheap cost keeps the test fast
+        let key_file = KeyFile::encrypt(&key_pair, "secret", 1 << 4, 1).unwrap();
+        assert_eq!(key_file.crypto.kdfparams.r, SCRYPT_R);
+        assert_eq!(key_file.decrypt("secret").unwrap().address(), key_pair.address());
+        assert!(key_file.decrypt("wrong").is_err());
+
+        // the json round trip keeps the geth field names
+        let json = serde_json::to_string(&key_file).unwrap();
+        assert!(json.contains("\"cipherparams\"") && json.contains("\"kdfparams\""));
+        let decoded: KeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.decrypt("secret").unwrap().address(), key_pair.address());
+
+        let mut broken = key_file.clone();
+        broken.crypto.kdfparams.n = 3;
+        assert!(broken.decrypt("secret").is_err());
+    }
+
+    // the key file of geth's keystore tests, its password is `foobar`
+    #[test]
+    fn t_geth_key_file() {
+        let json = r#"{"address":"7ef5a6135f1fd6a02593eedc869c6d41d934aef8","crypto":{"cipher":"aes-128-ctr","ciphertext":"1d0839166e7a15b9c1333fc865d69858b22df26815ccf601b28219b6192974e1","cipherparams":{"iv":"8df6caa7ff1b00c4e871f002cb7921ed"},"kdf":"scrypt","kdfparams":{"dklen":32,"n":8,"p":16,"r":8,"salt":"e5e6ef3f4ea695f496b643ebd3f75c0aa58ef4070e90c80c5d3fb0241bf1595c"},"mac":"6d16dfde774845e4585357f24bce530528bc69f4f84e1e22880d34fa45c273e5"},"id":"950077c7-71e3-4c44-a4a1-143919141ed4","version":3}"#;
+        let key_file: KeyFile = serde_json::from_str(json).unwrap();
+        let key_pair = key_file.decrypt("foobar").unwrap();
+        assert_eq!(hex::encode(key_pair.secret().to_vec()), "976f9f7772781ff6d1c93941129d417c49a209c674056a3cf5e27e225ee55fa8");
+        assert_eq!(hex::encode(key_pair.address().to_vec()), "7ef5a6135f1fd6a02593eedc869c6d41d934aef8");
+        assert!(key_file.decrypt("foobaz").is_err());
+    }
+
+    #[test]
+    fn t_save_key_file() {
+        use crate::common::random_dir;
+
+        let key_pair = Random.generate().unwrap();
+        let key_file = KeyFile::encrypt(&key_pair, "secret", 1 << 4, 1).unwrap();
+        let dir = *random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = format!("{}/key.json", dir);
+        key_file.save(&path).unwrap();
+        assert_eq!(KeyFile::load(&path).unwrap(), key_file);
+        // the existing key file is never overwritten
+        assert!(key_file.save(&path).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(!format!("{:?}", UnlockedKey::new(key_pair.clone())).contains(&hex::encode(key_pair.secret().to_vec())));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -30,6 +30,7 @@ extern crate env_logger;
 #[macro_use]
 extern crate failure;
 
+#[macro_use]
 pub mod common;
 pub mod util;
 pub mod consensus;
@@ -40,6 +41,8 @@ pub mod protocol;
 pub mod p2p;
 pub mod error;
 pub mod pprof;
+pub mod keystore;
+pub mod signer;
 #[macro_use]
 pub mod subscriber;
 pub mod minner;
@@ -47,4 +50,7 @@ pub mod cmd;
 pub mod config;
 pub mod logger;
 pub mod mocks;
-pub mod api;
\ No newline at end of file
+pub mod api;
+pub mod metrics;
+pub mod features;
+pub mod light;
\ No newline at end of file
@@ -0,0 +1,95 @@
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::{Address, Signature};
+
+use crate::common::merkle_tree_root;
+use crate::types::block::Header;
+use crate::types::transaction::Transaction;
+use crate::types::votes::{commit_quorum, verify_block_seals, Votes};
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// the inclusion proof of a transaction, the merkle tree doesn't expose its branches, so the
+/// proof carries the transactions of the block and the root is recomputed from them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionProof {
+    pub header: Header,
+    pub index: usize,
+    pub transactions: Vec<Transaction>,
+}
+
+impl TransactionProof {
+    pub fn new(header: Header, transactions: Vec<Transaction>, tx_hash: &Hash) -> Option<Self> {
+        let index = transactions.iter().position(|transaction| transaction.hash() == *tx_hash)?;
+        Some(TransactionProof { header, index, transactions })
+    }
+}
+
+/// verify the headers which follow the trusted header, every header must link to its parent
+/// and carry the commit seals of more than 2/3 validators
+pub fn verify_header_chain(trusted: &Header, headers: &[Header], validators: &[Address]) -> Result<(), String> {
+    let mut parent = trusted;
+    for header in headers {
+        if header.height != parent.height + 1 {
+            return Err(format!("height is not continuous, expect: {}, got: {}", parent.height + 1, header.height));
+        }
+        if header.prev_hash != parent.block_hash() {
+            return Err(format!("parent hash mismatch, height: {}", header.height));
+        }
+        verify_block_seals(header, validators).map_err(|err| format!("height: {}, {}", header.height, err))?;
+        parent = header;
+    }
+    Ok(())
+}
+
+/// verify the commit certificate of a block hash, the seals must come from more than 2/3 validators
+pub fn verify_commit_certificate(block_hash: &Hash, seals: &[Signature], validators: &[Address]) -> Result<(), String> {
+    let quorum = commit_quorum(validators.len());
+    if seals.len() < quorum {
+        return Err(format!("lack of votes, expect: {}, got: {}", quorum, seals.len()));
+    }
+    let votes = Votes::new(seals.to_vec());
+    if !votes.verify_commit_seals(block_hash, |address| validators.contains(&address)) {
+        return Err("invalid commit seals".to_string());
+    }
+    Ok(())
+}
+
+/// verify that the transaction is included in the block of the proof, the header itself
+/// should be verified by `verify_header_chain` first
+pub fn verify_transaction_proof(proof: &TransactionProof, tx_hash: &Hash) -> Result<(), String> {
+    let transaction = proof.transactions.get(proof.index).ok_or("proof index out of range".to_string())?;
+    if transaction.hash() != *tx_hash {
+        return Err(format!("transaction mismatch, expect: {:?}, got: {:?}", tx_hash, transaction.hash()));
+    }
+    let root = merkle_tree_root(proof.transactions.clone());
+    if root != proof.header.tx_hash {
+        return Err(format!("transactions root mismatch, expect: {:?}, got: {:?}", proof.header.tx_hash, root));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::types::votes::encrypt_commit_bytes;
+
+    #[test]
+    fn t_verify_header_chain() {
+        let key_pairs: Vec<_> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let validators: Vec<Address> = key_pairs.iter().map(|key_pair| key_pair.address()).collect();
+        let trusted = Header::new_mock(EMPTY_HASH, validators[0], EMPTY_HASH, 0, 0, None);
+        let mut header = Header::new_mock(trusted.block_hash(), validators[1], EMPTY_HASH, 1, 1, None);
+        let seals: Vec<Signature> = key_pairs.iter().map(|key_pair| encrypt_commit_bytes(&header.block_hash(), key_pair.secret())).collect();
+
+        assert!(verify_commit_certificate(&header.block_hash(), &seals, &validators).is_ok());
+        assert!(verify_commit_certificate(&header.block_hash(), &seals[..2], &validators).is_err());
+
+        assert!(verify_header_chain(&trusted, &[header.clone()], &validators).is_err());
+        header.votes = Some(Votes::new(seals[..3].to_vec()));
+        assert!(verify_header_chain(&trusted, &[header.clone()], &validators).is_ok());
+        assert!(verify_header_chain(&header, &[header.clone()], &validators).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use wasm_bindgen::prelude::*;
+
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::ethkey::{Address, Signature};
+
+use crate::types::block::Header;
+use super::TransactionProof;
+
+// the bindings take the json of the light api responses, so the browser can pass them through
+
+#[wasm_bindgen(js_name = verifyHeaderChain)]
+pub fn verify_header_chain(trusted: &str, headers: &str, validators: &str) -> Result<(), JsValue> {
+    let trusted: Header = from_json(trusted)?;
+    let headers: Vec<Header> = from_json(headers)?;
+    let validators: Vec<Address> = from_json(validators)?;
+    super::verify_header_chain(&trusted, &headers, &validators).map_err(|err| JsValue::from_str(&err))
+}
+
+#[wasm_bindgen(js_name = verifyCommitCertificate)]
+pub fn verify_commit_certificate(block_hash: &str, seals: &str, validators: &str) -> Result<(), JsValue> {
+    let block_hash: Hash = from_json(block_hash)?;
+    let seals: Vec<Signature> = from_json(seals)?;
+    let validators: Vec<Address> = from_json(validators)?;
+    super::verify_commit_certificate(&block_hash, &seals, &validators).map_err(|err| JsValue::from_str(&err))
+}
+
+#[wasm_bindgen(js_name = verifyTransactionProof)]
+pub fn verify_transaction_proof(proof: &str, tx_hash: &str) -> Result<(), JsValue> {
+    let proof: TransactionProof = from_json(proof)?;
+    let tx_hash: Hash = from_json(tx_hash)?;
+    super::verify_transaction_proof(&proof, &tx_hash).map_err(|err| JsValue::from_str(&err))
+}
+
+fn from_json<'a, T: serde::Deserialize<'a>>(s: &'a str) -> Result<T, JsValue> {
+    serde_json::from_str(s).map_err(|err| JsValue::from_str(&err.to_string()))
+}
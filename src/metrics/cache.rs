@@ -0,0 +1,122 @@
+use lru_time_cache::LruCache;
+
+use super::REGISTRY;
+
+/// the metrics of a named cache, eg: `cache_hit_total{cache="ledger_header"}`, the hit rate is
+/// `cache_hit_total / (cache_hit_total + cache_miss_total)`
+#[derive(Debug, Clone)]
+pub struct CacheMetrics {
+    hit: String,
+    miss: String,
+    eviction: String,
+    size: String,
+}
+
+impl CacheMetrics {
+    pub fn new(cache: &str) -> Self {
+        let labels = format!("{{cache=\"{}\"}}", cache);
+        CacheMetrics {
+            hit: format!("cache_hit_total{}", labels),
+            miss: format!("cache_miss_total{}", labels),
+            eviction: format!("cache_eviction_total{}", labels),
+            size: format!("cache_size{}", labels),
+        }
+    }
+
+    pub fn record(&self, hit: bool) {
+        if hit {
+            REGISTRY.inc_counter(&self.hit, 1);
+        } else {
+            REGISTRY.inc_counter(&self.miss, 1);
+        }
+    }
+
+    pub fn evict(&self, count: u64) {
+        if count > 0 {
+            REGISTRY.inc_counter(&self.eviction, count);
+        }
+    }
+
+    pub fn set_size(&self, size: usize) {
+        REGISTRY.set_gauge(&self.size, size as i64);
+    }
+}
+
+/// MeteredCache is a LruCache which reports its size, hits, misses and evictions, the expired
+/// entries dropped by an insert are counted as evictions too
+#[derive(Clone)]
+pub struct MeteredCache<K, V> {
+    cache: LruCache<K, V>,
+    metrics: CacheMetrics,
+}
+
+impl<K: Ord + Clone, V> MeteredCache<K, V> {
+    pub fn new(name: &str, cache: LruCache<K, V>) -> Self {
+        let metrics = CacheMetrics::new(name);
+        metrics.set_size(cache.len());
+        MeteredCache { cache, metrics }
+    }
+
+    pub fn with_capacity(name: &str, capacity: usize) -> Self {
+        Self::new(name, LruCache::with_capacity(capacity))
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let value = self.cache.get(key);
+        self.metrics.record(value.is_some());
+        value
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let value = self.cache.get_mut(key);
+        self.metrics.record(value.is_some());
+        value
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let before = self.cache.len();
+        let old = self.cache.insert(key, value);
+        let after = self.cache.len();
+        let expected = if old.is_some() { before } else { before + 1 };
+        self.metrics.evict(expected.saturating_sub(after) as u64);
+        self.metrics.set_size(after);
+        old
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.metrics.set_size(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_metered_cache() {
+        let mut cache: MeteredCache<u64, u64> = MeteredCache::with_capacity("t_metered_cache", 2);
+        assert!(cache.get(&1).is_none());
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get(&1), Some(&1));
+        // the least recently used key 2 is evicted
+        cache.insert(3, 3);
+        assert!(cache.get(&2).is_none());
+        cache.insert(3, 30);
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(REGISTRY.counter("cache_hit_total{cache=\"t_metered_cache\"}"), 1);
+        assert_eq!(REGISTRY.counter("cache_miss_total{cache=\"t_metered_cache\"}"), 2);
+        assert_eq!(REGISTRY.counter("cache_eviction_total{cache=\"t_metered_cache\"}"), 1);
+        assert_eq!(REGISTRY.gauge("cache_size{cache=\"t_metered_cache\"}"), 2);
+    }
+}
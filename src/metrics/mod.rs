@@ -0,0 +1,277 @@
+pub mod cache;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::thread::{spawn, sleep, JoinHandle};
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use crate::common::http_post;
+
+pub const PUSH_TIMEOUT: Duration = Duration::from_secs(3);
+pub const MAX_PUSH_BACKOFF: u32 = 16;
+// the rolling window size of histogram samples
+pub const HISTOGRAM_WINDOW: usize = 1024;
+// histogram buckets in milliseconds
+pub const HISTOGRAM_BUCKETS: [f64; 12] = [5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0];
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+}
+
+/// Registry holds all metrics of the node, the metric name may carry prometheus labels,
+/// eg: `p2p_bytes_total{direction="in",category="consensus"}`
+pub struct Registry {
+    counters: RwLock<BTreeMap<String, u64>>,
+    gauges: RwLock<BTreeMap<String, i64>>,
+    histograms: RwLock<BTreeMap<String, Histogram>>,
+}
+
+/// Histogram keeps the latest `HISTOGRAM_WINDOW` samples in memory
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    samples: VecDeque<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramSummary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: f64) {
+        if self.samples.len() >= HISTOGRAM_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    pub fn summary(&self) -> Option<HistogramSummary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.samples.iter().cloned().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        Some(HistogramSummary {
+            count: sorted.len(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+
+    fn encode(&self, output: &mut String, name: &str) {
+        let (base_name, labels) = split_labels(name);
+        let with_le = |le: &str| match labels {
+            Some(labels) => format!("{{{},le=\"{}\"}}", labels, le),
+            None => format!("{{le=\"{}\"}}", le),
+        };
+        for bucket in HISTOGRAM_BUCKETS.iter() {
+            let count = self.samples.iter().filter(|sample| *sample <= bucket).count();
+            output.push_str(&format!("{}_bucket{} {}\n", base_name, with_le(&bucket.to_string()), count));
+        }
+        let labels = labels.map(|labels| format!("{{{}}}", labels)).unwrap_or_default();
+        output.push_str(&format!("{}_bucket{} {}\n", base_name, with_le("+Inf"), self.samples.len()));
+        output.push_str(&format!("{}_sum{} {}\n", base_name, labels, self.samples.iter().sum::<f64>()));
+        output.push_str(&format!("{}_count{} {}\n", base_name, labels, self.samples.len()));
+    }
+}
+
+// split `name{labels}` into `name` and `labels`
+fn split_labels(name: &str) -> (&str, Option<&str>) {
+    match name.find('{') {
+        Some(idx) => (&name[..idx], Some(name[idx + 1..].trim_end_matches('}'))),
+        None => (name, None),
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            counters: RwLock::new(BTreeMap::new()),
+            gauges: RwLock::new(BTreeMap::new()),
+            histograms: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc_counter(&self, name: &str, delta: u64) {
+        let mut counters = self.counters.write();
+        *counters.entry(name.to_owned()).or_insert(0) += delta;
+    }
+
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters.read().get(name).cloned().unwrap_or(0)
+    }
+
+    pub fn set_gauge(&self, name: &str, value: i64) {
+        self.gauges.write().insert(name.to_owned(), value);
+    }
+
+    pub fn add_gauge(&self, name: &str, delta: i64) {
+        let mut gauges = self.gauges.write();
+        *gauges.entry(name.to_owned()).or_insert(0) += delta;
+    }
+
+    pub fn gauge(&self, name: &str) -> i64 {
+        self.gauges.read().get(name).cloned().unwrap_or(0)
+    }
+
+    /// the gauges of the base names with all their labels
+    pub fn gauges_of(&self, base_names: &[&str]) -> BTreeMap<String, i64> {
+        select_family(&self.gauges.read(), base_names)
+    }
+
+    /// the counters of the base names with all their labels
+    pub fn counters_of(&self, base_names: &[&str]) -> BTreeMap<String, u64> {
+        select_family(&self.counters.read(), base_names)
+    }
+
+    pub fn observe(&self, name: &str, value: f64) {
+        let mut histograms = self.histograms.write();
+        histograms.entry(name.to_owned()).or_insert_with(Histogram::default).observe(value);
+    }
+
+    pub fn histogram_summary(&self, name: &str) -> Option<HistogramSummary> {
+        self.histograms.read().get(name).and_then(|histogram| histogram.summary())
+    }
+
+    /// encode all metrics with prometheus text format
+    pub fn gather(&self) -> String {
+        let mut output = String::new();
+        encode_family(&mut output, "counter", &self.counters.read());
+        encode_family(&mut output, "gauge", &self.gauges.read());
+        let mut last_name = "";
+        let histograms = self.histograms.read();
+        for (name, histogram) in histograms.iter() {
+            let (base_name, _) = split_labels(name);
+            if base_name != last_name {
+                output.push_str(&format!("# TYPE {} histogram\n", base_name));
+                last_name = base_name;
+            }
+            histogram.encode(&mut output, name);
+        }
+        output
+    }
+}
+
+fn select_family<V: Clone>(metrics: &BTreeMap<String, V>, base_names: &[&str]) -> BTreeMap<String, V> {
+    metrics.iter()
+        .filter(|(name, _)| base_names.contains(&split_labels(name).0))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+fn encode_family<V: ::std::fmt::Display>(output: &mut String, kind: &str, metrics: &BTreeMap<String, V>) {
+    let mut last_name = "";
+    for (name, value) in metrics.iter() {
+        let (base_name, _) = split_labels(name);
+        if base_name != last_name {
+            output.push_str(&format!("# TYPE {} {}\n", base_name, kind));
+            last_name = base_name;
+        }
+        output.push_str(&format!("{} {}\n", name, value));
+    }
+}
+
+pub fn inc_counter(name: &str) {
+    REGISTRY.inc_counter(name, 1);
+}
+
+pub fn inc_counter_by(name: &str, delta: u64) {
+    REGISTRY.inc_counter(name, delta);
+}
+
+pub fn set_gauge(name: &str, value: i64) {
+    REGISTRY.set_gauge(name, value);
+}
+
+pub fn observe(name: &str, value: f64) {
+    REGISTRY.observe(name, value);
+}
+
+pub fn gather() -> String {
+    REGISTRY.gather()
+}
+
+/// Push the registry to a prometheus push gateway periodically, it's a fallback for the
+/// environments that can't scrape the `/metrics` endpoint. On failure, the push interval
+/// backs off exponentially up to `MAX_PUSH_BACKOFF` times.
+pub fn spawn_push_gateway(url: String, interval: Duration) -> JoinHandle<()> {
+    let url = format!("{}/metrics/job/consensus", url.trim_end_matches('/'));
+    info!("Start metrics push gateway, url: {}, interval: {:?}", url, interval);
+    spawn(move || {
+        let mut backoff: u32 = 1;
+        loop {
+            sleep(interval * backoff);
+            match http_post(&url, "text/plain; version=0.0.4", &gather(), PUSH_TIMEOUT) {
+                Ok(_) => {
+                    backoff = 1;
+                    inc_counter("metrics_push_success_total");
+                    set_gauge("metrics_push_up", 1);
+                    set_gauge("metrics_push_last_success_timestamp", chrono::Local::now().timestamp());
+                }
+                Err(err) => {
+                    backoff = ::std::cmp::min(backoff * 2, MAX_PUSH_BACKOFF);
+                    inc_counter("metrics_push_failure_total");
+                    set_gauge("metrics_push_up", 0);
+                    warn!("Failed to push metrics, url: {}, next backoff: {}, err: {}", url, backoff, err);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_registry() {
+        let registry = Registry::new();
+        registry.inc_counter("p2p_message_total{code=\"Block\"}", 2);
+        registry.inc_counter("p2p_message_total{code=\"Consensus\"}", 1);
+        registry.inc_counter("p2p_message_total{code=\"Block\"}", 1);
+        registry.set_gauge("chain_height", 10);
+        assert_eq!(registry.counter("p2p_message_total{code=\"Block\"}"), 3);
+        assert_eq!(registry.gauge("chain_height"), 10);
+
+        let output = registry.gather();
+        assert_eq!(output.matches("# TYPE p2p_message_total counter").count(), 1);
+        assert!(output.contains("p2p_message_total{code=\"Block\"} 3\n"));
+        assert!(output.contains("# TYPE chain_height gauge\nchain_height 10\n"));
+        assert_eq!(registry.counters_of(&["p2p_message_total"]).len(), 2);
+        assert_eq!(registry.gauges_of(&["chain_height", "chain"]).len(), 1);
+    }
+
+    #[test]
+    fn t_histogram() {
+        let registry = Registry::new();
+        assert_eq!(registry.histogram_summary("consensus_phase_duration_ms{phase=\"commit\"}"), None);
+        (1..101).for_each(|value| registry.observe("consensus_phase_duration_ms{phase=\"commit\"}", value as f64));
+        let summary = registry.histogram_summary("consensus_phase_duration_ms{phase=\"commit\"}").unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.min, 1.0);
+        assert_eq!(summary.max, 100.0);
+        assert_eq!(summary.p50, 50.0);
+
+        let output = registry.gather();
+        assert!(output.contains("# TYPE consensus_phase_duration_ms histogram\n"));
+        assert!(output.contains("consensus_phase_duration_ms_bucket{phase=\"commit\",le=\"10\"} 10\n"));
+        assert!(output.contains("consensus_phase_duration_ms_bucket{phase=\"commit\",le=\"+Inf\"} 100\n"));
+        assert!(output.contains("consensus_phase_duration_ms_count{phase=\"commit\"} 100\n"));
+
+        let mut histogram = Histogram::default();
+        (0..HISTOGRAM_WINDOW + 10).for_each(|value| histogram.observe(value as f64));
+        assert_eq!(histogram.summary().unwrap().min, 10.0);
+    }
+}
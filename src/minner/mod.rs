@@ -1,4 +1,6 @@
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crossbeam::scope;
 use ::actix::prelude::*;
@@ -6,7 +8,7 @@ use actix_broker::{BrokerSubscribe, BrokerIssue};
 use parking_lot::RwLock;
 use crossbeam::{Sender, Receiver, channel::bounded};
 use rand::random;
-use cryptocurrency_kit::ethkey::{Address, KeyPair};
+use cryptocurrency_kit::ethkey::Address;
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::crypto::Hash;
 use cryptocurrency_kit::crypto::CryptoHash;
@@ -16,18 +18,23 @@ use futures::*;
 use futures::sync::oneshot;
 
 use crate::{
-    subscriber::events::ChainEvent,
+    error::ChainError,
+    subscriber::events::{ChainEvent, TxPoolEvent},
     core::chain::Chain,
+    core::executor::block_bloom,
+    core::gas::{block_gas, next_gas_limit},
     core::tx_pool::{TxPool, SafeTxPool},
     consensus::consensus::{Engine, SafeEngine},
-    types::{Height, Timestamp},
+    consensus::error::EngineError,
+    signer::SafeSigner,
+    types::{Gas, Height, Timestamp},
     types::block::{Block, Header},
     types::transaction::{Transaction, merkle_root_transactions},
 };
 
 pub struct Minner {
     minter: Address,
-    key_pair: KeyPair,
+    signer: SafeSigner,
     chain: Arc<Chain>,
     txpool: Arc<RwLock<SafeTxPool>>,
     engine: Box<Engine>,
@@ -35,6 +42,97 @@ pub struct Minner {
     seal_rx: Receiver<()>,
     mint_height: Height,
     worker: tokio_threadpool::ThreadPool,
+    mode: MiningMode,
+    paused: bool,
+    // an explicit trigger of the on-demand mode
+    triggered: bool,
+    // a block is being sealed
+    mining: bool,
+}
+
+// how often the idle minner checks the tx pool
+pub const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiningMode {
+    // propose the next block as soon as the last one is committed
+    Continuous,
+    // only propose a block when the tx pool is not empty or it's triggered
+    OnDemand,
+}
+
+impl Default for MiningMode {
+    fn default() -> Self {
+        MiningMode::Continuous
+    }
+}
+
+impl FromStr for MiningMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "continuous" => Ok(MiningMode::Continuous),
+            "on_demand" => Ok(MiningMode::OnDemand),
+            other => Err(format!("unknown mining mode: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MinnerStatus {
+    pub mode: MiningMode,
+    pub paused: bool,
+    pub mining: bool,
+    pub mint_height: Height,
+}
+
+/// stop producing blocks, the block which is being sealed is aborted
+pub struct Pause;
+
+/// continue producing blocks
+pub struct Resume;
+
+pub struct SetMiningMode(pub MiningMode);
+
+/// propose a block now in the on-demand mode, even if the tx pool is empty
+pub struct TriggerMine;
+
+#[derive(Clone)]
+pub struct GetMinnerStatus;
+
+impl Message for Pause {
+    type Result = MinnerStatus;
+}
+
+impl Message for Resume {
+    type Result = MinnerStatus;
+}
+
+impl Message for SetMiningMode {
+    type Result = MinnerStatus;
+}
+
+impl Message for TriggerMine {
+    type Result = MinnerStatus;
+}
+
+impl Message for GetMinnerStatus {
+    type Result = MinnerStatus;
+}
+
+// the max transactions packed into a dev block
+pub const MAX_DEV_BLOCK_TRANSACTIONS: usize = 1_000;
+
+/// produce blocks immediately without consensus, only for dev mode
+pub struct MineBlocks {
+    pub blocks: u64,
+    pub interval: Duration,
+}
+
+impl Message for MineBlocks {
+    type Result = Result<Height, String>;
 }
 
 impl Actor for Minner {
@@ -44,7 +142,8 @@ impl Actor for Minner {
         self.subscribe_async::<ChainEvent>(ctx);
         info!("Start minner actor");
         self.chain.post_event(ChainEvent::SyncBlock(self.chain.get_last_height() + 1));
-        self.mine(self.seal_rx.clone());
+        self.try_mine();
+        ctx.run_interval(IDLE_CHECK_INTERVAL, |act, _| act.try_mine());
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -57,31 +156,100 @@ impl Handler<ChainEvent> for Minner {
     type Result = ();
     fn handle(&mut self, msg: ChainEvent, _ctx: &mut Self::Context) -> Self::Result {
         match msg {
-            ChainEvent::NewHeader(last_header) => {
-                debug!("Receive a new header event notify, hash:{:?}, height: {:?}", last_header.block_hash(), last_header.height);
-                if last_header.height >= self.mint_height {
-                    // stop current consensus
-                    self.seal_tx.send(()).unwrap();
-                    let seal = self.seal_rx.clone();
-                    self.mine(seal);
-                }
+            ChainEvent::Committed { block, .. } => {
+                // the committed transactions leave the pool, the blocks of every proposer and
+                // the synced ones publish `TxPoolEvent::Included`
+                let tx_hashes: Vec<&Hash> = block.transactions().iter().filter_map(|tx| tx.get_hash()).collect();
+                self.txpool.write().include_txs(block.height(), tx_hashes);
+                self.on_committed(block.header())
             }
             _ => {}
         }
     }
 }
 
+// the on-demand minner proposes as soon as a transaction arrives, not on the next idle check
+impl Handler<TxPoolEvent> for Minner {
+    type Result = ();
+    fn handle(&mut self, msg: TxPoolEvent, _ctx: &mut Self::Context) -> Self::Result {
+        if let TxPoolEvent::Added(_) = msg {
+            self.try_mine();
+        }
+    }
+}
+
+impl Handler<MineBlocks> for Minner {
+    type Result = ResponseFuture<Height, String>;
+    fn handle(&mut self, msg: MineBlocks, ctx: &mut Self::Context) -> Self::Result {
+        info!("Mine {} blocks on demand, interval: {:?}", msg.blocks, msg.interval);
+        let (tx, rx) = oneshot::channel();
+        self.mine_blocks(msg.blocks, msg.interval, tx, ctx);
+        Box::new(rx.then(|result| result.unwrap_or_else(|_| Err("the minner is stopped".to_string()))))
+    }
+}
+
+impl Handler<Pause> for Minner {
+    type Result = MessageResult<Pause>;
+    fn handle(&mut self, _: Pause, _ctx: &mut Self::Context) -> Self::Result {
+        info!("Pause minner, height: {}", self.mint_height);
+        self.paused = true;
+        if self.mining {
+            // the abort signal may be pending already
+            let _ = self.seal_tx.try_send(());
+            self.mining = false;
+        }
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<Resume> for Minner {
+    type Result = MessageResult<Resume>;
+    fn handle(&mut self, _: Resume, _ctx: &mut Self::Context) -> Self::Result {
+        info!("Resume minner");
+        self.paused = false;
+        self.try_mine();
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<SetMiningMode> for Minner {
+    type Result = MessageResult<SetMiningMode>;
+    fn handle(&mut self, msg: SetMiningMode, _ctx: &mut Self::Context) -> Self::Result {
+        info!("Switch mining mode, {:?} => {:?}", self.mode, msg.0);
+        self.mode = msg.0;
+        self.try_mine();
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<TriggerMine> for Minner {
+    type Result = MessageResult<TriggerMine>;
+    fn handle(&mut self, _: TriggerMine, _ctx: &mut Self::Context) -> Self::Result {
+        self.triggered = true;
+        self.try_mine();
+        MessageResult(self.status())
+    }
+}
+
+impl Handler<GetMinnerStatus> for Minner {
+    type Result = MessageResult<GetMinnerStatus>;
+    fn handle(&mut self, _: GetMinnerStatus, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.status())
+    }
+}
+
 impl Minner {
     pub fn new(minter: Address,
-               key_pair: KeyPair,
+               signer: SafeSigner,
                chain: Arc<Chain>,
                txpool: Arc<RwLock<SafeTxPool>>,
                engine: SafeEngine,
                tx: Sender<()>,
-               rx: Receiver<()>) -> Self {
+               rx: Receiver<()>,
+               mode: MiningMode) -> Self {
         Minner {
             minter,
-            key_pair,
+            signer,
             chain,
             txpool,
             engine,
@@ -89,47 +257,187 @@ impl Minner {
             seal_rx: rx,
             mint_height: 0,
             worker: tokio_threadpool::ThreadPool::new(),
+            mode,
+            paused: false,
+            triggered: false,
+            mining: false,
+        }
+    }
+
+    pub fn status(&self) -> MinnerStatus {
+        MinnerStatus {
+            mode: self.mode,
+            paused: self.paused,
+            mining: self.mining,
+            mint_height: self.mint_height,
+        }
+    }
+
+    fn should_mine(&self) -> bool {
+        if self.mining || self.paused || self.chain.is_halted() {
+            return false;
+        }
+        match self.mode {
+            MiningMode::Continuous => true,
+            MiningMode::OnDemand => self.triggered || self.txpool.read().len() > 0,
+        }
+    }
+
+    fn try_mine(&mut self) {
+        if self.should_mine() {
+            let seal = self.seal_rx.clone();
+            self.mine(seal);
         }
     }
 
     fn mine(&mut self, abort: Receiver<()>) {
         debug!("Ready to mine next block");
-        let mut block = self.packet_next_block();
+        // the signals of the aborted seals are drained, they'd abort the new seal at once
+        while abort.try_recv().is_ok() {}
+        let mut block = match self.packet_next_block() {
+            Ok(block) => block,
+            Err(err) => {
+                error!("Failed to pack the next block, err: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = self.engine.prepare(block.mut_header()) {
+            error!("Failed to prepare the next block, height: {}, err: {}", block.height(), err);
+            return;
+        }
         self.mint_height = block.height();
+        self.mining = true;
+        self.triggered = false;
         match self.engine.seal(&mut block, abort) {
             Ok(_) => {}
+            // the round has changed, the block is packed again on the next check
+            Err(EngineError::Interrupt) => {
+                self.mining = false;
+                debug!("The sealing block is superseded, height: {}", self.mint_height);
+            }
             Err(err) => {
+                self.mining = false;
                 error!("Failed to seal consensus, err: {:?}", err);
             }
         }
     }
 
-    fn packet_next_block(&self) -> Block {
-        let (next_time, pre_header) = self.next_block();
-        let coinbase = self.coinbase_transaction();
+    fn on_committed(&mut self, last_header: &Header) {
+        debug!("Receive a new header event notify, hash:{:?}, height: {:?}", last_header.block_hash(), last_header.height);
+        if self.sealed_by_self(last_header) {
+            if let Err(err) = self.engine.finalize(last_header) {
+                warn!("The engine failed to finalize the sealed block, height: {}, err: {}", last_header.height, err);
+            }
+        }
+        if let Err(err) = self.engine.on_new_chain_head(last_header) {
+            warn!("The engine failed to handle the new head, height: {}, err: {:?}", last_header.height, err);
+        }
+        if last_header.height >= self.mint_height {
+            // stop current consensus, the channel holds one signal, a pending one aborts
+            // the seal already, so the actor never blocks on a full channel
+            if self.mining {
+                let _ = self.seal_tx.try_send(());
+            }
+            self.mining = false;
+            self.try_mine();
+        }
+    }
+
+    // mine the dev blocks one by one, the next one is mined `interval` later, so the actor keeps
+    // handling the other messages between them
+    fn mine_blocks(&mut self, blocks: u64, interval: Duration, done: oneshot::Sender<Result<Height, String>>, ctx: &mut Context<Self>) {
+        if blocks > 0 {
+            if let Err(err) = self.mine_dev_block() {
+                let _ = done.send(Err(err));
+                return;
+            }
+        }
+        if blocks <= 1 {
+            let _ = done.send(Ok(self.chain.get_last_height()));
+            return;
+        }
+        ctx.run_later(interval, move |act, ctx| act.mine_blocks(blocks - 1, interval, done, ctx));
+    }
+
+    fn mine_dev_block(&mut self) -> Result<(), String> {
+        let transactions: Vec<Transaction> = {
+            let txpool = self.txpool.read();
+            txpool.get_n_tx(MAX_DEV_BLOCK_TRANSACTIONS as u64).into_iter().take(MAX_DEV_BLOCK_TRANSACTIONS).cloned().collect()
+        };
+        let block = self.packet_block(transactions)?;
+        match self.chain.insert_block(&block) {
+            Ok(_) | Err(ChainError::Exists(_)) => {}
+            Err(err) => return Err(format!("{}", err)),
+        }
+        let tx_hashes: Vec<&Hash> = block.transactions().iter().filter_map(|tx| tx.get_hash()).collect();
+        self.txpool.write().include_txs(block.height(), tx_hashes);
+        Ok(())
+    }
+
+    // the committed block is the one this minner is sealing
+    fn sealed_by_self(&self, header: &Header) -> bool {
+        self.mining && header.height == self.mint_height && header.proposer == self.minter
+    }
+
+    fn packet_next_block(&self) -> Result<Block, String> {
 //        let mut mock_transactions = generate_batch_transactions(self.key_pair.secret(), self.minter, self.chain.config.chain_id, 200);
 //        mock_transactions.push(coinbase);
+        self.packet_block(vec![])
+    }
+
+    // the coinbase transaction is always the first one
+    fn packet_block(&self, transactions: Vec<Transaction>) -> Result<Block, String> {
+        let (next_time, pre_header) = self.next_block();
+        let gas_limit = next_gas_limit(pre_header.gas_limit, &self.chain.config.gas);
+        let mut transactions = self.chain.proposal_policy().assemble(pre_header.height + 1, transactions);
+        // the transactions which don't fit the gas limit wait for the next block
+        let mut declared: Gas = 0;
+        let fits = transactions.iter().take_while(|transaction| {
+            declared = declared.saturating_add(transaction.gas());
+            declared <= gas_limit
+        }).count();
+        transactions.truncate(fits);
+        let coinbase = self.coinbase_transaction()?;
+        // the oversized transactions are skipped, the rest stop at the block size limit, the
+        // sealed parent header estimates the room of the new header
+        let limits = self.chain.config.size_limits();
+        let mut size = (rlp::encode(&pre_header).len() + rlp::encode(&coinbase).len()) as u64;
+        transactions.retain(|transaction| limits.check_transaction(transaction).is_ok());
+        let fits = transactions.iter().take_while(|transaction| {
+            size = size.saturating_add(rlp::encode(*transaction).len() as u64);
+            size <= limits.max_block_size
+        }).count();
+        if fits < transactions.len() {
+            debug!("The block is full, packed: {}, waiting: {}", fits, transactions.len() - fits);
+        }
+        transactions.truncate(fits);
+        transactions.insert(0, coinbase);
 
         let pre_hash: Hash = pre_header.block_hash();
-        let tx_hash = merkle_root_transactions(vec![coinbase.clone()]);
+        let tx_hash = merkle_root_transactions(transactions.clone());
         let extra = Vec::from("Coinse base");
 
         let mut header = Header::new_mock(pre_hash, self.minter, tx_hash, pre_header.height + 1, next_time, Some(extra));
+        header.gas_limit = gas_limit;
+        header.gas_used = block_gas(&transactions).1;
+        header.bloom = block_bloom(&transactions);
         header.cache_hash(None);
-        Block::new(header, vec![coinbase])
+        Ok(Block::new(header, transactions))
     }
 
-    fn coinbase_transaction(&self) -> Transaction {
+    fn coinbase_transaction(&self) -> Result<Transaction, String> {
         let nonce: u64 = random();
         let to = self.minter;
-        let amount = random::<u64>();
-        let gas_limit = random::<u64>();
+        // the coinbase transaction mints nothing, the block reward is written with the block indexes
+        let amount = 0;
+        // the coinbase transaction costs no gas
+        let gas_limit = 0;
         let gas_price = 1_u64;
         let payload = Vec::from(chrono::Local::now().to_string());
 
         let mut transaction = Transaction::new(nonce, to, amount, gas_limit, gas_price, payload);
-        transaction.sign(self.chain.config.chain_id, &self.key_pair.secret());
-        transaction
+        transaction.sign_with(self.chain.config.chain_id, self.signer.as_ref())?;
+        Ok(transaction)
     }
 
     fn next_block(&self) -> (u64, Header) {
@@ -150,6 +458,62 @@ impl Minner {
 mod test {
     use super::*;
     use cryptocurrency_kit::ethkey::{Random, Generator};
+    use parking_lot::Mutex;
+    use crate::consensus::error::EngineResult;
+    use crate::core::test_util::{memory_chain, sealed_block};
+    use crate::core::tx_pool::BaseTxPool;
+    use crate::signer::LocalSigner;
+
+    // records the lifecycle calls of the minner, the sealed block is kept for the commit
+    struct RecordEngine {
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        sealed: Arc<Mutex<Option<Block>>>,
+    }
+
+    impl Engine for RecordEngine {
+        fn start(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn author(&self, header: &Header) -> Result<Address, String> {
+            Ok(header.proposer)
+        }
+
+        fn verify_header(&self, _header: &Header, _seal: bool) -> EngineResult {
+            Ok(())
+        }
+
+        fn verify_seal(&self, _header: &Header) -> EngineResult {
+            Ok(())
+        }
+
+        fn prepare(&mut self, header: &mut Header) -> Result<(), String> {
+            self.calls.lock().push("prepare");
+            header.extra = Some(Vec::from("prepared"));
+            Ok(())
+        }
+
+        fn seal(&mut self, new_block: &mut Block, _abort: Receiver<()>) -> EngineResult {
+            assert_eq!(new_block.header().extra, Some(Vec::from("prepared")));
+            self.calls.lock().push("seal");
+            *self.sealed.lock() = Some(new_block.clone());
+            Ok(())
+        }
+
+        fn finalize(&mut self, _header: &Header) -> Result<(), String> {
+            self.calls.lock().push("finalize");
+            Ok(())
+        }
+
+        fn on_new_chain_head(&mut self, _header: &Header) -> EngineResult {
+            self.calls.lock().push("head");
+            Ok(())
+        }
+    }
 
     #[test]
     fn t_basecoin() {
@@ -167,4 +531,42 @@ mod test {
         let tx_hash = merkle_root_transactions(vec![coinbase.clone()]);
         println!("coin base hash: {:?}", tx_hash);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn t_mining_mode() {
+        assert_eq!(MiningMode::from_str("on_demand"), Ok(MiningMode::OnDemand));
+        assert_eq!(MiningMode::from_str("continuous"), Ok(MiningMode::Continuous));
+        assert!(MiningMode::from_str("paused").is_err());
+        assert_eq!(serde_json::to_string(&MiningMode::OnDemand).unwrap(), "\"on_demand\"");
+    }
+
+    #[test]
+    fn t_engine_lifecycle() {
+        let _system = System::new("t_engine_lifecycle");
+        let key_pair = Random.generate().unwrap();
+        let chain = memory_chain(&key_pair);
+        let signer: SafeSigner = Arc::new(LocalSigner::new(key_pair.clone()));
+        let txpool: Arc<RwLock<SafeTxPool>> = Arc::new(RwLock::new(Box::new(BaseTxPool::new())));
+        let (calls, sealed) = (Arc::new(Mutex::new(vec![])), Arc::new(Mutex::new(None)));
+        let engine = Box::new(RecordEngine { calls: calls.clone(), sealed: sealed.clone() });
+        let (tx, rx) = bounded(1);
+        let mut minner = Minner::new(key_pair.address(), signer, chain.clone(), txpool, engine, tx, rx, MiningMode::OnDemand);
+
+        // the header is prepared before the seal, the own block is finalized once it's committed
+        minner.triggered = true;
+        minner.try_mine();
+        assert_eq!(*calls.lock(), vec!["prepare", "seal"]);
+        let block = sealed.lock().take().unwrap();
+        minner.on_committed(block.header());
+        assert_eq!(*calls.lock(), vec!["prepare", "seal", "finalize", "head"]);
+        assert!(!minner.status().mining);
+
+        // the block of another proposer only moves the head
+        calls.lock().clear();
+        minner.triggered = true;
+        minner.try_mine();
+        let other = sealed_block(chain.get_last_hash(), 1, &Random.generate().unwrap());
+        minner.on_committed(other.header());
+        assert_eq!(*calls.lock(), vec!["prepare", "seal", "head"]);
+    }
+}
@@ -0,0 +1,259 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::{hash, Hash};
+use cryptocurrency_kit::ethkey::{Address, KeyPair};
+use cryptocurrency_kit::storage::values::StorageValue;
+use futures::Future;
+use tokio::timer::Delay;
+
+use crate::{
+    consensus::backend::{Backend, ImplBackend},
+    consensus::error::{EngineError, EngineResult},
+    consensus::types::{CommitCertificate, PrePrepare, Proposal, Subject},
+    consensus::validator::{fn_selector, ImplValidatorSet, ValidatorSet},
+    protocol::{GossipMessage, GossipMessages, MessageType},
+    types::block::{Block, Header},
+    types::votes::encrypt_commit_bytes,
+    types::Height,
+};
+
+/// the faults injected into a node, eg: `equivocate,withhold_commits,delay=500`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ByzantineBehavior {
+    // send a conflicting copy of every preprepare, prepare and commit message
+    pub equivocate: bool,
+    // never send the commit messages
+    pub withhold_commits: bool,
+    // propose the blocks with a broken transactions root
+    pub invalid_proposal: bool,
+    // delay all the outbound messages
+    pub delay: Option<Duration>,
+}
+
+impl ByzantineBehavior {
+    pub fn is_honest(&self) -> bool {
+        *self == ByzantineBehavior::default()
+    }
+}
+
+impl FromStr for ByzantineBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut behavior = ByzantineBehavior::default();
+        for flag in s.split(',').map(|flag| flag.trim()).filter(|flag| !flag.is_empty()) {
+            match flag {
+                "equivocate" => behavior.equivocate = true,
+                "withhold_commits" => behavior.withhold_commits = true,
+                "invalid_proposal" => behavior.invalid_proposal = true,
+                flag if flag.starts_with("delay=") => {
+                    let millis: u64 = flag["delay=".len()..].parse().map_err(|_| format!("invalid delay: {}", flag))?;
+                    behavior.delay = Some(Duration::from_millis(millis));
+                }
+                flag => return Err(format!("unknown byzantine flag: {}", flag)),
+            }
+        }
+        Ok(behavior)
+    }
+}
+
+/// ByzantineCore wraps the backend of a core, the consensus logic is untouched and only the
+/// outbound messages are tampered, so the fault tolerance of the honest nodes can be tested,
+/// eg: the faulty nodes of the simulation
+pub struct ByzantineCore<B = ImplBackend> {
+    inner: B,
+    key_pair: KeyPair,
+    behavior: ByzantineBehavior,
+}
+
+impl<B> ByzantineCore<B> {
+    pub fn new(inner: B, key_pair: KeyPair, behavior: ByzantineBehavior) -> Self {
+        ByzantineCore { inner, key_pair, behavior }
+    }
+
+    /// the messages which are really sent for the message of the core
+    pub fn tamper(&self, msg: GossipMessage) -> Vec<GossipMessage> {
+        if self.behavior.withhold_commits && msg.code == MessageType::Commit {
+            return vec![];
+        }
+        let msg = if self.behavior.invalid_proposal && msg.code == MessageType::Preprepare {
+            self.invalid_proposal(msg)
+        } else {
+            msg
+        };
+        let mut messages = vec![msg.clone()];
+        if self.behavior.equivocate {
+            if let Some(conflict) = self.conflict(&msg) {
+                messages.push(conflict);
+            }
+        }
+        messages
+    }
+
+    fn invalid_proposal(&self, mut msg: GossipMessage) -> GossipMessage {
+        msg.msg = tamper_proposal(&msg.msg, |header| header.tx_hash = hash(header.tx_hash.as_ref()));
+        msg.set_sign(self.key_pair.secret());
+        msg
+    }
+
+    // the same view with another digest
+    fn conflict(&self, msg: &GossipMessage) -> Option<GossipMessage> {
+        let mut conflict = msg.clone();
+        match msg.code {
+            MessageType::Preprepare => {
+                conflict.msg = tamper_proposal(&msg.msg, |header| header.time += 1);
+            }
+            MessageType::Prepare | MessageType::Commit => {
+                let mut subject: Subject = Subject::from_bytes(Cow::from(&msg.msg));
+                subject.digest = hash(subject.digest.as_ref());
+                if msg.code == MessageType::Commit {
+                    conflict.commit_seal = Some(encrypt_commit_bytes(&subject.digest, self.key_pair.secret()));
+                }
+                conflict.msg = subject.into_bytes();
+            }
+            MessageType::RoundChange => return None,
+        }
+        conflict.set_sign(self.key_pair.secret());
+        Some(conflict)
+    }
+}
+
+// modify the header of the proposal in a preprepare payload
+fn tamper_proposal<F: FnOnce(&mut Header)>(payload: &[u8], f: F) -> Vec<u8> {
+    let mut preprepare: PrePrepare = PrePrepare::from_bytes(Cow::from(payload));
+    let block = preprepare.proposal.block().clone();
+    // decode the header again, so the cached block hash is dropped
+    let mut header = Header::from_rlp(&block.header().rlp_bytes()).unwrap();
+    f(&mut header);
+    preprepare.proposal = Proposal::new(Block::new(header, block.transactions().clone()));
+    preprepare.into_bytes()
+}
+
+impl<B> Backend for ByzantineCore<B>
+    where B: Backend<ValidatorsType=ImplValidatorSet> + Clone + 'static {
+    type ValidatorsType = ImplValidatorSet;
+
+    fn address(&self) -> Address {
+        self.inner.address()
+    }
+
+    fn validators(&self, height: Height) -> ImplValidatorSet {
+        self.inner.validators(height)
+    }
+
+    fn gossip(&mut self, vals: &ValidatorSet, msg: GossipMessage) -> EngineResult {
+        for msg in self.tamper(msg) {
+            match self.behavior.delay {
+                Some(delay) => {
+                    // the set of the message's view, it's copied as the borrowed one is gone then
+                    let mut inner = self.inner.clone();
+                    let addresses: Vec<Address> = vals.list().iter().map(|validator| *validator.address()).collect();
+                    let vals = ImplValidatorSet::new(&addresses, Box::new(fn_selector));
+                    Arbiter::spawn(Delay::new(Instant::now() + delay).then(move |_| {
+                        if let Err(err) = inner.gossip(&vals, msg) {
+                            error!("Failed to gossip delayed message, err: {:?}", err);
+                        }
+                        Ok::<(), ()>(())
+                    }));
+                }
+                None => self.inner.gossip(vals, msg)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, proposal: &mut Proposal, certificate: CommitCertificate, messages: GossipMessages) -> Result<(), String> {
+        self.inner.commit(proposal, certificate, messages)
+    }
+
+    fn verify(&self, proposal: &Proposal) -> (Duration, Result<(), EngineError>) {
+        self.inner.verify(proposal)
+    }
+
+    fn check_signature(&self, data: &[u8; 32], address: Address, sig: &[u8]) -> Result<bool, ()> {
+        self.inner.check_signature(data, address, sig)
+    }
+
+    fn last_proposal(&self) -> Result<Proposal, ()> {
+        self.inner.last_proposal()
+    }
+
+    fn has_proposal(&self, hash: &Hash, height: Height) -> bool {
+        self.inner.has_proposal(hash, height)
+    }
+
+    fn get_proposer(&self, height: Height) -> Address {
+        self.inner.get_proposer(height)
+    }
+
+    fn parent_validators(&self, proposal: &Proposal) -> ImplValidatorSet {
+        self.inner.parent_validators(proposal)
+    }
+
+    fn has_bad_proposal(&self, hash: Hash) -> bool {
+        self.inner.has_bad_proposal(hash)
+    }
+
+    fn get_header_by_height(&self, height: Height) -> Option<Header> {
+        self.inner.get_header_by_height(height)
+    }
+
+    fn cancel_inflight(&mut self) {
+        self.inner.cancel_inflight()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::consensus::types::View;
+
+    #[test]
+    fn t_byzantine_behavior() {
+        let behavior = ByzantineBehavior::from_str("equivocate, withhold_commits,delay=500").unwrap();
+        assert!(behavior.equivocate && behavior.withhold_commits && !behavior.invalid_proposal);
+        assert_eq!(behavior.delay, Some(Duration::from_millis(500)));
+        assert!(ByzantineBehavior::from_str("").unwrap().is_honest());
+        assert!(ByzantineBehavior::from_str("sleep").is_err());
+    }
+
+    #[test]
+    fn t_byzantine_tamper() {
+        let key_pair = Random.generate().unwrap();
+        let block = Block::new(Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, None), vec![]);
+        let mut preprepare = GossipMessage::new(MessageType::Preprepare, PrePrepare::new(View::new(1, 0), Proposal::new(block.clone())).into_bytes(), None);
+        preprepare.set_sign(key_pair.secret());
+        let subject = Subject { view: View::new(1, 0), digest: block.hash() };
+        let mut commit = GossipMessage::new(MessageType::Commit, subject.clone().into_bytes(), Some(encrypt_commit_bytes(&block.hash(), key_pair.secret())));
+        commit.set_sign(key_pair.secret());
+        let byzantine = |flags: &str| ByzantineCore::new((), key_pair.clone(), ByzantineBehavior::from_str(flags).unwrap());
+
+        assert_eq!(byzantine("").tamper(commit.clone()), vec![commit.clone()]);
+        assert!(byzantine("withhold_commits").tamper(commit.clone()).is_empty());
+        assert_eq!(byzantine("withhold_commits").tamper(preprepare.clone()), vec![preprepare.clone()]);
+
+        // the conflicting commit of the same view is signed and sealed by the node
+        let mut messages = byzantine("equivocate").tamper(commit.clone());
+        assert_eq!(messages.len(), 2);
+        let conflict: Subject = Subject::from_bytes(Cow::from(&messages[1].msg));
+        assert_eq!((conflict.view.height, conflict.view.round), (1, 0));
+        assert_ne!(conflict.digest, subject.digest);
+        assert_ne!(messages[1].commit_seal, commit.commit_seal);
+        assert_eq!(messages[1].address().unwrap(), key_pair.address());
+        let proposal_hash = |msg: &GossipMessage| PrePrepare::from_bytes(Cow::from(&msg.msg)).proposal.block().hash();
+        let proposals = byzantine("equivocate").tamper(preprepare.clone());
+        assert_eq!(proposal_hash(&proposals[0]), block.hash());
+        assert_ne!(proposal_hash(&proposals[1]), block.hash());
+
+        // the transactions root of the proposal is broken, the message still comes from the node
+        let mut tampered = byzantine("invalid_proposal").tamper(preprepare.clone()).remove(0);
+        let header = PrePrepare::from_bytes(Cow::from(&tampered.msg)).proposal.block().header().clone();
+        assert_ne!(header.tx_hash, block.header().tx_hash);
+        assert_eq!(tampered.address().unwrap(), key_pair.address());
+    }
+}
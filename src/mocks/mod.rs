@@ -6,6 +6,7 @@ use std::env;
 use crate::config::Config;
 
 pub(crate) mod utils;
+pub mod byzantine;
 
 
 pub(crate) fn t_config() -> Config {
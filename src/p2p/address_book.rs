@@ -0,0 +1,67 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use libp2p::Multiaddr;
+
+/// AddressBook is the persistent peer store, the addresses survive the restarts,
+/// so the node can reconnect to the known peers without the seeds
+pub struct AddressBook {
+    path: PathBuf,
+    addresses: BTreeSet<String>,
+}
+
+impl AddressBook {
+    /// load the address book from `path`, a missing or broken file is an empty book
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let addresses = fs::read(&path)
+            .ok()
+            .and_then(|buf| serde_json::from_slice::<BTreeSet<String>>(&buf).ok())
+            .unwrap_or_default();
+        AddressBook { path, addresses }
+    }
+
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        self.addresses.iter().filter_map(|address| Multiaddr::from_str(address).ok()).collect()
+    }
+
+    /// merge the addresses into the book, returns the new ones
+    pub fn merge(&mut self, addresses: &[Multiaddr]) -> Vec<Multiaddr> {
+        let new_addresses: Vec<Multiaddr> = addresses.iter().filter(|address| self.addresses.insert(address.to_string())).cloned().collect();
+        if !new_addresses.is_empty() {
+            if let Err(err) = self.save() {
+                warn!("Failed to save the address book, path: {:?}, err: {}", self.path, err);
+            }
+        }
+        new_addresses
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let buf = serde_json::to_vec_pretty(&self.addresses).map_err(|err| err.to_string())?;
+        fs::write(&self.path, buf).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_address_book() {
+        let path = format!("{}/peers.json", random_dir());
+        let address = Multiaddr::from_str("/ip4/127.0.0.1/tcp/7960").unwrap();
+        let mut book = AddressBook::load(&path);
+        assert!(book.addresses().is_empty());
+        assert_eq!(book.merge(&[address.clone()]).len(), 1);
+        assert!(book.merge(&[address.clone()]).is_empty());
+
+        let book = AddressBook::load(&path);
+        assert_eq!(book.addresses(), vec![address]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::codec::{WireCodec, MSG_SIZE};
+use super::protocol::{P2PMsgCode, RawMessage};
+use crate::metrics;
+
+/// The traffic category of p2p messages, the handshake and ping messages are counted as discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficCategory {
+    Consensus,
+    BlockSync,
+    TxGossip,
+    Discovery,
+}
+
+impl TrafficCategory {
+    pub fn from_code(code: &P2PMsgCode) -> Self {
+        match code {
+            P2PMsgCode::Consensus => TrafficCategory::Consensus,
+            P2PMsgCode::Block | P2PMsgCode::Sync | P2PMsgCode::SyncReply => TrafficCategory::BlockSync,
+            P2PMsgCode::Transaction => TrafficCategory::TxGossip,
+            P2PMsgCode::Handshake | P2PMsgCode::Ping | P2PMsgCode::Records => TrafficCategory::Discovery,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrafficCategory::Consensus => "consensus",
+            TrafficCategory::BlockSync => "block_sync",
+            TrafficCategory::TxGossip => "tx_gossip",
+            TrafficCategory::Discovery => "discovery",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        }
+    }
+}
+
+/// the bytes of the message on the wire, including the size prefix
+pub fn wire_size(msg: &RawMessage, codec: WireCodec) -> u64 {
+    codec.encode(msg.clone()).len() as u64 + u64::from(MSG_SIZE)
+}
+
+/// account the message bytes by category, the peers aren't labels, their ids are unbounded
+pub fn record_traffic(direction: Direction, msg: &RawMessage, codec: WireCodec) {
+    let category = TrafficCategory::from_code(&msg.header().code);
+    metrics::REGISTRY.inc_counter(
+        &format!("p2p_bytes_total{{direction=\"{}\",category=\"{}\"}}", direction.as_str(), category.as_str()),
+        wire_size(msg, codec),
+    );
+}
+
+pub(crate) struct TokenBucket {
+    // bytes per second
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u64) -> Self {
+        TokenBucket { rate, tokens: rate as f64, last: Instant::now() }
+    }
+
+    pub(crate) fn try_consume(&mut self, bytes: u64) -> bool {
+        self.refill();
+        if self.tokens < bytes as f64 {
+            return false;
+        }
+        self.tokens -= bytes as f64;
+        true
+    }
+
+    /// consume the bytes or tell how long to wait for them, the bytes beyond the burst pass on a
+    /// full bucket and leave it in debt, so a big message is delayed but never starved
+    pub(crate) fn consume_or_wait(&mut self, bytes: u64) -> Option<Duration> {
+        self.refill();
+        let rate = ::std::cmp::max(self.rate, 1) as f64;
+        let needed = (bytes as f64).min(rate);
+        if self.tokens >= needed {
+            self.tokens -= bytes as f64;
+            return None;
+        }
+        Some(Duration::from_millis(((needed - self.tokens) * 1000.0 / rate).ceil() as u64))
+    }
+
+    fn refill(&mut self) {
+        let elapsed = self.last.elapsed();
+        self.last = Instant::now();
+        let refill = (elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9) * self.rate as f64;
+        // the burst is at most one second of traffic
+        self.tokens = (self.tokens + refill).min(self.rate as f64);
+    }
+}
+
+/// OutboundQuota limits the outbound bytes per second of every category over all peers,
+/// the categories without a quota are unlimited
+pub struct OutboundQuota {
+    buckets: HashMap<TrafficCategory, TokenBucket>,
+}
+
+impl OutboundQuota {
+    pub fn new(quotas: &HashMap<TrafficCategory, u64>) -> Self {
+        let buckets = quotas.iter().map(|(category, rate)| (*category, TokenBucket::new(*rate))).collect();
+        OutboundQuota { buckets }
+    }
+
+    /// returns false if the message exceeds the quota and should be dropped
+    pub fn try_consume(&mut self, category: TrafficCategory, bytes: u64) -> bool {
+        match self.buckets.get_mut(&category) {
+            Some(bucket) => {
+                if bucket.try_consume(bytes) {
+                    return true;
+                }
+                metrics::inc_counter(&format!("p2p_quota_dropped_total{{category=\"{}\"}}", category.as_str()));
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_outbound_quota() {
+        let mut quotas = HashMap::new();
+        quotas.insert(TrafficCategory::TxGossip, 100);
+        let mut quota = OutboundQuota::new(&quotas);
+        assert!(quota.try_consume(TrafficCategory::TxGossip, 60));
+        assert!(!quota.try_consume(TrafficCategory::TxGossip, 60));
+        assert!(quota.try_consume(TrafficCategory::Consensus, 1 << 20));
+        assert_eq!(TrafficCategory::from_code(&P2PMsgCode::Sync), TrafficCategory::BlockSync);
+    }
+
+    #[test]
+    fn t_consume_or_wait() {
+        let mut bucket = TokenBucket::new(1000);
+        assert_eq!(bucket.consume_or_wait(600), None);
+        // about 200 millis for the missing 200 bytes
+        let wait = bucket.consume_or_wait(600).unwrap();
+        assert!(wait > Duration::from_millis(150) && wait <= Duration::from_millis(200));
+
+        // a message beyond the burst passes on a full bucket, then the debt is waited for
+        let mut bucket = TokenBucket::new(1000);
+        assert_eq!(bucket.consume_or_wait(3000), None);
+        assert!(bucket.consume_or_wait(1).unwrap() > Duration::from_millis(1900));
+    }
+}
@@ -1,18 +1,96 @@
 use std::borrow::Cow;
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{BufMut, BytesMut};
 use cryptocurrency_kit::storage::values::StorageValue;
+use rlp::UntrustedRlp;
 use tokio::codec::{Decoder, Encoder};
 
 use super::protocol::*;
+use crate::core::limits::SizeLimits;
+use crate::metrics;
 
 pub const MAX_MSG_SIZE: u32 = 1 << 10;
 pub const MSG_SIZE: u32 = 4; // byte
 
+// the first protocol version which speaks rlp
+const RLP_PROTOCOL_VERSION: u32 = 2;
+
+/// the encoding of the message body, json bodies start with `{` and rlp bodies start with a
+/// list prefix (>= 0xc0), so a frame is decoded without knowing what the peer chose
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireCodec {
+    Json,
+    Rlp,
+}
+
+impl WireCodec {
+    pub fn encode(self, msg: RawMessage) -> Vec<u8> {
+        match self {
+            WireCodec::Json => msg.into_bytes(),
+            WireCodec::Rlp => rlp::encode(&msg).to_vec(),
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<RawMessage, io::Error> {
+        match buf.first() {
+            Some(prefix) if *prefix >= 0xc0 => UntrustedRlp::new(buf).as_val()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))),
+            Some(_) => Ok(RawMessage::from_bytes(Cow::from(buf.to_vec()))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "empty message")),
+        }
+    }
+}
+
+/// choose the codec with the remote protocol version, the handshake is always json
+pub fn negotiate_codec(remote_version: u32) -> WireCodec {
+    if ::std::cmp::min(remote_version, PROTOCOL_VERSION) >= RLP_PROTOCOL_VERSION {
+        WireCodec::Rlp
+    } else {
+        WireCodec::Json
+    }
+}
+
+/// the codec which the session writes with, it's switched after the handshake
+#[derive(Debug, Clone, Default)]
+pub struct WireSelector(Arc<AtomicBool>);
+
+impl WireSelector {
+    pub fn get(&self) -> WireCodec {
+        if self.0.load(Ordering::SeqCst) { WireCodec::Rlp } else { WireCodec::Json }
+    }
+
+    pub fn set(&self, codec: WireCodec) {
+        self.0.store(codec == WireCodec::Rlp, Ordering::SeqCst);
+    }
+}
+
 // |msg_size: 4bytes| msg encode |
-pub struct MsgPacketCodec;
+pub struct MsgPacketCodec {
+    wire: WireSelector,
+    // the bigger frames are refused before they're buffered
+    max_frame: usize,
+}
+
+impl Default for MsgPacketCodec {
+    fn default() -> Self {
+        MsgPacketCodec::reader(SizeLimits::default().max_frame_size())
+    }
+}
+
+impl MsgPacketCodec {
+    pub fn new(wire: WireSelector) -> Self {
+        MsgPacketCodec { wire, max_frame: SizeLimits::default().max_frame_size() }
+    }
+
+    /// the codec of the read half, the frames over `max_frame` fail the stream
+    pub fn reader(max_frame: usize) -> Self {
+        MsgPacketCodec { wire: WireSelector::default(), max_frame }
+    }
+}
 
 impl Decoder for MsgPacketCodec {
     type Item = RawMessage;
@@ -24,14 +102,17 @@ impl Decoder for MsgPacketCodec {
                 // continue read
                 return Ok(None);
             }
-            BigEndian::read_u32(src.as_ref())
+            BigEndian::read_u32(src.as_ref()) as usize
         };
+        if size > self.max_frame {
+            metrics::inc_counter("p2p_oversized_frames_total");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("oversized frame, size: {}, max: {}", size, self.max_frame)));
+        }
 
-        if src.len() >= (size + MSG_SIZE) as usize {
+        if src.len() >= size + MSG_SIZE as usize {
             src.split_to(MSG_SIZE as usize);
-            let buf = src.split_to(size as usize);
-            let raw_message: RawMessage = RawMessage::from_bytes(Cow::from(buf.to_vec()));
-            Ok(Some(raw_message))
+            let buf = src.split_to(size);
+            WireCodec::decode(buf.as_ref()).map(Some)
         } else {
             Ok(None)
         }
@@ -43,7 +124,8 @@ impl Encoder for MsgPacketCodec {
     type Error = io::Error;
 
     fn encode(&mut self, msg: RawMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let msg = msg.into_bytes();
+        let codec = if msg.header().code == P2PMsgCode::Handshake { WireCodec::Json } else { self.wire.get() };
+        let msg = codec.encode(msg);
         let size = msg.len() as u32;
         dst.reserve((size + MSG_SIZE) as usize);
         dst.put_u32_be(size);
@@ -51,3 +133,40 @@ impl Encoder for MsgPacketCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_wire_codec() {
+        let msg = RawMessage::new(Header::new(P2PMsgCode::Consensus, 3, 100, Some(vec![1, 2])), vec![7; 256]);
+        let json = WireCodec::Json.encode(msg.clone());
+        let binary = WireCodec::Rlp.encode(msg.clone());
+        assert!(binary.len() * 2 < json.len());
+        for buf in &[json, binary] {
+            let got = WireCodec::decode(buf).unwrap();
+            assert_eq!(got.header().code, P2PMsgCode::Consensus);
+            assert_eq!(got.header().peer_id, Some(vec![1, 2]));
+            assert_eq!(got.payload(), msg.payload());
+        }
+        assert_eq!(negotiate_codec(1), WireCodec::Json);
+        assert_eq!(negotiate_codec(PROTOCOL_VERSION), WireCodec::Rlp);
+
+        let wire = WireSelector::default();
+        let mut codec = MsgPacketCodec::new(wire.clone());
+        wire.set(WireCodec::Rlp);
+        let mut buf = BytesMut::new();
+        codec.encode(msg.clone(), &mut buf).unwrap();
+        assert!(buf[MSG_SIZE as usize] >= 0xc0);
+        let got = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(got.payload(), msg.payload());
+        assert!(buf.is_empty());
+
+        // the oversized frame fails before its body arrives
+        let mut reader = MsgPacketCodec::reader(64);
+        let mut buf = BytesMut::with_capacity(MSG_SIZE as usize);
+        buf.put_u32_be(65);
+        assert_eq!(reader.decode(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -12,16 +12,21 @@ use libp2p::PeerId;
 
 #[macro_use]
 use crate::subscriber::*;
+use crate::util::supervise::record_restart;
 
 pub struct DiscoverService {
     p2p_pid: Addr<ProcessSignals>,
+    peer_id: PeerId,
+    local_address: Multiaddr,
+    ttl: Duration,
 }
 
 impl Actor for DiscoverService {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
+    fn started(&mut self, ctx: &mut Context<Self>) {
         trace!("Discover service started");
+        self.spawn_mdns(ctx);
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -29,6 +34,13 @@ impl Actor for DiscoverService {
     }
 }
 
+// the mDNS service is created again when the discovery restarts
+impl Supervised for DiscoverService {
+    fn restarting(&mut self, _ctx: &mut Self::Context) {
+        record_restart("discover_service");
+    }
+}
+
 impl DiscoverService {
     pub fn spawn_discover_service(
         p2p_subscriber: Addr<ProcessSignals>,
@@ -36,8 +48,27 @@ impl DiscoverService {
         local_address: Multiaddr,
         ttl: Duration,
     ) -> Addr<DiscoverService> {
-        let mut service = MdnsService::new().expect("Error while creating mDNS service");
-        let p2p_subscriber_clone = p2p_subscriber.clone();
+        trace!("Create mDSN service successfully");
+        Supervisor::start(move |_| DiscoverService {
+            p2p_pid: p2p_subscriber,
+            peer_id,
+            local_address,
+            ttl,
+        })
+    }
+
+    // the service exits on an error, then the actor is stopped for the restart
+    fn spawn_mdns(&mut self, ctx: &mut Context<Self>) {
+        let mut service = match MdnsService::new() {
+            Ok(service) => service,
+            Err(err) => {
+                error!("Failed to create mDNS service, err: {:?}", err);
+                ctx.stop();
+                return;
+            }
+        };
+        let (peer_id, local_address, ttl) = (self.peer_id.clone(), self.local_address.clone(), self.ttl);
+        let p2p_subscriber_clone = self.p2p_pid.clone();
         let future = futures::future::poll_fn(move || -> Poll<(), io::Error> {
             loop {
                 let packet = match service.poll() {
@@ -48,7 +79,7 @@ impl DiscoverService {
                     MdnsPacket::Query(query) => {
                         query
                             .respond(peer_id.clone(), vec![local_address.clone()], ttl)
-                            .unwrap();
+                            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{:?}", err)))?;
                     }
                     MdnsPacket::Response(response) => {
                         let _peers_size = response.discovered_peers().count();
@@ -62,7 +93,10 @@ impl DiscoverService {
                                 addresses.push(address.clone());
                             }
 //                            trace!("Get a message from mDNS, local-id:{:?}, remote-id:{:?}", peer_id, id);
-                            // if the receiver actor's mailbox is full, ignore message
+                            // the mdns announcement isn't signed, the addresses are only dialed, the
+                            // peer proves its id by the discovery record of its handshake, and the
+                            // addresses of a known record win. If the receiver actor's mailbox is
+                            // full, ignore message
                             p2p_subscriber_clone.try_send(P2PEvent::AddPeer(id, addresses));
                         }
                     }
@@ -73,16 +107,11 @@ impl DiscoverService {
             }
         });
 
-        Arbiter::spawn(future.then(|_res| {
-            trace!("mDNS service exit");
-            future::result(Ok(()))
+        ctx.spawn(future.into_actor(self).then(|res, _act, ctx| {
+            trace!("mDNS service exit, result: {:?}", res);
+            ctx.stop();
+            actix::fut::ok(())
         }));
-
-        trace!("Create mDSN service successfully");
-        let p2p_subscriber = p2p_subscriber.clone();
-        Actor::create(|_| DiscoverService {
-            p2p_pid: p2p_subscriber,
-        })
     }
 }
 
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::{recover_bytes, KeyPair, Signature};
+use cryptocurrency_kit::storage::values::StorageValue;
+use libp2p::{Multiaddr, PeerId};
+
+use super::identity::public_peer_id;
+use crate::common::hash_algo::digest;
+
+// the records older than it are replays, the node signs a new one every handshake. The records
+// ahead of the local clock are accepted, the clock of the peer may be ahead, the sequence number
+// still orders the records of a node
+pub const MAX_RECORD_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+// the records sent to a peer at once, the newest ones
+pub const MAX_GOSSIP_RECORDS: usize = 64;
+// the verified records in the data directory, they're dialed on the start
+pub const RECORDS_FILE: &str = "records.json";
+
+/// DiscoveryRecord announces the addresses of a node, it's signed by the node key which the peer
+/// id is derived from, so nobody else can announce addresses for the peer id. A record with a
+/// higher sequence number supersedes the previous ones.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DiscoveryRecord {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    // millis
+    pub timestamp: u64,
+    pub seq: u64,
+    pub signature: Signature,
+}
+
+impl DiscoveryRecord {
+    pub fn sign(peer_id: &PeerId, addresses: &[Multiaddr], seq: u64, node_key: &KeyPair) -> Self {
+        let peer_id = peer_id.to_base58();
+        let addresses: Vec<String> = addresses.iter().map(|address| address.to_string()).collect();
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        let digest = record_digest(&peer_id, &addresses, timestamp, seq);
+        DiscoveryRecord { peer_id, addresses, timestamp, seq, signature: digest.sign(node_key.secret()).unwrap() }
+    }
+
+    /// the signer must own the peer id and the record must be fresh, it returns the peer id
+    pub fn verify(&self, now: u64) -> Result<PeerId, String> {
+        let peer_id = self.signer()?;
+        if !self.is_fresh(now) {
+            return Err(format!("the record is stale, timestamp: {}", self.timestamp));
+        }
+        Ok(peer_id)
+    }
+
+    /// the peer id whose node key signed the record
+    pub fn signer(&self) -> Result<PeerId, String> {
+        let peer_id = PeerId::from_str(&self.peer_id).map_err(|err| format!("invalid peer id, {:?}", err))?;
+        let digest = record_digest(&self.peer_id, &self.addresses, self.timestamp, self.seq);
+        let public = recover_bytes(&self.signature, digest.as_ref()).map_err(|_| "recover record signature failed".to_string())?;
+        let signer = public_peer_id(&public)?;
+        if signer != peer_id {
+            return Err(format!("the record of {} is signed by {}", self.peer_id, signer.to_base58()));
+        }
+        Ok(peer_id)
+    }
+
+    pub fn is_fresh(&self, now: u64) -> bool {
+        self.timestamp.saturating_add(MAX_RECORD_AGE.as_millis() as u64) >= now
+    }
+
+    pub fn multiaddrs(&self) -> Vec<Multiaddr> {
+        self.addresses.iter().filter_map(|address| Multiaddr::from_str(address).ok()).collect()
+    }
+}
+
+fn record_digest(peer_id: &str, addresses: &[String], timestamp: u64, seq: u64) -> Hash {
+    let mut bytes = peer_id.as_bytes().to_vec();
+    for address in addresses {
+        bytes.extend_from_slice(address.as_bytes());
+        bytes.push(0);
+    }
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    bytes.extend_from_slice(&seq.to_be_bytes());
+    digest(bytes)
+}
+
+/// the records which a peer gossips, see `P2PMsgCode::Records`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerRecords(pub Vec<DiscoveryRecord>);
+
+implement_storagevalue_traits! {PeerRecords}
+
+/// DiscoveryRecords keeps the newest verified record of every peer, the records are learned from
+/// the handshakes and the gossip of the peers, they're persisted if the path is set
+#[derive(Debug, Default)]
+pub struct DiscoveryRecords {
+    records: HashMap<PeerId, DiscoveryRecord>,
+    path: Option<PathBuf>,
+}
+
+impl DiscoveryRecords {
+    /// load the records of `path`, they're verified again, the stale and the forged ones are
+    /// dropped. A missing or broken file is empty
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let loaded = fs::read(&path)
+            .ok()
+            .and_then(|buf| serde_json::from_slice::<Vec<DiscoveryRecord>>(&buf).ok())
+            .unwrap_or_default();
+        let mut records = DiscoveryRecords { records: HashMap::new(), path: None };
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        for record in loaded {
+            if let Err(err) = records.insert(record, now) {
+                warn!("Drop the invalid discovery record, path: {:?}, err: {}", path, err);
+            }
+        }
+        records.path = Some(path);
+        records
+    }
+
+    /// verify the record and keep it if it's newer than the known one, it returns false if it's
+    /// superseded or stale, a record which isn't signed by its peer is an error
+    pub fn insert(&mut self, record: DiscoveryRecord, now: u64) -> Result<bool, String> {
+        let peer_id = record.signer()?;
+        if !record.is_fresh(now) {
+            return Ok(false);
+        }
+        if let Some(known) = self.records.get(&peer_id) {
+            if known.seq >= record.seq {
+                return Ok(known.seq == record.seq && *known == record);
+            }
+        }
+        self.records.insert(peer_id, record);
+        if let Err(err) = self.save() {
+            warn!("Failed to save the discovery records, path: {:?}, err: {}", self.path, err);
+        }
+        Ok(true)
+    }
+
+    /// the newest records, they're gossiped to the peers
+    pub fn newest(&self, max: usize) -> Vec<DiscoveryRecord> {
+        let mut records: Vec<DiscoveryRecord> = self.records.values().cloned().collect();
+        records.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        records.truncate(max);
+        records
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&DiscoveryRecord> {
+        self.records.get(peer_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let records: Vec<&DiscoveryRecord> = self.records.values().collect();
+        let buf = serde_json::to_vec_pretty(&records).map_err(|err| err.to_string())?;
+        fs::write(path, buf).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use std::borrow::Cow;
+    use super::super::identity::key_peer_id;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_discovery_record() {
+        let node_key = Random.generate().unwrap();
+        let peer_id = key_peer_id(&node_key).unwrap();
+        let address = Multiaddr::from_str("/ip4/127.0.0.1/tcp/7960").unwrap();
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let record = DiscoveryRecord::sign(&peer_id, &[address.clone()], 2, &node_key);
+        assert_eq!(record.verify(now), Ok(peer_id.clone()));
+        assert_eq!(record.multiaddrs(), vec![address]);
+
+        // the address is spoofed
+        let mut spoofed = record.clone();
+        spoofed.addresses = vec!["/ip4/10.0.0.1/tcp/7960".to_string()];
+        assert!(spoofed.verify(now).is_err());
+        // the peer id is claimed by another key
+        let other = DiscoveryRecord::sign(&peer_id, &[], 3, &Random.generate().unwrap());
+        assert!(other.verify(now).is_err());
+        // the replay of an old record
+        assert!(record.verify(now + MAX_RECORD_AGE.as_millis() as u64 + 1).is_err());
+
+        let mut records = DiscoveryRecords::default();
+        assert_eq!(records.insert(record.clone(), now), Ok(true));
+        let newer = DiscoveryRecord::sign(&peer_id, &[], 3, &node_key);
+        assert_eq!(records.insert(newer.clone(), now), Ok(true));
+        assert_eq!(records.insert(record, now), Ok(false));
+        assert_eq!(records.get(&peer_id), Some(&newer));
+        // the stale record is ignored, the forged one is an error
+        assert_eq!(records.insert(DiscoveryRecord::sign(&peer_id, &[], 4, &node_key), now + MAX_RECORD_AGE.as_millis() as u64 + 1), Ok(false));
+        assert!(records.insert(other, now).is_err());
+    }
+
+    #[test]
+    fn t_record_ahead_of_clock() {
+        let node_key = Random.generate().unwrap();
+        let peer_id = key_peer_id(&node_key).unwrap();
+        let record = DiscoveryRecord::sign(&peer_id, &[], 1, &node_key);
+        // the clock of the peer is an hour ahead
+        let now = record.timestamp - 60 * 60 * 1000;
+        assert_eq!(record.verify(now), Ok(peer_id.clone()));
+        let mut records = DiscoveryRecords::default();
+        assert_eq!(records.insert(record, now), Ok(true));
+    }
+
+    #[test]
+    fn t_persist_records() {
+        let path = format!("{}/{}", random_dir(), RECORDS_FILE);
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let (a, b) = (Random.generate().unwrap(), Random.generate().unwrap());
+        let address = Multiaddr::from_str("/ip4/127.0.0.1/tcp/7960").unwrap();
+        let record_a = DiscoveryRecord::sign(&key_peer_id(&a).unwrap(), &[address.clone()], 1, &a);
+        let record_b = DiscoveryRecord::sign(&key_peer_id(&b).unwrap(), &[address], 1, &b);
+        {
+            let mut records = DiscoveryRecords::load(&path);
+            assert_eq!(records.len(), 0);
+            assert_eq!(records.insert(record_a.clone(), now), Ok(true));
+            assert_eq!(records.insert(record_b.clone(), now), Ok(true));
+        }
+        let records = DiscoveryRecords::load(&path);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records.get(&key_peer_id(&a).unwrap()), Some(&record_a));
+        assert_eq!(records.newest(1).len(), 1);
+
+        // the forged record of the file is dropped on the load
+        let mut forged = record_b.clone();
+        forged.addresses = vec!["/ip4/10.0.0.1/tcp/7960".to_string()];
+        fs::write(&path, serde_json::to_vec(&vec![record_a.clone(), forged]).unwrap()).unwrap();
+        let records = DiscoveryRecords::load(&path);
+        assert_eq!(records.len(), 1);
+
+        // the gossip message carries the records
+        let message = PeerRecords(vec![record_a, record_b]);
+        assert_eq!(PeerRecords::from_bytes(Cow::from(message.clone().into_bytes())), message);
+    }
+}
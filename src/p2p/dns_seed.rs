@@ -0,0 +1,111 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use ::actix::prelude::*;
+use libp2p::{Multiaddr, PeerId};
+
+use super::address_book::AddressBook;
+use crate::metrics;
+use crate::subscriber::*;
+
+// the seeds are resolved again periodically, the dns records of public networks change over time
+pub const DNS_SEED_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// DnsSeeder resolves the dns seeds at startup and periodically, merges the addresses into
+/// the address book and dials them. The peer id of a seed address is unknown until the handshake,
+/// so a random one is used to dial, the duplicate connections are rejected by the handshake.
+pub struct DnsSeeder {
+    seeds: Vec<String>,
+    default_port: u16,
+    // the advertised address of the local node, a seed may resolve to it
+    local_address: Multiaddr,
+    address_book: AddressBook,
+    p2p_subscriber: Addr<ProcessSignals>,
+}
+
+impl Actor for DnsSeeder {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let known = self.address_book.addresses();
+        info!("Dns seeder started, seeds: {:?}, known addresses: {}", self.seeds, known.len());
+        self.dial(known);
+        self.resolve(ctx);
+        ctx.run_interval(DNS_SEED_INTERVAL, |act, ctx| act.resolve(ctx));
+    }
+}
+
+#[derive(Message)]
+struct SeedsResolved(Vec<Multiaddr>);
+
+impl Handler<SeedsResolved> for DnsSeeder {
+    type Result = ();
+
+    fn handle(&mut self, mut msg: SeedsResolved, _: &mut Self::Context) {
+        let local_address = self.local_address.clone();
+        msg.0.retain(|address| *address != local_address);
+        let new_addresses = self.address_book.merge(&msg.0);
+        debug!("Dns seeds resolved, addresses: {}, new: {}", msg.0.len(), new_addresses.len());
+        self.dial(msg.0);
+    }
+}
+
+impl DnsSeeder {
+    pub fn new(seeds: Vec<String>, default_port: u16, local_address: Multiaddr, address_book: AddressBook, p2p_subscriber: Addr<ProcessSignals>) -> Self {
+        DnsSeeder { seeds, default_port, local_address, address_book, p2p_subscriber }
+    }
+
+    // the dns lookup blocks, so it runs on a new thread
+    fn resolve(&self, ctx: &mut Context<Self>) {
+        if self.seeds.is_empty() {
+            return;
+        }
+        let seeds = self.seeds.clone();
+        let default_port = self.default_port;
+        let pid = ctx.address();
+        ::std::thread::spawn(move || {
+            let addresses: Vec<Multiaddr> = seeds.iter().flat_map(|seed| resolve_seed(seed, default_port)).collect();
+            pid.do_send(SeedsResolved(addresses));
+        });
+    }
+
+    fn dial(&self, addresses: Vec<Multiaddr>) {
+        for address in addresses {
+            // if the receiver actor's mailbox is full, ignore message
+            let _ = self.p2p_subscriber.try_send(P2PEvent::AddPeer(PeerId::random(), vec![address]));
+        }
+    }
+}
+
+/// resolve a seed like `seed.example.org` or `seed.example.org:7960` to the ipv4 addresses,
+/// the seed without port uses the `default_port`
+pub fn resolve_seed(seed: &str, default_port: u16) -> Vec<Multiaddr> {
+    let host = if seed.contains(':') { seed.to_owned() } else { format!("{}:{}", seed, default_port) };
+    match host.to_socket_addrs() {
+        Ok(addresses) => addresses
+            .filter_map(|address| match address {
+                SocketAddr::V4(address) => Multiaddr::from_str(&format!("/ip4/{}/tcp/{}", address.ip(), address.port())).ok(),
+                SocketAddr::V6(_) => None,
+            })
+            .collect(),
+        Err(err) => {
+            metrics::inc_counter("p2p_dns_seed_failed_total");
+            warn!("Failed to resolve dns seed: {}, err: {}", seed, err);
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_resolve_seed() {
+        let addresses = resolve_seed("127.0.0.1", 7960);
+        assert_eq!(addresses, vec![Multiaddr::from_str("/ip4/127.0.0.1/tcp/7960").unwrap()]);
+        let addresses = resolve_seed("127.0.0.1:7961", 7960);
+        assert_eq!(addresses, vec![Multiaddr::from_str("/ip4/127.0.0.1/tcp/7961").unwrap()]);
+    }
+}
@@ -0,0 +1,280 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::{public_to_address, recover_bytes, Address, Generator, KeyPair, Public, Random, Secret, Signature};
+use libp2p::core::PublicKey;
+use libp2p::{secio, PeerId};
+use parking_lot::RwLock;
+
+use crate::common::hash_algo::digest;
+use crate::signer::{SafeSigner, Signer};
+
+// the identity file in the data directory
+pub const IDENTITY_FILE: &str = "identity.json";
+
+/// NodeIdentity is the network identity of a node, it's persisted in the data directory on the
+/// first run, so a reprovisioned config.toml never changes it, only `identity rotate` does
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NodeIdentity {
+    // the hex secret of the node key
+    pub node_key: String,
+    pub peer_id: String,
+    // seconds
+    pub created: i64,
+}
+
+impl NodeIdentity {
+    /// a new node key, the peer id is derived from it
+    pub fn generate() -> Result<Self, String> {
+        let key_pair = Random.generate().map_err(|err| format!("{:?}", err))?;
+        let peer_id = key_peer_id(&key_pair)?;
+        Ok(Self::new(&key_pair, &peer_id))
+    }
+
+    pub fn new(node_key: &KeyPair, peer_id: &PeerId) -> Self {
+        NodeIdentity {
+            node_key: hex::encode(node_key.secret().as_ref()),
+            peer_id: peer_id.to_base58(),
+            created: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    pub fn path<P: AsRef<Path>>(store: P) -> PathBuf {
+        store.as_ref().join(IDENTITY_FILE)
+    }
+
+    /// the identity of the data directory, a broken file is an error, it's never replaced silently
+    pub fn load<P: AsRef<Path>>(store: P) -> Result<Option<Self>, String> {
+        let path = Self::path(store);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let buf = fs::read(&path).map_err(|err| format!("failed to read {:?}, {}", path, err))?;
+        let identity: NodeIdentity = serde_json::from_slice(&buf).map_err(|err| format!("broken identity file {:?}, {}", path, err))?;
+        identity.key_pair()?;
+        identity.peer_id()?;
+        Ok(Some(identity))
+    }
+
+    /// write a temporary file and rename it, a crash never leaves a half written identity. The
+    /// file keeps the node key, only the owner reads it
+    pub fn save<P: AsRef<Path>>(&self, store: P) -> Result<(), String> {
+        fs::create_dir_all(store.as_ref()).map_err(|err| err.to_string())?;
+        let path = Self::path(store);
+        let tmp = path.with_extension("json.tmp");
+        let buf = serde_json::to_vec_pretty(self).map_err(|err| err.to_string())?;
+        let _ = fs::remove_file(&tmp);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp).map_err(|err| format!("failed to create {:?}, {}", tmp, err))?;
+        file.write_all(&buf).and_then(|_| file.sync_all()).map_err(|err| err.to_string())?;
+        fs::rename(&tmp, &path).map_err(|err| err.to_string())
+    }
+
+    pub fn key_pair(&self) -> Result<KeyPair, String> {
+        Secret::from_str(&self.node_key).map_err(|err| format!("invalid node key, {:?}", err))
+            .and_then(|secret| KeyPair::from_secret(secret).map_err(|err| format!("{:?}", err)))
+    }
+
+    pub fn peer_id(&self) -> Result<PeerId, String> {
+        PeerId::from_str(&self.peer_id).map_err(|err| format!("invalid peer id, {:?}", err))
+    }
+}
+
+/// the peer id of the secp256k1 public key of the node key
+pub fn key_peer_id(key_pair: &KeyPair) -> Result<PeerId, String> {
+    secio::SecioKeyPair::secp256k1_raw_key(key_pair.secret().as_ref())
+        .map(|key| key.to_peer_id())
+        .map_err(|err| format!("{:?}", err))
+}
+
+/// the peer id of a recovered public key, the peer id is derived from the compressed key
+pub fn public_peer_id(public: &Public) -> Result<PeerId, String> {
+    let bytes: &[u8] = public.as_ref();
+    if bytes.len() != 64 {
+        return Err(format!("invalid public key length: {}", bytes.len()));
+    }
+    let mut compressed = vec![if bytes[63] & 1 == 0 { 0x02 } else { 0x03 }];
+    compressed.extend_from_slice(&bytes[..32]);
+    Ok(PublicKey::Secp256k1(compressed).into_peer_id())
+}
+
+// a binding older than it is refused, the node signs a new one before it expires
+pub const MAX_BINDING_AGE: Duration = Duration::from_secs(10 * 60);
+// the bindings ahead of the local clock by more than it are refused
+pub const MAX_BINDING_SKEW: Duration = Duration::from_secs(60);
+
+/// ValidatorBinding associates the network identity of a node with its validator key, the node
+/// key and the validator key both sign it, so a compromised node key can't sign consensus messages
+/// and can't claim another validator
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidatorBinding {
+    pub peer_id: String,
+    // the address of the node key
+    pub node: Address,
+    pub validator: Address,
+    // millis
+    pub timestamp: u64,
+    pub node_signature: Signature,
+    pub validator_signature: Signature,
+}
+
+impl ValidatorBinding {
+    /// the validator signs by its signer, the key may be kept by a remote signer
+    pub fn sign(peer_id: &PeerId, node_key: &KeyPair, validator: &Signer) -> Result<Self, String> {
+        let peer_id = peer_id.to_base58();
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        let digest = binding_digest(&peer_id, &node_key.address(), &validator.address(), timestamp);
+        Ok(ValidatorBinding {
+            peer_id,
+            node: node_key.address(),
+            validator: validator.address(),
+            timestamp,
+            node_signature: digest.sign(node_key.secret()).unwrap(),
+            validator_signature: validator.sign(&digest)?,
+        })
+    }
+
+    /// verify the binding of the peer at `now` (millis), the peer id must be derived from the
+    /// node key which signs it and the binding must be fresh, so a captured binding can't be
+    /// replayed for long. It returns the validator address
+    pub fn verify(&self, peer_id: &PeerId, now: u64) -> Result<Address, String> {
+        if self.peer_id != peer_id.to_base58() {
+            return Err(format!("peer id mismatch, expect: {}, got: {}", peer_id.to_base58(), self.peer_id));
+        }
+        if self.timestamp > now + MAX_BINDING_SKEW.as_millis() as u64 {
+            return Err(format!("the binding is ahead of the local clock, timestamp: {}, now: {}", self.timestamp, now));
+        }
+        if self.timestamp + (MAX_BINDING_AGE.as_millis() as u64) < now {
+            return Err(format!("the binding is expired, timestamp: {}, now: {}", self.timestamp, now));
+        }
+        let digest = binding_digest(&self.peer_id, &self.node, &self.validator, self.timestamp);
+        let recover = |signature: &Signature| recover_bytes(signature, digest.as_ref())
+            .map_err(|_| "recover binding signature failed".to_string());
+        let node = recover(&self.node_signature)?;
+        if public_to_address(&node) != self.node {
+            return Err(format!("invalid binding signature, expect: {:?}, got: {:?}", self.node, public_to_address(&node)));
+        }
+        if public_peer_id(&node)? != *peer_id {
+            return Err(format!("the peer id {} isn't derived from the node key {:?}", self.peer_id, self.node));
+        }
+        let validator = public_to_address(&recover(&self.validator_signature)?);
+        if validator != self.validator {
+            return Err(format!("invalid binding signature, expect: {:?}, got: {:?}", self.validator, validator));
+        }
+        Ok(self.validator)
+    }
+}
+
+/// BindingSigner keeps the binding of the local node fresh, a new one is signed when the last one
+/// is half way to its expiry
+pub struct BindingSigner {
+    peer_id: PeerId,
+    node_key: KeyPair,
+    signer: SafeSigner,
+    last: RwLock<Option<ValidatorBinding>>,
+}
+
+impl BindingSigner {
+    pub fn new(peer_id: PeerId, node_key: KeyPair, signer: SafeSigner) -> Self {
+        BindingSigner { peer_id, node_key, signer, last: RwLock::new(None) }
+    }
+
+    /// the binding of the handshake, none if the signer fails
+    pub fn binding(&self) -> Option<ValidatorBinding> {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let refresh = MAX_BINDING_AGE.as_millis() as u64 / 2;
+        if let Some(ref binding) = *self.last.read() {
+            if binding.timestamp + refresh > now {
+                return Some(binding.clone());
+            }
+        }
+        match ValidatorBinding::sign(&self.peer_id, &self.node_key, self.signer.as_ref()) {
+            Ok(binding) => {
+                *self.last.write() = Some(binding.clone());
+                Some(binding)
+            }
+            Err(err) => {
+                error!("Failed to sign the validator binding, err: {}", err);
+                None
+            }
+        }
+    }
+}
+
+fn binding_digest(peer_id: &str, node: &Address, validator: &Address, timestamp: u64) -> Hash {
+    let mut bytes = peer_id.as_bytes().to_vec();
+    bytes.extend_from_slice(node.as_ref());
+    bytes.extend_from_slice(validator.as_ref());
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    digest(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_dir;
+    use std::sync::Arc;
+    use crate::signer::LocalSigner;
+
+    #[test]
+    fn t_validator_binding() {
+        let (node_key, validator_key) = (Random.generate().unwrap(), Random.generate().unwrap());
+        let peer_id = key_peer_id(&node_key).unwrap();
+        let binding = ValidatorBinding::sign(&peer_id, &node_key, &LocalSigner::new(validator_key.clone())).unwrap();
+        let now = binding.timestamp;
+        assert_eq!(binding.verify(&peer_id, now), Ok(validator_key.address()));
+        assert!(binding.verify(&PeerId::random(), now).is_err());
+
+        let mut forged = binding.clone();
+        forged.validator = Random.generate().unwrap().address();
+        assert!(forged.verify(&peer_id, now).is_err());
+
+        // the peer id of another key
+        let other = PeerId::random();
+        let binding = ValidatorBinding::sign(&other, &node_key, &LocalSigner::new(validator_key.clone())).unwrap();
+        assert!(binding.verify(&other, now).unwrap_err().contains("isn't derived"));
+
+        // the replayed and the future bindings
+        let binding = ValidatorBinding::sign(&peer_id, &node_key, &LocalSigner::new(validator_key.clone())).unwrap();
+        let age = MAX_BINDING_AGE.as_millis() as u64;
+        assert!(binding.verify(&peer_id, binding.timestamp + age).is_ok());
+        assert!(binding.verify(&peer_id, binding.timestamp + age + 1).unwrap_err().contains("expired"));
+        assert!(binding.verify(&peer_id, binding.timestamp - MAX_BINDING_SKEW.as_millis() as u64 - 1).unwrap_err().contains("ahead"));
+
+        // the signer reuses the fresh binding
+        let signer = BindingSigner::new(peer_id.clone(), node_key, Arc::new(LocalSigner::new(validator_key)));
+        let first = signer.binding().unwrap();
+        assert_eq!(signer.binding(), Some(first));
+    }
+
+    #[test]
+    fn t_node_identity() {
+        let store = random_dir();
+        assert_eq!(NodeIdentity::load(&*store), Ok(None));
+        let identity = NodeIdentity::generate().unwrap();
+        assert_eq!(identity.peer_id().unwrap(), key_peer_id(&identity.key_pair().unwrap()).unwrap());
+        assert_eq!(identity.peer_id().unwrap(), public_peer_id(identity.key_pair().unwrap().public()).unwrap());
+        identity.save(&*store).unwrap();
+        assert_eq!(NodeIdentity::load(&*store), Ok(Some(identity.clone())));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(NodeIdentity::path(&*store)).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        // the file is replaced by the next save
+        identity.save(&*store).unwrap();
+
+        fs::write(NodeIdentity::path(&*store), b"{").unwrap();
+        assert!(NodeIdentity::load(&*store).is_err());
+    }
+}
@@ -1,9 +1,19 @@
+pub mod address_book;
 pub mod config;
 pub mod discover_service;
+pub mod dns_seed;
 pub mod node;
 pub mod server;
 pub mod session;
 pub mod codec;
+pub mod bandwidth;
+pub mod outbound;
 pub mod protocol;
+pub mod resume;
+pub mod identity;
+pub mod discovery;
+pub mod sync;
+pub mod serve;
+pub mod mux;
 #[macro_use]
 pub use crate::subscriber::*;
\ No newline at end of file
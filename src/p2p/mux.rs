@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ::actix::prelude::*;
+use parking_lot::RwLock;
+
+use super::server::{HandshakePacketFn, TcpServer};
+
+/// the server and the handshake of a chain behind the shared port
+#[derive(Clone)]
+pub struct MuxRoute {
+    pub server: Addr<TcpServer>,
+    pub handshake_fn: Arc<HandshakePacketFn>,
+}
+
+#[derive(Default)]
+struct MuxState {
+    routes: HashMap<u64, MuxRoute>,
+    // the first server binds the port, the others only register their routes
+    claimed: bool,
+}
+
+/// PortMux lets the chains of one process share a p2p port. The inbound session of the shared
+/// listener waits for the remote handshake, and joins the server of its chain id.
+#[derive(Clone, Default)]
+pub struct PortMux {
+    state: Arc<RwLock<MuxState>>,
+}
+
+impl PortMux {
+    /// true for the first caller, it binds the listener for all the chains
+    pub fn claim_listener(&self) -> bool {
+        let mut state = self.state.write();
+        !::std::mem::replace(&mut state.claimed, true)
+    }
+
+    pub fn register(&self, chain_id: u64, route: MuxRoute) {
+        info!("Register the chain {} on the shared p2p port", chain_id);
+        self.state.write().routes.insert(chain_id, route);
+    }
+
+    pub fn route(&self, chain_id: u64) -> Option<MuxRoute> {
+        self.state.read().routes.get(&chain_id).cloned()
+    }
+
+    pub fn chains(&self) -> Vec<u64> {
+        let mut chains: Vec<u64> = self.state.read().routes.keys().cloned().collect();
+        chains.sort();
+        chains
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use libp2p::PeerId;
+    use crate::config::Config;
+    use super::super::protocol::{Handshake, NodeType};
+
+    fn route(chain_id: u64) -> MuxRoute {
+        // the server is never started, nothing is sent to it
+        let (tx, _) = actix::dev::channel::channel::<TcpServer>(1);
+        let mut params = Config::default().consensus_params();
+        params.chain_id = chain_id;
+        let handshake = Handshake::new("0.1.1".to_string(), PeerId::random(), EMPTY_HASH, NodeType::Full, 0, EMPTY_HASH, params);
+        MuxRoute { server: Addr::new(tx), handshake_fn: Arc::new(move || handshake.clone()) }
+    }
+
+    #[test]
+    fn t_port_mux() {
+        let mux = PortMux::default();
+        let other = mux.clone();
+        assert!(mux.claim_listener());
+        assert!(!other.claim_listener());
+        assert!(mux.route(98).is_none());
+        assert!(other.chains().is_empty());
+    }
+
+    // the sessions of a shared port join the chain of the remote handshake, the chains of the
+    // other ports are out of the mux
+    #[test]
+    fn t_port_mux_routes() {
+        let (shared, other) = (PortMux::default(), PortMux::default());
+        shared.register(98, route(98));
+        shared.register(99, route(99));
+        other.register(100, route(100));
+        assert_eq!(shared.chains(), vec![98, 99]);
+        assert_eq!(other.chains(), vec![100]);
+        assert_eq!((shared.route(99).unwrap().handshake_fn)().chain_id(), 99);
+        assert_eq!((shared.route(98).unwrap().handshake_fn)().chain_id(), 98);
+        assert!(shared.route(100).is_none() && other.route(98).is_none());
+        // every port has its own listener
+        assert!(shared.claim_listener() && other.claim_listener());
+        assert!(!shared.clone().claim_listener());
+
+        // a restarted chain replaces its route
+        shared.register(99, route(99));
+        assert_eq!(shared.chains(), vec![98, 99]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::bandwidth::TrafficCategory;
+use super::protocol::{P2PMsgCode, RawMessage};
+use crate::metrics;
+
+// the queued messages are moved into the session mailboxes at least this often
+pub const OUTBOUND_FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// the per-session outbound queues, eg: `[p2p.outbound]`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutboundConfig {
+    // the queue of a session is congested when it holds so many messages, the tx gossip is dropped
+    // until it drains to the low watermark
+    #[serde(default = "default_high_watermark")]
+    pub high_watermark: usize,
+    #[serde(default = "default_low_watermark")]
+    pub low_watermark: usize,
+    // the block and transaction announcements queued longer are dropped, in millis
+    #[serde(default = "default_stale_after", with = "serde_millis")]
+    pub stale_after: Duration,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        OutboundConfig { high_watermark: default_high_watermark(), low_watermark: default_low_watermark(), stale_after: default_stale_after() }
+    }
+}
+
+fn default_high_watermark() -> usize {
+    1024
+}
+
+fn default_low_watermark() -> usize {
+    256
+}
+
+fn default_stale_after() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// the consensus messages are sent before the blocks, the blocks before the tx gossip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Consensus = 0,
+    Blocks = 1,
+    Gossip = 2,
+}
+
+impl Priority {
+    pub fn of(code: &P2PMsgCode) -> Self {
+        match TrafficCategory::from_code(code) {
+            TrafficCategory::Consensus | TrafficCategory::Discovery => Priority::Consensus,
+            TrafficCategory::BlockSync => Priority::Blocks,
+            TrafficCategory::TxGossip => Priority::Gossip,
+        }
+    }
+}
+
+// the broadcast blocks and transactions, a peer which gets them late learns them by the sync anyway
+fn is_announcement(msg: &RawMessage) -> bool {
+    let header = msg.header();
+    header.peer_id.is_none() && (header.code == P2PMsgCode::Block || header.code == P2PMsgCode::Transaction)
+}
+
+fn record_drop(reason: &str) {
+    metrics::inc_counter("p2p_session_dropped_total");
+    metrics::inc_counter(&format!("p2p_outbound_dropped_total{{reason=\"{}\"}}", reason));
+}
+
+/// OutboundQueue holds the messages to a session until its mailbox has room, a slow peer
+/// fills its own queue instead of blocking the others
+pub struct OutboundQueue {
+    config: OutboundConfig,
+    // by priority, the oldest message is in the front
+    queues: [VecDeque<(RawMessage, Instant)>; 3],
+    congested: bool,
+}
+
+impl OutboundQueue {
+    pub fn new(config: OutboundConfig) -> Self {
+        OutboundQueue { config, queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()], congested: false }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_congested(&self) -> bool {
+        self.congested
+    }
+
+    /// queue the message, returns false if it's dropped
+    pub fn push(&mut self, msg: RawMessage, now: Instant) -> bool {
+        let priority = Priority::of(&msg.header().code);
+        if self.len() >= self.config.high_watermark {
+            self.congested = true;
+        }
+        if self.congested && priority == Priority::Gossip {
+            record_drop("congested");
+            return false;
+        }
+        // the full queue makes room by the oldest message of a lower priority
+        if self.len() >= self.config.high_watermark && !self.evict_below(priority) {
+            record_drop("full");
+            return false;
+        }
+        self.queues[priority as usize].push_back((msg, now));
+        true
+    }
+
+    /// the next message by priority, the stale announcements are dropped
+    pub fn pop(&mut self, now: Instant) -> Option<(RawMessage, Instant)> {
+        let stale_after = self.config.stale_after;
+        let mut next = None;
+        for queue in self.queues.iter_mut() {
+            while let Some((msg, queued_at)) = queue.pop_front() {
+                if is_announcement(&msg) && now.duration_since(queued_at) >= stale_after {
+                    record_drop("stale");
+                    continue;
+                }
+                next = Some((msg, queued_at));
+                break;
+            }
+            if next.is_some() {
+                break;
+            }
+        }
+        if self.congested && self.len() <= self.config.low_watermark {
+            self.congested = false;
+        }
+        next
+    }
+
+    /// put back the message which the mailbox refused, it's sent first next time
+    pub fn requeue(&mut self, msg: RawMessage, queued_at: Instant) {
+        let priority = Priority::of(&msg.header().code);
+        self.queues[priority as usize].push_front((msg, queued_at));
+    }
+
+    pub fn clear(&mut self) {
+        self.queues.iter_mut().for_each(|queue| queue.clear());
+    }
+
+    fn evict_below(&mut self, priority: Priority) -> bool {
+        for queue in self.queues.iter_mut().skip(priority as usize + 1).rev() {
+            if queue.pop_front().is_some() {
+                record_drop("evicted");
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::protocol::Header;
+
+    fn message(code: P2PMsgCode) -> RawMessage {
+        RawMessage::new(Header::new(code, 10, 0, None), vec![])
+    }
+
+    #[test]
+    fn t_outbound_queue() {
+        let config = OutboundConfig { high_watermark: 3, low_watermark: 1, stale_after: Duration::from_secs(5) };
+        let mut queue = OutboundQueue::new(config);
+        let now = Instant::now();
+        assert!(queue.push(message(P2PMsgCode::Transaction), now));
+        assert!(queue.push(message(P2PMsgCode::Block), now));
+        assert!(queue.push(message(P2PMsgCode::Consensus), now));
+        // congested, the gossip is dropped and the consensus message evicts the gossip
+        assert!(!queue.push(message(P2PMsgCode::Transaction), now));
+        assert!(queue.is_congested());
+        assert!(queue.push(message(P2PMsgCode::Consensus), now));
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.pop(now).unwrap().0.header().code, P2PMsgCode::Consensus);
+        let (msg, queued_at) = queue.pop(now).unwrap();
+        assert_eq!(msg.header().code, P2PMsgCode::Consensus);
+        queue.requeue(msg, queued_at);
+        assert_eq!(queue.pop(now).unwrap().0.header().code, P2PMsgCode::Consensus);
+        assert!(!queue.is_congested());
+
+        // the announced block is stale
+        assert_eq!(queue.len(), 1);
+        assert!(queue.pop(now + Duration::from_secs(5)).is_none());
+        assert!(queue.is_empty());
+    }
+}
@@ -6,6 +6,13 @@ use libp2p::{PeerId, Multiaddr};
 use cryptocurrency_kit::crypto::{CryptoHash, Hash, hash};
 use cryptocurrency_kit::storage::values::StorageValue;
 use serde::{Deserialize, Serialize};
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use crate::config::ConsensusParams;
+use crate::types::Height;
+use super::discovery::DiscoveryRecord;
+use super::identity::ValidatorBinding;
+use super::sync::default_capabilities;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Message, Eq, PartialEq)]
 pub enum P2PMsgCode {
@@ -15,11 +22,47 @@ pub enum P2PMsgCode {
     Block,
     Consensus,
     Sync,
+    // the historical blocks with their commit certificates, see `serve::SyncReply`
+    SyncReply,
+    // the signed addresses of the known peers, see `discovery::PeerRecords`
+    Records,
 }
 
 implement_storagevalue_traits! {P2PMsgCode}
 implement_cryptohash_traits! {P2PMsgCode}
 
+impl Encodable for P2PMsgCode {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        let code: u8 = match self {
+            P2PMsgCode::Ping => 0,
+            P2PMsgCode::Handshake => 1,
+            P2PMsgCode::Transaction => 2,
+            P2PMsgCode::Block => 3,
+            P2PMsgCode::Consensus => 4,
+            P2PMsgCode::Sync => 5,
+            P2PMsgCode::SyncReply => 6,
+            P2PMsgCode::Records => 7,
+        };
+        s.append(&code);
+    }
+}
+
+impl Decodable for P2PMsgCode {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        match rlp.as_val::<u8>()? {
+            0 => Ok(P2PMsgCode::Ping),
+            1 => Ok(P2PMsgCode::Handshake),
+            2 => Ok(P2PMsgCode::Transaction),
+            3 => Ok(P2PMsgCode::Block),
+            4 => Ok(P2PMsgCode::Consensus),
+            5 => Ok(P2PMsgCode::Sync),
+            6 => Ok(P2PMsgCode::SyncReply),
+            7 => Ok(P2PMsgCode::Records),
+            _ => Err(DecoderError::Custom("unknown p2p message code")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum BoundType {
     InBound,
@@ -35,6 +78,26 @@ pub struct RawMessage {
 implement_storagevalue_traits! {RawMessage}
 implement_cryptohash_traits! {RawMessage}
 
+impl Encodable for RawMessage {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.header);
+        s.append(&self.payload);
+    }
+}
+
+impl Decodable for RawMessage {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(RawMessage {
+            header: rlp.val_at(0)?,
+            payload: rlp.val_at(1)?,
+        })
+    }
+}
+
 impl RawMessage {
     pub fn new(header: Header, payload: Vec<u8>) -> Self {
         RawMessage {
@@ -72,6 +135,38 @@ pub struct Header {
 implement_cryptohash_traits! {Header}
 implement_storagevalue_traits! {Header}
 
+// the peer id is a list of zero or one item
+impl Encodable for Header {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append(&self.code);
+        s.append(&(self.ttl as u64));
+        s.append(&self.create_time);
+        match self.peer_id {
+            Some(ref peer_id) => s.begin_list(1).append(peer_id),
+            None => s.begin_list(0),
+        };
+    }
+}
+
+impl Decodable for Header {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 4 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        let peer_id: Vec<Vec<u8>> = rlp.list_at(3)?;
+        if peer_id.len() > 1 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(Header {
+            code: rlp.val_at(0)?,
+            ttl: rlp.val_at::<u64>(1)? as usize,
+            create_time: rlp.val_at(2)?,
+            peer_id: peer_id.into_iter().next(),
+        })
+    }
+}
+
 impl Header {
     pub fn new(code: P2PMsgCode, ttl: usize, create_time: u64, peer_id: Option<Vec<u8>>) -> Self {
         Header { code: code, ttl: ttl, create_time: create_time, peer_id: peer_id }
@@ -80,26 +175,85 @@ impl Header {
 
 pub type Payload = Vec<u8>;
 
+// the p2p protocol version, the version 2 speaks rlp after the handshake, the version 3 replies
+// the sync requests by `P2PMsgCode::SyncReply`, the version 4 signs its addresses and gossips the
+// records of the peers
+pub const PROTOCOL_VERSION: u32 = 4;
+// the lowest version which the peers can talk with, the newer peers are accepted and talk at the
+// lower of the two versions, eg: the codec and the replies are negotiated by it
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+// the lowest version which decodes `P2PMsgCode::SyncReply`, the older peers are served the blocks
+pub const SYNC_REPLY_VERSION: u32 = 3;
+// the lowest version which must sign its addresses in the handshake and decodes `P2PMsgCode::Records`
+pub const RECORDS_VERSION: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+pub enum NodeType {
+    Validator,
+    Full,
+    Light,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Handshake {
     version: String,
     peer_id: String,
     genesis: Hash,
+    protocol_version: u32,
+    node_type: NodeType,
+    height: Height,
+    block_hash: Hash,
+    consensus_params: ConsensusParams,
+    // the validator which runs the node, it's absent on the full nodes
+    #[serde(default)]
+    binding: Option<ValidatorBinding>,
+    // the sync capability flags, eg: `CAP_ARCHIVE`, the old peers are told by the node type
+    #[serde(default)]
+    capabilities: Option<u32>,
+    // the signed addresses of the node, the old peers don't send it
+    #[serde(default)]
+    discovery: Option<DiscoveryRecord>,
 }
 
 implement_storagevalue_traits! {Handshake}
 implement_cryptohash_traits! {Handshake}
 
 impl Handshake {
-    pub fn new(version: String, peer_id: PeerId, genesis: Hash) -> Self {
+    pub fn new(version: String, peer_id: PeerId, genesis: Hash, node_type: NodeType, height: Height, block_hash: Hash, consensus_params: ConsensusParams) -> Self {
         let peer_id = peer_id.to_base58();
         Handshake {
             version: version,
             peer_id: peer_id,
             genesis: genesis,
+            protocol_version: PROTOCOL_VERSION,
+            node_type: node_type,
+            height: height,
+            block_hash: block_hash,
+            consensus_params: consensus_params,
+            binding: None,
+            capabilities: Some(default_capabilities(node_type)),
+            discovery: None,
         }
     }
 
+    pub fn with_binding(mut self, binding: Option<ValidatorBinding>) -> Self {
+        self.binding = binding;
+        self
+    }
+
+    pub fn binding(&self) -> Option<&ValidatorBinding> {
+        self.binding.as_ref()
+    }
+
+    pub fn with_discovery(mut self, discovery: Option<DiscoveryRecord>) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    pub fn discovery(&self) -> Option<&DiscoveryRecord> {
+        self.discovery.as_ref()
+    }
+
     pub fn version(&self) -> &String {
         &self.version
     }
@@ -111,4 +265,48 @@ impl Handshake {
     pub fn genesis(&self) -> &Hash {
         &self.genesis
     }
+
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    pub fn with_protocol_version(mut self, protocol_version: u32) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// the version which the local node talks with the peer at, the newer peers talk at the local
+    /// version, the older ones at theirs
+    pub fn negotiated_version(&self) -> u32 {
+        ::std::cmp::min(self.protocol_version, PROTOCOL_VERSION)
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        self.node_type
+    }
+
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities.unwrap_or_else(|| default_capabilities(self.node_type))
+    }
+
+    pub fn height(&self) -> Height {
+        self.height
+    }
+
+    pub fn block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn consensus_params(&self) -> &ConsensusParams {
+        &self.consensus_params
+    }
+
+    /// the fingerprint of consensus-critical parameters
+    pub fn chain_id(&self) -> u64 {
+        self.consensus_params.chain_id
+    }
+
+    pub fn fingerprint(&self) -> Hash {
+        self.consensus_params.fingerprint()
+    }
 }
\ No newline at end of file
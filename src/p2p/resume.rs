@@ -0,0 +1,140 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use cryptocurrency_kit::crypto::Hash;
+use libp2p::PeerId;
+
+use crate::metrics;
+use crate::metrics::cache::CacheMetrics;
+use crate::types::Height;
+
+// the max message hashes remembered for a peer
+pub const MAX_KNOWN_MESSAGES: usize = 1 << 12;
+// the max disconnected peers waiting to resume
+const MAX_RESUMABLE_SESSIONS: usize = 1 << 10;
+
+lazy_static! {
+    // the known messages of all the peers are reported as one cache, it has no size gauge
+    static ref KNOWN_MESSAGES_METRICS: CacheMetrics = CacheMetrics::new("p2p_known_messages");
+}
+
+/// the hashes of the messages a peer has sent or received, the oldest is forgotten first
+#[derive(Debug, Clone, Default)]
+pub struct KnownMessages {
+    order: VecDeque<Hash>,
+    hashes: BTreeSet<Hash>,
+}
+
+impl KnownMessages {
+    pub fn insert(&mut self, hash: Hash) {
+        if !self.hashes.insert(hash) {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > MAX_KNOWN_MESSAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+                KNOWN_MESSAGES_METRICS.evict(1);
+            }
+        }
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        let known = self.hashes.contains(hash);
+        KNOWN_MESSAGES_METRICS.record(known);
+        known
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+}
+
+/// the state of a peer session that survives a brief disconnect
+#[derive(Debug, Clone)]
+pub struct PeerState {
+    pub first_connected: chrono::DateTime<chrono::Utc>,
+    // the messages received from the peer
+    pub score: u64,
+    pub known: KnownMessages,
+    // the highest height the peer has announced
+    pub height: Height,
+}
+
+impl PeerState {
+    pub fn new(height: Height) -> Self {
+        PeerState { first_connected: chrono::Utc::now(), score: 0, known: KnownMessages::default(), height }
+    }
+}
+
+/// ResumableSessions keeps the state of the disconnected peers for a grace window, a peer which
+/// reconnects within it resumes the state instead of starting as a new peer
+pub struct ResumableSessions {
+    grace: Duration,
+    sessions: HashMap<PeerId, (Instant, PeerState)>,
+}
+
+impl ResumableSessions {
+    pub fn new(grace: Duration) -> Self {
+        ResumableSessions { grace, sessions: HashMap::new() }
+    }
+
+    pub fn suspend(&mut self, peer_id: PeerId, state: PeerState) {
+        self.expire();
+        if self.sessions.len() >= MAX_RESUMABLE_SESSIONS {
+            return;
+        }
+        self.sessions.insert(peer_id, (Instant::now(), state));
+    }
+
+    /// take the state of the peer, the height is updated by the new handshake
+    pub fn resume(&mut self, peer_id: &PeerId, height: Height) -> Option<PeerState> {
+        self.expire();
+        let (_, mut state) = self.sessions.remove(peer_id)?;
+        state.height = ::std::cmp::max(state.height, height);
+        metrics::inc_counter("p2p_session_resumed_total");
+        Some(state)
+    }
+
+    fn expire(&mut self) {
+        let grace = self.grace;
+        self.sessions.retain(|_, (suspended, _)| suspended.elapsed() < grace);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::hash;
+
+    #[test]
+    fn t_resumable_sessions() {
+        let peer_id = PeerId::random();
+        let mut state = PeerState::new(10);
+        state.score = 3;
+        state.known.insert(hash(vec![1]));
+
+        let mut sessions = ResumableSessions::new(Duration::from_secs(10));
+        assert!(sessions.resume(&peer_id, 11).is_none());
+        sessions.suspend(peer_id.clone(), state);
+        let state = sessions.resume(&peer_id, 11).unwrap();
+        assert_eq!((state.score, state.height), (3, 11));
+        assert!(state.known.contains(&hash(vec![1])));
+        assert!(sessions.resume(&peer_id, 11).is_none());
+
+        let mut sessions = ResumableSessions::new(Duration::from_millis(1));
+        sessions.suspend(peer_id.clone(), PeerState::new(0));
+        ::std::thread::sleep(Duration::from_millis(5));
+        assert!(sessions.resume(&peer_id, 0).is_none());
+    }
+
+    #[test]
+    fn t_known_messages() {
+        let mut known = KnownMessages::default();
+        for idx in 0..MAX_KNOWN_MESSAGES + 1 {
+            known.insert(hash((idx as u64).to_be_bytes().to_vec()));
+        }
+        assert_eq!(known.len(), MAX_KNOWN_MESSAGES);
+        assert!(!known.contains(&hash(0_u64.to_be_bytes().to_vec())));
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use futures::future;
+use libp2p::PeerId;
+use parking_lot::RwLock;
+use tokio_threadpool::ThreadPool;
+
+use super::bandwidth::TokenBucket;
+use crate::consensus::types::CommitCertificate;
+use crate::core::ledger::Ledger;
+use crate::metrics;
+use crate::types::Height;
+use crate::types::block::Block;
+
+// a reply carries at most so many blocks
+pub const MAX_SERVE_BATCH: usize = 32;
+// the reply is closed when its blocks exceed it, a single bigger block is still served alone
+pub const MAX_SERVE_BATCH_BYTES: usize = 1 << 20;
+// the blocks served for one sync request, the peer requests the rest after it imports them
+pub const MAX_SERVE_BLOCKS: u64 = 256;
+// the bytes per second served to every peer
+pub const DEFAULT_SERVE_RATE: u64 = 4 << 20;
+
+/// SyncReply is a batch of the historical blocks, the certificates let the receiver check that
+/// the blocks were committed by the quorum of their validators.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SyncReply {
+    pub blocks: Vec<Block>,
+    // the certificates of the blocks which have one, in the order of the blocks
+    pub certificates: Vec<CommitCertificate>,
+}
+
+implement_storagevalue_traits! {SyncReply}
+implement_cryptohash_traits! {SyncReply}
+
+impl SyncReply {
+    /// every certificate must commit one of the blocks by the validators of its height
+    pub fn verify_certificates<F>(&self, validators_of: F) -> Result<(), String>
+        where F: Fn(Height) -> Vec<Address> {
+        for certificate in &self.certificates {
+            let height = certificate.height();
+            let block = self.blocks.iter().find(|block| block.height() == height)
+                .ok_or(format!("the certificate of height {} has no block", height))?;
+            if block.hash() != certificate.block_hash {
+                return Err(format!("the certificate of height {} commits another block", height));
+            }
+            certificate.verify(&validators_of(height))?;
+        }
+        Ok(())
+    }
+}
+
+/// split the blocks into the replies which are bounded by `MAX_SERVE_BATCH` and
+/// `MAX_SERVE_BATCH_BYTES`
+pub fn batch_blocks<I>(blocks: I) -> Vec<SyncReply>
+    where I: Iterator<Item=(Block, Option<CommitCertificate>)> {
+    let (mut replies, mut reply, mut bytes) = (vec![], SyncReply::default(), 0);
+    for (block, certificate) in blocks {
+        let size = ::rlp::encode(&block).len();
+        if !reply.blocks.is_empty() && (reply.blocks.len() >= MAX_SERVE_BATCH || bytes + size > MAX_SERVE_BATCH_BYTES) {
+            replies.push(::std::mem::replace(&mut reply, SyncReply::default()));
+            bytes = 0;
+        }
+        reply.certificates.extend(certificate);
+        reply.blocks.push(block);
+        bytes += size;
+    }
+    if !reply.blocks.is_empty() {
+        replies.push(reply);
+    }
+    replies
+}
+
+/// read the blocks from `from` and their certificates, at most `MAX_SERVE_BLOCKS` of them
+pub fn read_blocks(ledger: &RwLock<Ledger>, from: Height) -> Vec<SyncReply> {
+    let ledger = ledger.read();
+    let to = ::std::cmp::min(*ledger.get_last_block_height(), from.saturating_add(MAX_SERVE_BLOCKS - 1));
+    let blocks = (from..=to).map(|height| (ledger.get_block_by_height(height), height))
+        .take_while(|(block, _)| block.is_some())
+        .map(|(block, height)| (block.unwrap(), ledger.get_commit_certificate(height)));
+    batch_blocks(blocks)
+}
+
+/// BlockServer serves the historical blocks of the store to the syncing peers, the store is read
+/// by its own pool, and the bytes served to every peer are limited by its own budget.
+pub struct BlockServer {
+    ledger: Arc<RwLock<Ledger>>,
+    // bytes per second
+    rate: u64,
+    budgets: HashMap<PeerId, TokenBucket>,
+    // the peers whose request is being read or waits for the budget, their other requests are
+    // ignored until it's served
+    pending: HashSet<PeerId>,
+    pool: ThreadPool,
+}
+
+impl BlockServer {
+    pub fn new(ledger: Arc<RwLock<Ledger>>, rate: u64) -> Self {
+        BlockServer { ledger, rate, budgets: HashMap::new(), pending: HashSet::new(), pool: ThreadPool::new() }
+    }
+
+    /// read the blocks from `from` in the pool and hand the replies to `done`, it's false if the
+    /// last request of the peer is still served
+    pub fn request<F>(&mut self, peer: &PeerId, from: Height, done: F) -> bool
+        where F: FnOnce(Vec<SyncReply>) + Send + 'static {
+        if !self.pending.insert(peer.clone()) {
+            metrics::inc_counter("p2p_serve_busy_total");
+            return false;
+        }
+        let ledger = self.ledger.clone();
+        self.pool.spawn(future::lazy(move || {
+            done(read_blocks(&ledger, from));
+            Ok(())
+        }));
+        true
+    }
+
+    /// the replies which fit the budget of the peer, the rest is returned with the time to wait
+    /// for the budget, it's never dropped
+    pub fn admit(&mut self, peer: &PeerId, replies: Vec<SyncReply>) -> (Vec<SyncReply>, Option<(Duration, Vec<SyncReply>)>) {
+        let rate = self.rate;
+        let budget = self.budgets.entry(peer.clone()).or_insert_with(|| TokenBucket::new(rate));
+        let mut served = vec![];
+        let mut replies = replies.into_iter();
+        while let Some(reply) = replies.next() {
+            if let Some(wait) = budget.consume_or_wait(reply.clone().into_bytes().len() as u64) {
+                metrics::inc_counter("p2p_serve_throttled_total");
+                debug!("The serve budget of the peer is used up, peer: {}, from height: {}, wait: {:?}", peer.to_base58(), reply.blocks[0].height(), wait);
+                let rest = ::std::iter::once(reply).chain(replies).collect();
+                return (served, Some((wait, rest)));
+            }
+            metrics::inc_counter_by("p2p_served_blocks_total", reply.blocks.len() as u64);
+            served.push(reply);
+        }
+        self.pending.remove(peer);
+        (served, None)
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.budgets.remove(peer);
+        self.pending.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use std::borrow::Cow;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::consensus::types::View;
+    use crate::core::test_util::{memory_ledger, sealed_block};
+    use crate::p2p::protocol::{P2PMsgCode, PROTOCOL_VERSION, SYNC_REPLY_VERSION};
+    use crate::p2p::server::sync_reply_message;
+    use crate::types::block::{Blocks, Header};
+
+    #[test]
+    fn t_batch_blocks() {
+        let block = |height: Height| Block::new(Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, height, height, None), vec![]);
+        let certificate = |block: &Block| CommitCertificate::new(View { round: 0, height: block.height() }, block.hash(), vec![]);
+
+        let blocks: Vec<Block> = (1..=MAX_SERVE_BATCH as Height + 2).map(block).collect();
+        let replies = batch_blocks(blocks.iter().map(|block| (block.clone(), Some(certificate(block)))));
+        assert_eq!(replies.iter().map(|reply| reply.blocks.len()).collect::<Vec<_>>(), vec![MAX_SERVE_BATCH, 2]);
+        assert_eq!(replies[1].certificates.len(), 2);
+        assert!(batch_blocks(vec![].into_iter()).is_empty());
+
+        // the certificate of another block
+        let mut reply = replies[1].clone();
+        assert!(reply.verify_certificates(|_| vec![]).is_err());
+        reply.certificates[0].block_hash = blocks[0].hash();
+        assert!(reply.verify_certificates(|_| vec![]).unwrap_err().contains("commits another block"));
+    }
+
+    fn served_ledger(blocks: Height) -> Arc<RwLock<Ledger>> {
+        let key_pair = Random.generate().unwrap();
+        let mut ledger = memory_ledger(&key_pair);
+        let genesis = Block::new(Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 0, 0, None), vec![]);
+        ledger.add_genesis_block(&genesis);
+        ledger.reload_meta();
+        let mut parent = genesis.hash();
+        for height in 1..=blocks {
+            let block = sealed_block(parent, height, &key_pair);
+            parent = block.hash();
+            ledger.add_block(&block);
+        }
+        Arc::new(RwLock::new(ledger))
+    }
+
+    #[test]
+    fn t_serve_blocks() {
+        let ledger = served_ledger(40);
+        let replies = read_blocks(&ledger, 5);
+        assert_eq!(replies.iter().map(|reply| reply.blocks.len()).sum::<usize>(), 36);
+        assert_eq!(replies[0].blocks[0].height(), 5);
+        assert!(read_blocks(&ledger, 41).is_empty());
+
+        // the store is read by the pool, the second request waits for the first one
+        let peer = PeerId::random();
+        let mut server = BlockServer::new(ledger, 1);
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        assert!(server.request(&peer, 5, move |replies| tx.send(replies).unwrap()));
+        assert!(!server.request(&peer, 5, |_| {}));
+        let replies = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // the tiny budget lets one reply through, the rest waits for it and is never dropped
+        let (served, deferred) = server.admit(&peer, replies);
+        assert_eq!(served.len(), 1);
+        let (wait, rest) = deferred.unwrap();
+        assert!(wait > Duration::from_secs(1));
+        assert_eq!(rest[0].blocks[0].height(), 5 + MAX_SERVE_BATCH as Height);
+        assert!(!server.request(&peer, 5, |_| {}));
+
+        // the removed peer requests again
+        server.remove_peer(&peer);
+        assert!(server.request(&peer, 40, |_| {}));
+        let (served, deferred) = server.admit(&peer, read_blocks(&server.ledger, 40));
+        assert_eq!((served.len(), deferred.is_none()), (1, true));
+        assert!(server.request(&peer, 40, |_| {}));
+    }
+
+    #[test]
+    fn t_sync_reply_message() {
+        let peer = PeerId::random();
+        let reply = read_blocks(&served_ledger(3), 1).remove(0);
+        let msg = sync_reply_message(&peer, SYNC_REPLY_VERSION, reply.clone());
+        assert_eq!(msg.header().code, P2PMsgCode::SyncReply);
+        assert_eq!(SyncReply::from_bytes(Cow::from(msg.payload())).blocks.len(), 3);
+        // the peers of the older protocol can't decode the reply
+        let msg = sync_reply_message(&peer, SYNC_REPLY_VERSION - 1, reply);
+        assert_eq!(msg.header().code, P2PMsgCode::Block);
+        assert_eq!(Blocks::from_rlp(msg.payload()).unwrap().0.len(), 3);
+        assert!(PROTOCOL_VERSION >= SYNC_REPLY_VERSION);
+    }
+}
@@ -1,13 +1,16 @@
 use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::net;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use ::actix::prelude::*;
 use actix_broker::BrokerSubscribe;
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::{Address, KeyPair};
 use futures::prelude::*;
 use libp2p::{
     core::nodes::swarm::NetworkBehaviour,
@@ -22,41 +25,94 @@ use uuid::Uuid;
 use lru_time_cache::LruCache;
 use chrono::Local;
 
-use super::codec::MsgPacketCodec;
-use super::protocol::{BoundType, RawMessage, Header as RawHeader, P2PMsgCode, Payload, Handshake};
+use super::bandwidth::{wire_size, OutboundQuota, TrafficCategory};
+use super::codec::{MsgPacketCodec, WireCodec, WireSelector};
+use super::outbound::{OutboundConfig, OutboundQueue, OUTBOUND_FLUSH_INTERVAL};
+use super::resume::{PeerState, ResumableSessions};
+use super::protocol::{BoundType, RawMessage, Header as RawHeader, P2PMsgCode, Payload, Handshake, NodeType, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, RECORDS_VERSION, SYNC_REPLY_VERSION};
+use super::serve::{BlockServer, SyncReply};
 use super::session::Session;
+use super::discovery::{DiscoveryRecord, DiscoveryRecords, PeerRecords, MAX_GOSSIP_RECORDS};
+use super::identity::BindingSigner;
+use super::sync::{PeerClass, SyncPeer, SyncPolicy, SyncStatus};
+use super::mux::{MuxRoute, PortMux};
 use crate::{
+    core::chain::Chain,
+    types::Height,
     types::block::Blocks,
     common::{multiaddr_to_ipv4, random_uuid},
     error::P2PError,
+    metrics,
+    metrics::cache::MeteredCache,
     subscriber::P2PEvent,
     subscriber::events::{BroadcastEvent, ChainEvent},
+    util::supervise::{guard, record_restart},
 };
 
 pub const MAX_OUTBOUND_CONNECTION_MAILBOX: usize = 1 << 10;
 pub const MAX_INBOUND_CONNECTION_MAILBOX: usize = 1 << 9;
+// the sessions ping every second, a peer which sent neither a message nor a ping within the
+// timeout is dropped
+pub const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const PEER_REAP_INTERVAL: Duration = Duration::from_secs(5);
 
 lazy_static! {
     pub static ref ZERO_PEER: PeerId =
         { PeerId::from_str("QmX5e9hkQf7B45e2MZf38vhsC2wfA5aKQrrBuLujwaUBGw").unwrap() };
 }
 
-pub type AuthorFn = Fn(Handshake) -> bool;
+pub type AuthorFn = Fn(Handshake) -> Result<(), P2PError>;
 pub type HandleMsgFn = Fn(PeerId, RawMessage) -> Result<(), String>;
 
-pub type HandshakePacketFn = Fn() -> Handshake;
+pub type HandshakePacketFn = Fn() -> Handshake + Send + Sync;
 
-pub fn author_handshake(genesis: Hash) -> impl Fn(Handshake) -> bool {
+pub fn author_handshake(genesis: Hash, chain_id: u64) -> impl Fn(Handshake) -> Result<(), P2PError> {
     move |handshake: Handshake| {
         if *handshake.genesis() != genesis {
-            return false;
+            return Err(P2PError::DifferentGenesis);
+        }
+        // the devnets with the same genesis must not be connected
+        if handshake.chain_id() != chain_id {
+            return Err(P2PError::DifferentChainId(chain_id, handshake.chain_id()));
+        }
+        if let Some(binding) = handshake.binding() {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            binding.verify(&handshake.peer_id(), now).map_err(P2PError::InvalidBinding)?;
         }
-        true
+        // the newer peers are accepted, they talk at the local version during a rolling upgrade
+        if handshake.protocol_version() < MIN_PROTOCOL_VERSION {
+            return Err(P2PError::IncompatibleProtocol(PROTOCOL_VERSION, handshake.protocol_version()));
+        }
+        Ok(())
+    }
+}
+
+/// the handshake of the local node, `discovery` is the node key with the advertised address, the
+/// record is signed for every handshake and its sequence number is the signing time, so a newer
+/// record always supersedes the older ones
+pub fn handshake_packet(peer_id: PeerId, chain: Arc<Chain>, node_type: NodeType, binding: Option<BindingSigner>, discovery: Option<(KeyPair, Multiaddr)>) -> impl Fn() -> Handshake + Send + Sync {
+    let consensus_params = chain.config.consensus_params();
+    move || {
+        let record = discovery.as_ref().map(|(node_key, address)| {
+            let seq = chrono::Utc::now().timestamp_millis() as u64;
+            DiscoveryRecord::sign(&peer_id, &[address.clone()], seq, node_key)
+        });
+        let last_block = chain.get_last_block();
+        Handshake::new(
+            "0.1.1".to_string(),
+            peer_id.clone(),
+            chain.get_genesis().hash(),
+            node_type,
+            last_block.height(),
+            last_block.hash(),
+            consensus_params.clone(),
+        ).with_binding(binding.as_ref().and_then(|binding| binding.binding())).with_discovery(record)
     }
 }
 
 pub enum ServerEvent {
-    Connected(PeerId, BoundType, Addr<Session>, RawMessage),
+    // the remote address is absent if the socket is gone
+    Connected(PeerId, BoundType, Addr<Session>, RawMessage, Option<Multiaddr>),
     Disconnected(PeerId),
     Message(PeerId, RawMessage),
     Ping(PeerId),
@@ -79,36 +135,197 @@ pub struct TcpServer {
     key: Option<secio::SecioKeyPair>,
     node_info: (PeerId, Multiaddr),
     peers: HashMap<PeerId, ConnectInfo>,
-    genesis: Hash,
-    cache: LruCache<Hash, bool>,
+    handshake_fn: Arc<HandshakePacketFn>,
+    cache: MeteredCache<Hash, bool>,
     author_fn: Box<AuthorFn>,
     handles: Box<HandleMsgFn>,
+    // the mailbox capacity of every session, the messages to a slow peer wait in its outbound queue
+    session_mailbox_capacity: usize,
+    outbound_config: OutboundConfig,
+    // the outbound quotas of the traffic categories, shared by all sessions
+    outbound_quota: OutboundQuota,
+    // the state of the peers which disconnected recently
+    resumable: ResumableSessions,
+    // the listener is bound again when the server restarts
+    listen_addr: net::SocketAddr,
+    listener: Option<TcpListener>,
+    restarted: bool,
+    // the port is shared with the other chains of the process, only one server listens on it
+    mux: Option<PortMux>,
+    listening: bool,
+    // the sync strategy of every peer class, it's switched at runtime by `SetSyncPolicy`
+    sync_policy: SyncPolicy,
+    // the verified addresses of the peers, the unsigned announcements can't override them, they're
+    // learned from the handshakes and the gossip of the peers
+    discovery: DiscoveryRecords,
+    // serves the sync requests from the store, the requests are left to the handles without it
+    block_server: Option<BlockServer>,
+    // the largest frame read from the sessions
+    max_frame: usize,
 }
 
 struct ConnectInfo {
     connect_time: chrono::DateTime<chrono::Utc>,
+    // the last time which the peer sent a message or a ping
+    last_seen: chrono::DateTime<chrono::Utc>,
+    address: Option<Multiaddr>,
     bound_type: BoundType,
     pid: Addr<Session>,
+    // the peer status exchanged by handshake
+    handshake: Handshake,
+    // the peer has the same consensus fingerprint
+    fingerprint_matched: bool,
+    // the state which is kept when the peer reconnects within the grace window
+    state: PeerState,
+    messages_in: u64,
+    messages_out: u64,
+    // the messages waiting for room in the session mailbox
+    outbound: OutboundQueue,
 }
 
 impl ConnectInfo {
-    fn new(connect_time: chrono::DateTime<chrono::Utc>, bound_type: BoundType, pid: Addr<Session>) -> Self {
+    fn new(connect_time: chrono::DateTime<chrono::Utc>, address: Option<Multiaddr>, bound_type: BoundType, pid: Addr<Session>, handshake: Handshake, fingerprint_matched: bool, state: PeerState, outbound: OutboundQueue) -> Self {
         ConnectInfo {
             connect_time: connect_time,
+            last_seen: connect_time,
+            address: address,
             bound_type: bound_type,
             pid: pid,
+            handshake: handshake,
+            fingerprint_matched: fingerprint_matched,
+            state: state,
+            messages_in: 0,
+            messages_out: 0,
+            outbound: outbound,
+        }
+    }
+
+    fn peer_info(&self, peer_id: &PeerId) -> PeerInfo {
+        PeerInfo {
+            peer_id: peer_id.to_base58(),
+            address: self.address.as_ref().map(|address| address.to_string()),
+            direction: match self.bound_type {
+                BoundType::InBound => "inbound",
+                BoundType::OutBound => "outbound",
+            },
+            node_type: self.handshake.node_type(),
+            validator: self.handshake.binding().map(|binding| binding.validator),
+            handshake_height: self.handshake.height(),
+            height: self.state.height,
+            protocol_version: self.handshake.protocol_version(),
+            fingerprint_matched: self.fingerprint_matched,
+            score: self.state.score,
+            messages_in: self.messages_in,
+            messages_out: self.messages_out,
+            connected_at: self.connect_time.timestamp_millis() as u64,
+            last_seen: self.last_seen.timestamp_millis() as u64,
         }
     }
 }
 
+/// the statistics of a connected peer, see `GET /peers`
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub address: Option<String>,
+    pub direction: &'static str,
+    pub node_type: NodeType,
+    // the validator which the peer is bound to by its handshake
+    pub validator: Option<Address>,
+    pub handshake_height: Height,
+    pub height: Height,
+    pub protocol_version: u32,
+    pub fingerprint_matched: bool,
+    pub score: u64,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    // millis
+    pub connected_at: u64,
+    pub last_seen: u64,
+}
+
+/// list the connected peers, ordered by the peer id
+#[derive(Clone)]
+pub struct GetPeers;
+
+impl Message for GetPeers {
+    type Result = Vec<PeerInfo>;
+}
+
+impl Handler<GetPeers> for TcpServer {
+    type Result = MessageResult<GetPeers>;
+
+    fn handle(&mut self, _: GetPeers, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(peer_list(&self.peers))
+    }
+}
+
+fn peer_list(peers: &HashMap<PeerId, ConnectInfo>) -> Vec<PeerInfo> {
+    let mut peers: Vec<PeerInfo> = peers.iter().map(|(peer_id, info)| info.peer_info(peer_id)).collect();
+    peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+    peers
+}
+
+// the peers which were not seen within the timeout
+fn idle_peers(peers: &HashMap<PeerId, ConnectInfo>, now: chrono::DateTime<chrono::Utc>, timeout: Duration) -> Vec<PeerId> {
+    let timeout = chrono::Duration::from_std(timeout).unwrap();
+    peers.iter().filter(|(_, info)| now.signed_duration_since(info.last_seen) > timeout).map(|(peer_id, _)| peer_id.clone()).collect()
+}
+
+/// the sync policy with the strategy of every connected peer
+#[derive(Clone)]
+pub struct GetSyncStatus;
+
+impl Message for GetSyncStatus {
+    type Result = SyncStatus;
+}
+
+impl Handler<GetSyncStatus> for TcpServer {
+    type Result = MessageResult<GetSyncStatus>;
+
+    fn handle(&mut self, _: GetSyncStatus, _ctx: &mut Self::Context) -> Self::Result {
+        let mut peers: Vec<SyncPeer> = self.peers.iter().map(|(peer_id, info)| {
+            let class = PeerClass::from_capabilities(info.handshake.capabilities());
+            let strategy = self.sync_policy.strategy(class);
+            SyncPeer { peer_id: peer_id.to_base58(), class, strategy, height: info.state.height, active: strategy.is_enabled() }
+        }).collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        MessageResult(SyncStatus { policy: self.sync_policy.clone(), peers })
+    }
+}
+
+/// switch the sync strategies at runtime, the next sync request follows it
+#[derive(Clone, Message)]
+pub struct SetSyncPolicy(pub SyncPolicy);
+
+impl Handler<SetSyncPolicy> for TcpServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetSyncPolicy, _ctx: &mut Self::Context) -> Self::Result {
+        info!("Switch the sync policy: {:?}", msg.0);
+        self.sync_policy = msg.0;
+    }
+}
+
+// the multiaddr of the remote end of the socket
+fn remote_multiaddr(stream: &TcpStream) -> Option<Multiaddr> {
+    let addr = stream.peer_addr().ok()?;
+    let protocol = if addr.is_ipv4() { "ip4" } else { "ip6" };
+    Multiaddr::from_str(&format!("/{}/{}/tcp/{}", protocol, addr.ip(), addr.port())).ok()
+}
+
 fn node_info(peers: &HashMap<PeerId, ConnectInfo>) -> String {
     let mut info: Vec<String> = vec![];
     for peer in peers {
         info.push(format!(
-            "{}----> [bound: {:?}, connect_time: {:?}]",
+            "{}----> [bound: {:?}, connect_time: {:?}, node_type: {:?}, height: {}, fingerprint_matched: {}, score: {}]",
             peer.0.to_base58(),
             peer.1.bound_type,
-            peer.1.connect_time
+            peer.1.connect_time,
+            peer.1.handshake.node_type(),
+            peer.1.state.height,
+            peer.1.fingerprint_matched,
+            peer.1.state.score
         ));
     }
     info.join("\n")
@@ -122,7 +339,17 @@ impl Actor for TcpServer {
             "[{:?}] Server start, listen on: {:?}",
             self.node_info.0, self.node_info.1
         );
-        self.subscribe_async::<BroadcastEvent>(ctx);
+        self.listen(ctx);
+        // the peers of the persisted records are dialed, the mdns and the seeds may miss them
+        for record in self.discovery.newest(MAX_GOSSIP_RECORDS) {
+            if let Ok(peer_id) = PeerId::from_str(&record.peer_id) {
+                self.add_peer(peer_id, record.multiaddrs());
+            }
+        }
+        // the subscription is kept by the address, it survives the restarts
+        if !self.restarted {
+            self.subscribe_async::<BroadcastEvent>(ctx);
+        }
         ctx.run_interval(::std::time::Duration::from_secs(2), |act, _| {
             debug!(
                 "Connect clients: {}\nlocal-id:{}, \n{}",
@@ -132,18 +359,20 @@ impl Actor for TcpServer {
             );
         });
 
-        ctx.run_interval(Duration::from_secs(3), |act, _| {
-            let mut peers = vec![];
-            act.peers.iter().for_each(|kv| {
-                let sub = chrono::Utc::now().timestamp() - kv.1.connect_time.timestamp();
-                if sub > 3 {
-                    peers.push(kv.0.clone());
-                }
-            });
+        // the queued messages of the slow sessions are retried when their mailboxes have room
+        ctx.run_interval(OUTBOUND_FLUSH_INTERVAL, |act, _| {
+            let mut queued = 0;
+            for (peer, info) in act.peers.iter_mut() {
+                flush_session(peer, info);
+                queued += info.outbound.len();
+            }
+            metrics::set_gauge("p2p_outbound_queued", queued as i64);
+        });
 
-            for peer in peers {
-                debug!("Remove peer {}", peer.to_base58());
-                if let Some(connect_info) = act.peers.remove(&peer) {
+        ctx.run_interval(PEER_REAP_INTERVAL, |act, _| {
+            for peer in idle_peers(&act.peers, chrono::Utc::now(), PEER_IDLE_TIMEOUT) {
+                debug!("Remove the idle peer {}", peer.to_base58());
+                if let Some(connect_info) = act.remove_peer(&peer) {
                     connect_info.pid.do_send(SessionEvent::Stop);
                 }
             }
@@ -159,6 +388,14 @@ impl Actor for TcpServer {
     }
 }
 
+// the peers are kept, the sessions talk to the same address after the restart
+impl Supervised for TcpServer {
+    fn restarting(&mut self, _ctx: &mut Self::Context) {
+        record_restart("tcp_server");
+        self.restarted = true;
+    }
+}
+
 impl Handler<P2PEvent> for TcpServer {
     type Result = ();
 
@@ -180,10 +417,21 @@ impl Handler<BroadcastEvent> for TcpServer {
     type Result = ();
 
     /// handle p2p event
-    fn handle(&mut self, msg: BroadcastEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: BroadcastEvent, ctx: &mut Self::Context) -> Self::Result {
         debug!("TcpServer[e:BroadcastEvent]");
+        guard("tcp_server", ctx, || self.handle_broadcast_event(msg));
+    }
+}
+
+impl TcpServer {
+    fn handle_broadcast_event(&mut self, msg: BroadcastEvent) {
         match msg {
-            BroadcastEvent::Consensus(msg) => {
+            BroadcastEvent::Consensus(msg, token) => {
+                if token.map(|token| token.is_cancelled()).unwrap_or(false) {
+                    metrics::inc_counter("p2p_cancelled_consensus_message_total");
+                    debug!("Drop the consensus message of the superseded round");
+                    return;
+                }
                 let header = RawHeader::new(P2PMsgCode::Consensus, 10, chrono::Local::now().timestamp_millis() as u64, None);
                 let payload = msg.into_payload();
                 let msg = RawMessage::new(header, payload);
@@ -194,21 +442,20 @@ impl Handler<BroadcastEvent> for TcpServer {
                 if let Some(peer_id) = peer_id {
                     header.peer_id = Some(peer_id.as_bytes().to_vec());
                 }
-                let payload = blocks.into_bytes();
+                let payload = blocks.rlp_bytes();
                 let msg = RawMessage::new(header, payload);
                 self.broadcast(&msg);
             }
             BroadcastEvent::Sync(height) => {
-                self.peers.keys().take(1).for_each(|peer_id| {
+                if let Some(peer_id) = self.best_peer(height) {
                     let header = RawHeader::new(P2PMsgCode::Sync, 10, chrono::Local::now().timestamp_millis() as u64, Some(peer_id.as_bytes().to_vec()));
                     let payload = height.into_bytes();
                     let msg = RawMessage::new(header, payload);
                     self.broadcast(&msg);
-                });
+                }
             }
             _ => unimplemented!()
         }
-        ()
     }
 }
 
@@ -219,16 +466,17 @@ impl Handler<ChainEvent> for TcpServer {
     /// handle p2p event
     fn handle(&mut self, msg: ChainEvent, ctx: &mut Self::Context) -> Self::Result {
         match msg {
-            ChainEvent::NewBlock(block) => {
+            ChainEvent::Committed { block, .. } => {
                 ctx.notify(BroadcastEvent::Blocks(None, Blocks(vec![block])));
             }
-            ChainEvent::NewHeader(_) => {}
             ChainEvent::SyncBlock(height) => {
                 ctx.notify(BroadcastEvent::Sync(height))
             }
             ChainEvent::PostBlock(peer_id, blocks) => {
                 ctx.notify(BroadcastEvent::Blocks(peer_id, blocks))
             }
+            ChainEvent::Reverted { .. } | ChainEvent::SyncStarted { .. } | ChainEvent::SyncFinished { .. } => {}
+            ChainEvent::SafetyViolation(_) => {}
         }
         ()
     }
@@ -236,26 +484,38 @@ impl Handler<ChainEvent> for TcpServer {
 
 impl Handler<ServerEvent> for TcpServer {
     type Result = Result<PeerId, P2PError>;
-    fn handle(&mut self, msg: ServerEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ServerEvent, ctx: &mut Self::Context) -> Self::Result {
+        guard("tcp_server", ctx, || self.handle_server_event(msg)).unwrap_or(Err(P2PError::InvalidMessage))
+    }
+}
+
+impl TcpServer {
+    fn handle_server_event(&mut self, msg: ServerEvent) -> Result<PeerId, P2PError> {
         match msg {
-            ServerEvent::Connected(ref peer_id, ref bound_type, ref pid, ref raw_msg) => {
+            ServerEvent::Connected(ref peer_id, ref bound_type, ref pid, ref raw_msg, ref address) => {
                 debug!("Connected peer: {:?}", peer_id);
-                return self.handle_handshake(bound_type.clone(), pid.clone(), raw_msg.payload());
+                return self.handle_handshake(bound_type.clone(), pid.clone(), raw_msg.payload(), address.clone());
             }
             ServerEvent::Disconnected(ref peer_id) => {
                 debug!("Disconnected peer: {:?}", peer_id);
-                self.peers.remove(&peer_id);
+                self.remove_peer(&peer_id);
                 return Ok(peer_id.clone());
             }
             ServerEvent::Ping(ref peer_id) => {
-                let mut info = self.peers.get_mut(peer_id).unwrap();
-                info.connect_time = chrono::Utc::now();
+                if let Some(info) = self.peers.get_mut(peer_id) {
+                    info.last_seen = chrono::Utc::now();
+                }
                 return Ok(peer_id.clone());
             }
 
             // 接收端
             ServerEvent::Message(ref peer_id, ref raw_msg) => {
                 let hash: Hash = raw_msg.hash();
+                if let Some(info) = self.peers.get_mut(peer_id) {
+                    info.state.known.insert(hash);
+                    info.messages_in += 1;
+                    info.last_seen = chrono::Utc::now();
+                }
                 let now = Local::now().timestamp_millis() as u64;
                 if now < raw_msg.header().create_time {
                     trace!("Skip message({:?}) cause of timeout", hash.short());
@@ -265,24 +525,58 @@ impl Handler<ServerEvent> for TcpServer {
                     trace!("Skip message({:?}) cause of received", hash.short());
                     return Ok(peer_id.clone());
                 } else {
-                    (self.handles)(peer_id.clone(), raw_msg.clone());
+                    if let Some(info) = self.peers.get_mut(peer_id) {
+                        info.state.score += 1;
+                    }
+                    if raw_msg.header().code == P2PMsgCode::Sync && self.block_server.is_some() {
+                        self.serve_blocks(peer_id, raw_msg);
+                        return Ok(peer_id.clone());
+                    }
+                    if raw_msg.header().code == P2PMsgCode::Records {
+                        self.handle_records(peer_id, raw_msg);
+                        return Ok(peer_id.clone());
+                    }
+                    if raw_msg.header().code == P2PMsgCode::SyncReply {
+                        if let Err(err) = self.accept_sync_reply(peer_id, raw_msg) {
+                            debug!("Reject the synced blocks, peer: {}, err: {}", peer_id.to_base58(), err);
+                            if let Some(info) = self.peers.get_mut(peer_id) {
+                                info.state.score = info.state.score.saturating_sub(1);
+                            }
+                            return Ok(peer_id.clone());
+                        }
+                    }
+                    // the rejected message takes back the score it earned
+                    if let Err(err) = (self.handles)(peer_id.clone(), raw_msg.clone()) {
+                        debug!("Reject message({:?}), err: {}", hash.short(), err);
+                        if let Some(info) = self.peers.get_mut(peer_id) {
+                            info.state.score = info.state.score.saturating_sub(1);
+                        }
+                    }
                     return Ok(peer_id.clone());
                 }
             }
         }
         Err(P2PError::InvalidMessage)
     }
-}
 
-impl TcpServer {
     pub fn new(
         peer_id: PeerId,
         mul_addr: Multiaddr,
         key: Option<secio::SecioKeyPair>,
-        genesis: Hash,
-        author: Box<Fn(Handshake) -> bool>,
+        handshake_fn: Arc<HandshakePacketFn>,
+        author: Box<AuthorFn>,
         handles: Box<Fn(PeerId, RawMessage) -> Result<(), String>>,
+        session_mailbox_capacity: usize,
+        outbound_config: OutboundConfig,
+        outbound_quotas: &HashMap<TrafficCategory, u64>,
+        session_grace: Duration,
+        sync_policy: SyncPolicy,
+        mux: Option<PortMux>,
+        block_server: Option<BlockServer>,
+        max_frame: usize,
+        discovery: DiscoveryRecords,
     ) -> Addr<TcpServer> {
+        let outbound_quota = OutboundQuota::new(outbound_quotas);
         let mut addr: String = String::new();
         mul_addr.iter().for_each(|item| match &item {
             Protocol::Ip4(ref ip4) => {
@@ -295,26 +589,64 @@ impl TcpServer {
         });
         let socket_addr = net::SocketAddr::from_str(&addr).unwrap();
 
-        // bind tcp listen address
-        let lis = TcpListener::bind(&socket_addr).unwrap();
+        // bind tcp listen address, the port of the other chain is bound already
+        let listening = mux.as_ref().map_or(true, |mux| mux.claim_listener());
+        let lis = if listening { Some(TcpListener::bind(&socket_addr).unwrap()) } else { None };
+        let route_handshake_fn = handshake_fn.clone();
+        let server_mux = mux.clone();
         // create tcp server and dispatch coming connection to self handle
-        TcpServer::create(move |ctx| {
+        let server = Supervisor::start(move |ctx| {
             ctx.set_mailbox_capacity(MAX_INBOUND_CONNECTION_MAILBOX);
-            ctx.add_message_stream(lis.incoming().map_err(|_| ()).map(move |s| {
-                trace!("New connection are comming");
-                TcpConnectInBound(s)
-            }));
             TcpServer {
                 pid: ctx.address().clone(),
                 key: key,
                 node_info: (peer_id.clone(), mul_addr.clone()),
                 peers: HashMap::new(),
-                cache: LruCache::with_expiry_duration_and_capacity(Duration::from_secs(5), 100_000),
-                genesis: genesis,
+                cache: MeteredCache::new("p2p_gossip_seen", LruCache::with_expiry_duration_and_capacity(Duration::from_secs(5), 100_000)),
+                handshake_fn: handshake_fn,
                 author_fn: author,
                 handles: handles,
+                session_mailbox_capacity: session_mailbox_capacity,
+                outbound_config: outbound_config,
+                outbound_quota: outbound_quota,
+                resumable: ResumableSessions::new(session_grace),
+                listen_addr: socket_addr,
+                listener: lis,
+                restarted: false,
+                sync_policy: sync_policy,
+                mux: server_mux,
+                listening: listening,
+                discovery: discovery,
+                block_server: block_server,
+                max_frame: max_frame,
             }
-        })
+        });
+        if let Some(mux) = mux {
+            let chain_id = route_handshake_fn().chain_id();
+            mux.register(chain_id, MuxRoute { server: server.clone(), handshake_fn: route_handshake_fn });
+        }
+        server
+    }
+
+    // dispatch the coming connections to self handle
+    fn listen(&mut self, ctx: &mut Context<Self>) {
+        if !self.listening {
+            return;
+        }
+        let listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => match TcpListener::bind(&self.listen_addr) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Failed to bind the listen address: {:?}, err: {:?}", self.listen_addr, err);
+                    return;
+                }
+            },
+        };
+        ctx.add_message_stream(listener.incoming().map_err(|_| ()).map(move |s| {
+            trace!("New connection are comming");
+            TcpConnectInBound(s)
+        }));
     }
 
     fn add_peer(&mut self, remote_id: PeerId, remote_addresses: Vec<Multiaddr>) {
@@ -322,10 +654,26 @@ impl TcpServer {
             return;
         }
 
-        let mul_addr = remote_addresses[0].clone();
+        // the announcement of mdns isn't signed, the addresses of the verified record win
+        let remote_addresses = match self.discovery.get(&remote_id).map(|record| record.multiaddrs()) {
+            Some(ref signed) if !signed.is_empty() => {
+                if !remote_addresses.iter().all(|address| signed.contains(address)) {
+                    metrics::inc_counter("p2p_spoofed_address_total");
+                    warn!("Ignore the unsigned addresses of peer {}, addresses: {:?}, signed: {:?}", remote_id.to_base58(), remote_addresses, signed);
+                }
+                signed.clone()
+            }
+            _ => remote_addresses,
+        };
+        let mul_addr = match remote_addresses.first() {
+            Some(address) => address.clone(),
+            None => return,
+        };
         let local_id = self.node_info.0.clone();
         let server_id = self.pid.clone();
-        let genesis = self.genesis.clone();
+        let handshake_fn = self.handshake_fn.clone();
+        let mailbox_capacity = self.session_mailbox_capacity;
+        let max_frame = self.max_frame;
         let delay = rand::random::<u64>() % 100;
         let timer_fut = Delay::new(Instant::now() + Duration::from_millis(delay));
         tokio::spawn(timer_fut.and_then(move |_| {
@@ -334,8 +682,10 @@ impl TcpServer {
                 remote_id,
                 local_id,
                 mul_addr,
-                genesis,
+                handshake_fn,
                 server_id,
+                mailbox_capacity,
+                max_frame,
             );
             futures::future::ok(())
         }).map_err(|err| panic!(err)));
@@ -349,8 +699,8 @@ impl TcpServer {
         bound_type: BoundType,
         pid: Addr<Session>,
         payload: &Vec<u8>,
+        address: Option<Multiaddr>,
     ) -> Result<PeerId, P2PError> {
-        use std::borrow::Cow;
         let handshake: Handshake = Handshake::from_bytes(Cow::from(payload));
         let peer_id = handshake.peer_id();
         if self.peers.contains_key(&peer_id) {
@@ -360,35 +710,258 @@ impl TcpServer {
             return Err(P2PError::HandShakeFailed);
         }
 
-        if !(self.author_fn)(handshake.clone()) {
-            return Err(P2PError::DifferentGenesis);
+        if let Err(err) = (self.author_fn)(handshake.clone()) {
+            warn!("Reject peer {}, reason: {}", peer_id.to_base58(), err);
+            return Err(err);
         }
 
         match bound_type {
             BoundType::InBound => {}
             BoundType::OutBound => {}
         }
-        let connect_info = ConnectInfo::new(chrono::Utc::now(), BoundType::InBound, pid);
+        // the peer with different consensus parameters is kept but de-prioritized
+        let local = (self.handshake_fn)();
+        let fingerprint_matched = local.fingerprint() == handshake.fingerprint();
+        if !fingerprint_matched {
+            metrics::inc_counter("p2p_fingerprint_mismatch_total");
+            error!("Consensus fingerprint mismatch, peer: {}, local: {:?}, remote: {:?}, diff: [{}]",
+                   peer_id.to_base58(),
+                   local.fingerprint().short(),
+                   handshake.fingerprint().short(),
+                   local.consensus_params().diff(handshake.consensus_params()).join(", "));
+        }
+        // the record proves the peer id, the clock of the peer doesn't matter, a stale record is
+        // only ignored
+        match handshake.discovery() {
+            Some(record) if record.peer_id != peer_id.to_base58() => {
+                metrics::inc_counter("p2p_invalid_discovery_record_total");
+                warn!("Reject peer {}, the discovery record is of {}", peer_id.to_base58(), record.peer_id);
+                return Err(P2PError::HandShakeFailed);
+            }
+            Some(record) => {
+                let now = chrono::Utc::now().timestamp_millis() as u64;
+                match self.discovery.insert(record.clone(), now) {
+                    Ok(true) => debug!("Peer {} announces the addresses {:?}, seq: {}", peer_id.to_base58(), record.addresses, record.seq),
+                    Ok(false) => debug!("Ignore the stale discovery record of peer {}, seq: {}", peer_id.to_base58(), record.seq),
+                    Err(err) => {
+                        metrics::inc_counter("p2p_invalid_discovery_record_total");
+                        warn!("Reject peer {}, invalid discovery record, err: {}", peer_id.to_base58(), err);
+                        return Err(P2PError::HandShakeFailed);
+                    }
+                }
+            }
+            None if handshake.negotiated_version() >= RECORDS_VERSION => {
+                metrics::inc_counter("p2p_invalid_discovery_record_total");
+                warn!("Reject peer {}, its addresses aren't signed", peer_id.to_base58());
+                return Err(P2PError::HandShakeFailed);
+            }
+            // the old peers don't sign their addresses
+            None => debug!("Peer {} has no discovery record", peer_id.to_base58()),
+        }
+        // the binding is verified by the author of the handshake
+        if let Some(binding) = handshake.binding() {
+            debug!("Peer {} is bound to validator {:?}, node key: {:?}", peer_id.to_base58(), binding.validator, binding.node);
+        }
+        let state = match self.resumable.resume(&peer_id, handshake.height()) {
+            Some(state) => {
+                debug!("Resume peer session, peer: {}, score: {}, known: {}", peer_id.to_base58(), state.score, state.known.len());
+                state
+            }
+            None => PeerState::new(handshake.height()),
+        };
+        let version = handshake.negotiated_version();
+        let connect_info = ConnectInfo::new(chrono::Utc::now(), address, bound_type, pid, handshake, fingerprint_matched, state, OutboundQueue::new(self.outbound_config));
         self.peers.entry(peer_id.clone()).or_insert(connect_info);
+        if version >= RECORDS_VERSION {
+            self.send_records(&peer_id);
+        }
         Ok(peer_id)
     }
 
-    fn broadcast(&self, msg: &RawMessage) {
+    // the new peer learns the signed addresses of the known peers
+    fn send_records(&mut self, peer_id: &PeerId) {
+        let records: Vec<DiscoveryRecord> = self.discovery.newest(MAX_GOSSIP_RECORDS + 1).into_iter()
+            .filter(|record| record.peer_id != peer_id.to_base58())
+            .take(MAX_GOSSIP_RECORDS)
+            .collect();
+        if records.is_empty() {
+            return;
+        }
+        let header = RawHeader::new(P2PMsgCode::Records, 1, Local::now().timestamp_millis() as u64, Some(peer_id.as_bytes().to_vec()));
+        self.broadcast(&RawMessage::new(header, PeerRecords(records).into_bytes()));
+    }
+
+    // every gossiped record is verified like the one of a handshake, the new peers are dialed by
+    // the signed addresses
+    fn handle_records(&mut self, peer_id: &PeerId, raw_msg: &RawMessage) {
+        let PeerRecords(records) = PeerRecords::from_bytes(Cow::from(raw_msg.payload()));
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        for record in records.into_iter().take(MAX_GOSSIP_RECORDS) {
+            let remote = match record.signer() {
+                Ok(remote) => remote,
+                Err(err) => {
+                    metrics::inc_counter("p2p_invalid_discovery_record_total");
+                    debug!("Drop the gossiped discovery record, peer: {}, err: {}", peer_id.to_base58(), err);
+                    continue;
+                }
+            };
+            if remote == self.node_info.0 || self.discovery.get(&remote) == Some(&record) {
+                continue;
+            }
+            if let Ok(true) = self.discovery.insert(record.clone(), now) {
+                debug!("Learn the addresses of peer {} from peer {}, addresses: {:?}", remote.to_base58(), peer_id.to_base58(), record.addresses);
+                self.add_peer(remote, record.multiaddrs());
+            }
+        }
+    }
+
+    // reply the sync request with the blocks of the store, the store is read off the actor and
+    // the replies come back by `ServeBlocks`
+    fn serve_blocks(&mut self, peer_id: &PeerId, raw_msg: &RawMessage) {
+        let from = Height::from_bytes(Cow::from(raw_msg.payload()));
+        let (pid, peer) = (self.pid.clone(), peer_id.clone());
+        if let Some(ref mut block_server) = self.block_server {
+            if !block_server.request(peer_id, from, move |replies| pid.do_send(ServeBlocks(peer, replies))) {
+                debug!("Ignore the sync request, the last one of the peer is still served, peer: {}, from height: {}", peer_id.to_base58(), from);
+            }
+        }
+    }
+
+    // the state of the removed peer is kept for the grace window
+    fn remove_peer(&mut self, peer_id: &PeerId) -> Option<ConnectInfo> {
+        let connect_info = self.peers.remove(peer_id)?;
+        if let Some(ref mut block_server) = self.block_server {
+            block_server.remove_peer(peer_id);
+        }
+        self.resumable.suspend(peer_id.clone(), connect_info.state.clone());
+        Some(connect_info)
+    }
+
+    // the synced blocks must follow the strategy of the peer class, eg: the light peers must
+    // certify every block
+    fn accept_sync_reply(&self, peer_id: &PeerId, raw_msg: &RawMessage) -> Result<(), String> {
+        let info = self.peers.get(peer_id).ok_or("unknown peer".to_string())?;
+        let strategy = self.sync_policy.strategy(PeerClass::from_capabilities(info.handshake.capabilities()));
+        strategy.accept(&SyncReply::from_bytes(Cow::from(raw_msg.payload())))
+    }
+
+    // choose the highest peer which has the block at the height and whose class the sync policy
+    // allows, the peers with different consensus fingerprint are the last choice
+    fn best_peer(&self, height: Height) -> Option<PeerId> {
+        let policy = &self.sync_policy;
+        self.peers
+            .iter()
+            .filter(|(_, info)| policy.allows(info.handshake.capabilities()))
+            .filter(|(_, info)| info.state.height >= height)
+            .max_by_key(|(_, info)| (info.fingerprint_matched, info.state.height, info.state.score))
+            .map(|(peer_id, _)| peer_id.clone())
+            .or_else(|| {
+                self.peers.iter()
+                    .filter(|(_, info)| policy.allows(info.handshake.capabilities()))
+                    .max_by_key(|(_, info)| info.fingerprint_matched)
+                    .map(|(peer_id, _)| peer_id.clone())
+            })
+    }
+
+    fn broadcast(&mut self, msg: &RawMessage) {
+        let category = TrafficCategory::from_code(&msg.header().code);
+        // the peers may speak rlp, the json size is the upper bound
+        let size = wire_size(msg, WireCodec::Json);
+        let hash: Hash = msg.hash();
         if let Some(ref peer) = msg.header().peer_id {
             let peer = PeerId::from_bytes(peer.clone()).unwrap();
             debug!("Broadcast message, code: {:?}, peer: {:?}", msg.header(), peer.to_base58());
-            if let Some(info) = self.peers.get(&peer) {
-                info.pid.do_send(msg.clone());
+            if let Some(info) = self.peers.get_mut(&peer) {
+                if self.outbound_quota.try_consume(category, size) {
+                    info.state.known.insert(hash);
+                    send_to_session(&peer, info, msg.clone());
+                }
             }
         } else {
-            for (peer, info) in &self.peers {
+            for (peer, info) in self.peers.iter_mut() {
+                // don't announce the message to the peer which already has it
+                if info.state.known.contains(&hash) {
+                    continue;
+                }
+                if !self.outbound_quota.try_consume(category, size) {
+                    debug!("Outbound quota exceeded, code: {:?}, peer: {:?}", msg.header().code, peer.to_base58());
+                    continue;
+                }
                 debug!("Broadcast message, code: {:?}, peer: {:?}", msg.header(), peer.to_base58());
-                info.pid.do_send(msg.clone());
+                info.state.known.insert(hash);
+                send_to_session(peer, info, msg.clone());
+            }
+        }
+    }
+}
+
+// the message waits in the outbound queue of the session, it's dropped by the queue if the peer
+// is too slow
+fn send_to_session(peer: &PeerId, info: &mut ConnectInfo, msg: RawMessage) {
+    let code = msg.header().code;
+    if !info.outbound.push(msg, Instant::now()) {
+        debug!("Drop message to slow peer, code: {:?}, peer: {:?}", code, peer.to_base58());
+    }
+    flush_session(peer, info);
+}
+
+// move the queued messages into the session's bounded mailbox until it's full
+fn flush_session(peer: &PeerId, info: &mut ConnectInfo) {
+    while let Some((msg, queued_at)) = info.outbound.pop(Instant::now()) {
+        match info.pid.try_send(msg) {
+            Ok(_) => info.messages_out += 1,
+            Err(SendError::Full(msg)) => {
+                trace!("The session mailbox is full, queued: {}, peer: {:?}", info.outbound.len() + 1, peer.to_base58());
+                info.outbound.requeue(msg, queued_at);
+                break;
+            }
+            Err(SendError::Closed(_)) => {
+                info.outbound.clear();
+                break;
             }
         }
     }
 }
 
+/// the replies of a sync request read by the block server
+#[derive(Message)]
+struct ServeBlocks(PeerId, Vec<SyncReply>);
+
+impl Handler<ServeBlocks> for TcpServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServeBlocks, ctx: &mut Context<Self>) {
+        let ServeBlocks(peer_id, replies) = msg;
+        // the budget and the pending request of a removed peer are dropped with it
+        let version = match self.peers.get(&peer_id) {
+            Some(info) => info.handshake.negotiated_version(),
+            None => return,
+        };
+        let (served, deferred) = match self.block_server {
+            Some(ref mut block_server) => block_server.admit(&peer_id, replies),
+            None => return,
+        };
+        debug!("Serve the sync request, peer: {}, batches: {}, deferred: {}", peer_id.to_base58(), served.len(), deferred.as_ref().map_or(0, |(_, rest)| rest.len()));
+        for reply in served {
+            self.broadcast(&sync_reply_message(&peer_id, version, reply));
+        }
+        if let Some((wait, rest)) = deferred {
+            ctx.notify_later(ServeBlocks(peer_id, rest), wait);
+        }
+    }
+}
+
+/// the message of a reply to the peer, the peers below `SYNC_REPLY_VERSION` get the plain blocks
+pub fn sync_reply_message(peer_id: &PeerId, version: u32, reply: SyncReply) -> RawMessage {
+    let (code, payload) = if version >= SYNC_REPLY_VERSION {
+        (P2PMsgCode::SyncReply, reply.into_bytes())
+    } else {
+        (P2PMsgCode::Block, Blocks(reply.blocks).rlp_bytes())
+    };
+    let header = RawHeader::new(code, 10, Local::now().timestamp_millis() as u64, Some(peer_id.as_bytes().to_vec()));
+    RawMessage::new(header, payload)
+}
+
 #[derive(Message)]
 struct TcpConnectOutBound(TcpStream, PeerId);
 
@@ -407,18 +980,25 @@ impl Handler<TcpConnectOutBound> for TcpServer {
         let peer_id = msg.1.clone();
         let server_id = self.pid.clone();
         let local_id = self.node_info.0.clone();
-        let genesis = self.genesis.clone();
+        let handshake_fn = self.handshake_fn.clone();
+        let mailbox_capacity = self.session_mailbox_capacity;
+        let max_frame = self.max_frame;
         Session::create(move |ctx| {
+            ctx.set_mailbox_capacity(mailbox_capacity);
+            let address = remote_multiaddr(&msg.0);
             let (r, w) = msg.0.split();
-            Session::add_stream(FramedRead::new(r, MsgPacketCodec), ctx);
+            let wire = WireSelector::default();
+            Session::add_stream(FramedRead::new(r, MsgPacketCodec::reader(max_frame)), ctx);
             Session::new(
                 ctx.address().clone(),
                 peer_id,
                 local_id,
                 server_id,
-                actix::io::FramedWrite::new(w, MsgPacketCodec, ctx),
+                actix::io::FramedWrite::new(w, MsgPacketCodec::new(wire.clone()), ctx),
                 BoundType::OutBound,
-                genesis,
+                handshake_fn,
+                wire,
+                address,
             )
         });
     }
@@ -433,19 +1013,27 @@ impl Handler<TcpConnectInBound> for TcpServer {
     fn handle(&mut self, msg: TcpConnectInBound, _: &mut Context<Self>) {
         let server_id = self.pid.clone();
         let local_id = self.node_info.0.clone();
-        let genesis = self.genesis.clone();
+        let handshake_fn = self.handshake_fn.clone();
+        let mailbox_capacity = self.session_mailbox_capacity;
+        let max_frame = self.max_frame;
+        let mux = self.mux.clone();
         Session::create(move |ctx| {
+            ctx.set_mailbox_capacity(mailbox_capacity);
+            let address = remote_multiaddr(&msg.0);
             let (r, w) = msg.0.split();
-            Session::add_stream(FramedRead::new(r, MsgPacketCodec), ctx);
+            let wire = WireSelector::default();
+            Session::add_stream(FramedRead::new(r, MsgPacketCodec::reader(max_frame)), ctx);
             Session::new(
                 ctx.address().clone(),
                 ZERO_PEER.clone(),
                 local_id,
                 server_id,
-                actix::io::FramedWrite::new(w, MsgPacketCodec, ctx),
+                actix::io::FramedWrite::new(w, MsgPacketCodec::new(wire.clone()), ctx),
                 BoundType::InBound,
-                genesis,
-            )
+                handshake_fn,
+                wire,
+                address,
+            ).with_mux(mux)
         });
     }
 }
@@ -463,8 +1051,10 @@ impl TcpDial {
         peer_id: PeerId,
         local_id: PeerId,
         mul_addr: Multiaddr,
-        genesis: Hash,
+        handshake_fn: Arc<HandshakePacketFn>,
         tcp_server: Addr<TcpServer>,
+        mailbox_capacity: usize,
+        max_frame: usize,
     ) {
         let socket_addr = multiaddr_to_ipv4(&mul_addr).unwrap();
         trace!(
@@ -478,19 +1068,24 @@ impl TcpDial {
                     trace!("Dialing remote peer: {:?}", peer_id);
                     let peer_id = peer_id.clone();
                     let local_id = local_id.clone();
-                    let genesis = genesis.clone();
+                    let handshake_fn = handshake_fn.clone();
                     let tcp_server = tcp_server.clone();
                     Session::create(move |ctx| {
+                        ctx.set_mailbox_capacity(mailbox_capacity);
+                        let address = remote_multiaddr(&stream);
                         let (r, w) = stream.split();
-                        Session::add_stream(FramedRead::new(r, MsgPacketCodec), ctx);
+                        let wire = WireSelector::default();
+                        Session::add_stream(FramedRead::new(r, MsgPacketCodec::reader(max_frame)), ctx);
                         Session::new(
                             ctx.address().clone(),
                             peer_id,
                             local_id,
                             tcp_server,
-                            actix::io::FramedWrite::new(w, MsgPacketCodec, ctx),
+                            actix::io::FramedWrite::new(w, MsgPacketCodec::new(wire.clone()), ctx),
                             BoundType::OutBound,
-                            genesis,
+                            handshake_fn,
+                            wire,
+                            address,
                         )
                     });
 
@@ -503,3 +1098,70 @@ impl TcpDial {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use crate::config::Config;
+
+    fn connect_info(peer_id: &PeerId, connect_time: chrono::DateTime<chrono::Utc>, height: Height) -> ConnectInfo {
+        // the session is never started, nothing is sent to it
+        let (tx, _) = actix::dev::channel::channel::<Session>(1);
+        let handshake = Handshake::new("0.1.1".to_string(), peer_id.clone(), EMPTY_HASH, NodeType::Full, height, EMPTY_HASH, Config::default().consensus_params());
+        ConnectInfo::new(connect_time, None, BoundType::InBound, Addr::new(tx), handshake, true, PeerState::new(height), OutboundQueue::new(OutboundConfig::default()))
+    }
+
+    #[test]
+    fn t_idle_peers() {
+        let now = chrono::Utc::now();
+        let (active, idle) = (PeerId::random(), PeerId::random());
+        let mut peers = HashMap::new();
+        // connected long ago but still pinging
+        let mut info = connect_info(&active, now - chrono::Duration::minutes(10), 1);
+        info.last_seen = now - chrono::Duration::seconds(1);
+        peers.insert(active.clone(), info);
+        peers.insert(idle.clone(), connect_info(&idle, now - chrono::Duration::minutes(1), 1));
+
+        assert_eq!(idle_peers(&peers, now, PEER_IDLE_TIMEOUT), vec![idle]);
+        assert!(idle_peers(&peers, now - chrono::Duration::minutes(1), PEER_IDLE_TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn t_get_peers() {
+        let now = chrono::Utc::now();
+        let mut peers = HashMap::new();
+        for height in 0..4 {
+            let peer_id = PeerId::random();
+            let mut info = connect_info(&peer_id, now, height);
+            info.messages_in = height;
+            peers.insert(peer_id, info);
+        }
+        let list = peer_list(&peers);
+        assert_eq!(list.len(), 4);
+        assert!(list.windows(2).all(|pair| pair[0].peer_id < pair[1].peer_id));
+        for info in &list {
+            let peer_id = PeerId::from_str(&info.peer_id).unwrap();
+            assert_eq!((info.height, info.handshake_height, info.messages_in), (peers[&peer_id].state.height, peers[&peer_id].handshake.height(), peers[&peer_id].messages_in));
+            assert_eq!((info.direction, info.connected_at, info.last_seen), ("inbound", now.timestamp_millis() as u64, now.timestamp_millis() as u64));
+        }
+    }
+
+    #[test]
+    fn t_author_handshake() {
+        use crate::p2p::codec::negotiate_codec;
+        let config = Config::default();
+        let author = author_handshake(EMPTY_HASH, config.chain_id);
+        let handshake = |protocol_version: u32| Handshake::new("0.1.1".to_string(), PeerId::random(), EMPTY_HASH, NodeType::Full, 0, EMPTY_HASH, config.consensus_params())
+            .with_protocol_version(protocol_version);
+        assert!(author(handshake(PROTOCOL_VERSION)).is_ok());
+        assert!(author(handshake(MIN_PROTOCOL_VERSION)).is_ok());
+        assert!(author(handshake(MIN_PROTOCOL_VERSION - 1)).is_err());
+        // the newer peer is accepted and talks at the local version
+        let newer = handshake(PROTOCOL_VERSION + 1);
+        assert!(author(newer.clone()).is_ok());
+        assert_eq!(newer.negotiated_version(), PROTOCOL_VERSION);
+        assert_eq!(handshake(SYNC_REPLY_VERSION - 1).negotiated_version(), SYNC_REPLY_VERSION - 1);
+        assert_eq!(negotiate_codec(newer.protocol_version()), negotiate_codec(PROTOCOL_VERSION));
+    }
+}
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::io;
 use std::net;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::mpsc::sync_channel;
 use std::time::Duration;
 
@@ -14,11 +15,14 @@ use libp2p::PeerId;
 use cryptocurrency_kit::crypto::Hash;
 use tokio::{codec::FramedRead, io::WriteHalf, net::TcpListener, net::TcpStream};
 
-use super::codec::MsgPacketCodec;
-use super::protocol::{BoundType, RawMessage, Header, P2PMsgCode, Handshake};
-use super::server::{ServerEvent, SessionEvent, TcpServer};
+use super::bandwidth::{record_traffic, Direction};
+use super::codec::{negotiate_codec, MsgPacketCodec, WireSelector};
+use super::mux::PortMux;
+use super::protocol::{BoundType, RawMessage, Header, Handshake, P2PMsgCode};
+use super::server::{HandshakePacketFn, ServerEvent, SessionEvent, TcpServer};
 use crate::common::multiaddr_to_ipv4;
 use crate::error::P2PError;
+use crate::util::request::{send_timeout, DEFAULT_REQUEST_TIMEOUT};
 
 pub struct Session {
     pid: Option<Addr<Session>>,
@@ -27,28 +31,22 @@ pub struct Session {
     server: Addr<TcpServer>,
     bound_type: BoundType,
     handshaked: bool,
-    genesis: Hash,
+    handshake_fn: Arc<HandshakePacketFn>,
     framed: actix::io::FramedWrite<WriteHalf<TcpStream>, MsgPacketCodec>,
+    // the codec of the written messages, it's negotiated by the handshake
+    wire: WireSelector,
+    remote_addr: Option<Multiaddr>,
+    // the inbound session of a shared port, it joins the chain of the remote handshake
+    mux: Option<PortMux>,
 }
 
 impl Actor for Session {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        // send a handshake message
-        {
-            let peer_id = self.local_id.clone();
-            let handshake = Handshake::new("0.1.1".to_string(), peer_id.clone(), self.genesis.clone());
-            let raw_message = RawMessage::new(
-                Header::new(
-                    P2PMsgCode::Handshake,
-                    10,
-                    chrono::Local::now().timestamp_nanos() as u64,
-                    None,
-                ),
-                handshake.into_bytes(),
-            );
-            ctx.add_message_stream(once(Ok(raw_message)));
+        // send a handshake message, the session of a shared port replies it after the chain is known
+        if self.mux.is_none() {
+            ctx.add_message_stream(once(Ok(self.handshake_message())));
         }
 
         ctx.run_later(Duration::from_secs(1), |act, ctx| {
@@ -99,26 +97,45 @@ impl actix::io::WriteHandler<io::Error> for Session {}
 impl StreamHandler<RawMessage, io::Error> for Session {
     fn handle(&mut self, msg: RawMessage, ctx: &mut Context<Self>) {
         debug!("Read message: {:?}, local_id:{:?}, peer_id:{:?}", msg.header(), self.local_id.to_base58(), self.peer_id.to_base58());
+        record_traffic(Direction::Inbound, &msg, self.wire.get());
         match msg.header().code {
             P2PMsgCode::Handshake => {
-                self.server
-                    .send(ServerEvent::Connected(
-                        self.peer_id.clone(),
-                        self.bound_type,
-                        self.pid.as_ref().unwrap().clone(),
-                        msg.clone(),
-                    ))
+                let remote = Handshake::from_bytes(Cow::from(msg.payload().clone()));
+                if let Some(mux) = self.mux.take() {
+                    match mux.route(remote.chain_id()) {
+                        Some(route) => {
+                            self.server = route.server;
+                            self.handshake_fn = route.handshake_fn;
+                            ctx.notify(self.handshake_message());
+                        }
+                        None => {
+                            debug!("No chain {} on the shared port, peer: {}", remote.chain_id(), remote.peer_id().to_base58());
+                            ctx.stop();
+                            return;
+                        }
+                    }
+                }
+                let remote_version = remote.protocol_version();
+                let event = ServerEvent::Connected(
+                    self.peer_id.clone(),
+                    self.bound_type,
+                    self.pid.as_ref().unwrap().clone(),
+                    msg.clone(),
+                    self.remote_addr.clone(),
+                );
+                send_timeout(&self.server, event, DEFAULT_REQUEST_TIMEOUT)
                     .into_actor(self)
                     .then(|res, act, ctx| {
                         match res {
                             Ok(res) => {
                                 if let Err(err) = res {
-                                    trace!("Author fail, err: {:?}", err);
+                                    debug!("Author fail, peer: {}, err: {}", act.peer_id.to_base58(), err);
                                     ctx.stop();
                                 } else {
                                     let peer = res.unwrap();
                                     act.handshaked = true;
                                     act.peer_id = peer;
+                                    act.wire.set(negotiate_codec(remote_version));
                                     trace!(
                                         "Author successfully, local_id: {}, peer: {}",
                                         act.local_id.to_base58(),
@@ -126,20 +143,22 @@ impl StreamHandler<RawMessage, io::Error> for Session {
                                     );
                                 }
                             }
-                            Err(err) => panic!(err),
+                            Err(err) => {
+                                debug!("Server didn't author the peer, peer: {}, err: {}", act.peer_id.to_base58(), err);
+                                ctx.stop();
+                            }
                         }
                         actix::fut::ok(())
                     })
                     .wait(ctx);
             }
             P2PMsgCode::Transaction => {}
-            P2PMsgCode::Block | P2PMsgCode::Consensus | P2PMsgCode::Sync => {
+            P2PMsgCode::Block | P2PMsgCode::Consensus | P2PMsgCode::Sync | P2PMsgCode::SyncReply | P2PMsgCode::Records => {
                 self.server.do_send(ServerEvent::Message(self.peer_id.clone(), msg));
             }
             P2PMsgCode::Ping => {
                 assert!(self.handshaked);
-                self.server
-                    .send(ServerEvent::Ping(self.peer_id.clone()))
+                send_timeout(&self.server, ServerEvent::Ping(self.peer_id.clone()), DEFAULT_REQUEST_TIMEOUT)
                     .into_actor(self)
                     .then(|res, act, ctx| {
                         match res {
@@ -148,7 +167,10 @@ impl StreamHandler<RawMessage, io::Error> for Session {
                                     ctx.stop();
                                 }
                             }
-                            Err(err) => panic!(err),
+                            Err(err) => {
+                                debug!("Server didn't reply the ping, peer: {}, err: {}", act.peer_id.to_base58(), err);
+                                ctx.stop();
+                            }
                         }
                         actix::fut::ok(())
                     })
@@ -167,6 +189,7 @@ impl Handler<RawMessage> for Session {
         if msg.header().code != P2PMsgCode::Ping {
             debug!("Write message: {:?}, local_id:{:?}, peer_id:{:?}", msg.header(), self.local_id.to_base58(), self.peer_id.to_base58());
         }
+        record_traffic(Direction::Outbound, &msg, self.wire.get());
         self.framed.write(msg);
     }
 }
@@ -186,7 +209,9 @@ impl Session {
         server: Addr<TcpServer>,
         framed: actix::io::FramedWrite<WriteHalf<TcpStream>, MsgPacketCodec>,
         bound_type: BoundType,
-        genesis: Hash,
+        handshake_fn: Arc<HandshakePacketFn>,
+        wire: WireSelector,
+        remote_addr: Option<Multiaddr>,
     ) -> Session {
         Session {
             pid: Some(self_pid),
@@ -196,7 +221,28 @@ impl Session {
             handshaked: false,
             framed: framed,
             bound_type: bound_type,
-            genesis: genesis,
+            handshake_fn: handshake_fn,
+            wire,
+            remote_addr,
+            mux: None,
         }
     }
+
+    pub fn with_mux(mut self, mux: Option<PortMux>) -> Self {
+        self.mux = mux;
+        self
+    }
+
+    fn handshake_message(&self) -> RawMessage {
+        let handshake = (self.handshake_fn)();
+        RawMessage::new(
+            Header::new(
+                P2PMsgCode::Handshake,
+                10,
+                chrono::Local::now().timestamp_nanos() as u64,
+                None,
+            ),
+            handshake.into_bytes(),
+        )
+    }
 }
\ No newline at end of file
@@ -0,0 +1,184 @@
+use super::protocol::NodeType;
+use super::serve::SyncReply;
+use crate::types::Height;
+
+// the capability flags advertised by the handshake
+// serves the full blocks from the genesis
+pub const CAP_ARCHIVE: u32 = 1;
+// serves the headers with their commit certificates
+pub const CAP_LIGHT: u32 = 1 << 1;
+// serves the state snapshots
+pub const CAP_SNAPSHOT: u32 = 1 << 2;
+
+/// the capabilities of the peers which don't advertise them, they're told by the node type
+pub fn default_capabilities(node_type: NodeType) -> u32 {
+    match node_type {
+        NodeType::Light => 0,
+        _ => CAP_ARCHIVE,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerClass {
+    Archival,
+    SnapshotCapable,
+    LightCapable,
+    // the peer serves nothing to sync from
+    Other,
+}
+
+impl PeerClass {
+    /// the richest capability decides the class, the archival peers can serve everything
+    pub fn from_capabilities(capabilities: u32) -> Self {
+        if capabilities & CAP_ARCHIVE != 0 {
+            PeerClass::Archival
+        } else if capabilities & CAP_SNAPSHOT != 0 {
+            PeerClass::SnapshotCapable
+        } else if capabilities & CAP_LIGHT != 0 {
+            PeerClass::LightCapable
+        } else {
+            PeerClass::Other
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStrategy {
+    // the blocks are imported with or without their certificates
+    FullBlocks,
+    // the blocks are imported only if every one of them carries its commit certificate
+    HeadersAndCertificates,
+    // never sync from the class
+    Disabled,
+}
+
+impl SyncStrategy {
+    pub fn is_enabled(self) -> bool {
+        self != SyncStrategy::Disabled
+    }
+
+    /// the reply of a peer synced by the strategy can be imported, the certificates themselves
+    /// are verified by the importer
+    pub fn accept(self, reply: &SyncReply) -> Result<(), String> {
+        match self {
+            SyncStrategy::FullBlocks => Ok(()),
+            SyncStrategy::HeadersAndCertificates => {
+                match reply.blocks.iter().find(|block| !reply.certificates.iter().any(|certificate| certificate.height() == block.height())) {
+                    Some(block) => Err(format!("the block of height {} has no certificate", block.height())),
+                    None => Ok(()),
+                }
+            }
+            SyncStrategy::Disabled => Err("the peer isn't synced from".to_string()),
+        }
+    }
+}
+
+/// SyncPolicy chooses the sync strategy of every peer class, eg: `[p2p.sync]`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncPolicy {
+    #[serde(default = "default_archival_strategy")]
+    pub archival: SyncStrategy,
+    #[serde(default = "default_light_strategy")]
+    pub light: SyncStrategy,
+    #[serde(default = "default_snapshot_strategy")]
+    pub snapshot: SyncStrategy,
+}
+
+fn default_archival_strategy() -> SyncStrategy {
+    SyncStrategy::FullBlocks
+}
+
+fn default_light_strategy() -> SyncStrategy {
+    SyncStrategy::HeadersAndCertificates
+}
+
+// the state snapshots aren't imported, the snapshot peers miss the blocks below their snapshot
+fn default_snapshot_strategy() -> SyncStrategy {
+    SyncStrategy::Disabled
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        SyncPolicy {
+            archival: default_archival_strategy(),
+            light: default_light_strategy(),
+            snapshot: default_snapshot_strategy(),
+        }
+    }
+}
+
+impl SyncPolicy {
+    pub fn strategy(&self, class: PeerClass) -> SyncStrategy {
+        match class {
+            PeerClass::Archival => self.archival,
+            PeerClass::SnapshotCapable => self.snapshot,
+            PeerClass::LightCapable => self.light,
+            PeerClass::Other => SyncStrategy::Disabled,
+        }
+    }
+
+    /// the peer can be synced from
+    pub fn allows(&self, capabilities: u32) -> bool {
+        self.strategy(PeerClass::from_capabilities(capabilities)).is_enabled()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncPeer {
+    pub peer_id: String,
+    pub class: PeerClass,
+    pub strategy: SyncStrategy,
+    pub height: Height,
+    // the strategy isn't disabled, so the peer is a sync candidate
+    pub active: bool,
+}
+
+/// the sync policy and the strategy of every connected peer, it's served by `/status/sync`
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    pub policy: SyncPolicy,
+    pub peers: Vec<SyncPeer>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::{CryptoHash, EMPTY_HASH};
+    use cryptocurrency_kit::ethkey::Address;
+    use crate::consensus::types::{CommitCertificate, View};
+    use crate::types::block::{Block, Header};
+
+    #[test]
+    fn t_sync_policy() {
+        let mut policy = SyncPolicy::default();
+        assert_eq!(PeerClass::from_capabilities(CAP_ARCHIVE | CAP_SNAPSHOT), PeerClass::Archival);
+        assert_eq!(PeerClass::from_capabilities(default_capabilities(NodeType::Light)), PeerClass::Other);
+        assert_eq!(policy.strategy(PeerClass::LightCapable), SyncStrategy::HeadersAndCertificates);
+        assert!(policy.allows(CAP_ARCHIVE));
+        assert!(policy.allows(CAP_LIGHT));
+        assert!(!policy.allows(CAP_SNAPSHOT));
+
+        policy.archival = SyncStrategy::Disabled;
+        assert!(!policy.allows(CAP_ARCHIVE));
+        let policy: SyncPolicy = toml::from_str("light = \"disabled\"").unwrap();
+        assert!(!policy.allows(CAP_LIGHT));
+        assert!(toml::from_str::<SyncPolicy>("snapshot = \"snapshots\"").is_err());
+    }
+
+    #[test]
+    fn t_sync_strategy_accept() {
+        let block = |height: Height| Block::new(Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, height, height, None), vec![]);
+        let blocks = vec![block(1), block(2)];
+        let certificate = CommitCertificate::new(View { round: 0, height: 1 }, blocks[0].hash(), vec![]);
+        let reply = SyncReply { blocks: blocks.clone(), certificates: vec![certificate.clone()] };
+
+        assert!(SyncStrategy::FullBlocks.accept(&reply).is_ok());
+        assert_eq!(SyncStrategy::HeadersAndCertificates.accept(&reply), Err("the block of height 2 has no certificate".to_string()));
+        assert!(SyncStrategy::Disabled.accept(&reply).is_err());
+        let certified = SyncReply { blocks: blocks[..1].to_vec(), certificates: vec![certificate] };
+        assert!(SyncStrategy::HeadersAndCertificates.accept(&certified).is_ok());
+    }
+}
@@ -1,18 +1,160 @@
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use ::actix::prelude::*;
 use futures::prelude::*;
+use parking_lot::{Mutex, RwLock};
 use tokio::prelude::*;
 use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
 
+use crate::common::random_dir;
+use crate::features::Feature;
+use crate::metrics::REGISTRY;
+use crate::types::Timestamp;
+
+// the queue sizes which show the backlog of the actor mailboxes
+pub const MAILBOX_GAUGES: [&str; 6] = [
+    "subscriber_pending", "chain_import_queue_size", "consensus_backlog_size",
+    "chain_future_blocks", "chain_orphan_blocks", "txpool_pending",
+];
+// the messages dropped because a mailbox is full
+pub const MAILBOX_DROP_COUNTERS: [&str; 2] = ["subscriber_dropped_total", "p2p_session_dropped_total"];
+
+lazy_static! {
+    static ref OUTPUT_DIR: RwLock<Option<String>> = RwLock::new(None);
+    // the start time of the running profile
+    static ref PROFILE: Mutex<Option<Timestamp>> = Mutex::new(None);
+}
+
+// the spans are only recorded while a profile runs, flame keeps them in memory
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+/// the directory of the flame graphs, eg: `pprof_dir` of the config
+pub fn set_output_dir(dir: String) {
+    *OUTPUT_DIR.write() = Some(dir);
+}
+
+pub fn output_dir() -> String {
+    OUTPUT_DIR.read().clone().unwrap_or_else(|| *random_dir())
+}
+
+/// record the span until the guard is dropped, nothing is recorded when no profile runs
+pub fn span(name: &'static str) -> Option<flame::SpanGuard> {
+    if PROFILING.load(Ordering::Relaxed) {
+        Some(flame::start_guard(name))
+    } else {
+        None
+    }
+}
+
+/// start a profile, it fails when one is running
+pub fn start_profile() -> Result<Timestamp, String> {
+    let mut profile = PROFILE.lock();
+    if let Some(started) = *profile {
+        return Err(format!("the profile started at {} is running", started));
+    }
+    let started = chrono::Local::now().timestamp() as Timestamp;
+    flame::clear();
+    PROFILING.store(true, Ordering::SeqCst);
+    *profile = Some(started);
+    info!("Start profiling");
+    Ok(started)
+}
+
+/// stop the running profile, the flame graph is written into the output directory and returned
+pub fn stop_profile() -> Result<(String, String), String> {
+    let mut profile = PROFILE.lock();
+    let started = profile.take().ok_or("no profile is running".to_string())?;
+    PROFILING.store(false, Ordering::SeqCst);
+    let dir = output_dir();
+    ::std::fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    let graph = format!("{}/flame-graph-{}.html", dir, started);
+    flame::dump_html(&mut File::create(&graph).map_err(|err| err.to_string())?).map_err(|err| err.to_string())?;
+    flame::clear();
+    info!("Stop profiling, flame graph=> {}", graph);
+    let html = ::std::fs::read_to_string(&graph).map_err(|err| err.to_string())?;
+    Ok((graph, html))
+}
+
+/// the memory of the process in kB, it's read from `/proc/self/status`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct HeapStats {
+    // the resident memory and its peak
+    pub rss: u64,
+    pub rss_peak: u64,
+    // the virtual memory and the data segment
+    pub virtual_size: u64,
+    pub data: u64,
+}
+
+impl HeapStats {
+    pub fn read() -> Result<HeapStats, String> {
+        let status = ::std::fs::read_to_string("/proc/self/status").map_err(|err| err.to_string())?;
+        Ok(HeapStats::parse(&status))
+    }
+
+    pub fn parse(status: &str) -> HeapStats {
+        let mut stats = HeapStats::default();
+        for line in status.lines() {
+            let mut fields = line.split_whitespace();
+            let (key, value) = match (fields.next(), fields.next().and_then(|value| value.parse().ok())) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            match key {
+                "VmRSS:" => stats.rss = value,
+                "VmHWM:" => stats.rss_peak = value,
+                "VmSize:" => stats.virtual_size = value,
+                "VmData:" => stats.data = value,
+                _ => {}
+            }
+        }
+        stats
+    }
+}
+
+/// the backlog and the drops of the actor mailboxes, actix doesn't expose the mailbox sizes, so
+/// they're the queues in front of the actors
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MailboxStats {
+    pub queues: BTreeMap<String, i64>,
+    pub dropped: BTreeMap<String, u64>,
+}
+
+impl MailboxStats {
+    pub fn gather() -> MailboxStats {
+        MailboxStats {
+            queues: REGISTRY.gauges_of(&MAILBOX_GAUGES),
+            dropped: REGISTRY.counters_of(&MAILBOX_DROP_COUNTERS),
+        }
+    }
+}
+
 pub fn spawn_signal_handler(dir: String) {
+    let code = run_until_signal(dir, || ());
+    ::std::process::exit(code);
+}
+
+/// Wait for SIGINT or SIGTERM in a new thread and run `on_signal` then, eg: stop the embedded
+/// node, the process isn't exited
+pub fn spawn_signal_watcher<F: FnOnce() + Send + 'static>(dir: String, on_signal: F) {
+    ::std::thread::spawn(move || {
+        let code = run_until_signal(dir, on_signal);
+        info!("Signal handler stopped, code: {}", code);
+    });
+}
+
+// the system of the current thread runs until a signal, the flame graph is written into `dir`
+fn run_until_signal<F: FnOnce() + Send + 'static>(dir: String, on_signal: F) -> i32 {
     let int_fut = Signal::new(SIGINT).flatten_stream();
     let term_fut = Signal::new(SIGTERM).flatten_stream();
     let s_stream = int_fut.select(term_fut);
 
     info!("Start signal handler");
     flame::start("read file");
-    let code = System::run(move || {
+    System::run(move || {
         tokio::spawn(
             s_stream
                 .into_future()
@@ -24,18 +166,53 @@ pub fn spawn_signal_handler(dir: String) {
                     let graph = dir.to_owned() + "/flame-graph.html";
                     info!("flame graph=> {}", graph);
                     flame::dump_html(&mut File::create(graph).unwrap()).unwrap();
+                    on_signal();
                     future::ok(())
                 })
                 .map_err(|_err| ()),
         );
-    });
-    ::std::process::exit(code);
+    })
+}
+
+/// Write a crash dump bundle into `dir` when the node panics, the bundle carries the panic
+/// message, location and the node features.
+pub fn install_crash_dump(dir: String, features: Vec<Feature>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = info.payload().downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let location = info.location().map(|location| format!("{}:{}", location.file(), location.line()));
+        let now = chrono::Local::now();
+        let bundle = json!({
+            "time": now.to_rfc3339(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "message": message,
+            "location": location,
+            "features": features,
+        });
+        let path = format!("{}/crash-{}.json", dir, now.timestamp());
+        if ::std::fs::create_dir_all(&dir).is_ok() {
+            if let Ok(mut file) = File::create(&path) {
+                let _ = serde_json::to_writer_pretty(&mut file, &bundle);
+                error!("Crash dump => {}", path);
+            }
+        }
+        default_hook(info);
+    }));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn t_heap_stats() {
+        let status = "Name:\tconsensus\nVmHWM:\t  2048 kB\nVmRSS:\t  1024 kB\nVmSize:\t  8192 kB\nVmData:\t  4096 kB\nThreads:\t8\n";
+        assert_eq!(HeapStats::parse(status), HeapStats { rss: 1024, rss_peak: 2048, virtual_size: 8192, data: 4096 });
+        assert_eq!(HeapStats::parse(""), HeapStats::default());
+    }
+
     #[test]
     fn t_spawn_signal_handler() {
         use crate::common::random_dir;
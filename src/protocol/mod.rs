@@ -16,8 +16,10 @@ use std::io::Cursor;
 use std::sync::RwLock;
 
 use crate::{
+    consensus::sign_guard::SignState,
     consensus::types::View,
     consensus::validator::{self, fn_selector, ImplValidatorSet, ValidatorSet},
+    signer::Signer,
     types::EMPTY_ADDRESS,
 };
 
@@ -91,6 +93,12 @@ impl GossipMessage {
         self.signature = Some(signature);
     }
 
+    /// sign the vote by the signer of the validator, the key may be kept by a remote signer
+    pub fn sign_with(&mut self, signer: &Signer, vote: &SignState) -> Result<(), String> {
+        self.signature = Some(signer.sign_vote(&self.sign_digest(), vote)?);
+        Ok(())
+    }
+
     pub fn set_seal(&mut self, digest: Hash, secret: &Secret) {
         let seal_sign = digest.sign(secret).unwrap();
         self.commit_seal = Some(seal_sign);
@@ -139,6 +147,14 @@ impl GossipMessage {
     }
 }
 
+/// GossipMessages is a batch of consensus messages, it is used to persist the messages that
+/// contributed to a committed block
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GossipMessages(pub Vec<GossipMessage>);
+
+implement_cryptohash_traits! {GossipMessages}
+implement_storagevalue_traits! {GossipMessages}
+
 pub struct MessageManage<RHS = ImplValidatorSet>
     where
         RHS: ValidatorSet,
@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::crossbeam_channel::{self, RecvTimeoutError, Sender};
+use cryptocurrency_kit::crypto::Hash;
+use cryptocurrency_kit::ethkey::signature::sign_bytes;
+use cryptocurrency_kit::ethkey::{public_to_address, recover_bytes, Address, KeyPair, Signature};
+use parking_lot::Mutex;
+use sha3::{Digest, Keccak256};
+
+use crate::consensus::sign_guard::{SafeSignGuard, SignState};
+use crate::metrics;
+
+// the requests older than it are refused by the signer, in millis
+pub const MAX_REQUEST_AGE: u64 = 30_000;
+pub const DEFAULT_SIGNER_TIMEOUT: Duration = Duration::from_secs(3);
+const KIND_DIGEST: &str = "digest";
+const KIND_BYTES: &str = "bytes";
+
+/// Signer signs for the validator, the consensus core, the minner and the engines never touch
+/// the validator key directly, so the key can be kept by a remote signing box or an HSM.
+pub trait Signer {
+    /// the validator address of the key
+    fn address(&self) -> Address;
+    /// sign a digest, eg: the consensus messages, the commit seals and the proposer seals
+    fn sign(&self, digest: &Hash) -> Result<Signature, String>;
+    /// sign a raw payload which is hashed by the signer, eg: the transactions
+    fn sign_bytes(&self, payload: &[u8]) -> Result<Signature, String>;
+    /// sign a digest for the vote which the sign guard allowed, a remote signer checks the vote
+    /// against its own high-water mark
+    fn sign_vote(&self, digest: &Hash, _vote: &SignState) -> Result<Signature, String> {
+        self.sign(digest)
+    }
+}
+
+pub type SafeSigner = Arc<Signer + Send + Sync>;
+
+/// LocalSigner keeps the validator key in the memory of the node
+pub struct LocalSigner {
+    key_pair: KeyPair,
+}
+
+impl LocalSigner {
+    pub fn new(key_pair: KeyPair) -> Self {
+        LocalSigner { key_pair }
+    }
+}
+
+impl Signer for LocalSigner {
+    fn address(&self) -> Address {
+        self.key_pair.address()
+    }
+
+    fn sign(&self, digest: &Hash) -> Result<Signature, String> {
+        digest.sign(self.key_pair.secret()).map_err(|err| format!("{:?}", err))
+    }
+
+    fn sign_bytes(&self, payload: &[u8]) -> Result<Signature, String> {
+        sign_bytes(self.key_pair.secret(), payload).map_err(|err| format!("{:?}", err))
+    }
+}
+
+/// SignRequest is a line of json on the connection to the signer, the `auth` proves that the
+/// node knows the shared token. The requests of a connection are answered in order, the `id`
+/// matches the response. The consensus signatures carry the `vote`, the signer refuses the votes
+/// below its high-water mark.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SignRequest {
+    #[serde(default)]
+    pub id: u64,
+    // `digest` or `bytes`
+    pub kind: String,
+    pub payload: String,
+    // millis
+    pub timestamp: u64,
+    #[serde(default)]
+    pub vote: Option<SignState>,
+    pub auth: String,
+}
+
+impl SignRequest {
+    pub fn new(id: u64, token: &[u8], kind: &str, payload: &[u8], vote: Option<SignState>, timestamp: u64) -> Self {
+        SignRequest {
+            id,
+            kind: kind.to_owned(),
+            payload: hex::encode(payload),
+            timestamp,
+            auth: hex::encode(request_auth(token, kind, payload, vote.as_ref(), timestamp)),
+            vote,
+        }
+    }
+
+    /// the payload of the authenticated and fresh request
+    pub fn verify(&self, token: &[u8], now: u64) -> Result<Vec<u8>, String> {
+        if self.kind != KIND_DIGEST && self.kind != KIND_BYTES {
+            return Err(format!("unknown kind: {}", self.kind));
+        }
+        let payload = hex::decode(&self.payload).map_err(|err| format!("invalid payload, {:?}", err))?;
+        let auth = hex::decode(&self.auth).map_err(|err| format!("invalid auth, {:?}", err))?;
+        if !constant_time_eq(&auth, &request_auth(token, &self.kind, &payload, self.vote.as_ref(), self.timestamp)) {
+            return Err("unauthenticated request".to_string());
+        }
+        if self.timestamp + MAX_REQUEST_AGE < now || self.timestamp > now + MAX_REQUEST_AGE {
+            return Err(format!("stale request, timestamp: {}", self.timestamp));
+        }
+        if self.kind == KIND_DIGEST && payload.len() != 32 {
+            return Err(format!("invalid digest length: {}", payload.len()));
+        }
+        Ok(payload)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SignResponse {
+    #[serde(default)]
+    pub id: u64,
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl SignResponse {
+    fn into_signature(self) -> Result<Signature, String> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        decode_signature(&self.signature.ok_or("the response has no signature".to_string())?)
+    }
+}
+
+type Waiter = Sender<Result<Signature, String>>;
+
+// the connection to the signer and its number, a new connection is opened after a failure
+type Connection = Arc<Mutex<Option<(u64, TcpStream)>>>;
+// the waiting requests by the id, with the number of their connection
+type Waiters = Arc<Mutex<HashMap<u64, (u64, Waiter)>>>;
+
+/// RemoteSigner asks the signing box over one persistent tcp connection, the requests of all
+/// callers are pipelined on it and a reader thread hands the responses to their callers, so a
+/// signature costs one round trip. The recovered signer of every signature must be the validator,
+/// so a wrong or swapped key is never used.
+pub struct RemoteSigner {
+    endpoint: String,
+    validator: Address,
+    token: Vec<u8>,
+    timeout: Duration,
+    connection: Connection,
+    waiters: Waiters,
+    next_id: AtomicU64,
+}
+
+impl RemoteSigner {
+    pub fn new(endpoint: &str, validator: Address, token: Vec<u8>, timeout: Duration) -> Self {
+        RemoteSigner {
+            endpoint: endpoint.to_owned(),
+            validator,
+            token,
+            timeout,
+            connection: Arc::new(Mutex::new(None)),
+            waiters: Arc::new(Mutex::new(HashMap::new())),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// sign a random digest, the node refuses to start if the signer is unreachable or holds
+    /// another key
+    pub fn probe(&self) -> Result<(), String> {
+        let digest: [u8; 32] = rand::random();
+        self.sign(&Hash::from_slice(&digest).unwrap()).map(|_| ())
+    }
+
+    fn request(&self, kind: &str, payload: &[u8], vote: Option<&SignState>) -> Result<Signature, String> {
+        let result = self.send(kind, payload, vote).and_then(|signature| {
+            let signer = recover_bytes(&signature, payload)
+                .map(|public| public_to_address(&public))
+                .map_err(|_| "invalid signature".to_string())?;
+            if signer != self.validator {
+                return Err(format!("the signature is signed by {:?}, not the validator {:?}", signer, self.validator));
+            }
+            Ok(signature)
+        });
+        match result {
+            Ok(_) => metrics::inc_counter("remote_signer_requests_total"),
+            Err(ref err) => {
+                metrics::inc_counter("remote_signer_failures_total");
+                warn!("Remote signer failed, endpoint: {}, err: {}", self.endpoint, err);
+            }
+        }
+        result
+    }
+
+    fn send(&self, kind: &str, payload: &[u8], vote: Option<&SignState>) -> Result<Signature, String> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        let request = SignRequest::new(id, &self.token, kind, payload, vote.cloned(), timestamp);
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        {
+            // the writes of the callers are serialized by the connection lock
+            let mut connection = self.connection.lock();
+            if connection.is_none() {
+                *connection = Some(self.connect()?);
+            }
+            let (number, written) = {
+                let (number, stream) = connection.as_mut().unwrap();
+                self.waiters.lock().insert(id, (*number, tx));
+                (*number, write_line(stream, &request))
+            };
+            if let Err(err) = written {
+                self.waiters.lock().remove(&id);
+                close(&mut connection, number);
+                return Err(err);
+            }
+        }
+        let result = match rx.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(format!("the signer didn't answer in {:?}", self.timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err("the connection to the signer is closed".to_string()),
+        };
+        self.waiters.lock().remove(&id);
+        result
+    }
+
+    // open the connection and start its reader
+    fn connect(&self) -> Result<(u64, TcpStream), String> {
+        let address = self.endpoint.to_socket_addrs().map_err(|err| err.to_string())?
+            .next().ok_or(format!("unresolved signer endpoint: {}", self.endpoint))?;
+        let stream = TcpStream::connect_timeout(&address, self.timeout).map_err(|err| err.to_string())?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|err| err.to_string())?;
+        stream.set_nodelay(true).map_err(|err| err.to_string())?;
+        let reader = stream.try_clone().map_err(|err| err.to_string())?;
+        let number = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (connection, waiters) = (self.connection.clone(), self.waiters.clone());
+        thread::Builder::new().name("remote-signer".to_string()).spawn(move || {
+            read_responses(reader, number, connection, waiters);
+        }).map_err(|err| err.to_string())?;
+        debug!("Connected to the remote signer, endpoint: {}", self.endpoint);
+        Ok((number, stream))
+    }
+}
+
+// hand the responses to their waiters until the connection is closed, the waiters of the closed
+// connection fail then instead of at their timeouts
+fn read_responses(stream: TcpStream, number: u64, connection: Connection, waiters: Waiters) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                debug!("The connection to the remote signer is closed, err: {}", err);
+                break;
+            }
+        }
+        let response: SignResponse = match serde_json::from_str(&line) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Invalid response of the remote signer, err: {}", err);
+                break;
+            }
+        };
+        if let Some((_, waiter)) = waiters.lock().remove(&response.id) {
+            let _ = waiter.send(response.into_signature());
+        }
+    }
+    close(&mut connection.lock(), number);
+    waiters.lock().retain(|_, (connection, _)| *connection != number);
+}
+
+// close the connection if it's still the numbered one
+fn close(connection: &mut Option<(u64, TcpStream)>, number: u64) {
+    if connection.as_ref().map_or(false, |(current, _)| *current == number) {
+        if let Some((_, stream)) = connection.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for RemoteSigner {
+    fn drop(&mut self) {
+        // the reader thread exits with the connection
+        if let Some((_, stream)) = self.connection.lock().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn address(&self) -> Address {
+        self.validator
+    }
+
+    fn sign(&self, digest: &Hash) -> Result<Signature, String> {
+        self.request(KIND_DIGEST, digest.as_ref(), None)
+    }
+
+    fn sign_bytes(&self, payload: &[u8]) -> Result<Signature, String> {
+        self.request(KIND_BYTES, payload, None)
+    }
+
+    fn sign_vote(&self, digest: &Hash, vote: &SignState) -> Result<Signature, String> {
+        self.request(KIND_DIGEST, digest.as_ref(), Some(vote))
+    }
+}
+
+/// the side of the signing box, every connection is served by its own thread until it's closed.
+/// The votes are checked against the high-water mark of the signer, which is shared by all nodes
+/// that use it, so two nodes of one validator never sign two votes for a step.
+pub fn serve_signer(listener: TcpListener, signer: SafeSigner, token: Vec<u8>, guard: SafeSignGuard) -> Result<(), String> {
+    info!("Signer is serving, validator: {:?}, address: {:?}", signer.address(), listener.local_addr());
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|err| err.to_string())?;
+        let (signer, token, guard) = (signer.clone(), token.clone(), guard.clone());
+        thread::Builder::new().name("signer-connection".to_string()).spawn(move || {
+            let peer = stream.peer_addr();
+            if let Err(err) = serve_connection(stream, &signer, &token, &guard) {
+                warn!("Failed to answer the sign requests, peer: {:?}, err: {}", peer, err);
+            }
+        }).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream, signer: &SafeSigner, token: &[u8], guard: &SafeSignGuard) -> Result<(), String> {
+    let mut writer = stream.try_clone().map_err(|err| err.to_string())?;
+    writer.set_write_timeout(Some(DEFAULT_SIGNER_TIMEOUT)).map_err(|err| err.to_string())?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(());
+        }
+        let request: SignRequest = serde_json::from_str(&line).map_err(|err| format!("invalid message, {}", err))?;
+        let response = match answer(&request, signer, token, guard) {
+            Ok(signature) => SignResponse { id: request.id, signature: Some(hex::encode(signature.to_vec())), error: None },
+            Err(err) => SignResponse { id: request.id, signature: None, error: Some(err) },
+        };
+        write_line(&mut writer, &response)?;
+    }
+}
+
+fn answer(request: &SignRequest, signer: &SafeSigner, token: &[u8], guard: &SafeSignGuard) -> Result<Signature, String> {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    let payload = request.verify(token, now)?;
+    if let Some(ref vote) = request.vote {
+        guard.lock().check_state(vote.clone()).map_err(|err| {
+            metrics::inc_counter("remote_signer_refused_total");
+            format!("the vote is refused, {}", err)
+        })?;
+    }
+    if request.kind == KIND_DIGEST {
+        signer.sign(&Hash::from_slice(&payload).unwrap())
+    } else {
+        signer.sign_bytes(&payload)
+    }
+}
+
+/// the token shared by the node and the signer, the trailing newline of the file is ignored
+pub fn read_token_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
+    let token = fs::read_to_string(path.as_ref()).map_err(|err| format!("failed to read the token file {:?}, {}", path.as_ref(), err))?;
+    let token = token.trim_end_matches(|c| c == '\n' || c == '\r');
+    if token.is_empty() {
+        return Err(format!("the token file {:?} is empty", path.as_ref()));
+    }
+    Ok(token.as_bytes().to_vec())
+}
+
+// keccak256(token || kind || payload || vote || timestamp), the vote is its json
+fn request_auth(token: &[u8], kind: &str, payload: &[u8], vote: Option<&SignState>, timestamp: u64) -> Vec<u8> {
+    let mut hasher = Keccak256::default();
+    hasher.input(token);
+    hasher.input(kind.as_bytes());
+    hasher.input(payload);
+    if let Some(vote) = vote {
+        hasher.input(&serde_json::to_vec(vote).unwrap());
+    }
+    hasher.input(&timestamp.to_be_bytes());
+    hasher.result().to_vec()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn decode_signature(signature: &str) -> Result<Signature, String> {
+    let bytes = hex::decode(signature).map_err(|err| format!("invalid signature, {:?}", err))?;
+    if bytes.len() != 65 {
+        return Err(format!("invalid signature length: {}", bytes.len()));
+    }
+    Ok(Signature::from_slice(&bytes))
+}
+
+fn write_line<T: serde::Serialize>(stream: &mut TcpStream, value: &T) -> Result<(), String> {
+    let mut line = serde_json::to_vec(value).map_err(|err| err.to_string())?;
+    line.push(b'\n');
+    stream.write_all(&line).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+    use crate::common::random_dir;
+    use crate::consensus::sign_guard::{SignGuard, SignStep};
+
+    // the signing box on a random port, the sign state is kept in a new directory
+    fn start_signer(key_pair: &KeyPair, token: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap().to_string();
+        let dir = *random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let guard = Arc::new(Mutex::new(SignGuard::load(&dir).unwrap()));
+        let (local, token): (SafeSigner, _) = (Arc::new(LocalSigner::new(key_pair.clone())), token.to_vec());
+        thread::spawn(move || serve_signer(listener, local, token, guard));
+        endpoint
+    }
+
+    #[test]
+    fn t_remote_signer() {
+        let key_pair = Random.generate().unwrap();
+        let token = b"token".to_vec();
+        let endpoint = start_signer(&key_pair, &token);
+
+        let signer = RemoteSigner::new(&endpoint, key_pair.address(), token.clone(), DEFAULT_SIGNER_TIMEOUT);
+        assert!(signer.probe().is_ok());
+        let signature = signer.sign_bytes(b"payload").unwrap();
+        assert_eq!(recover_bytes(&signature, b"payload").map(|public| public_to_address(&public)).ok(), Some(key_pair.address()));
+
+        // the signer holds another key
+        let other = RemoteSigner::new(&endpoint, Random.generate().unwrap().address(), token.clone(), DEFAULT_SIGNER_TIMEOUT);
+        assert!(other.probe().is_err());
+        // the wrong token
+        let other = RemoteSigner::new(&endpoint, key_pair.address(), b"wrong".to_vec(), DEFAULT_SIGNER_TIMEOUT);
+        assert!(other.probe().unwrap_err().contains("unauthenticated"));
+
+        // the replayed request
+        let request = SignRequest::new(1, &token, KIND_DIGEST, &[0; 32], None, 1);
+        assert!(request.verify(&token, 1).is_ok());
+        assert!(request.verify(&token, 1 + MAX_REQUEST_AGE + 1).unwrap_err().contains("stale"));
+        // the vote is authenticated too
+        let mut forged = request.clone();
+        forged.vote = Some(SignState { height: 1, round: 0, step: SignStep::Commit, digest: Hash::zero() });
+        assert!(forged.verify(&token, 1).unwrap_err().contains("unauthenticated"));
+    }
+
+    // the requests of many callers share one connection, a closed connection is opened again
+    #[test]
+    fn t_remote_signer_connection() {
+        let key_pair = Random.generate().unwrap();
+        let endpoint = start_signer(&key_pair, b"token");
+        let signer = Arc::new(RemoteSigner::new(&endpoint, key_pair.address(), b"token".to_vec(), DEFAULT_SIGNER_TIMEOUT));
+        let callers: Vec<_> = (0..8u8).map(|i| {
+            let signer = signer.clone();
+            thread::spawn(move || (0..8u8).all(|j| signer.sign_bytes(&[i, j]).is_ok()))
+        }).collect();
+        assert!(callers.into_iter().all(|caller| caller.join().unwrap()));
+        let number = signer.connection.lock().as_ref().map(|(number, _)| *number).unwrap();
+
+        // the connection which the signer closed
+        signer.connection.lock().as_ref().unwrap().1.shutdown(Shutdown::Both).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(signer.connection.lock().is_none());
+        assert!(signer.sign_bytes(b"again").is_ok());
+        assert_ne!(signer.connection.lock().as_ref().map(|(number, _)| *number), Some(number));
+    }
+
+    // two nodes of one validator share the signer, the second vote of a step is refused
+    #[test]
+    fn t_remote_signer_high_water_mark() {
+        let key_pair = Random.generate().unwrap();
+        let endpoint = start_signer(&key_pair, b"token");
+        let (first, second) = (
+            RemoteSigner::new(&endpoint, key_pair.address(), b"token".to_vec(), DEFAULT_SIGNER_TIMEOUT),
+            RemoteSigner::new(&endpoint, key_pair.address(), b"token".to_vec(), DEFAULT_SIGNER_TIMEOUT),
+        );
+        let (block, other) = (Hash::from_slice(&[1; 32]).unwrap(), Hash::from_slice(&[2; 32]).unwrap());
+        let vote = |height, step, digest| SignState { height, round: 0, step, digest };
+        assert!(first.sign_vote(&block, &vote(5, SignStep::Prepare, block)).is_ok());
+        // the same vote signs again, eg: the commit seal and the message of one commit
+        assert!(second.sign_vote(&other, &vote(5, SignStep::Prepare, block)).is_ok());
+        assert!(second.sign_vote(&other, &vote(5, SignStep::Prepare, other)).unwrap_err().contains("refused"));
+        assert!(second.sign_vote(&other, &vote(4, SignStep::Commit, other)).unwrap_err().contains("refused"));
+        assert!(first.sign_vote(&block, &vote(5, SignStep::Commit, block)).is_ok());
+        // the signatures without a vote aren't guarded
+        assert!(second.sign(&other).is_ok());
+    }
+}
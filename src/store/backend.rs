@@ -0,0 +1,158 @@
+use kvdb::DBTransaction;
+use kvdb_rocksdb::Database;
+
+use super::memory::MemoryDB;
+use super::types::{Fork, Iter, Snapshot};
+
+/// the store path of the memory store, eg: `store = ":memory:"`
+pub const MEMORY_STORE: &str = ":memory:";
+const READ_ONLY: &str = "the store is opened read only";
+
+/// Backend is the store of a node, it's picked by the `store` path of the config, the memory
+/// store is lost when the node stops, eg: the tests and the ephemeral devnets
+#[derive(Debug)]
+pub enum Backend {
+    RocksDB(Database),
+    Memory(MemoryDB),
+    // the rocksdb store which refuses the writes of the chain data, eg: the inspector of a
+    // stopped node. Rocksdb itself still opens the files read-write, see `open_read_only`
+    ReadOnly(Database),
+}
+
+impl Backend {
+    /// open the rocksdb store at the path or a fresh memory store for `:memory:`
+    pub fn open(path: &str) -> Result<Self, String> {
+        if is_memory(path) {
+            return Ok(Backend::Memory(MemoryDB::new()));
+        }
+        Database::open_default(path).map(Backend::RocksDB).map_err(|err| err.to_string())
+    }
+
+    /// open the existing rocksdb store at the path, every write and flush of the chain data fails.
+    /// The vendored kvdb-rocksdb has no read-only open, so rocksdb still takes the LOCK of the
+    /// store and may replay the WAL and rewrite the MANIFEST on open: the files of the store can
+    /// change, the chain data can't. Examine a copy to keep the files untouched.
+    pub fn open_read_only(path: &str) -> Result<Self, String> {
+        if is_memory(path) {
+            return Err("the memory store can't be opened read only".to_string());
+        }
+        Database::open_default(path).map(Backend::ReadOnly).map_err(|err| err.to_string())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Backend::ReadOnly(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_memory(&self) -> bool {
+        match self {
+            Backend::Memory(_) => true,
+            _ => false,
+        }
+    }
+
+    /// the writable rocksdb store, eg: the compaction rewrites its files
+    pub fn rocksdb(&self) -> Option<&Database> {
+        match self {
+            Backend::RocksDB(db) => Some(db),
+            Backend::Memory(_) | Backend::ReadOnly(_) => None,
+        }
+    }
+}
+
+pub fn is_memory(path: &str) -> bool {
+    path == MEMORY_STORE
+}
+
+impl From<Database> for Backend {
+    fn from(db: Database) -> Self {
+        Backend::RocksDB(db)
+    }
+}
+
+impl From<MemoryDB> for Backend {
+    fn from(db: MemoryDB) -> Self {
+        Backend::Memory(db)
+    }
+}
+
+impl Snapshot for Backend {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Backend::RocksDB(db) | Backend::ReadOnly(db) => Snapshot::get(db, key),
+            Backend::Memory(db) => db.get(key),
+        }
+    }
+
+    fn iter<'a>(&'a self, from: &[u8]) -> Iter<'a> {
+        match self {
+            Backend::RocksDB(db) | Backend::ReadOnly(db) => Snapshot::iter(db, from),
+            Backend::Memory(db) => db.iter(from),
+        }
+    }
+}
+
+impl Fork for Backend {
+    fn write(&self, tx: DBTransaction) -> Result<(), String> {
+        match self {
+            Backend::RocksDB(db) => Fork::write(db, tx),
+            Backend::Memory(db) => db.write(tx),
+            Backend::ReadOnly(_) => Err(READ_ONLY.to_string()),
+        }
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        match self {
+            Backend::RocksDB(db) => Fork::flush(db),
+            Backend::Memory(db) => db.flush(),
+            Backend::ReadOnly(_) => Err(READ_ONLY.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use cryptocurrency_kit::crypto::CryptoHash;
+
+    use super::*;
+    use super::super::schema::Schema;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_backend() {
+        let memory = Backend::open(MEMORY_STORE).unwrap();
+        assert!(memory.is_memory() && memory.rocksdb().is_none());
+        let rocksdb = Backend::open(&random_dir()).unwrap();
+        assert!(!rocksdb.is_memory() && rocksdb.rocksdb().is_some());
+
+        // the same writes read the same over both stores
+        for backend in vec![memory, rocksdb] {
+            let schema = Schema::new(Arc::new(backend));
+            let mut hashes = schema.block_hashes_by_height();
+            hashes.extend((0..5_u64).map(|height| height.hash()));
+            assert_eq!(schema.block_hashes_by_height().len(), 5);
+            assert_eq!(schema.block_hashes_by_height().iter().count(), 5);
+            assert!(schema.database().flush().is_ok());
+        }
+
+        // the read only store reads the written store and refuses the writes
+        let path = random_dir();
+        {
+            let schema = Schema::new(Arc::new(Backend::open(&path).unwrap()));
+            let mut hashes = schema.block_hashes_by_height();
+            hashes.push(0_u64.hash());
+            schema.database().flush().unwrap();
+        }
+        assert!(Backend::open_read_only(MEMORY_STORE).is_err());
+        let read_only = Backend::open_read_only(&path).unwrap();
+        assert!(read_only.is_read_only() && read_only.rocksdb().is_none());
+        let schema = Schema::new(Arc::new(read_only));
+        assert_eq!(schema.block_hashes_by_height().get(0), Some(0_u64.hash()));
+        assert!(schema.database().write(DBTransaction::new()).is_err());
+        assert!(schema.database().flush().is_err());
+    }
+}
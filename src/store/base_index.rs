@@ -6,15 +6,16 @@ use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
 use cryptocurrency_kit::storage::keys::StorageKey;
 use cryptocurrency_kit::storage::values::StorageValue;
 use kvdb::{DBTransaction, DBValue};
-use kvdb_rocksdb::{Database, DatabaseIterator};
+use kvdb_rocksdb::Database;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 
-use super::types::Iter;
+use super::iter::RawIter;
+use super::types::Fork;
 
 const COL: Option<u32> = None;
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum IndexType {
     Entry,
     KeySet,
@@ -50,23 +51,24 @@ impl From<u8> for IndexType {
 implement_cryptohash_traits!(IndexType);
 implement_storagevalue_traits!(IndexType);
 
-pub struct BaseIndex {
+/// BaseIndex reads and writes the keys under its name in the store `T`, the rocksdb store by default
+pub struct BaseIndex<T: ?Sized = Database> {
     name: String,
     index_id: Option<Vec<u8>>,
     index_type: IndexType,
-    view: Arc<Database>,
+    view: Arc<T>,
 }
 
+/// the pairs of an index in the key order or the reverse order, the keys are stripped of the
+/// index name and id
 pub struct BaseIndexIter<'a, K, V> {
-    base_iter: Iter<'a>,
+    raw: RawIter<'a>,
     base_prefix_len: usize,
-    index_id: Vec<u8>,
-    ended: bool,
     _k: PhantomData<K>,
     _v: PhantomData<V>,
 }
 
-impl std::fmt::Debug for BaseIndex {
+impl<T: ?Sized> std::fmt::Debug for BaseIndex<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let index_id = self
             .index_id
@@ -80,8 +82,8 @@ impl std::fmt::Debug for BaseIndex {
     }
 }
 
-impl BaseIndex {
-    pub fn new<S: AsRef<str>>(index_name: S, index_type: IndexType, view: Arc<Database>) -> Self {
+impl<T: Fork + ?Sized> BaseIndex<T> {
+    pub fn new<S: AsRef<str>>(index_name: S, index_type: IndexType, view: Arc<T>) -> Self {
         Self {
             name: index_name.as_ref().to_string(),
             index_id: None,
@@ -97,16 +99,23 @@ impl BaseIndex {
         let mut prefix_key = vec![0; name_len + index_len + key.size()];
         prefix_key[..name_len].copy_from_slice(self.name.as_bytes());
 
-        if let Some(ref _prefix) = self.index_id {
-            prefix_key[name_len..index_len]
-                .copy_from_slice(&self.index_id.as_ref().map_or(&vec![], |item| item));
+        if let Some(ref index_id) = self.index_id {
+            prefix_key[name_len..name_len + index_len].copy_from_slice(index_id);
         }
 
         key.write(&mut prefix_key[name_len + index_len..]);
         prefix_key
     }
 
-    pub fn snapshot(&self) -> &Database {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    pub fn snapshot(&self) -> &T {
         &self.view
     }
 
@@ -116,7 +125,7 @@ impl BaseIndex {
             V: StorageValue,
     {
         let key = self.prefix_key(key);
-        if let Some(value) = self.view.get(COL, &key).unwrap() {
+        if let Some(value) = self.view.get(&key) {
             return Some(StorageValue::from_bytes(Cow::from(value.as_ref())));
         }
         None
@@ -126,26 +135,48 @@ impl BaseIndex {
         where
             K: StorageKey + ?Sized,
     {
-        self.view.get(COL, &self.prefix_key(key)).unwrap().is_some()
+        self.view.contains(&self.prefix_key(key))
     }
 
-    pub fn iter<P, K, V>(&self, subprefix: &P) -> BaseIndexIter<K, V>
+    fn base_prefix_len(&self) -> usize {
+        self.name.len() + self.index_id.as_ref().map_or(0, |index_id| index_id.len())
+    }
+
+    // the prefix of the subprefix and the key of `from` under it
+    fn seek_key<P, F>(&self, subprefix: &P, from: &F) -> (Vec<u8>, Vec<u8>)
         where
             P: StorageKey,
-            K: StorageKey,
-            V: StorageValue,
+            F: StorageKey + ?Sized,
     {
-        let iter_prefix = self.prefix_key(subprefix);
+        let prefix = self.prefix_key(subprefix);
+        let mut seek = prefix.clone();
+        let mut buf = vec![0; from.size()];
+        from.write(&mut buf);
+        seek.extend_from_slice(&buf);
+        (prefix, seek)
+    }
+
+    fn base_iter<'a, K, V>(&self, raw: RawIter<'a>) -> BaseIndexIter<'a, K, V> {
         BaseIndexIter {
-            base_iter: self.view.iter_from_prefix(COL, &iter_prefix).unwrap(),
-            base_prefix_len: self.name.len() + self.index_id.as_ref().map_or(0, |p| p.len()),
-            index_id: iter_prefix,
-            ended: false,
+            raw,
+            base_prefix_len: self.base_prefix_len(),
             _k: PhantomData,
             _v: PhantomData,
         }
     }
 
+    /// the pairs under the subprefix in the key order
+    pub fn iter<P, K, V>(&self, subprefix: &P) -> BaseIndexIter<K, V>
+        where
+            P: StorageKey,
+            K: StorageKey,
+            V: StorageValue,
+    {
+        let prefix = self.prefix_key(subprefix);
+        self.base_iter(RawIter::forward(self.view.iter(&prefix), prefix))
+    }
+
+    /// the pairs under the subprefix from the first key at or above `from`
     pub fn iter_from<P, F, K, V>(&self, subprefix: &P, from: &F) -> BaseIndexIter<K, V>
         where
             P: StorageKey,
@@ -153,30 +184,36 @@ impl BaseIndex {
             K: StorageKey,
             V: StorageValue,
     {
-        let mut prefix_buf = self.prefix_key(subprefix);
-        let base_prefix_len = prefix_buf.len();
-
-        let iter_prefix = {
-            let mut buf = vec![0; from.size()];
-            from.write(&mut buf);
-            prefix_buf.extend_from_slice(&buf);
-            prefix_buf
-        };
-        //        use std::io::{self, Write};
-        //        writeln!(io::stdout(), "iter_prefix {:?}", iter_prefix).unwrap();
+        let (prefix, seek) = self.seek_key(subprefix, from);
+        self.base_iter(RawIter::forward(self.view.iter(&seek), prefix))
+    }
 
-        BaseIndexIter {
-            base_iter: self.view.iter_from_prefix(COL, &iter_prefix).unwrap(),
-            base_prefix_len,
-            index_id: Vec::from(&iter_prefix[..base_prefix_len]),
-            ended: false,
-            _k: PhantomData,
-            _v: PhantomData,
-        }
+    /// the pairs under the subprefix in the reverse order, the range is buffered
+    pub fn iter_rev<P, K, V>(&self, subprefix: &P) -> BaseIndexIter<K, V>
+        where
+            P: StorageKey,
+            K: StorageKey,
+            V: StorageValue,
+    {
+        let prefix = self.prefix_key(subprefix);
+        self.base_iter(RawIter::reverse(self.view.iter(&prefix), prefix, None))
+    }
+
+    /// the pairs under the subprefix in the reverse order from the last key at or below `from`,
+    /// the range is buffered
+    pub fn iter_rev_from<P, F, K, V>(&self, subprefix: &P, from: &F) -> BaseIndexIter<K, V>
+        where
+            P: StorageKey,
+            F: StorageKey + ?Sized,
+            K: StorageKey,
+            V: StorageValue,
+    {
+        let (prefix, seek) = self.seek_key(subprefix, from);
+        self.base_iter(RawIter::reverse(self.view.iter(&prefix), prefix, Some(seek)))
     }
 
     /////////////////////////////
-    pub fn fork(&mut self) -> &Database {
+    pub fn fork(&mut self) -> &T {
         &self.view
     }
 
@@ -186,9 +223,10 @@ impl BaseIndex {
 
     pub fn put_transaction(&self, tx: DBTransaction) {
         self.view.write(tx).unwrap();
-        self.view.flush().unwrap();
     }
 
+    /// the write is visible at once, it's flushed by the durability level of the ledger, see
+    /// `store::durability`
     pub fn put<K, V>(&mut self, key: &K, value: V)
         where
             K: StorageKey,
@@ -198,7 +236,6 @@ impl BaseIndex {
         let mut tx = self.view.transaction();
         tx.put_vec(COL, &key, value.into_bytes());
         self.view.write(tx).unwrap();
-        self.view.flush().unwrap();
     }
 
     pub fn remove<K>(&mut self, key: &K)
@@ -209,19 +246,16 @@ impl BaseIndex {
         let mut tx = self.view.transaction();
         tx.delete(COL, &key);
         self.view.write(tx).unwrap();
-        self.view.flush().unwrap();
     }
 
+    /// remove the keys of the index, the keys of the other indexes are untouched
     pub fn clear(&mut self) {
         let prefix = self.prefix_key("");
-        if let Some(iter) = self.view.iter_from_prefix(COL, &prefix) {
-            let mut tx = self.view.transaction();
-            iter.for_each(|item| {
-                tx.delete(COL, &item.0);
-            });
-            self.view.write(tx).unwrap();
-            self.view.flush().unwrap();
-        }
+        let mut tx = self.view.transaction();
+        RawIter::forward(self.view.iter(&prefix), prefix.clone()).for_each(|(key, _)| {
+            tx.delete(COL, &key);
+        });
+        self.view.write(tx).unwrap();
     }
 }
 
@@ -233,20 +267,7 @@ impl<'a, K, V> Iterator for BaseIndexIter<'a, K, V>
     type Item = (K::Owned, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.ended {
-            return None;
-        }
-
-        if let Some((k, v)) = self.base_iter.next() {
-            if k.starts_with(&self.index_id) {
-                return Some((
-                    K::read(&k[self.base_prefix_len..]),
-                    V::from_bytes(Cow::Borrowed(&v)),
-                ));
-            }
-        }
-        self.ended = true;
-        None
+        self.raw.next().map(|(k, v)| (K::read(&k[self.base_prefix_len..]), V::from_bytes(Cow::Borrowed(&v))))
     }
 }
 
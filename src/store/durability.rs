@@ -0,0 +1,195 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::types::Fork;
+use crate::metrics;
+
+/// when the committed blocks are synced to the disk, every write reaches the write ahead log at
+/// once, so a process crash loses nothing, the levels differ in how many blocks an os crash or a
+/// power failure may lose
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DurabilityLevel {
+    // sync every committed block
+    Block,
+    // sync every `flush_blocks` blocks or after `flush_interval`, whichever comes first
+    Batch,
+    // never sync, the os writes the log back by itself
+    Os,
+}
+
+/// how a flush syncs the write ahead log
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    // the flush only drains the buffered writes, the os writes the log back by itself
+    Never,
+    // the flush syncs the log files and the store directory
+    Flush,
+}
+
+/// the durability of the store writes, eg: `[durability]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DurabilityConfig {
+    #[serde(default = "default_level")]
+    pub level: DurabilityLevel,
+    #[serde(default = "default_flush_blocks")]
+    pub flush_blocks: u64,
+    // in millis, it's checked when a block is committed
+    #[serde(default = "default_flush_interval", with = "serde_millis")]
+    pub flush_interval: Duration,
+    #[serde(default = "default_fsync")]
+    pub fsync: FsyncPolicy,
+}
+
+impl Default for DurabilityConfig {
+    fn default() -> Self {
+        DurabilityConfig {
+            level: default_level(),
+            flush_blocks: default_flush_blocks(),
+            flush_interval: default_flush_interval(),
+            fsync: default_fsync(),
+        }
+    }
+}
+
+fn default_level() -> DurabilityLevel {
+    DurabilityLevel::Block
+}
+
+fn default_flush_blocks() -> u64 {
+    16
+}
+
+fn default_flush_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_fsync() -> FsyncPolicy {
+    FsyncPolicy::Flush
+}
+
+/// Durability syncs the store at the block commits by the configured level, the indexes write
+/// without syncing
+#[derive(Debug)]
+pub struct Durability {
+    config: DurabilityConfig,
+    // the store directory, the log isn't synced without it
+    path: Option<String>,
+    // the blocks committed since the last flush
+    pending: u64,
+    flushed: Instant,
+}
+
+impl Durability {
+    pub fn new(config: DurabilityConfig, path: Option<String>) -> Self {
+        Durability { config, path, pending: 0, flushed: Instant::now() }
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// a block is committed, true if the store is flushed and synced
+    pub fn commit<T: Fork + ?Sized>(&mut self, db: &T) -> bool {
+        self.pending += 1;
+        let due = match self.config.level {
+            DurabilityLevel::Block => true,
+            DurabilityLevel::Batch => self.pending >= self.config.flush_blocks || self.flushed.elapsed() >= self.config.flush_interval,
+            DurabilityLevel::Os => false,
+        };
+        if due {
+            self.flush(db);
+        }
+        due
+    }
+
+    /// flush the store and sync the log by the policy, eg: before the node stops
+    pub fn flush<T: Fork + ?Sized>(&mut self, db: &T) {
+        let start = Instant::now();
+        if let Err(err) = db.flush() {
+            error!("Failed to flush the store, err: {}", err);
+            return;
+        }
+        // the store writes the log without syncing it, the writes are synced here
+        if let (FsyncPolicy::Flush, Some(path)) = (self.config.fsync, self.path.as_ref()) {
+            if let Err(err) = sync_wal(Path::new(path)) {
+                metrics::inc_counter("store_sync_errors_total");
+                error!("Failed to sync the write ahead log, path: {}, err: {}", path, err);
+                return;
+            }
+        }
+        let elapsed = start.elapsed();
+        metrics::inc_counter("store_flushes_total");
+        metrics::observe("store_flush_duration_ms", elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_micros() as f64 / 1000.0);
+        self.pending = 0;
+        self.flushed = Instant::now();
+    }
+}
+
+// sync the write ahead log files of the store, they're named `<number>.log`, it's the
+// `FlushWAL(sync)` of rocksdb which the store doesn't expose. The directory is synced too, or
+// else a new log file may be lost with its entry.
+fn sync_wal(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(path).map_err(|err| err.to_string())?;
+    let mut synced = vec![];
+    for entry in entries.filter_map(Result::ok) {
+        let file = entry.path();
+        if file.extension().map_or(false, |extension| extension == "log") {
+            File::open(&file).and_then(|log| log.sync_all()).map_err(|err| format!("{:?}, {}", file, err))?;
+            synced.push(file);
+        }
+    }
+    File::open(path).and_then(|dir| dir.sync_all()).map_err(|err| format!("{:?}, {}", path, err))?;
+    Ok(synced)
+}
+
+#[cfg(test)]
+mod tests {
+    use kvdb_rocksdb::Database;
+
+    use super::*;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_durability() {
+        let path = random_dir();
+        let db = Database::open_default(&path).unwrap();
+        let config = DurabilityConfig { level: DurabilityLevel::Batch, flush_blocks: 3, flush_interval: Duration::from_secs(3600), fsync: FsyncPolicy::Flush };
+        let mut durability = Durability::new(config.clone(), Some(path.to_string()));
+        assert!(!durability.commit(&db));
+        assert!(!durability.commit(&db));
+        assert!(durability.commit(&db));
+        assert_eq!(durability.pending(), 0);
+
+        // the interval flushes a batch before it's full
+        let mut durability = Durability::new(DurabilityConfig { flush_interval: Duration::from_millis(0), ..config }, None);
+        assert!(durability.commit(&db));
+
+        let mut durability = Durability::new(DurabilityConfig { level: DurabilityLevel::Os, ..Default::default() }, None);
+        assert!(!durability.commit(&db));
+        assert_eq!(durability.pending(), 1);
+        assert!(Durability::new(DurabilityConfig::default(), None).commit(&db));
+    }
+
+    #[test]
+    fn t_sync_wal() {
+        let path = random_dir();
+        let db = Database::open_default(&path).unwrap();
+        let value = b"the block which must survive a power failure".to_vec();
+        let mut tx = db.transaction();
+        tx.put(None, b"block", &value);
+        Fork::write(&db, tx).unwrap();
+
+        // the write is in a log file which the flush syncs
+        let synced = sync_wal(Path::new(path.as_str())).unwrap();
+        assert!(synced.iter().any(|log| {
+            let buf = fs::read(log).unwrap();
+            buf.windows(value.len()).any(|window| window == value.as_slice())
+        }));
+        assert!(sync_wal(Path::new("/nonexistent/store")).is_err());
+        assert_eq!(DurabilityConfig::default().fsync, FsyncPolicy::Flush);
+    }
+}
@@ -8,24 +8,35 @@ use cryptocurrency_kit::types::Zero;
 use kvdb_rocksdb::Database;
 
 use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::Fork;
 
 #[derive(Debug)]
-pub struct Entry<V> {
-    base: BaseIndex,
+pub struct Entry<V, T: ?Sized = Database> {
+    base: BaseIndex<T>,
     _v: PhantomData<V>,
 }
 
-impl<V> Entry<V>
+impl<V, T> Entry<V, T>
 where
     V: StorageValue,
+    T: Fork + ?Sized,
 {
-    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<Database>) -> Self {
+    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<T>) -> Self {
         Entry {
             base: BaseIndex::new(index_name, IndexType::Entry, view),
             _v: PhantomData,
         }
     }
 
+    /// the index name, the keys of the index are prefixed with it
+    pub fn name(&self) -> &str {
+        self.base.name()
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.base.index_type()
+    }
+
     pub fn get(&self) -> Option<V> {
         self.base.get(&Zero)
     }
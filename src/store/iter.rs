@@ -0,0 +1,125 @@
+use std::vec;
+
+use super::types::Iter;
+
+type KeyValue = (Box<[u8]>, Box<[u8]>);
+
+/// RawIter yields the pairs whose keys start with the prefix. The store iterator never stops by
+/// itself, it runs to the end of the store, so the scan ends at the first key out of the prefix,
+/// the keys under a prefix are contiguous.
+pub enum RawIter<'a> {
+    Forward {
+        // it's dropped at the end of the prefix
+        inner: Option<Iter<'a>>,
+        prefix: Vec<u8>,
+    },
+    // the store iterator only moves forward, the reverse scan buffers the range
+    Reverse(vec::IntoIter<KeyValue>),
+}
+
+impl<'a> RawIter<'a> {
+    /// the keys under the prefix in order, `inner` is positioned at or after the prefix
+    pub fn forward(inner: Iter<'a>, prefix: Vec<u8>) -> Self {
+        RawIter::Forward { inner: Some(inner), prefix }
+    }
+
+    /// the keys under the prefix in the reverse order, from the last key at or below `seek`,
+    /// `inner` is positioned at the prefix
+    pub fn reverse(inner: Iter<'a>, prefix: Vec<u8>, seek: Option<Vec<u8>>) -> Self {
+        let forward = RawIter::forward(inner, prefix);
+        let mut pairs: Vec<KeyValue> = match seek {
+            Some(seek) => forward.take_while(|(key, _)| key[..] <= seek[..]).collect(),
+            None => forward.collect(),
+        };
+        pairs.reverse();
+        RawIter::Reverse(pairs.into_iter())
+    }
+}
+
+impl<'a> Iterator for RawIter<'a> {
+    type Item = KeyValue;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            RawIter::Forward { inner, prefix } => {
+                let pair = inner.as_mut()?.next();
+                match pair {
+                    Some(pair) if pair.0.starts_with(prefix) => Some(pair),
+                    // the rest of the store is out of the prefix
+                    _ => {
+                        *inner = None;
+                        None
+                    }
+                }
+            }
+            RawIter::Reverse(pairs) => pairs.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use kvdb_rocksdb::Database;
+    use rand::{random, thread_rng, Rng};
+
+    use super::super::map_index::MapIndex;
+    use crate::common::random_dir;
+
+    // the scans of random key sets match the ones of an ordered map
+    #[test]
+    fn t_prefix_scans() {
+        for _ in 0..20 {
+            let db = Arc::new(Database::open_default(&random_dir()).unwrap());
+            // the neighbour indexes sort before and after the scanned one
+            let (mut before, mut index, mut after): (MapIndex<u64, u64>, MapIndex<u64, u64>, MapIndex<u64, u64>) =
+                (MapIndex::new("idx_a", db.clone()), MapIndex::new("idx_b", db.clone()), MapIndex::new("idx_c", db.clone()));
+            let mut model = BTreeMap::new();
+            let len = thread_rng().gen_range(0, 64);
+            for _ in 0..len {
+                let (key, value) = (random::<u64>() % 1000, random::<u64>());
+                index.put(&key, value);
+                model.insert(key, value);
+                before.put(&key, 0);
+                after.put(&key, 0);
+            }
+            let expect: Vec<(u64, u64)> = model.iter().map(|(key, value)| (*key, *value)).collect();
+            assert_eq!(index.iter().collect::<Vec<_>>(), expect);
+            assert_eq!(index.keys().collect::<Vec<_>>(), model.keys().cloned().collect::<Vec<_>>());
+            assert_eq!(index.values().collect::<Vec<_>>(), model.values().cloned().collect::<Vec<_>>());
+            assert_eq!(index.iter_rev().collect::<Vec<_>>(), expect.iter().rev().cloned().collect::<Vec<_>>());
+
+            let seek = random::<u64>() % 1000;
+            let from: Vec<(u64, u64)> = model.range(seek..).map(|(key, value)| (*key, *value)).collect();
+            assert_eq!(index.iter_from(&seek).collect::<Vec<_>>(), from);
+            assert_eq!(index.iter_from(&seek).keys().collect::<Vec<_>>(), from.iter().map(|(key, _)| *key).collect::<Vec<_>>());
+            let down: Vec<(u64, u64)> = model.range(..=seek).rev().map(|(key, value)| (*key, *value)).collect();
+            assert_eq!(index.iter_rev_from(&seek).collect::<Vec<_>>(), down);
+            assert_eq!(index.iter_rev_from(&seek).values().collect::<Vec<_>>(), down.iter().map(|(_, value)| *value).collect::<Vec<_>>());
+
+            // the clear never reaches the neighbours
+            index.clear();
+            assert_eq!(index.iter().count(), 0);
+            assert_eq!(before.iter().count(), model.len());
+            assert_eq!(after.iter().count(), model.len());
+        }
+    }
+
+    #[test]
+    fn t_string_prefix_scans() {
+        let db = Arc::new(Database::open_default(&random_dir()).unwrap());
+        let mut index: MapIndex<String, u64> = MapIndex::new("idx", db.clone());
+        let mut model = BTreeMap::new();
+        for idx in 0..50_u64 {
+            let key: String = (0..thread_rng().gen_range(1, 6)).map(|_| thread_rng().gen_range(b'a', b'e') as char).collect();
+            index.put(&key, idx);
+            model.insert(key, idx);
+        }
+        assert_eq!(index.keys().collect::<Vec<_>>(), model.keys().cloned().collect::<Vec<_>>());
+        let seek = "c".to_string();
+        assert_eq!(index.iter_from(&seek).keys().collect::<Vec<_>>(), model.range(seek.clone()..).map(|(key, _)| key.clone()).collect::<Vec<_>>());
+        assert_eq!(index.iter_rev_from(&seek).keys().collect::<Vec<_>>(), model.range(..=seek).rev().map(|(key, _)| key.clone()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,116 @@
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::keys::StorageKey;
+
+use crate::types::Height;
+
+const ADDRESS_SIZE: usize = 20;
+const HEIGHT_SIZE: usize = 8;
+const POSITION_SIZE: usize = 4;
+
+/// the key of the per address indexes, the keys of an address are contiguous and in the height
+/// order, so an address is scanned by its prefix, eg: `schema.rewards().iter_prefix(&address)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AddressHeightKey {
+    pub address: Address,
+    pub height: Height,
+}
+
+impl AddressHeightKey {
+    pub fn new(address: Address, height: Height) -> Self {
+        AddressHeightKey { address, height }
+    }
+}
+
+impl StorageKey for AddressHeightKey {
+    fn size(&self) -> usize {
+        ADDRESS_SIZE + HEIGHT_SIZE
+    }
+
+    fn write(&self, buffer: &mut [u8]) {
+        self.address.write(&mut buffer[..ADDRESS_SIZE]);
+        self.height.write(&mut buffer[ADDRESS_SIZE..ADDRESS_SIZE + HEIGHT_SIZE]);
+    }
+
+    fn read(buffer: &[u8]) -> Self {
+        AddressHeightKey {
+            address: Address::read(&buffer[..ADDRESS_SIZE]),
+            height: Height::read(&buffer[ADDRESS_SIZE..ADDRESS_SIZE + HEIGHT_SIZE]),
+        }
+    }
+}
+
+/// the key of the address transaction index, one key per transaction, the keys of an address are
+/// in the commit order, so a page of them is a scan of its prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AddressTxKey {
+    pub address: Address,
+    pub height: Height,
+    /// the position of the transaction in the block
+    pub position: u32,
+}
+
+impl AddressTxKey {
+    pub fn new(address: Address, height: Height, position: u32) -> Self {
+        AddressTxKey { address, height, position }
+    }
+}
+
+impl StorageKey for AddressTxKey {
+    fn size(&self) -> usize {
+        ADDRESS_SIZE + HEIGHT_SIZE + POSITION_SIZE
+    }
+
+    fn write(&self, buffer: &mut [u8]) {
+        self.address.write(&mut buffer[..ADDRESS_SIZE]);
+        self.height.write(&mut buffer[ADDRESS_SIZE..ADDRESS_SIZE + HEIGHT_SIZE]);
+        self.position.write(&mut buffer[ADDRESS_SIZE + HEIGHT_SIZE..ADDRESS_SIZE + HEIGHT_SIZE + POSITION_SIZE]);
+    }
+
+    fn read(buffer: &[u8]) -> Self {
+        AddressTxKey {
+            address: Address::read(&buffer[..ADDRESS_SIZE]),
+            height: Height::read(&buffer[ADDRESS_SIZE..ADDRESS_SIZE + HEIGHT_SIZE]),
+            position: u32::read(&buffer[ADDRESS_SIZE + HEIGHT_SIZE..ADDRESS_SIZE + HEIGHT_SIZE + POSITION_SIZE]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use super::super::map_index::MapIndex;
+    use super::super::memory::MemoryDB;
+
+    #[test]
+    fn t_address_height_key() {
+        let db = Arc::new(MemoryDB::new());
+        let mut index: MapIndex<AddressHeightKey, u64, MemoryDB> = MapIndex::new("idx", db.clone());
+        let (a, b) = (Address::from(1), Address::from(2));
+        for height in vec![300, 2, 1 << 40] {
+            index.put(&AddressHeightKey::new(a, height), height);
+            index.put(&AddressHeightKey::new(b, height), 0);
+        }
+        // the heights of an address are in order, the other addresses are out of the prefix
+        assert_eq!(index.iter_prefix(&a).map(|(key, value)| (key.height, value)).collect::<Vec<_>>(), vec![(2, 2), (300, 300), (1 << 40, 1 << 40)]);
+        assert_eq!(index.iter_prefix(&b).count(), 3);
+        assert_eq!(index.get(&AddressHeightKey::new(a, 300)), Some(300));
+    }
+
+    #[test]
+    fn t_address_tx_key() {
+        let db = Arc::new(MemoryDB::new());
+        let mut index: MapIndex<AddressTxKey, u64, MemoryDB> = MapIndex::new("idx", db.clone());
+        let (a, b) = (Address::from(1), Address::from(2));
+        for (height, position) in vec![(300, 1), (2, 7), (300, 0), (1 << 40, 256)] {
+            index.put(&AddressTxKey::new(a, height, position), height);
+            index.put(&AddressTxKey::new(b, height, position), 0);
+        }
+        // the transactions of an address are in the (height, position) order
+        let keys: Vec<(u64, u32)> = index.iter_prefix(&a).map(|(key, _)| (key.height, key.position)).collect();
+        assert_eq!(keys, vec![(2, 7), (300, 0), (300, 1), (1 << 40, 256)]);
+        assert_eq!(index.iter_prefix(&b).count(), 4);
+        assert_eq!(index.iter_prefix(&a).skip(1).take(2).map(|(key, _)| key.position).collect::<Vec<_>>(), vec![0, 1]);
+    }
+}
@@ -4,9 +4,10 @@ use std::{cell::Cell, marker::PhantomData};
 use cryptocurrency_kit::crypto::*;
 use cryptocurrency_kit::storage::{keys::StorageKey, values::StorageValue};
 use cryptocurrency_kit::types::Zero;
-use kvdb_rocksdb::{Database, DatabaseIterator};
+use kvdb_rocksdb::Database;
 
 use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::Fork;
 
 /// data format
 /// |length|l-0, l-1, l-2, l-3|
@@ -17,8 +18,8 @@ use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
 /// ListX: IDX_NAMEX,
 
 #[debug]
-pub struct ListIndex<V> {
-    base: BaseIndex,
+pub struct ListIndex<V, T: ?Sized = Database> {
+    base: BaseIndex<T>,
     length: Cell<Option<u64>>,
     _v: PhantomData<V>,
 }
@@ -28,11 +29,18 @@ pub struct ListIndexIter<'a, V> {
     base_iter: BaseIndexIter<'a, u64, V>,
 }
 
-impl<V> ListIndex<V>
+pub struct ListIndexRevIter<'a, V, T: ?Sized = Database> {
+    list: &'a ListIndex<V, T>,
+    // the values below it are not read yet
+    next: u64,
+}
+
+impl<V, T> ListIndex<V, T>
 where
     V: StorageValue,
+    T: Fork + ?Sized,
 {
-    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<Database>) -> Self {
+    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<T>) -> Self {
         Self {
             base: BaseIndex::new(index_name, IndexType::List, view),
             length: Cell::new(None),
@@ -40,6 +48,15 @@ where
         }
     }
 
+    /// the index name, the keys of the index are prefixed with it
+    pub fn name(&self) -> &str {
+        self.base.name()
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.base.index_type()
+    }
+
     pub fn get(&self, index: u64) -> Option<V>
     where
         V: StorageValue,
@@ -82,6 +99,11 @@ where
         }
     }
 
+    /// the values from the last one, they're read by the index, the list is never buffered
+    pub fn iter_rev(&self) -> ListIndexRevIter<V, T> {
+        ListIndexRevIter { list: self, next: self.len() }
+    }
+
     /// mut
     pub fn set_len(&mut self, len: u64) {
         self.base.put(&Zero, len);
@@ -144,9 +166,10 @@ where
     }
 }
 
-impl<'a, V> ::std::iter::IntoIterator for &'a ListIndex<V>
+impl<'a, V, T> ::std::iter::IntoIterator for &'a ListIndex<V, T>
 where
     V: StorageValue,
+    T: Fork + ?Sized,
 {
     type Item = V;
     type IntoIter = ListIndexIter<'a, V>;
@@ -166,6 +189,21 @@ where
     }
 }
 
+impl<'a, V, T> Iterator for ListIndexRevIter<'a, V, T>
+where
+    V: StorageValue,
+    T: Fork + ?Sized,
+{
+    type Item = V;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 {
+            return None;
+        }
+        self.next -= 1;
+        self.list.get(self.next)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -230,6 +268,7 @@ mod tests {
             list_index.iter_from(3).collect::<Vec<u8>>(),
             Vec::<u8>::new()
         );
+        assert_eq!(list_index.iter_rev().collect::<Vec<u8>>(), vec![3, 2, 1]);
     }
     fn newdb() -> Database {
         use crate::common::random_dir;
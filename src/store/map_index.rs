@@ -6,10 +6,11 @@ use cryptocurrency_kit::types::Zero;
 use kvdb_rocksdb::Database;
 
 use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::Fork;
 
 //#[derive(Debug)]
-pub struct MapIndex<K, V> {
-    base: BaseIndex,
+pub struct MapIndex<K, V, T: ?Sized = Database> {
+    base: BaseIndex<T>,
     _k: PhantomData<K>,
     _v: PhantomData<V>,
 }
@@ -30,6 +31,21 @@ where
     }
 }
 
+impl<'a, K, V> MapIndexIter<'a, K, V>
+where
+    K: StorageKey + 'a,
+    V: StorageValue + 'a,
+{
+    /// the keys of the scan, eg: `index.iter_from(&height).keys()`
+    pub fn keys(self) -> impl Iterator<Item = K::Owned> + 'a {
+        self.map(|(k, ..)| k)
+    }
+
+    pub fn values(self) -> impl Iterator<Item = V> + 'a {
+        self.map(|(.., v)| v)
+    }
+}
+
 //#[derive(Debug, Clone)]
 pub struct MapIndexKeys<'a, K> {
     base_iter: BaseIndexIter<'a, K, Zero>,
@@ -39,12 +55,13 @@ pub struct MapIndexValues<'a, V> {
     base_iter: BaseIndexIter<'a, Zero, V>,
 }
 
-impl<K, V> MapIndex<K, V>
+impl<K, V, T> MapIndex<K, V, T>
 where
     K: StorageKey,
     V: StorageValue,
+    T: Fork + ?Sized,
 {
-    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<Database>) -> Self {
+    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<T>) -> Self {
         Self {
             base: BaseIndex::new(index_name, IndexType::Map, view),
             _k: PhantomData,
@@ -62,6 +79,15 @@ where
     //        }
     //    }
 
+    /// the index name, the keys of the index are prefixed with it
+    pub fn name(&self) -> &str {
+        self.base.name()
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.base.index_type()
+    }
+
     pub fn get<Q>(&self, key: &Q) -> Option<V>
     where
         K: Borrow<Q>,
@@ -84,6 +110,42 @@ where
         }
     }
 
+    /// the pairs from the first key at or above `from`
+    pub fn iter_from<Q>(&self, from: &Q) -> MapIndexIter<K, V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        MapIndexIter {
+            base_iter: self.base.iter_from(&(), from),
+        }
+    }
+
+    /// the pairs whose keys start with the prefix, eg: the address of an `AddressHeightKey`
+    pub fn iter_prefix<P: StorageKey>(&self, prefix: &P) -> MapIndexIter<K, V> {
+        MapIndexIter {
+            base_iter: self.base.iter(prefix),
+        }
+    }
+
+    /// the pairs from the last key, the index is buffered, so it suits the small ones
+    pub fn iter_rev(&self) -> MapIndexIter<K, V> {
+        MapIndexIter {
+            base_iter: self.base.iter_rev(&()),
+        }
+    }
+
+    /// the pairs from the last key at or below `from`, the keys below it are buffered
+    pub fn iter_rev_from<Q>(&self, from: &Q) -> MapIndexIter<K, V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        MapIndexIter {
+            base_iter: self.base.iter_rev_from(&(), from),
+        }
+    }
+
     pub fn keys(&self) -> MapIndexKeys<K> {
         MapIndexKeys {
             base_iter: self.base.iter(&()),
@@ -97,10 +159,11 @@ where
     }
 }
 
-impl<K, V> MapIndex<K, V>
+impl<K, V, T> MapIndex<K, V, T>
 where
     K: StorageKey,
     V: StorageValue,
+    T: Fork + ?Sized,
 {
     pub fn put(&mut self, key: &K, value: V) {
         self.base.put(key, value)
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use kvdb::{DBOp, DBTransaction};
+use parking_lot::RwLock;
+
+use super::types::{Fork, Iter, Snapshot};
+
+/// MemoryDB keeps the store in an ordered map, eg: the unit tests, nothing is persisted
+#[derive(Debug, Default)]
+pub struct MemoryDB {
+    map: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryDB {
+    pub fn new() -> Self {
+        MemoryDB::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.read().is_empty()
+    }
+}
+
+impl Snapshot for MemoryDB {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.map.read().get(key).cloned()
+    }
+
+    fn iter<'a>(&'a self, from: &[u8]) -> Iter<'a> {
+        Box::new(MemoryIter { db: self, next: Bound::Included(from.to_vec()) })
+    }
+}
+
+impl Fork for MemoryDB {
+    fn write(&self, tx: DBTransaction) -> Result<(), String> {
+        let mut map = self.map.write();
+        for op in tx.ops {
+            match op {
+                DBOp::Insert { key, value, .. } => {
+                    map.insert(key.to_vec(), value.to_vec());
+                }
+                DBOp::Delete { key, .. } => {
+                    map.remove(&key[..]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// the scan takes the lock per key, it sees the writes made meanwhile
+struct MemoryIter<'a> {
+    db: &'a MemoryDB,
+    next: Bound<Vec<u8>>,
+}
+
+impl<'a> Iterator for MemoryIter<'a> {
+    type Item = (Box<[u8]>, Box<[u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = {
+            let map = self.db.map.read();
+            let (key, value) = map.range((self.next.clone(), Bound::Unbounded)).next()?;
+            (key.clone(), value.clone())
+        };
+        self.next = Bound::Excluded(key.clone());
+        Some((key.into_boxed_slice(), value.into_boxed_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use rand::random;
+
+    use super::*;
+    use super::super::list_index::ListIndex;
+    use super::super::map_index::MapIndex;
+
+    // the indexes behave the same over the memory store
+    #[test]
+    fn t_memory_indexes() {
+        let db = Arc::new(MemoryDB::new());
+        let (mut index, mut after): (MapIndex<u64, u64, MemoryDB>, MapIndex<u64, u64, MemoryDB>) =
+            (MapIndex::new("idx_b", db.clone()), MapIndex::new("idx_c", db.clone()));
+        let mut model = BTreeMap::new();
+        for _ in 0..64 {
+            let (key, value) = (random::<u64>() % 1000, random::<u64>());
+            index.put(&key, value);
+            after.put(&key, 0);
+            model.insert(key, value);
+        }
+        assert_eq!(index.iter().collect::<Vec<_>>(), model.iter().map(|(key, value)| (*key, *value)).collect::<Vec<_>>());
+        let seek = random::<u64>() % 1000;
+        assert_eq!(index.iter_from(&seek).keys().collect::<Vec<_>>(), model.range(seek..).map(|(key, _)| *key).collect::<Vec<_>>());
+        assert_eq!(index.iter_rev_from(&seek).keys().collect::<Vec<_>>(), model.range(..=seek).rev().map(|(key, _)| *key).collect::<Vec<_>>());
+
+        let removed = *model.keys().next().unwrap();
+        index.remove(&removed);
+        assert_eq!(index.get(&removed), None);
+        index.clear();
+        assert_eq!(index.iter().count(), 0);
+        assert_eq!(after.iter().count(), model.len());
+
+        let mut list: ListIndex<u64, MemoryDB> = ListIndex::new("list", db.clone());
+        list.extend(0..10);
+        assert_eq!(list.len(), 10);
+        assert_eq!(list.iter_rev().collect::<Vec<_>>(), (0..10).rev().collect::<Vec<_>>());
+        assert_eq!(list.pop(), Some(9));
+        assert_eq!(ListIndex::<u64, MemoryDB>::new("list", db.clone()).len(), 9);
+    }
+}
@@ -1,7 +1,12 @@
+pub mod backend;
 mod base_index;
+pub mod durability;
 mod entry;
 mod list_index;
 mod map_index;
 mod iter;
+pub mod keys;
+pub mod memory;
 pub mod schema;
-mod types;
\ No newline at end of file
+pub mod staged;
+pub mod types;
\ No newline at end of file
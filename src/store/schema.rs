@@ -3,14 +3,29 @@ use std::sync::Arc;
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::ethkey::Address;
-use kvdb_rocksdb::Database;
 
+use super::backend::Backend;
 use super::entry::Entry;
+use super::keys::{AddressHeightKey, AddressTxKey};
 use super::list_index::ListIndex;
 use super::map_index::MapIndex;
+use super::types::Fork;
 use crate::{
     types::block::{Block, Header},
-    types::{Validator, ValidatorArray, HashesEntry, Bloom, Height, transaction::Transaction},
+    types::{Validator, ValidatorArray, HashesEntry, Bloom, Height, account::Account, transaction::Transaction},
+    types::receipt::Receipts,
+    protocol::GossipMessages,
+    core::safety::SafetyEvidence,
+    core::checkpoint::TrustedCheckpoint,
+    core::state::StateSnapshot,
+    core::staking::{Releases, StakingConfig, ValidatorStake},
+    core::reward::RewardConfig,
+    core::fee::{FeeConfig, FeeIncome},
+    subscriber::cursor::SubscriptionCursor,
+    consensus::types::CommitCertificate,
+    consensus::dpos::delegates::Vote,
+    consensus::dpos::engine::MissedSlots,
+    consensus::epoch::Epoch,
 };
 
 macro_rules! define_name {
@@ -20,53 +35,91 @@ macro_rules! define_name {
         )+
     ) => (
         $(const $name: &str = concat!("core.", $value);)*
+
+        /// the key prefixes of all indexes, eg: the disk usage is accounted by them
+        pub const INDEX_NAMES: &[&str] = &[$($name),*];
     );
 }
 
 define_name!(
     TRANSACTIONS => "transaction_hash";
     TRANSACTIONS_HASH => "transaction_block";
-    BLOCKS => "blocks";
     HEADERS => "headers";
     BLOCK_HASHES_BY_HEIGHT => "block_hashes_by_height";
     BLOCK_TRANSACTIONS => "block_transactions";
     PRECOMMITS => "precommits";
     CONFIGS => "configs";
     CONSENSUS_MESSAGE_CACHE => "consensus_message_cache";
+    CONSENSUS_MESSAGES => "consensus_messages";
     VALIDATORS => "validators";
+    ACCOUNTS => "accounts";
+    GENESIS_ALLOC => "genesis_alloc";
+    ADDRESS_TXS => "address_tx_index";
+    ADDRESS_TX_COUNTS => "address_tx_counts";
+    SAFETY_EVIDENCE => "safety_evidence";
+    CHECKPOINTS => "checkpoints";
+    LAST_CHECKPOINT => "last_checkpoint";
+    SUBSCRIPTIONS => "subscriptions";
+    COMMIT_CERTIFICATES => "commit_certificates";
+    TX_LOCATIONS => "transaction_locations";
+    DPOS_VOTES => "dpos_votes";
+    DPOS_MISSED_SLOTS => "dpos_missed_slots";
+    EPOCHS => "epochs";
+    VALIDATOR_CHANGES => "validator_changes";
+    RECEIPTS => "receipts";
+    STATE_ROOTS => "state_roots";
+    STATE_SNAPSHOTS => "state_snapshots";
+    STAKES => "stakes";
+    STAKE_RELEASES => "stake_releases";
+    STAKING_CONFIG => "staking_config";
+    REWARDS => "rewards";
+    REWARD_CONFIG => "reward_config";
+    FEE_INCOME => "fee_income";
+    FEE_CONFIG => "fee_config";
+    PRUNED_BYTES => "pruned_bytes";
 );
 
-struct TxLocation {
-    block_height: Height,
-    position_in_block: u64,
+/// where a committed transaction is, tx hash => location
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TxLocation {
+    pub block_height: Height,
+    pub block_hash: Hash,
+    // the index in the block body, the coinbase transaction is 0
+    pub position: u64,
 }
 
-pub struct Schema {
-    db: Arc<Database>,
+implement_storagevalue_traits! {TxLocation}
+implement_cryptohash_traits! {TxLocation}
+
+/// Schema opens the indexes of the chain over the store `T`, the store of the config by default
+pub struct Schema<T: ?Sized = Backend> {
+    db: Arc<T>,
 }
 
-impl Schema {
-    pub fn new(db: Arc<Database>) -> Self {
+impl<T: Fork + ?Sized> Schema<T> {
+    pub fn new(db: Arc<T>) -> Self {
         Schema { db }
     }
 
-    pub fn transaction(&self) -> MapIndex<Hash, Transaction> {
-        MapIndex::new(TRANSACTIONS, self.db.clone())
+    pub fn database(&self) -> &Arc<T> {
+        &self.db
     }
 
-    pub fn transaction_hashes(&self) -> MapIndex<Hash, HashesEntry> {
-        MapIndex::new(TRANSACTIONS_HASH, self.db.clone())
+    pub fn transaction(&self) -> MapIndex<Hash, Transaction, T> {
+        MapIndex::new(TRANSACTIONS, self.db.clone())
     }
 
-    pub fn blocks(&self) -> MapIndex<Hash, Block> {
-        MapIndex::new(BLOCKS, self.db.clone())
+    /// block body index, block hash => the transaction hashes of the block
+    pub fn block_bodies(&self) -> MapIndex<Hash, HashesEntry, T> {
+        MapIndex::new(TRANSACTIONS_HASH, self.db.clone())
     }
 
-    pub fn headers(&self) -> MapIndex<Hash, Header> {
+    /// block header index, the header only queries never load the bodies
+    pub fn headers(&self) -> MapIndex<Hash, Header, T> {
         MapIndex::new(HEADERS, self.db.clone())
     }
 
-    pub fn block_hashes_by_height(&self) -> ListIndex<Hash> {
+    pub fn block_hashes_by_height(&self) -> ListIndex<Hash, T> {
         ListIndex::new(BLOCK_HASHES_BY_HEIGHT, self.db.clone())
     }
 
@@ -82,7 +135,139 @@ impl Schema {
         Block::new2(header, vec![])
     }
 
-    pub fn validators(&self) -> Entry<ValidatorArray> {
+    /// the prepare and commit messages which committed the block at height
+    pub fn consensus_messages(&self) -> MapIndex<Height, GossipMessages, T> {
+        MapIndex::new(CONSENSUS_MESSAGES, self.db.clone())
+    }
+
+    /// the commit certificate of the block at height, height => certificate
+    pub fn commit_certificates(&self) -> MapIndex<Height, CommitCertificate, T> {
+        MapIndex::new(COMMIT_CERTIFICATES, self.db.clone())
+    }
+
+    /// transaction location index, tx hash => the block and the position of the transaction
+    pub fn tx_locations(&self) -> MapIndex<Hash, TxLocation, T> {
+        MapIndex::new(TX_LOCATIONS, self.db.clone())
+    }
+
+    /// dpos vote index, (voter, height) => the vote of the voter at the height
+    pub fn dpos_votes(&self) -> MapIndex<AddressHeightKey, Vote, T> {
+        MapIndex::new(DPOS_VOTES, self.db.clone())
+    }
+
+    /// the slots missed by the delegates before a dpos block, height => the missed slots
+    pub fn dpos_missed_slots(&self) -> MapIndex<Height, MissedSlots, T> {
+        MapIndex::new(DPOS_MISSED_SLOTS, self.db.clone())
+    }
+
+    /// the decided epochs, epoch number => the epoch with its validators
+    pub fn epochs(&self) -> MapIndex<u64, Epoch, T> {
+        MapIndex::new(EPOCHS, self.db.clone())
+    }
+
+    /// the validator sets of the config, the first height of a set => the set, the pbft epochs
+    /// which start at or after the height are kept by it
+    pub fn validator_changes(&self) -> MapIndex<Height, ValidatorArray, T> {
+        MapIndex::new(VALIDATOR_CHANGES, self.db.clone())
+    }
+
+    /// the receipts with the logs of the transactions, height => receipts
+    pub fn receipts(&self) -> MapIndex<Height, Receipts, T> {
+        MapIndex::new(RECEIPTS, self.db.clone())
+    }
+
+    /// the state roots of the retained snapshots, height => root
+    pub fn state_roots(&self) -> MapIndex<Height, Hash, T> {
+        MapIndex::new(STATE_ROOTS, self.db.clone())
+    }
+
+    /// the periodic account state snapshots, height => all account states after the block
+    pub fn state_snapshots(&self) -> MapIndex<Height, StateSnapshot, T> {
+        MapIndex::new(STATE_SNAPSHOTS, self.db.clone())
+    }
+
+    /// the stake bonded to the validators, validator => stake
+    pub fn stakes(&self) -> MapIndex<Address, ValidatorStake, T> {
+        MapIndex::new(STAKES, self.db.clone())
+    }
+
+    /// the unbonded stakes released at a height, height => (delegator, amount)
+    pub fn stake_releases(&self) -> MapIndex<Height, Releases, T> {
+        MapIndex::new(STAKE_RELEASES, self.db.clone())
+    }
+
+    /// the staking rules, they're set by the genesis config
+    pub fn staking_config(&self) -> Entry<StakingConfig, T> {
+        Entry::new(STAKING_CONFIG, self.db.clone())
+    }
+
+    /// the block rewards paid to the proposers, (address, height) => amount
+    pub fn rewards(&self) -> MapIndex<AddressHeightKey, u64, T> {
+        MapIndex::new(REWARDS, self.db.clone())
+    }
+
+    /// the reward schedule, it's set by the genesis config
+    pub fn reward_config(&self) -> Entry<RewardConfig, T> {
+        Entry::new(REWARD_CONFIG, self.db.clone())
+    }
+
+    /// the unburned fees paid to the proposers, address => (height, amount)
+    pub fn fee_income(&self) -> MapIndex<Address, FeeIncome, T> {
+        MapIndex::new(FEE_INCOME, self.db.clone())
+    }
+
+    /// the transaction fee rules, they're set by the genesis config
+    pub fn fee_config(&self) -> Entry<FeeConfig, T> {
+        Entry::new(FEE_CONFIG, self.db.clone())
+    }
+
+    /// account state index, address => the confirmed balance and nonce
+    pub fn accounts(&self) -> MapIndex<Address, Account, T> {
+        MapIndex::new(ACCOUNTS, self.db.clone())
+    }
+
+    /// the balances allocated by the genesis, they are the base of the historical account states
+    pub fn genesis_alloc(&self) -> MapIndex<Address, Account, T> {
+        MapIndex::new(GENESIS_ALLOC, self.db.clone())
+    }
+
+    /// address transaction index, (address, height, position) => the hash of a transaction which
+    /// sends to or from the address
+    pub fn address_txs(&self) -> MapIndex<AddressTxKey, Hash, T> {
+        MapIndex::new(ADDRESS_TXS, self.db.clone())
+    }
+
+    /// the number of the transactions in the address transaction index, address => count
+    pub fn address_tx_counts(&self) -> MapIndex<Address, u64, T> {
+        MapIndex::new(ADDRESS_TX_COUNTS, self.db.clone())
+    }
+
+    /// the evidence of conflicting finalized blocks, the node refuses to start while it's present
+    pub fn safety_evidence(&self) -> Entry<SafetyEvidence, T> {
+        Entry::new(SAFETY_EVIDENCE, self.db.clone())
+    }
+
+    /// the checkpoint records, height => checkpoint
+    pub fn checkpoints(&self) -> MapIndex<Height, TrustedCheckpoint, T> {
+        MapIndex::new(CHECKPOINTS, self.db.clone())
+    }
+
+    /// the highest checkpoint record, the blocks below it are finalized
+    pub fn last_checkpoint(&self) -> Entry<TrustedCheckpoint, T> {
+        Entry::new(LAST_CHECKPOINT, self.db.clone())
+    }
+
+    /// the bytes dropped by the prunes and the truncates since the last compaction
+    pub fn pruned_bytes(&self) -> Entry<u64, T> {
+        Entry::new(PRUNED_BYTES, self.db.clone())
+    }
+
+    /// the durable webhook cursors, digest(name) => cursor
+    pub fn subscriptions(&self) -> MapIndex<Hash, SubscriptionCursor, T> {
+        MapIndex::new(SUBSCRIPTIONS, self.db.clone())
+    }
+
+    pub fn validators(&self) -> Entry<ValidatorArray, T> {
         Entry::new(VALIDATORS, self.db.clone())
     }
 
@@ -105,6 +290,8 @@ impl Schema {
 
 #[cfg(test)]
 mod tests {
+    use kvdb_rocksdb::Database;
+
     use super::*;
     use crate::common::random_dir;
     use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random, Secret};
@@ -162,4 +349,32 @@ mod tests {
             writeln!(io::stdout(), "{:#?}", zero_tx1.unwrap()).unwrap();
         }
     }
+    // every accessor opens a declared index with the type of its data
+    #[test]
+    fn t_index_types() {
+        use super::super::base_index::IndexType;
+
+        let schema = Schema::new(Arc::new(Database::open_default(&random_dir()).unwrap()));
+        let indexes = vec![
+            (schema.transaction().name().to_string(), schema.transaction().index_type()),
+            (schema.headers().name().to_string(), schema.headers().index_type()),
+            (schema.block_hashes_by_height().name().to_string(), schema.block_hashes_by_height().index_type()),
+            (schema.accounts().name().to_string(), schema.accounts().index_type()),
+            (schema.receipts().name().to_string(), schema.receipts().index_type()),
+            (schema.staking_config().name().to_string(), schema.staking_config().index_type()),
+            (schema.validators().name().to_string(), schema.validators().index_type()),
+        ];
+        for (name, _) in indexes.iter() {
+            assert!(INDEX_NAMES.contains(&name.as_str()), "{} is not declared", name);
+        }
+        let types: Vec<IndexType> = indexes.into_iter().map(|(_, index_type)| index_type).collect();
+        assert_eq!(types, vec![IndexType::Map, IndexType::Map, IndexType::List, IndexType::Map, IndexType::Map, IndexType::Entry, IndexType::Entry]);
+
+        // the length of a list is tracked under the index name, the items are typed
+        let mut hashes = schema.block_hashes_by_height();
+        hashes.push(1_u64.hash());
+        hashes.push(2_u64.hash());
+        assert_eq!(schema.block_hashes_by_height().len(), 2);
+        assert_eq!(schema.block_hash_by_height(1), Some(2_u64.hash()));
+    }
 }
@@ -0,0 +1,165 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::sync::Arc;
+use std::vec;
+
+use kvdb::{DBOp, DBTransaction};
+use parking_lot::RwLock;
+
+use super::types::{Fork, Iter, Snapshot};
+
+const COL: Option<u32> = None;
+
+// the staged value of a key, none is a deletion
+type Change = (Vec<u8>, Option<Vec<u8>>);
+
+/// StagedFork keeps the writes over a store in memory until `merge` writes them at once, the
+/// reads see the staged writes over the store, eg: the writes of a block never reach the store
+/// in part
+pub struct StagedFork<T: ?Sized> {
+    base: Arc<T>,
+    changes: RwLock<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl<T: Fork + ?Sized> StagedFork<T> {
+    pub fn new(base: Arc<T>) -> Self {
+        StagedFork { base, changes: RwLock::new(BTreeMap::new()) }
+    }
+
+    /// the staged keys, the deletions are counted too
+    pub fn len(&self) -> usize {
+        self.changes.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.read().is_empty()
+    }
+
+    /// write the staged changes into the store in one transaction, the fork is empty afterwards
+    pub fn merge(&self) -> Result<(), String> {
+        let changes = ::std::mem::replace(&mut *self.changes.write(), BTreeMap::new());
+        let mut tx = self.base.transaction();
+        for (key, value) in changes {
+            match value {
+                Some(value) => tx.put_vec(COL, &key, value),
+                None => tx.delete(COL, &key),
+            }
+        }
+        self.base.write(tx)
+    }
+}
+
+impl<T: Fork + ?Sized> Snapshot for StagedFork<T> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.changes.read().get(key) {
+            Some(change) => change.clone(),
+            None => self.base.get(key),
+        }
+    }
+
+    // the staged changes of the range are copied, the scan doesn't hold the lock
+    fn iter<'a>(&'a self, from: &[u8]) -> Iter<'a> {
+        let changes: Vec<Change> = self.changes.read().range(from.to_vec()..)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Box::new(StagedIter { base: self.base.iter(from).peekable(), changes: changes.into_iter().peekable() })
+    }
+}
+
+impl<T: Fork + ?Sized> Fork for StagedFork<T> {
+    fn write(&self, tx: DBTransaction) -> Result<(), String> {
+        let mut changes = self.changes.write();
+        for op in tx.ops {
+            match op {
+                DBOp::Insert { key, value, .. } => {
+                    changes.insert(key.to_vec(), Some(value.to_vec()));
+                }
+                DBOp::Delete { key, .. } => {
+                    changes.insert(key.to_vec(), None);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // nothing reaches the store before the merge
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// the pairs of the store and the staged changes in the key order, a staged key shadows the key
+// of the store and a staged deletion hides it
+struct StagedIter<'a> {
+    base: Peekable<Iter<'a>>,
+    changes: Peekable<vec::IntoIter<Change>>,
+}
+
+impl<'a> Iterator for StagedIter<'a> {
+    type Item = (Box<[u8]>, Box<[u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.base.peek(), self.changes.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((base_key, _)), Some((key, _))) => base_key[..].cmp(&key[..]),
+            };
+            match order {
+                Ordering::Less => return self.base.next(),
+                Ordering::Equal => {
+                    self.base.next();
+                }
+                Ordering::Greater => {}
+            }
+            if let Some((key, Some(value))) = self.changes.next() {
+                return Some((key.into_boxed_slice(), value.into_boxed_slice()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::map_index::MapIndex;
+    use super::super::memory::MemoryDB;
+
+    #[test]
+    fn t_staged_fork() {
+        let db = Arc::new(MemoryDB::new());
+        let mut index: MapIndex<u64, u64, MemoryDB> = MapIndex::new("staged", db.clone());
+        for key in 0..5 {
+            index.put(&key, key);
+        }
+        let staged = Arc::new(StagedFork::new(db.clone()));
+        let mut staged_index: MapIndex<u64, u64, StagedFork<MemoryDB>> = MapIndex::new("staged", staged.clone());
+        staged_index.put(&1, 10);
+        staged_index.remove(&2);
+        staged_index.put(&7, 7);
+        assert_eq!(staged.len(), 3);
+
+        // the fork reads its writes over the store, the store doesn't see them before the merge
+        assert_eq!(staged_index.get(&1), Some(10));
+        assert_eq!(staged_index.get(&2), None);
+        assert_eq!(staged_index.iter().collect::<Vec<_>>(), vec![(0, 0), (1, 10), (3, 3), (4, 4), (7, 7)]);
+        assert_eq!(staged_index.iter_from(&2).keys().collect::<Vec<_>>(), vec![3, 4, 7]);
+        assert_eq!(index.get(&1), Some(1));
+        assert_eq!(index.iter().count(), 5);
+
+        staged.merge().unwrap();
+        assert!(staged.is_empty());
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![(0, 0), (1, 10), (3, 3), (4, 4), (7, 7)]);
+        assert_eq!(staged_index.iter().count(), 5);
+        // the dropped fork writes nothing
+        {
+            let dropped = Arc::new(StagedFork::new(db.clone()));
+            let mut dropped_index: MapIndex<u64, u64, StagedFork<MemoryDB>> = MapIndex::new("staged", dropped);
+            dropped_index.clear();
+            assert_eq!(dropped_index.iter().count(), 0);
+        }
+        assert_eq!(index.iter().count(), 5);
+    }
+}
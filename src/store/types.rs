@@ -1,27 +1,56 @@
 use kvdb::DBTransaction;
-use kvdb_rocksdb::DatabaseIterator;
+use kvdb_rocksdb::Database;
 
-pub type Iter<'a> = DatabaseIterator<'a>;
+/// the pairs of a scan in the key order
+pub type Iter<'a> = Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
 
+const COL: Option<u32> = None;
 
-pub trait Snapshot: 'static {
-    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>>;
+/// Snapshot reads the keys of a store, the indexes prefix the keys with their name
+pub trait Snapshot: Send + Sync + 'static {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
 
-    fn contains(&self, name: &str, key: &[u8]) -> bool {
-        self.get(name, key).is_some()
+    fn contains(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
     }
 
-    fn iter<'a> (&'a self, name: &str, from: &[u8]) -> Iter<'a>;
+    /// the pairs from the first key at or above `from` to the end of the store, the caller stops
+    /// the scan at the end of its prefix, see `store::iter`
+    fn iter<'a>(&'a self, from: &[u8]) -> Iter<'a>;
 }
 
-pub struct Fork {
-    snapshot: Box<dyn Snapshot>,
-    transaction: DBTransaction,
+/// Fork writes the changes of a transaction at once, the writes are visible to the reads at once.
+/// The stores write through, `staged::StagedFork` keeps the writes until they're merged
+pub trait Fork: Snapshot {
+    fn transaction(&self) -> DBTransaction {
+        DBTransaction::new()
+    }
+
+    fn write(&self, tx: DBTransaction) -> Result<(), String>;
+
+    /// persist the written changes, see `store::durability`
+    fn flush(&self) -> Result<(), String>;
+}
+
+impl Snapshot for Database {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        Database::get(self, COL, key).unwrap().map(|value| value.to_vec())
+    }
+
+    fn iter<'a>(&'a self, from: &[u8]) -> Iter<'a> {
+        match self.iter_from_prefix(COL, from) {
+            Some(iter) => Box::new(iter),
+            None => Box::new(::std::iter::empty()),
+        }
+    }
 }
 
-//
-//impl Snapshot for Fork {
-//    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
-//
-//    }
-//}
\ No newline at end of file
+impl Fork for Database {
+    fn write(&self, tx: DBTransaction) -> Result<(), String> {
+        Database::write(self, tx).map_err(|err| err.to_string())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        Database::flush(self).map_err(|err| err.to_string())
+    }
+}
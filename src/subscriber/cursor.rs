@@ -0,0 +1,472 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::storage::values::StorageValue;
+use parking_lot::Mutex;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    common::hash_algo::digest,
+    common::http_post_json,
+    core::chain::Chain,
+    metrics,
+    subscriber::events::{committed_event, COMMITTED_EVENT},
+    types::block::Block,
+    types::Height,
+    util::blocking::BlockingPool,
+};
+
+// the blocks posted in one tick, the cursor is persisted after every acknowledged block
+pub const CURSOR_BATCH_SIZE: u64 = 50;
+pub const CURSOR_INTERVAL: Duration = Duration::from_millis(500);
+// the ticks skipped after a failed delivery grow up to it
+const MAX_BACKOFF_TICKS: u64 = 60;
+// the webhooks which are posted at once, a slow receiver only holds its own thread
+const DELIVERY_THREADS: usize = 4;
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// the events of the cursors, they're named like the events of the webhooks and the websockets
+pub const CURSOR_EVENTS: &[&str] = &[COMMITTED_EVENT];
+
+/// the events which a subscription receives, all of them if it's empty, eg: `["committed"]`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionFilter {
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl SubscriptionFilter {
+    pub fn accepts(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|accepted| accepted == event)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.events.iter().find(|event| !CURSOR_EVENTS.contains(&event.as_str())) {
+            Some(event) => Err(format!("unknown event {}, expect one of {:?}", event, CURSOR_EVENTS)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// SubscriptionCursor is a named subscription with the next height to deliver, it's persisted in
+/// the store, so the delivery resumes from it after the node or the receiver restarts. The blocks
+/// are posted to the webhook of the url, a cursor without it is read by a websocket connection
+/// which acknowledges the blocks, eg: `{"method": "subscribe", "params": ["cursor", {"name": "indexer"}]}`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SubscriptionCursor {
+    pub name: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub filter: SubscriptionFilter,
+    // the next block height to deliver, the blocks below it are acknowledged
+    pub position: Height,
+    #[serde(default)]
+    pub failures: u64,
+}
+
+implement_storagevalue_traits! {SubscriptionCursor}
+implement_cryptohash_traits! {SubscriptionCursor}
+
+/// the subscriptions are keyed by the digest of the name
+pub fn cursor_key(name: &str) -> Hash {
+    digest(name.as_bytes().to_vec())
+}
+
+pub fn load_cursors(chain: &Chain) -> Vec<SubscriptionCursor> {
+    let mut cursors: Vec<SubscriptionCursor> = chain.get_ledger().read().get_schema().subscriptions().values().collect();
+    cursors.sort_by(|a, b| a.name.cmp(&b.name));
+    cursors
+}
+
+pub fn load_cursor(chain: &Chain, name: &str) -> Option<SubscriptionCursor> {
+    chain.get_ledger().read().get_schema().subscriptions().get(&cursor_key(name))
+}
+
+fn store_cursor(chain: &Chain, cursor: &SubscriptionCursor) {
+    chain.get_ledger().read().get_schema().subscriptions().put(&cursor_key(&cursor.name), cursor.clone());
+}
+
+// the bodies of a block, the events dropped by the filter are absent
+fn event_bodies(block: &Block, filter: &SubscriptionFilter) -> Vec<::serde_json::Value> {
+    let mut bodies = vec![];
+    if filter.accepts(COMMITTED_EVENT) {
+        bodies.push(committed_event(block));
+    }
+    bodies
+}
+
+// post the bodies of the block, the receiver acknowledges them with a 2xx status
+fn post(url: &str, filter: &SubscriptionFilter, block: &Block) -> Result<(), String> {
+    for body in event_bodies(block, filter) {
+        let status = http_post_json(url, &body.to_string(), WEBHOOK_TIMEOUT)?;
+        if status < 200 || status >= 300 {
+            return Err(format!("unacknowledged status {}", status));
+        }
+    }
+    Ok(())
+}
+
+// the websocket connection which reads a cursor, the blocks from `next` are unsent
+struct Socket {
+    connection: usize,
+    subscription: u64,
+    sender: Sender<String>,
+    next: Height,
+}
+
+// the delivery of a cursor, a new generation replaces it when the cursor is put again, so a post
+// in flight never persists its position over the new cursor
+struct Delivery {
+    cursor: SubscriptionCursor,
+    generation: u64,
+    backoff: u64,
+    skipped: u64,
+    // a webhook post is in flight on the pool
+    posting: bool,
+    socket: Option<Socket>,
+}
+
+struct Deliveries {
+    chain: Arc<Chain>,
+    deliveries: Mutex<HashMap<String, Delivery>>,
+    generations: AtomicU64,
+    pool: BlockingPool,
+}
+
+impl Deliveries {
+    fn insert(&self, deliveries: &mut HashMap<String, Delivery>, cursor: SubscriptionCursor, socket: Option<Socket>) {
+        let generation = self.generations.fetch_add(1, Ordering::SeqCst);
+        let delivery = Delivery { cursor, generation, backoff: 0, skipped: 0, posting: false, socket };
+        deliveries.insert(delivery.cursor.name.clone(), delivery);
+    }
+
+    // update the delivery of the generation and persist its cursor, the replaced or removed
+    // deliveries are left alone
+    fn update<F: FnOnce(&mut Delivery)>(&self, name: &str, generation: u64, update: F) {
+        let mut deliveries = self.deliveries.lock();
+        if let Some(delivery) = deliveries.get_mut(name).filter(|delivery| delivery.generation == generation) {
+            update(delivery);
+            store_cursor(&self.chain, &delivery.cursor);
+        }
+    }
+}
+
+// a tick of the deliveries, the webhooks are posted on the pool and the blocks of the websockets
+// are queued without blocking
+fn deliver(inner: &Arc<Deliveries>) {
+    let last_height = inner.chain.get_last_height();
+    let mut deliveries = inner.deliveries.lock();
+    for delivery in deliveries.values_mut() {
+        match delivery.cursor.url.clone() {
+            Some(url) => post_batch(inner, delivery, url, last_height),
+            None => push_batch(&inner.chain, delivery, last_height),
+        }
+    }
+}
+
+fn post_batch(inner: &Arc<Deliveries>, delivery: &mut Delivery, url: String, last_height: Height) {
+    if delivery.posting || delivery.cursor.position > last_height {
+        return;
+    }
+    if delivery.skipped < delivery.backoff {
+        delivery.skipped += 1;
+        return;
+    }
+    delivery.skipped = 0;
+    delivery.posting = true;
+    let (deliveries, name, generation) = (inner.clone(), delivery.cursor.name.clone(), delivery.generation);
+    let (filter, mut position) = (delivery.cursor.filter.clone(), delivery.cursor.position);
+    let end_height = min(last_height, position + CURSOR_BATCH_SIZE - 1);
+    inner.pool.run(move || {
+        while position <= end_height {
+            let result = deliveries.chain.get_block_by_height(position)
+                .ok_or(format!("the block {} is missing", position))
+                .and_then(|block| post(&url, &filter, &block));
+            if let Err(err) = result {
+                metrics::inc_counter("subscription_delivery_failure_total");
+                warn!("Failed to deliver the subscription `{}`, height: {}, err: {}", name, position, err);
+                deliveries.update(&name, generation, |delivery| {
+                    delivery.cursor.failures += 1;
+                    delivery.backoff = min(MAX_BACKOFF_TICKS, max(1, delivery.backoff * 2));
+                    delivery.posting = false;
+                });
+                return;
+            }
+            // the position is persisted after every acknowledged block
+            position += 1;
+            deliveries.update(&name, generation, |delivery| delivery.cursor.position = position);
+        }
+        deliveries.update(&name, generation, |delivery| {
+            delivery.backoff = 0;
+            delivery.posting = false;
+        });
+    });
+}
+
+// queue the blocks of the unacknowledged window, a full queue is retried on the next tick and a
+// closed one detaches the connection
+fn push_batch(chain: &Chain, delivery: &mut Delivery, last_height: Height) {
+    let end_height = min(last_height, delivery.cursor.position + CURSOR_BATCH_SIZE - 1);
+    let mut closed = false;
+    if let Some(socket) = delivery.socket.as_mut() {
+        'blocks: while socket.next <= end_height {
+            let block = match chain.get_block_by_height(socket.next) {
+                Some(block) => block,
+                None => break,
+            };
+            for body in event_bodies(&block, &delivery.cursor.filter) {
+                let message = json!({"jsonrpc": "2.0", "method": "subscription", "params": {"subscription": socket.subscription, "result": body}});
+                match socket.sender.try_send(message.to_string()) {
+                    Ok(()) => metrics::inc_counter("api_ws_notifications_total"),
+                    Err(ref err) if err.is_closed() => {
+                        closed = true;
+                        break 'blocks;
+                    }
+                    Err(_) => break 'blocks,
+                }
+            }
+            socket.next += 1;
+        }
+    }
+    if closed {
+        delivery.socket = None;
+    }
+}
+
+/// SubscriptionManager persists the cursors and delivers them, one thread ticks all deliveries,
+/// the webhooks are posted on a small pool and the websocket connections are sent the blocks of
+/// their cursors. It never blocks its callers, so the async apis can put the cursors
+pub struct SubscriptionManager {
+    inner: Arc<Deliveries>,
+}
+
+impl SubscriptionManager {
+    pub fn new(chain: Arc<Chain>) -> Self {
+        let inner = Arc::new(Deliveries {
+            chain,
+            deliveries: Mutex::new(HashMap::new()),
+            generations: AtomicU64::new(0),
+            pool: BlockingPool::new(DELIVERY_THREADS),
+        });
+        // the ticker exits with the manager
+        let ticker: Weak<Deliveries> = Arc::downgrade(&inner);
+        thread::Builder::new().name("subscriptions".to_string()).spawn(move || loop {
+            thread::sleep(CURSOR_INTERVAL);
+            match ticker.upgrade() {
+                Some(inner) => deliver(&inner),
+                None => break,
+            }
+        }).expect("Failed to spawn the subscription thread");
+        SubscriptionManager { inner }
+    }
+
+    /// start the deliveries of the persisted cursors
+    pub fn resume(&self) {
+        let mut deliveries = self.inner.deliveries.lock();
+        for cursor in load_cursors(&self.inner.chain) {
+            info!("Subscription `{}` resumed, url: {:?}, position: {}", cursor.name, cursor.url, cursor.position);
+            self.inner.insert(&mut deliveries, cursor, None);
+        }
+    }
+
+    /// create or replace the named subscription, the delivery restarts from the new position and
+    /// the attached websocket connection is kept
+    pub fn put(&self, cursor: SubscriptionCursor) {
+        let mut deliveries = self.inner.deliveries.lock();
+        let socket = deliveries.remove(&cursor.name)
+            .and_then(|delivery| delivery.socket)
+            .filter(|_| cursor.url.is_none())
+            .map(|socket| Socket { next: cursor.position, ..socket });
+        store_cursor(&self.inner.chain, &cursor);
+        self.inner.insert(&mut deliveries, cursor, socket);
+    }
+
+    pub fn remove(&self, name: &str) -> Option<SubscriptionCursor> {
+        let mut deliveries = self.inner.deliveries.lock();
+        deliveries.remove(name);
+        let cursor = load_cursor(&self.inner.chain, name)?;
+        self.inner.chain.get_ledger().read().get_schema().subscriptions().remove(&cursor_key(name));
+        Some(cursor)
+    }
+
+    /// the websocket connection reads the cursor from its position, a cursor is read by one
+    /// connection at a time
+    pub fn attach(&self, name: &str, connection: usize, subscription: u64, sender: Sender<String>) -> Result<(), String> {
+        let mut deliveries = self.inner.deliveries.lock();
+        let delivery = deliveries.get_mut(name).ok_or(format!("no subscription `{}`", name))?;
+        if delivery.cursor.url.is_some() {
+            return Err(format!("the subscription `{}` is delivered to a webhook", name));
+        }
+        if delivery.socket.is_some() {
+            return Err(format!("the subscription `{}` is read by another connection", name));
+        }
+        delivery.socket = Some(Socket { connection, subscription, sender, next: delivery.cursor.position });
+        Ok(())
+    }
+
+    pub fn detach(&self, name: &str, connection: usize) {
+        if let Some(delivery) = self.inner.deliveries.lock().get_mut(name) {
+            if delivery.socket.as_ref().map_or(false, |socket| socket.connection == connection) {
+                delivery.socket = None;
+            }
+        }
+    }
+
+    /// the connection acknowledges the sent blocks up to the height, the position of the cursor
+    /// moves past it and is persisted
+    pub fn ack(&self, name: &str, connection: usize, height: Height) -> Result<(), String> {
+        let mut deliveries = self.inner.deliveries.lock();
+        let delivery = deliveries.get_mut(name)
+            .filter(|delivery| delivery.socket.as_ref().map_or(false, |socket| socket.connection == connection))
+            .ok_or(format!("the subscription `{}` isn't read by the connection", name))?;
+        let next = delivery.socket.as_ref().map_or(0, |socket| socket.next);
+        if height >= next {
+            return Err(format!("the block {} isn't sent yet", height));
+        }
+        if height + 1 > delivery.cursor.position {
+            delivery.cursor.position = height + 1;
+            store_cursor(&self.inner.chain, &delivery.cursor);
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn deliver(&self) {
+        deliver(&self.inner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use ::actix::prelude::*;
+    use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+    use tokio::prelude::Stream;
+    use tokio::sync::mpsc::channel;
+
+    use crate::core::test_util::{memory_chain, sealed_block};
+
+    #[test]
+    fn t_subscription_cursor() {
+        let filter = SubscriptionFilter { events: vec![COMMITTED_EVENT.to_owned()] };
+        assert!(filter.accepts(COMMITTED_EVENT));
+        assert!(!filter.accepts("reverted"));
+        assert!(SubscriptionFilter::default().accepts(COMMITTED_EVENT));
+        assert!(filter.validate().is_ok());
+        assert!(SubscriptionFilter { events: vec!["new_block".to_owned()] }.validate().is_err());
+
+        let cursor = SubscriptionCursor { name: "indexer".to_owned(), url: Some("http://127.0.0.1:9000".to_owned()), filter, position: 10, failures: 0 };
+        let decoded = SubscriptionCursor::from_bytes(::std::borrow::Cow::from(cursor.clone().into_bytes()));
+        assert_eq!(decoded, cursor);
+        assert_eq!(cursor_key("indexer"), cursor_key("indexer"));
+        assert_ne!(cursor_key("indexer"), cursor_key("archive"));
+    }
+
+    fn chain_of(key_pair: &KeyPair, blocks: Height) -> Arc<Chain> {
+        let chain = memory_chain(key_pair);
+        let mut parent = chain.get_last_hash();
+        for height in 1..=blocks {
+            let block = sealed_block(parent, height, key_pair);
+            parent = block.hash();
+            chain.insert_block(&block).unwrap();
+        }
+        chain
+    }
+
+    fn cursor(name: &str, url: Option<String>, position: Height) -> SubscriptionCursor {
+        SubscriptionCursor { name: name.to_owned(), url, filter: SubscriptionFilter::default(), position, failures: 0 }
+    }
+
+    // the websocket reads the blocks of its window and moves the cursor by the acks
+    #[test]
+    fn t_websocket_cursor() {
+        let _system = System::new("t_websocket_cursor");
+        let key_pair = Random.generate().unwrap();
+        let chain = chain_of(&key_pair, CURSOR_BATCH_SIZE + 10);
+        let manager = SubscriptionManager::new(chain.clone());
+        manager.put(cursor("hook", Some("http://127.0.0.1:1".to_owned()), 0));
+        manager.put(cursor("indexer", None, 5));
+        let (sender, receiver) = channel(CURSOR_BATCH_SIZE as usize * 2);
+        assert!(manager.attach("hook", 1, 1, sender.clone()).is_err());
+        assert!(manager.attach("missing", 1, 1, sender.clone()).is_err());
+        manager.attach("indexer", 1, 7, sender.clone()).unwrap();
+        assert!(manager.attach("indexer", 2, 1, sender.clone()).is_err());
+        assert!(manager.ack("indexer", 1, 5).is_err());
+
+        // the unacknowledged blocks stop at the window
+        manager.deliver();
+        manager.deliver();
+        let mut messages = receiver.wait();
+        let heights: Vec<u64> = (0..CURSOR_BATCH_SIZE).map(|_| {
+            let message: serde_json::Value = serde_json::from_str(&messages.next().unwrap().unwrap()).unwrap();
+            assert_eq!(message["params"]["subscription"], json!(7));
+            assert_eq!(message["params"]["result"]["event"], json!(COMMITTED_EVENT));
+            message["params"]["result"]["height"].as_u64().unwrap()
+        }).collect();
+        assert_eq!(heights, (5..5 + CURSOR_BATCH_SIZE).collect::<Vec<_>>());
+
+        assert!(manager.ack("indexer", 2, 5).is_err());
+        assert!(manager.ack("indexer", 1, 5 + CURSOR_BATCH_SIZE).is_err());
+        manager.ack("indexer", 1, 9).unwrap();
+        assert_eq!(load_cursor(&chain, "indexer").unwrap().position, 10);
+        manager.deliver();
+        let message: serde_json::Value = serde_json::from_str(&messages.next().unwrap().unwrap()).unwrap();
+        assert_eq!(message["params"]["result"]["height"], json!(5 + CURSOR_BATCH_SIZE));
+
+        // a new connection resumes from the acknowledged position
+        manager.detach("indexer", 1);
+        let (sender, receiver) = channel(CURSOR_BATCH_SIZE as usize * 2);
+        manager.attach("indexer", 2, 1, sender).unwrap();
+        manager.deliver();
+        let message: serde_json::Value = serde_json::from_str(&receiver.wait().next().unwrap().unwrap()).unwrap();
+        assert_eq!(message["params"]["result"]["height"], json!(10));
+
+        assert!(manager.remove("indexer").is_some());
+        assert!(load_cursor(&chain, "indexer").is_none());
+    }
+
+    // the webhook acknowledges the blocks with 200, the position is persisted
+    #[test]
+    fn t_webhook_cursor() {
+        let _system = System::new("t_webhook_cursor");
+        let key_pair = Random.generate().unwrap();
+        let chain = chain_of(&key_pair, 3);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        let manager = SubscriptionManager::new(chain.clone());
+        manager.put(cursor("hook", Some(url), 1));
+        manager.deliver();
+        for _ in 0..50 {
+            if load_cursor(&chain, "hook").unwrap().position == 4 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(load_cursor(&chain, "hook").unwrap().position, 4);
+
+        // the replaced cursor isn't overwritten by the old delivery
+        manager.put(cursor("hook", Some("http://127.0.0.1:1".to_owned()), 2));
+        manager.deliver();
+        thread::sleep(Duration::from_millis(200));
+        let cursor = load_cursor(&chain, "hook").unwrap();
+        assert_eq!((cursor.position, cursor.failures), (2, 1));
+    }
+}
@@ -1,18 +1,45 @@
+use std::time::Duration;
+
 use ::actix::prelude::*;
 use actix_broker::BrokerIssue;
+use cryptocurrency_kit::crypto::Hash;
 use libp2p::PeerId;
 
-use crate::types::block::{Header, Block, Blocks};
+use crate::consensus::types::Round;
+use crate::types::block::{Block, Blocks};
 use crate::types::Height;
+use crate::types::transaction::Transaction;
+use crate::core::safety::SafetyEvidence;
 
 pub const MAX_MAILBOX_CAPACITY: usize = 1 << 11;
 
+/// the event of a committed block, the webhooks, the cursors and the websockets share the name
+pub const COMMITTED_EVENT: &str = "committed";
+
+/// the json of a committed block for the subscribers
+pub fn committed_event(block: &Block) -> serde_json::Value {
+    json!({
+        "event": COMMITTED_EVENT,
+        "height": block.height(),
+        "hash": format!("{:?}", block.hash()),
+        "block": block,
+    })
+}
+
 #[derive(Message, Clone, Debug)]
 pub enum ChainEvent {
-    NewBlock(Block),
-    NewHeader(Header),
+    // the block is appended to the chain, the duration is the time since its parent and the round
+    // is known only if the local consensus committed it
+    Committed { block: Block, duration: Duration, round: Option<Round> },
+    // the blocks are dropped from the chain, the highest first
+    Reverted { blocks: Vec<Block> },
+    // the chain falls behind the network, it syncs the blocks up to the height
+    SyncStarted { from: Height, to: Height },
+    SyncFinished { height: Height },
     SyncBlock(Height),
     PostBlock(Option<PeerId>, Blocks),
+    // two finalized blocks at the same height, the node halts
+    SafetyViolation(SafetyEvidence),
 }
 
 // cross thread event
@@ -24,6 +51,25 @@ pub mod ChainEventCT {
     impl_subscribe_handler! {ChainEvent}
 }
 
+/// the changes of the transaction pool, the subscribers react to them instead of polling the pool
+#[derive(Message, Clone, Debug)]
+pub enum TxPoolEvent {
+    Added(Transaction),
+    // the transaction is rejected or evicted, with the reason
+    Dropped(Hash, String),
+    // the transactions are included by the block at the height and removed from the pool
+    Included(Height, Vec<Hash>),
+}
+
+// cross thread event
+pub mod TxPoolEventCT {
+    use ::actix::prelude::*;
+    use super::TxPoolEvent;
+    use crate::subscriber::impl_subscribe_handler;
+
+    impl_subscribe_handler! {TxPoolEvent}
+}
+
 pub enum SubscriberType {
     Async,
     Sync,
@@ -69,14 +115,15 @@ impl ChainEventSubscriber {
 }
 
 
-use crate::types::transaction::Transaction;
 use crate::protocol::GossipMessage;
+use crate::util::cancel::CancelToken;
 
 #[derive(Message, Clone, Debug)]
 pub enum BroadcastEvent {
     Transaction(Transaction),
     Blocks(Option<PeerId>, Blocks),
-    Consensus(GossipMessage),
+    // the message is dropped if the token is cancelled before it's sent
+    Consensus(GossipMessage, Option<CancelToken>),
     Sync(Height),
 }
 
@@ -183,7 +230,7 @@ mod test {
 
         ::std::thread::spawn(move || {
             while true {
-                sub.do_send(BroadcastEvent::Consensus(GossipMessage::new(MessageType::RoundChange, vec![], None)));
+                sub.do_send(BroadcastEvent::Consensus(GossipMessage::new(MessageType::RoundChange, vec![], None), None));
                 ::std::thread::sleep(::std::time::Duration::from_secs(2));
             }
         });
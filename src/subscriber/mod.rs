@@ -4,6 +4,8 @@ use libp2p::Multiaddr;
 use libp2p::PeerId;
 
 pub mod events;
+pub mod queue;
+pub mod cursor;
 
 use crate::types::block::{Block, Header};
 use super::*;
@@ -16,10 +18,8 @@ pub enum P2PEvent {
 
 impl_subscribe_handler! {P2PEvent}
 
-pub fn spawn_sync_subscriber() -> Addr<ProcessSignals> {
-    Actor::create(|_| ProcessSignals {
-        subscribers: vec![],
-    })
+pub fn spawn_sync_subscriber(capacity: usize, policy: queue::OverflowPolicy) -> Addr<ProcessSignals> {
+    Actor::create(move |_| ProcessSignals::with_policy(capacity, policy))
 }
 
 #[macro_export]
@@ -41,13 +41,23 @@ macro_rules! impl_subscribe_handler {
             }
         }
 
+        /// ProcessSignals distributes the events to all subscribers, every subscriber has
+        /// a bounded queue, the overflow events are handled by the policy
         #[derive(Clone)]
         pub struct ProcessSignals {
-            subscribers: Vec<Recipient<$key>>,
+            subscribers: Vec<$crate::subscriber::queue::BoundedRecipient<$key>>,
+            capacity: usize,
+            policy: $crate::subscriber::queue::OverflowPolicy,
         }
 
         impl Actor for ProcessSignals {
             type Context = Context<Self>;
+
+            fn started(&mut self, ctx: &mut Self::Context) {
+                ctx.run_interval($crate::subscriber::queue::FLUSH_INTERVAL, |act, _| {
+                    act.flush();
+                });
+            }
         }
 
         impl Handler<$key> for ProcessSignals {
@@ -76,22 +86,44 @@ macro_rules! impl_subscribe_handler {
 
         impl ProcessSignals {
             pub fn new() -> Self {
-                ProcessSignals {subscribers: vec![]}
+                ProcessSignals::with_policy($crate::subscriber::queue::DEFAULT_QUEUE_CAPACITY,
+                                            $crate::subscriber::queue::OverflowPolicy::default())
+            }
+
+            pub fn with_policy(capacity: usize, policy: $crate::subscriber::queue::OverflowPolicy) -> Self {
+                ProcessSignals {
+                    subscribers: vec![],
+                    capacity: capacity,
+                    policy: policy,
+                }
             }
 
             pub fn subscribe(&mut self, recipient: Recipient<$key>) {
-                self.subscribers.push(recipient);
+                let subscriber = $crate::subscriber::queue::BoundedRecipient::new(recipient,
+                                                                                 self.capacity,
+                                                                                 self.policy,
+                                                                                 stringify!($key));
+                self.subscribers.push(subscriber);
             }
 
             pub fn unsubscribe(&mut self, recipient: Recipient<$key>) {
-                self.subscribers.remove_item(&recipient);
+                self.subscribers.retain(|subscriber| *subscriber.recipient() != recipient);
             }
 
             /// Async send a message to subscriber mailbox
             pub fn distribute(&mut self, msg: $key) {
-                for subscriber in &self.subscribers {
-                    subscriber.do_send(msg.clone());
+                for subscriber in self.subscribers.iter_mut() {
+                    subscriber.push(msg.clone());
                 }
+                self.subscribers.retain(|subscriber| !subscriber.is_closed());
+            }
+
+            /// deliver the pending messages of the slow subscribers
+            pub fn flush(&mut self) {
+                for subscriber in self.subscribers.iter_mut() {
+                    subscriber.flush();
+                }
+                self.subscribers.retain(|subscriber| !subscriber.is_closed());
             }
         }
     };
@@ -132,9 +164,7 @@ mod tests {
         use chrono::Local;
         use chrono::Timelike;
         let system = System::new("test");
-        let subscribe_pid = Actor::create(|_| ProcessSignals {
-            subscribers: vec![],
-        });
+        let subscribe_pid = Actor::create(|_| ProcessSignals::new());
         (0..10).for_each(|_idx| {
             let name = format!("{}", Local::now().time().nanosecond());
             let worker = Worker::create(|_| Worker {
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use ::actix::prelude::*;
+
+use crate::metrics;
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1 << 10;
+// the max time that the `Block` policy waits for the slow subscriber
+pub const BLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to do when the subscriber's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    // drop the oldest pending event
+    DropOldest,
+    // wait the subscriber for at most `BLOCK_TIMEOUT`, then drop the oldest pending event
+    Block,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::DropOldest
+    }
+}
+
+/// BoundedRecipient buffers the events which can't be delivered because the subscriber's
+/// mailbox is full, the buffer never grows over `capacity`.
+#[derive(Clone)]
+pub struct BoundedRecipient<M>
+    where M: Message + Send + Clone,
+          M::Result: Send {
+    recipient: Recipient<M>,
+    pending: VecDeque<M>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    // the metric names of the dropped and the pending events
+    metric: String,
+    pending_metric: String,
+    closed: bool,
+}
+
+impl<M> BoundedRecipient<M>
+    where M: Message + Send + Clone,
+          M::Result: Send {
+    pub fn new(recipient: Recipient<M>, capacity: usize, policy: OverflowPolicy, event: &str) -> Self {
+        BoundedRecipient {
+            recipient,
+            pending: VecDeque::new(),
+            capacity: ::std::cmp::max(capacity, 1),
+            policy,
+            metric: format!("subscriber_dropped_total{{event=\"{}\"}}", event),
+            pending_metric: format!("subscriber_pending{{event=\"{}\"}}", event),
+            closed: false,
+        }
+    }
+
+    pub fn recipient(&self) -> &Recipient<M> {
+        &self.recipient
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn push(&mut self, msg: M) {
+        if self.closed {
+            return;
+        }
+        self.pending.push_back(msg);
+        self.flush();
+        if self.pending.len() <= self.capacity {
+            metrics::set_gauge(&self.pending_metric, self.pending.len() as i64);
+            return;
+        }
+        if self.policy == OverflowPolicy::Block {
+            let deadline = Instant::now() + BLOCK_TIMEOUT;
+            while self.pending.len() > self.capacity && !self.closed && Instant::now() < deadline {
+                sleep(Duration::from_millis(1));
+                self.flush();
+            }
+        }
+        while self.pending.len() > self.capacity {
+            self.pending.pop_front();
+            metrics::inc_counter(&self.metric);
+        }
+        metrics::set_gauge(&self.pending_metric, self.pending.len() as i64);
+    }
+
+    /// deliver the pending events until the subscriber's mailbox is full
+    pub fn flush(&mut self) {
+        while let Some(msg) = self.pending.pop_front() {
+            match self.recipient.try_send(msg) {
+                Ok(_) => {}
+                Err(SendError::Full(msg)) => {
+                    self.pending.push_front(msg);
+                    return;
+                }
+                Err(SendError::Closed(_)) => {
+                    self.closed = true;
+                    self.pending.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use super::transaction::Transaction;
+use super::Height;
+
+/// the confirmed state of an account
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    // the next nonce that the chain accepts
+    pub nonce: u64,
+}
+
+implement_hash_algo_traits! {Account}
+implement_storagevalue_traits! {Account}
+
+impl Account {
+    pub fn credit(&mut self, amount: u64) {
+        self.balance = self.balance.saturating_add(amount);
+    }
+
+    pub fn debit(&mut self, transaction: &Transaction) {
+        self.balance = self.balance.saturating_sub(transaction.amount());
+        self.nonce = ::std::cmp::max(self.nonce, transaction.nonce() + 1);
+    }
+
+    /// move the amount of the transaction out only if the balance covers it, the nonce is used
+    /// either way, returns whether the amount is moved
+    pub fn try_debit(&mut self, transaction: &Transaction) -> bool {
+        self.nonce = ::std::cmp::max(self.nonce, transaction.nonce() + 1);
+        if self.balance < transaction.amount() {
+            return false;
+        }
+        self.balance -= transaction.amount();
+        true
+    }
+
+    /// charge at most the balance for the fee, returns the charged amount
+    pub fn charge(&mut self, fee: u64) -> u64 {
+        let charged = ::std::cmp::min(self.balance, fee);
+        self.balance -= charged;
+        charged
+    }
+
+    /// move the amount out without a transaction, eg: the released stakes
+    pub fn withdraw(&mut self, amount: u64) {
+        self.balance = self.balance.saturating_sub(amount);
+    }
+}
+
+/// the state of an account at a height, `last_active_height` is the last block which
+/// sent to or from the account
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountState {
+    pub address: Address,
+    pub height: Height,
+    pub balance: u64,
+    pub nonce: u64,
+    pub last_active_height: Option<Height>,
+}
+
+/// AccountSummary overlays the pending transactions of the pool on the confirmed state,
+/// the pending transactions which were already confirmed (nonce < confirmed nonce) are ignored
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountSummary {
+    pub address: Address,
+    pub balance: u64,
+    pub nonce: u64,
+    pub pending_count: usize,
+    pub pending_outgoing: u64,
+    pub pending_balance: u64,
+    // the next nonce that a wallet should use
+    pub next_nonce: u64,
+}
+
+impl AccountSummary {
+    pub fn new(address: Address, account: Account, pending: &[&Transaction]) -> Self {
+        let mut pending: Vec<&&Transaction> = pending.iter().filter(|tx| tx.nonce() >= account.nonce).collect();
+        pending.sort_by_key(|tx| tx.nonce());
+        let pending_outgoing = pending.iter().fold(0_u64, |acc, tx| acc.saturating_add(tx.amount()));
+        let mut next_nonce = account.nonce;
+        for tx in pending.iter() {
+            if tx.nonce() == next_nonce {
+                next_nonce += 1;
+            } else if tx.nonce() > next_nonce {
+                // there is a nonce gap
+                break;
+            }
+        }
+        AccountSummary {
+            address,
+            balance: account.balance,
+            nonce: account.nonce,
+            pending_count: pending.len(),
+            pending_outgoing,
+            pending_balance: account.balance.saturating_sub(pending_outgoing),
+            next_nonce,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(nonce: u64, amount: u64) -> Transaction {
+        Transaction::new(nonce, Address::from(1), amount, 21000, 1, vec![])
+    }
+
+    #[test]
+    fn t_account_summary() {
+        let account = Account { balance: 100, nonce: 3 };
+        let (tx2, tx3, tx4, tx6) = (transaction(2, 10), transaction(3, 20), transaction(4, 30), transaction(6, 5));
+        let summary = AccountSummary::new(Address::from(2), account.clone(), &[&tx6, &tx2, &tx4, &tx3]);
+        assert_eq!(summary.pending_count, 3);
+        assert_eq!(summary.pending_outgoing, 55);
+        assert_eq!(summary.pending_balance, 45);
+        assert_eq!(summary.next_nonce, 5);
+
+        let summary = AccountSummary::new(Address::from(2), account, &[]);
+        assert_eq!(summary.next_nonce, 3);
+        assert_eq!(summary.pending_balance, 100);
+
+        let mut account = Account::default();
+        account.credit(10);
+        account.debit(&tx4);
+        assert_eq!(account, Account { balance: 0, nonce: 5 });
+
+        // the amount above the balance isn't moved, the nonce is used
+        let mut account = Account { balance: 10, nonce: 0 };
+        assert!(!account.try_debit(&tx2));
+        assert_eq!(account, Account { balance: 10, nonce: 3 });
+        assert!(account.try_debit(&transaction(3, 10)));
+        assert_eq!(account, Account { balance: 0, nonce: 4 });
+        account.credit(5);
+        assert_eq!(account.charge(8), 5);
+        assert_eq!(account.balance, 0);
+    }
+}
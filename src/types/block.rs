@@ -1,9 +1,12 @@
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash, EMPTY_HASH};
+
+use crate::common::hash_algo::digest;
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::ethkey::signature::*;
-use cryptocurrency_kit::ethkey::{Address, Secret, Signature};
+use cryptocurrency_kit::ethkey::{public_to_address, recover_bytes, Address, Secret, Signature};
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 use std::io::Cursor;
 use std::borrow::Cow;
@@ -12,6 +15,15 @@ use super::transaction::Transaction;
 use super::votes::Votes;
 use super::{Bloom, Difficulty, Gas, Height, Timestamp};
 
+// the bft header rules, the fields inherited from the pow headers have fixed meanings: the
+// difficulty is always `BFT_DIFFICULTY` (the round isn't known when the proposal is built, and a
+// locked proposal is re-proposed in later rounds), there is no mix digest or nonce, the extra is
+// the vanity of the proposer followed by the `IstanbulExtra`, the commit seals are carried by
+// `votes` too
+pub const BFT_DIFFICULTY: Difficulty = 1;
+// the max length of the vanity, like istanbul
+pub const EXTRA_VANITY: usize = 32;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Header {
     pub prev_hash: Hash,
@@ -36,7 +48,7 @@ pub struct Header {
     hash_cache: Option<Hash>, // use atomic pre instant of it
 }
 
-implement_cryptohash_traits! {Header}
+implement_hash_algo_traits! {Header}
 implement_storagevalue_traits! {Header}
 
 impl Header {
@@ -73,16 +85,103 @@ impl Header {
         }
     }
 
+    /// the block hash is the hash of the canonical rlp encoding without votes
     pub fn block_hash(&self) -> Hash {
-        self.hash_cache.map_or_else(|| {
-            let mut header = self.clone();
-            header.votes = None;
-            <Header as CryptoHash>::hash(&header)
-        }, |hash| hash)
+        self.hash_cache.map_or_else(|| digest(self.rlp_bytes_without_votes()), |hash| hash)
+    }
+
+    pub fn rlp_bytes(&self) -> Vec<u8> {
+        rlp::encode(self).to_vec()
+    }
+
+    pub fn rlp_bytes_without_votes(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new();
+        self.stream_rlp(&mut stream, false);
+        stream.out()
+    }
+
+    pub fn from_rlp(bytes: &[u8]) -> Result<Header, DecoderError> {
+        UntrustedRlp::new(bytes).as_val()
+    }
+
+    fn stream_rlp(&self, s: &mut RlpStream, with_votes: bool) {
+        s.begin_list(13);
+        s.append(&self.prev_hash.as_ref().to_vec());
+        s.append(&self.proposer.to_vec());
+        s.append(&self.root.as_ref().to_vec());
+        s.append(&self.tx_hash.as_ref().to_vec());
+        s.append(&self.receipt_hash.as_ref().to_vec());
+        s.append(&self.bloom);
+        s.append(&self.difficulty);
+        s.append(&self.height);
+        s.append(&self.gas_limit);
+        s.append(&self.gas_used);
+        s.append(&self.time);
+        match self.extra {
+            // the seals are left out of the block hash, they sign it
+            Some(ref extra) if !with_votes && extra.len() > EXTRA_VANITY => {
+                let filtered = IstanbulExtra::from_extra(extra)
+                    .map(|istanbul| istanbul.filtered().to_extra(&extra[..EXTRA_VANITY]))
+                    .unwrap_or_else(|_| extra.clone());
+                s.begin_list(1).append(&filtered);
+            }
+            Some(ref extra) => {
+                s.begin_list(1).append(extra);
+            }
+            None => {
+                s.begin_list(0);
+            }
+        }
+        match self.votes {
+            Some(ref votes) if with_votes => {
+                s.begin_list(1).append(votes);
+            }
+            _ => {
+                s.begin_list(0);
+            }
+        }
     }
 
     pub fn new_mock(pre_hash: Hash, proposer: Address, tx_hash: Hash, height: Height, tm: Timestamp, extra: Option<Vec<u8>>) -> Self {
-        Self::new(pre_hash, proposer, EMPTY_HASH, tx_hash, EMPTY_HASH, 0, 0, height, 0, 0, tm, None, extra)
+        Self::new(pre_hash, proposer, EMPTY_HASH, tx_hash, EMPTY_HASH, Bloom::zero(), BFT_DIFFICULTY, height, 0, 0, tm, None, extra)
+    }
+
+    /// the vanity bytes of the proposer, it's empty without the extra
+    pub fn vanity(&self) -> &[u8] {
+        self.extra.as_ref().map_or(&[], |extra| &extra[..::std::cmp::min(extra.len(), EXTRA_VANITY)])
+    }
+
+    /// the istanbul extra after the vanity, it's absent on the blocks which carry only a vanity
+    pub fn istanbul_extra(&self) -> Option<IstanbulExtra> {
+        self.extra.as_ref().and_then(|extra| IstanbulExtra::from_extra(extra).ok())
+    }
+
+    /// write the istanbul extra after the vanity, the seals don't change the block hash
+    pub fn set_istanbul_extra(&mut self, istanbul: &IstanbulExtra) {
+        let extra = istanbul.to_extra(self.vanity());
+        self.extra = Some(extra);
+        self.hash_cache = None;
+    }
+
+    /// the commit seals of the block, they're absent until the block is committed
+    pub fn commit_seals(&self) -> Option<&Votes> {
+        self.votes.as_ref()
+    }
+
+    /// check the fields which the bft header rules fix, see `BFT_DIFFICULTY`, the headers below
+    /// the activation height were written before the rules, they're not checked
+    pub fn verify_bft_fields(&self, activation_height: Height) -> Result<(), String> {
+        if self.height < activation_height {
+            return Ok(());
+        }
+        if self.difficulty != BFT_DIFFICULTY {
+            return Err(format!("difficulty must be {}, got: {}", BFT_DIFFICULTY, self.difficulty));
+        }
+        let extra_len = self.extra.as_ref().map_or(0, |extra| extra.len());
+        if extra_len > EXTRA_VANITY && self.istanbul_extra().is_none() {
+            return Err(format!("extra is longer than {} bytes and isn't an istanbul extra, got: {}", EXTRA_VANITY, extra_len));
+        }
+        Ok(())
     }
 
     pub fn cache_hash(&mut self, block_hash: Option<Hash>) {
@@ -101,7 +200,7 @@ impl Header {
             root: Hash::zero(),
             tx_hash: Hash::zero(),
             receipt_hash: Hash::zero(),
-            bloom: 0,
+            bloom: Bloom::zero(),
             difficulty: 0,
             height: 0,
             gas_limit: 0,
@@ -114,6 +213,168 @@ impl Header {
     }
 }
 
+impl Encodable for Header {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        self.stream_rlp(s, true);
+    }
+}
+
+impl Decodable for Header {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 13 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(Header {
+            prev_hash: decode_hash(rlp, 0)?,
+            proposer: decode_address(rlp, 1)?,
+            root: decode_hash(rlp, 2)?,
+            tx_hash: decode_hash(rlp, 3)?,
+            receipt_hash: decode_hash(rlp, 4)?,
+            bloom: rlp.val_at(5)?,
+            difficulty: rlp.val_at(6)?,
+            height: rlp.val_at(7)?,
+            gas_limit: rlp.val_at(8)?,
+            gas_used: rlp.val_at(9)?,
+            time: rlp.val_at(10)?,
+            extra: decode_option(rlp, 11)?,
+            votes: decode_option(rlp, 12)?,
+            hash_cache: None,
+        })
+    }
+}
+
+/// IstanbulExtra follows the vanity in `Header.extra` like the istanbul bft, so its tooling reads
+/// the validators and the seals: `vanity (EXTRA_VANITY bytes) | rlp([validators, seal, committed_seal])`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IstanbulExtra {
+    pub validators: Vec<Address>,
+    // the signature of the proposer over the block hash
+    pub seal: Option<Signature>,
+    pub committed_seal: Vec<Signature>,
+}
+
+impl IstanbulExtra {
+    pub fn new(validators: Vec<Address>) -> Self {
+        IstanbulExtra { validators, seal: None, committed_seal: vec![] }
+    }
+
+    /// the vanity is padded or truncated to `EXTRA_VANITY` bytes
+    pub fn to_extra(&self, vanity: &[u8]) -> Vec<u8> {
+        let mut extra = vanity.to_vec();
+        extra.resize(EXTRA_VANITY, 0);
+        extra.extend_from_slice(&rlp::encode(self));
+        extra
+    }
+
+    pub fn from_extra(extra: &[u8]) -> Result<IstanbulExtra, DecoderError> {
+        if extra.len() <= EXTRA_VANITY {
+            return Err(DecoderError::RlpInvalidLength);
+        }
+        UntrustedRlp::new(&extra[EXTRA_VANITY..]).as_val()
+    }
+
+    /// the extra without the seals, the block hash covers it
+    pub fn filtered(&self) -> IstanbulExtra {
+        IstanbulExtra::new(self.validators.clone())
+    }
+
+    pub fn recover_proposer(&self, block_hash: &Hash) -> Result<Address, String> {
+        let seal = self.seal.as_ref().ok_or("the proposer seal is absent".to_string())?;
+        recover_bytes(seal, block_hash.as_ref())
+            .map(|public| public_to_address(&public))
+            .map_err(|_| "recover proposer seal failed".to_string())
+    }
+}
+
+impl Encodable for IstanbulExtra {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.begin_list(self.validators.len());
+        for validator in &self.validators {
+            s.append(&validator.to_vec());
+        }
+        // the absent seal is the empty bytes
+        s.append(&self.seal.as_ref().map_or(vec![], |seal| seal.to_vec()));
+        s.begin_list(self.committed_seal.len());
+        for seal in &self.committed_seal {
+            s.append(&seal.to_vec());
+        }
+    }
+}
+
+impl Decodable for IstanbulExtra {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 3 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        let validators = rlp.at(0)?;
+        let validators = (0..validators.item_count()?)
+            .map(|idx| decode_address(&validators, idx))
+            .collect::<Result<Vec<_>, _>>()?;
+        let seal = match rlp.val_at::<Vec<u8>>(1)?.is_empty() {
+            true => None,
+            false => Some(decode_signature(rlp, 1)?),
+        };
+        let committed_seal = rlp.at(2)?;
+        let committed_seal = (0..committed_seal.item_count()?)
+            .map(|idx| decode_signature(&committed_seal, idx))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(IstanbulExtra { validators, seal, committed_seal })
+    }
+}
+
+impl Encodable for Votes {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(self.len());
+        for vote in self.votes() {
+            s.append(&vote.to_vec());
+        }
+    }
+}
+
+impl Decodable for Votes {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let mut votes = vec![];
+        for idx in 0..rlp.item_count()? {
+            votes.push(decode_signature(rlp, idx)?);
+        }
+        Ok(Votes::new(votes))
+    }
+}
+
+pub(crate) fn decode_hash(rlp: &UntrustedRlp, idx: usize) -> Result<Hash, DecoderError> {
+    let bytes: Vec<u8> = rlp.val_at(idx)?;
+    Hash::from_slice(&bytes).ok_or(DecoderError::RlpInvalidLength)
+}
+
+pub(crate) fn decode_address(rlp: &UntrustedRlp, idx: usize) -> Result<Address, DecoderError> {
+    let bytes: Vec<u8> = rlp.val_at(idx)?;
+    if bytes.len() != 20 {
+        return Err(DecoderError::RlpInvalidLength);
+    }
+    Ok(Address::from_slice(&bytes))
+}
+
+pub(crate) fn decode_signature(rlp: &UntrustedRlp, idx: usize) -> Result<Signature, DecoderError> {
+    let bytes: Vec<u8> = rlp.val_at(idx)?;
+    if bytes.len() != 65 {
+        return Err(DecoderError::RlpInvalidLength);
+    }
+    let mut signature = [0_u8; 65];
+    signature.copy_from_slice(&bytes);
+    Ok(Signature::from(signature))
+}
+
+// the optional value is encoded as a list with zero or one item
+pub(crate) fn decode_option<T: Decodable>(rlp: &UntrustedRlp, idx: usize) -> Result<Option<T>, DecoderError> {
+    let item = rlp.at(idx)?;
+    match item.item_count()? {
+        0 => Ok(None),
+        1 => Ok(Some(item.val_at(0)?)),
+        _ => Err(DecoderError::RlpIncorrectListLen),
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct HeaderBytes<'a> {
     pub prev_hash: Cow<'a, Hash>,
@@ -159,14 +420,47 @@ pub struct Block {
     transactions: Vec<Transaction>,
 }
 
-implement_cryptohash_traits! {Block}
+implement_hash_algo_traits! {Block}
 implement_storagevalue_traits! {Block}
 
+impl Encodable for Block {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.header);
+        s.append_list(&self.transactions);
+    }
+}
+
+impl Decodable for Block {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(Block {
+            header: rlp.val_at(0)?,
+            transactions: rlp.list_at(1)?,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blocks(pub Vec<Block>);
-implement_cryptohash_traits! {Blocks}
+implement_hash_algo_traits! {Blocks}
 implement_storagevalue_traits! {Blocks}
 
+impl Blocks {
+    /// the canonical encoding for wire transfer
+    pub fn rlp_bytes(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new();
+        stream.append_list(&self.0);
+        stream.out()
+    }
+
+    pub fn from_rlp(bytes: &[u8]) -> Result<Blocks, DecoderError> {
+        UntrustedRlp::new(bytes).as_list().map(Blocks)
+    }
+}
+
 
 impl Block {
     pub fn new(header: Header, txs: Vec<Transaction>) -> Self {
@@ -237,4 +531,85 @@ mod tests {
         let j_str = serde_json::to_string(&header).unwrap();
         writeln!(io::stdout(), "{}", j_str).unwrap();
     }
+
+    fn fixture_header() -> Header {
+        let proposer = Address::from_slice(&hex::decode("72d5c75fd6703414aa87f79b3e4797dd09cd9251").unwrap());
+        let hash_of = |v: u8| Hash::from_slice(&[v; 32]).unwrap();
+        Header::new(hash_of(1), proposer, hash_of(2), hash_of(3), hash_of(4), Bloom::zero(), 0, 1, 1024, 0, 1544610951, None, Some(Vec::from("abc")))
+    }
+
+    #[test]
+    fn t_bft_fields() {
+        let mut header = Header::new_mock(EMPTY_HASH, Address::from(1), EMPTY_HASH, 1, 1, Some(vec![1; EXTRA_VANITY]));
+        assert!(header.verify_bft_fields(0).is_ok());
+        assert_eq!(header.vanity().len(), EXTRA_VANITY);
+        assert!(header.commit_seals().is_none());
+        header.extra = Some(vec![1; EXTRA_VANITY + 1]);
+        assert!(header.verify_bft_fields(0).is_err());
+        header.extra = None;
+        header.difficulty = 0;
+        assert!(header.verify_bft_fields(0).is_err());
+        // the headers before the activation height are not checked
+        assert!(header.verify_bft_fields(2).is_ok());
+        assert!(header.verify_bft_fields(1).is_err());
+    }
+
+    #[test]
+    fn t_istanbul_extra() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+        let key_pair = Random.generate().unwrap();
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 1, Some(Vec::from("vanity")));
+        assert!(header.istanbul_extra().is_none());
+
+        header.set_istanbul_extra(&IstanbulExtra::new(vec![key_pair.address(), Address::from(2)]));
+        let block_hash = header.block_hash();
+        let mut istanbul = header.istanbul_extra().unwrap();
+        istanbul.seal = Some(block_hash.sign(key_pair.secret()).unwrap());
+        istanbul.committed_seal = vec![block_hash.sign(key_pair.secret()).unwrap()];
+        header.set_istanbul_extra(&istanbul);
+
+        assert_eq!(header.block_hash(), block_hash);
+        assert_eq!(&header.vanity()[..6], b"vanity");
+        assert!(header.verify_bft_fields(0).is_ok());
+        let decoded = Header::from_rlp(&header.rlp_bytes()).unwrap().istanbul_extra().unwrap();
+        assert_eq!(decoded, istanbul);
+        assert_eq!(decoded.recover_proposer(&block_hash), Ok(key_pair.address()));
+    }
+
+    #[test]
+    fn t_header_rlp_fixtures() {
+        let header = Header::zero_header();
+        assert_eq!(hex::encode(header.rlp_bytes()),
+                   "f8a1a00000000000000000000000000000000000000000000000000000000000000000940000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000000808080808080c0c0");
+        assert_eq!(header.block_hash(), hash(header.rlp_bytes()));
+
+        let header = fixture_header();
+        let expect = "f8aba001010101010101010101010101010101010101010101010101010101010101019472d5c75fd6703414aa87f79b3e4797dd09cd9251a00202020202020202020202020202020202020202020202020202020202020202a00303030303030303030303030303030303030303030303030303030303030303a0040404040404040404040404040404040404040404040404040404040404040480800182040080845c10e487c483616263c0";
+        assert_eq!(hex::encode(header.rlp_bytes()), expect);
+        assert_eq!(header.block_hash(), hash(hex::decode(expect).unwrap()));
+    }
+
+    #[test]
+    fn t_header_rlp_votes() {
+        use cryptocurrency_kit::ethkey::{Generator, Random};
+
+        let mut header = fixture_header();
+        let block_hash = header.block_hash();
+        let key_pair = Random.generate().unwrap();
+        let signature = block_hash.sign(key_pair.secret()).unwrap();
+        header.votes = Some(Votes::new(vec![signature.clone()]));
+        // the votes are not a part of block hash
+        assert_eq!(header.block_hash(), block_hash);
+
+        let decoded = Header::from_rlp(&header.rlp_bytes()).unwrap();
+        assert_eq!(decoded.block_hash(), block_hash);
+        assert_eq!(decoded.votes.unwrap().votes(), &vec![signature]);
+
+        let block = Block::new(header, vec![]);
+        let blocks = Blocks(vec![block.clone(), block]);
+        let decoded = Blocks::from_rlp(&blocks.rlp_bytes()).unwrap();
+        assert_eq!(decoded.0.len(), 2);
+        assert_eq!(decoded.0[1].hash(), block_hash);
+        assert!(Header::from_rlp(&[0xc0]).is_err());
+    }
 }
\ No newline at end of file
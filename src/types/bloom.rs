@@ -0,0 +1,185 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use sha3::{Digest, Keccak256};
+
+pub const BLOOM_SIZE: usize = 256;
+// the bits set by an item
+const BLOOM_BITS: usize = 3;
+
+/// Bloom is the 2048 bits bloom of the logs, every item sets 3 bits picked by its keccak like
+/// ethereum. It's encoded as a big endian number without the leading zero bytes, so the zero
+/// bloom of the older headers keeps their hashes, eg: `0x80` is the empty bloom
+#[derive(Clone, Copy)]
+pub struct Bloom([u8; BLOOM_SIZE]);
+
+impl Bloom {
+    pub fn zero() -> Self {
+        Bloom([0; BLOOM_SIZE])
+    }
+
+    /// the bloom of one item
+    pub fn from_item(item: &[u8]) -> Self {
+        let mut bloom = Bloom::zero();
+        bloom.accrue(item);
+        bloom
+    }
+
+    /// the big endian bytes, at most `BLOOM_SIZE`, the missing leading bytes are zeros
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > BLOOM_SIZE {
+            return None;
+        }
+        let mut bloom = Bloom::zero();
+        bloom.0[BLOOM_SIZE - bytes.len()..].copy_from_slice(bytes);
+        Some(bloom)
+    }
+
+    pub fn accrue(&mut self, item: &[u8]) {
+        let digest = Keccak256::digest(item);
+        for idx in 0..BLOOM_BITS {
+            let bit = ((digest[2 * idx] as usize) << 8 | digest[2 * idx + 1] as usize) % (BLOOM_SIZE * 8);
+            self.0[BLOOM_SIZE - 1 - bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// false if the item was surely never accrued
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.contains_bloom(&Bloom::from_item(item))
+    }
+
+    pub fn contains_bloom(&self, other: &Bloom) -> bool {
+        self.0.iter().zip(other.0.iter()).all(|(bits, other)| bits & other == *other)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    // the bytes without the leading zeros
+    fn trimmed(&self) -> &[u8] {
+        let start = self.0.iter().position(|byte| *byte != 0).unwrap_or(BLOOM_SIZE);
+        &self.0[start..]
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom::zero()
+    }
+}
+
+impl PartialEq for Bloom {
+    fn eq(&self, other: &Bloom) -> bool {
+        self.0[..] == other.0[..]
+    }
+}
+
+impl Eq for Bloom {}
+
+impl fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.trimmed()))
+    }
+}
+
+impl BitOr for Bloom {
+    type Output = Bloom;
+
+    fn bitor(mut self, other: Bloom) -> Bloom {
+        self |= other;
+        self
+    }
+}
+
+impl BitOrAssign for Bloom {
+    fn bitor_assign(&mut self, other: Bloom) {
+        for (bits, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *bits |= *other;
+        }
+    }
+}
+
+impl Encodable for Bloom {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.append(&self.trimmed().to_vec());
+    }
+}
+
+impl Decodable for Bloom {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let bytes: Vec<u8> = rlp.as_val()?;
+        // the encoding is canonical, like the numbers
+        if bytes.first() == Some(&0) {
+            return Err(DecoderError::RlpInvalidIndirection);
+        }
+        Bloom::from_slice(&bytes).ok_or(DecoderError::RlpIsTooBig)
+    }
+}
+
+// the hex string of the big endian bytes
+impl Serialize for Bloom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{}", hex::encode(&self.0[..])))
+    }
+}
+
+struct BloomVisitor;
+
+impl<'de> Visitor<'de> for BloomVisitor {
+    type Value = Bloom;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a hex string of at most {} bytes or the number of an older header", BLOOM_SIZE)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Bloom, E> {
+        let bytes = hex::decode(value.trim_start_matches("0x")).map_err(E::custom)?;
+        Bloom::from_slice(&bytes).ok_or_else(|| E::custom("the bloom is too long"))
+    }
+
+    // the older headers kept a number
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Bloom, E> {
+        Ok(Bloom::from_slice(&value.to_be_bytes()).unwrap())
+    }
+}
+
+impl<'de> Deserialize<'de> for Bloom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bloom, D::Error> {
+        deserializer.deserialize_any(BloomVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_bloom() {
+        let mut bloom = Bloom::zero();
+        assert!(bloom.is_zero() && !bloom.contains(b"a"));
+        bloom.accrue(b"a");
+        assert!(bloom.contains(b"a") && !bloom.contains(b"b"));
+        assert!(bloom.as_bytes().iter().map(|byte| byte.count_ones()).sum::<u32>() <= 3);
+        assert_eq!(bloom | Bloom::from_item(b"b"), Bloom::from_item(b"b") | bloom);
+        assert!((bloom | Bloom::from_item(b"b")).contains_bloom(&bloom));
+
+        // the rlp round trips, the zero bloom is encoded like the number 0 of the older headers
+        let decoded: Bloom = rlp::decode(&rlp::encode(&bloom));
+        assert_eq!(decoded, bloom);
+        assert_eq!(rlp::encode(&Bloom::zero()).to_vec(), rlp::encode(&0u64).to_vec());
+        assert!(UntrustedRlp::new(&rlp::encode(&vec![0u8, 1])).as_val::<Bloom>().is_err());
+
+        let json = serde_json::to_string(&bloom).unwrap();
+        assert_eq!(json.len(), 2 + 2 + 2 * BLOOM_SIZE);
+        assert_eq!(serde_json::from_str::<Bloom>(&json).unwrap(), bloom);
+        assert_eq!(serde_json::from_str::<Bloom>("0").unwrap(), Bloom::zero());
+    }
+}
@@ -12,9 +12,14 @@ use std::clone::Clone;
 use std::cmp::{Ord, Ordering, PartialEq};
 use std::fmt::Display;
 
+pub mod account;
+pub mod bloom;
 pub mod transaction;
 pub mod block;
 pub mod votes;
+pub mod receipt;
+
+pub use self::bloom::Bloom;
 
 lazy_static! {
     pub static ref EMPTY_ADDRESS: Address = {
@@ -24,7 +29,6 @@ lazy_static! {
 
 pub type Height = u64;
 pub type Timestamp = u64;
-pub type Bloom = u64;
 pub type Difficulty = u64;
 pub type Gas = u64;
 
@@ -36,13 +40,13 @@ pub struct ValidatorArray {
     index: HashMap<Address, usize>,
 }
 
-implement_cryptohash_traits! {ValidatorArray}
+implement_hash_algo_traits! {ValidatorArray}
 implement_storagevalue_traits! {ValidatorArray}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HashesEntry(pub Vec<Hash>);
 
-implement_cryptohash_traits! {HashesEntry}
+implement_hash_algo_traits! {HashesEntry}
 implement_storagevalue_traits! {HashesEntry}
 
 impl ValidatorArray {
@@ -61,6 +65,10 @@ impl ValidatorArray {
     pub fn have(&self, address: &Address) -> bool {
         self.index.contains_key(address)
     }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.inner
+    }
 }
 
 impl From<Vec<Validator>> for ValidatorArray {
@@ -81,7 +89,7 @@ pub struct Validator {
     address: Address,
 }
 
-implement_cryptohash_traits! {Validator}
+implement_hash_algo_traits! {Validator}
 implement_storagevalue_traits! {Validator}
 
 impl Ord for Validator {
@@ -0,0 +1,143 @@
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use super::{Bloom, Height};
+
+lazy_static! {
+    pub static ref TRANSFER_TOPIC: Hash = event_topic("Transfer(address,address,uint64)");
+    pub static ref MINT_TOPIC: Hash = event_topic("Mint(address,uint64)");
+}
+
+/// Log is an event emitted by the execution of a transaction, the address and the topics are
+/// indexed by the bloom of the header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<Hash>,
+    pub data: Vec<u8>,
+}
+
+impl Log {
+    pub fn bloom(&self) -> Bloom {
+        self.topics.iter().fold(Bloom::from_item(self.address.as_ref()), |bloom, topic| bloom | Bloom::from_item(topic.as_ref()))
+    }
+}
+
+/// Receipt is the result of a committed transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tx_hash: Hash,
+    pub height: Height,
+    // the index in the block body, the coinbase transaction is 0
+    pub position: u64,
+    pub logs: Vec<Log>,
+    // the gas used and the fee paid by the sender, the burned part of the fee is not paid to
+    // the proposer
+    #[serde(default)]
+    pub gas_used: u64,
+    #[serde(default)]
+    pub fee: u64,
+    #[serde(default)]
+    pub burned: u64,
+}
+
+/// the receipts of a block, in the order of its transactions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Receipts(pub Vec<Receipt>);
+
+implement_hash_algo_traits! {Receipts}
+implement_storagevalue_traits! {Receipts}
+
+/// LogFilter selects the logs of a height range by an address and a topic, the address matches the
+/// logs which it emits or whose topics index it, eg: the transfers to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    pub from: Height,
+    pub to: Height,
+    pub address: Option<Address>,
+    pub topic: Option<Hash>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log: &Log) -> bool {
+        self.address.map_or(true, |address| log.address == address || log.topics.contains(&address_topic(&address)))
+            && self.topic.map_or(true, |topic| log.topics.contains(&topic))
+    }
+
+    /// false if the block surely has no matched log, the zero bloom is a block without logs
+    pub fn may_match(&self, bloom: &Bloom) -> bool {
+        self.address.map_or(true, |address| bloom.contains(address.as_ref()) || bloom.contains(address_topic(&address).as_ref()))
+            && self.topic.map_or(true, |topic| bloom.contains(topic.as_ref()))
+    }
+}
+
+/// a matched log with its position in the chain
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub height: Height,
+    pub tx_hash: Hash,
+    // the index in the logs of the transaction
+    pub log_index: u64,
+    pub log: Log,
+}
+
+/// the topic of an event signature, eg: `Transfer(address,address,uint64)`
+pub fn event_topic(signature: &str) -> Hash {
+    Hash::from_slice(&keccak(signature.as_bytes())).unwrap()
+}
+
+/// the address as an indexed topic, it's left padded with zeros
+pub fn address_topic(address: &Address) -> Hash {
+    let mut topic = [0u8; 32];
+    topic[12..].copy_from_slice(address.as_ref());
+    Hash::from_slice(&topic).unwrap()
+}
+
+pub fn logs_bloom<'a, I: IntoIterator<Item=&'a Log>>(logs: I) -> Bloom {
+    logs.into_iter().fold(Bloom::zero(), |bloom, log| bloom | log.bloom())
+}
+
+/// the bloom of the logs of the receipts, the header of their block must carry it
+pub fn receipts_bloom(receipts: &[Receipt]) -> Bloom {
+    logs_bloom(receipts.iter().flat_map(|receipt| receipt.logs.iter()))
+}
+
+fn keccak(data: &[u8]) -> Vec<u8> {
+    Keccak256::digest(data).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_log_filter() {
+        let (from, to) = (Address::from(1), Address::from(2));
+        let log = Log { address: from, topics: vec![*TRANSFER_TOPIC, address_topic(&from), address_topic(&to)], data: vec![] };
+        let bloom = logs_bloom(vec![&log]);
+        assert!(bloom.contains(from.as_ref()));
+        assert!(bloom.contains(TRANSFER_TOPIC.as_ref()));
+
+        let mut filter = LogFilter { from: 0, to: 10, address: Some(from), topic: Some(address_topic(&to)) };
+        assert!(filter.matches(&log) && filter.may_match(&bloom));
+        // the zero bloom is a block without logs
+        assert!(!filter.may_match(&Bloom::zero()));
+        filter.topic = Some(*MINT_TOPIC);
+        assert!(!filter.matches(&log) && !filter.may_match(&bloom));
+        // the recipient matches the transfers to it
+        filter.address = Some(to);
+        filter.topic = Some(*TRANSFER_TOPIC);
+        assert!(filter.matches(&log) && filter.may_match(&bloom));
+        filter.address = Some(Address::from(3));
+        assert!(!filter.matches(&log) && !filter.may_match(&bloom));
+        filter.address = None;
+        filter.topic = None;
+        assert!(filter.matches(&log));
+    }
+}
@@ -1,16 +1,20 @@
-use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
 use cryptocurrency_kit::ethkey::signature::*;
-use cryptocurrency_kit::ethkey::{Address, Secret, Signature};
+use cryptocurrency_kit::ethkey::{public_to_address, Address, Secret, Signature};
 use cryptocurrency_kit::storage::keys::StorageKey;
 use cryptocurrency_kit::storage::values::StorageValue;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 use std::borrow::Cow;
 use std::io::Cursor;
 
+use crate::common::hash_algo::digest;
 use crate::common::merkle_tree_root;
+use crate::signer::Signer;
 use super::Gas;
+use super::block::{decode_address, decode_signature};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
@@ -25,13 +29,16 @@ pub struct Transaction {
     payload: Vec<u8>,
     #[serde(rename = "sign")]
     signature: Option<Signature>,
+    // the chain which the transaction is signed for, it's the replay protection
+    #[serde(default)]
+    chain_id: u64,
     #[serde(skip_serializing, skip_deserializing)]
     hash: Option<Hash>,
 }
 
 impl CryptoHash for Transaction {
     fn hash(&self) -> Hash {
-        hash(self.hash_payload())
+        digest(self.hash_payload())
     }
 }
 
@@ -54,6 +61,7 @@ impl Transaction {
             amount: amount,
             payload: payload,
             signature: None,
+            chain_id: 0,
             hash: None,
         }
     }
@@ -73,6 +81,9 @@ impl Transaction {
     pub fn nonce(&self) -> u64 {
         self.account_nonce
     }
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
     pub fn to(&self) -> Option<&Address> {
         self.recipient.as_ref()
     }
@@ -83,20 +94,36 @@ impl Transaction {
         to_string(self).unwrap()
     }
 
-    /// TODO
-    pub fn sign(&mut self, _chain_id: u64, secret: &Secret) {
+    /// the chain id is a part of signature payload, so the transaction can't be replayed on other chains
+    pub fn sign(&mut self, chain_id: u64, secret: &Secret) {
+        self.chain_id = chain_id;
         let signature = sign_bytes(secret, &TransactionSignature::packet_signature(&self));
         self.signature = Some(signature.unwrap());
     }
 
-    pub fn verify_sign(&self, _chai_id: u64) -> bool {
-        if self.signature.is_none() {
+    /// sign by the signer of the validator, the key may be kept by a remote signer
+    pub fn sign_with(&mut self, chain_id: u64, signer: &Signer) -> Result<(), String> {
+        self.chain_id = chain_id;
+        self.signature = Some(signer.sign_bytes(&TransactionSignature::packet_signature(&self))?);
+        Ok(())
+    }
+
+    pub fn verify_sign(&self, chain_id: u64) -> bool {
+        if self.signature.is_none() || self.chain_id != chain_id {
             return false;
         }
         let payload = self.signature_payload();
         recover_bytes(self.signature.as_ref().unwrap(), &payload).is_ok()
     }
 
+    /// the signer of the transaction, it's none if the transaction is unsigned
+    pub fn sender(&self) -> Option<Address> {
+        let signature = self.signature.as_ref()?;
+        recover_bytes(signature, &self.signature_payload())
+            .ok()
+            .map(|public| public_to_address(&public))
+    }
+
     pub fn set_hash(&mut self, hash: Hash) {
         self.hash = Some(hash)
     }
@@ -114,6 +141,70 @@ impl Transaction {
     }
 }
 
+impl Encodable for Transaction {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(8);
+        s.append(&self.account_nonce);
+        s.append(&self.gas_price);
+        s.append(&self.gas_limit);
+        match self.recipient {
+            Some(ref recipient) => {
+                s.begin_list(1).append(&recipient.to_vec());
+            }
+            None => {
+                s.begin_list(0);
+            }
+        }
+        s.append(&self.amount);
+        s.append(&self.payload);
+        match self.signature {
+            Some(ref signature) => {
+                s.begin_list(1).append(&signature.to_vec());
+            }
+            None => {
+                s.begin_list(0);
+            }
+        }
+        s.append(&self.chain_id);
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 8 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        let recipient = rlp.at(3)?;
+        let recipient = match recipient.item_count()? {
+            0 => None,
+            1 => Some(decode_address(&recipient, 0)?),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+        let signature = rlp.at(6)?;
+        let signature = match signature.item_count()? {
+            0 => None,
+            1 => Some(decode_signature(&signature, 0)?),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+        let mut transaction = Transaction {
+            account_nonce: rlp.val_at(0)?,
+            gas_price: rlp.val_at(1)?,
+            gas_limit: rlp.val_at(2)?,
+            recipient: recipient,
+            amount: rlp.val_at(4)?,
+            payload: rlp.val_at(5)?,
+            signature: signature,
+            chain_id: rlp.val_at(7)?,
+            hash: None,
+        };
+        if transaction.signature.is_some() && transaction.recipient.is_some() {
+            let tx_hash = CryptoHash::hash(&transaction);
+            transaction.set_hash(tx_hash);
+        }
+        Ok(transaction)
+    }
+}
+
 impl Eq for Transaction {}
 
 impl PartialEq for Transaction {
@@ -135,6 +226,7 @@ struct TransactionSignature {
     payload: Vec<u8>,
     #[serde(rename = "sign")]
     signature: Option<Signature>,
+    chain_id: u64,
 }
 
 implement_storagevalue_traits! {TransactionSignature}
@@ -151,6 +243,7 @@ impl TransactionSignature {
             amount: tx.amount,
             payload: tx.payload.clone(),
             signature: Some(sign),
+            chain_id: tx.chain_id,
         };
         signature.into_bytes()
     }
@@ -164,6 +257,7 @@ impl TransactionSignature {
             amount: tx.amount,
             payload: tx.payload.clone(),
             signature: None,
+            chain_id: tx.chain_id,
         };
         signature.into_bytes()
     }
@@ -190,4 +284,24 @@ mod tests {
         writeln!(io::stdout(), "hash: {:?}", hash).unwrap();
         writeln!(io::stdout(), "{}", tx.pretty_json()).unwrap();
     }
+
+    #[test]
+    fn t_chain_id_replay_protection() {
+        let keypair = Random.generate().unwrap();
+        let mut tx = Transaction::new(10, Address::from(100), 89, 10, 90, vec![]);
+        tx.sign(100, keypair.secret());
+        assert!(tx.verify_sign(100));
+        assert!(!tx.verify_sign(101));
+        assert_eq!(tx.sender(), Some(keypair.address()));
+
+        let decoded: Transaction = rlp::decode(&rlp::encode(&tx));
+        assert_eq!(decoded.chain_id(), 100);
+        assert_eq!(decoded.sender(), Some(keypair.address()));
+
+        // replay on another chain, the signer changes
+        let mut replayed = tx.clone();
+        replayed.chain_id = 101;
+        assert!(replayed.verify_sign(101));
+        assert_ne!(replayed.sender(), Some(keypair.address()));
+    }
 }
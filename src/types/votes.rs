@@ -4,12 +4,19 @@ use cryptocurrency_kit::ethkey::Secret;
 use cryptocurrency_kit::ethkey::{public_to_address, recover_bytes};
 use cryptocurrency_kit::ethkey::{Address, Public, Signature};
 
+use crate::common::hash_algo;
 use crate::protocol::{GossipMessage, MessageType};
+use crate::types::block::Header;
 
 const SIGN_OP_OFFSET: usize = 0;
 const SIGN_ROUND_OFFSET: usize = 1;
 const SIGN_PACKET_SIZE: usize = 9;
 
+// the workers of batch verification
+pub const BATCH_VERIFY_WORKERS: usize = 4;
+// verify in the current thread if the votes are less than it
+pub const BATCH_VERIFY_THRESHOLD: usize = 8;
+
 use std::io::Cursor;
 use std::io::Write;
 
@@ -48,6 +55,27 @@ impl Votes {
         &self.0
     }
 
+    /// verify all commit seals on the block hash in parallel, every seal must be signed by
+    /// a different validator
+    pub fn verify_commit_seals<F>(&self, digest: &Hash, author: F) -> bool
+        where
+            F: Fn(Address) -> bool,
+    {
+        let mut signers = Vec::with_capacity(self.len());
+        for result in batch_recover_commit_seals(digest, &self.0) {
+            match result {
+                Ok(address) => {
+                    if !author(address) || signers.contains(&address) {
+                        return false;
+                    }
+                    signers.push(address);
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
     pub fn verify_signs<F>(&self, digest: Hash, author: F) -> bool
         where
             F: Fn(Address) -> bool,
@@ -74,12 +102,12 @@ pub fn decrypt_commit_bytes<T: AsRef<[u8]>>(
         return Err("sign bytes size not equal SIGN_PACKET_SIZE".to_string());
     }
     let op_code = MessageType::Commit;
-    let digest = hash(input);
+    let digest = hash_algo::digest(input);
     let mut input = Cursor::new(vec![0_u8; 1 + HASH_SIZE]);
     input.write_u8(op_code as u8).unwrap();
     input.write_all(digest.as_ref()).unwrap();
     let buffer = input.into_inner();
-    let digest: Hash = hash(buffer);
+    let digest: Hash = hash_algo::digest(buffer);
     match recover_bytes(signture, digest.as_ref()) {
         Ok(ref public) => {
             let address = public_to_address(public);
@@ -91,13 +119,62 @@ pub fn decrypt_commit_bytes<T: AsRef<[u8]>>(
     }
 }
 
+/// recover the signer of a commit seal, it's the reverse of `encrypt_commit_bytes`
+pub fn recover_commit_seal(digest: &Hash, seal: &Signature) -> Result<Address, String> {
+    let mut input = Cursor::new(vec![0_u8; 1 + HASH_SIZE]);
+    input.write_u8(MessageType::Commit as u8).unwrap();
+    input.write_all(digest.as_ref()).unwrap();
+    let digest: Hash = hash_algo::digest(input.into_inner());
+    recover_bytes(seal, digest.as_ref())
+        .map(|public| public_to_address(&public))
+        .map_err(|_| "recover commit seal failed".to_string())
+}
+
+/// recover the signers of the commit seals with a thread pool, the results keep the order of seals
+pub fn batch_recover_commit_seals(digest: &Hash, seals: &[Signature]) -> Vec<Result<Address, String>> {
+    if seals.len() < BATCH_VERIFY_THRESHOLD {
+        return seals.iter().map(|seal| recover_commit_seal(digest, seal)).collect();
+    }
+    let chunk_size = (seals.len() + BATCH_VERIFY_WORKERS - 1) / BATCH_VERIFY_WORKERS;
+    crossbeam::scope(|scope| {
+        let handles: Vec<_> = seals.chunks(chunk_size).map(|chunk| {
+            scope.spawn(move |_| {
+                chunk.iter().map(|seal| recover_commit_seal(digest, seal)).collect::<Vec<_>>()
+            })
+        }).collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    }).unwrap()
+}
+
+/// the commit seals which finalize a block, more than 2/3 of the validators
+pub fn commit_quorum(validators: usize) -> usize {
+    (validators as f32 * 2.0 / 3.0).floor() as usize + 1
+}
+
+/// verify the seals of an imported block, it needs more than 2/3 validators' commit seals
+pub fn verify_block_seals(header: &Header, validators: &[Address]) -> Result<(), String> {
+    let votes = header.votes.as_ref().ok_or("lack of votes".to_string())?;
+    let quorum = commit_quorum(validators.len());
+    if votes.len() < quorum {
+        return Err(format!("lack of votes, expect: {}, got: {}", quorum, votes.len()));
+    }
+    if !votes.verify_commit_seals(&header.block_hash(), |address| validators.contains(&address)) {
+        return Err("invalid commit seals".to_string());
+    }
+    Ok(())
+}
+
 pub fn encrypt_commit_bytes(digest: &Hash, secret: &Secret) -> Signature {
+    commit_seal_digest(digest).sign(secret).unwrap()
+}
+
+/// the digest which the commit seal of the proposal signs
+pub fn commit_seal_digest(digest: &Hash) -> Hash {
     let mut input = Cursor::new(vec![0_u8; 1 + HASH_SIZE]);
     input.write_u8(MessageType::Commit as u8).unwrap();
     input.write_all(digest.as_ref()).unwrap();
     let buffer = input.into_inner();
-    let digest = hash(buffer);
-    digest.sign(secret).unwrap()
+    hash_algo::digest(buffer)
 }
 
 
@@ -108,6 +185,27 @@ mod tests{
     use cryptocurrency_kit::ethkey::Generator;
     use cryptocurrency_kit::ethkey::Random;
 
+    #[test]
+    fn t_batch_verify_commit_seals() {
+        let digest = hash(vec![1, 2, 3]);
+        let key_pairs: Vec<KeyPair> = (0..10).map(|_| Random.generate().unwrap()).collect();
+        let addresses: Vec<Address> = key_pairs.iter().map(|key_pair| key_pair.address()).collect();
+        let seals: Vec<Signature> = key_pairs.iter().map(|key_pair| encrypt_commit_bytes(&digest, key_pair.secret())).collect();
+
+        let recovered: Vec<Address> = batch_recover_commit_seals(&digest, &seals).into_iter().map(|result| result.unwrap()).collect();
+        assert_eq!(recovered, addresses);
+
+        let votes = Votes::new(seals.clone());
+        assert!(votes.verify_commit_seals(&digest, |address| addresses.contains(&address)));
+        assert!(!votes.verify_commit_seals(&hash(vec![4]), |address| addresses.contains(&address)));
+        assert!(!votes.verify_commit_seals(&digest, |address| address != addresses[9]));
+
+        // duplicate seals
+        let mut duplicated = seals[..2].to_vec();
+        duplicated.push(seals[0].clone());
+        assert!(!Votes::new(duplicated).verify_commit_seals(&digest, |_| true));
+    }
+
     #[test]
     fn t_random() {
         (0..10).for_each(|_|{
@@ -0,0 +1,74 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::thread;
+
+use crossbeam::channel::{unbounded, Sender};
+use futures::channel::oneshot;
+
+pub const DEFAULT_BLOCKING_THREADS: usize = 4;
+
+type Job = Box<FnMut() + Send>;
+
+/// BlockingPool runs the long blocking calls, eg: a state replay, on its own threads, so an async
+/// handler awaits the result instead of blocking the executor. The threads exit when the pool is
+/// dropped.
+pub struct BlockingPool {
+    jobs: Sender<Job>,
+}
+
+impl Default for BlockingPool {
+    fn default() -> Self {
+        BlockingPool::new(DEFAULT_BLOCKING_THREADS)
+    }
+}
+
+impl BlockingPool {
+    pub fn new(threads: usize) -> Self {
+        let (jobs, rx) = unbounded::<Job>();
+        for id in 0..threads.max(1) {
+            let rx = rx.clone();
+            thread::Builder::new().name(format!("blocking-{}", id)).spawn(move || {
+                while let Ok(mut job) = rx.recv() {
+                    // a panicking call drops its sender, the thread takes the next job
+                    if catch_unwind(AssertUnwindSafe(|| job())).is_err() {
+                        error!("A blocking call panicked");
+                    }
+                }
+            }).expect("Failed to spawn the blocking thread");
+        }
+        BlockingPool { jobs }
+    }
+
+    /// run the call on the pool, the receiver is canceled if the call panics
+    pub fn run<T, F>(&self, call: F) -> oneshot::Receiver<T>
+        where T: Send + 'static, F: FnOnce() -> T + Send + 'static {
+        let (tx, rx) = oneshot::channel();
+        let mut call = Some(call);
+        let job: Job = Box::new(move || {
+            if let Some(call) = call.take() {
+                let _ = tx.send(call());
+            }
+        });
+        if self.jobs.send(job).is_err() {
+            error!("The blocking pool is stopped");
+        }
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn t_blocking_pool() {
+        let pool = BlockingPool::new(2);
+        let results: Vec<_> = (0..8u64).map(|i| pool.run(move || i * 2)).collect();
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(block_on(result).unwrap(), i as u64 * 2);
+        }
+        // a panicking call cancels its receiver and the pool keeps running
+        assert!(block_on(pool.run(|| -> u64 { panic!("boom") })).is_err());
+        assert_eq!(block_on(pool.run(|| 1)).unwrap(), 1);
+    }
+}
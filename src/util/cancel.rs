@@ -0,0 +1,53 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Canceller invalidates the tokens which were issued before the last `cancel`, the core cancels
+/// on every round transition, so the proposals and the messages of a superseded round are dropped
+/// by the block assembly and the outbound queues instead of being finished
+#[derive(Debug, Clone, Default)]
+pub struct Canceller {
+    generation: Arc<AtomicUsize>,
+}
+
+impl Canceller {
+    pub fn token(&self) -> CancelToken {
+        CancelToken {
+            generation: self.generation.clone(),
+            issued: self.generation.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    generation: Arc<AtomicUsize>,
+    issued: usize,
+}
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.generation.load(Ordering::SeqCst) != self.issued
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_cancel_token() {
+        let canceller = Canceller::default();
+        let first = canceller.token();
+        assert!(!first.is_cancelled());
+        canceller.clone().cancel();
+        assert!(first.is_cancelled());
+        let second = canceller.token();
+        assert!(!second.is_cancelled());
+        canceller.cancel();
+        assert!(second.is_cancelled());
+    }
+}
@@ -0,0 +1,121 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// the output format of the read apis and the cli tools, eg: `/header/10?format=canonical`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Pretty,
+    // sorted keys, no whitespace, integral numbers without fraction and lowercase hex, so the
+    // outputs of two nodes can be diffed byte for byte
+    Canonical,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "pretty" => Ok(OutputFormat::Pretty),
+            "canonical" => Ok(OutputFormat::Canonical),
+            _ => Err(format!("unknown format: {}, expect `json`, `pretty` or `canonical`", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn render<T: Serialize>(self, value: &T) -> String {
+        match self {
+            OutputFormat::Json => serde_json::to_string(value).unwrap(),
+            OutputFormat::Pretty => serde_json::to_string_pretty(value).unwrap(),
+            OutputFormat::Canonical => to_canonical_json(value),
+        }
+    }
+}
+
+pub fn to_canonical_json<T: Serialize>(value: &T) -> String {
+    let value = serde_json::to_value(value).unwrap();
+    let mut out = String::new();
+    write_value(&value, &mut out);
+    out
+}
+
+fn write_value(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(n, out),
+        Value::String(s) => out.push_str(&serde_json::to_string(&canonical_hex(s)).unwrap()),
+        Value::Array(values) => {
+            out.push('[');
+            for (idx, value) in values.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                write_value(value, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (idx, key) in keys.into_iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key).unwrap());
+                out.push(':');
+                write_value(&map[key], out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+// the integral floats are written as integers, eg: `1.0` => `1`
+fn write_number(n: &Number, out: &mut String) {
+    if let Some(v) = n.as_u64() {
+        out.push_str(&v.to_string());
+    } else if let Some(v) = n.as_i64() {
+        out.push_str(&v.to_string());
+    } else if let Some(v) = n.as_f64() {
+        if v.fract() == 0.0 && v.abs() < (1_u64 << 53) as f64 {
+            out.push_str(&(v as i64).to_string());
+        } else {
+            out.push_str(&v.to_string());
+        }
+    }
+}
+
+// the hex strings (`0x` prefixed, or the bare hashes and addresses) are lowercase
+fn canonical_hex(s: &str) -> String {
+    let digits = s.trim_start_matches("0x");
+    let is_hex = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex && (s.starts_with("0x") || (digits.len() >= 40 && digits.len() % 2 == 0)) {
+        s.to_ascii_lowercase()
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_canonical_json() {
+        let value = json!({
+            "b": [1.0, -2, 2.5],
+            "a": {"y": "0xABcd", "x": "Hello"},
+            "hash": "AB".repeat(32),
+        });
+        let expect = format!(r#"{{"a":{{"x":"Hello","y":"0xabcd"}},"b":[1,-2,2.5],"hash":"{}"}}"#, "ab".repeat(32));
+        assert_eq!(to_canonical_json(&value), expect);
+        assert_eq!(OutputFormat::from_str("canonical"), Ok(OutputFormat::Canonical));
+        assert!(OutputFormat::from_str("xml").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::metrics;
+use crate::types::Timestamp;
+
+// the seconds between 1900-01-01 (ntp era) and 1970-01-01 (unix epoch)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+const NTP_PACKET_SIZE: usize = 48;
+pub const NTP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// query the clock offset to the sntp server in millis, it's positive if the local clock is behind
+pub fn sntp_offset(server: &str, timeout: Duration) -> Result<i64, String> {
+    let addr = server.to_socket_addrs()
+        .map_err(|err| format!("resolve {} failed, err: {}", server, err))?
+        .next()
+        .ok_or(format!("resolve {} failed", server))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|err| err.to_string())?;
+    socket.set_read_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+
+    let mut request = [0u8; NTP_PACKET_SIZE];
+    // LI = 0, VN = 3, Mode = 3 (client)
+    request[0] = 0x1b;
+    let origin = now_millis();
+    socket.send_to(&request, addr).map_err(|err| err.to_string())?;
+    let mut response = [0u8; NTP_PACKET_SIZE];
+    let (size, _) = socket.recv_from(&mut response).map_err(|err| format!("read from {} failed, err: {}", server, err))?;
+    let destination = now_millis();
+    if size < NTP_PACKET_SIZE {
+        return Err(format!("short ntp response from {}, size: {}", server, size));
+    }
+    let (receive, transmit) = (ntp_millis(&response[32..40]), ntp_millis(&response[40..48]));
+    Ok(clock_offset(origin, receive, transmit, destination))
+}
+
+// ((t1 - t0) + (t2 - t3)) / 2
+fn clock_offset(origin: i64, receive: i64, transmit: i64, destination: i64) -> i64 {
+    ((receive - origin) + (transmit - destination)) / 2
+}
+
+// the 64 bits ntp timestamp, 32 bits seconds and 32 bits fraction
+fn ntp_millis(buf: &[u8]) -> i64 {
+    let seconds = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64;
+    let fraction = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as u64;
+    ((seconds.saturating_sub(NTP_UNIX_OFFSET)) * 1000 + (fraction * 1000 >> 32)) as i64
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// compare the local clock with the first reachable ntp server, the drift over `max_drift` is an
+/// error if `strict` is set, otherwise it's only warned. It returns the offset in millis
+pub fn check_clock_drift(servers: &[String], max_drift: Duration, strict: bool) -> Result<Option<i64>, String> {
+    for server in servers {
+        let offset = match sntp_offset(server, NTP_TIMEOUT) {
+            Ok(offset) => offset,
+            Err(err) => {
+                warn!("Failed to query the ntp server, err: {}", err);
+                continue;
+            }
+        };
+        metrics::set_gauge("clock_drift_ms", offset);
+        if offset.abs() as u128 <= max_drift.as_millis() {
+            info!("Local clock drift: {}ms, ntp server: {}", offset, server);
+            return Ok(Some(offset));
+        }
+        let err = format!("the local clock drifts {}ms from {}, the max drift is {:?}", offset, server, max_drift);
+        if strict {
+            return Err(err);
+        }
+        warn!("{}", err);
+        return Ok(Some(offset));
+    }
+    if !servers.is_empty() {
+        warn!("No ntp server is reachable, the clock drift is not checked");
+    }
+    Ok(None)
+}
+
+/// the unix time in seconds which the chain reads, eg: for the timestamps of the future blocks
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+pub type SafeClock = Arc<Clock + Send + Sync>;
+
+/// the local clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        chrono::Local::now().timestamp() as Timestamp
+    }
+}
+
+/// the clock which only moves when it's advanced, the tests drive the time by it
+pub struct ManualClock(Mutex<Timestamp>);
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> Self {
+        ManualClock(Mutex::new(now))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock() += duration.as_secs();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        *self.0.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_clock_offset() {
+        // the server is 500ms ahead and the round trip takes 100ms
+        assert_eq!(clock_offset(1000, 1550, 1550, 1100), 500);
+        assert_eq!(clock_offset(1000, 550, 550, 1100), -500);
+
+        let mut buf = [0u8; 8];
+        buf[..4].copy_from_slice(&((NTP_UNIX_OFFSET + 10) as u32).to_be_bytes());
+        buf[4..].copy_from_slice(&(1u32 << 31).to_be_bytes());
+        assert_eq!(ntp_millis(&buf), 10_500);
+
+        assert_eq!(check_clock_drift(&[], Duration::from_secs(1), true), Ok(None));
+    }
+}
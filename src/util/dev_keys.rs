@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use cryptocurrency_kit::crypto::hash;
+use cryptocurrency_kit::ethkey::{Address, KeyPair, Secret};
+use libp2p::PeerId;
+
+use crate::p2p::identity::key_peer_id;
+
+/// it's mixed into every derived key. The keys are derived from public indexes, anyone can sign
+/// with them, so they are only accepted by the nodes in dev mode.
+pub const DEV_KEY_MARKER: &str = "consensus-rs/dev-only";
+/// the config values which select a derived key, eg: `secret = "dev:2"`
+pub const DEV_KEY_PREFIX: &str = "dev:";
+
+const VALIDATOR_ROLE: &str = "validator";
+const NODE_ROLE: &str = "node";
+
+// the fixed hash function is used, the keys don't change with the hash algorithm of a chain
+fn derive_key_pair(role: &str, index: u64) -> KeyPair {
+    let mut nonce: u64 = 0;
+    loop {
+        let seed = format!("{}/{}/{}/{}", DEV_KEY_MARKER, role, index, nonce);
+        let bytes = hash(seed.into_bytes());
+        // a digest out of the curve order is skipped
+        let key_pair = Secret::from_str(&hex::encode(bytes.as_ref())).ok()
+            .and_then(|secret| KeyPair::from_secret(secret).ok());
+        if let Some(key_pair) = key_pair {
+            return key_pair;
+        }
+        nonce += 1;
+    }
+}
+
+/// the validator key of `validator-{index}`
+pub fn dev_key_pair(index: u64) -> KeyPair {
+    derive_key_pair(VALIDATOR_ROLE, index)
+}
+
+pub fn dev_validator_address(index: u64) -> Address {
+    dev_key_pair(index).address()
+}
+
+/// the p2p identity key of the node which runs `validator-{index}`
+pub fn dev_node_key(index: u64) -> KeyPair {
+    derive_key_pair(NODE_ROLE, index)
+}
+
+pub fn dev_peer_id(index: u64) -> PeerId {
+    key_peer_id(&dev_node_key(index)).expect("the dev node key is a valid secp256k1 key")
+}
+
+/// the readable name of the derived keys in the logs
+pub fn dev_name(index: u64) -> String {
+    format!("{}-{}", VALIDATOR_ROLE, index)
+}
+
+/// the keys of a local network, they are printed by the launcher so the configs can be written
+#[derive(Debug, Clone, Serialize)]
+pub struct DevKey {
+    pub name: String,
+    pub secret: String,
+    pub address: String,
+    pub node_key: String,
+    pub peer_id: String,
+}
+
+pub fn dev_keys(count: u64) -> Vec<DevKey> {
+    (0..count).map(|index| DevKey {
+        name: dev_name(index),
+        secret: format!("{}{}", DEV_KEY_PREFIX, index),
+        address: format!("0x{}", hex::encode(dev_validator_address(index).as_ref())),
+        node_key: format!("{}{}", DEV_KEY_PREFIX, index),
+        peer_id: dev_peer_id(index).to_base58(),
+    }).collect()
+}
+
+/// the index of a `dev:{index}` config value
+pub fn parse_dev_index(value: &str) -> Option<u64> {
+    if !value.starts_with(DEV_KEY_PREFIX) {
+        return None;
+    }
+    value[DEV_KEY_PREFIX.len()..].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_dev_keys() {
+        assert_eq!(dev_validator_address(2), dev_validator_address(2));
+        assert_ne!(dev_validator_address(2), dev_validator_address(3));
+        assert_ne!(dev_key_pair(2).address(), dev_node_key(2).address());
+        assert_eq!(dev_peer_id(1), dev_peer_id(1));
+        assert_ne!(dev_peer_id(1), dev_peer_id(2));
+        assert_eq!(dev_name(2), "validator-2");
+        assert_eq!(parse_dev_index("dev:2"), Some(2));
+        assert_eq!(parse_dev_index("dev:x"), None);
+        assert_eq!(parse_dev_index("0x12"), None);
+        assert_eq!(dev_keys(3)[2].name, "validator-2");
+        assert_eq!(parse_dev_index(&dev_keys(3)[2].secret), Some(2));
+    }
+}
@@ -1,3 +1,13 @@
+pub mod cancel;
+pub mod blocking;
+pub mod canonical;
+pub mod clock;
+pub mod dev_keys;
+pub mod request;
+pub mod rng;
+pub mod single_flight;
+pub mod supervise;
+
 use ::actix::prelude::*;
 
 use std::time::Duration;
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use ::actix::dev::ToEnvelope;
+use ::actix::prelude::*;
+use futures::Future;
+
+use crate::error::RequestError;
+use crate::metrics;
+
+// the timeout of the requests between the actors
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the timeout of every attempt and the extra attempts after a timeout, a stopped actor is never retried
+#[derive(Debug, Clone, Copy)]
+pub struct RequestPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        RequestPolicy { timeout: DEFAULT_REQUEST_TIMEOUT, retries: 2 }
+    }
+}
+
+fn translate(err: MailboxError, timeout: Duration) -> RequestError {
+    match err {
+        MailboxError::Closed => {
+            metrics::inc_counter("actor_request_closed_total");
+            RequestError::Closed
+        }
+        MailboxError::Timeout => {
+            metrics::inc_counter("actor_request_timeout_total");
+            RequestError::Timeout(timeout)
+        }
+    }
+}
+
+/// send the message and wait for the reply asynchronously, the mailbox error is typed
+pub fn send_timeout<A, M>(addr: &Addr<A>, msg: M, timeout: Duration) -> impl Future<Item=M::Result, Error=RequestError>
+    where A: Handler<M>,
+          A::Context: ToEnvelope<A, M>,
+          M: Message + Send + 'static,
+          M::Result: Send {
+    addr.send(msg).timeout(timeout).map_err(move |err| translate(err, timeout))
+}
+
+/// send the message and block until the reply, only for the callers outside the actor system, eg: the api
+pub fn send_wait<A, M>(addr: &Addr<A>, msg: M, timeout: Duration) -> Result<M::Result, RequestError>
+    where A: Handler<M>,
+          A::Context: ToEnvelope<A, M>,
+          M: Message + Send + 'static,
+          M::Result: Send {
+    send_timeout(addr, msg, timeout).wait()
+}
+
+/// like `send_wait`, but the request is sent again after a timeout, so the message must be idempotent
+pub fn send_retry<A, M>(addr: &Addr<A>, msg: M, policy: &RequestPolicy) -> Result<M::Result, RequestError>
+    where A: Handler<M>,
+          A::Context: ToEnvelope<A, M>,
+          M: Message + Send + Clone + 'static,
+          M::Result: Send {
+    let mut attempt = 0;
+    loop {
+        match send_wait(addr, msg.clone(), policy.timeout) {
+            Err(RequestError::Timeout(_)) if attempt < policy.retries => {
+                attempt += 1;
+                metrics::inc_counter("actor_request_retry_total");
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sleeper;
+
+    impl Actor for Sleeper {
+        type Context = SyncContext<Self>;
+    }
+
+    #[derive(Clone)]
+    struct Sleep(Duration);
+
+    impl Message for Sleep {
+        type Result = ();
+    }
+
+    impl Handler<Sleep> for Sleeper {
+        type Result = ();
+
+        fn handle(&mut self, msg: Sleep, _: &mut Self::Context) {
+            ::std::thread::sleep(msg.0);
+        }
+    }
+
+    #[test]
+    fn t_send_retry() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        ::std::thread::spawn(move || {
+            System::run(move || {
+                tx.send(SyncArbiter::start(1, || Sleeper)).unwrap();
+            });
+        });
+        let addr = rx.recv().unwrap();
+        let policy = RequestPolicy { timeout: Duration::from_millis(200), retries: 1 };
+        assert_eq!(send_retry(&addr, Sleep(Duration::from_millis(1)), &policy), Ok(()));
+        assert_eq!(send_retry(&addr, Sleep(Duration::from_millis(500)), &policy), Err(RequestError::Timeout(policy.timeout)));
+    }
+}
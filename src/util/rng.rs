@@ -0,0 +1,33 @@
+use rand::prng::XorShiftRng;
+use rand::SeedableRng;
+
+/// the rng of a seed, the same seed replays the same sequence, eg: the simulations and the fuzz
+/// runs are reproduced by the seed of their reports
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    let mut bytes = [0_u8; 16];
+    let (low, high) = bytes.split_at_mut(8);
+    for (idx, byte) in low.iter_mut().enumerate() {
+        *byte = (seed >> (idx * 8)) as u8;
+        // the xorshift state must not be all zero
+        high[idx] = !*byte;
+    }
+    XorShiftRng::from_seed(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn t_seeded_rng() {
+        let sequence = |seed: u64| {
+            let mut rng = seeded_rng(seed);
+            (0..4).map(|_| rng.gen::<u64>()).collect::<Vec<u64>>()
+        };
+        assert_eq!(sequence(7), sequence(7));
+        assert_ne!(sequence(7), sequence(8));
+        // the zero seed still has a state
+        assert_ne!(sequence(0), vec![0; 4]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+
+type Waiters<V> = Mutex<HashMap<String, Vec<oneshot::Sender<V>>>>;
+
+/// SingleFlight runs one fetch for the concurrent calls of the same key, the other callers await
+/// its result instead of blocking a thread, nothing is cached after the fetch returns
+pub struct SingleFlight<V> {
+    calls: Arc<Waiters<V>>,
+}
+
+/// Flight is the call of the first caller of a key, `finish` shares the result with the waiting
+/// callers. If it's dropped unfinished, eg: the fetch panicked, the waiters are canceled and the
+/// key is free for a new fetch
+pub struct Flight<V> {
+    calls: Arc<Waiters<V>>,
+    key: Option<String>,
+}
+
+impl<V: Clone> Default for SingleFlight<V> {
+    fn default() -> Self {
+        SingleFlight { calls: Arc::new(Mutex::new(HashMap::new())) }
+    }
+}
+
+impl<V: Clone> SingleFlight<V> {
+    /// the flight of the first caller of the key, the receiver of the result for the others
+    pub fn join(&self, key: String) -> Result<Flight<V>, oneshot::Receiver<V>> {
+        let mut calls = self.calls.lock();
+        if let Some(waiters) = calls.get_mut(&key) {
+            crate::metrics::inc_counter("single_flight_shared_total");
+            let (tx, rx) = oneshot::channel();
+            waiters.push(tx);
+            return Err(rx);
+        }
+        calls.insert(key.clone(), vec![]);
+        Ok(Flight { calls: self.calls.clone(), key: Some(key) })
+    }
+}
+
+impl<V: Clone> Flight<V> {
+    pub fn finish(mut self, value: V) {
+        // the later callers start a new fetch
+        let waiters = self.key.take().and_then(|key| self.calls.lock().remove(&key)).unwrap_or_default();
+        for waiter in waiters {
+            let _ = waiter.send(value.clone());
+        }
+    }
+}
+
+impl<V> Drop for Flight<V> {
+    fn drop(&mut self) {
+        // the senders of the waiters are dropped with the key
+        if let Some(key) = self.key.take() {
+            self.calls.lock().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use futures::executor::block_on;
+
+    #[test]
+    fn t_single_flight() {
+        let flight: SingleFlight<usize> = SingleFlight::default();
+        let leader = flight.join("block".to_string()).ok().unwrap();
+        let waiters: Vec<_> = (0..8).map(|_| flight.join("block".to_string()).err().unwrap()).collect();
+        // the other keys are not shared
+        assert!(flight.join("header".to_string()).is_ok());
+        leader.finish(10);
+        for waiter in waiters {
+            assert_eq!(block_on(waiter).unwrap(), 10);
+        }
+        // the finished key starts a new fetch
+        assert!(flight.join("block".to_string()).is_ok());
+    }
+
+    #[test]
+    fn t_single_flight_panic() {
+        let flight: SingleFlight<usize> = SingleFlight::default();
+        let leader = flight.join("block".to_string()).ok().unwrap();
+        let waiter = flight.join("block".to_string()).err().unwrap();
+        let result = catch_unwind(AssertUnwindSafe(move || {
+            let _leader = leader;
+            panic!("the fetch panicked");
+        }));
+        assert!(result.is_err());
+        // the waiter is canceled instead of waiting forever, the key is free
+        assert!(block_on(waiter).is_err());
+        let leader = flight.join("block".to_string()).ok().unwrap();
+        let waiter = flight.join("block".to_string()).err().unwrap();
+        leader.finish(1);
+        assert_eq!(block_on(waiter).unwrap(), 1);
+    }
+}
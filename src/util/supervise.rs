@@ -0,0 +1,85 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use ::actix::prelude::*;
+
+use crate::metrics;
+
+/// count the restart of a supervised actor, eg: `actor_restart_total{actor="core"}`
+pub fn record_restart(actor: &str) {
+    warn!("The {} actor is restarting", actor);
+    metrics::inc_counter(&restart_metric_name(actor));
+}
+
+pub fn restart_metric_name(actor: &str) -> String {
+    format!("actor_restart_total{{actor=\"{}\"}}", actor)
+}
+
+/// run the handler body, the actor is stopped on a panic so its supervisor restarts it
+pub fn guard<A, R, F>(actor: &str, ctx: &mut Context<A>, f: F) -> Option<R>
+    where A: Actor<Context=Context<A>>,
+          F: FnOnce() -> R {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Some(result),
+        Err(_) => {
+            error!("The {} actor panicked, stop it for the restart", actor);
+            ctx.stop();
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Flaky {
+        restarts: usize,
+    }
+
+    impl Actor for Flaky {
+        type Context = Context<Self>;
+    }
+
+    impl Supervised for Flaky {
+        fn restarting(&mut self, _ctx: &mut Context<Self>) {
+            self.restarts += 1;
+            record_restart("flaky");
+        }
+    }
+
+    struct Work(bool);
+
+    impl Message for Work {
+        type Result = Option<usize>;
+    }
+
+    impl Handler<Work> for Flaky {
+        type Result = Option<usize>;
+
+        fn handle(&mut self, msg: Work, ctx: &mut Self::Context) -> Self::Result {
+            let restarts = self.restarts;
+            guard("flaky", ctx, || {
+                if msg.0 {
+                    panic!("flaky work");
+                }
+                restarts
+            })
+        }
+    }
+
+    #[test]
+    fn t_supervise() {
+        let system = System::new("t_supervise");
+        let addr = Supervisor::start(|_| Flaky { restarts: 0 });
+        let work = addr.send(Work(true))
+            .and_then(move |_| addr.send(Work(false)))
+            .map(|restarts| {
+                assert_eq!(restarts, Some(1));
+                System::current().stop();
+            })
+            .map_err(|_| panic!("the flaky actor is gone"));
+        Arbiter::spawn(work);
+        system.run();
+        assert!(metrics::gather().contains(&restart_metric_name("flaky")));
+    }
+}